@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::file_utils;
+use crate::index::ArchiveMember;
+
+/// Archive formats whose members can be indexed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn format_of(path: &str) -> Option<Format> {
+    let lower = path.to_lowercase();
+    if lower.ends_with(".zip") {
+        Some(Format::Zip)
+    } else if lower.ends_with(".tar") {
+        Some(Format::Tar)
+    } else if lower.ends_with(".tar.gz") || lower.ends_with(".tgz") {
+        Some(Format::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Whether a path names an archive whose members can be indexed (zip, tar, tar.gz)
+pub fn is_archive(path: &str) -> bool {
+    format_of(path).is_some()
+}
+
+/// Hash the regular, non-empty members of an archive, in archive order
+/// `archive` is the archive's index path, recorded on each member.
+pub fn read_members(full_path: &Path, archive: &str) -> Result<Vec<ArchiveMember>> {
+    let format = format_of(archive).context(format!("Not a supported archive: {}", archive))?;
+    let file = File::open(full_path)
+        .context(format!("Failed to open file: {}", full_path.display()))?;
+    let mut members = Vec::new();
+    let mut record = |member: String, size: u64, reader: &mut dyn Read| -> Result<()> {
+        let (num_bytes, sha256) = hash_reader(reader, size)
+            .context(format!("Failed to read {} in {}", member, archive))?;
+        if num_bytes > 0 {
+            members.push(ArchiveMember { archive: archive.to_string(), member, num_bytes, sha256 });
+        }
+        Ok(())
+    };
+
+    match format {
+        Format::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file))
+                .context(format!("Failed to read zip archive: {}", archive))?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i)
+                    .context(format!("Failed to read zip archive: {}", archive))?;
+                if !entry.is_file() {
+                    continue;
+                }
+                let name = entry.name().to_string();
+                let size = entry.size();
+                record(name, size, &mut entry)?;
+            }
+        }
+        Format::Tar => read_tar(tar::Archive::new(BufReader::new(file)), archive, &mut record)?,
+        Format::TarGz => {
+            let decoder = flate2::read::GzDecoder::new(BufReader::new(file));
+            read_tar(tar::Archive::new(decoder), archive, &mut record)?
+        }
+    }
+    Ok(members)
+}
+
+fn read_tar<R: Read>(
+    mut tar: tar::Archive<R>,
+    archive: &str,
+    record: &mut impl FnMut(String, u64, &mut dyn Read) -> Result<()>,
+) -> Result<()> {
+    let entries = tar.entries()
+        .context(format!("Failed to read tar archive: {}", archive))?;
+    for entry in entries {
+        let mut entry = entry.context(format!("Failed to read tar archive: {}", archive))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()
+            .context(format!("Failed to read tar archive: {}", archive))?
+            .to_string_lossy()
+            .to_string();
+        let size = entry.size();
+        record(name, size, &mut entry)?;
+    }
+    Ok(())
+}
+
+/// Size and SHA256 of everything a reader yields, read in buffers sized for `size` (the member's recorded size)
+fn hash_reader(reader: &mut dyn Read, size: u64) -> Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; file_utils::hash_buffer_size(size)];
+    let mut num_bytes = 0;
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        num_bytes += bytes_read as u64;
+    }
+    Ok((num_bytes, format!("{:x}", hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_is_archive() {
+        assert!(is_archive("backups/2017.zip"));
+        assert!(is_archive("backups/2017.TAR.GZ"));
+        assert!(is_archive("a.tgz"));
+        assert!(is_archive("a.tar"));
+        assert!(!is_archive("a.gz"));
+        assert!(!is_archive("zip"));
+    }
+
+    #[test]
+    fn test_read_members_of_zip_and_tar() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let zip_path = dir.path().join("photos.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("album/", options).unwrap();
+        zip.start_file("album/a.jpg", options).unwrap();
+        zip.write_all(b"photo a").unwrap();
+        zip.start_file("empty.txt", options).unwrap();
+        zip.finish().unwrap();
+
+        let members = read_members(&zip_path, "backups/photos.zip").unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].archive, "backups/photos.zip");
+        assert_eq!(members[0].member, "album/a.jpg");
+        assert_eq!(members[0].num_bytes, 7);
+        assert_eq!(members[0].sha256, format!("{:x}", Sha256::digest(b"photo a")));
+
+        let tar_path = dir.path().join("photos.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(File::create(&tar_path).unwrap(), flate2::Compression::default());
+        let mut tar = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(7);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "album/a.jpg", &b"photo a"[..]).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let members = read_members(&tar_path, "photos.tar.gz").unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].member, "album/a.jpg");
+        assert_eq!(members[0].sha256, format!("{:x}", Sha256::digest(b"photo a")));
+    }
+}
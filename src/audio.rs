@@ -0,0 +1,361 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Extensions of audio files that are fingerprinted
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "wav", "ogg", "oga", "m4a", "aac"];
+
+/// Rate audio is reduced to before fingerprinting; the bands used lie well below its Nyquist frequency
+const SAMPLE_RATE: usize = 5512;
+/// Samples per analysis frame (0.37 s)
+const FRAME: usize = 2048;
+/// Samples between the starts of consecutive frames (one 32-bit word every 0.19 s)
+const HOP: usize = 1024;
+/// Only the start of a track is fingerprinted
+const MAX_SECONDS: usize = 120;
+/// Frequency range split into 33 bands, whose 32 neighbouring pairs give the bits of a word
+const LOW_HZ: f32 = 300.0;
+const HIGH_HZ: f32 = 2000.0;
+const BANDS: usize = 33;
+
+/// Fraction of differing bits below which two fingerprints are the same recording
+const MAX_BIT_ERROR_RATE: f64 = 0.25;
+/// Words two fingerprints must share to be compared (about 4 seconds)
+const MIN_OVERLAP: usize = 20;
+/// Frames the fingerprints may be shifted against each other (encoder delays differ)
+const MAX_SHIFT: isize = 3;
+
+/// Fingerprint of an audio file, recorded with `fingerprint_audio`
+/// The fingerprint is empty for files that could not be decoded, so they are not tried again.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioFingerprint {
+    /// Index path of the file
+    pub path: String,
+    /// Length of the track, if the container records it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Base64 of the fingerprint's 32-bit words (little-endian)
+    pub fingerprint: String,
+}
+
+impl AudioFingerprint {
+    fn words(&self) -> Vec<u32> {
+        base64::engine::general_purpose::STANDARD
+            .decode(&self.fingerprint)
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+}
+
+/// Whether a path names an audio file that can be fingerprinted
+pub fn is_audio(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Decode the start of an audio file and fingerprint it
+/// Files that cannot be decoded get an empty fingerprint; only errors opening the file are returned.
+pub fn fingerprint_file(full_path: &Path, path: &str) -> Result<AudioFingerprint> {
+    let file = File::open(full_path)
+        .context(format!("Failed to open file: {}", full_path.display()))?;
+    let (samples, duration_ms) = decode_mono(file, path).unwrap_or_default();
+    let words = fingerprint_samples(&samples);
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    Ok(AudioFingerprint {
+        path: path.to_string(),
+        duration_ms,
+        fingerprint: base64::engine::general_purpose::STANDARD.encode(bytes),
+    })
+}
+
+/// Decode up to MAX_SECONDS of audio as mono samples at SAMPLE_RATE, with the track length if known
+fn decode_mono(file: File, path: &str) -> Result<(Vec<f32>, Option<u64>), DecodeError> {
+    let mut hint = Hint::new();
+    if let Some(ext) = Path::new(path).extension() {
+        hint.with_extension(&ext.to_string_lossy());
+    }
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut format = probed.format;
+    let track = format.tracks().iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(DecodeError::Unsupported("no audio track"))?;
+    let track_id = track.id;
+    let source_rate = track.codec_params.sample_rate.ok_or(DecodeError::Unsupported("unknown sample rate"))?;
+    let duration_ms = track.codec_params.n_frames.map(|frames| frames * 1000 / source_rate as u64);
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut resampler = Resampler::new(source_rate as usize);
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    while resampler.output.len() < MAX_SECONDS * SAMPLE_RATE {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A damaged packet is skipped, as players do
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(e) => return Err(e),
+        };
+        let spec = *decoded.spec();
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * spec.channels.count() => buffer,
+            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        let channels = spec.channels.count().max(1);
+        for frame in buffer.samples().chunks_exact(channels) {
+            resampler.push(frame.iter().sum::<f32>() / channels as f32);
+        }
+    }
+    Ok((resampler.output, duration_ms))
+}
+
+/// Reduces a signal to SAMPLE_RATE by averaging the input samples behind each output sample
+struct Resampler {
+    /// Input samples per output sample
+    step: f64,
+    /// Input position where the next output sample ends
+    next: f64,
+    position: u64,
+    sum: f32,
+    count: u32,
+    output: Vec<f32>,
+}
+
+impl Resampler {
+    fn new(source_rate: usize) -> Self {
+        let step = source_rate as f64 / SAMPLE_RATE as f64;
+        Self { step, next: step, position: 0, sum: 0.0, count: 0, output: Vec::new() }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.sum += sample;
+        self.count += 1;
+        self.position += 1;
+        if self.position as f64 >= self.next {
+            self.output.push(self.sum / self.count as f32);
+            self.sum = 0.0;
+            self.count = 0;
+            self.next += self.step;
+        }
+    }
+}
+
+/// Fingerprint mono samples at SAMPLE_RATE: one word per frame, each bit telling whether the
+/// energy difference of two neighbouring bands grew or shrank since the previous frame.
+/// Comparing differences makes the bits independent of volume and robust to lossy encoding.
+fn fingerprint_samples(samples: &[f32]) -> Vec<u32> {
+    let window: Vec<f32> = (0..FRAME).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME as f32).cos()).collect();
+    let edges: Vec<usize> = (0..=BANDS)
+        .map(|band| {
+            let hz = LOW_HZ * (HIGH_HZ / LOW_HZ).powf(band as f32 / BANDS as f32);
+            (hz * FRAME as f32 / SAMPLE_RATE as f32).round() as usize
+        })
+        .collect();
+
+    let mut words = Vec::new();
+    let mut previous: Option<Vec<f32>> = None;
+    let mut spectrum = vec![(0.0f32, 0.0f32); FRAME];
+    for start in (0..samples.len().saturating_sub(FRAME - 1)).step_by(HOP) {
+        for (i, value) in spectrum.iter_mut().enumerate() {
+            *value = (samples[start + i] * window[i], 0.0);
+        }
+        fft(&mut spectrum);
+        let energies: Vec<f32> = edges
+            .windows(2)
+            .map(|edge| spectrum[edge[0]..edge[1].max(edge[0] + 1)].iter().map(|(re, im)| re * re + im * im).sum())
+            .collect();
+        if let Some(previous) = &previous {
+            let mut word = 0u32;
+            for band in 0..BANDS - 1 {
+                let now = energies[band] - energies[band + 1];
+                let before = previous[band] - previous[band + 1];
+                if now - before > 0.0 {
+                    word |= 1 << band;
+                }
+            }
+            words.push(word);
+        }
+        previous = Some(energies);
+    }
+    words
+}
+
+/// In-place radix-2 FFT of (re, im) pairs; the length must be a power of two
+fn fft(data: &mut [(f32, f32)]) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (re, im) = data[start + k + len / 2];
+                let odd = (re * cos - im * sin, re * sin + im * cos);
+                let even = data[start + k];
+                data[start + k] = (even.0 + odd.0, even.1 + odd.1);
+                data[start + k + len / 2] = (even.0 - odd.0, even.1 - odd.1);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Smallest fraction of differing bits between two fingerprints over the allowed shifts,
+/// or None if they overlap too little to compare
+fn bit_error_rate(a: &[u32], b: &[u32]) -> Option<f64> {
+    (-MAX_SHIFT..=MAX_SHIFT)
+        .filter_map(|shift| {
+            let (a, b) = if shift >= 0 { (&a[shift as usize..], b) } else { (a, &b[(-shift) as usize..]) };
+            let overlap = a.len().min(b.len());
+            if overlap < MIN_OVERLAP {
+                return None;
+            }
+            let errors: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+            Some(errors as f64 / (overlap * (BANDS - 1)) as f64)
+        })
+        .min_by(|x, y| x.total_cmp(y))
+}
+
+/// Whether two tracks are close enough in length to be the same recording
+fn similar_length(a: Option<u64>, b: Option<u64>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.abs_diff(b) <= (a.max(b) / 50).max(2000),
+        _ => true,
+    }
+}
+
+/// Group the paths of recordings that sound the same, e.g. one track encoded at several bitrates
+/// Groups (of two or more paths) and their paths are sorted.
+pub fn similar_groups(prints: &[AudioFingerprint]) -> Vec<Vec<String>> {
+    let mut tracks: Vec<(&AudioFingerprint, Vec<u32>)> = prints
+        .iter()
+        .map(|print| (print, print.words()))
+        .filter(|(_, words)| words.len() >= MIN_OVERLAP)
+        .collect();
+    // Sorted by length, only tracks of similar length need comparing (unknown lengths go last)
+    tracks.sort_by_key(|(print, _)| (print.duration_ms.is_none(), print.duration_ms));
+
+    let mut parent: Vec<usize> = (0..tracks.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let first_unknown = tracks.iter().position(|(print, _)| print.duration_ms.is_none()).unwrap_or(tracks.len());
+    for i in 0..tracks.len() {
+        let mut j = i + 1;
+        while j < tracks.len() {
+            let (a, b) = (&tracks[i], &tracks[j]);
+            if !similar_length(a.0.duration_ms, b.0.duration_ms) {
+                // Every later track with a known length is longer still
+                j = first_unknown.max(j + 1);
+                continue;
+            }
+            if bit_error_rate(&a.1, &b.1).is_some_and(|rate| rate <= MAX_BIT_ERROR_RATE) {
+                let (x, y) = (root(&mut parent, i), root(&mut parent, j));
+                parent[x] = y;
+            }
+            j += 1;
+        }
+    }
+
+    let mut groups: std::collections::BTreeMap<usize, Vec<String>> = std::collections::BTreeMap::new();
+    for (i, (print, _)) in tracks.iter().enumerate() {
+        let group = root(&mut parent, i);
+        groups.entry(group).or_default().push(print.path.clone());
+    }
+    let mut groups: Vec<Vec<String>> = groups.into_values().filter(|paths| paths.len() > 1).collect();
+    groups.iter_mut().for_each(|paths| paths.sort());
+    groups.sort();
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A few seconds of a melody at SAMPLE_RATE: notes with harmonics (so every band carries
+    /// energy), changing every quarter second
+    fn melody(notes: &[f32], gain: f32, noise: f32) -> Vec<f32> {
+        let mut seed = 1u32;
+        (0..SAMPLE_RATE * 8)
+            .map(|i| {
+                let note = notes[(i * 4 / SAMPLE_RATE) % notes.len()];
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let tone: f32 = (1..=12).map(|k| (2.0 * PI * note / 2.0 * k as f32 * t).sin() / k as f32).sum();
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let hiss = (seed >> 16) as f32 / 65_536.0 - 0.5;
+                gain * tone + noise * hiss
+            })
+            .collect()
+    }
+
+    fn print(path: &str, samples: &[f32]) -> AudioFingerprint {
+        let bytes: Vec<u8> = fingerprint_samples(samples).iter().flat_map(|word| word.to_le_bytes()).collect();
+        AudioFingerprint {
+            path: path.to_string(),
+            duration_ms: Some(8000),
+            fingerprint: base64::engine::general_purpose::STANDARD.encode(bytes),
+        }
+    }
+
+    #[test]
+    fn test_is_audio() {
+        assert!(is_audio("music/Track 01.MP3"));
+        assert!(is_audio("a.flac"));
+        assert!(!is_audio("a.mp4"));
+    }
+
+    #[test]
+    fn test_similar_groups() {
+        let tune = [440.0, 523.0, 659.0, 784.0, 659.0, 523.0, 392.0, 330.0];
+        let other = [349.0, 1047.0, 880.0, 311.0, 1175.0, 466.0];
+        let prints = vec![
+            print("a/original.flac", &melody(&tune, 0.8, 0.0)),
+            // The same tune, quieter and with noise, as a lossy encoding would leave it
+            print("b/quiet.mp3", &melody(&tune, 0.3, 0.05)),
+            print("c/other.mp3", &melody(&other, 0.8, 0.0)),
+        ];
+        assert_eq!(similar_groups(&prints), vec![vec!["a/original.flac".to_string(), "b/quiet.mp3".to_string()]]);
+
+        // Tracks of very different length are never compared
+        let mut long = prints[1].clone();
+        long.duration_ms = Some(200_000);
+        assert!(similar_groups(&[prints[0].clone(), long]).is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::index::{Index, Tombstone, OCI_DIR};
+use crate::registry;
+use crate::roots::Roots;
+
+/// Where content that left the repository can be found now
+#[derive(Debug, Clone, PartialEq)]
+pub enum Whereabouts {
+    /// Still in this repository under another path
+    Moved(String),
+    /// Moved aside by `prune` and not yet purged
+    Pruneyard(PathBuf),
+    /// Indexed in another registered repository
+    Elsewhere(PathBuf),
+    /// Not found anywhere that was searched
+    Lost,
+}
+
+/// A departed file and where its content is now
+#[derive(Debug, Clone)]
+pub struct Departure {
+    pub tombstone: Tombstone,
+    pub whereabouts: Whereabouts,
+}
+
+/// Trace every departed file of a repository, returning the departures (by path) and the
+/// number of registered repositories that could not be searched because they are not mounted
+/// Content that came back to its old path is no longer departed and is left out.
+pub fn departed(index: &Index, repo_root: &Path) -> Result<(Vec<Departure>, usize)> {
+    // Only the latest tombstone counts when the same content left the same path repeatedly
+    let mut seen = HashSet::new();
+    let mut tombstones: Vec<Tombstone> = index.tombstones()?
+        .into_iter()
+        .rev()
+        .filter(|t| seen.insert((t.path.clone(), t.sha256.clone())))
+        .collect();
+    tombstones.sort_by(|a, b| a.path.cmp(&b.path).then(a.removed.cmp(&b.removed)));
+
+    let this_repo = repo_root.canonicalize().unwrap_or_else(|_| repo_root.to_path_buf());
+    let mut others = Vec::new();
+    let mut unmounted = 0;
+    for repo in registry::load()? {
+        if repo.canonicalize().is_ok_and(|repo| repo == this_repo) {
+            continue;
+        }
+        if !registry::is_available(&repo) {
+            unmounted += 1;
+            continue;
+        }
+        let opened = Config::load_if_present(&repo)
+            .and_then(|config| Ok((config.unwrap_or_else(Config::new), Index::load(&repo)?)));
+        match opened {
+            Ok((config, other)) => others.push((Roots::from_config(repo, &config), other)),
+            Err(e) => tracing::warn!("Skipping repository {}: {:#}", repo.display(), e),
+        }
+    }
+
+    let pruneyard = repo_root.join(OCI_DIR).join("pruneyard");
+    let mut departures = Vec::new();
+    for tombstone in tombstones {
+        if index.get(&tombstone.path)?.is_some_and(|entry| entry.sha256 == tombstone.sha256) {
+            continue;
+        }
+        let whereabouts = locate(index, &pruneyard, &others, &tombstone)?;
+        departures.push(Departure { tombstone, whereabouts });
+    }
+    Ok((departures, unmounted))
+}
+
+/// Look for departed content here first, then in the pruneyard, then in other repositories
+fn locate(index: &Index, pruneyard: &Path, others: &[(Roots, Index)], tombstone: &Tombstone) -> Result<Whereabouts> {
+    if let Some(entry) = index.find_by_hash(&tombstone.sha256)?.into_iter().next() {
+        return Ok(Whereabouts::Moved(entry.path));
+    }
+
+    // The pruneyard is not indexed; a file of the right size at the pruned path is taken as the content
+    let pruned = pruneyard.join(&tombstone.path);
+    if std::fs::metadata(&pruned).is_ok_and(|metadata| metadata.is_file() && metadata.len() == tombstone.num_bytes) {
+        return Ok(Whereabouts::Pruneyard(pruned));
+    }
+
+    for (roots, other) in others {
+        if let Some(entry) = other.find_by_hash(&tombstone.sha256)?.into_iter().next() {
+            return Ok(Whereabouts::Elsewhere(roots.full_path(&entry.path)));
+        }
+    }
+    Ok(Whereabouts::Lost)
+}
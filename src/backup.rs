@@ -0,0 +1,194 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::CONFIG_FILE;
+use crate::index::OCI_DIR;
+use crate::oplog::format_timestamp;
+use crate::store::Backend;
+
+/// Directory under `.oci` holding copies of the index taken before commands that remove data from it
+pub const BACKUPS_DIR: &str = "backups";
+
+/// A copy of the index file in `.oci/backups`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    /// File name: `<UTC time>-<command>-<index file name>`, e.g. `2024-05-01-183000-gc-index.db`
+    pub name: String,
+    pub path: PathBuf,
+    /// Backend whose index file it copies
+    pub backend: Backend,
+}
+
+/// Scratch repository under `.oci` where a backup is opened before it replaces the index
+const STAGING_DIR: &str = "restoring";
+
+pub fn backups_dir(repo_root: &Path) -> PathBuf {
+    repo_root.join(OCI_DIR).join(BACKUPS_DIR)
+}
+
+/// Copy the index before `command` (e.g. "gc") changes it, then delete all but the newest `keep`
+/// backups. Nothing is copied when `keep` is 0 or there is no index file yet.
+pub fn create(repo_root: &Path, command: &str, keep: usize, now: u64) -> Result<Option<PathBuf>> {
+    let backend = Backend::detect(repo_root);
+    let source = backend.index_path(repo_root);
+    if keep == 0 || !source.is_file() {
+        return Ok(None);
+    }
+    let dir = backups_dir(repo_root);
+    fs::create_dir_all(&dir).context(format!("Failed to create {}", dir.display()))?;
+
+    let stamp = format_timestamp(now).replace(' ', "-").replace(':', "");
+    let mut dest = dir.join(format!("{}-{}-{}", stamp, command, backend.file_name()));
+    let mut n = 1;
+    // A second backup within the same second gets a number after the time, which sorts after it
+    while dest.exists() {
+        n += 1;
+        dest = dir.join(format!("{}.{}-{}-{}", stamp, n, command, backend.file_name()));
+    }
+    copy_index(&source, backend, &dest).context(format!("Failed to back up the index to {}", dest.display()))?;
+
+    let backups = list(repo_root)?;
+    for old in &backups[..backups.len().saturating_sub(keep)] {
+        fs::remove_file(&old.path).context(format!("Failed to remove old backup {}", old.path.display()))?;
+    }
+    Ok(Some(dest))
+}
+
+/// Every backup of a repository, oldest first (names start with the time they were taken)
+pub fn list(repo_root: &Path) -> Result<Vec<Backup>> {
+    let dir = backups_dir(repo_root);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(&dir).context(format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let backend = [Backend::Sqlite, Backend::Log, Backend::Encrypted]
+            .into_iter()
+            .find(|backend| name.ends_with(&format!("-{}", backend.file_name())));
+        if let (Some(backend), true) = (backend, entry.file_type()?.is_file()) {
+            backups.push(Backup { name, path: entry.path(), backend });
+        }
+    }
+    backups.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(backups)
+}
+
+/// The backup named `name`, or the only one whose name starts with it (e.g. its time)
+pub fn find(backups: &[Backup], name: &str) -> Result<Backup> {
+    if let Some(backup) = backups.iter().find(|backup| backup.name == name) {
+        return Ok(backup.clone());
+    }
+    let matches: Vec<&Backup> = backups.iter().filter(|backup| backup.name.starts_with(name)).collect();
+    match matches.as_slice() {
+        [backup] => Ok((*backup).clone()),
+        [] => bail!("No backup named {} (see 'oci restore-index')", name),
+        _ => bail!(
+            "{} matches several backups: {}",
+            name,
+            matches.iter().map(|backup| backup.name.as_str()).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+/// Copy a backup into a scratch repository with this repository's config (which names the
+/// keyfile and the SQLite settings), where it can be opened without touching the index.
+/// Returns the scratch repository's root.
+pub fn stage(repo_root: &Path, backup: &Backup) -> Result<PathBuf> {
+    // SQLite takes a file too short to have a header for an empty database
+    if backup.backend == Backend::Sqlite && !has_sqlite_header(&backup.path)? {
+        bail!("Backup {} is not a SQLite database", backup.name);
+    }
+    let staging = repo_root.join(OCI_DIR).join(STAGING_DIR);
+    if staging.exists() {
+        fs::remove_dir_all(&staging).context(format!("Failed to remove {}", staging.display()))?;
+    }
+    fs::create_dir_all(staging.join(OCI_DIR)).context(format!("Failed to create {}", staging.display()))?;
+    let config = repo_root.join(OCI_DIR).join(CONFIG_FILE);
+    if config.is_file() {
+        fs::copy(&config, staging.join(OCI_DIR).join(CONFIG_FILE))?;
+    }
+    fs::copy(&backup.path, backup.backend.index_path(&staging))
+        .context(format!("Failed to copy backup {}", backup.path.display()))?;
+    Ok(staging)
+}
+
+/// Replace the index with the one staged by `stage`, then remove the scratch repository.
+/// The index files of every backend go first, including SQLite's write-ahead log, which would
+/// otherwise be applied on top of the restored database.
+pub fn install(repo_root: &Path, staging: &Path, backend: Backend) -> Result<()> {
+    for existing in [Backend::Sqlite, Backend::Log, Backend::Encrypted] {
+        let path = existing.index_path(repo_root);
+        for suffix in ["", "-wal", "-shm"] {
+            let path = PathBuf::from(format!("{}{}", path.display(), suffix));
+            if path.exists() {
+                fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+            }
+        }
+    }
+    fs::rename(backend.index_path(staging), backend.index_path(repo_root))
+        .context("Failed to move the restored index into place")?;
+    fs::remove_dir_all(staging).context(format!("Failed to remove {}", staging.display()))?;
+    Ok(())
+}
+
+fn has_sqlite_header(path: &Path) -> Result<bool> {
+    let mut header = [0u8; 16];
+    let mut file = fs::File::open(path).context(format!("Failed to open {}", path.display()))?;
+    Ok(std::io::Read::read_exact(&mut file, &mut header).is_ok() && &header == b"SQLite format 3\0")
+}
+
+/// A SQLite index is copied through SQLite, so changes still in its write-ahead log are included
+/// and the copy is consistent (a database SQLite can't read is copied as it is, damage and all);
+/// the other backends are single files written as a whole or appended to
+fn copy_index(source: &Path, backend: Backend, dest: &Path) -> Result<()> {
+    match backend {
+        Backend::Sqlite => {
+            let vacuumed = rusqlite::Connection::open_with_flags(source, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .and_then(|conn| conn.execute("VACUUM INTO ?1", [dest.to_string_lossy()]));
+            if vacuumed.is_err() {
+                let _ = fs::remove_file(dest);
+                fs::copy(source, dest)?;
+            }
+        }
+        Backend::Log | Backend::Encrypted => {
+            fs::copy(source, dest)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{FileEntry, Index};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_create_rotates_backups() {
+        let repo = TempDir::new().unwrap();
+        fs::create_dir_all(repo.path().join(OCI_DIR)).unwrap();
+        assert_eq!(create(repo.path(), "gc", 2, 0).unwrap(), None);
+
+        let mut index = Index::open(repo.path(), Backend::Sqlite).unwrap();
+        index.upsert(FileEntry { path: "a.txt".to_string(), sha256: "aa".to_string(), ..Default::default() }).unwrap();
+        index.save(repo.path()).unwrap();
+
+        let first = create(repo.path(), "gc", 2, 1_000).unwrap().unwrap();
+        assert_eq!(first.file_name().unwrap(), "1970-01-01-000001-gc-index.db");
+        let second = create(repo.path(), "gc", 2, 1_000).unwrap().unwrap();
+        assert_eq!(second.file_name().unwrap(), "1970-01-01-000001.2-gc-index.db");
+        create(repo.path(), "reset", 2, 60_000).unwrap();
+        let names: Vec<String> = list(repo.path()).unwrap().into_iter().map(|backup| backup.name).collect();
+        assert_eq!(names, vec!["1970-01-01-000001.2-gc-index.db", "1970-01-01-000100-reset-index.db"]);
+
+        let copy = TempDir::new().unwrap();
+        fs::create_dir_all(copy.path().join(OCI_DIR)).unwrap();
+        fs::copy(&second, Backend::Sqlite.index_path(copy.path())).unwrap();
+        let restored = Index::open(copy.path(), Backend::Sqlite).unwrap();
+        assert_eq!(restored.get("a.txt").unwrap().unwrap().sha256, "aa");
+        assert_eq!(create(repo.path(), "gc", 0, 120_000).unwrap(), None);
+    }
+}
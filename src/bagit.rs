@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::digest::Algorithm;
+
+/// Version of the BagIt specification (RFC 8493) bags are written in
+const BAGIT_VERSION: &str = "1.0";
+
+/// Directory of a bag holding the payload
+pub const PAYLOAD_DIR: &str = "data";
+
+/// A file of a bag's payload
+pub struct PayloadFile {
+    /// Path below the payload directory, with `/` separators
+    pub path: String,
+    /// Hex digest in the bag's manifest algorithm
+    pub digest: String,
+    pub num_bytes: u64,
+}
+
+/// Write the tag files of a bag whose payload is already in place: `bagit.txt`, `bag-info.txt`,
+/// the payload manifest in `algorithm`, and a SHA256 tag manifest of those three.
+/// `date` is the bagging date (YYYY-MM-DD).
+pub fn write_tag_files(bag: &Path, algorithm: Algorithm, payload: &[PayloadFile], date: &str) -> Result<()> {
+    let tag_files = [
+        ("bagit.txt".to_string(), format!("BagIt-Version: {}\nTag-File-Character-Encoding: UTF-8\n", BAGIT_VERSION)),
+        ("bag-info.txt".to_string(), bag_info(payload, date)),
+        (format!("manifest-{}.txt", algorithm), manifest(payload)),
+    ];
+    let mut tag_manifest = String::new();
+    for (name, contents) in &tag_files {
+        let path = bag.join(name);
+        fs::write(&path, contents).context(format!("Failed to write {}", path.display()))?;
+        tag_manifest.push_str(&format!("{:x}  {}\n", Sha256::digest(contents.as_bytes()), name));
+    }
+    let path = bag.join("tagmanifest-sha256.txt");
+    fs::write(&path, tag_manifest).context(format!("Failed to write {}", path.display()))
+}
+
+/// `<digest>  data/<path>` lines, one per payload file
+fn manifest(payload: &[PayloadFile]) -> String {
+    payload
+        .iter()
+        .map(|file| format!("{}  {}/{}\n", file.digest, PAYLOAD_DIR, encode_path(&file.path)))
+        .collect()
+}
+
+/// The bag's metadata, with the payload's total size and file count as its Payload-Oxum
+fn bag_info(payload: &[PayloadFile], date: &str) -> String {
+    let num_bytes: u64 = payload.iter().map(|file| file.num_bytes).sum();
+    format!(
+        "Bagging-Date: {}\nBag-Software-Agent: oci {}\nPayload-Oxum: {}.{}\n",
+        date,
+        env!("CARGO_PKG_VERSION"),
+        num_bytes,
+        payload.len()
+    )
+}
+
+/// A path as manifests must write it: with `%`, carriage returns and line feeds percent-encoded
+fn encode_path(path: &str) -> String {
+    path.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_tag_files() {
+        let bag = TempDir::new().unwrap();
+        let payload = vec![
+            PayloadFile { path: "a.txt".to_string(), digest: "aa".to_string(), num_bytes: 3 },
+            PayloadFile { path: "100%\nsure.txt".to_string(), digest: "bb".to_string(), num_bytes: 4 },
+        ];
+        write_tag_files(bag.path(), Algorithm::Md5, &payload, "2024-05-01").unwrap();
+
+        let read = |name: &str| fs::read_to_string(bag.path().join(name)).unwrap();
+        assert_eq!(read("bagit.txt"), "BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n");
+        assert_eq!(read("manifest-md5.txt"), "aa  data/a.txt\nbb  data/100%25%0Asure.txt\n");
+        assert!(read("bag-info.txt").contains("Bagging-Date: 2024-05-01\n"));
+        assert!(read("bag-info.txt").contains("Payload-Oxum: 7.2\n"));
+        let tag_manifest = read("tagmanifest-sha256.txt");
+        assert_eq!(tag_manifest.lines().count(), 3);
+        assert!(tag_manifest.contains(&format!("{:x}  bagit.txt", Sha256::digest(read("bagit.txt").as_bytes()))));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::display::{format_bytes, format_bytes_iec, human_readable};
+use crate::index::{dir_prefix, parent_dir, FileEntry};
+use crate::oplog::format_local_timestamp;
+
+/// Group shown for files without an extension
+const NO_EXTENSION: &str = "(none)";
+/// Group shown for files whose content type was not detected
+const UNKNOWN_TYPE: &str = "(unknown)";
+/// Group shown for files directly in the repository root
+const TOP_LEVEL: &str = ".";
+
+/// Totals for one group of a `stats` breakdown
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GroupTotals {
+    pub files: usize,
+    pub bytes: u64,
+    /// Bytes taken by extra copies of content stored elsewhere in the index
+    pub wasted: u64,
+}
+
+/// Totals per lowercased extension (e.g. ".cr2"), largest first
+pub fn by_extension(entries: &[FileEntry]) -> Vec<(String, GroupTotals)> {
+    group(entries, |entry| {
+        Path::new(&entry.path)
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy().to_lowercase()))
+            .unwrap_or_else(|| NO_EXTENSION.to_string())
+    })
+}
+
+/// Totals per detected content type (e.g. "image/jpeg"), largest first
+pub fn by_type(entries: &[FileEntry]) -> Vec<(String, GroupTotals)> {
+    group(entries, |entry| entry.mime.clone().unwrap_or_else(|| UNKNOWN_TYPE.to_string()))
+}
+
+/// Totals per directory, cut to its first `depth` components (files deeper are counted in their ancestor), largest first
+pub fn by_dir(entries: &[FileEntry], depth: usize) -> Vec<(String, GroupTotals)> {
+    group(entries, |entry| {
+        let dir = parent_dir(&entry.path);
+        if dir.is_empty() {
+            TOP_LEVEL.to_string()
+        } else {
+            dir.split('/').take(depth.max(1)).collect::<Vec<_>>().join("/")
+        }
+    })
+}
+
+/// Totals per year of last modification (local time), oldest first
+pub fn by_year(entries: &[FileEntry]) -> Vec<(String, GroupTotals)> {
+    let mut groups = group(entries, |entry| modified_year(entry.modified));
+    groups.sort_by(|a, b| a.0.cmp(&b.0));
+    groups
+}
+
+/// The oldest and newest entries by modification time, if there are any
+pub fn age_range(entries: &[FileEntry]) -> Option<(&FileEntry, &FileEntry)> {
+    let oldest = entries.iter().min_by(|a, b| a.modified.cmp(&b.modified).then_with(|| a.path.cmp(&b.path)))?;
+    let newest = entries.iter().max_by(|a, b| a.modified.cmp(&b.modified).then_with(|| b.path.cmp(&a.path)))?;
+    Some((oldest, newest))
+}
+
+fn modified_year(millis: u64) -> String {
+    format_local_timestamp(millis).chars().take(4).collect()
+}
+
+/// Totals per directory below `base` (an index path, "" for the repository root), by path, for `du`.
+/// Each file counts in every directory containing it down to `depth` levels below `base` (unlimited
+/// if None); the totals for `base` itself come last, keyed by "".
+pub fn rollup(entries: &[FileEntry], base: &str, depth: Option<usize>) -> Vec<(String, GroupTotals)> {
+    let prefix = dir_prefix(base);
+    let mut dirs: BTreeMap<String, GroupTotals> = BTreeMap::new();
+    let mut total = GroupTotals::default();
+    for entry in entries {
+        let Some(rel) = entry.path.strip_prefix(&prefix) else { continue };
+        total.files += 1;
+        total.bytes += entry.num_bytes;
+        let components: Vec<&str> = rel.split('/').collect();
+        let levels = (components.len() - 1).min(depth.unwrap_or(usize::MAX));
+        for level in 1..=levels {
+            let totals = dirs.entry(components[..level].join("/")).or_default();
+            totals.files += 1;
+            totals.bytes += entry.num_bytes;
+        }
+    }
+    let mut rows: Vec<_> = dirs.into_iter().collect();
+    rows.push((String::new(), total));
+    rows
+}
+
+/// Sum entries per group
+/// Of the files sharing a full hash, the first by path counts as the original and the others as waste.
+fn group(entries: &[FileEntry], key: impl Fn(&FileEntry) -> String) -> Vec<(String, GroupTotals)> {
+    let mut first_by_hash: HashMap<&str, &str> = HashMap::new();
+    for entry in entries.iter().filter(|e| !e.sha256.is_empty()) {
+        let first = first_by_hash.entry(&entry.sha256).or_insert(&entry.path);
+        if entry.path.as_str() < *first {
+            *first = &entry.path;
+        }
+    }
+
+    let mut groups: HashMap<String, GroupTotals> = HashMap::new();
+    for entry in entries {
+        let totals = groups.entry(key(entry)).or_default();
+        totals.files += 1;
+        totals.bytes += entry.num_bytes;
+        if first_by_hash.get(entry.sha256.as_str()).is_some_and(|first| *first != entry.path) {
+            totals.wasted += entry.num_bytes;
+        }
+    }
+
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(&b.0)));
+    groups
+}
+
+/// Table lines for a breakdown, with each group's share of the total size
+pub fn lines(heading: &str, groups: &[(String, GroupTotals)]) -> Vec<String> {
+    let total: u64 = groups.iter().map(|(_, totals)| totals.bytes).sum();
+    let size = if human_readable() { format_bytes_iec } else { format_bytes };
+    let width = groups.iter().map(|(name, _)| name.chars().count()).chain([heading.len()]).max().unwrap_or(0);
+
+    let mut lines = vec![format!("  {:<width$} {:>8} {:>12} {:>6} {:>12}", heading, "Files", "Size", "Share", "Duplicates", width = width)];
+    for (name, totals) in groups {
+        let share = if total > 0 { totals.bytes as f64 / total as f64 * 100.0 } else { 0.0 };
+        lines.push(format!(
+            "  {:<width$} {:>8} {:>12} {:>5.1}% {:>12}",
+            name,
+            totals.files,
+            size(totals.bytes),
+            share,
+            size(totals.wasted),
+            width = width
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, num_bytes: u64, sha256: &str) -> FileEntry {
+        FileEntry { path: path.to_string(), num_bytes, sha256: sha256.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_breakdowns() {
+        let entries = vec![
+            entry("2017/raw/a.CR2", 100, "aa"),
+            entry("2017/raw/b.cr2", 100, "bb"),
+            entry("backup/2017/a.cr2", 100, "aa"),
+            entry("notes", 5, ""),
+            entry("2018/c.jpg", 10, "cc"),
+        ];
+
+        assert_eq!(by_extension(&entries), vec![
+            (".cr2".to_string(), GroupTotals { files: 3, bytes: 300, wasted: 100 }),
+            (".jpg".to_string(), GroupTotals { files: 1, bytes: 10, wasted: 0 }),
+            ("(none)".to_string(), GroupTotals { files: 1, bytes: 5, wasted: 0 }),
+        ]);
+
+        let dirs: Vec<(String, usize)> = by_dir(&entries, 1).into_iter().map(|(name, t)| (name, t.files)).collect();
+        assert_eq!(dirs, vec![
+            ("2017".to_string(), 2),
+            ("backup".to_string(), 1),
+            ("2018".to_string(), 1),
+            (".".to_string(), 1),
+        ]);
+        assert_eq!(by_dir(&entries, 2)[0].0, "2017/raw");
+        // The copy under 2017/ sorts first, so the backup copy is the waste
+        assert_eq!(by_dir(&entries, 1)[1].1.wasted, 100);
+    }
+
+    #[test]
+    fn test_rollup() {
+        let entries = vec![
+            entry("photos/2017/raw/a.cr2", 100, "aa"),
+            entry("photos/2017/b.jpg", 10, "bb"),
+            entry("photos/2018/c.jpg", 20, "cc"),
+            entry("photos/notes.txt", 1, "dd"),
+            entry("photoshop/d.psd", 50, "ee"),
+        ];
+
+        let rows: Vec<(String, usize, u64)> = rollup(&entries, "photos", Some(1)).into_iter().map(|(dir, t)| (dir, t.files, t.bytes)).collect();
+        assert_eq!(rows, vec![
+            ("2017".to_string(), 2, 110),
+            ("2018".to_string(), 1, 20),
+            (String::new(), 4, 131),
+        ]);
+
+        let dirs: Vec<String> = rollup(&entries, "", None).into_iter().map(|(dir, _)| dir).collect();
+        assert_eq!(dirs, vec!["photos", "photos/2017", "photos/2017/raw", "photos/2018", "photoshop", ""]);
+    }
+
+    #[test]
+    fn test_by_year_and_age_range() {
+        // Mid-year timestamps, so the local time zone cannot move them across a year boundary
+        let mut old = entry("old.jpg", 10, "aa");
+        old.modified = 1_182_000_000_000; // 2007-06-16
+        let mut newer = entry("newer.jpg", 20, "bb");
+        newer.modified = 1_592_000_000_000; // 2020-06-12
+        let mut newest = entry("newest.jpg", 30, "cc");
+        newest.modified = 1_593_000_000_000; // 2020-06-24
+        let entries = vec![newer, old, newest];
+
+        let years: Vec<(String, usize, u64)> = by_year(&entries).into_iter().map(|(year, t)| (year, t.files, t.bytes)).collect();
+        assert_eq!(years, vec![("2007".to_string(), 1, 10), ("2020".to_string(), 2, 50)]);
+
+        let (oldest, newest) = age_range(&entries).unwrap();
+        assert_eq!((oldest.path.as_str(), newest.path.as_str()), ("old.jpg", "newest.jpg"));
+        assert!(age_range(&[]).is_none());
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::path::{Component, Path};
+
+use crate::index::OCI_DIR;
+
+/// Conventional extension of an index bundle (`export --bundle`)
+pub const BUNDLE_EXTENSION: &str = "ocib";
+
+/// Pack the files of an exported `.oci` directory into a gzipped tar at `bundle`, stored as
+/// `.oci/<name>` so the bundle unpacks into the layout of an export
+pub fn write(oci_dir: &Path, bundle: &Path) -> Result<()> {
+    let mut names: Vec<_> = fs::read_dir(oci_dir)
+        .context(format!("Failed to read {}", oci_dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .map(|entry| entry.file_name())
+        .collect();
+    names.sort();
+
+    // Written next to the destination and renamed, so a failed export leaves no partial bundle
+    let temp = bundle.with_extension(format!("{}.tmp", BUNDLE_EXTENSION));
+    let result = (|| -> Result<()> {
+        let file = File::create(&temp).context(format!("Failed to create {}", temp.display()))?;
+        let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        for name in &names {
+            tar.append_path_with_name(oci_dir.join(name), Path::new(OCI_DIR).join(name))
+                .context(format!("Failed to add {} to the bundle", name.to_string_lossy()))?;
+        }
+        tar.into_inner()?.finish()?.sync_all()?;
+        fs::rename(&temp, bundle).context(format!("Failed to write {}", bundle.display()))
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Unpack a bundle into `dest`, which then holds the bundled `.oci` directory
+/// Only plain files directly inside `.oci` are accepted.
+pub fn extract(bundle: &Path, dest: &Path) -> Result<()> {
+    let file = File::open(bundle).context(format!("Failed to open bundle {}", bundle.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    fs::create_dir_all(dest).context(format!("Failed to create {}", dest.display()))?;
+    let mut found_index = false;
+    for entry in archive.entries().context(format!("{} is not an oci bundle", bundle.display()))? {
+        let mut entry = entry.context(format!("{} is not an oci bundle", bundle.display()))?;
+        let path = entry.path()?.into_owned();
+        let components: Vec<Component> = path.components().collect();
+        let is_plain = matches!(components.as_slice(), [Component::Normal(dir), Component::Normal(_)] if *dir == OCI_DIR);
+        if !is_plain || !entry.header().entry_type().is_file() {
+            bail!("Unexpected entry in bundle {}: {}", bundle.display(), path.display());
+        }
+        found_index |= path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("index."));
+        entry.unpack_in(dest).context(format!("Failed to unpack {}", path.display()))?;
+    }
+    if !found_index {
+        bail!("{} holds no index", bundle.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let oci_dir = dir.path().join("export").join(OCI_DIR);
+        fs::create_dir_all(oci_dir.join("keys")).unwrap();
+        fs::write(oci_dir.join("index.db"), "database").unwrap();
+        fs::write(oci_dir.join("ignore"), "*.tmp\n").unwrap();
+
+        let bundle = dir.path().join("archive.ocib");
+        write(&oci_dir, &bundle).unwrap();
+        assert!(!dir.path().join("archive.ocib.tmp").exists());
+
+        let unpacked = dir.path().join("unpacked");
+        extract(&bundle, &unpacked).unwrap();
+        assert_eq!(fs::read_to_string(unpacked.join(OCI_DIR).join("index.db")).unwrap(), "database");
+        assert_eq!(fs::read_to_string(unpacked.join(OCI_DIR).join("ignore")).unwrap(), "*.tmp\n");
+        assert!(!unpacked.join(OCI_DIR).join("keys").exists());
+
+        // Anything that isn't a bundle is refused
+        fs::write(dir.path().join("notes.txt"), "not a bundle").unwrap();
+        assert!(extract(&dir.path().join("notes.txt"), &dir.path().join("other")).is_err());
+    }
+}
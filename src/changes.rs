@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::index::OCI_DIR;
+
+/// File in `.oci` recording where the change journal of each scanned directory stood when the
+/// last update of the whole repository started
+pub const CHANGES_FILE: &str = "changes";
+
+/// Whether this platform has a change journal `update --fast` can read
+pub const SUPPORTED: bool = cfg!(any(target_os = "macos", windows));
+
+/// Name of the journal, for messages
+pub const JOURNAL: &str = if cfg!(windows) { "USN journal" } else { "FSEvents" };
+
+/// Whether this platform can watch trees for changes as they happen (`oci watch`)
+pub const WATCH_SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
+/// Name of the mechanism behind `Watcher`, for messages
+pub const WATCHER: &str = if cfg!(target_os = "linux") { "inotify" } else { "FSEvents" };
+
+#[cfg(target_os = "linux")]
+pub use crate::inotify::Watcher;
+#[cfg(target_os = "macos")]
+pub use crate::fsevents::Watcher;
+
+/// Stand-in for platforms without a watcher (`WATCH_SUPPORTED` is false)
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub struct Watcher;
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+impl Watcher {
+    pub fn start(_roots: &[PathBuf], _skip_dir: Box<dyn Fn(&Path) -> bool>) -> Result<Self> {
+        anyhow::bail!("Watching for changes is not supported on this platform")
+    }
+
+    pub fn watched_dirs(&self) -> usize {
+        0
+    }
+
+    pub fn collect(&mut self, _period: std::time::Duration) -> Result<Vec<ChangedDir>> {
+        Ok(Vec::new())
+    }
+}
+
+/// A position in a volume's change journal
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /// Journal the position belongs to (e.g. "fsevents")
+    pub backend: String,
+    /// Identity of the volume's journal; positions from another volume (or a reformatted one) are meaningless
+    pub volume: String,
+    pub position: u64,
+}
+
+/// A directory the journal reports as changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangedDir {
+    pub path: PathBuf,
+    /// Whether everything below it has to be rescanned, because the journal coalesced or dropped events
+    pub recursive: bool,
+}
+
+/// The current journal position of the volume holding `dir` (None where there is no journal)
+pub fn current(dir: &Path) -> Result<Option<Cursor>> {
+    #[cfg(target_os = "macos")]
+    return crate::fsevents::current(dir);
+    #[cfg(windows)]
+    return crate::usn::current(dir);
+    #[cfg(not(any(target_os = "macos", windows)))]
+    {
+        let _ = dir;
+        Ok(None)
+    }
+}
+
+/// Directories below `dir` changed since `cursor`, or None if the journal can't tell (its history
+/// was purged, the volume changed, or events for the whole tree were dropped)
+pub fn changed_since(dir: &Path, cursor: &Cursor) -> Result<Option<Vec<ChangedDir>>> {
+    #[cfg(target_os = "macos")]
+    return Ok(crate::fsevents::changed_since(dir, cursor)?.map(collapse));
+    #[cfg(windows)]
+    return Ok(crate::usn::changed_since(dir, cursor)?.map(collapse));
+    #[cfg(not(any(target_os = "macos", windows)))]
+    {
+        let _ = (dir, cursor);
+        Ok(None)
+    }
+}
+
+/// The positions recorded by the last update of the whole repository, by scanned directory
+pub fn load(repo_root: &Path) -> Result<HashMap<PathBuf, Cursor>> {
+    let path = repo_root.join(OCI_DIR).join(CHANGES_FILE);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+    };
+    // `<backend>\t<volume>\t<position>\t<directory>`; anything unreadable just means a full scan
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let backend = fields.next()?.to_string();
+            let volume = fields.next()?.to_string();
+            let position = fields.next()?.parse().ok()?;
+            let dir = PathBuf::from(fields.next()?);
+            Some((dir, Cursor { backend, volume, position }))
+        })
+        .collect())
+}
+
+/// Record the positions taken before an update of the whole repository
+pub fn save(repo_root: &Path, cursors: &[(PathBuf, Cursor)]) -> Result<()> {
+    if cursors.is_empty() {
+        return forget(repo_root);
+    }
+    let contents: String = cursors
+        .iter()
+        .map(|(dir, cursor)| format!("{}\t{}\t{}\t{}\n", cursor.backend, cursor.volume, cursor.position, dir.display()))
+        .collect();
+    let path = repo_root.join(OCI_DIR).join(CHANGES_FILE);
+    fs::write(&path, contents).context(format!("Failed to write {}", path.display()))
+}
+
+/// Drop the recorded positions, so the next `update --fast` scans everything (after the index
+/// stopped reflecting the tree, e.g. on `reset` or `snapshot restore`)
+pub fn forget(repo_root: &Path) -> Result<()> {
+    match fs::remove_file(repo_root.join(OCI_DIR).join(CHANGES_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).context("Failed to remove change journal positions"),
+        _ => Ok(()),
+    }
+}
+
+/// Sort and deduplicate changed directories, dropping those a recursive rescan already covers
+pub fn collapse(mut dirs: Vec<ChangedDir>) -> Vec<ChangedDir> {
+    dirs.sort_by(|a, b| a.path.cmp(&b.path).then_with(|| b.recursive.cmp(&a.recursive)));
+    let mut result: Vec<ChangedDir> = Vec::new();
+    for dir in dirs {
+        let covered = result.iter().any(|kept| {
+            kept.path == dir.path || (kept.recursive && dir.path.starts_with(&kept.path))
+        });
+        if !covered {
+            result.push(dir);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn changed(path: &str, recursive: bool) -> ChangedDir {
+        ChangedDir { path: PathBuf::from(path), recursive }
+    }
+
+    #[test]
+    fn test_collapse_and_cursors() {
+        let dirs = collapse(vec![
+            changed("/r/photos/2020", false),
+            changed("/r/docs", false),
+            changed("/r/photos", true),
+            changed("/r/docs", false),
+            changed("/r/docs/old", false),
+        ]);
+        assert_eq!(dirs, vec![changed("/r/docs", false), changed("/r/docs/old", false), changed("/r/photos", true)]);
+
+        let repo = TempDir::new().unwrap();
+        fs::create_dir_all(repo.path().join(OCI_DIR)).unwrap();
+        assert!(load(repo.path()).unwrap().is_empty());
+        let cursor = Cursor { backend: "fsevents".to_string(), volume: "A1B2".to_string(), position: 42 };
+        save(repo.path(), &[(PathBuf::from("/Volumes/My Drive"), cursor.clone())]).unwrap();
+        assert_eq!(load(repo.path()).unwrap().get(Path::new("/Volumes/My Drive")), Some(&cursor));
+        forget(repo.path()).unwrap();
+        assert!(load(repo.path()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::file_utils;
+use crate::index::{Index, OCI_DIR};
+
+const CHECKS_DIR: &str = "checks";
+
+/// How serious a finding is; any error fails `oci check`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        })
+    }
+}
+
+/// One line of check output: `{"severity": "error", "path": "a/b.jpg", "message": "..."}`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FindingLine {
+    severity: Severity,
+    #[serde(default)]
+    path: Option<String>,
+    message: String,
+}
+
+/// A finding reported by an external check
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// Name of the check executable that reported it
+    pub check: String,
+    pub severity: Severity,
+    /// Index path the finding is about (None for findings about the whole index)
+    pub path: Option<String>,
+    pub message: String,
+}
+
+/// List the executables in `.oci/checks`, sorted by name (hidden files are skipped)
+pub fn discover(repo_root: &Path) -> Result<Vec<PathBuf>> {
+    let checks_dir = repo_root.join(OCI_DIR).join(CHECKS_DIR);
+    if !checks_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut checks = Vec::new();
+    for entry in fs::read_dir(&checks_dir).context("Failed to read .oci/checks")? {
+        let path = entry.context("Failed to read .oci/checks")?.path();
+        let hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if !hidden && path.is_file() && file_utils::is_executable(&path) {
+            checks.push(path);
+        }
+    }
+    checks.sort();
+    Ok(checks)
+}
+
+/// Run a check and collect its findings
+/// The check runs in the repository root with the index location passed as its only argument
+/// and in `OCI_INDEX` (with the repository root in `OCI_REPO_ROOT`); `oci check` refuses encrypted
+/// indexes, whose file a check could not read. A check that cannot be run,
+/// prints malformed output, or fails without reporting an error produces an error finding itself.
+pub fn run(check: &Path, repo_root: &Path) -> Vec<Finding> {
+    let name = check.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let index_path = Index::database_path(repo_root);
+    let check_error = |message: String| Finding {
+        check: name.clone(),
+        severity: Severity::Error,
+        path: None,
+        message,
+    };
+
+    let output = match Command::new(check)
+        .arg(&index_path)
+        .env("OCI_INDEX", &index_path)
+        .env("OCI_REPO_ROOT", repo_root)
+        .current_dir(repo_root)
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => return vec![check_error(format!("failed to run: {}", e))],
+    };
+
+    let mut findings = parse_output(&name, &String::from_utf8_lossy(&output.stdout));
+    let reported_error = findings.iter().any(|f| f.severity == Severity::Error);
+    if !output.status.success() && !reported_error {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let detail = stderr.lines().last().map(|l| format!(": {}", l.trim())).unwrap_or_default();
+        findings.push(check_error(format!("exited with {}{}", output.status, detail)));
+    }
+    findings
+}
+
+/// Parse JSON-lines check output (blank lines are ignored)
+fn parse_output(check: &str, stdout: &str) -> Vec<Finding> {
+    stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match serde_json::from_str::<FindingLine>(line) {
+            Ok(finding) => Finding {
+                check: check.to_string(),
+                severity: finding.severity,
+                path: finding.path,
+                message: finding.message,
+            },
+            Err(e) => Finding {
+                check: check.to_string(),
+                severity: Severity::Error,
+                path: None,
+                message: format!("invalid output line ({}): {}", e, line),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let findings = parse_output(
+            "sidecars",
+            "{\"severity\":\"error\",\"path\":\"raw/a.cr2\",\"message\":\"missing a.xmp\"}\n\n{\"severity\":\"info\",\"message\":\"checked 2 files\"}\nnot json\n",
+        );
+
+        assert_eq!(findings.len(), 3);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(findings[0].path.as_deref(), Some("raw/a.cr2"));
+        assert_eq!(findings[1].severity, Severity::Info);
+        assert_eq!(findings[1].path, None);
+        assert_eq!(findings[2].severity, Severity::Error);
+        assert!(findings[2].message.starts_with("invalid output line"));
+    }
+}
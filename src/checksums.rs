@@ -0,0 +1,93 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::digest::Algorithm;
+use crate::file_utils;
+
+/// Base name of the checksum file written into each directory, followed by the format's extension
+pub const FILE_STEM: &str = "checksums";
+
+/// What a directory's checksum file holds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// `<digest>  <name>` lines in an indexed algorithm, as read by sha256sum -c and friends
+    Digests(Algorithm),
+    /// `<name> <CRC32>` lines, as read by cksfv and most SFV checkers
+    Sfv,
+}
+
+impl Format {
+    /// Name of the checksum file in each directory (e.g. `checksums.sha256`)
+    pub fn file_name(&self) -> String {
+        match self {
+            Format::Digests(algorithm) => format!("{}.{}", FILE_STEM, algorithm),
+            Format::Sfv => format!("{}.sfv", FILE_STEM),
+        }
+    }
+
+    /// A checksum file listing `files` (names within the directory and their checksums)
+    pub fn contents(&self, files: &[(String, String)]) -> String {
+        match self {
+            Format::Digests(_) => files.iter().map(|(name, hex)| coreutils_line(hex, name)).collect(),
+            Format::Sfv => {
+                let mut contents = format!("; Generated by oci {}\n", env!("CARGO_PKG_VERSION"));
+                for (name, crc) in files {
+                    contents.push_str(&format!("{} {}\n", name, crc.to_uppercase()));
+                }
+                contents
+            }
+        }
+    }
+}
+
+/// A `<digest>  <path>` line, with GNU coreutils' escaping for paths with backslashes or newlines
+pub fn coreutils_line(hex: &str, path: &str) -> String {
+    if path.contains(['\\', '\n']) {
+        format!("\\{}  {}\n", hex, path.replace('\\', "\\\\").replace('\n', "\\n"))
+    } else {
+        format!("{}  {}\n", hex, path)
+    }
+}
+
+/// The CRC32 of a file as SFV writes it (8 hex digits); the index has no CRC32s, so this reads the file
+pub fn crc32(path: &Path) -> Result<String> {
+    let mut file = File::open(file_utils::long_path(path)).context(format!("Failed to open {}", path.display()))?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer).context(format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:08x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_formats() {
+        let files = vec![
+            ("a.txt".to_string(), "aa".to_string()),
+            ("back\\slash.txt".to_string(), "bb".to_string()),
+        ];
+        let sha256 = Format::Digests(Algorithm::Sha256);
+        assert_eq!(sha256.file_name(), "checksums.sha256");
+        assert_eq!(sha256.contents(&files), "aa  a.txt\n\\bb  back\\\\slash.txt\n");
+
+        assert_eq!(Format::Sfv.file_name(), "checksums.sfv");
+        let sfv = Format::Sfv.contents(&[("a.txt".to_string(), "0d4a1185".to_string())]);
+        assert!(sfv.starts_with("; Generated by oci"));
+        assert!(sfv.ends_with("\na.txt 0D4A1185\n"));
+
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("hello.txt"), b"hello world").unwrap();
+        assert_eq!(crc32(&dir.path().join("hello.txt")).unwrap(), "0d4a1185");
+    }
+}
@@ -1,16 +1,62 @@
 use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
 use walkdir::WalkDir;
+use ed25519_dalek::SigningKey;
 
-use crate::file_utils;
+use crate::file_utils::{self, CaptureOptions};
 use crate::ignore;
-use crate::index::{Index, OCI_DIR};
-use crate::config::Config;
-use crate::scanner::FileScanner;
-use crate::display::{DisplayContext, StatusMarker};
+use crate::index::{dir_prefix, ArchiveMember, FileEntry, Index, OCI_DIR};
+use crate::config::{self, parse_size, ChangeDetection, Config, UserConfig};
+use crate::scanner::{self, FileScanner};
+use crate::error::OciError;
+use crate::display::{self, format_bytes, DisplayContext, OutputFormat, StatusMarker};
 use crate::dir_utils;
+use crate::disposal::Disposal;
+use crate::content_class::{self, ClassSummary, ContentClass};
+use crate::tiering::{self, StorageClass};
+use crate::oplog::{self, Operation};
+use crate::roots::{self, Roots};
+use crate::policy::{Policy, Violation};
+use crate::source::{self, Source, SourceRepo};
+use crate::signing;
+use crate::tags::Tags;
+use crate::dedupe::{self, ExportFormat, KeepPolicy};
+use crate::checks::{self, Severity};
+use crate::store::Backend;
+use crate::lock::{self, RepoLock};
+use crate::legacy;
+use crate::breakdown;
+use crate::suggest;
+use crate::sync;
+use crate::archive;
+use crate::audio::{self, AudioFingerprint};
+use crate::digest::{self, Algorithm};
+use crate::manifest;
+use crate::dup_dirs;
+use crate::snapshot;
+use crate::bagit;
+use crate::checksums;
+use crate::parity::{self, Par2};
+use crate::backup;
+use crate::bundle;
+use crate::hooks::{self, Hook};
+use crate::changes::{self, ChangedDir};
+use crate::daemon;
+use crate::ipc::{self, IndexView};
+use crate::merge::{self, Outcome, Prefer};
+use crate::media::{self, MediaFilter, MediaInfo};
+use crate::registry;
+use crate::audit::{self, Whereabouts};
+use crate::verify::{self, Finding, IoBudget, VerifySummary};
+use crate::pager;
+use crate::progress::{self, Progress};
+use crate::since;
+use crate::hash_cache::{HashCache, InodeKey};
 
 /// Get the logical current directory, preserving symlinks
 /// PWD environment variable contains the logical path, while env::current_dir() resolves symlinks
@@ -31,54 +77,308 @@ fn get_logical_current_dir() -> Result<PathBuf> {
 }
 
 /// Find the repository root by looking for .oci directory
+/// Inside a registered root of a multi-root index, the root marker points at the repository root.
 /// Returns the logical (non-canonicalized) path to preserve user's view through symlinks
 fn find_repo_root() -> Result<PathBuf> {
-    let mut current_dir = get_logical_current_dir()?;
-    
+    find_repo_root_from(get_logical_current_dir()?)
+}
+
+/// Find the repository holding a directory, as `find_repo_root` does for the current one
+fn find_repo_root_from(mut current_dir: PathBuf) -> Result<PathBuf> {
     loop {
         let oci_path = current_dir.join(OCI_DIR);
         if oci_path.exists() && oci_path.is_dir() {
             return Ok(current_dir);
         }
         
+        let marker_path = current_dir.join(roots::ROOT_MARKER);
+        if marker_path.is_file() {
+            let repo_root = roots::read_marker(&marker_path)?;
+            if !repo_root.join(OCI_DIR).is_dir() {
+                bail!("Root marker {} points at a missing index: {}", marker_path.display(), repo_root.display());
+            }
+            return Ok(repo_root);
+        }
+        
         if !current_dir.pop() {
-            bail!("Not in an oci repository (or any parent directory)");
+            return Err(OciError::NotARepository.into());
         }
     }
 }
 
-/// Format bytes in a human-readable format
-fn format_bytes(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
-    
-    if bytes >= GB {
-        format!("{:.2} GB", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.2} MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.2} KB", bytes as f64 / KB as f64)
-    } else {
-        format!("{} bytes", bytes)
-    }
-}
-
 /// Check the version of the index and warn if it doesn't match the tool version
-fn check_version(repo_root: &Path) -> Result<()> {
+/// Returns the loaded config for commands that need repository settings
+fn check_version(repo_root: &Path) -> Result<Config> {
     let config = Config::load(repo_root)?;
+    config.markers.clone().install();
+    display::set_color_mode(config.color());
+    if config.human_readable() {
+        display::set_human_readable(true);
+    }
+    if !config.index_hidden {
+        ignore::set_skip_hidden(true);
+    }
+    ignore::set_skip_vcs(config.skip_vcs);
+    if config.respect_gitignore {
+        ignore::set_respect_gitignore(Roots::from_config(repo_root.to_path_buf(), &config));
+    }
     if !config.check_version() {
         config.warn_version_mismatch();
     }
+    Ok(config)
+}
+
+/// Lock the repository for a mutating command, warning if SQLite is used on a network filesystem
+/// (waits for a held lock when `--wait` was given)
+fn lock_repo(repo_root: &Path, config: &Config) -> Result<RepoLock> {
+    if let Some(fs_name) = lock::network_filesystem(&repo_root.join(OCI_DIR)) {
+        if Backend::detect(repo_root) == Backend::Sqlite {
+            warn!(
+                "this index is on a network filesystem ({}), where SQLite locking is unreliable.\n  \
+                 Concurrent access from several machines can corrupt .oci/index.db; consider re-creating\n  \
+                 the index with 'oci init --backend log'.",
+                fs_name
+            );
+        }
+    }
+    RepoLock::acquire(repo_root, config.lock_strategy, lock::wait_enabled())
+}
+
+/// Copy the index to `.oci/backups` before `command` removes data from it, keeping the newest
+/// copies per the `backups` config key
+fn back_up_index(repo_root: &Path, config: &Config, command: &str) -> Result<()> {
+    if let Some(path) = backup::create(repo_root, command, config.backups, file_utils::now_millis())? {
+        info!("Backed up the index to {}", path.display());
+    }
     Ok(())
 }
 
+/// Check if an error was caused by missing permissions by examining the full error chain
+pub(crate) fn is_permission_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        let msg = cause.to_string();
+        msg.contains("Operation not permitted") || msg.contains("Permission denied")
+    })
+}
+
+/// Report policy violations and block the action unless the policy is explicitly overridden
+fn enforce_policy(violations: &[Violation], action: &str, override_policy: bool) -> Result<()> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    
+    for violation in violations {
+        eprintln!("Policy violation: {}", violation);
+    }
+    
+    if override_policy {
+        eprintln!("Overriding .oci/policy.toml for {} ({} violation(s))", action, violations.len());
+        return Ok(());
+    }
+    
+    bail!(
+        "{} blocked by .oci/policy.toml ({} violation(s)); use --override-policy to proceed",
+        action, violations.len()
+    );
+}
+
+/// Determine the status of a tracked (non-ignored) file relative to its index entry
+fn file_status(
+    entry: Option<&FileEntry>,
+    full_path: &Path,
+    options: CaptureOptions,
+    policy: ChangeDetection,
+) -> Result<StatusMarker> {
+    match entry {
+        None => Ok(StatusMarker::Added),
+        Some(entry) if file_utils::has_changed(entry, full_path, policy)? => Ok(StatusMarker::Updated),
+        Some(entry) if file_utils::xattrs_changed(entry, full_path, options)? => Ok(StatusMarker::Metadata),
+        Some(_) => Ok(StatusMarker::Unchanged),
+    }
+}
+
+/// Settings shared by every file visited during a status or update run
+#[derive(Clone, Copy)]
+struct ScanContext<'a> {
+    roots: &'a Roots,
+    display_ctx: &'a DisplayContext,
+    patterns: &'a [String],
+    options: CaptureOptions,
+    change_detection: ChangeDetection,
+    classes: &'a [ContentClass],
+    /// Policy checked before indexing files (None for read-only scans or when overridden)
+    policy: Option<&'a Policy>,
+    /// Archives that already have members recorded (None unless `index_archives` is on)
+    archives: Option<&'a HashSet<String>>,
+    /// Photos and videos whose metadata was already extracted (None unless `extract_media` is on)
+    media: Option<&'a HashSet<String>>,
+    /// Audio files already fingerprinted (None unless `fingerprint_audio` is on)
+    audio: Option<&'a HashSet<String>>,
+    /// Hashes by inode, to reuse for moved files (None for read-only scans or when disabled)
+    hash_cache: Option<&'a HashCache>,
+    /// How far below the scanned path to look (`--max-depth`; None for no limit)
+    depth_limit: Option<DepthLimit<'a>>,
+    /// Files last modified before this time (epoch milliseconds) are passed over (`status --since`)
+    since: Option<u64>,
+    verbose: bool,
+}
+
+impl ScanContext<'_> {
+    /// Whether a file on disk was last modified before `--since`
+    fn predates_since(&self, path: &Path) -> bool {
+        self.since.is_some_and(|since| file_utils::get_modified_time(path).is_ok_and(|modified| modified < since))
+    }
+}
+
+/// A `--max-depth` limit, counted in directory levels below the path given on the command line
+#[derive(Clone, Copy)]
+struct DepthLimit<'a> {
+    /// Index path of the scanned path
+    scope: &'a str,
+    /// 1 = only files directly inside the scope
+    max_depth: usize,
+}
+
+impl<'a> DepthLimit<'a> {
+    fn new(scope: &'a str, max_depth: Option<usize>) -> Result<Option<Self>> {
+        match max_depth {
+            Some(0) => bail!("--max-depth must be at least 1"),
+            Some(max_depth) => Ok(Some(DepthLimit { scope, max_depth })),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether an index path is within the limit, so index comparisons see the same files as the walk
+    fn allows(&self, path: &str) -> bool {
+        levels_below(self.scope, path) <= self.max_depth
+    }
+
+    /// WalkDir depth for a walk starting at the directory with this index path
+    /// (deeper than the scope for the roots under the virtual root of a multi-root index)
+    fn walk_depth(&self, dir: &str) -> usize {
+        self.max_depth.saturating_sub(levels_below(self.scope, dir))
+    }
+}
+
+/// Maps the paths a status or update walk finds back to index paths. Owned, so the walk's
+/// directory filter can run on its worker threads.
+#[derive(Clone)]
+struct WalkPaths {
+    canonical_base: PathBuf,
+    canonical_start: PathBuf,
+    /// Index path of the directory the walk starts at
+    logical_start: PathBuf,
+    prefix: String,
+}
+
+impl WalkPaths {
+    fn new(base: &Path, prefix: &str, start: &Path) -> Result<Self> {
+        Ok(WalkPaths {
+            // Canonicalize the base and start for consistent path comparisons with the walker,
+            // which may return canonical paths from the OS
+            canonical_base: file_utils::short_path(&base.canonicalize().context("Failed to canonicalize repo root")?).into_owned(),
+            canonical_start: file_utils::short_path(&start.canonicalize().context("Failed to canonicalize scan directory")?).into_owned(),
+            logical_start: roots::join_index_path(prefix, start.strip_prefix(base)
+                .context("Scan dir is outside repository")?),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Index path of a walked path, mapping the canonical start back to its logical form
+    fn index_path(&self, path: &Path) -> Option<PathBuf> {
+        let path = file_utils::short_path(path);
+        let canonical_rel = path.strip_prefix(&self.canonical_base).ok()?;
+        Some(match path.strip_prefix(&self.canonical_start) {
+            Ok(rel) => self.logical_start.join(rel),
+            Err(_) => roots::join_index_path(&self.prefix, canonical_rel),
+        })
+    }
+
+    /// Directory filter for `scanner::parallel_walk`: skips `.oci` and ignored directories
+    fn skip_dir(&self, patterns: &[String]) -> impl Fn(&Path) -> bool + Send + Sync + 'static {
+        let (paths, patterns) = (self.clone(), patterns.to_vec());
+        move |dir| {
+            paths.index_path(dir).is_some_and(|rel| {
+                let skip = rel.starts_with(OCI_DIR) || ignore::should_ignore_dir(&rel, &patterns);
+                if skip {
+                    debug!("Skipping ignored directory: {}", rel.display());
+                }
+                skip
+            })
+        }
+    }
+}
+
+/// Number of path components of an index path below a directory ("" for the top level)
+fn levels_below(dir: &str, path: &str) -> usize {
+    let rel = path.strip_prefix(&dir_prefix(dir)).unwrap_or(path);
+    if rel.is_empty() || rel == dir {
+        0
+    } else {
+        rel.split('/').count()
+    }
+}
+
+/// Fold a change into its content class summary instead of listing it
+/// Returns true if the change was summarized (and should not be displayed individually).
+/// Verbose runs always list every file.
+fn summarize_change(
+    ctx: &ScanContext,
+    summary: &mut ClassSummary,
+    rel_path: &str,
+    marker: &StatusMarker,
+    bytes: impl FnOnce() -> u64,
+) -> bool {
+    if ctx.verbose || !marker.is_change() {
+        return false;
+    }
+    
+    match content_class::classify(ctx.classes, Path::new(rel_path)) {
+        Some(class) => {
+            summary.record(class, marker, bytes());
+            true
+        }
+        None => false,
+    }
+}
+
 /// Initialize a new index
-pub fn init() -> Result<()> {
+pub fn init(backend: String, encrypt: bool, keyfile: Option<String>) -> Result<()> {
+    let mut backend: Backend = backend.parse()?;
+    if encrypt {
+        if backend != Backend::Sqlite {
+            bail!("Only the sqlite backend can be encrypted");
+        }
+        backend = Backend::Encrypted;
+    } else if keyfile.is_some() {
+        bail!("--keyfile requires --encrypt");
+    }
     let current_dir = env::current_dir()
         .context("Failed to get current directory")?;
     
     let oci_dir = current_dir.join(OCI_DIR);
+    let mut config = Config::new();
+    if let Some(keyfile) = keyfile {
+        // Stored as given, but resolved now so a relative path keeps working from subdirectories
+        let path = normalize_path(&current_dir.join(keyfile));
+        if !path.is_file() {
+            bail!("Keyfile not found: {}", path.display());
+        }
+        config.keyfile = Some(path.to_string_lossy().to_string());
+    }
+    
+    // An index from an early version only needs converting
+    if legacy::needs_import(&current_dir) {
+        if Config::load_if_present(&current_dir)?.is_none() {
+            config.save(&current_dir)?;
+        }
+        let mut index = Index::open(&current_dir, backend)?;
+        let count = legacy::import(&mut index, &current_dir)?;
+        ignore::init_ignore_file(&current_dir, UserConfig::load()?.ignore_template.as_deref())?;
+        println!("Upgraded legacy oci index in {} ({} file(s))", oci_dir.display(), count);
+        register_repo(&current_dir);
+        return Ok(());
+    }
     
     if oci_dir.exists() {
         bail!("Index already exists at {}", oci_dir.display());
@@ -87,41 +387,49 @@ pub fn init() -> Result<()> {
     fs::create_dir_all(&oci_dir)
         .context("Failed to create .oci directory")?;
     
-    let index = Index::new()?;
-    index.save(&current_dir)?;
-    
-    // Initialize config with current version
-    let config = Config::new();
+    // Initialize config with current version (first, since an encrypted index may name its keyfile there)
     config.save(&current_dir)?;
     
-    // Initialize ignore with default patterns
-    ignore::init_ignore_file(&current_dir)?;
+    match backend {
+        Backend::Sqlite => Index::new()?.save(&current_dir)?,
+        Backend::Log => {
+            Index::open(&current_dir, backend)?;
+        }
+        Backend::Encrypted => Index::open(&current_dir, backend)?.save(&current_dir)?,
+    }
+    
+    // Initialize ignore with default patterns (or the user's template)
+    ignore::init_ignore_file(&current_dir, UserConfig::load()?.ignore_template.as_deref())?;
     
     println!("Initialized empty oci index in {}", oci_dir.display());
+    register_repo(&current_dir);
     Ok(())
 }
 
+/// Add a new repository to the registry; failing to do so does not fail the command
+fn register_repo(repo_root: &Path) {
+    if let Err(e) = registry::register(repo_root) {
+        warn!("Could not register repository: {:#}", e);
+    }
+}
+
 /// Add a pattern to the ignore list
 pub fn ignore(pattern: Option<String>) -> Result<()> {
     let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
+    let config = check_version(&repo_root)?;
     let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
     
     let pattern_to_add = if let Some(p) = pattern {
         // Convert relative path to absolute from repo root
         if Path::new(&p).is_relative() {
-            let full_path = current_dir.join(&p);
-            let rel_path = full_path.strip_prefix(&repo_root)
-                .context("Path is outside repository")?;
-            rel_path.to_string_lossy().to_string()
+            roots.require_index_path(&roots.locate(&current_dir.join(&p)))?
         } else {
             p
         }
     } else {
         // Use current directory
-        let rel_path = current_dir.strip_prefix(&repo_root)
-            .context("Current directory is outside repository")?;
-        rel_path.to_string_lossy().to_string()
+        roots.require_index_path(&current_dir)?
     };
     
     ignore::add_pattern(&repo_root, &pattern_to_add)?;
@@ -130,11 +438,59 @@ pub fn ignore(pattern: Option<String>) -> Result<()> {
     Ok(())
 }
 
+/// Validate a target path given on the command line and compute its index path
+/// Paths under the virtual root of a multi-root index are mapped onto the root they name.
+fn resolve_target(roots: &Roots, index: &Index, current_dir: &Path, target_path: &Path) -> Result<(PathBuf, String)> {
+    let target_path = roots.locate(target_path);
+    if !target_path.exists() {
+        let hint = match roots.index_path(&target_path) {
+            Some(rel_path) => did_you_mean(roots, index, current_dir, &rel_path)?,
+            None => String::new(),
+        };
+        bail!("Path does not exist: {}{}", target_path.display(), hint);
+    }
+
+    // Canonicalize for validation only - check if target is within repository bounds
+    // (the virtual root of a multi-root index contains every root by definition)
+    let is_virtual_root = roots.is_multi() && target_path == roots.repo_root();
+    if !is_virtual_root {
+        let canonical_target = target_path
+            .canonicalize()
+            .context("Failed to canonicalize path")?;
+        if !roots.contains_canonical(&canonical_target)? {
+            return Err(OciError::PathOutsideRepo(target_path).into());
+        }
+    }
+
+    // Compute the index path using the logical paths
+    // This preserves the user's view of the filesystem through symlinks
+    let rel_path = roots.require_index_path(&target_path)?;
+    Ok((target_path, rel_path))
+}
+
+/// " (did you mean ...?)" naming indexed paths close to one that matched nothing, or "" if none are close
+/// Suggestions are shown relative to the current directory, like the path the user typed.
+fn did_you_mean(roots: &Roots, index: &Index, current_dir: &Path, rel_path: &str) -> Result<String> {
+    let suggestions: Vec<String> = suggest::similar_paths(index, rel_path)?
+        .into_iter()
+        .map(|path| match roots.full_path(&path).strip_prefix(current_dir) {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative.to_string_lossy().to_string(),
+            _ => path,
+        })
+        .collect();
+    Ok(match suggestions.split_last() {
+        None => String::new(),
+        Some((last, [])) => format!(" (did you mean {}?)", last),
+        Some((last, rest)) => format!(" (did you mean {} or {}?)", rest.join(", "), last),
+    })
+}
+
 /// Determine what to scan based on status command arguments
 fn determine_scan_target(
     pattern: Option<String>,
     recursive: bool,
-    repo_root: &Path,
+    roots: &Roots,
+    index: &Index,
     current_dir: &Path,
 ) -> Result<(PathBuf, String, bool)> {
     if let Some(p) = pattern {
@@ -148,78 +504,59 @@ fn determine_scan_target(
         } else {
             current_dir.join(&p)
         };
-        
-        if !target_path.exists() {
-            bail!("Path does not exist: {}", target_path.display());
-        }
-
-        // Canonicalize for validation only - check if target is within repository bounds
-        let canonical_target = target_path
-            .canonicalize()
-            .context("Failed to canonicalize path")?;
-        let canonical_repo = repo_root
-            .canonicalize()
-            .context("Failed to canonicalize repo root")?;
-        
-        if !canonical_target.starts_with(&canonical_repo) {
-            bail!("Path is outside repository");
-        }
-
-        // Compute the relative path using the logical paths
-        // This preserves the user's view of the filesystem through symlinks
-        let rel_path = target_path
-            .strip_prefix(repo_root)
-            .context("Path is outside repository")?;
-        let rel_path_str = rel_path.to_string_lossy().to_string();
+        let (target_path, rel_path_str) = resolve_target(roots, index, current_dir, &target_path)?;
 
         // Use the logical path for scanning
         let is_recursive = target_path.is_dir() && recursive;
         Ok((target_path, rel_path_str, is_recursive))
     } else if recursive {
         // No path, but -r flag: scan from current directory recursively
-        let rel_current = current_dir
-            .strip_prefix(repo_root)
-            .context("Current directory is outside repository")?;
-        Ok((
-            current_dir.to_path_buf(),
-            rel_current.to_string_lossy().to_string(),
-            true,
-        ))
+        let rel_current = roots.require_index_path(current_dir)?;
+        Ok((current_dir.to_path_buf(), rel_current, true))
     } else {
         // No path, no -r flag: scan entire repository from root
-        Ok((repo_root.to_path_buf(), String::new(), true))
+        Ok((roots.repo_root().to_path_buf(), String::new(), true))
     }
 }
 
+/// The index path of the directory a `status` covers and whether it recurses, when that is a
+/// plain directory on disk (anything else is resolved against the index by `determine_scan_target`)
+fn status_dir(pattern: Option<&str>, recursive: bool, roots: &Roots, current_dir: &Path) -> Option<(String, bool)> {
+    let Some(pattern) = pattern else {
+        return if recursive { Some((roots.index_path(current_dir)?, true)) } else { Some((String::new(), true)) };
+    };
+    let path = match pattern {
+        "." => current_dir.to_path_buf(),
+        ".." => current_dir.parent()?.to_path_buf(),
+        _ if Path::new(pattern).components().all(|c| matches!(c, std::path::Component::Normal(_))) => current_dir.join(pattern),
+        _ => return None,
+    };
+    let path = roots.locate(&path);
+    path.is_dir().then(|| roots.index_path(&path)).flatten().map(|dir| (dir, recursive))
+}
+
 /// Scan the filesystem and display status as we go (streaming output)
+#[tracing::instrument(name = "scan", level = "debug", skip_all, fields(dir = %scan_dir.display()))]
 fn scan_and_display_status(
     scan_dir: &Path,
     is_recursive: bool,
-    repo_root: &Path,
-    patterns: &[String],
     index: &Index,
-    display_ctx: &DisplayContext,
-    verbose: bool,
+    ctx: &ScanContext,
+    summary: &mut ClassSummary,
 ) -> Result<(std::collections::HashSet<String>, bool)> {
+    let ScanContext { roots, display_ctx, patterns, options, change_detection, verbose, .. } = *ctx;
     let mut fs_files = std::collections::HashSet::new();
     let mut has_changes = false;
 
-    // Canonicalize repo_root for consistent path comparisons with WalkDir
-    // WalkDir may return canonical paths from the OS  
-    let canonical_repo = repo_root.canonicalize()
-        .context("Failed to canonicalize repo root")?;
-
-    // Also get the logical and canonical scan_dir to map paths back to logical form
-    let canonical_scan = scan_dir.canonicalize()
-        .context("Failed to canonicalize scan directory")?;
-    let logical_scan_rel = scan_dir.strip_prefix(repo_root)
+    // The repository root (or registered root) containing the scan dir, and its index path prefix
+    let (base, prefix) = roots.base_of(scan_dir)
         .context("Scan dir is outside repository")?;
+    let paths = WalkPaths::new(base, prefix, scan_dir)?;
+    let logical_scan_rel = &paths.logical_start;
 
     if scan_dir.is_file() {
         // Single file
-        let rel_path = scan_dir
-            .strip_prefix(repo_root)
-            .context("Path is outside repository")?;
+        let rel_path = logical_scan_rel.as_path();
         let rel_path_str = rel_path.to_string_lossy().to_string();
 
         if ignore::should_ignore(rel_path, patterns) {
@@ -228,117 +565,68 @@ fn scan_and_display_status(
                 let display_entry = display_ctx.create_status_entry(scan_dir, display_path)?;
                 StatusMarker::Ignored.display(&file_utils::format_entry(&display_entry));
             }
-        } else {
+        } else if !ctx.predates_since(scan_dir) {
             fs_files.insert(rel_path_str.clone());
             
             // Check status and display immediately
-            if let Some(entry) = index.get(&rel_path_str)? {
-                if file_utils::has_changed(&entry, scan_dir)? {
-                    let display_path = display_ctx.make_relative(&rel_path_str)?;
-                    let display_entry = display_ctx.create_status_entry(scan_dir, display_path)?;
-                    StatusMarker::Updated.display(&file_utils::format_entry(&display_entry));
-                    has_changes = true;
-                } else if verbose {
-                    let display_path = display_ctx.make_relative(&rel_path_str)?;
-                    let display_entry = display_ctx.create_status_entry(scan_dir, display_path)?;
-                    StatusMarker::Unchanged.display(&file_utils::format_entry(&display_entry));
-                }
-            } else {
+            let marker = file_status(index.get(&rel_path_str)?.as_ref(), scan_dir, options, change_detection)?;
+            let summarized = summarize_change(ctx, summary, &rel_path_str, &marker, || {
+                file_utils::get_file_size(scan_dir).unwrap_or(0)
+            });
+            if !summarized && (marker.is_change() || verbose) {
                 let display_path = display_ctx.make_relative(&rel_path_str)?;
                 let display_entry = display_ctx.create_status_entry(scan_dir, display_path)?;
-                StatusMarker::Added.display(&file_utils::format_entry(&display_entry));
-                has_changes = true;
+                marker.display(&file_utils::format_entry(&display_entry));
             }
+            has_changes |= marker.is_change();
         }
     } else {
         // Directory - walk and display as we go, filtering out ignored directories
-        let base_walker = if is_recursive {
-            WalkDir::new(scan_dir)
-        } else {
-            WalkDir::new(scan_dir).max_depth(1)
-        };
-        
-        let walker = base_walker.into_iter().filter_entry(|e| {
-            // Skip .oci directory and ignored directories
-            // Use canonical_repo for path stripping since WalkDir returns canonical paths
-            if let Ok(canonical_rel) = e.path().strip_prefix(&canonical_repo) {
-                // Map canonical path back to logical path by replacing canonical_scan prefix with logical
-                let rel = if let Ok(rel_from_scan) = e.path().strip_prefix(&canonical_scan) {
-                    logical_scan_rel.join(rel_from_scan)
-                } else {
-                    canonical_rel.to_path_buf()
-                };
-                
-                let rel_str = rel.to_string_lossy();
-                if rel_str.starts_with(".oci") {
-                    return false;
-                }
-                
-                // Skip directories that match ignore patterns
-                if e.file_type().is_dir() && ignore::should_ignore(&rel, patterns) {
-                    if verbose {
-                        eprintln!("Skipping ignored directory: {}", rel.display());
-                    }
-                    return false;
-                }
-            }
-            true
-        });
+        let mut max_depth = if is_recursive { None } else { Some(1) };
+        if let Some(limit) = ctx.depth_limit {
+            let depth = limit.walk_depth(&logical_scan_rel.to_string_lossy());
+            max_depth = Some(max_depth.map_or(depth, |max| depth.min(max)));
+        }
+        let walker = scanner::parallel_walk(scan_dir, max_depth, paths.skip_dir(patterns))?;
 
         for entry in walker {
             // Handle permission errors gracefully - skip and continue
             let entry = match entry {
                 Ok(e) => e,
                 Err(err) => {
-                    if verbose {
-                        eprintln!("Warning: Skipping due to error: {}", err);
-                    }
+                    info!("Skipping due to error: {}", err);
                     continue;
                 }
             };
             
             if entry.file_type().is_file() {
-                // Get canonical relative path and map back to logical  
-                let canonical_rel = entry
-                    .path()
-                    .strip_prefix(&canonical_repo)
-                    .context("Path is outside repository")?;
-                    
-                // Map canonical path back to logical by replacing canonical_scan prefix with logical
-                let rel_path = if let Ok(rel_from_scan) = entry.path().strip_prefix(&canonical_scan) {
-                    logical_scan_rel.join(rel_from_scan)
-                } else {
-                    canonical_rel.to_path_buf()
-                };
+                let file_path = entry.path();
+                let rel_path = paths.index_path(&file_path).context("Path is outside repository")?;
                 let rel_path_str = rel_path.to_string_lossy().to_string();
 
                 if ignore::should_ignore(&rel_path, patterns) {
                     if verbose {
                         let display_path = display_ctx.make_relative(&rel_path_str)?;
-                        let display_entry = display_ctx.create_status_entry(entry.path(), display_path)?;
+                        let display_entry = display_ctx.create_status_entry(&file_path, display_path)?;
                         StatusMarker::Ignored.display(&file_utils::format_entry(&display_entry));
                     }
                 } else {
                     fs_files.insert(rel_path_str.clone());
+                    if ctx.predates_since(&file_path) {
+                        continue;
+                    }
                     
                     // Check status and display immediately
-                    if let Some(idx_entry) = index.get(&rel_path_str)? {
-                        if file_utils::has_changed(&idx_entry, entry.path())? {
-                            let display_path = display_ctx.make_relative(&rel_path_str)?;
-                            let display_entry = display_ctx.create_status_entry(entry.path(), display_path)?;
-                            StatusMarker::Updated.display(&file_utils::format_entry(&display_entry));
-                            has_changes = true;
-                        } else if verbose {
-                            let display_path = display_ctx.make_relative(&rel_path_str)?;
-                            let display_entry = display_ctx.create_status_entry(entry.path(), display_path)?;
-                            StatusMarker::Unchanged.display(&file_utils::format_entry(&display_entry));
-                        }
-                    } else {
+                    let marker = file_status(index.get(&rel_path_str)?.as_ref(), &file_path, options, change_detection)?;
+                    let summarized = summarize_change(ctx, summary, &rel_path_str, &marker, || {
+                        file_utils::get_file_size(&file_path).unwrap_or(0)
+                    });
+                    if !summarized && (marker.is_change() || verbose) {
                         let display_path = display_ctx.make_relative(&rel_path_str)?;
-                        let display_entry = display_ctx.create_status_entry(entry.path(), display_path)?;
-                        StatusMarker::Added.display(&file_utils::format_entry(&display_entry));
-                        has_changes = true;
+                        let display_entry = display_ctx.create_status_entry(&file_path, display_path)?;
+                        marker.display(&file_utils::format_entry(&display_entry));
                     }
+                    has_changes |= marker.is_change();
                 }
             }
         }
@@ -351,14 +639,17 @@ fn scan_and_display_status(
 fn display_deleted_files(
     fs_files: &std::collections::HashSet<String>,
     indexed_files: Vec<crate::index::FileEntry>,
-    display_ctx: &DisplayContext,
+    ctx: &ScanContext,
+    summary: &mut ClassSummary,
 ) -> Result<bool> {
     let mut has_deletes = false;
 
     for entry in indexed_files {
         if !fs_files.contains(&entry.path) {
-            let formatted = display_ctx.format_entry_relative(&entry)?;
-            StatusMarker::Deleted.display(&formatted);
+            if !summarize_change(ctx, summary, &entry.path, &StatusMarker::Deleted, || entry.num_bytes) {
+                let formatted = ctx.display_ctx.format_entry_relative(&entry)?;
+                StatusMarker::Deleted.display(&formatted);
+            }
             has_deletes = true;
         }
     }
@@ -367,47 +658,108 @@ fn display_deleted_files(
 }
 
 /// Check status of files
-pub fn status(pattern: Option<String>, recursive: bool, verbose: bool) -> Result<()> {
+/// Show the status of the filesystem against the index, returning whether anything changed
+pub fn status(
+    pattern: Option<String>,
+    recursive: bool,
+    verbose: bool,
+    legend: bool,
+    max_depth: Option<usize>,
+    limit: Option<usize>,
+    since: Option<String>,
+) -> Result<bool> {
     let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
+    let config = check_version(&repo_root)?;
+    
+    if legend {
+        for line in config.markers.legend() {
+            println!("{}", line);
+        }
+        println!("Change a marker with marker.<name>=<symbol> in .oci/config");
+        return Ok(false);
+    }
+    let since = since.as_deref().map(since::parse).transpose()?;
+    if let Some(limit) = limit {
+        display::set_limit(limit);
+    }
+    let _pager = pager::start();
     
     let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+
+    // A running daemon's watcher knows whether anything changed since its last batch, which
+    // saves scanning when nothing did (changes are still listed by scanning)
+    if !verbose && config.change_detection == ChangeDetection::Metadata {
+        if let Some((dir, recursive)) = status_dir(pattern.as_deref(), recursive, &roots, &current_dir) {
+            if ipc::daemon_reports_clean(&repo_root, &dir, recursive)? {
+                println!("No changes");
+                return Ok(false);
+            }
+        }
+    }
     
     let index = Index::load(&repo_root)?;
     let patterns = ignore::load_patterns(&repo_root)?;
 
     // Determine what to scan based on arguments
     let (scan_dir, scan_rel_path, is_recursive) =
-        determine_scan_target(pattern, recursive, &repo_root, &current_dir)?;
-
-    let display_ctx = DisplayContext::new(repo_root.clone(), current_dir);
+        determine_scan_target(pattern, recursive, &roots, &index, &current_dir)?;
+    let depth_limit = DepthLimit::new(&scan_rel_path, max_depth)?;
+
+    let display_ctx = DisplayContext::new(roots, current_dir);
+    let ctx = ScanContext {
+        roots: display_ctx.roots(),
+        display_ctx: &display_ctx,
+        patterns: &patterns,
+        options: CaptureOptions::from_config(&config),
+        change_detection: config.change_detection,
+        classes: &config.content_classes,
+        policy: None,
+        archives: None,
+        media: None,
+        audio: None,
+        hash_cache: None,
+        depth_limit,
+        since,
+        verbose,
+    };
+    let mut summary = ClassSummary::new();
 
     // Scan filesystem and display status as we go (streaming output)
-    let (fs_files, has_changes) = scan_and_display_status(
-        &scan_dir,
-        is_recursive,
-        &repo_root,
-        &patterns,
-        &index,
-        &display_ctx,
-        verbose,
-    )?;
+    // The virtual root of a multi-root index scans every registered root
+    let mut fs_files = std::collections::HashSet::new();
+    let mut has_changes = false;
+    for target in display_ctx.roots().scan_targets(&scan_dir) {
+        let (target_files, target_changes) =
+            scan_and_display_status(&target, is_recursive, &index, &ctx, &mut summary)?;
+        fs_files.extend(target_files);
+        has_changes |= target_changes;
+    }
 
     // Get indexed files for comparison (to find deleted files)
-    let indexed_files: Vec<_> = if is_recursive {
+    let mut indexed_files: Vec<_> = if is_recursive {
         index.get_dir_files_recursive(&scan_rel_path)?
     } else {
         index.get_dir_files(&scan_rel_path)?
     };
+    if let Some(limit) = depth_limit {
+        indexed_files.retain(|entry| limit.allows(&entry.path));
+    }
 
     // Display deleted files (must wait until scan is complete)
-    let has_deletes = display_deleted_files(&fs_files, indexed_files, &display_ctx)?;
+    let has_deletes = display_deleted_files(&fs_files, indexed_files, &ctx, &mut summary)?;
+    if let Some(more) = display::omitted_summary() {
+        println!("{}", more);
+    }
+
+    // Summaries for content classes come after the per-file listing
+    summary.print();
 
     if !verbose && !has_changes && !has_deletes {
         println!("No changes");
     }
 
-    Ok(())
+    Ok(has_changes || has_deletes)
 }
 
 /// Update statistics tracker
@@ -416,19 +768,90 @@ struct UpdateStats {
     updated_count: usize,
     removed_count: usize,
     skipped_count: usize,
+    quick_hashed_count: usize,
+    full_hashed_count: usize,
+    policy_skipped_count: usize,
+    archives_read_count: usize,
+    archive_members_count: usize,
+    types_detected_count: usize,
+    digests_computed_count: usize,
+    media_read_count: usize,
+    audio_fingerprinted_count: usize,
+    cached_hash_count: usize,
+    class_summary: ClassSummary,
+    /// Files seen, bytes hashed and time taken, for the live estimate and the throughput summary
+    progress: Progress,
+    /// Files fully hashed and the time spent reading and hashing them, to tell a disk-bound
+    /// update from one slowed down by the number of files
+    hashed_count: usize,
+    hash_time: Duration,
 }
 
 impl UpdateStats {
-    fn new() -> Self {
+    fn new(progress: Progress) -> Self {
         Self {
             added_count: 0,
             updated_count: 0,
             removed_count: 0,
             skipped_count: 0,
+            quick_hashed_count: 0,
+            full_hashed_count: 0,
+            policy_skipped_count: 0,
+            archives_read_count: 0,
+            archive_members_count: 0,
+            types_detected_count: 0,
+            digests_computed_count: 0,
+            media_read_count: 0,
+            audio_fingerprinted_count: 0,
+            cached_hash_count: 0,
+            class_summary: ClassSummary::new(),
+            progress,
+            hashed_count: 0,
+            hash_time: Duration::ZERO,
+        }
+    }
+
+    /// Count a file fully hashed, and the time it took
+    fn hashed(&mut self, num_bytes: u64, started: Instant) {
+        self.progress.hashed(num_bytes);
+        self.hashed_count += 1;
+        self.hash_time += started.elapsed();
+    }
+
+    /// Op log summary, or None if the index was not modified
+    fn log_summary(&self) -> Option<String> {
+        if self.added_count + self.updated_count + self.removed_count + self.full_hashed_count
+            + self.archives_read_count + self.types_detected_count + self.digests_computed_count
+            + self.media_read_count + self.audio_fingerprinted_count == 0
+        {
+            return None;
         }
+        let mut summary = format!(
+            "{} added, {} updated, {} removed, {} full hash(es)",
+            self.added_count, self.updated_count, self.removed_count, self.full_hashed_count
+        );
+        if self.archives_read_count > 0 {
+            summary.push_str(&format!(", {} archive(s) read", self.archives_read_count));
+        }
+        if self.media_read_count > 0 {
+            summary.push_str(&format!(", {} media file(s) read", self.media_read_count));
+        }
+        if self.audio_fingerprinted_count > 0 {
+            summary.push_str(&format!(", {} audio fingerprint(s)", self.audio_fingerprinted_count));
+        }
+        if self.types_detected_count > 0 {
+            summary.push_str(&format!(", {} type(s) detected", self.types_detected_count));
+        }
+        if self.digests_computed_count > 0 {
+            summary.push_str(&format!(", {} digest backfill(s)", self.digests_computed_count));
+        }
+        Some(summary)
     }
 
     fn print_summary(&self) {
+        let throughput = self.progress.finish();
+        self.class_summary.print();
+
         let total_changed = self.added_count + self.updated_count + self.removed_count;
         if total_changed > 0 {
             println!(
@@ -442,171 +865,311 @@ impl UpdateStats {
         if self.skipped_count > 0 {
             println!("Skipped {} unchanged file(s)", self.skipped_count);
         }
+
+        if self.full_hashed_count > 0 {
+            println!("Computed {} pending full hash(es)", self.full_hashed_count);
+        }
+
+        if self.cached_hash_count > 0 {
+            println!("Reused the hash of {} moved or renamed file(s) from the hash cache", self.cached_hash_count);
+        }
+
+        if self.types_detected_count > 0 {
+            println!("Detected the content type of {} existing file(s)", self.types_detected_count);
+        }
+
+        if self.digests_computed_count > 0 {
+            println!("Computed the missing digests of {} existing file(s)", self.digests_computed_count);
+        }
+
+        if self.media_read_count > 0 {
+            println!("Extracted metadata from {} photo(s) and video(s)", self.media_read_count);
+        }
+
+        if self.audio_fingerprinted_count > 0 {
+            println!("Fingerprinted {} audio file(s)", self.audio_fingerprinted_count);
+        }
+
+        if self.archives_read_count > 0 {
+            println!(
+                "Indexed {} member(s) of {} archive(s)",
+                self.archive_members_count, self.archives_read_count
+            );
+        }
+
+        if self.quick_hashed_count > 0 {
+            println!(
+                "Quick-hashed {} large file(s); run 'oci update --full-hash' to compute full hashes",
+                self.quick_hashed_count
+            );
+        }
+
+        if self.policy_skipped_count > 0 {
+            println!(
+                "Skipped {} file(s) violating .oci/policy.toml; use --override-policy to index them",
+                self.policy_skipped_count
+            );
+        }
+
+        // Both rates on one line: the overall one includes walking and comparing, the other only hashing
+        if throughput.is_some() {
+            println!("{}", progress::update_throughput(self.progress.bytes(), self.progress.elapsed(), self.hash_time));
+            println!("{}", progress::time_split(
+                self.hashed_count,
+                self.hash_time,
+                self.progress.files(),
+                self.progress.elapsed(),
+            ));
+        }
     }
 }
 
-/// Update a single file in the index
-fn update_single_file(
+/// Bring the index entry of a tracked (non-ignored) file up to date
+fn update_file(
     index: &mut Index,
-    target_path: &Path,
-    repo_root: &Path,
-    display_ctx: &DisplayContext,
-    patterns: &[String],
-    verbose: bool,
+    full_path: &Path,
+    rel_path_str: &str,
+    ctx: &ScanContext,
     stats: &mut UpdateStats,
 ) -> Result<()> {
-    let rel_path = target_path
-        .strip_prefix(repo_root)
-        .context("Path is outside repository")?;
-    let rel_path_str = rel_path.to_string_lossy().to_string();
-
-    if ignore::should_ignore(rel_path, patterns) {
-        // File is ignored
-        if verbose {
-            let display_path = display_ctx.make_relative(&rel_path_str)?;
-            StatusMarker::Ignored.display(&display_path);
+    let ScanContext { display_ctx, options, change_detection, policy, verbose, .. } = *ctx;
+    let display_path = display_ctx.make_relative(rel_path_str)?;
+    let existing = index.get(rel_path_str)?;
+
+    // Check if file should be updated, but handle permission errors gracefully
+    let marker = match file_status(existing.as_ref(), full_path, options, change_detection) {
+        Ok(marker) => marker,
+        Err(e) if is_permission_error(&e) => {
+            warn!("Skipping file (permission denied): {}", display_path);
+            return Ok(()); // Skip this file
         }
-    } else {
-        let is_new = index.get(&rel_path_str)?.is_none();
-        let display_path = display_ctx.make_relative(&rel_path_str)?;
+        Err(e) => return Err(e),
+    };
 
-        // Check if file should be updated, but handle permission errors gracefully
-        let should_update = match should_update_file(index, target_path, &rel_path_str) {
-            Ok(should) => should,
-            Err(e) => {
-                // Check if it's a permission error by examining the full error chain
-                let is_permission_error = e.chain().any(|cause| {
-                    let msg = cause.to_string();
-                    msg.contains("Operation not permitted") || msg.contains("Permission denied")
-                });
-                
-                if is_permission_error {
-                    eprintln!("Warning: Skipping file (permission denied): {}", display_path);
-                    return Ok(()); // Skip this file
-                } else {
-                    return Err(e);
-                }
-            }
-        };
+    // New contents are only indexed if the policy allows it
+    if matches!(marker, StatusMarker::Added | StatusMarker::Updated) {
+        let num_bytes = file_utils::get_file_size(full_path).unwrap_or(0);
+        if let Some(violation) = policy.and_then(|p| p.check_index(rel_path_str, num_bytes)) {
+            progress::clear_line();
+            eprintln!("Policy violation: {}", violation);
+            stats.policy_skipped_count += 1;
+            return Ok(());
+        }
+    }
 
-        if should_update {
-            let marker = if is_new {
-                StatusMarker::Added
-            } else {
-                StatusMarker::Updated
-            };
-            marker.display(&display_path);
+    let show = !summarize_change(ctx, &mut stats.class_summary, rel_path_str, &marker, || {
+        file_utils::get_file_size(full_path).unwrap_or(0)
+    });
+
+    // Archives are read when their content changes, or once after `index_archives` is turned on
+    let mut read_members = ctx.archives
+        .is_some_and(|archives| archive::is_archive(rel_path_str) && !archives.contains(rel_path_str));
+    let mut read_media = ctx.media
+        .is_some_and(|media| media::is_media(rel_path_str) && !media.contains(rel_path_str));
+    let mut read_audio = ctx.audio
+        .is_some_and(|audio| audio::is_audio(rel_path_str) && !audio.contains(rel_path_str));
+
+    // Known hashes keep the cache current, so a later move of the file can reuse them
+    let cache_key = match ctx.hash_cache {
+        Some(_) => InodeKey::of(full_path).unwrap_or(None),
+        None => None,
+    };
+    let remember = |sha256: &str| match (ctx.hash_cache, &cache_key) {
+        (Some(cache), Some(key)) if !sha256.is_empty() => cache.put(key, sha256),
+        _ => Ok(()),
+    };
 
-            // Try to create file entry, but handle permission errors gracefully
-            match file_utils::create_file_entry(target_path, rel_path_str) {
-                Ok(entry) => {
-                    index.upsert(entry)?;
-                    if is_new {
-                        stats.added_count += 1;
-                    } else {
-                        stats.updated_count += 1;
+    match (marker, existing) {
+        (StatusMarker::Unchanged, existing) => {
+            if let Some(entry) = &existing {
+                remember(&entry.sha256)?;
+            }
+            stats.skipped_count += 1;
+            if verbose {
+                StatusMarker::Unchanged.display(&display_path);
+            }
+            // Entries indexed before `digests` asked for more algorithms get them in one read
+            if let Some(mut entry) = existing.clone().filter(|entry| file_utils::digests_missing(entry, options)) {
+                let started = Instant::now();
+                match file_utils::compute_digests(full_path, options.digests) {
+                    Ok((sha256, digests)) => {
+                        stats.hashed(entry.num_bytes, started);
+                        entry.sha256 = sha256;
+                        entry.digests = digests;
+                        index.upsert(entry)?;
+                        stats.digests_computed_count += 1;
+                    }
+                    Err(e) if is_permission_error(&e) => {
+                        warn!("Skipping file (permission denied): {}", display_path);
                     }
+                    Err(e) => return Err(e),
                 }
-                Err(e) => {
-                    // Check if it's a permission error by examining the full error chain
-                    let is_permission_error = e.chain().any(|cause| {
-                        let msg = cause.to_string();
-                        msg.contains("Operation not permitted") || msg.contains("Permission denied")
-                    });
-                    
-                    if is_permission_error {
-                        eprintln!("Warning: Skipping file (permission denied): {}", display_path);
+            // Entries indexed before type detection was on get their type without a re-hash
+            } else if let Some(mut entry) = existing.clone().filter(|entry| options.detect_types && entry.mime.is_none()) {
+                match file_utils::detect_type(full_path) {
+                    Ok(mime) => {
+                        entry.mime = Some(mime);
+                        entry.allocated = entry.allocated.or(file_utils::get_allocated_size(full_path)?);
+                        index.upsert(entry)?;
+                        stats.types_detected_count += 1;
+                    }
+                    Err(e) if is_permission_error(&e) => {
+                        warn!("Skipping file (permission denied): {}", display_path);
+                    }
+                    Err(e) => return Err(e),
+                }
+            } else if let Some(mut entry) = existing.filter(|entry| entry.allocated.is_none()) {
+                // Likewise entries indexed before allocated sizes were recorded
+                entry.allocated = file_utils::get_allocated_size(full_path)?;
+                if entry.allocated.is_some() {
+                    index.upsert(entry)?;
+                }
+            }
+        }
+        (StatusMarker::Metadata, Some(mut entry)) => {
+            // Only extended attributes changed - no need to re-hash
+            if show {
+                StatusMarker::Metadata.display(&display_path);
+            }
+            entry.xattrs = Some(file_utils::read_xattrs(full_path)?);
+            remember(&entry.sha256)?;
+            index.upsert(entry)?;
+            stats.updated_count += 1;
+        }
+        (marker, _) => {
+            if show {
+                marker.display(&display_path);
+            }
+
+            // A file moved or renamed outside oci keeps its inode, and the hash it had (which
+            // saves nothing when other digests have to be read anyway)
+            let cached = match (ctx.hash_cache, &cache_key) {
+                (Some(cache), Some(key)) if options.digests.is_empty() => cache.get(key)?,
+                _ => None,
+            };
+            let from_cache = cached.is_some();
+
+            // Try to create file entry, but handle permission errors gracefully
+            let started = Instant::now();
+            match file_utils::create_file_entry_with_hash(full_path, rel_path_str.to_string(), options, cached) {
+                Ok(entry) => {
+                    if from_cache {
+                        stats.cached_hash_count += 1;
+                    } else if entry.sha256.is_empty() {
+                        stats.quick_hashed_count += 1;
+                    } else {
+                        stats.hashed(entry.num_bytes, started);
+                    }
+                    remember(&entry.sha256)?;
+                    index.upsert(entry)?;
+                    read_members |= ctx.archives.is_some() && archive::is_archive(rel_path_str);
+                    read_media |= ctx.media.is_some() && media::is_media(rel_path_str);
+                    read_audio |= ctx.audio.is_some() && audio::is_audio(rel_path_str);
+                    if matches!(marker, StatusMarker::Added) {
+                        stats.added_count += 1;
                     } else {
-                        // Other errors should still fail
-                        return Err(e);
+                        stats.updated_count += 1;
                     }
                 }
+                Err(e) if is_permission_error(&e) => {
+                    warn!("Skipping file (permission denied): {}", display_path);
+                }
+                // Other errors should still fail
+                Err(e) => return Err(e),
             }
-        } else {
-            stats.skipped_count += 1;
-            if verbose {
-                let display_path = display_ctx.make_relative(&rel_path_str)?;
-                StatusMarker::Unchanged.display(&display_path);
+        }
+    }
+
+    if read_members {
+        // An unreadable archive is still indexed as a file; only its members are left out
+        let members = archive::read_members(full_path, rel_path_str).unwrap_or_else(|e| {
+            warn!("Skipping archive members of {}: {:#}", display_path, e);
+            Vec::new()
+        });
+        stats.archives_read_count += 1;
+        stats.archive_members_count += members.len();
+        index.set_archive_members(rel_path_str, &members)?;
+    }
+
+    if read_media {
+        match media::read_media(full_path, rel_path_str) {
+            Ok(info) => {
+                index.set_media(&info)?;
+                stats.media_read_count += 1;
             }
+            Err(e) => warn!("Skipping metadata of {}: {:#}", display_path, e),
+        }
+    }
+
+    if read_audio {
+        match audio::fingerprint_file(full_path, rel_path_str) {
+            Ok(print) => {
+                index.set_audio(&print)?;
+                stats.audio_fingerprinted_count += 1;
+            }
+            Err(e) => warn!("Skipping audio fingerprint of {}: {:#}", display_path, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Update a single file in the index
+fn update_single_file(
+    index: &mut Index,
+    target_path: &Path,
+    ctx: &ScanContext,
+    stats: &mut UpdateStats,
+) -> Result<()> {
+    let ScanContext { roots, display_ctx, patterns, verbose, .. } = *ctx;
+    let rel_path_str = roots.require_index_path(target_path)?;
+
+    if ignore::should_ignore(Path::new(&rel_path_str), patterns) {
+        // File is ignored
+        if verbose {
+            let display_path = display_ctx.make_relative(&rel_path_str)?;
+            StatusMarker::Ignored.display(&display_path);
         }
+    } else {
+        update_file(index, target_path, &rel_path_str, ctx, stats)?;
+        stats.progress.file_done();
     }
 
     Ok(())
 }
 
 /// Update all files in a directory recursively
+#[tracing::instrument(name = "scan", level = "debug", skip_all, fields(dir = %target_path.display()))]
 fn update_directory(
     index: &mut Index,
     target_path: &Path,
-    repo_root: &Path,
-    display_ctx: &DisplayContext,
-    patterns: &[String],
-    verbose: bool,
+    ctx: &ScanContext,
     stats: &mut UpdateStats,
 ) -> Result<()> {
+    let ScanContext { roots, display_ctx, patterns, verbose, .. } = *ctx;
     let mut fs_files = std::collections::HashSet::new();
 
-    // Canonicalize repo_root for consistent path comparisons with WalkDir
-    let canonical_repo = repo_root.canonicalize()
-        .context("Failed to canonicalize repo root")?;
-
-    // Also get the logical and canonical target to map paths back to logical form
-    let canonical_target = target_path.canonicalize()
-        .context("Failed to canonicalize target path")?;
-    let logical_target_rel = target_path.strip_prefix(repo_root)
+    // The repository root (or registered root) containing the target, and its index path prefix
+    let (base, prefix) = roots.base_of(target_path)
         .context("Target path is outside repository")?;
+    let paths = WalkPaths::new(base, prefix, target_path)?;
+    let logical_target_rel = &paths.logical_start;
 
     // Walk the directory tree, filtering out ignored directories
-    for entry in WalkDir::new(target_path).into_iter().filter_entry(|e| {
-        // Skip .oci directory
-        // Use canonical_repo for path stripping since WalkDir returns canonical paths
-        if let Ok(canonical_rel) = e.path().strip_prefix(&canonical_repo) {
-            // Map canonical path back to logical path by replacing canonical_target prefix with logical
-            let rel = if let Ok(rel_from_target) = e.path().strip_prefix(&canonical_target) {
-                logical_target_rel.join(rel_from_target)
-            } else {
-                canonical_rel.to_path_buf()
-            };
-            
-            let rel_str = rel.to_string_lossy();
-            if rel_str.starts_with(".oci") {
-                return false;
-            }
-            
-            // Skip directories that match ignore patterns (much more efficient!)
-            if e.file_type().is_dir() && ignore::should_ignore(&rel, patterns) {
-                if verbose {
-                    eprintln!("Skipping ignored directory: {}", rel.display());
-                }
-                return false;
-            }
-        }
-        true
-    }) {
+    let max_depth = ctx.depth_limit.map(|limit| limit.walk_depth(&logical_target_rel.to_string_lossy()));
+    for entry in scanner::parallel_walk(target_path, max_depth, paths.skip_dir(patterns))? {
         // Handle permission errors gracefully - skip and continue
         let entry = match entry {
             Ok(e) => e,
             Err(err) => {
-                if verbose {
-                    eprintln!("Warning: Skipping due to error: {}", err);
-                }
+                info!("Skipping due to error: {}", err);
                 continue;
             }
         };
 
         if entry.file_type().is_file() {
-            // Get canonical relative path and map back to logical
-            let canonical_rel = entry
-                .path()
-                .strip_prefix(&canonical_repo)
-                .context("Path is outside repository")?;
-                
-            // Map canonical path back to logical by replacing canonical_target prefix with logical
-            let rel_path = if let Ok(rel_from_target) = entry.path().strip_prefix(&canonical_target) {
-                logical_target_rel.join(rel_from_target)
-            } else {
-                canonical_rel.to_path_buf()
-            };
+            let file_path = entry.path();
+            let rel_path = paths.index_path(&file_path).context("Path is outside repository")?;
             let rel_path_str = rel_path.to_string_lossy().to_string();
 
             if ignore::should_ignore(&rel_path, patterns) {
@@ -618,89 +1181,142 @@ fn update_directory(
                 }
             } else {
                 fs_files.insert(rel_path_str.clone());
-
-                let is_new = index.get(&rel_path_str)?.is_none();
-
-                // Check if file should be updated, but handle permission errors gracefully
-                let should_update = match should_update_file(index, entry.path(), &rel_path_str) {
-                    Ok(should) => should,
-                    Err(e) => {
-                        // Check if it's a permission error by examining the full error chain
-                        let is_permission_error = e.chain().any(|cause| {
-                            let msg = cause.to_string();
-                            msg.contains("Operation not permitted") || msg.contains("Permission denied")
-                        });
-                        
-                        if is_permission_error {
-                            let display_path = display_ctx.make_relative(&rel_path_str)?;
-                            eprintln!("Warning: Skipping file (permission denied): {}", display_path);
-                            continue; // Skip this file and move to the next
-                        } else {
-                            return Err(e);
-                        }
-                    }
-                };
-
-                if should_update {
-                    let display_path = display_ctx.make_relative(&rel_path_str)?;
-                    let marker = if is_new {
-                        StatusMarker::Added
-                    } else {
-                        StatusMarker::Updated
-                    };
-                    marker.display(&display_path);
-
-                    // Try to create file entry, but handle permission errors gracefully
-                    match file_utils::create_file_entry(entry.path(), rel_path_str.clone()) {
-                        Ok(file_entry) => {
-                            index.upsert(file_entry)?;
-                            if is_new {
-                                stats.added_count += 1;
-                            } else {
-                                stats.updated_count += 1;
-                            }
-                        }
-                        Err(e) => {
-                            // Check if it's a permission error by examining the full error chain
-                            let is_permission_error = e.chain().any(|cause| {
-                                let msg = cause.to_string();
-                                msg.contains("Operation not permitted") || msg.contains("Permission denied")
-                            });
-                            
-                            if is_permission_error {
-                                eprintln!("Warning: Skipping file (permission denied): {}", display_path);
-                            } else {
-                                // Other errors should still fail
-                                return Err(e);
-                            }
-                        }
-                    }
-                } else {
-                    stats.skipped_count += 1;
-                    if verbose {
-                        let display_path = display_ctx.make_relative(&rel_path_str)?;
-                        StatusMarker::Unchanged.display(&display_path);
-                    }
-                }
+                update_file(index, &file_path, &rel_path_str, ctx, stats)?;
+                stats.progress.file_done();
             }
         }
     }
 
     // Now check for deleted files in the index
-    let rel_target = target_path
-        .strip_prefix(repo_root)
-        .context("Path is outside repository")?;
-    let rel_target_str = rel_target.to_string_lossy().to_string();
+    let rel_target_str = logical_target_rel.to_string_lossy().to_string();
 
     let indexed_files = index.get_dir_files_recursive(&rel_target_str)?;
+    let mut removed = Vec::new();
 
+    let within_depth = |path: &str| ctx.depth_limit.is_none_or(|limit| limit.allows(path));
     for indexed_entry in indexed_files {
-        if !fs_files.contains(&indexed_entry.path) {
+        if !fs_files.contains(&indexed_entry.path) && within_depth(&indexed_entry.path) {
             // File is in index but not on disk - remove it
-            let display_path = display_ctx.make_relative(&indexed_entry.path)?;
-            StatusMarker::Deleted.display(&display_path);
-            index.remove(&indexed_entry.path)?;
-            stats.removed_count += 1;
+            removed.push(indexed_entry);
+        }
+    }
+    remove_deleted(index, removed, ctx, stats)
+}
+
+/// Drop entries whose files are gone from the index, reporting each as deleted
+fn remove_deleted(
+    index: &mut Index,
+    removed: Vec<FileEntry>,
+    ctx: &ScanContext,
+    stats: &mut UpdateStats,
+) -> Result<()> {
+    for entry in &removed {
+        let bytes = entry.num_bytes;
+        if !summarize_change(ctx, &mut stats.class_summary, &entry.path, &StatusMarker::Deleted, || bytes) {
+            StatusMarker::Deleted.display(&ctx.display_ctx.make_relative(&entry.path)?);
+        }
+    }
+    stats.removed_count += removed.len();
+    index.remove_departed(removed, "deleted")
+}
+
+/// Update only the directories a change journal reports as changed (`update --fast`). A changed
+/// directory's own files are rescanned; subdirectories that appeared in it (e.g. moved in) are
+/// scanned in full, and indexed subdirectories that left it are dropped.
+fn update_changed_dirs(
+    index: &mut Index,
+    dirs: &[ChangedDir],
+    ctx: &ScanContext,
+    stats: &mut UpdateStats,
+) -> Result<()> {
+    for dir in dirs {
+        let Some(rel) = ctx.roots.index_path(&dir.path) else {
+            continue;
+        };
+        if !rel.is_empty() && ignore::should_ignore_dir(Path::new(&rel), ctx.patterns) {
+            continue;
+        }
+        if !dir.path.is_dir() {
+            let removed = index.get_dir_files_recursive(&rel)?;
+            remove_deleted(index, removed, ctx, stats)?;
+            continue;
+        }
+        if dir.recursive {
+            update_directory(index, &dir.path, ctx, stats)?;
+            continue;
+        }
+        let shallow = ScanContext { depth_limit: Some(DepthLimit { scope: &rel, max_depth: 1 }), ..*ctx };
+        update_directory(index, &dir.path, &shallow, stats)?;
+
+        let prefix = dir_prefix(&rel);
+        let mut indexed_subdirs = std::collections::BTreeSet::new();
+        index.visit_dir_files(&rel, true, |entry| {
+            if let Some((name, _)) = entry.path.strip_prefix(&prefix).and_then(|rest| rest.split_once('/')) {
+                indexed_subdirs.insert(name.to_string());
+            }
+            Ok(())
+        })?;
+        let read_dir = fs::read_dir(&dir.path).context(format!("Failed to read {}", dir.path.display()))?;
+        for entry in read_dir {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let indexed = indexed_subdirs.remove(&name);
+            let sub_rel = format!("{}{}", prefix, name);
+            if ignore::should_ignore_dir(Path::new(&sub_rel), ctx.patterns) {
+                if indexed {
+                    let removed = index.get_dir_files_recursive(&sub_rel)?;
+                    remove_deleted(index, removed, ctx, stats)?;
+                }
+            } else if !indexed {
+                update_directory(index, &entry.path(), ctx, stats)?;
+            }
+        }
+        for name in indexed_subdirs {
+            let removed = index.get_dir_files_recursive(&format!("{}{}", prefix, name))?;
+            remove_deleted(index, removed, ctx, stats)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compute full hashes for indexed entries under the target that only have a quick fingerprint
+fn compute_pending_hashes(
+    index: &mut Index,
+    target_path: &Path,
+    ctx: &ScanContext,
+    stats: &mut UpdateStats,
+) -> Result<()> {
+    let rel_target = ctx.roots.require_index_path(target_path)?;
+    let entries = if target_path.is_file() {
+        index.get(&rel_target)?.into_iter().collect()
+    } else {
+        index.get_dir_files_recursive(&rel_target)?
+    };
+
+    let within_depth = |path: &str| ctx.depth_limit.is_none_or(|limit| limit.allows(path));
+    for mut entry in entries.into_iter().filter(|e| e.sha256.is_empty() && within_depth(&e.path)) {
+        let display_path = ctx.display_ctx.make_relative(&entry.path)?;
+        let started = Instant::now();
+        match file_utils::compute_digests(&ctx.roots.full_path(&entry.path), ctx.options.digests) {
+            Ok((sha256, digests)) => {
+                StatusMarker::Updated.display(&display_path);
+                stats.hashed(entry.num_bytes, started);
+                stats.progress.file_done();
+                if let (Some(cache), Ok(Some(key))) = (ctx.hash_cache, InodeKey::of(&ctx.roots.full_path(&entry.path))) {
+                    cache.put(&key, &sha256)?;
+                }
+                entry.sha256 = sha256;
+                entry.digests = digests;
+                index.upsert(entry)?;
+                stats.full_hashed_count += 1;
+            }
+            Err(e) if is_permission_error(&e) => {
+                warn!("Skipping file (permission denied): {}", display_path);
+            }
+            Err(e) => return Err(e),
         }
     }
 
@@ -708,11 +1324,53 @@ fn update_directory(
 }
 
 /// Update the index with changes from the filesystem
-pub fn update(pattern: Option<String>, verbose: bool) -> Result<()> {
+/// With `full_hash`, large files are fully hashed and pending quick-hash entries are completed.
+pub fn update(
+    pattern: Option<String>,
+    verbose: bool,
+    full_hash: bool,
+    override_policy: bool,
+    max_depth: Option<usize>,
+    fast: bool,
+) -> Result<()> {
+    if fast && !changes::SUPPORTED {
+        bail!("update --fast needs a filesystem change journal, which is only read on macOS (FSEvents) and Windows (NTFS USN journal)");
+    }
+    let plan = if fast { ScanPlan::Journal } else { ScanPlan::Walk };
+    run_update(pattern, verbose, full_hash, override_policy, max_depth, plan)
+}
+
+/// What an update visits below its target
+enum ScanPlan {
+    /// Walk everything
+    Walk,
+    /// Only the directories the change journal reports since the last update (`--fast`)
+    Journal,
+    /// Directories a watcher reported as changed (`watch`)
+    Changed(Vec<ChangedDir>),
+}
+
+fn run_update(
+    pattern: Option<String>,
+    verbose: bool,
+    full_hash: bool,
+    override_policy: bool,
+    max_depth: Option<usize>,
+    plan: ScanPlan,
+) -> Result<()> {
+    let fast = matches!(plan, ScanPlan::Journal);
+    let watching = matches!(plan, ScanPlan::Changed(_));
+    let command_name = if watching { "watch" } else { "update" };
     let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
+    let mut config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let policy = Policy::load(&repo_root)?;
     let current_dir = get_logical_current_dir()?;
     let mut index = Index::load(&repo_root)?;
+    // Loading upgraded the index to this release's formats, so it is kept with this release from now on
+    if config.is_older_release() {
+        config.record_tool_version(&repo_root)?;
+    }
     let patterns = ignore::load_patterns(&repo_root)?;
 
     let target_path = if let Some(p) = pattern {
@@ -730,921 +1388,4782 @@ pub fn update(pattern: Option<String>, verbose: bool) -> Result<()> {
         repo_root.clone()
     };
 
-    if !target_path.exists() {
-        bail!("Path does not exist: {}", target_path.display());
+    // Use the logical path to preserve user's view through symlinks
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let (target_path, scope) = resolve_target(&roots, &index, &current_dir, &target_path)?;
+    let depth_limit = DepthLimit::new(&scope, max_depth)?;
+    let mut hook_fields = serde_json::Map::new();
+    hook_fields.insert("command".to_string(), command_name.into());
+    hook_fields.insert("scope".to_string(), scope.clone().into());
+    hooks::run(&repo_root, Hook::PreUpdate, hook_fields.clone()).context("Update cancelled")?;
+    let display_ctx = DisplayContext::new(roots, current_dir);
+    let mut options = CaptureOptions::from_config(&config);
+    if full_hash {
+        options.quick_hash_threshold = 0;
     }
-
-    // Canonicalize only for validation - check if path is within repository
-    let canonical_target = target_path
-        .canonicalize()
-        .context("Failed to canonicalize path")?;
-    let canonical_repo = repo_root
-        .canonicalize()
-        .context("Failed to canonicalize repo root")?;
-    
-    if !canonical_target.starts_with(&canonical_repo) {
-        bail!("Path is outside repository");
+    let indexed_archives: Option<HashSet<String>> = if config.index_archives {
+        Some(index.archive_members()?.into_iter().map(|member| member.archive).collect())
+    } else {
+        None
+    };
+    let extracted_media: Option<HashSet<String>> = if config.extract_media {
+        Some(index.media()?.into_iter().map(|info| info.path).collect())
+    } else {
+        None
+    };
+    let fingerprinted_audio: Option<HashSet<String>> = if config.fingerprint_audio {
+        Some(index.audio_fingerprints()?.into_iter().map(|print| print.path).collect())
+    } else {
+        None
+    };
+    // Re-hashing everything is the point of `change_detection=hash`, and an encrypted index
+    // shouldn't leave its hashes next to it in the clear
+    let hash_cache = if config.hash_cache
+        && config.change_detection == ChangeDetection::Metadata
+        && Backend::detect(&repo_root) != Backend::Encrypted
+    {
+        Some(HashCache::open(&repo_root)?)
+    } else {
+        None
+    };
+    let ctx = ScanContext {
+        roots: display_ctx.roots(),
+        display_ctx: &display_ctx,
+        patterns: &patterns,
+        options,
+        change_detection: config.change_detection,
+        classes: &config.content_classes,
+        policy: (!override_policy).then_some(&policy),
+        archives: indexed_archives.as_ref(),
+        media: extracted_media.as_ref(),
+        audio: fingerprinted_audio.as_ref(),
+        hash_cache: hash_cache.as_ref(),
+        depth_limit,
+        since: None,
+        verbose,
+    };
+    let mut stats = UpdateStats::new(Progress::new(true));
+    if stats.progress.is_live() && !target_path.is_file() {
+        // Files indexed so far are the best guess at how many the scan will find
+        let expected = display_ctx.roots().index_path(&target_path)
+            .map_or(Ok(0), |rel| index.get_dir_files_recursive(&rel).map(|entries| entries.len()))?;
+        if expected > 0 {
+            stats.progress.expect(expected, None);
+        }
     }
 
-    // Use the logical path to preserve user's view through symlinks
-    let display_ctx = DisplayContext::new(repo_root.clone(), current_dir);
-    let mut stats = UpdateStats::new();
+    // Journal positions are taken before the scan, so changes made during it are seen next time
+    let whole_repo = scope.is_empty() && !target_path.is_file();
+    let scan_targets = display_ctx.roots().scan_targets(&target_path);
+    let mut cursors = Vec::new();
+    if whole_repo && changes::SUPPORTED && !watching {
+        for target in &scan_targets {
+            if let Some(cursor) = changes::current(target)? {
+                cursors.push((target.clone(), cursor));
+            }
+        }
+    }
+    let previous = if fast { changes::load(&repo_root)? } else { std::collections::HashMap::new() };
 
+    // Commit writes in batches rather than one transaction per file
+    index.begin_batch()?;
+    let mut walked_everything = true;
+    let mut changed_dirs = 0;
     if target_path.is_file() {
-        update_single_file(
-            &mut index,
-            &target_path,
-            &repo_root,
-            &display_ctx,
-            &patterns,
-            verbose,
-            &mut stats,
-        )?;
+        update_single_file(&mut index, &target_path, &ctx, &mut stats)?;
+    } else if let ScanPlan::Changed(dirs) = &plan {
+        changed_dirs = dirs.len();
+        walked_everything = false;
+        update_changed_dirs(&mut index, dirs, &ctx, &mut stats)?;
     } else {
-        update_directory(
-            &mut index,
-            &target_path,
-            &repo_root,
-            &display_ctx,
-            &patterns,
-            verbose,
-            &mut stats,
-        )?;
+        // The virtual root of a multi-root index updates every registered root
+        for target in &scan_targets {
+            let changed = match previous.get(target) {
+                Some(cursor) if fast => changes::changed_since(target, cursor)?,
+                _ => None,
+            };
+            match changed {
+                Some(dirs) => {
+                    info!("Checking {} changed director(ies) under {}", dirs.len(), target.display());
+                    changed_dirs += dirs.len();
+                    walked_everything = false;
+                    update_changed_dirs(&mut index, &dirs, &ctx, &mut stats)?;
+                }
+                None => {
+                    if fast {
+                        println!("No usable {} history for {}; scanning everything", changes::JOURNAL, target.display());
+                    }
+                    info!("Scanning {}", target.display());
+                    update_directory(&mut index, target, &ctx, &mut stats)?;
+                }
+            }
+        }
+    }
+
+    if full_hash {
+        compute_pending_hashes(&mut index, &target_path, &ctx, &mut stats)?;
     }
 
+    let summary = stats.log_summary();
+    if let Some(summary) = &summary {
+        let command = if scope.is_empty() { command_name.to_string() } else { format!("update {}", scope) };
+        let mut summary = summary.clone();
+        if override_policy {
+            summary.push_str(" (policy overridden)");
+        }
+        index.log_operation(&Operation::new(&config, &command, summary))?;
+    }
+    index.commit_batch()?;
     index.save(&repo_root)?;
+    if whole_repo && changes::SUPPORTED && !watching {
+        changes::save(&repo_root, &cursors)?;
+    }
+    if let Some(hash_cache) = hash_cache {
+        // Only a scan of the whole repository sees every inode still in use
+        hash_cache.finish(whole_repo && walked_everything && depth_limit.is_none())?;
+    }
+    if !walked_everything {
+        let reported_by = if watching { changes::WATCHER } else { changes::JOURNAL };
+        println!("Checked {} changed director(ies) reported by {}", changed_dirs, reported_by);
+    }
     stats.print_summary();
 
+    if let Some(summary) = summary {
+        hook_fields.insert("added".to_string(), stats.added_count.into());
+        hook_fields.insert("updated".to_string(), stats.updated_count.into());
+        hook_fields.insert("removed".to_string(), stats.removed_count.into());
+        hook_fields.insert("full_hashes".to_string(), stats.full_hashed_count.into());
+        hook_fields.insert("summary".to_string(), summary.into());
+        run_post_hook(&repo_root, Hook::PostUpdate, hook_fields);
+    }
+
     Ok(())
 }
 
-/// List files in the index
-pub fn ls(recursive: bool) -> Result<()> {
+/// Keep the index current: watch the repository's trees and apply the changes reported every
+/// `interval` seconds as one update, until interrupted
+pub fn watch(verbose: bool, interval: u64) -> Result<()> {
+    check_watch_options(interval)?;
     let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
-    let current_dir = get_logical_current_dir()?;
-    let index = Index::load(&repo_root)?;
+    if let Some(pid) = daemon::running(&repo_root)? {
+        bail!("The daemon (pid {}) already keeps this index current", pid);
+    }
+    watch_repo(&repo_root, verbose, interval, || Ok(()), || false, None)
+}
 
-    let rel_current = current_dir
-        .strip_prefix(&repo_root)
-        .context("Current directory is outside repository")?;
-    let rel_current_str = rel_current.to_string_lossy().to_string();
+fn check_watch_options(interval: u64) -> Result<()> {
+    if !changes::WATCH_SUPPORTED {
+        bail!("Watching for changes needs inotify (Linux) or FSEvents (macOS), which this platform lacks");
+    }
+    if interval == 0 {
+        bail!("--interval must be at least 1");
+    }
+    Ok(())
+}
 
-    let mut entries: Vec<_> = if recursive {
-        index.get_dir_files_recursive(&rel_current_str)?
-    } else {
-        index.get_dir_files(&rel_current_str)?
+/// The loop behind `watch` and `daemon run`: catch up, then apply each interval's changes until
+/// `stop` says so, calling `on_idle` after intervals without any. With a query server, the
+/// watcher is read every tick and what it reported is published for `status`.
+fn watch_repo(
+    repo_root: &Path,
+    verbose: bool,
+    interval: u64,
+    mut on_idle: impl FnMut() -> Result<()>,
+    stop: impl Fn() -> bool,
+    server: Option<&ipc::Server>,
+) -> Result<()> {
+    let config = check_version(repo_root)?;
+    let roots = Roots::from_config(repo_root.to_path_buf(), &config);
+    let patterns = ignore::load_patterns(repo_root)?;
+    // Other commands run between batches, and a batch waits for them rather than failing
+    lock::set_wait(true);
+
+    // Watch before catching up, so nothing changed during the first update is missed
+    let skip_roots = roots.clone();
+    let skip_dir = move |dir: &Path| {
+        skip_roots.index_path(dir)
+            .is_none_or(|rel| !rel.is_empty() && ignore::should_ignore_dir(Path::new(&rel), &patterns))
     };
-
-    if entries.is_empty() {
-        println!("No files in index");
-        return Ok(());
+    let mut watcher = changes::Watcher::start(&roots.scan_targets(repo_root), Box::new(skip_dir))?;
+    println!(
+        "Watching {} director(ies) under {} with {} (Ctrl-C to stop)",
+        watcher.watched_dirs(), repo_root.display(), changes::WATCHER
+    );
+    let catch_up = if changes::SUPPORTED { ScanPlan::Journal } else { ScanPlan::Walk };
+    run_update(None, verbose, false, false, None, catch_up)?;
+
+    let interval = Duration::from_secs(interval);
+    let tick = if server.is_some() { ipc::TICK.min(interval) } else { interval };
+    // Directories a failed batch (e.g. a policy violation) left for the next full update
+    let mut failed: Vec<ChangedDir> = Vec::new();
+    let publish = |dirs: &[ChangedDir], failed: &[ChangedDir]| {
+        if let Some(server) = server {
+            server.publish(&changes::collapse([dirs, failed].concat()), &roots);
+        }
+    };
+    let settle = || server.map_or(Ok(()), ipc::Server::settle);
+    publish(&[], &failed);
+    settle()?;
+
+    while !stop() {
+        let started = Instant::now();
+        let mut dirs = Vec::new();
+        while !stop() && started.elapsed() < interval {
+            dirs.extend(watcher.collect(tick)?);
+            dirs = changes::collapse(dirs);
+            publish(&dirs, &failed);
+        }
+        if stop() {
+            break;
+        }
+        let result = if dirs.is_empty() {
+            on_idle()
+        } else {
+            run_update(None, verbose, false, false, None, ScanPlan::Changed(dirs.clone()))
+        };
+        if let Err(e) = result {
+            warn!("{:#}", e);
+            failed.extend(dirs);
+        }
+        publish(&[], &failed);
+        settle()?;
     }
+    Ok(())
+}
 
-    // Sort by path for consistent output
-    entries.sort_by(|a, b| a.path.cmp(&b.path));
+/// Options of `daemon start` and `daemon run`
+pub struct DaemonOptions {
+    pub interval: u64,
+    /// Read rate of the idle-time verification
+    pub io_limit: String,
+    /// Days between the start of idle-time verification passes
+    pub verify_every: u64,
+    pub no_verify: bool,
+    pub verbose: bool,
+}
 
-    let display_ctx = DisplayContext::new(repo_root, current_dir);
-    for entry in entries {
-        let formatted = display_ctx.format_entry_relative(&entry)?;
-        println!("{}", formatted);
+impl DaemonOptions {
+    /// The arguments that pass these options on to `daemon run`
+    fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--interval".to_string(), self.interval.to_string(),
+            "--io-limit".to_string(), self.io_limit.clone(),
+            "--verify-every".to_string(), self.verify_every.to_string(),
+        ];
+        if self.no_verify {
+            args.push("--no-verify".to_string());
+        }
+        if self.verbose {
+            args.push("-v".to_string());
+        }
+        args
+    }
+}
+
+/// Start the daemon in the background and wait until it has recorded itself
+pub fn daemon_start(options: DaemonOptions) -> Result<()> {
+    check_watch_options(options.interval)?;
+    parse_size("--io-limit", &options.io_limit)?;
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    if let Some(pid) = daemon::running(&repo_root)? {
+        bail!("A daemon is already running for this repository (pid {})", pid);
     }
 
+    let mut child = daemon::spawn(&repo_root, &options.to_args())?;
+    let log = daemon::log_path(&repo_root);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while daemon::running(&repo_root)? != Some(child.id()) {
+        if child.try_wait()?.is_some() || Instant::now() > deadline {
+            bail!("The daemon did not start; see {}", log.display());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    println!("Started the daemon (pid {}); its output goes to {}", child.id(), log.display());
     Ok(())
 }
 
-/// Find files by hash
-pub fn grep(hash: &str) -> Result<()> {
+/// Ask the repository's daemon to stop and wait for it to finish its batch
+pub fn daemon_stop() -> Result<()> {
     let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
-    let index = Index::load(&repo_root)?;
-    
-    let matches = index.find_by_hash(hash)?;
-    
-    if matches.is_empty() {
-        println!("No files found with hash: {}", hash);
-        return Ok(());
+    let Some(pid) = daemon::running(&repo_root)? else {
+        bail!("No daemon is running for this repository");
+    };
+    daemon::request_stop(pid)?;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while daemon::is_alive(pid) {
+        if Instant::now() > deadline {
+            println!("Asked the daemon (pid {}) to stop; it is still finishing its current batch", pid);
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(50));
     }
-    
-    println!("Found {} file(s) with hash {}:", matches.len(), hash);
-    for entry in matches {
-        println!("{}", file_utils::format_entry(&entry));
+    println!("Stopped the daemon (pid {})", pid);
+    Ok(())
+}
+
+pub fn daemon_status() -> Result<()> {
+    let repo_root = find_repo_root()?;
+    match daemon::running(&repo_root)? {
+        Some(pid) => {
+            println!("Daemon running (pid {}); its output goes to {}", pid, daemon::log_path(&repo_root).display());
+            if let Some(position) = daemon::load_verify_position(&repo_root) {
+                println!("Idle-time verification has reached {}", position);
+            }
+        }
+        None => println!("No daemon running"),
     }
-    
     Ok(())
 }
 
-/// Find duplicate files (files with identical content)
-pub fn duplicates() -> Result<()> {
+/// Run the daemon in the foreground: keep the index current like `watch`, and verify the index
+/// a slice at a time whenever nothing changed for an interval
+pub fn daemon_run(options: DaemonOptions) -> Result<()> {
+    check_watch_options(options.interval)?;
+    let rate = parse_size("--io-limit", &options.io_limit)?;
+    if rate == 0 {
+        bail!("--io-limit must be more than 0");
+    }
     let repo_root = find_repo_root()?;
     check_version(&repo_root)?;
-    let current_dir = get_logical_current_dir()?;
-    let index = Index::load(&repo_root)?;
+    let _pid = daemon::PidFile::create(&repo_root)?;
+    daemon::handle_stop_signals();
+    println!("Daemon started (pid {})", std::process::id());
+    let server = start_query_server(&repo_root);
+
+    let mut verifier = IdleVerifier::new(&repo_root, rate, Duration::from_secs(options.interval), options.verify_every);
+    let on_idle = || if options.no_verify { Ok(()) } else { verifier.verify_slice() };
+    watch_repo(&repo_root, options.verbose, options.interval, on_idle, daemon::stop_requested, server.as_ref())?;
+    println!("Daemon stopped");
+    Ok(())
+}
 
-    // Get all files from the repository recursively
-    let entries: Vec<_> = index.get_dir_files_recursive("")?;
+/// Answer `status`, `ls` and `grep` for other commands, or carry on without (they read the index themselves)
+#[cfg(unix)]
+fn start_query_server(repo_root: &Path) -> Option<ipc::Server> {
+    match ipc::Server::start(repo_root) {
+        Ok(server) => {
+            println!("Answering queries on {}", server.socket_path().display());
+            Some(server)
+        }
+        Err(e) => {
+            warn!("Not answering queries: {:#}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn start_query_server(_repo_root: &Path) -> Option<ipc::Server> {
+    None
+}
+
+/// A verification pass over the index spread over the daemon's idle time: files are re-hashed
+/// in path order, one slice of about an interval's worth of reading at a time
+struct IdleVerifier {
+    repo_root: PathBuf,
+    /// Bytes per second, and bytes per slice
+    rate: u64,
+    slice_bytes: u64,
+    /// Days to wait after a pass before starting the next
+    every_days: u64,
+    /// Files left in this pass
+    queue: std::collections::VecDeque<String>,
+    summary: VerifySummary,
+}
+
+impl IdleVerifier {
+    fn new(repo_root: &Path, rate: u64, interval: Duration, every_days: u64) -> Self {
+        IdleVerifier {
+            repo_root: repo_root.to_path_buf(),
+            rate,
+            slice_bytes: (rate as f64 * interval.as_secs_f64()) as u64,
+            every_days,
+            queue: Default::default(),
+            summary: VerifySummary::default(),
+        }
+    }
+
+    fn verify_slice(&mut self) -> Result<()> {
+        let config = check_version(&self.repo_root)?;
+        let roots = Roots::from_config(self.repo_root.clone(), &config);
+        let mut index = Index::load(&self.repo_root)?;
+        if self.queue.is_empty() {
+            // A pass interrupted by a restart carries on after the last file it verified
+            let position = daemon::load_verify_position(&self.repo_root);
+            if position.is_none() {
+                let last_pass = [index.last_operation("verify")?, index.last_operation("verify (failed)")?]
+                    .into_iter()
+                    .flatten()
+                    .map(|op| op.timestamp)
+                    .max();
+                let due = self.every_days * 24 * 60 * 60 * 1000;
+                if last_pass.is_some_and(|last| file_utils::now_millis().saturating_sub(last) < due) {
+                    return Ok(());
+                }
+            }
+            let mut paths: Vec<String> = index.get_dir_files_recursive("")?.into_iter().map(|entry| entry.path).collect();
+            paths.sort();
+            self.queue = paths.into_iter().filter(|path| position.as_ref().is_none_or(|position| path > position)).collect();
+            if self.queue.is_empty() {
+                return daemon::save_verify_position(&self.repo_root, None);
+            }
+        }
+
+        // A budget per slice, so time spent on batches doesn't turn into a burst of reading
+        let budget = IoBudget::new(Some(self.rate));
+        let mut read = 0;
+        let mut last = None;
+        while read < self.slice_bytes {
+            let Some(path) = self.queue.pop_front() else {
+                break;
+            };
+            // The entry as of now: the file may have been updated since the pass started
+            let Some(entry) = index.get(&path)? else {
+                continue;
+            };
+            if entry.sha256.is_empty() {
+                self.summary.pending += 1;
+                continue;
+            }
+            let full_path = roots.full_path(&path);
+            let finding = verify::check_file(&full_path, &entry.sha256, |bytes| {
+                budget.consume(bytes);
+                read += bytes;
+            })?;
+            match finding {
+                None | Some(Finding::AttributesChanged) => self.summary.verified += 1,
+                // Deletions and changes not yet batched are the watcher's; only a file whose
+                // size and modified time still match its entry has really changed underneath
+                Some(Finding::Missing) => {}
+                Some(Finding::Mismatch) if file_utils::has_changed(&entry, &full_path, ChangeDetection::Metadata).unwrap_or(true) => {}
+                Some(Finding::Mismatch) => {
+                    println!("Mismatch: {}", path);
+                    self.summary.mismatched += 1;
+                }
+                Some(Finding::PermissionDenied) => warn!("Skipping file (permission denied): {}", path),
+            }
+            last = Some(path);
+        }
+        self.summary.bytes += read;
+
+        if !self.queue.is_empty() {
+            if let Some(last) = last {
+                daemon::save_verify_position(&self.repo_root, Some(&last))?;
+            }
+            return Ok(());
+        }
+        let summary = std::mem::take(&mut self.summary);
+        let command = if summary.failed() { "verify (failed)" } else { "verify" };
+        index.log_operation(&Operation::new(&config, command, summary.describe()))?;
+        index.save(&self.repo_root)?;
+        daemon::save_verify_position(&self.repo_root, None)?;
+        println!("Verified {} file(s) in idle time: {}", summary.verified + summary.mismatched, summary.describe());
+        Ok(())
+    }
+}
+
+/// Run a hook after its command's changes are saved, warning rather than failing if it does
+fn run_post_hook(repo_root: &Path, hook: Hook, fields: serde_json::Map<String, serde_json::Value>) {
+    if let Err(e) = hooks::run(repo_root, hook, fields) {
+        warn!("{:#}", e);
+    }
+}
+
+/// Remove index entries whose files are gone, checking only metadata (much faster than `update`)
+/// Entries under a registered root that is not mounted are kept, as their files can't be checked.
+pub fn gc() -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let display_ctx = DisplayContext::new(roots.clone(), get_logical_current_dir()?);
+    let mut index = Index::load(&repo_root)?;
+
+    let entries = index.get_dir_files_recursive("")?;
+    let checked = entries.len();
+    let mut unmounted = std::collections::BTreeSet::new();
+    let mut removed = Vec::new();
+    for entry in entries {
+        let full_path = roots.full_path(&entry.path);
+        let base = roots.base_of(&full_path).map(|(base, _)| base.to_path_buf());
+        if let Some(base) = base.filter(|base| !base.is_dir()) {
+            unmounted.insert(base);
+            continue;
+        }
+        let gone = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) => !metadata.is_file(),
+            Err(e) => matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory),
+        };
+        if gone {
+            StatusMarker::Deleted.display(&display_ctx.make_relative(&entry.path)?);
+            removed.push(entry);
+        }
+    }
+
+    for base in &unmounted {
+        println!("Note: skipped files under {}, which is not mounted", base.display());
+    }
+    if removed.is_empty() {
+        println!("No missing files among {} indexed file(s)", checked);
+        return Ok(());
+    }
+
+    let count = removed.len();
+    let bytes: u64 = removed.iter().map(|entry| entry.num_bytes).sum();
+    back_up_index(&repo_root, &config, "gc")?;
+    index.remove_departed(removed, "deleted")?;
+    index.log_operation(&Operation::new(&config, "gc", format!("removed {} missing file(s) ({})", count, format_bytes(bytes))))?;
+    index.save(&repo_root)?;
+    println!("Removed {} missing file(s) ({}) from the index; checked {} file(s)", count, format_bytes(bytes), checked);
+    Ok(())
+}
+
+/// Move a file or directory on disk and rewrite its index entries in place (no re-hash)
+pub fn mv(source: String, destination: String) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let mut index = Index::load(&repo_root)?;
+    
+    let src_rel = repo_relative_path(&roots, &current_dir, &source)?;
+    let src_path = roots.full_path(&src_rel);
+    if src_rel.is_empty() || src_path.symlink_metadata().is_err() {
+        bail!("Path does not exist: {}{}", source, did_you_mean(&roots, &index, &current_dir, &src_rel)?);
+    }
+    if is_oci_internal(&src_rel) {
+        bail!("Cannot move {}: it is part of the repository's {} directory", source, OCI_DIR);
+    }
+    
+    // Like mv, moving onto an existing directory moves into it
+    let mut dst_rel = repo_relative_path(&roots, &current_dir, &destination)?;
+    if roots.full_path(&dst_rel).is_dir() {
+        let name = src_rel.rsplit('/').next().unwrap_or(&src_rel);
+        dst_rel = format!("{}{}", dir_prefix(&dst_rel), name);
+    }
+    let dst_path = roots.full_path(&dst_rel);
+    if dst_path.symlink_metadata().is_ok() {
+        bail!("Destination already exists: {}", dst_path.display());
+    }
+    if dst_rel.starts_with(&dir_prefix(&src_rel)) {
+        bail!("Cannot move {} into itself", source);
+    }
+    if is_oci_internal(&dst_rel) {
+        bail!("Cannot move {} into the repository's {} directory", source, OCI_DIR);
+    }
+    let patterns = ignore::load_patterns(&repo_root)?;
+    let is_dir = src_path.is_dir();
+    let ignored = |rel: &str| {
+        if is_dir {
+            ignore::should_ignore_dir(Path::new(rel), &patterns)
+        } else {
+            ignore::should_ignore(Path::new(rel), &patterns)
+        }
+    };
+    if ignored(&src_rel) {
+        bail!("Source is ignored: {} (use mv and 'oci update' to bring it into the index)", src_rel);
+    }
+    if ignored(&dst_rel) {
+        bail!("Destination is ignored: {} (use mv and 'oci update' to drop it from the index)", dst_rel);
+    }
+    
+    fs::rename(&src_path, &dst_path)
+        .context(format!("Failed to move {} to {}", src_path.display(), dst_path.display()))?;
+    
+    // Entries keep their hashes and modified times, which a rename does not change
+    let src_prefix = dir_prefix(&src_rel);
+    let renamed = |path: &str| match path.strip_prefix(&src_prefix) {
+        Some(rest) => format!("{}/{}", dst_rel, rest),
+        None => dst_rel.clone(),
+    };
+    let mut entries = index.get_dir_files_recursive(&src_rel)?;
+    entries.extend(index.get(&src_rel)?);
+    let tags: Vec<(String, StorageClass)> = index.storage_class_tags()?
+        .into_iter()
+        .filter(|(path, _)| *path == src_rel || path.starts_with(&src_prefix))
+        .collect();
+    let labels: Vec<(String, String)> = index.tags()?
+        .into_iter()
+        .filter(|(path, _)| *path == src_rel || path.starts_with(&src_prefix))
+        .collect();
+    let mut members: Vec<(String, Vec<ArchiveMember>)> = Vec::new();
+    for member in index.archive_members()? {
+        if member.archive != src_rel && !member.archive.starts_with(&src_prefix) {
+            continue;
+        }
+        let archive = renamed(&member.archive);
+        let member = ArchiveMember { archive: archive.clone(), ..member };
+        match members.last_mut() {
+            Some((last, list)) if *last == archive => list.push(member),
+            _ => members.push((archive, vec![member])),
+        }
+    }
+    let media: Vec<MediaInfo> = index.media()?
+        .into_iter()
+        .filter(|info| info.path == src_rel || info.path.starts_with(&src_prefix))
+        .map(|info| MediaInfo { path: renamed(&info.path), ..info })
+        .collect();
+    let prints: Vec<AudioFingerprint> = index.audio_fingerprints()?
+        .into_iter()
+        .filter(|print| print.path == src_rel || print.path.starts_with(&src_prefix))
+        .map(|print| AudioFingerprint { path: renamed(&print.path), ..print })
+        .collect();
+    
+    index.begin_batch()?;
+    index.remove_many(entries.iter().map(|entry| &entry.path))?;
+    index.upsert_many(entries.iter().map(|entry| FileEntry { path: renamed(&entry.path), ..entry.clone() }))?;
+    for (path, class) in &tags {
+        index.clear_storage_class(path)?;
+        index.set_storage_class(&renamed(path), *class)?;
+    }
+    for (path, tag) in &labels {
+        index.remove_tag(path, tag)?;
+        index.add_tag(&renamed(path), tag)?;
+    }
+    for (archive, list) in &members {
+        index.set_archive_members(archive, list)?;
+    }
+    for info in &media {
+        index.set_media(info)?;
+    }
+    for print in &prints {
+        index.set_audio(print)?;
+    }
+    index.log_operation(&Operation::new(
+        &config,
+        "mv",
+        format!("moved {} to {} ({} entries)", src_rel, dst_rel, entries.len()),
+    ))?;
+    index.commit_batch()?;
+    index.save(&repo_root)?;
+    
+    println!("Moved {} -> {} ({} index entries updated)", src_rel, dst_rel, entries.len());
+    Ok(())
+}
+
+/// Delete a file or directory by moving it into the pruneyard and dropping its index entries
+/// (`prune --restore` brings it back, `prune --purge` deletes it for good)
+pub fn rm(path: String, override_policy: bool, trash: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    // The pruneyard is under this root, and 'prune --restore' only knows this root's paths
+    if !config.roots.is_empty() {
+        bail!("Cannot remove: rm does not support indexes with multiple roots yet");
+    }
+    let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let mut index = Index::load(&repo_root)?;
+    
+    let rel_path = repo_relative_path(&roots, &current_dir, &path)?;
+    let full_path = roots.full_path(&rel_path);
+    if rel_path.is_empty() {
+        bail!("Cannot remove the root of the repository");
+    }
+    if is_oci_internal(&rel_path) {
+        bail!("Cannot remove {}: it is part of the repository's {} directory", path, OCI_DIR);
+    }
+    if full_path.symlink_metadata().is_err() {
+        bail!("Path does not exist: {}{}", path, did_you_mean(&roots, &index, &current_dir, &rel_path)?);
+    }
+    
+    // Every file goes, whether indexed or not, so the directory can be cleaned up afterwards
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&full_path) {
+        let entry = entry?;
+        if !entry.file_type().is_dir() {
+            let rel = entry.path().strip_prefix(&full_path).context("Path is outside repository")?;
+            let file_rel = if rel.as_os_str().is_empty() {
+                rel_path.clone()
+            } else {
+                roots::join_index_path(&rel_path, rel).to_string_lossy().to_string()
+            };
+            files.push((file_rel, entry.into_path()));
+        }
+    }
+    
+    let policy = Policy::load(&repo_root)?;
+    let violations: Vec<_> = files.iter()
+        .filter_map(|(file_rel, _)| policy.check_prune(file_rel))
+        .collect();
+    enforce_policy(&violations, "Remove", override_policy)?;
+    
+    // Empty parents are cleaned up to the root the path belongs to
+    let base = roots.base_of(&full_path).map_or(repo_root.clone(), |(base, _)| base.to_path_buf());
+    let disposal = Disposal::new(&repo_root, trash || config.prune_to_trash);
+    let mut total_bytes = 0u64;
+    for (file_rel, source_file) in &files {
+        total_bytes += source_file.symlink_metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        disposal.dispose(source_file, file_rel)?;
+        dir_utils::remove_empty_parent_dirs(source_file, &base)?;
+        println!("Removed: {}", file_rel);
+    }
+    if full_path.is_dir() {
+        // Only empty subdirectories are left
+        fs::remove_dir_all(&full_path)
+            .context(format!("Failed to remove directory: {}", full_path.display()))?;
+        dir_utils::remove_empty_parent_dirs(&full_path, &base)?;
+    }
+    
+    let mut entries = index.get_dir_files_recursive(&rel_path)?;
+    entries.extend(index.get(&rel_path)?);
+    index.remove_departed(entries, "removed")?;
+    index.log_operation(&Operation::new(
+        &config,
+        "rm",
+        format!("removed {} ({} file(s), {} bytes)", rel_path, files.len(), total_bytes),
+    ))?;
+    index.save(&repo_root)?;
+    
+    match disposal {
+        Disposal::Pruneyard(_) => println!(
+            "Moved {} file(s) ({}) to the pruneyard; 'oci prune --restore' brings them back",
+            files.len(),
+            format_bytes(total_bytes)
+        ),
+        Disposal::Trash => println!("Moved {} file(s) ({}) to the trash", files.len(), format_bytes(total_bytes)),
+    }
+    Ok(())
+}
+
+/// Copy files into the repository, skipping content that is already indexed anywhere in it
+/// Copied files are indexed with the hash computed for the duplicate check, so they need no update.
+pub fn cp(source: String, destination: String) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let options = CaptureOptions::from_config(&config);
+    let patterns = ignore::load_patterns(&repo_root)?;
+    let mut index = Index::load(&repo_root)?;
+    
+    let src_dir = normalize_path(&current_dir.join(&source));
+    if !src_dir.is_dir() {
+        bail!("Source is not a directory: {}", source);
+    }
+    let dst_rel = repo_relative_path(&roots, &current_dir, &destination)?;
+    let dst_dir = roots.full_path(&dst_rel);
+    if dst_dir.starts_with(&src_dir) {
+        bail!("Cannot copy {} into itself", source);
+    }
+    
+    // Content copied in this run counts as present too, so a card holding the same photo twice copies it once
+    let mut copied_hashes: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+    let (mut copied, mut copied_bytes) = (0usize, 0u64);
+    let (mut duplicates, mut duplicate_bytes) = (0usize, 0u64);
+    let (mut existing, mut ignored) = (0usize, 0usize);
+    
+    index.begin_batch()?;
+    for entry in WalkDir::new(&src_dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(&src_dir).context("Path is outside source")?;
+        let dst_path_rel = roots::join_index_path(&dst_rel, rel).to_string_lossy().to_string();
+        if ignore::should_ignore(Path::new(&dst_path_rel), &patterns) {
+            ignored += 1;
+            continue;
+        }
+        
+        let num_bytes = file_utils::get_file_size(entry.path())?;
+        let (sha256, digests) = file_utils::compute_digests(entry.path(), options.digests)?;
+        let indexed = index.find_by_hash(&sha256)?.into_iter().next().map(|entry| entry.path);
+        if let Some(original) = indexed.or_else(|| copied_hashes.get(&sha256).cloned()) {
+            println!("Skipped (duplicate of {}): {}", original, rel.display());
+            duplicates += 1;
+            duplicate_bytes += num_bytes;
+            continue;
+        }
+        
+        let dst_path = dst_dir.join(rel);
+        if dst_path.symlink_metadata().is_ok() {
+            println!("Skipped (exists): {}", dst_path_rel);
+            existing += 1;
+            continue;
+        }
+        if let Some(parent) = dst_path.parent() {
+            fs::create_dir_all(parent)
+                .context(format!("Failed to create directory: {}", parent.display()))?;
+        }
+        file_utils::copy_preserving_mtime(entry.path(), &dst_path)?;
+        
+        let xattrs = if options.xattrs { Some(file_utils::read_xattrs(&dst_path)?) } else { None };
+        let mime = if options.detect_types { Some(file_utils::detect_type(&dst_path)?) } else { None };
+        index.upsert(FileEntry {
+            num_bytes,
+            modified: file_utils::get_modified_time(&dst_path)?,
+            sha256: sha256.clone(),
+            path: dst_path_rel.clone(),
+            xattrs,
+            quick_hash: None,
+            mime,
+            allocated: file_utils::get_allocated_size(&dst_path)?,
+            digests,
+        })?;
+        println!("Copied: {}", dst_path_rel);
+        copied_hashes.insert(sha256, dst_path_rel);
+        copied += 1;
+        copied_bytes += num_bytes;
+    }
+    
+    let mut counts = format!(
+        "{} file(s) ({}), skipped {} duplicate(s) ({})",
+        copied, format_bytes(copied_bytes), duplicates, format_bytes(duplicate_bytes)
+    );
+    if existing > 0 {
+        counts.push_str(&format!(", {} already existed", existing));
+    }
+    if ignored > 0 {
+        counts.push_str(&format!(", {} ignored", ignored));
+    }
+    index.log_operation(&Operation::new(&config, "cp", format!("copied {} from {}", counts, src_dir.display())))?;
+    index.commit_batch()?;
+    index.save(&repo_root)?;
+    
+    println!("Copied {}", counts);
+    Ok(())
+}
+
+/// Mirror the indexed files to a destination directory, verifying every copy against the index
+pub fn sync(destination: String, delete: bool, dry_run: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let patterns = ignore::load_patterns(&repo_root)?;
+    let mut index = Index::load(&repo_root)?;
+    
+    let dest = normalize_path(&current_dir.join(&destination));
+    if dest.exists() && roots.contains_canonical(&dest.canonicalize()?)? {
+        bail!("Destination is inside the repository: {}", dest.display());
+    }
+    if let Ok(canonical_repo) = repo_root.canonicalize() {
+        if dest.canonicalize().is_ok_and(|dest| canonical_repo.starts_with(dest)) {
+            bail!("Destination contains the repository: {}", dest.display());
+        }
+    }
+    if !dry_run {
+        fs::create_dir_all(&dest)
+            .context(format!("Failed to create directory: {}", dest.display()))?;
+    }
+    
+    let options = sync::SyncOptions { delete, dry_run };
+    let summary = sync::sync(&roots, &index, &patterns, &dest, options, |action| match action {
+        sync::Action::Copy(path) => StatusMarker::Added.display(path),
+        sync::Action::Replace(path) => StatusMarker::Updated.display(path),
+        sync::Action::Move { from, to } => StatusMarker::Added.display(&format!("{} (moved from {})", to, from)),
+        sync::Action::Delete(path) => StatusMarker::Deleted.display(path),
+        sync::Action::Stale(path) => {
+            warn!("Skipping {}: changed since the last update (run 'oci update')", path);
+        }
+        sync::Action::Failed(path) => {
+            error!("Copy of {} does not match the index; the destination was left unchanged", path);
+        }
+    })?;
+    
+    let verb = if dry_run { "Would sync" } else { "Synced" };
+    println!("{} to {}: {} ({} copied)", verb, dest.display(), summary.describe(), format_bytes(summary.bytes_copied));
+    if !dry_run {
+        index.log_operation(&Operation::new(&config, "sync", format!("to {}: {}", dest.display(), summary.describe())))?;
+        index.save(&repo_root)?;
+    }
+    
+    if summary.stale + summary.failed > 0 {
+        bail!(
+            "{} file(s) could not be synced ({} changed since the last update, {} failed verification)",
+            summary.stale + summary.failed, summary.stale, summary.failed
+        );
+    }
+    Ok(())
+}
+
+/// List files in the index
+#[allow(clippy::too_many_arguments)]
+pub fn ls(
+    recursive: bool,
+    tree: bool,
+    content_type: Option<String>,
+    tag: Option<String>,
+    filter: MediaFilter,
+    format: Option<String>,
+    limit: Option<usize>,
+    since: Option<String>,
+) -> Result<()> {
+    let format: Option<OutputFormat> = format.map(|format| format.parse()).transpose()?;
+    let since = since.as_deref().map(since::parse).transpose()?;
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    if let Some(limit) = limit {
+        display::set_limit(limit);
+    }
+    let _pager = pager::start();
+    let current_dir = get_logical_current_dir()?;
+    let mut index = IndexView::open(&repo_root)?;
+
+    let roots = Roots::from_config(repo_root, &config);
+    let rel_current_str = roots.require_index_path(&current_dir)?;
+
+    if format == Some(OutputFormat::Ndjson) {
+        let display_ctx = DisplayContext::new(roots, current_dir);
+        return ls_ndjson(&mut index, &display_ctx, &rel_current_str, recursive, content_type, tag, filter, since);
+    }
+
+    let mut entries = index.dir_files(&rel_current_str, recursive || tree)?;
+
+    if entries.is_empty() {
+        println!("No files in index");
+        return Ok(());
+    }
+
+    if let Some(content_type) = content_type {
+        let pattern = content_type_pattern(content_type)?;
+        entries.retain(|entry| entry.mime.as_deref().is_some_and(|mime| pattern.matches(mime)));
+        if entries.is_empty() {
+            println!("No files of type {} in index", pattern);
+            return Ok(());
+        }
+    }
+
+    if let Some(tag) = tag {
+        let tags = Tags::new(index.tags()?);
+        entries.retain(|entry| tags.has(&entry.path, &tag));
+        if entries.is_empty() {
+            println!("No files tagged {} in index", tag);
+            return Ok(());
+        }
+    }
+
+    if let Some(since) = since {
+        entries.retain(|entry| entry.modified >= since);
+        if entries.is_empty() {
+            println!("No files modified since {} in index", oplog::format_local_timestamp(since));
+            return Ok(());
+        }
+    }
+
+    let media: std::collections::HashMap<String, MediaInfo> = if filter.is_active() || filter.show {
+        index.media()?.into_iter().map(|info| (info.path.clone(), info)).collect()
+    } else {
+        std::collections::HashMap::new()
+    };
+    if filter.is_active() {
+        entries.retain(|entry| media.get(&entry.path).is_some_and(|info| filter.matches(info)));
+        if entries.is_empty() {
+            println!("No matching photos or videos in index");
+            return Ok(());
+        }
+    }
+
+    if tree {
+        let prefix = dir_prefix(&rel_current_str);
+        let files: Vec<(String, u64)> = entries.iter()
+            .map(|entry| (entry.path.strip_prefix(&prefix).unwrap_or(&entry.path).to_string(), entry.num_bytes))
+            .collect();
+        for line in display::format_tree(".", &files) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    // Sort by path for consistent output
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let display_ctx = DisplayContext::new(roots, current_dir);
+    for entry in entries {
+        if !display::within_limit() {
+            continue;
+        }
+        if let Some(format) = &format {
+            println!("{}", format.render_entry(&entry, &display_ctx.make_relative(&entry.path)?));
+            continue;
+        }
+        let formatted = display_ctx.format_entry_relative(&entry)?;
+        match media.get(&entry.path).map(MediaInfo::describe).filter(|info| filter.show && !info.is_empty()) {
+            Some(info) => println!("{}  [{}]", formatted, info),
+            None => println!("{}", formatted),
+        }
+    }
+    // After a template the count goes to stderr, keeping stdout to one file per line
+    match display::omitted_summary() {
+        Some(more) if format.is_some() => eprintln!("{}", more),
+        Some(more) => println!("{}", more),
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Pattern for `ls --type`; a bare top-level type ("image") matches all of its subtypes
+fn content_type_pattern(content_type: String) -> Result<glob::Pattern> {
+    let pattern = if content_type.contains('/') { content_type } else { format!("{}/*", content_type) };
+    glob::Pattern::new(&pattern).context(format!("Invalid content type pattern: {}", pattern))
+}
+
+/// `ls --format ndjson`: stream each matching entry as a JSON line as it comes out of the index,
+/// so listings of millions of files never sit in memory; nothing at all is printed for no matches
+#[allow(clippy::too_many_arguments)]
+fn ls_ndjson(
+    index: &mut IndexView,
+    display_ctx: &DisplayContext,
+    dir: &str,
+    recursive: bool,
+    content_type: Option<String>,
+    tag: Option<String>,
+    filter: MediaFilter,
+    since: Option<u64>,
+) -> Result<()> {
+    use std::io::Write;
+
+    let pattern = content_type.map(content_type_pattern).transpose()?;
+    let tags = match tag {
+        Some(tag) => Some((Tags::new(index.tags()?), tag)),
+        None => None,
+    };
+    let media: std::collections::HashMap<String, MediaInfo> = if filter.is_active() {
+        index.media()?.into_iter().map(|info| (info.path.clone(), info)).collect()
+    } else {
+        std::collections::HashMap::new()
+    };
+
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let result = index.visit_dir_files(dir, recursive, |entry| {
+        let keep = since.is_none_or(|since| entry.modified >= since)
+            && pattern.as_ref().is_none_or(|p| entry.mime.as_deref().is_some_and(|mime| p.matches(mime)))
+            && tags.as_ref().is_none_or(|(tags, tag)| tags.has(&entry.path, tag))
+            && (!filter.is_active() || media.get(&entry.path).is_some_and(|info| filter.matches(info)));
+        if keep && display::within_limit() {
+            let line = OutputFormat::Ndjson.render_entry(&entry, &display_ctx.make_relative(&entry.path)?);
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }).and_then(|()| Ok(out.flush()?));
+    if let Some(more) = display::omitted_summary() {
+        eprintln!("{}", more);
+    }
+
+    // A reader that stops early (`| head`) is not an error
+    match result {
+        Err(err) if err.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) => Ok(()),
+        result => result,
+    }
+}
+
+/// Find files by hash, for each of several hashes (`-` reads more from stdin)
+pub fn grep(args: Vec<String>, algorithm: Option<String>, format: Option<String>) -> Result<()> {
+    let format: Option<OutputFormat> = format.map(|format| format.parse()).transpose()?;
+    let algorithm: Option<Algorithm> = algorithm.map(|algorithm| algorithm.parse()).transpose()?;
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    let mut index = IndexView::open(&repo_root)?;
+
+    let mut hashes = Vec::new();
+    for arg in args {
+        if arg == "-" {
+            hashes.extend(read_hashes(std::io::stdin().lock())?);
+        } else {
+            hashes.push(arg.to_ascii_lowercase());
+        }
+    }
+    if hashes.is_empty() {
+        bail!("No hashes given on stdin");
+    }
+
+    let mut unmatched = Vec::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        // Only SHA256 is recorded for every file, so other digests are looked up by name
+        let algorithm = algorithm.unwrap_or_else(|| Algorithm::for_hex_len(hash.len()));
+        let found = match &format {
+            Some(format) => grep_hash_formatted(&mut index, hash, algorithm, format)?,
+            None => {
+                if i > 0 {
+                    println!();
+                }
+                grep_hash(&mut index, hash, algorithm)?
+            }
+        };
+        if !found {
+            unmatched.push(hash);
+        }
+    }
+
+    // With a template, stdout only carries the matches, so a pipeline never reads this report
+    if format.is_some() {
+        if !unmatched.is_empty() {
+            eprintln!("No match for {} of {} hash(es):", unmatched.len(), hashes.len());
+            for hash in unmatched {
+                eprintln!("  {}", hash);
+            }
+        }
+    } else if hashes.len() > 1 && !unmatched.is_empty() {
+        println!();
+        println!("No match for {} of {} hash(es):", unmatched.len(), hashes.len());
+        for hash in unmatched {
+            println!("  {}", hash);
+        }
+    }
+    Ok(())
+}
+
+/// Print the files with one hash using a template; returns whether there were any
+fn grep_hash_formatted(index: &mut IndexView, hash: &str, algorithm: Algorithm, format: &OutputFormat) -> Result<bool> {
+    let (matches, members) = find_hash(index, hash, algorithm)?;
+    for entry in &matches {
+        println!("{}", format.render_entry(entry, &entry.path));
+    }
+    for member in &members {
+        println!("{}", format.render_member(member, &member.archive));
+    }
+    Ok(!matches.is_empty() || !members.is_empty())
+}
+
+/// The files and archive members with a hash (members only record SHA256)
+fn find_hash(index: &mut IndexView, hash: &str, algorithm: Algorithm) -> Result<(Vec<FileEntry>, Vec<ArchiveMember>)> {
+    let matches = index.find_by_digest(algorithm, hash)?;
+    let members = match algorithm {
+        Algorithm::Sha256 => index.find_members_by_hash(hash)?,
+        _ => Vec::new(),
+    };
+    Ok((matches, members))
+}
+
+/// Hashes listed one per line, as the first word of each line so the output of `sha256sum` and
+/// similar tools works as is; blank lines and `#` comments are skipped
+fn read_hashes(reader: impl std::io::BufRead) -> Result<Vec<String>> {
+    let mut hashes = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("Failed to read hashes from stdin")?;
+        if let Some(hash) = line.split_whitespace().next().filter(|word| !word.starts_with('#')) {
+            hashes.push(hash.to_ascii_lowercase());
+        }
+    }
+    Ok(hashes)
+}
+
+/// List the files with one hash; returns whether there were any
+fn grep_hash(index: &mut IndexView, hash: &str, algorithm: Algorithm) -> Result<bool> {
+    let (matches, members) = find_hash(index, hash, algorithm)?;
+    let label = match algorithm {
+        Algorithm::Sha256 => "hash".to_string(),
+        other => format!("{} digest", other),
+    };
+    
+    if matches.is_empty() && members.is_empty() {
+        println!("No files found with {}: {}", label, hash);
+        return Ok(false);
+    }
+    
+    println!("Found {} file(s) with {} {}:", matches.len() + members.len(), label, display::highlight_hash(hash));
+    for entry in matches {
+        let line = file_utils::format_entry(&entry);
+        println!("{}", line.replacen(&entry.sha256, &display::highlight_hash(&entry.sha256), 1));
+    }
+    for member in members {
+        let line = file_utils::format_member(&member);
+        println!("{}", line.replacen(&member.sha256, &display::highlight_hash(&member.sha256), 1));
+    }
+    
+    Ok(true)
+}
+
+/// Find duplicate files (files with identical content)
+#[allow(clippy::too_many_arguments)]
+pub fn duplicates(audio: bool, resolve: bool, auto: bool, keep: Vec<String>, paranoid: bool, against: Vec<String>, dirs: bool, format: Option<String>) -> Result<()> {
+    let format = format.map(|format| format.parse::<ExportFormat>()).transpose()?;
+    if (auto || !keep.is_empty() || paranoid) && !resolve {
+        let flag = if auto { "--auto" } else if paranoid { "--paranoid" } else { "--keep" };
+        bail!("{} requires --resolve", flag);
+    }
+    if audio && resolve {
+        bail!("--resolve only works on identical files, not --audio groups");
+    }
+    if !against.is_empty() && (audio || resolve) {
+        bail!("--against cannot be combined with {}", if audio { "--audio" } else { "--resolve" });
+    }
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+
+    if !against.is_empty() {
+        return cross_index_duplicates(&repo_root, &config, current_dir, &against);
+    }
+
+    if resolve {
+        let keep = match keep.is_empty() {
+            true => None,
+            false => match KeepPolicy::parse(&keep)? {
+                KeepPolicy::PreferDir(dir) => {
+                    let roots = Roots::from_config(repo_root.clone(), &config);
+                    Some(KeepPolicy::PreferDir(repo_relative_path(&roots, &current_dir, &dir)?))
+                }
+                policy => Some(policy),
+            },
+        };
+        return resolve_duplicates(&repo_root, &config, current_dir, auto, keep, paranoid);
+    }
+    let index = Index::load(&repo_root)?;
+
+    if audio {
+        let display_ctx = DisplayContext::new(Roots::from_config(repo_root, &config), current_dir);
+        return audio_duplicates(&index, &config, &display_ctx);
+    }
+    if dirs {
+        let display_ctx = DisplayContext::new(Roots::from_config(repo_root, &config), current_dir);
+        return duplicate_dirs(&index, &display_ctx);
+    }
+
+    // Get all files from the repository recursively
+    let entries: Vec<_> = index.get_dir_files_recursive("")?;
+    let total_files = entries.len();
 
     // Group files by hash
     let mut hash_groups: std::collections::HashMap<String, Vec<crate::index::FileEntry>> =
         std::collections::HashMap::new();
 
-    for entry in entries {
-        hash_groups
-            .entry(entry.sha256.clone())
-            .or_default()
-            .push(entry);
+    // Entries awaiting a full hash are grouped by quick fingerprint as candidates
+    let (pending, hashed): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.sha256.is_empty());
+    let candidate_groups = quick_hash_candidate_groups(pending, &hashed);
+
+    for entry in hashed {
+        hash_groups
+            .entry(entry.sha256.clone())
+            .or_default()
+            .push(entry);
+    }
+
+    // Archive members count as copies of loose files with the same hash
+    let mut members_by_hash: std::collections::HashMap<String, Vec<ArchiveMember>> =
+        std::collections::HashMap::new();
+    for member in index.archive_members()? {
+        if hash_groups.contains_key(&member.sha256) {
+            members_by_hash.entry(member.sha256.clone()).or_default().push(member);
+        }
+    }
+
+    // Filter to only hashes with duplicates (more than 1 file)
+    let mut duplicate_groups: Vec<_> = hash_groups
+        .into_iter()
+        .map(|(hash, files)| {
+            let members = members_by_hash.remove(&hash).unwrap_or_default();
+            (hash, files, members)
+        })
+        .filter(|(_, files, members)| files.len() + members.len() > 1)
+        .collect();
+
+    // Only loose files that are confirmed copies of each other, as the other tools would find
+    if let Some(format) = format {
+        let roots = Roots::from_config(repo_root, &config);
+        duplicate_groups.sort_by(|a, b| a.0.cmp(&b.0));
+        let groups: Vec<Vec<(PathBuf, FileEntry)>> = duplicate_groups
+            .into_iter()
+            .filter(|(_, files, _)| files.len() > 1)
+            .map(|(_, mut files, _)| {
+                files.sort_by(|a, b| a.path.cmp(&b.path));
+                files.into_iter().map(|entry| (roots.full_path(&entry.path), entry)).collect()
+            })
+            .collect();
+        print!("{}", dedupe::export(format, &groups, &current_dir, total_files));
+        return Ok(());
+    }
+
+    let display_ctx = DisplayContext::new(Roots::from_config(repo_root, &config), current_dir);
+
+    if duplicate_groups.is_empty() {
+        println!("No duplicate files found");
+        display_candidate_groups(candidate_groups, &display_ctx)?;
+        return Ok(());
+    }
+
+    // Sort groups by hash for consistent output
+    duplicate_groups.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // Calculate statistics
+    let total_duplicate_files: usize =
+        duplicate_groups.iter().map(|(_, files, members)| files.len() + members.len()).sum();
+    let total_groups = duplicate_groups.len();
+
+    // Calculate wasted space (all but one copy of each duplicate set, or every loose copy
+    // of content that is also kept in an archive)
+    let wasted_bytes: u64 = duplicate_groups
+        .iter()
+        .map(|(_, files, members)| {
+            let file_size = files[0].num_bytes;
+            let redundant = if members.is_empty() { files.len() - 1 } else { files.len() };
+            file_size * redundant as u64
+        })
+        .sum();
+    // On disk, keeping the copy that takes the most space
+    let wasted_allocated: u64 = duplicate_groups
+        .iter()
+        .map(|(_, files, members)| {
+            let total: u64 = files.iter().map(FileEntry::allocated_bytes).sum();
+            let kept = if members.is_empty() { files.iter().map(FileEntry::allocated_bytes).max().unwrap_or(0) } else { 0 };
+            total - kept
+        })
+        .sum();
+
+    println!(
+        "Found {} duplicate file(s) in {} group(s)",
+        total_duplicate_files, total_groups
+    );
+    println!("Potential space savings: {}, {} on disk\n",
+        display::format_total(wasted_bytes), display::format_total(wasted_allocated));
+
+    // Display each group
+    for (hash, mut files, mut members) in duplicate_groups {
+        println!("Hash: {}", display::highlight_hash(&hash));
+
+        // Sort files by path within each group for consistent output
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        members.sort_by(|a, b| (&a.archive, &a.member).cmp(&(&b.archive, &b.member)));
+
+        for entry in files {
+            let formatted = display_ctx.format_entry_relative(&entry)?;
+            println!("  {}", formatted);
+        }
+        for member in members {
+            println!("  {}", display_ctx.format_member_relative(&member)?);
+        }
+        println!();
+    }
+
+    display_candidate_groups(candidate_groups, &display_ctx)?;
+
+    Ok(())
+}
+
+/// Report content this index shares with other indexes (`duplicates --against`), listing
+/// where each copy lives. Duplicates found only within one index are left to plain `duplicates`.
+fn cross_index_duplicates(repo_root: &Path, config: &Config, current_dir: PathBuf, against: &[String]) -> Result<()> {
+    use std::collections::BTreeMap;
+
+    let local_index = Index::load(repo_root)?;
+    let mut locations: Vec<(String, Vec<FileEntry>)> = vec![("here".to_string(), local_index.get_dir_files_recursive("")?)];
+    for source_path in against {
+        let source = Source::parse(source_path, &current_dir)?;
+        let source_repo = source.open()?;
+        let index = Index::load(&source_repo.root).context(format!("Failed to load the index of {}", source))?;
+        locations.push((source.to_string(), index.get_dir_files_recursive("")?));
+    }
+
+    // Copies of each hash, per location (by position in `locations`)
+    let mut copies: BTreeMap<String, BTreeMap<usize, Vec<FileEntry>>> = BTreeMap::new();
+    let mut unhashed = 0;
+    for (i, (_, entries)) in locations.iter_mut().enumerate() {
+        for entry in entries.drain(..) {
+            // Entries still awaiting a full hash can't be matched
+            if entry.sha256.is_empty() {
+                unhashed += 1;
+                continue;
+            }
+            copies.entry(entry.sha256.clone()).or_default().entry(i).or_default().push(entry);
+        }
+    }
+    copies.retain(|_, by_location| by_location.len() > 1 && by_location.contains_key(&0));
+
+    if copies.is_empty() {
+        println!("No content here is also in {}", against.join(", "));
+    } else {
+        let display_ctx = DisplayContext::new(Roots::from_config(repo_root.to_path_buf(), config), current_dir);
+        let local: Vec<&FileEntry> = copies.values().flat_map(|by_location| &by_location[&0]).collect();
+        println!(
+            "Found {} file(s) here ({}) with copies in other indexes, in {} group(s)\n",
+            local.len(), display::format_total(local.iter().map(|e| e.num_bytes).sum()), copies.len()
+        );
+        for (hash, by_location) in copies {
+            println!("Hash: {}", display::highlight_hash(&hash));
+            for (i, mut files) in by_location {
+                files.sort_by(|a, b| a.path.cmp(&b.path));
+                println!("  {}:", locations[i].0);
+                for entry in &files {
+                    let formatted = match i {
+                        0 => display_ctx.format_entry_relative(entry)?,
+                        _ => file_utils::format_entry(entry),
+                    };
+                    println!("    {}", formatted);
+                }
+            }
+            println!();
+        }
+    }
+    if unhashed > 0 {
+        println!("Note: {} file(s) are awaiting a full hash and were not compared", unhashed);
+    }
+
+    Ok(())
+}
+
+/// Walk each group of identical files, keep one copy and move the others to the pruneyard
+/// (`duplicates --resolve`). A keep policy chooses the copy without asking, as does `auto`,
+/// which keeps the first copy by path. With `paranoid`, a copy is only moved once it has been
+/// compared byte for byte with the one kept.
+fn resolve_duplicates(
+    repo_root: &Path,
+    config: &Config,
+    current_dir: PathBuf,
+    auto: bool,
+    keep_policy: Option<KeepPolicy>,
+    paranoid: bool,
+) -> Result<()> {
+    let auto = auto || keep_policy.is_some();
+    let _lock = lock_repo(repo_root, config)?;
+    if has_pending_changes(repo_root)? {
+        return Err(OciError::PendingChanges { action: "resolve duplicates", source: None }.into());
+    }
+    let mut index = Index::load(repo_root)?;
+    let roots = Roots::from_config(repo_root.to_path_buf(), config);
+    let policy = Policy::load(repo_root)?;
+
+    let mut groups: std::collections::BTreeMap<String, Vec<FileEntry>> = std::collections::BTreeMap::new();
+    for entry in index.get_dir_files_recursive("")?.into_iter().filter(|e| !e.sha256.is_empty()) {
+        groups.entry(entry.sha256.clone()).or_default().push(entry);
+    }
+    let groups: Vec<Vec<FileEntry>> = groups.into_values().filter(|files| files.len() > 1).collect();
+    if groups.is_empty() {
+        println!("No duplicate files found");
+        return Ok(());
+    }
+
+    let display_ctx = DisplayContext::new(roots.clone(), current_dir);
+    let pruneyard_path = repo_root.join(OCI_DIR).join("pruneyard");
+    let mut moved = 0;
+    let mut moved_bytes = 0u64;
+    let mut unresolved = 0;
+    let mut stdin = std::io::stdin().lock();
+    for (i, mut files) in groups.into_iter().enumerate() {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let keep = if let Some(policy) = &keep_policy {
+            match policy.choose(&files) {
+                Some(keep) => keep,
+                None => {
+                    unresolved += 1;
+                    continue;
+                }
+            }
+        } else if auto {
+            0
+        } else {
+            println!("Group {} ({} copies of {}):", i + 1, files.len(), format_bytes(files[0].num_bytes));
+            for (n, entry) in files.iter().enumerate() {
+                println!("  {}) {}", n + 1, display_ctx.format_entry_relative(entry)?);
+            }
+            match ask_keep(&mut stdin, files.len())? {
+                KeepChoice::Copy(n) => n,
+                KeepChoice::Skip => {
+                    println!();
+                    continue;
+                }
+                KeepChoice::Quit => break,
+            }
+        };
+
+        for (n, entry) in files.iter().enumerate() {
+            if n == keep {
+                continue;
+            }
+            if let Some(violation) = policy.check_prune(&entry.path) {
+                println!("Kept ({}): {}", violation, entry.path);
+                continue;
+            }
+            let source_file = roots.full_path(&entry.path);
+            if paranoid && !file_utils::same_content(&source_file, &roots.full_path(&files[keep].path))? {
+                println!("Kept (differs from {} byte for byte): {}", files[keep].path, entry.path);
+                continue;
+            }
+            let dest_file = pruneyard_path.join(&entry.path);
+            if let Some(parent) = dest_file.parent() {
+                fs::create_dir_all(parent)
+                    .context(format!("Failed to create directory: {}", parent.display()))?;
+            }
+            fs::rename(&source_file, &dest_file)
+                .context(format!("Failed to move file: {}", source_file.display()))?;
+            let base = roots.base_of(&source_file).map_or(repo_root.to_path_buf(), |(base, _)| base.to_path_buf());
+            dir_utils::remove_empty_parent_dirs(&source_file, &base)?;
+            index.remove_departed(vec![entry.clone()], "pruned")?;
+            if !display::quiet() {
+                println!("Pruned (duplicate of {}): {}", files[keep].path, entry.path);
+            }
+            moved += 1;
+            moved_bytes += entry.num_bytes;
+        }
+        if !auto {
+            println!();
+        }
+    }
+
+    if unresolved > 0 {
+        if let Some(KeepPolicy::PreferDir(dir)) = &keep_policy {
+            println!("Left {} group(s) without a copy in {}/ as they are", unresolved, dir);
+        }
+    }
+    if moved == 0 {
+        println!("No files moved");
+        return Ok(());
+    }
+    let command = match &keep_policy {
+        Some(policy) => format!("duplicates --resolve --keep {}", policy),
+        None => "duplicates --resolve".to_string(),
+    };
+    index.log_operation(&Operation::new(
+        config,
+        &command,
+        format!("moved {} duplicate file(s) to the pruneyard ({})", moved, format_bytes(moved_bytes)),
+    ))?;
+    index.save(repo_root)?;
+    println!("Moved {} duplicate file(s) to .oci/pruneyard/ ({})", moved, format_bytes(moved_bytes));
+    println!("Undo with 'oci prune --restore', or delete them for good with 'oci prune --purge'");
+    Ok(())
+}
+
+enum KeepChoice {
+    Copy(usize),
+    Skip,
+    Quit,
+}
+
+/// Ask which copy of a group to keep, until the answer is a copy number, `s` or `q`
+/// (end of input counts as quitting)
+fn ask_keep(input: &mut impl std::io::BufRead, copies: usize) -> Result<KeepChoice> {
+    loop {
+        print!("Keep which copy? [1-{}, s = skip, q = quit]: ", copies);
+        std::io::Write::flush(&mut std::io::stdout())?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            println!();
+            return Ok(KeepChoice::Quit);
+        }
+        match line.trim() {
+            "s" | "S" => return Ok(KeepChoice::Skip),
+            "q" | "Q" => return Ok(KeepChoice::Quit),
+            answer => match answer.parse::<usize>() {
+                Ok(n) if (1..=copies).contains(&n) => return Ok(KeepChoice::Copy(n - 1)),
+                _ => println!("Enter a number from 1 to {}, s or q", copies),
+            },
+        }
+    }
+}
+
+/// Show groups of audio files that sound the same (`duplicates --audio`)
+/// Copies usually differ in bitrate, so the savings assume the largest file of each group is kept.
+/// Compare the content of two directories, each in whichever repository holds it
+pub fn overlap(first: String, second: String) -> Result<()> {
+    let current_dir = get_logical_current_dir()?;
+    let sides = [&first, &second]
+        .into_iter()
+        .map(|dir| -> Result<Vec<FileEntry>> {
+            let path = normalize_path(&current_dir.join(dir));
+            let repo_root = find_repo_root_from(path.clone())
+                .ok()
+                .with_context(|| format!("{} is not in an oci repository", dir))?;
+            let config = Config::load(&repo_root)?;
+            let roots = Roots::from_config(repo_root.clone(), &config);
+            let index_dir = roots.require_index_path(&roots.locate(&path))?;
+            let entries = Index::load(&repo_root)?.get_dir_files_recursive(&index_dir)?;
+            if entries.is_empty() {
+                bail!("No indexed files under {}", dir);
+            }
+            Ok(entries)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let overlap = dup_dirs::overlap(&sides[0], &sides[1]);
+
+    let names = [format!("{}/", first.trim_end_matches('/')), format!("{}/", second.trim_end_matches('/'))];
+    let percent = |part: u64, whole: u64| if whole > 0 { part as f64 / whole as f64 * 100.0 } else { 100.0 };
+    println!("Shared content: {} distinct file(s), {}", overlap.shared_hashes, format_bytes(overlap.shared_bytes));
+    for (name, side) in names.iter().zip([&overlap.first, &overlap.second]) {
+        println!(
+            "  {:.1}% of {} ({} file(s), {} distinct)",
+            percent(overlap.shared_bytes, side.distinct_bytes), name, side.files, format_bytes(side.distinct_bytes)
+        );
+    }
+    for (name, side) in names.iter().zip([&overlap.first, &overlap.second]) {
+        println!(
+            "Only in {}: {} file(s), {} of unique content",
+            name, side.unique_files, format_bytes(side.unique_bytes)
+        );
+    }
+    for (name, side) in names.iter().zip([&overlap.first, &overlap.second]) {
+        if side.unhashed > 0 {
+            println!("Note: {} file(s) in {} are awaiting a full hash and were not compared", side.unhashed, name);
+        }
+    }
+    for (i, side) in [&overlap.first, &overlap.second].into_iter().enumerate() {
+        if side.unique_files == 0 && side.unhashed == 0 {
+            println!("Everything in {} is also in {}", names[i], names[1 - i]);
+        }
+    }
+    Ok(())
+}
+
+/// Report directories whose complete contents are duplicated elsewhere (`duplicates --dirs`)
+fn duplicate_dirs(index: &Index, display_ctx: &DisplayContext) -> Result<()> {
+    let found = dup_dirs::find(&index.get_dir_files_recursive("")?);
+    if found.is_empty() {
+        println!("No duplicate directories found");
+        return Ok(());
+    }
+
+    let total_files: usize = found.iter().map(|dup| dup.files).sum();
+    let savings: u64 = found.iter().map(|dup| dup.bytes).sum();
+    println!("Found {} duplicate director(ies) holding {} file(s)", found.len(), total_files);
+    println!("Potential space savings: {}\n", display::format_total(savings));
+
+    let show = |dir: &str| -> Result<String> {
+        let relative = display_ctx.make_relative(dir)?;
+        Ok(if relative.is_empty() { ".".to_string() } else { format!("{}/", relative) })
+    };
+    for dup in &found {
+        println!("{} ({} file(s), {})", show(&dup.dir)?, dup.files, format_bytes(dup.bytes));
+        let relation = if dup.identical { "identical to" } else { "contained in" };
+        println!("  {} {}", relation, show(&dup.copy_of)?);
+    }
+    Ok(())
+}
+
+fn audio_duplicates(index: &Index, config: &Config, display_ctx: &DisplayContext) -> Result<()> {
+    let prints = index.audio_fingerprints()?;
+    if prints.is_empty() && !config.fingerprint_audio {
+        bail!("No audio fingerprints in the index; run 'oci config set fingerprint_audio true' and 'oci update'");
+    }
+
+    let mut groups = Vec::new();
+    for paths in audio::similar_groups(&prints) {
+        let mut entries = Vec::new();
+        for path in paths {
+            entries.extend(index.get(&path)?);
+        }
+        if entries.len() > 1 {
+            groups.push(entries);
+        }
+    }
+    if groups.is_empty() {
+        println!("No similar audio files found");
+        return Ok(());
+    }
+
+    let total_files: usize = groups.iter().map(Vec::len).sum();
+    let savings: u64 = groups
+        .iter()
+        .map(|entries| {
+            let total: u64 = entries.iter().map(|entry| entry.num_bytes).sum();
+            total - entries.iter().map(|entry| entry.num_bytes).max().unwrap_or(0)
+        })
+        .sum();
+    println!("Found {} similar audio file(s) in {} group(s)", total_files, groups.len());
+    println!("Potential space savings: {} (keeping the largest file of each group)\n", display::format_total(savings));
+
+    for (i, entries) in groups.iter().enumerate() {
+        println!("Group {}:", i + 1);
+        for entry in entries {
+            println!("  {}", display_ctx.format_entry_relative(entry)?);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Group entries that only have a quick fingerprint into duplicate candidate groups
+/// Large fully-hashed files are fingerprinted on the fly so they can join a group.
+fn quick_hash_candidate_groups(
+    pending: Vec<FileEntry>,
+    hashed: &[FileEntry],
+) -> Vec<(String, Vec<FileEntry>)> {
+    if pending.is_empty() {
+        return Vec::new();
+    }
+
+    let mut groups: std::collections::BTreeMap<String, Vec<FileEntry>> = std::collections::BTreeMap::new();
+    for entry in pending {
+        if let Some(quick_hash) = entry.quick_hash.clone() {
+            groups.entry(quick_hash).or_default().push(entry);
+        }
+    }
+
+    // Fully hashed entries only carry a fingerprint if they were quick-hashed before
+    for entry in hashed {
+        if let Some(group) = entry.quick_hash.as_ref().and_then(|q| groups.get_mut(q)) {
+            group.push(entry.clone());
+        }
+    }
+
+    groups.into_iter().filter(|(_, files)| files.len() > 1).collect()
+}
+
+/// Display duplicate candidates found by quick fingerprint
+fn display_candidate_groups(groups: Vec<(String, Vec<FileEntry>)>, display_ctx: &DisplayContext) -> Result<()> {
+    if groups.is_empty() {
+        return Ok(());
+    }
+
+    println!(
+        "Found {} duplicate candidate group(s) by quick hash (run 'oci update --full-hash' to confirm)\n",
+        groups.len()
+    );
+    for (quick_hash, mut files) in groups {
+        println!("Quick hash: {}", quick_hash);
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        for entry in files {
+            println!("  {}", display_ctx.format_entry_relative(&entry)?);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Restore files from pruneyard back to their original locations
+fn prune_restore(repo_root: &Path, config: &Config) -> Result<()> {
+    let options = CaptureOptions::from_config(config);
+    let pruneyard_path = repo_root.join(OCI_DIR).join("pruneyard");
+
+    if !pruneyard_path.exists() {
+        println!("No pruneyard directory exists");
+        return Ok(());
+    }
+
+    let mut index = Index::load(repo_root)?;
+    let mut restored_count = 0;
+
+    // Walk through pruneyard and restore files (in the long form, as pruned files can be nested deeper
+    // in the pruneyard than they were in the repository)
+    let long_pruneyard = file_utils::long_path(&pruneyard_path);
+    for entry in WalkDir::new(&long_pruneyard) {
+        let entry = entry?;
+
+        if entry.file_type().is_file() {
+            let rel_from_pruneyard = entry
+                .path()
+                .strip_prefix(&long_pruneyard)
+                .context("Failed to get relative path from pruneyard")?;
+            let original_path = repo_root.join(rel_from_pruneyard);
+
+            // Create parent directories if needed
+            if let Some(parent) = original_path.parent() {
+                fs::create_dir_all(file_utils::long_path(parent))
+                    .context(format!("Failed to create directory: {}", parent.display()))?;
+            }
+
+            // Move file back to original location
+            fs::rename(entry.path(), file_utils::long_path(&original_path)).context(format!(
+                "Failed to restore file: {}",
+                entry.path().display()
+            ))?;
+
+            // Add back to index
+            let rel_path_str = rel_from_pruneyard.to_string_lossy().to_string();
+            let file_entry = file_utils::create_file_entry(&original_path, rel_path_str, options)?;
+            index.upsert(file_entry)?;
+
+            println!("Restored: {}", rel_from_pruneyard.display());
+            restored_count += 1;
+        }
+    }
+
+    // Remove empty pruneyard directory
+    if restored_count > 0 {
+        fs::remove_dir_all(&long_pruneyard)
+            .context("Failed to remove pruneyard directory")?;
+    }
+
+    if restored_count > 0 {
+        index.log_operation(&Operation::new(config, "prune --restore", format!("restored {} file(s)", restored_count)))?;
+    }
+    index.save(repo_root)?;
+
+    println!("Restored {} file(s) from pruneyard", restored_count);
+    Ok(())
+}
+
+/// Permanently delete all files in pruneyard
+fn prune_purge(repo_root: &Path, config: &Config, force: bool, override_policy: bool) -> Result<()> {
+    // Check for pending changes in local index before purging
+    if has_pending_changes(repo_root)? {
+        return Err(OciError::PendingChanges { action: "purge", source: None }.into());
+    }
+
+    let pruneyard_path = repo_root.join(OCI_DIR).join("pruneyard");
+
+    if !pruneyard_path.exists() {
+        println!("No pruneyard directory exists");
+        return Ok(());
+    }
+
+    let count = dir_utils::count_files_in_dir(&pruneyard_path)?;
+    
+    let policy = Policy::load(repo_root)?;
+    let mut index = Index::load(repo_root)?;
+    let last_verify = index.last_operation("verify")?.map(|op| op.timestamp);
+    let violations: Vec<_> = policy.check_purge(last_verify, file_utils::now_millis()).into_iter().collect();
+    enforce_policy(&violations, "Purge", override_policy)?;
+
+    // Ask for confirmation unless --force is used
+    if !force {
+        println!("This will permanently delete {} pruned file(s).", count);
+        print!("Are you sure you want to continue? (y/N): ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+
+        let confirmed =
+            input.trim().eq_ignore_ascii_case("y") || input.trim().eq_ignore_ascii_case("yes");
+
+        if !confirmed {
+            println!("Purge cancelled");
+            return Ok(());
+        }
+    }
+
+    back_up_index(repo_root, config, "purge")?;
+    fs::remove_dir_all(file_utils::long_path(&pruneyard_path)).context("Failed to remove pruneyard directory")?;
+
+    let mut summary = format!("permanently deleted {} file(s)", count);
+    if override_policy && !violations.is_empty() {
+        summary.push_str(" (policy overridden)");
+    }
+    index.log_operation(&Operation::new(config, "prune --purge", summary))?;
+
+    println!("Permanently deleted {} pruned file(s)", count);
+    Ok(())
+}
+
+/// Find files to prune based on source index and ignore patterns
+fn find_files_to_prune(
+    local_index: &Index,
+    source_index: &Index,
+    repo_root: &Path,
+    source_patterns: &[String],
+    local_patterns: &[String],
+    no_ignore: bool,
+    ignored: bool,
+) -> Result<Vec<(String, String, bool)>> {
+    let mut files_to_prune: Vec<(String, String, bool)> = Vec::new();
+
+    // Get all files from local index
+    let local_files = local_index.get_dir_files_recursive("")?;
+
+    // Check indexed files
+    for local_entry in &local_files {
+        let mut should_prune = false;
+        let mut prune_reason = String::new();
+
+        // Check if hash exists in source index (entries awaiting a full hash can't be matched)
+        let source_matches = if local_entry.sha256.is_empty() {
+            Vec::new()
+        } else {
+            source_index.find_by_hash(&local_entry.sha256)?
+        };
+        if !source_matches.is_empty() {
+            should_prune = true;
+            prune_reason = "duplicate".to_string();
+        } else if !local_entry.sha256.is_empty()
+            && !source_index.find_members_by_hash(&local_entry.sha256)?.is_empty()
+        {
+            // Kept inside an archive in the source (recorded with `index_archives`)
+            should_prune = true;
+            prune_reason = "archived".to_string();
+        }
+
+        // Check if file matches source ignore patterns (unless --no-ignore)
+        if !no_ignore && !source_patterns.is_empty() {
+            let path = Path::new(&local_entry.path);
+            if ignore::should_ignore(path, source_patterns) {
+                should_prune = true;
+                prune_reason = "ignored".to_string();
+            }
+        }
+
+        // Check if file matches local ignore patterns (if --ignored flag is present)
+        if ignored && !local_patterns.is_empty() {
+            let path = Path::new(&local_entry.path);
+            if ignore::should_ignore(path, local_patterns) {
+                should_prune = true;
+                prune_reason = "ignored".to_string();
+            }
+        }
+
+        if should_prune {
+            files_to_prune.push((local_entry.path.clone(), prune_reason, true));
+        }
+    }
+
+    // Also check for files on filesystem that match ignore patterns but aren't in local index
+    let check_fs_ignored =
+        (!no_ignore && !source_patterns.is_empty()) || (ignored && !local_patterns.is_empty());
+    if check_fs_ignored {
+        for entry in WalkDir::new(repo_root).into_iter().filter_entry(|e| {
+            // Don't walk into .oci directory
+            if let Ok(rel) = e.path().strip_prefix(repo_root) {
+                !rel.starts_with(OCI_DIR)
+            } else {
+                true
+            }
+        }) {
+            // Handle permission errors gracefully - skip and continue
+            let entry = match entry {
+                Ok(e) => e,
+                Err(_err) => {
+                    // Silently skip permission errors during prune
+                    continue;
+                }
+            };
+
+            if entry.file_type().is_file() {
+                let rel_path = entry
+                    .path()
+                    .strip_prefix(repo_root)
+                    .context("Path is outside repository")?;
+                let rel_path_str = rel_path.to_string_lossy().to_string();
+
+                // Skip if already in our prune list
+                if files_to_prune.iter().any(|(p, _, _)| p == &rel_path_str) {
+                    continue;
+                }
+
+                // Skip if in local index (we already checked those above)
+                if local_index.get(&rel_path_str)?.is_some() {
+                    continue;
+                }
+
+                // Check if file matches source ignore patterns
+                if !no_ignore && ignore::should_ignore(rel_path, source_patterns) {
+                    files_to_prune.push((rel_path_str.clone(), "ignored".to_string(), false));
+                }
+
+                // Check if file matches local ignore patterns (if --ignored flag is present)
+                if ignored && ignore::should_ignore(rel_path, local_patterns) {
+                    // Only add if not already in list
+                    if !files_to_prune.iter().any(|(p, _, _)| p == &rel_path_str) {
+                        files_to_prune.push((rel_path_str, "ignored".to_string(), false));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(files_to_prune)
+}
+
+/// Execute the prune by moving files to the pruneyard (or the trash)
+fn execute_prune(
+    files_to_prune: Vec<(String, String, bool)>,
+    local_index: &mut Index,
+    repo_root: &Path,
+    disposal: &Disposal,
+) -> Result<(usize, usize, usize, u64, u64)> {
+    let mut pruned_count = 0;
+    let mut duplicate_count = 0;
+    let mut ignored_count = 0;
+    let mut total_bytes = 0u64;
+    let mut total_allocated = 0u64;
+
+    // Move files to pruneyard
+    for (path, reason, in_index) in files_to_prune {
+        let source_file = repo_root.join(&path);
+
+        // Get file size before moving
+        if let Ok(size) = file_utils::get_file_size(&source_file) {
+            total_bytes += size;
+            total_allocated += file_utils::get_allocated_size(&source_file).ok().flatten().unwrap_or(size);
+        }
+
+        // Move the file
+        disposal.dispose(&source_file, &path)?;
+
+        // Remove empty parent directories
+        dir_utils::remove_empty_parent_dirs(&source_file, repo_root)?;
+
+        // Remove from index if it was in the index
+        if in_index {
+            if let Some(entry) = local_index.get(&path)? {
+                local_index.remove_departed(vec![entry], "pruned")?;
+            }
+        }
+
+        if !display::quiet() {
+            println!("Pruned ({}): {}", reason, path);
+        }
+        pruned_count += 1;
+
+        if reason == "duplicate" || reason == "archived" {
+            duplicate_count += 1;
+        } else if reason == "ignored" {
+            ignored_count += 1;
+        }
+    }
+
+    Ok((pruned_count, duplicate_count, ignored_count, total_bytes, total_allocated))
+}
+
+/// Describe a prune without touching the filesystem or the index (`prune --dry-run`): the files
+/// by reason, the source copies of each duplicate, and the space the prune would reclaim
+fn print_prune_report(
+    files_to_prune: &[(String, String, bool)],
+    local_index: &Index,
+    source_index: Option<&Index>,
+    repo_root: &Path,
+    disposal: &Disposal,
+    violations: &[Violation],
+) -> Result<()> {
+    let mut total_bytes = 0u64;
+    let mut total_allocated = 0u64;
+    let mut counts = Vec::new();
+
+    for (title, reasons) in [("Duplicates", &["duplicate", "archived"][..]), ("Ignored", &["ignored"][..])] {
+        let files: Vec<_> = files_to_prune.iter().filter(|(_, reason, _)| reasons.contains(&reason.as_str())).collect();
+        counts.push(files.len());
+        if files.is_empty() {
+            continue;
+        }
+
+        let mut lines = Vec::new();
+        let mut section_bytes = 0u64;
+        for (path, reason, _) in files {
+            let file = repo_root.join(path);
+            let size = file_utils::get_file_size(&file).unwrap_or(0);
+            section_bytes += size;
+            total_allocated += file_utils::get_allocated_size(&file).ok().flatten().unwrap_or(size);
+            lines.push(format!("  {} ({})", path, format_bytes(size)));
+
+            // Where the source keeps the same content
+            let sha256 = local_index.get(path)?.map(|entry| entry.sha256).unwrap_or_default();
+            match (source_index, reason.as_str()) {
+                (Some(source_index), "duplicate") => {
+                    let mut copies: Vec<_> = source_index.find_by_hash(&sha256)?.into_iter().map(|copy| copy.path).collect();
+                    copies.sort();
+                    for copy in copies {
+                        lines.push(format!("      = {}", copy));
+                    }
+                }
+                (Some(source_index), "archived") => {
+                    for member in source_index.find_members_by_hash(&sha256)? {
+                        lines.push(format!("      = {}!{}", member.archive, member.member));
+                    }
+                }
+                _ => {}
+            }
+        }
+        total_bytes += section_bytes;
+        println!("{} ({} file(s), {}):", title, counts[counts.len() - 1], format_bytes(section_bytes));
+        for line in lines {
+            println!("{}", line);
+        }
+        println!();
+    }
+
+    for violation in violations {
+        println!("Policy violation: {}", violation);
+    }
+    if !violations.is_empty() {
+        println!("The prune would be blocked by .oci/policy.toml ({} violation(s)) unless --override-policy is given",
+            violations.len());
+    }
+    println!(
+        "Would prune {} file(s) to {} ({} duplicates, {} ignored), reclaiming {}, {} on disk",
+        files_to_prune.len(), disposal.destination(), counts[0], counts[1], format_bytes(total_bytes),
+        format_bytes(total_allocated)
+    );
+    println!("Dry run: nothing was moved and the index is unchanged");
+    Ok(())
+}
+
+/// Keep only the files to prune whose match in the source is confirmed by a byte comparison
+/// (`prune --paranoid`); files pruned for ignore patterns need no match. Content recorded only
+/// inside a source archive can't be compared and is kept.
+fn confirm_byte_for_byte(
+    files_to_prune: Vec<(String, String, bool)>,
+    local_index: &Index,
+    source_index: &Index,
+    repo_root: &Path,
+    source_root: &Path,
+) -> Result<Vec<(String, String, bool)>> {
+    let mut confirmed = Vec::new();
+    for (path, reason, in_index) in files_to_prune {
+        let matched = match reason.as_str() {
+            "duplicate" => {
+                let sha256 = local_index.get(&path)?.map(|entry| entry.sha256).unwrap_or_default();
+                let mut matched = false;
+                for copy in source_index.find_by_hash(&sha256)? {
+                    let source_file = source_root.join(&copy.path);
+                    if source_file.is_file() && file_utils::same_content(&repo_root.join(&path), &source_file)? {
+                        matched = true;
+                        break;
+                    }
+                }
+                if !matched {
+                    println!("Kept (no byte-identical copy in the source): {}", path);
+                }
+                matched
+            }
+            "archived" => {
+                println!("Kept (only inside a source archive, can't compare byte for byte): {}", path);
+                false
+            }
+            _ => true,
+        };
+        if matched {
+            confirmed.push((path, reason, in_index));
+        }
+    }
+    Ok(confirmed)
+}
+
+/// Refuse a source whose signature does not check out against the `--verify-key` key
+fn verify_source(repo_root: &Path, source: &Source, source_repo: &SourceRepo, verify_key: Option<&str>) -> Result<()> {
+    let Some(verify_key) = verify_key else {
+        return Ok(());
+    };
+    let key = signing::resolve_key(repo_root, verify_key)?;
+    source_repo.verify(&key).context(format!("Cannot trust the index of {}", source))?;
+    println!("Verified the signature of {} ({})", source, signing::encode_public_key(&key));
+    Ok(())
+}
+
+/// The part of the repository a prune considers (`--path` and `--match`; everything by default)
+pub struct PruneScope {
+    /// File or directory, relative to the current directory
+    pub path: Option<String>,
+    /// Patterns in ignore-file syntax, any of which a file must match
+    pub matches: Vec<String>,
+}
+
+impl PruneScope {
+    /// Index path of `--path` ("" for the whole repository)
+    fn resolve_dir(&self, repo_root: &Path, config: &Config) -> Result<String> {
+        let Some(path) = &self.path else {
+            return Ok(String::new());
+        };
+        let roots = Roots::from_config(repo_root.to_path_buf(), config);
+        let rel_path = repo_relative_path(&roots, &get_logical_current_dir()?, path)?;
+        if !roots.full_path(&rel_path).exists() {
+            bail!("Path does not exist: {}", path);
+        }
+        Ok(rel_path)
+    }
+
+    /// Whether an index path is under the resolved `--path` and matches a `--match` pattern
+    fn contains(&self, dir: &str, path: &str) -> bool {
+        (dir.is_empty() || path == dir || path.starts_with(&dir_prefix(dir)))
+            && (self.matches.is_empty() || ignore::matches_any(Path::new(path), &self.matches))
+    }
+}
+
+/// Prune files that exist in another index
+#[allow(clippy::too_many_arguments)]
+pub fn prune(
+    source: Option<String>,
+    purge: bool,
+    restore: bool,
+    force: bool,
+    no_ignore: bool,
+    ignored: bool,
+    override_policy: bool,
+    verify_key: Option<String>,
+    skip_tags: Vec<String>,
+    paranoid: bool,
+    trash: bool,
+    dry_run: bool,
+    scope: PruneScope,
+) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let disposal = Disposal::new(&repo_root, trash || config.prune_to_trash);
+
+    if !config.roots.is_empty() {
+        bail!("Cannot prune: prune does not support indexes with multiple roots yet");
+    }
+
+    // Handle restore flag
+    if restore {
+        return prune_restore(&repo_root, &config);
+    }
+
+    // Handle purge flag
+    if purge {
+        return prune_purge(&repo_root, &config, force, override_policy);
+    }
+
+    // Check for pending changes in local index
+    if has_pending_changes(&repo_root)? {
+        return Err(OciError::PendingChanges { action: "prune", source: None }.into());
+    }
+
+    let scope_dir = scope.resolve_dir(&repo_root, &config)?;
+
+    // If --ignored flag is present without a source, just prune local ignored files
+    if ignored && source.is_none() {
+        return prune_local_ignored_files(&repo_root, &config, override_policy, &disposal, dry_run, &scope, &scope_dir);
+    }
+
+    // Need source path for prune operation (unless only using --ignored)
+    let source_path = source.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Source path is required for prune operation (unless using --ignored without source)"
+        )
+    })?;
+
+    // Load local and source indices
+    let mut local_index = Index::load(&repo_root)?;
+
+    let source = Source::parse(&source_path, &get_logical_current_dir()?)?;
+    let source_repo = source.open()?;
+    verify_source(&repo_root, &source, &source_repo, verify_key.as_deref())?;
+    if paranoid && (source_repo.is_export() || !matches!(source, Source::Local(_))) {
+        bail!("--paranoid needs a local source whose files can be read, not {}", source);
+    }
+
+    // Every local file is a copy of itself in this repository's own index file or snapshots
+    if let Source::Local(path) | Source::File(path) = &source {
+        if path.canonicalize()?.starts_with(repo_root.canonicalize()?.join(OCI_DIR)) {
+            bail!("Cannot prune using this repository's own index or snapshots as source");
+        }
+    }
+
+    if source_repo.is_export() {
+        println!("Note: {} is an exported index; pruning against the state it was exported in", source);
+    } else if let Source::Local(source_abs_path) = &source {
+        // Canonicalize both paths to compare them properly
+        let canonical_source = source_abs_path
+            .canonicalize()
+            .context("Failed to canonicalize source path")?;
+        let canonical_local = repo_root
+            .canonicalize()
+            .context("Failed to canonicalize local path")?;
+
+        if canonical_source == canonical_local {
+            bail!("Cannot prune using the same index as source and local");
+        }
+
+        // Check for pending changes in source index
+        if has_pending_changes(source_abs_path)? {
+            return Err(OciError::PendingChanges {
+                action: "prune",
+                source: Some(source_abs_path.display().to_string()),
+            }.into());
+        }
+    } else {
+        println!("Note: cannot check {} for pending changes; pruning against its last update", source);
+    }
+
+    let source_index = Index::load(&source_repo.root).context("Failed to load source index")?;
+
+    // Load source ignore patterns if not disabled
+    let source_patterns = if !no_ignore {
+        ignore::load_patterns(&source_repo.root)?
+    } else {
+        Vec::new()
+    };
+
+    // Load local ignore patterns if --ignored flag is present
+    let local_patterns = if ignored {
+        ignore::load_patterns(&repo_root)?
+    } else {
+        Vec::new()
+    };
+
+    // Find files to prune
+    let mut files_to_prune = find_files_to_prune(
+        &local_index,
+        &source_index,
+        &repo_root,
+        &source_patterns,
+        &local_patterns,
+        no_ignore,
+        ignored,
+    )?;
+    files_to_prune.retain(|(path, _, _)| scope.contains(&scope_dir, path));
+
+    if !skip_tags.is_empty() {
+        let tags = Tags::new(local_index.tags()?);
+        let before = files_to_prune.len();
+        files_to_prune.retain(|(path, _, _)| !tags.has_any(path, &skip_tags));
+        if files_to_prune.len() < before {
+            println!("Kept {} file(s) tagged {}", before - files_to_prune.len(), skip_tags.join(" or "));
+        }
+    }
+
+    if paranoid {
+        files_to_prune = confirm_byte_for_byte(files_to_prune, &local_index, &source_index, &repo_root, &source_repo.root)?;
+    }
+
+    if files_to_prune.is_empty() {
+        println!("No files to prune");
+        return Ok(());
+    }
+
+    let policy = Policy::load(&repo_root)?;
+    let violations: Vec<_> = files_to_prune.iter()
+        .filter_map(|(path, _, _)| policy.check_prune(path))
+        .collect();
+    if dry_run {
+        return print_prune_report(&files_to_prune, &local_index, Some(&source_index), &repo_root, &disposal, &violations);
+    }
+    enforce_policy(&violations, "Prune", override_policy)?;
+
+    // Execute prune
+    let (pruned_count, duplicate_count, ignored_count, total_bytes, total_allocated) =
+        execute_prune(files_to_prune, &mut local_index, &repo_root, &disposal)?;
+
+    local_index.log_operation(&Operation::new(
+        &config,
+        &format!("prune {}", source_path),
+        format!("pruned {} file(s) ({} duplicates, {} ignored, {})",
+            pruned_count, duplicate_count, ignored_count, format_bytes(total_bytes)),
+    ))?;
+    local_index.save(&repo_root)?;
+
+    // Clean up any remaining empty directories
+    let empty_dirs_removed = dir_utils::remove_all_empty_dirs(&repo_root)?;
+
+    if pruned_count > 0 {
+        println!(
+            "Pruned {} file(s) to {} ({} duplicates, {} ignored, {}, {} on disk)",
+            pruned_count, disposal.destination(), duplicate_count, ignored_count, format_bytes(total_bytes),
+            format_bytes(total_allocated)
+        );
+    } else {
+        println!("Pruned 0 file(s)");
+    }
+    if pruned_count > 0 {
+        let mut hook_fields = serde_json::Map::new();
+        hook_fields.insert("command".to_string(), format!("prune {}", source_path).into());
+        hook_fields.insert("pruned".to_string(), pruned_count.into());
+        hook_fields.insert("duplicates".to_string(), duplicate_count.into());
+        hook_fields.insert("ignored".to_string(), ignored_count.into());
+        hook_fields.insert("bytes".to_string(), total_bytes.into());
+        hook_fields.insert("destination".to_string(), disposal.destination().to_string().into());
+        run_post_hook(&repo_root, Hook::PostPrune, hook_fields);
+    }
+
+    if empty_dirs_removed > 0 {
+        println!(
+            "Removed {} empty director{}",
+            empty_dirs_removed,
+            if empty_dirs_removed == 1 { "y" } else { "ies" }
+        );
+    }
+
+    Ok(())
+}
+
+pub fn diff(source_path: String, verify_key: Option<String>) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+
+    let source = Source::parse(&source_path, &current_dir)?;
+    let source_repo = source.open()?;
+    verify_source(&repo_root, &source, &source_repo, verify_key.as_deref())?;
+    let local_index = Index::load(&repo_root)?;
+    let source_index = Index::load(&source_repo.root).context("Failed to load source index")?;
+
+    let roots = Roots::from_config(repo_root, &config);
+    let rel_current_str = roots.require_index_path(&current_dir)?;
+
+    let local_files = local_index.get_dir_files_recursive(&rel_current_str)?;
+    let source_files: std::collections::HashMap<String, FileEntry> = source_index
+        .get_dir_files_recursive(&rel_current_str)?
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+
+    let mut changes: Vec<(StatusMarker, FileEntry)> = Vec::new();
+    for local_entry in &local_files {
+        match source_files.get(&local_entry.path) {
+            None => changes.push((StatusMarker::Added, local_entry.clone())),
+            Some(source_entry) if local_entry.content_differs(source_entry) => {
+                changes.push((StatusMarker::Updated, local_entry.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    let local_paths: std::collections::HashSet<&str> = local_files.iter().map(|e| e.path.as_str()).collect();
+    for (path, source_entry) in &source_files {
+        if !local_paths.contains(path.as_str()) {
+            changes.push((StatusMarker::Deleted, source_entry.clone()));
+        }
+    }
+
+    if changes.is_empty() {
+        println!("No differences from {}", source);
+        return Ok(());
+    }
+
+    changes.sort_by(|a, b| a.1.path.cmp(&b.1.path));
+
+    let display_ctx = DisplayContext::new(roots, current_dir);
+    let only_local = changes.iter().filter(|(m, _)| matches!(m, StatusMarker::Added)).count();
+    let only_source = changes.iter().filter(|(m, _)| matches!(m, StatusMarker::Deleted)).count();
+    let differ = changes.len() - only_local - only_source;
+    for (marker, entry) in &changes {
+        marker.display(&display_ctx.format_entry_relative(entry)?);
+    }
+
+    println!();
+    println!(
+        "{} only here, {} only in {}, {} with different content",
+        only_local, only_source, source, differ
+    );
+
+    Ok(())
+}
+
+/// Compare the whole index with another index, by path and by content
+pub fn compare(other_path: String, verify_key: Option<String>) -> Result<()> {
+    use std::collections::{BTreeMap, HashMap};
+
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+
+    let other = Source::parse(&other_path, &get_logical_current_dir()?)?;
+    let other_repo = other.open()?;
+    verify_source(&repo_root, &other, &other_repo, verify_key.as_deref())?;
+    let local_index = Index::load(&repo_root)?;
+    let other_index = Index::load(&other_repo.root).context("Failed to load other index")?;
+
+    let local_files = local_index.get_dir_files_recursive("")?;
+    let other_files = other_index.get_dir_files_recursive("")?;
+    let paths_by_hash = |files: &[FileEntry]| {
+        let mut map: HashMap<String, Vec<String>> = HashMap::new();
+        for entry in files.iter().filter(|e| !e.sha256.is_empty()) {
+            map.entry(entry.sha256.clone()).or_default().push(entry.path.clone());
+        }
+        map
+    };
+    let local_hashes = paths_by_hash(&local_files);
+    let other_hashes = paths_by_hash(&other_files);
+    let other_by_path: HashMap<&str, &FileEntry> = other_files.iter().map(|e| (e.path.as_str(), e)).collect();
+    let local_paths: HashSet<&str> = local_files.iter().map(|e| e.path.as_str()).collect();
+
+    let mut identical = 0;
+    let mut conflicts: Vec<&FileEntry> = Vec::new();
+    // Unmatched paths whose content is on the other side, as (here, there) per hash
+    let mut moved: BTreeMap<&str, (Vec<&str>, Vec<&str>)> = BTreeMap::new();
+    let mut only_local: Vec<&FileEntry> = Vec::new();
+    let mut only_other: Vec<&FileEntry> = Vec::new();
+
+    for entry in &local_files {
+        match other_by_path.get(entry.path.as_str()) {
+            Some(other_entry) if entry.content_differs(other_entry) => conflicts.push(entry),
+            Some(_) => identical += 1,
+            None if other_hashes.contains_key(&entry.sha256) => {
+                moved.entry(entry.sha256.as_str()).or_default().0.push(entry.path.as_str());
+            }
+            None => only_local.push(entry),
+        }
+    }
+    for entry in &other_files {
+        if local_paths.contains(entry.path.as_str()) {
+            continue;
+        }
+        if local_hashes.contains_key(&entry.sha256) {
+            moved.entry(entry.sha256.as_str()).or_default().1.push(entry.path.as_str());
+        } else {
+            only_other.push(entry);
+        }
+    }
+
+    if conflicts.is_empty() && moved.is_empty() && only_local.is_empty() && only_other.is_empty() {
+        println!("Identical to {} ({} file(s))", other, identical);
+        return Ok(());
+    }
+
+    if !conflicts.is_empty() {
+        println!("Different content at the same path ({}):", conflicts.len());
+        for entry in &conflicts {
+            println!("  {}", entry.path);
+        }
+    }
+    if !moved.is_empty() {
+        println!("Same content at a different path ({}):", moved.len());
+        for (hash, (here, there)) in &moved {
+            // A side without unmatched paths still holds the content at a path both sides share
+            let here = if here.is_empty() { local_hashes[*hash].iter().map(String::as_str).collect() } else { here.clone() };
+            let there = if there.is_empty() { other_hashes[*hash].iter().map(String::as_str).collect() } else { there.clone() };
+            println!("  {} -> {}", here.join(", "), there.join(", "));
+        }
+    }
+    let total_bytes = |entries: &[&FileEntry]| entries.iter().map(|e| e.num_bytes).sum::<u64>();
+    if !only_local.is_empty() {
+        println!("Only here ({}, {}):", only_local.len(), format_bytes(total_bytes(&only_local)));
+        for entry in &only_local {
+            println!("  {}", entry.path);
+        }
+    }
+    if !only_other.is_empty() {
+        println!("Only in {} ({}, {}):", other, only_other.len(), format_bytes(total_bytes(&only_other)));
+        for entry in &only_other {
+            println!("  {}", entry.path);
+        }
+    }
+
+    println!();
+    println!(
+        "{} identical, {} with different content, {} at a different path, {} only here, {} only in {}",
+        identical, conflicts.len(), moved.len(), only_local.len(), only_other.len(), other
+    );
+
+    Ok(())
+}
+
+/// Merge another index into this one, so files brought over from another drive need no hashing.
+/// Its paths go under `prefix`; where both indexes hold different content at a path, `prefer` decides.
+/// Archive members, media metadata and audio fingerprints come along with the entries written,
+/// tags are added, and storage classes are taken for paths that have none here.
+pub fn merge(other_path: String, prefix: Option<String>, prefer: String, verify_key: Option<String>, dry_run: bool) -> Result<()> {
+    let prefer: Prefer = prefer.parse()?;
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let prefix = match prefix {
+        Some(prefix) => subdir_index_path(&prefix, &roots, &current_dir).context("Invalid --prefix")?,
+        None => String::new(),
+    };
+
+    let other = Source::parse(&other_path, &current_dir)?;
+    let other_repo = other.open()?;
+    verify_source(&repo_root, &other, &other_repo, verify_key.as_deref())?;
+    if other_repo.root.canonicalize().ok() == repo_root.canonicalize().ok() {
+        bail!("Cannot merge an index into itself");
+    }
+    let other_index = Index::load(&other_repo.root).context("Failed to load other index")?;
+
+    let _lock = if dry_run { None } else { Some(lock_repo(&repo_root, &config)?) };
+    let mut index = Index::load(&repo_root)?;
+    let ours = index.get_dir_files_recursive("")?.into_iter().map(|entry| (entry.path.clone(), entry)).collect();
+    let planned = merge::plan(&ours, other_index.get_dir_files_recursive("")?, &prefix, prefer);
+    let count = |wanted: &[Outcome]| planned.iter().filter(|(_, outcome)| wanted.contains(outcome)).count();
+
+    let conflicts: Vec<_> = planned.iter().filter(|(_, outcome)| matches!(outcome, Outcome::KeptOurs | Outcome::TookTheirs)).collect();
+    if !conflicts.is_empty() {
+        println!("Different content at the same path ({}):", conflicts.len());
+        for (entry, outcome) in &conflicts {
+            let kept = if *outcome == Outcome::TookTheirs { "took theirs" } else { "kept ours" };
+            println!("  {} ({})", entry.path, kept);
+        }
+    }
+    let mut summary = format!(
+        "{} added, {} identical, {} conflict(s) ({} kept ours, {} took theirs)",
+        count(&[Outcome::Added]), count(&[Outcome::Identical, Outcome::Hashed]), conflicts.len(),
+        count(&[Outcome::KeptOurs]), count(&[Outcome::TookTheirs])
+    );
+    if count(&[Outcome::Hashed]) > 0 {
+        summary.push_str(&format!(", {} full hash(es) taken", count(&[Outcome::Hashed])));
+    }
+    let into = if prefix.is_empty() { String::new() } else { format!(" into {}/", prefix) };
+    if dry_run {
+        println!("Would merge {} file(s) from {}{}: {}", planned.len(), other, into, summary);
+        println!("Dry run: the index is unchanged");
+        return Ok(());
+    }
+
+    back_up_index(&repo_root, &config, "merge")?;
+    index.begin_batch()?;
+    let mut written = HashSet::new();
+    for (entry, outcome) in planned.iter().filter(|(_, outcome)| outcome.writes()) {
+        // Our sidecar records describe content that is being replaced
+        if *outcome == Outcome::TookTheirs {
+            index.remove(&entry.path)?;
+        }
+        index.upsert(entry.clone())?;
+        written.insert(entry.path.clone());
+    }
+    let mut members: std::collections::BTreeMap<String, Vec<ArchiveMember>> = std::collections::BTreeMap::new();
+    for mut member in other_index.archive_members()? {
+        member.archive = merge::prefixed(&prefix, &member.archive);
+        if written.contains(&member.archive) {
+            members.entry(member.archive.clone()).or_default().push(member);
+        }
+    }
+    for (archive, members) in &members {
+        index.set_archive_members(archive, members)?;
+    }
+    for mut info in other_index.media()? {
+        info.path = merge::prefixed(&prefix, &info.path);
+        if written.contains(&info.path) {
+            index.set_media(&info)?;
+        }
+    }
+    for mut print in other_index.audio_fingerprints()? {
+        print.path = merge::prefixed(&prefix, &print.path);
+        if written.contains(&print.path) {
+            index.set_audio(&print)?;
+        }
+    }
+    for (path, tag) in other_index.tags()? {
+        index.add_tag(&merge::prefixed(&prefix, &path), &tag)?;
+    }
+    let classified: HashSet<String> = index.storage_class_tags()?.into_iter().map(|(path, _)| path).collect();
+    for (path, class) in other_index.storage_class_tags()? {
+        let path = merge::prefixed(&prefix, &path);
+        if !classified.contains(&path) {
+            index.set_storage_class(&path, class)?;
+        }
+    }
+    index.commit_batch()?;
+    index.log_operation(&Operation::new(&config, &format!("merge {}", other_path), summary.clone()))?;
+    index.save(&repo_root)?;
+    // The next update has to check the merged entries wherever they are, not only where the journal saw changes
+    changes::forget(&repo_root)?;
+
+    println!("Merged {} file(s) from {}{}: {}", planned.len(), other, into, summary);
+    Ok(())
+}
+
+/// The index path of a directory given relative to the current directory, which need not exist
+/// (anything leading out of the current directory is refused)
+fn subdir_index_path(dir: &str, roots: &Roots, current_dir: &Path) -> Result<String> {
+    if !Path::new(dir).components().all(|c| matches!(c, std::path::Component::Normal(_) | std::path::Component::CurDir)) {
+        bail!("Not a directory below the current one: {}", dir);
     }
+    roots.require_index_path(&roots.locate(&current_dir.join(dir)))
+        .map(|dir| dir.trim_end_matches('/').to_string())
+}
 
-    // Filter to only hashes with duplicates (more than 1 file)
-    let mut duplicate_groups: Vec<_> = hash_groups
+/// Give a subtree its own repository at `dest`, with the subtree's entries (and their archive
+/// members, media metadata, audio fingerprints, tags and storage classes) moved to its root, so
+/// the new repository needs no hashing. This index is left as it is.
+pub fn split(subdir: String, dest: String) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let mut config = check_version(&repo_root)?;
+    if Backend::detect(&repo_root) == Backend::Encrypted {
+        bail!("An encrypted index can't be split, as the new index would not be encrypted");
+    }
+    let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let dir = subdir_index_path(&subdir, &roots, &current_dir)?;
+    if dir.is_empty() {
+        bail!("Give a subdirectory to split off, not the repository itself");
+    }
+    let dest = normalize_path(&current_dir.join(dest));
+    if dest.join(OCI_DIR).exists() {
+        bail!("Index already exists at {}", dest.join(OCI_DIR).display());
+    }
+
+    let index = Index::load(&repo_root)?;
+    let prefix = dir_prefix(&dir);
+    // Index path in the new repository of a path at or below the subtree
+    let relocate = |path: &str| -> Option<String> {
+        if path == dir { Some(String::new()) } else { path.strip_prefix(&prefix).map(str::to_string) }
+    };
+    let entries: Vec<FileEntry> = index.get_dir_files_recursive(&dir)?
         .into_iter()
-        .filter(|(_, files)| files.len() > 1)
+        .map(|mut entry| {
+            entry.path = relocate(&entry.path).unwrap_or_default();
+            entry
+        })
         .collect();
+    if entries.is_empty() {
+        bail!("No indexed files under {}", dir);
+    }
 
-    if duplicate_groups.is_empty() {
-        println!("No duplicate files found");
-        return Ok(());
+    fs::create_dir_all(dest.join(OCI_DIR)).context(format!("Failed to create {}", dest.join(OCI_DIR).display()))?;
+    // The same settings, so entries are read the same way (but not this repository's roots or keyfile)
+    config.roots.clear();
+    config.keyfile = None;
+    config.save(&dest)?;
+    let ignore_file = repo_root.join(OCI_DIR).join(ignore::OCIGNORE_FILE);
+    if ignore_file.is_file() {
+        fs::copy(&ignore_file, dest.join(OCI_DIR).join(ignore::OCIGNORE_FILE)).context("Failed to copy ignore patterns")?;
+    } else {
+        ignore::init_ignore_file(&dest, UserConfig::load()?.ignore_template.as_deref())?;
     }
 
-    // Sort groups by hash for consistent output
-    duplicate_groups.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut split = Index::open(&dest, Backend::detect(&repo_root))?;
+    split.begin_batch()?;
+    split.upsert_many(entries.iter().cloned())?;
+    let mut members: std::collections::BTreeMap<String, Vec<ArchiveMember>> = std::collections::BTreeMap::new();
+    for mut member in index.archive_members()? {
+        if let Some(archive) = relocate(&member.archive) {
+            member.archive = archive;
+            members.entry(member.archive.clone()).or_default().push(member);
+        }
+    }
+    for (archive, members) in &members {
+        split.set_archive_members(archive, members)?;
+    }
+    for mut info in index.media()? {
+        if let Some(path) = relocate(&info.path) {
+            info.path = path;
+            split.set_media(&info)?;
+        }
+    }
+    for mut print in index.audio_fingerprints()? {
+        if let Some(path) = relocate(&print.path) {
+            print.path = path;
+            split.set_audio(&print)?;
+        }
+    }
+    for (path, class) in index.storage_class_tags()? {
+        if let Some(path) = relocate(&path) {
+            split.set_storage_class(&path, class)?;
+        }
+    }
+    for (path, tag) in index.tags()? {
+        if let Some(path) = relocate(&path) {
+            split.add_tag(&path, &tag)?;
+        }
+    }
+    split.commit_batch()?;
+    let total_bytes: u64 = entries.iter().map(|e| e.num_bytes).sum();
+    let summary = format!("{} file(s) ({}) from {} in {}", entries.len(), format_bytes(total_bytes), dir, repo_root.display());
+    split.log_operation(&Operation::new(&config, "split", summary))?;
+    split.save(&dest)?;
+
+    println!("Split {} file(s) ({}) under {} into a new index in {}", entries.len(), format_bytes(total_bytes), dir, dest.join(OCI_DIR).display());
+    register_repo(&dest);
+    Ok(())
+}
 
-    // Calculate statistics
-    let total_duplicate_files: usize =
-        duplicate_groups.iter().map(|(_, files)| files.len()).sum();
-    let total_groups = duplicate_groups.len();
+/// List the content of another index that this index has nowhere (by hash, including archive members)
+pub fn missing(source_path: String, verify_key: Option<String>) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
 
-    // Calculate wasted space (all but one copy of each duplicate set)
-    let wasted_bytes: u64 = duplicate_groups
-        .iter()
-        .map(|(_, files)| {
-            let file_size = files[0].num_bytes;
-            file_size * (files.len() as u64 - 1)
-        })
-        .sum();
+    let source = Source::parse(&source_path, &get_logical_current_dir()?)?;
+    let source_repo = source.open()?;
+    verify_source(&repo_root, &source, &source_repo, verify_key.as_deref())?;
+    let local_index = Index::load(&repo_root)?;
+    let source_index = Index::load(&source_repo.root).context("Failed to load source index")?;
+
+    let mut missing: Vec<FileEntry> = Vec::new();
+    let mut unhashed = 0;
+    let mut known: std::collections::HashMap<String, bool> = std::collections::HashMap::new();
+    for entry in source_index.get_dir_files_recursive("")? {
+        // Entries still awaiting a full hash can't be matched
+        if entry.sha256.is_empty() {
+            unhashed += 1;
+            continue;
+        }
+        let present = match known.get(&entry.sha256) {
+            Some(present) => *present,
+            None => {
+                let present = !local_index.find_by_hash(&entry.sha256)?.is_empty()
+                    || !local_index.find_members_by_hash(&entry.sha256)?.is_empty();
+                known.insert(entry.sha256.clone(), present);
+                present
+            }
+        };
+        if !present {
+            missing.push(entry);
+        }
+    }
+
+    if missing.is_empty() {
+        println!("Nothing in {} is missing here", source);
+    } else {
+        missing.sort_by(|a, b| a.path.cmp(&b.path));
+        for entry in &missing {
+            println!("{}", file_utils::format_entry(entry));
+        }
+
+        let total_bytes: u64 = missing.iter().map(|e| e.num_bytes).sum();
+        let mut seen = HashSet::new();
+        let unique: Vec<&FileEntry> = missing.iter().filter(|e| seen.insert(e.sha256.as_str())).collect();
+        let unique_bytes: u64 = unique.iter().map(|e| e.num_bytes).sum();
+        println!();
+        println!(
+            "{} file(s) ({}) in {} are missing here, {} with distinct content ({})",
+            missing.len(), format_bytes(total_bytes), source, unique.len(), format_bytes(unique_bytes)
+        );
+    }
+    if unhashed > 0 {
+        println!("Note: {} file(s) in {} are awaiting a full hash and were not compared", unhashed, source);
+    }
+
+    Ok(())
+}
+
+pub fn check(external: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+
+    if !external {
+        bail!("No checks selected: use --external to run the executables in .oci/checks");
+    }
+    // Checks read the index file themselves, and an encrypted one is only readable by oci
+    if Backend::detect(&repo_root) == Backend::Encrypted {
+        bail!("External checks can't read an encrypted index, and oci won't write a decrypted copy for them");
+    }
+
+    let check_paths = checks::discover(&repo_root)?;
+    if check_paths.is_empty() {
+        println!("No checks found in .oci/checks");
+        return Ok(());
+    }
 
+    let mut findings: Vec<_> = check_paths.iter()
+        .flat_map(|check| checks::run(check, &repo_root))
+        .collect();
+    findings.sort_by(|a, b| {
+        (a.severity, &a.check, &a.path).cmp(&(b.severity, &b.check, &b.path))
+    });
+
+    let display_ctx = DisplayContext::new(
+        Roots::from_config(repo_root, &config),
+        get_logical_current_dir()?,
+    );
+    for finding in &findings {
+        let location = match &finding.path {
+            Some(path) => format!("{}: ", display_ctx.make_relative(path)?),
+            None => String::new(),
+        };
+        println!("{:<7} {}: {}{}", finding.severity, finding.check, location, finding.message);
+    }
+
+    let count = |severity: Severity| findings.iter().filter(|f| f.severity == severity).count();
+    let errors = count(Severity::Error);
+    if !findings.is_empty() {
+        println!();
+    }
     println!(
-        "Found {} duplicate file(s) in {} group(s)",
-        total_duplicate_files, total_groups
+        "Ran {} check(s): {} error(s), {} warning(s), {} info",
+        check_paths.len(), errors, count(Severity::Warning), count(Severity::Info)
     );
+
+    if errors > 0 {
+        bail!("Checks failed");
+    }
+    Ok(())
+}
+
+pub fn serve(bind: String) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+
+    let listener = std::net::TcpListener::bind(&bind)
+        .context(format!("Failed to listen on {}", bind))?;
+    let address = listener.local_addr().context("Failed to read listening address")?;
+    println!("Serving {} on http://{} (Ctrl-C to stop)", repo_root.display(), address);
+
+    crate::serve::run(listener, &repo_root)
+}
+
+/// Write a copy of the index (and ignore patterns) to a directory, to hand out as a prune source
+#[allow(clippy::too_many_arguments)]
+pub fn export(
+    dest: Option<String>,
+    bundle: Option<String>,
+    manifest: Option<String>,
+    bagit: Option<String>,
+    path: Option<String>,
+    algorithm: Option<String>,
+    sign: bool,
+    tag: Option<String>,
+) -> Result<()> {
+    if let Some(manifest) = manifest {
+        return export_manifest(manifest, algorithm, tag);
+    }
+    if let Some(bag) = bagit {
+        return export_bagit(bag, path, algorithm, tag);
+    }
+    if algorithm.is_some() {
+        bail!("--algorithm requires --manifest or --bagit");
+    }
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    // Fail before writing anything rather than leave an unsigned export behind
+    let key = if sign { Some(signing::signing_key(&repo_root)?) } else { None };
+
+    let current_dir = std::env::current_dir()?;
+    let Some(bundle) = bundle else {
+        let dest = current_dir.join(dest.context("Missing export directory")?);
+        return export_to(&repo_root, &dest, key, tag.as_deref(), &dest.display().to_string());
+    };
+
+    // A bundle is an export staged in a temporary directory and packed into one file
+    let bundle = current_dir.join(bundle);
+    if bundle.exists() {
+        bail!("{} already exists", bundle.display());
+    }
+    let staging = tempfile::Builder::new()
+        .prefix("oci-bundle-")
+        .tempdir()
+        .context("Failed to create a temporary directory for the bundle")?;
+    export_to(&repo_root, staging.path(), key, tag.as_deref(), &bundle.display().to_string())?;
+    let config_file = repo_root.join(OCI_DIR).join(config::CONFIG_FILE);
+    if config_file.exists() {
+        fs::copy(&config_file, staging.path().join(OCI_DIR).join(config::CONFIG_FILE)).context("Failed to copy config")?;
+    }
+    bundle::write(&staging.path().join(OCI_DIR), &bundle)
+}
+
+/// Write the digests of the indexed files (or those with a tag) as a checksum file, in the
+/// `<digest>  <path>` format of sha256sum and md5sum, with paths relative to the repository root
+fn export_manifest(manifest: String, algorithm: Option<String>, tag: Option<String>) -> Result<()> {
+    let algorithm: Algorithm = algorithm.as_deref().unwrap_or("sha256").parse()?;
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let manifest = std::env::current_dir()?.join(manifest);
+    if manifest.exists() {
+        bail!("{} already exists", manifest.display());
+    }
+
+    let index = Index::load(&repo_root)?;
+    let tags = Tags::new(index.tags()?);
+    let mut entries = index.get_dir_files_recursive("")?;
+    if let Some(tag) = &tag {
+        entries.retain(|entry| tags.has(&entry.path, tag));
+        if entries.is_empty() {
+            bail!("No files tagged {} in index", tag);
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut contents = String::new();
+    let mut skipped = 0;
+    for entry in &entries {
+        let Some(hex) = digest::recorded(entry, algorithm) else {
+            skipped += 1;
+            continue;
+        };
+        contents.push_str(&checksums::coreutils_line(hex, &entry.path));
+    }
+    if skipped == entries.len() {
+        bail!("No {} digests in the index{}", algorithm, missing_digest_hint(&config, algorithm));
+    }
+    fs::write(&manifest, contents).context(format!("Failed to write {}", manifest.display()))?;
+
+    println!("Wrote {} digests of {} file(s) to {}", algorithm, entries.len() - skipped, manifest.display());
+    if skipped > 0 {
+        println!("Skipped {} file(s) without a {} digest{}", skipped, algorithm, missing_digest_hint(&config, algorithm));
+    }
+    Ok(())
+}
+
+/// Write a BagIt bag of the indexed files (those under `path`, or with a tag): the files are
+/// copied into its payload and its manifest is written from the index, so nothing is hashed.
+/// Files that no longer match their entries would make the bag invalid, so they stop it before any copying.
+fn export_bagit(bag: String, path: Option<String>, algorithm: Option<String>, tag: Option<String>) -> Result<()> {
+    let algorithm: Algorithm = algorithm.as_deref().unwrap_or("sha256").parse()?;
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let bag = std::env::current_dir()?.join(bag);
+    if bag.exists() && fs::read_dir(&bag).map_or(true, |mut entries| entries.next().is_some()) {
+        bail!("{} already exists and is not an empty directory", bag.display());
+    }
+
+    let subtree = match &path {
+        Some(path) => repo_relative_path(&roots, &get_logical_current_dir()?, path)?,
+        None => String::new(),
+    };
+    let index = Index::load(&repo_root)?;
+    let tags = Tags::new(index.tags()?);
+    let mut entries = match index.get(&subtree)? {
+        Some(entry) => vec![entry],
+        None => index.get_dir_files_recursive(&subtree)?,
+    };
+    if let Some(tag) = &tag {
+        entries.retain(|entry| tags.has(&entry.path, tag));
+    }
+    if entries.is_empty() {
+        let tagged = tag.as_ref().map(|tag| format!(" tagged {}", tag)).unwrap_or_default();
+        bail!("No files{} in index under {}", tagged, path.as_deref().unwrap_or("."));
+    }
+
+    let missing = entries.iter().filter(|entry| digest::recorded(entry, algorithm).is_none()).count();
+    if missing > 0 {
+        bail!("{} file(s) have no {} digest in the index{}", missing, algorithm, missing_digest_hint(&config, algorithm));
+    }
+    let changed: Vec<&str> = entries
+        .iter()
+        .filter(|entry| file_utils::has_changed(entry, &roots.full_path(&entry.path), ChangeDetection::Metadata).unwrap_or(true))
+        .map(|entry| entry.path.as_str())
+        .collect();
+    if let Some(first) = changed.first() {
+        bail!("{} file(s) changed or missing since they were indexed, e.g. {} (run 'oci update' first)", changed.len(), first);
+    }
+
+    // A single file is bagged under its name, a directory's files under their paths below it
+    let payload_path = |entry: &FileEntry| match entry.path == subtree {
+        true => entry.path.rsplit('/').next().unwrap_or(&entry.path).to_string(),
+        false => entry.path[dir_prefix(&subtree).len()..].to_string(),
+    };
+    let payload_dir = bag.join(bagit::PAYLOAD_DIR);
+    let mut payload = Vec::new();
+    let copied = entries.iter().try_for_each(|entry| {
+        let rel = payload_path(entry);
+        let dest = payload_dir.join(&rel);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(file_utils::long_path(parent)).context(format!("Failed to create directory: {}", parent.display()))?;
+        }
+        file_utils::copy_preserving_mtime(&roots.full_path(&entry.path), &dest)?;
+        let digest = digest::recorded(entry, algorithm).unwrap_or_default().to_string();
+        payload.push(bagit::PayloadFile { path: rel, digest, num_bytes: entry.num_bytes });
+        Ok::<_, anyhow::Error>(())
+    });
+    if let Err(err) = copied {
+        // Leave no payload behind that the manifest doesn't describe
+        let _ = fs::remove_dir_all(file_utils::long_path(&payload_dir));
+        return Err(err);
+    }
+    let date = oplog::format_local_timestamp(file_utils::now_millis());
+    bagit::write_tag_files(&bag, algorithm, &payload, &date[..10])?;
+
+    let num_bytes: u64 = payload.iter().map(|file| file.num_bytes).sum();
+    println!("Wrote a bag of {} file(s) ({}) to {}", payload.len(), format_bytes(num_bytes), bag.display());
+    Ok(())
+}
+
+/// How to get the digests missing from a manifest
+/// Make a recovery set with par2 for each directory holding indexed files (under `path`),
+/// protecting the files directly in it. Directories with files that changed since they were
+/// indexed are skipped: the set records the indexed digests, and `verify --repair` trusts it to restore those.
+pub fn parity(path: Option<String>, redundancy: u8) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let subtree = match &path {
+        Some(path) => repo_relative_path(&roots, &get_logical_current_dir()?, path)?,
+        None => String::new(),
+    };
+    let index = Index::load(&repo_root)?;
+    let entries = index.get_dir_files_recursive(&subtree)?;
+    if entries.is_empty() {
+        bail!("No files in index under {}", path.as_deref().unwrap_or("."));
+    }
+
+    // Empty files have nothing to recover, and files pending a full hash cannot be checked after a repair
+    let mut dirs: std::collections::BTreeMap<&str, Vec<&FileEntry>> = std::collections::BTreeMap::new();
+    for entry in entries.iter().filter(|entry| entry.num_bytes > 0 && !entry.sha256.is_empty()) {
+        let dir = entry.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        dirs.entry(dir).or_default().push(entry);
+    }
+
+    let par2 = Par2::new(&config);
+    let (mut made, mut protected, mut num_bytes, mut changed) = (0, 0, 0, Vec::new());
+    for (dir, files) in &dirs {
+        if let Some(entry) = files.iter().find(|entry| {
+            file_utils::has_changed(entry, &roots.full_path(&entry.path), ChangeDetection::Metadata).unwrap_or(true)
+        }) {
+            changed.push(entry.path.as_str());
+            continue;
+        }
+        let named: Vec<(String, String)> = files
+            .iter()
+            .map(|entry| (entry.path.rsplit('/').next().unwrap_or(&entry.path).to_string(), entry.sha256.clone()))
+            .collect();
+        par2.create(&parity::set_dir(&repo_root, dir), &roots.full_path(dir), &named, redundancy)?;
+        made += 1;
+        protected += files.len();
+        num_bytes += files.iter().map(|entry| entry.num_bytes).sum::<u64>();
+    }
+
     println!(
-        "Potential space savings: {} bytes ({:.2} MB)\n",
-        wasted_bytes,
-        wasted_bytes as f64 / 1_048_576.0
+        "Made {} recovery set(s) with {}% redundancy, protecting {} file(s) ({})",
+        made,
+        redundancy,
+        protected,
+        format_bytes(num_bytes)
     );
+    if let Some(first) = changed.first() {
+        println!(
+            "Skipped {} director(ies) with files changed or missing since they were indexed, e.g. {} (run 'oci update' first)",
+            changed.len(),
+            first
+        );
+    }
+    Ok(())
+}
 
-    // Display each group
-    let display_ctx = DisplayContext::new(repo_root, current_dir);
-    for (hash, mut files) in duplicate_groups {
-        println!("Hash: {}", hash);
+/// Write a checksum file into each directory holding indexed files (under `path`), listing the
+/// digests of the files directly in it, so each directory can be checked with sha256sum -c or an
+/// SFV checker without oci. Digests come from the index; SFV's CRC32s are not indexed, so those
+/// files are read. Files that changed since they were indexed are left out rather than listed with stale digests.
+pub fn checksums(path: Option<String>, algorithm: Option<String>, sfv: bool) -> Result<()> {
+    let format = if sfv {
+        checksums::Format::Sfv
+    } else {
+        checksums::Format::Digests(algorithm.as_deref().unwrap_or("sha256").parse()?)
+    };
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let subtree = match &path {
+        Some(path) => repo_relative_path(&roots, &get_logical_current_dir()?, path)?,
+        None => String::new(),
+    };
+    let file_name = format.file_name();
+
+    // The checksum files would otherwise be indexed on the next update, and change whenever they are rewritten
+    let patterns = ignore::load_patterns(&repo_root)?;
+    if !ignore::should_ignore(Path::new(&file_name), &patterns) {
+        ignore::add_pattern(&repo_root, &file_name)?;
+        println!("Added {} to the ignore patterns", file_name);
+    }
+
+    let index = Index::load(&repo_root)?;
+    let entries: Vec<FileEntry> = index
+        .get_dir_files_recursive(&subtree)?
+        .into_iter()
+        .filter(|entry| entry.path.rsplit('/').next() != Some(file_name.as_str()))
+        .collect();
+    if entries.is_empty() {
+        bail!("No files in index under {}", path.as_deref().unwrap_or("."));
+    }
+
+    // Entries come in path order, but a directory's files are not contiguous once it has subdirectories
+    let mut dirs: std::collections::BTreeMap<&str, Vec<(String, String)>> = std::collections::BTreeMap::new();
+    let (mut without_digest, mut changed, mut unlisted) = (0, 0, 0);
+    for entry in &entries {
+        let (dir, name) = entry.path.rsplit_once('/').unwrap_or(("", entry.path.as_str()));
+        let full_path = roots.full_path(&entry.path);
+        if file_utils::has_changed(entry, &full_path, ChangeDetection::Metadata).unwrap_or(true) {
+            changed += 1;
+            continue;
+        }
+        let checksum = match format {
+            checksums::Format::Digests(algorithm) => match digest::recorded(entry, algorithm) {
+                Some(hex) => hex.to_string(),
+                None => {
+                    without_digest += 1;
+                    continue;
+                }
+            },
+            // SFV has no escaping, so names with line breaks cannot be listed
+            checksums::Format::Sfv if name.contains(['\r', '\n']) => {
+                unlisted += 1;
+                continue;
+            }
+            checksums::Format::Sfv => checksums::crc32(&full_path)?,
+        };
+        dirs.entry(dir).or_default().push((name.to_string(), checksum));
+    }
+
+    let mut listed = 0;
+    for (dir, files) in &dirs {
+        let checksum_file = roots.full_path(dir).join(&file_name);
+        fs::write(file_utils::long_path(&checksum_file), format.contents(files))
+            .context(format!("Failed to write {}", checksum_file.display()))?;
+        listed += files.len();
+    }
+
+    println!("Wrote {} to {} director(ies), listing {} file(s)", file_name, dirs.len(), listed);
+    if changed > 0 {
+        println!("Skipped {} file(s) changed or missing since they were indexed (run 'oci update' first)", changed);
+    }
+    if let checksums::Format::Digests(algorithm) = format {
+        if without_digest > 0 {
+            println!("Skipped {} file(s) without a {} digest{}", without_digest, algorithm, missing_digest_hint(&config, algorithm));
+        }
+    }
+    if unlisted > 0 {
+        println!("Skipped {} file(s) whose names SFV cannot hold", unlisted);
+    }
+    Ok(())
+}
+
+fn missing_digest_hint(config: &Config, algorithm: Algorithm) -> String {
+    if algorithm == Algorithm::Sha256 {
+        " (large files await a full hash; run oci update --full-hash)".to_string()
+    } else if !config.digests.contains(algorithm) {
+        format!(" (add {} to the digests key in .oci/config and run oci update)", algorithm)
+    } else {
+        " (run oci update --full-hash to compute them)".to_string()
+    }
+}
+
+/// Write an export to `dest`, reporting it as written to `target` (the directory, or the bundle it goes into)
+fn export_to(repo_root: &Path, dest: &Path, key: Option<SigningKey>, tag: Option<&str>, target: &str) -> Result<()> {
+    let dest_oci = dest.join(OCI_DIR);
+    if dest_oci.exists() {
+        bail!("{} already contains an index", dest.display());
+    }
+
+    let index = Index::load(repo_root)?;
+    let tags = Tags::new(index.tags()?);
+    let mut entries = index.get_dir_files_recursive("")?;
+    if let Some(tag) = tag {
+        entries.retain(|entry| tags.has(&entry.path, tag));
+        if entries.is_empty() {
+            bail!("No files tagged {} in index", tag);
+        }
+    }
+    if Backend::detect(repo_root) == Backend::Encrypted {
+        warn!("The index is encrypted, but the export is not: its file names and hashes can be read by anyone with {}", target);
+    }
+    write_index_copy(repo_root, &index, &entries, dest)?;
+    let ignore_file = ignore::ignore_file(repo_root);
+    if ignore_file.exists() {
+        fs::copy(&ignore_file, dest_oci.join(ignore::OCIGNORE_FILE)).context("Failed to copy ignore patterns")?;
+    }
+
+    let total_bytes: u64 = entries.iter().map(|e| e.num_bytes).sum();
+    println!("Exported {} file(s) ({}) to {}", entries.len(), format_bytes(total_bytes), target);
+    if let Some(key) = key {
+        let names = [Backend::Sqlite.file_name(), ignore::OCIGNORE_FILE, source::EXPORT_FILE];
+        signing::sign(&dest_oci, &names, &key)?;
+        println!("Signed with {}", signing::encode_public_key(&key.verifying_key()));
+    }
+    Ok(())
+}
+
+/// Write the given entries, with their archive members, media and audio data and the index's
+/// storage classes and tags, to a new SQLite index at `dest`, marked as an export.
+/// The copy is never encrypted, so callers decide whether an encrypted index may be copied.
+fn write_index_copy(repo_root: &Path, index: &Index, entries: &[FileEntry], dest: &Path) -> Result<()> {
+    let copied: HashSet<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+    let mut copy = Index::open(dest, Backend::Sqlite)?;
+    copy.begin_batch()?;
+    copy.upsert_many(entries.iter().cloned())?;
+    let mut members: std::collections::BTreeMap<String, Vec<ArchiveMember>> = std::collections::BTreeMap::new();
+    for member in index.archive_members()?.into_iter().filter(|m| copied.contains(m.archive.as_str())) {
+        members.entry(member.archive.clone()).or_default().push(member);
+    }
+    for (archive, members) in &members {
+        copy.set_archive_members(archive, members)?;
+    }
+    for info in index.media()?.into_iter().filter(|info| copied.contains(info.path.as_str())) {
+        copy.set_media(&info)?;
+    }
+    for print in index.audio_fingerprints()?.into_iter().filter(|print| copied.contains(print.path.as_str())) {
+        copy.set_audio(&print)?;
+    }
+    for (path, class) in index.storage_class_tags()? {
+        copy.set_storage_class(&path, class)?;
+    }
+    for (path, tag) in index.tags()? {
+        copy.add_tag(&path, &tag)?;
+    }
+    copy.commit_batch()?;
+    copy.save(dest)?;
+
+    fs::write(
+        dest.join(OCI_DIR).join(source::EXPORT_FILE),
+        format!("{}\n{}\n", repo_root.display(), file_utils::now_millis()),
+    ).context("Failed to write export marker")?;
+    Ok(())
+}
+
+/// Snapshots are plain SQLite inside the repository, which would leave an encrypted index readable next to it
+fn refuse_encrypted_snapshots(repo_root: &Path) -> Result<()> {
+    if Backend::detect(repo_root) == Backend::Encrypted {
+        bail!("An encrypted index has no snapshots, as they would not be encrypted");
+    }
+    Ok(())
+}
+
+/// Save the index as a named snapshot under `.oci/snapshots`
+pub fn snapshot_save(name: String) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    refuse_encrypted_snapshots(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    snapshot::validate_name(&name)?;
+    let dest = snapshot::snapshot_dir(&repo_root, &name);
+    if dest.exists() {
+        bail!("Snapshot {} already exists", name);
+    }
+
+    let index = Index::load(&repo_root)?;
+    let entries = index.get_dir_files_recursive("")?;
+    write_index_copy(&repo_root, &index, &entries, &dest)?;
+    let total_bytes: u64 = entries.iter().map(|e| e.num_bytes).sum();
+    println!("Saved snapshot {} ({} file(s), {})", name, entries.len(), format_bytes(total_bytes));
+    Ok(())
+}
+
+/// List the snapshots of the index, oldest first
+pub fn snapshot_list() -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    let snapshots = snapshot::list(&repo_root)?;
+    if snapshots.is_empty() {
+        println!("No snapshots");
+        return Ok(());
+    }
+    for snapshot in snapshots {
+        let entries = Index::load(&snapshot::snapshot_dir(&repo_root, &snapshot.name))?.get_dir_files_recursive("")?;
+        let total_bytes: u64 = entries.iter().map(|e| e.num_bytes).sum();
+        let created = snapshot.created.map(oplog::format_local_timestamp).unwrap_or_else(|| "unknown".to_string());
+        println!("{}  {}  {} file(s), {}", created, snapshot.name, entries.len(), format_bytes(total_bytes));
+    }
+    Ok(())
+}
+
+/// Replace the indexed files with a snapshot's, after saving the current state as another snapshot.
+/// Tags and storage classes are left as they are.
+pub fn snapshot_restore(name: String, force: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    // Its automatic snapshot of the current index would be written in the clear
+    refuse_encrypted_snapshots(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    snapshot::validate_name(&name)?;
+    let source_dir = snapshot::snapshot_dir(&repo_root, &name);
+    if !source_dir.join(OCI_DIR).is_dir() {
+        bail!("No snapshot named {} (see 'oci snapshot list')", name);
+    }
+    let saved = Index::load(&source_dir).context(format!("Failed to load snapshot {}", name))?;
+    let entries = saved.get_dir_files_recursive("")?;
+
+    let mut index = Index::load(&repo_root)?;
+    let current = index.get_dir_files_recursive("")?;
+    if !force {
+        println!("This will replace the {} indexed file(s) with the {} file(s) of snapshot {}.", current.len(), entries.len(), name);
+        print!("Are you sure you want to continue? (y/N): ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        
+        let confirmed = input.trim().eq_ignore_ascii_case("y") || input.trim().eq_ignore_ascii_case("yes");
+        
+        if !confirmed {
+            println!("Restore cancelled");
+            return Ok(());
+        }
+    }
 
-        // Sort files by path within each group for consistent output
-        files.sort_by(|a, b| a.path.cmp(&b.path));
+    let backup = snapshot::backup_name(&repo_root, file_utils::now_millis());
+    write_index_copy(&repo_root, &index, &current, &snapshot::snapshot_dir(&repo_root, &backup))?;
 
-        for entry in files {
-            let formatted = display_ctx.format_entry_relative(&entry)?;
-            println!("  {}", formatted);
-        }
-        println!();
+    index.begin_batch()?;
+    index.clear()?;
+    index.upsert_many(entries.iter().cloned())?;
+    let mut members: std::collections::BTreeMap<String, Vec<ArchiveMember>> = std::collections::BTreeMap::new();
+    for member in saved.archive_members()? {
+        members.entry(member.archive.clone()).or_default().push(member);
+    }
+    for (archive, members) in &members {
+        index.set_archive_members(archive, members)?;
     }
+    for info in saved.media()? {
+        index.set_media(&info)?;
+    }
+    for print in saved.audio_fingerprints()? {
+        index.set_audio(&print)?;
+    }
+    index.commit_batch()?;
+    index.save(&repo_root)?;
+    // The restored index doesn't match the tree as of the recorded journal positions
+    changes::forget(&repo_root)?;
+    index.log_operation(&Operation::new(&config, "snapshot restore",
+        format!("restored {} file(s) from {}, replacing {} (saved as {})", entries.len(), name, current.len(), backup)))?;
 
+    println!("Restored snapshot {} ({} file(s))", name, entries.len());
+    println!("The previous index was saved as snapshot {}", backup);
     Ok(())
 }
 
-/// Restore files from pruneyard back to their original locations
-fn prune_restore(repo_root: &Path) -> Result<()> {
-    let pruneyard_path = repo_root.join(OCI_DIR).join("pruneyard");
+pub fn keys_generate(force: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    let key = signing::generate(&repo_root, force)?;
+    println!("Created a signing key in {}", repo_root.join(OCI_DIR).join(signing::KEYS_DIR).display());
+    println!("Public key: {}", signing::encode_public_key(&key));
+    Ok(())
+}
 
-    if !pruneyard_path.exists() {
-        println!("No pruneyard directory exists");
-        return Ok(());
+pub fn keys_show() -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    match signing::public_key(&repo_root)? {
+        Some(key) => println!("{}", signing::encode_public_key(&key)),
+        None => bail!("No signing key in this repository; create one with 'oci keys generate'"),
     }
+    Ok(())
+}
 
-    let mut index = Index::load(repo_root)?;
-    let mut restored_count = 0;
+pub fn keys_trust(name: String, key: String) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    let key = signing::resolve_key(&repo_root, &key)?;
+    signing::trust(&repo_root, &name, &key)?;
+    println!("Trusted {} as {}", signing::encode_public_key(&key), name);
+    Ok(())
+}
 
-    // Walk through pruneyard and restore files
-    for entry in WalkDir::new(&pruneyard_path) {
-        let entry = entry?;
+pub fn keys_list() -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    if let Some(key) = signing::public_key(&repo_root)? {
+        println!("{:<16} {}", "(signing)", signing::encode_public_key(&key));
+    }
+    for (name, key) in signing::trusted_keys(&repo_root)? {
+        println!("{:<16} {}", name, signing::encode_public_key(&key));
+    }
+    Ok(())
+}
 
-        if entry.file_type().is_file() {
-            let rel_from_pruneyard = entry
-                .path()
-                .strip_prefix(&pruneyard_path)
-                .context("Failed to get relative path from pruneyard")?;
-            let original_path = repo_root.join(rel_from_pruneyard);
+pub fn publish(static_dir: String, title: Option<String>) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    let index = Index::load(&repo_root)?;
+    let entries = index.get_dir_files_recursive("")?;
 
-            // Create parent directories if needed
-            if let Some(parent) = original_path.parent() {
-                fs::create_dir_all(parent)
-                    .context(format!("Failed to create directory: {}", parent.display()))?;
-            }
+    let title = title.unwrap_or_else(|| {
+        repo_root.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| "Catalog".to_string())
+    });
+    let out_dir = std::env::current_dir()?.join(&static_dir);
+    crate::publish::write_static(&entries, &title, &out_dir)?;
 
-            // Move file back to original location
-            fs::rename(entry.path(), &original_path).context(format!(
-                "Failed to restore file: {}",
-                entry.path().display()
-            ))?;
+    let total_bytes: u64 = entries.iter().map(|e| e.num_bytes).sum();
+    println!("Published {} file(s) ({}) to {}", entries.len(), format_bytes(total_bytes), out_dir.display());
+    println!("Open {} in a browser, or copy the directory to a web server", out_dir.join(crate::publish::PAGE_FILE).display());
+    Ok(())
+}
 
-            // Add back to index
-            let rel_path_str = rel_from_pruneyard.to_string_lossy().to_string();
-            let file_entry = file_utils::create_file_entry(&original_path, rel_path_str)?;
-            index.upsert(file_entry)?;
+pub fn fulfill(request_file: String, output: Option<String>) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let index = Index::load(&repo_root)?;
 
-            println!("Restored: {}", rel_from_pruneyard.display());
-            restored_count += 1;
+    let contents = fs::read_to_string(&request_file)
+        .context(format!("Failed to read request file: {}", request_file))?;
+    let requests = crate::fulfill::parse_request(&contents);
+    if requests.is_empty() {
+        bail!("No files requested in {}", request_file);
+    }
+
+    let output = output.map(PathBuf::from).unwrap_or_else(|| Path::new(&request_file).with_extension("zip"));
+    if output.exists() {
+        bail!("{} already exists", output.display());
+    }
+    let mut delivery = crate::fulfill::Delivery::create(&output)?;
+    let mut undelivered = Vec::new();
+
+    for request in &requests {
+        let mut reason = "not in the index";
+        let mut delivered = false;
+        for entry in crate::fulfill::candidates(&index, request)? {
+            // Deliver under the requested name even if the content was found elsewhere
+            let name = request.path.clone().unwrap_or_else(|| entry.path.clone());
+            if delivery.contains(&name) {
+                delivered = true;
+                break;
+            }
+            let full_path = roots.full_path(&entry.path);
+            if !full_path.exists() {
+                reason = "missing on disk";
+                continue;
+            }
+            let expected = request.sha256.as_deref().or(Some(entry.sha256.as_str()).filter(|h| !h.is_empty()));
+            if delivery.add(&name, &full_path, entry.modified, expected)? {
+                if !display::quiet() {
+                    if name == entry.path {
+                        println!("+ {}", name);
+                    } else {
+                        println!("+ {} (from {})", name, entry.path);
+                    }
+                }
+                delivered = true;
+                break;
+            }
+            println!("Mismatch: {} (trying other copies)", entry.path);
+            reason = "content differs from the index";
+        }
+        if !delivered {
+            println!("Not delivered: {} ({})", request.describe(), reason);
+            undelivered.push(format!("{}  {}", request.describe(), reason));
         }
     }
 
-    // Remove empty pruneyard directory
-    if restored_count > 0 {
-        fs::remove_dir_all(&pruneyard_path)
-            .context("Failed to remove pruneyard directory")?;
+    if delivery.len() == 0 {
+        drop(delivery);
+        fs::remove_file(&output).ok();
+        bail!("None of the {} requested file(s) could be delivered", requests.len());
     }
 
-    index.save(repo_root)?;
+    let (count, total_bytes) = (delivery.len(), delivery.total_bytes);
+    delivery.finish(&undelivered)?;
+    println!("Packed {} of {} requested file(s) ({}) into {}",
+        count, requests.len(), format_bytes(total_bytes), output.display());
 
-    println!("Restored {} file(s) from pruneyard", restored_count);
+    if !undelivered.is_empty() {
+        bail!("{} requested file(s) could not be delivered (listed in the archive's MISSING.txt)", undelivered.len());
+    }
     Ok(())
 }
 
-/// Permanently delete all files in pruneyard
-fn prune_purge(repo_root: &Path, force: bool) -> Result<()> {
-    // Check for pending changes in local index before purging
-    if has_pending_changes(repo_root)? {
-        bail!("Cannot purge: there are pending changes in the local index. Run 'oci status' to see changes.");
+pub fn reset(force: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    
+    // Ask for confirmation unless --force is used
+    if !force {
+        println!("This will remove all entries from the index.");
+        print!("Are you sure you want to continue? (y/N): ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        
+        let confirmed = input.trim().eq_ignore_ascii_case("y") || input.trim().eq_ignore_ascii_case("yes");
+        
+        if !confirmed {
+            println!("Reset cancelled");
+            return Ok(());
+        }
     }
+    
+    back_up_index(&repo_root, &config, "reset")?;
+    let mut index = Index::load(&repo_root)?;
+    let removed = index.get_dir_files_recursive("")?.len();
+    index.clear()?;
+    HashCache::remove(&repo_root)?;
+    changes::forget(&repo_root)?;
+    index.log_operation(&Operation::new(&config, "reset", format!("removed {} file(s)", removed)))?;
+    
+    println!("Reset index (removed all entries)");
+    Ok(())
+}
 
-    let pruneyard_path = repo_root.join(OCI_DIR).join("pruneyard");
-
-    if !pruneyard_path.exists() {
-        println!("No pruneyard directory exists");
+/// List the index backups, or replace the index with one of them after checking that it opens
+/// (the index being replaced is backed up first, even if it can't be opened itself)
+pub fn restore_index(name: Option<String>, force: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let backups = backup::list(&repo_root)?;
+    let Some(name) = name else {
+        if backups.is_empty() {
+            println!("No index backups");
+            return Ok(());
+        }
+        for backup in &backups {
+            let size = fs::metadata(&backup.path).map(|metadata| metadata.len()).unwrap_or(0);
+            println!("{}  {}", backup.name, format_bytes(size));
+        }
+        println!("Restore one with 'oci restore-index <name>'");
         return Ok(());
+    };
+    let _lock = lock_repo(&repo_root, &config)?;
+    let backup = backup::find(&backups, &name)?;
+
+    let unusable = || format!("Backup {} cannot be opened; the index is unchanged", backup.name);
+    let staging = backup::stage(&repo_root, &backup).with_context(unusable)?;
+    let restored = match Index::open(&staging, backup.backend).and_then(|index| index.get_dir_files_recursive("")) {
+        Ok(entries) => entries.len(),
+        Err(err) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(err.context(unusable()));
+        }
+    };
+
+    if !force {
+        match Index::load(&repo_root).and_then(|index| index.get_dir_files_recursive("")) {
+            Ok(current) => println!(
+                "This will replace the index ({} file(s)) with backup {} ({} file(s)).",
+                current.len(), backup.name, restored
+            ),
+            Err(_) => println!("This will replace the index, which cannot be opened, with backup {} ({} file(s)).", backup.name, restored),
+        }
+        print!("Are you sure you want to continue? (y/N): ");
+        std::io::Write::flush(&mut std::io::stdout())?;
+        
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        
+        let confirmed = input.trim().eq_ignore_ascii_case("y") || input.trim().eq_ignore_ascii_case("yes");
+        
+        if !confirmed {
+            let _ = fs::remove_dir_all(&staging);
+            println!("Restore cancelled");
+            return Ok(());
+        }
     }
 
-    let count = dir_utils::count_files_in_dir(&pruneyard_path)?;
+    back_up_index(&repo_root, &config, "restore-index")?;
+    backup::install(&repo_root, &staging, backup.backend)?;
+    // The restored index doesn't match the tree as of the recorded journal positions
+    changes::forget(&repo_root)?;
+    let mut index = Index::load(&repo_root)?;
+    index.log_operation(&Operation::new(&config, "restore-index", format!("restored {} file(s) from {}", restored, backup.name)))?;
+    index.save(&repo_root)?;
 
+    println!("Restored the index from backup {} ({} file(s))", backup.name, restored);
+    println!("Run 'oci status' to see how it differs from the files");
+    Ok(())
+}
+
+/// Remove the index (deinitialize)
+pub fn deinit(force: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let oci_dir = repo_root.join(OCI_DIR);
+    
     // Ask for confirmation unless --force is used
     if !force {
-        println!("This will permanently delete {} pruned file(s).", count);
+        println!("This will permanently delete the index at {}", oci_dir.display());
         print!("Are you sure you want to continue? (y/N): ");
         std::io::Write::flush(&mut std::io::stdout())?;
-
+        
         let mut input = String::new();
         std::io::stdin().read_line(&mut input)?;
-
-        let confirmed =
-            input.trim().eq_ignore_ascii_case("y") || input.trim().eq_ignore_ascii_case("yes");
-
+        
+        let confirmed = input.trim().eq_ignore_ascii_case("y") || input.trim().eq_ignore_ascii_case("yes");
+        
         if !confirmed {
-            println!("Purge cancelled");
+            println!("Deinit cancelled");
             return Ok(());
         }
     }
-
-    fs::remove_dir_all(&pruneyard_path).context("Failed to remove pruneyard directory")?;
-
-    println!("Permanently deleted {} pruned file(s)", count);
+    
+    fs::remove_dir_all(&oci_dir)
+        .context("Failed to remove .oci directory")?;
+    
+    println!("Deinitialized oci index at {}", oci_dir.display());
+    if let Err(e) = registry::unregister(&repo_root) {
+        warn!("Could not unregister repository: {:#}", e);
+    }
+    Ok(())
+}
+
+/// Show index statistics
+pub fn stats(by_extension: bool, by_dir: Option<usize>, by_type: bool, age: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    let index = Index::load(&repo_root)?;
+    
+    // Get all files from the index
+    let all_files = index.get_dir_files_recursive("")?;
+    
+    if all_files.is_empty() {
+        println!("Index is empty");
+        return Ok(());
+    }
+    
+    // Calculate statistics
+    let total_files = all_files.len();
+    let total_size: u64 = all_files.iter().map(|f| f.num_bytes).sum();
+    let total_allocated: u64 = all_files.iter().map(FileEntry::allocated_bytes).sum();
+    
+    // Group files by hash to find unique hashes and duplicates
+    let mut hash_map: std::collections::HashMap<String, Vec<&crate::index::FileEntry>> = 
+        std::collections::HashMap::new();
+    
+    // Entries awaiting a full hash are counted separately
+    let pending_hashes = all_files.iter().filter(|f| f.sha256.is_empty()).count();
+    
+    for entry in all_files.iter().filter(|f| !f.sha256.is_empty()) {
+        hash_map.entry(entry.sha256.clone())
+            .or_default()
+            .push(entry);
+    }
+    
+    let unique_hashes = hash_map.len();
+    
+    // Calculate duplicate files (count all files in groups with >1 file)
+    let duplicate_files: usize = hash_map.values()
+        .filter(|files| files.len() > 1)
+        .map(|files| files.len())
+        .sum();
+    
+    // Calculate unique size (sum of sizes for one file per hash)
+    let unique_size: u64 = hash_map.values()
+        .map(|files| files[0].num_bytes)
+        .sum();
+    
+    // Calculate wasted space (duplicates)
+    let wasted_space: u64 = hash_map.values()
+        .filter(|files| files.len() > 1)
+        .map(|files| {
+            let file_size = files[0].num_bytes;
+            file_size * (files.len() as u64 - 1)
+        })
+        .sum();
+    // What removing the duplicates would free on disk, keeping the copy that takes the most space
+    let wasted_allocated: u64 = hash_map.values()
+        .filter(|files| files.len() > 1)
+        .map(|files| {
+            let total: u64 = files.iter().map(|f| f.allocated_bytes()).sum();
+            total - files.iter().map(|f| f.allocated_bytes()).max().unwrap_or(0)
+        })
+        .sum();
+    
+    // Calculate storage efficiency (how much space is actual unique content)
+    let storage_efficiency = if total_size > 0 {
+        (unique_size as f64 / total_size as f64) * 100.0
+    } else {
+        100.0
+    };
+    
+    // Display statistics
+    println!("Index Statistics:");
+    println!("  Total files: {}", total_files);
+    println!("  Total size: {}", display::format_total(total_size));
+    println!("  Allocated on disk: {}", display::format_total(total_allocated));
+    println!("  Unique hashes: {}", unique_hashes);
+    println!("  Duplicate files: {}", duplicate_files);
+    
+    if duplicate_files > 0 {
+        let duplicate_groups = hash_map.values().filter(|files| files.len() > 1).count();
+        println!("  Duplicate groups: {}", duplicate_groups);
+        println!("  Wasted space: {}", display::format_total(wasted_space));
+        println!("  Wasted on disk: {}", display::format_total(wasted_allocated));
+    }
+    
+    println!("  Storage efficiency: {:.2}%", storage_efficiency);
+    
+    if pending_hashes > 0 {
+        println!("  Pending full hashes: {}", pending_hashes);
+    }
+    
+    if by_extension {
+        println!();
+        println!("By extension:");
+        for line in breakdown::lines("Extension", &breakdown::by_extension(&all_files)) {
+            println!("{}", line);
+        }
+    }
+    
+    if let Some(depth) = by_dir {
+        println!();
+        println!("By directory:");
+        for line in breakdown::lines("Directory", &breakdown::by_dir(&all_files, depth)) {
+            println!("{}", line);
+        }
+    }
+    
+    if by_type {
+        println!();
+        println!("By type:");
+        for line in breakdown::lines("Type", &breakdown::by_type(&all_files)) {
+            println!("{}", line);
+        }
+    }
+    
+    if age {
+        println!();
+        println!("By age:");
+        if let Some((oldest, newest)) = breakdown::age_range(&all_files) {
+            println!("  Oldest: {}  {}", oplog::format_local_timestamp(oldest.modified), oldest.path);
+            println!("  Newest: {}  {}", oplog::format_local_timestamp(newest.modified), newest.path);
+        }
+        for line in breakdown::lines("Year", &breakdown::by_year(&all_files)) {
+            println!("{}", line);
+        }
+    }
+    
     Ok(())
 }
 
-/// Find files to prune based on source index and ignore patterns
-fn find_files_to_prune(
-    local_index: &Index,
-    source_index: &Index,
+/// Prune files matching local ignore patterns
+fn prune_local_ignored_files(
     repo_root: &Path,
-    source_patterns: &[String],
-    local_patterns: &[String],
-    no_ignore: bool,
-    ignored: bool,
-) -> Result<Vec<(String, String, bool)>> {
-    let mut files_to_prune: Vec<(String, String, bool)> = Vec::new();
-
-    // Get all files from local index
+    config: &Config,
+    override_policy: bool,
+    disposal: &Disposal,
+    dry_run: bool,
+    scope: &PruneScope,
+    scope_dir: &str,
+) -> Result<()> {
+    let mut local_index = Index::load(repo_root)?;
+    let local_patterns = ignore::load_patterns(repo_root)?;
+    
+    if local_patterns.is_empty() {
+        println!("No ignore patterns defined in local ignore");
+        return Ok(());
+    }
+    
+    // Find files to prune - store as (path, in_index)
+    let mut files_to_prune: Vec<(String, bool)> = Vec::new();
+    
+    // Check files in the index
     let local_files = local_index.get_dir_files_recursive("")?;
-
-    // Check indexed files
     for local_entry in &local_files {
-        let mut should_prune = false;
-        let mut prune_reason = String::new();
-
-        // Check if hash exists in source index
-        let source_matches = source_index.find_by_hash(&local_entry.sha256)?;
-        if !source_matches.is_empty() {
-            should_prune = true;
-            prune_reason = "duplicate".to_string();
-        }
-
-        // Check if file matches source ignore patterns (unless --no-ignore)
-        if !no_ignore && !source_patterns.is_empty() {
-            let path = Path::new(&local_entry.path);
-            if ignore::should_ignore(path, source_patterns) {
-                should_prune = true;
-                prune_reason = "ignored".to_string();
-            }
-        }
-
-        // Check if file matches local ignore patterns (if --ignored flag is present)
-        if ignored && !local_patterns.is_empty() {
-            let path = Path::new(&local_entry.path);
-            if ignore::should_ignore(path, local_patterns) {
-                should_prune = true;
-                prune_reason = "ignored".to_string();
-            }
-        }
-
-        if should_prune {
-            files_to_prune.push((local_entry.path.clone(), prune_reason, true));
+        let path = Path::new(&local_entry.path);
+        if ignore::should_ignore(path, &local_patterns) {
+            files_to_prune.push((local_entry.path.clone(), true));
         }
     }
-
-    // Also check for files on filesystem that match ignore patterns but aren't in local index
-    let check_fs_ignored =
-        (!no_ignore && !source_patterns.is_empty()) || (ignored && !local_patterns.is_empty());
-    if check_fs_ignored {
-        for entry in WalkDir::new(repo_root).into_iter().filter_entry(|e| {
+    
+    // Check files on filesystem that aren't in the index
+    for entry in WalkDir::new(repo_root).into_iter()
+        .filter_entry(|e| {
             // Don't walk into .oci directory
             if let Ok(rel) = e.path().strip_prefix(repo_root) {
-                let rel_str = rel.to_string_lossy();
-                !rel_str.starts_with(".oci")
+                !rel.starts_with(OCI_DIR)
             } else {
                 true
             }
         }) {
-            // Handle permission errors gracefully - skip and continue
-            let entry = match entry {
-                Ok(e) => e,
-                Err(_err) => {
-                    // Silently skip permission errors during prune
-                    continue;
-                }
-            };
-
-            if entry.file_type().is_file() {
-                let rel_path = entry
-                    .path()
-                    .strip_prefix(repo_root)
-                    .context("Path is outside repository")?;
-                let rel_path_str = rel_path.to_string_lossy().to_string();
-
-                // Skip if already in our prune list
-                if files_to_prune.iter().any(|(p, _, _)| p == &rel_path_str) {
-                    continue;
-                }
-
-                // Skip if in local index (we already checked those above)
-                if local_index.get(&rel_path_str)?.is_some() {
-                    continue;
-                }
-
-                // Check if file matches source ignore patterns
-                if !no_ignore && ignore::should_ignore(rel_path, source_patterns) {
-                    files_to_prune.push((rel_path_str.clone(), "ignored".to_string(), false));
-                }
-
-                // Check if file matches local ignore patterns (if --ignored flag is present)
-                if ignored && ignore::should_ignore(rel_path, local_patterns) {
-                    // Only add if not already in list
-                    if !files_to_prune.iter().any(|(p, _, _)| p == &rel_path_str) {
-                        files_to_prune.push((rel_path_str, "ignored".to_string(), false));
-                    }
-                }
+        // Handle permission errors gracefully - skip and continue
+        let entry = match entry {
+            Ok(e) => e,
+            Err(_err) => {
+                // Silently skip permission errors
+                continue;
+            }
+        };
+        
+        if entry.file_type().is_file() {
+            let rel_path = entry.path().strip_prefix(repo_root)
+                .context("Path is outside repository")?;
+            let rel_path_str = rel_path.to_string_lossy().to_string();
+            
+            // Skip if already in our prune list
+            if files_to_prune.iter().any(|(p, _)| p == &rel_path_str) {
+                continue;
+            }
+            
+            // Skip if in local index (we already checked those above)
+            if local_index.get(&rel_path_str)?.is_some() {
+                continue;
+            }
+            
+            // Check if file matches local ignore patterns
+            if ignore::should_ignore(rel_path, &local_patterns) {
+                files_to_prune.push((rel_path_str, false));
             }
         }
     }
-
-    Ok(files_to_prune)
-}
-
-/// Execute the prune by moving files to pruneyard
-fn execute_prune(
-    files_to_prune: Vec<(String, String, bool)>,
-    local_index: &mut Index,
-    repo_root: &Path,
-) -> Result<(usize, usize, usize, u64)> {
-    let pruneyard_path = repo_root.join(OCI_DIR).join("pruneyard");
-    fs::create_dir_all(&pruneyard_path).context("Failed to create pruneyard directory")?;
-
+    
+    files_to_prune.retain(|(path, _)| scope.contains(scope_dir, path));
+    
+    if files_to_prune.is_empty() {
+        println!("No ignored files to prune");
+        return Ok(());
+    }
+    
+    let policy = Policy::load(repo_root)?;
+    let violations: Vec<_> = files_to_prune.iter()
+        .filter_map(|(path, _)| policy.check_prune(path))
+        .collect();
+    if dry_run {
+        let files: Vec<_> = files_to_prune.into_iter()
+            .map(|(path, in_index)| (path, "ignored".to_string(), in_index))
+            .collect();
+        return print_prune_report(&files, &local_index, None, repo_root, disposal, &violations);
+    }
+    enforce_policy(&violations, "Prune", override_policy)?;
+    
     let mut pruned_count = 0;
-    let mut duplicate_count = 0;
-    let mut ignored_count = 0;
     let mut total_bytes = 0u64;
-
-    // Move files to pruneyard
-    for (path, reason, in_index) in files_to_prune {
+    let mut total_allocated = 0u64;
+    
+    // Move files to the pruneyard (or the trash)
+    for (path, in_index) in files_to_prune {
         let source_file = repo_root.join(&path);
-        let dest_file = pruneyard_path.join(&path);
-
+        
         // Get file size before moving
         if let Ok(size) = file_utils::get_file_size(&source_file) {
             total_bytes += size;
+            total_allocated += file_utils::get_allocated_size(&source_file).ok().flatten().unwrap_or(size);
         }
-
-        // Create parent directories in pruneyard
-        if let Some(parent) = dest_file.parent() {
-            fs::create_dir_all(parent)
-                .context(format!("Failed to create directory: {}", parent.display()))?;
-        }
-
+        
         // Move the file
-        fs::rename(&source_file, &dest_file)
-            .context(format!("Failed to move file: {}", source_file.display()))?;
-
+        disposal.dispose(&source_file, &path)?;
+        
         // Remove empty parent directories
         dir_utils::remove_empty_parent_dirs(&source_file, repo_root)?;
-
+        
         // Remove from index if it was in the index
         if in_index {
-            local_index.remove(&path)?;
+            if let Some(entry) = local_index.get(&path)? {
+                local_index.remove_departed(vec![entry], "pruned")?;
+            }
         }
-
-        println!("Pruned ({}): {}", reason, path);
+        
+        println!("Pruned (ignored): {}", path);
         pruned_count += 1;
-
-        if reason == "duplicate" {
-            duplicate_count += 1;
-        } else if reason == "ignored" {
-            ignored_count += 1;
-        }
     }
+    
+    local_index.log_operation(&Operation::new(
+        config,
+        "prune --ignored",
+        format!("pruned {} ignored file(s) ({})", pruned_count, format_bytes(total_bytes)),
+    ))?;
+    local_index.save(repo_root)?;
 
-    Ok((pruned_count, duplicate_count, ignored_count, total_bytes))
-}
-
-/// Prune files that exist in another index
-pub fn prune(
-    source: Option<String>,
-    purge: bool,
-    restore: bool,
-    force: bool,
-    no_ignore: bool,
-    ignored: bool,
-) -> Result<()> {
-    let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
-
-    // Handle restore flag
-    if restore {
-        return prune_restore(&repo_root);
+    // Clean up any remaining empty directories
+    let empty_dirs_removed = dir_utils::remove_all_empty_dirs(repo_root)?;
+    
+    if pruned_count > 0 {
+        println!("Pruned {} ignored file(s) to {} ({}, {} on disk)",
+            pruned_count, disposal.destination(), format_bytes(total_bytes), format_bytes(total_allocated));
+        let mut hook_fields = serde_json::Map::new();
+        hook_fields.insert("command".to_string(), "prune --ignored".into());
+        hook_fields.insert("pruned".to_string(), pruned_count.into());
+        hook_fields.insert("duplicates".to_string(), 0.into());
+        hook_fields.insert("ignored".to_string(), pruned_count.into());
+        hook_fields.insert("bytes".to_string(), total_bytes.into());
+        hook_fields.insert("destination".to_string(), disposal.destination().to_string().into());
+        run_post_hook(repo_root, Hook::PostPrune, hook_fields);
+    } else {
+        println!("Pruned 0 file(s)");
     }
-
-    // Handle purge flag
-    if purge {
-        return prune_purge(&repo_root, force);
+    
+    if empty_dirs_removed > 0 {
+        println!("Removed {} empty director{}", empty_dirs_removed, if empty_dirs_removed == 1 { "y" } else { "ies" });
     }
+    
+    Ok(())
+}
 
-    // Check for pending changes in local index
-    if has_pending_changes(&repo_root)? {
-        bail!("Cannot prune: there are pending changes in the local index. Run 'oci status' to see changes.");
-    }
+/// Check if there are any pending changes in the repository
+fn has_pending_changes(repo_root: &Path) -> Result<bool> {
+    let index = Index::load(repo_root)?;
+    let patterns = ignore::load_patterns(repo_root)?;
+    let config = Config::load(repo_root)?;
+    let options = CaptureOptions::from_config(&config);
 
-    // If --ignored flag is present without a source, just prune local ignored files
-    if ignored && source.is_none() {
-        return prune_local_ignored_files(&repo_root);
-    }
+    // Use scanner to get filesystem state
+    let roots = Roots::from_config(repo_root.to_path_buf(), &config);
+    let scanner = FileScanner::new(roots.clone(), patterns);
+    let scan_result = scanner.scan_repository_filtered()?;
+    let fs_files = scan_result.tracked_files;
 
-    // Need source path for prune operation (unless only using --ignored)
-    let source_path = source.ok_or_else(|| {
-        anyhow::anyhow!(
-            "Source path is required for prune operation (unless using --ignored without source)"
-        )
-    })?;
+    // Get all indexed files
+    let indexed_files = index.get_dir_files_recursive("")?;
 
-    // Load local and source indices
-    let mut local_index = Index::load(&repo_root)?;
+    // Check for modified or added files
+    for fs_path in &fs_files {
+        let full_path = roots.full_path(fs_path);
 
-    let source_abs_path = if Path::new(&source_path).is_absolute() {
-        PathBuf::from(&source_path)
-    } else {
-        get_logical_current_dir()?.join(&source_path)
-    };
+        // Added, modified, or metadata drift
+        if file_status(index.get(fs_path)?.as_ref(), &full_path, options, config.change_detection)?.is_change() {
+            return Ok(true);
+        }
+    }
 
-    if !source_abs_path.exists() {
-        bail!("Source path does not exist: {}", source_abs_path.display());
+    // Check for deleted files
+    for entry in indexed_files {
+        if !fs_files.contains(&entry.path) {
+            return Ok(true);
+        }
     }
 
-    // Canonicalize both paths to compare them properly
-    let canonical_source = source_abs_path
-        .canonicalize()
-        .context("Failed to canonicalize source path")?;
-    let canonical_local = repo_root
-        .canonicalize()
-        .context("Failed to canonicalize local path")?;
+    Ok(false)
+}
 
-    if canonical_source == canonical_local {
-        bail!("Cannot prune using the same index as source and local");
+/// List all files sorted by size in descending order (largest first)
+pub fn hogs() -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let index = Index::load(&repo_root)?;
+    
+    // Get all files from the repository
+    let mut entries: Vec<_> = index.get_dir_files_recursive("")?;
+    
+    if entries.is_empty() {
+        println!("No files in index");
+        return Ok(());
     }
-
-    // Check for pending changes in source index
-    if has_pending_changes(&source_abs_path)? {
-        bail!(
-            "Cannot prune: there are pending changes in the source index at {}. Run 'oci status' in the source directory to see changes.",
-            source_abs_path.display()
+    
+    // Sort by size in descending order (largest first)
+    entries.sort_by_key(|e| std::cmp::Reverse(e.num_bytes));
+    
+    let display_ctx = DisplayContext::new(Roots::from_config(repo_root, &config), current_dir);
+    for entry in entries {
+        let display_path = display_ctx.make_relative(&entry.path)?;
+        let human_size = format_bytes(entry.num_bytes);
+        println!("{:>10} {:>15} {} {}", 
+            human_size,
+            entry.modified,
+            file_utils::display_hash(&entry),
+            display_path
         );
     }
+    
+    Ok(())
+}
 
-    let source_index = Index::load(&source_abs_path).context("Failed to load source index")?;
-
-    // Load source ignore patterns if not disabled
-    let source_patterns = if !no_ignore {
-        ignore::load_patterns(&source_abs_path)?
-    } else {
-        Vec::new()
-    };
+/// Indexed size and file count per subdirectory, like `du` but from the index
+pub fn du(path: Option<String>, recursive: bool, depth: Option<usize>) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let index = Index::load(&repo_root)?;
+    let roots = Roots::from_config(repo_root, &config);
+    let base = repo_relative_path(&roots, &current_dir, path.as_deref().unwrap_or("."))?;
+    let display_ctx = DisplayContext::new(roots, current_dir);
 
-    // Load local ignore patterns if --ignored flag is present
-    let local_patterns = if ignored {
-        ignore::load_patterns(&repo_root)?
-    } else {
-        Vec::new()
-    };
+    let entries = index.get_dir_files_recursive(&base)?;
+    if entries.is_empty() {
+        bail!("No indexed files under {}", path.as_deref().unwrap_or("."));
+    }
+    let depth = if recursive { None } else { Some(depth.unwrap_or(1)) };
+    for (dir, totals) in breakdown::rollup(&entries, &base, depth) {
+        let index_path = if dir.is_empty() { base.clone() } else { format!("{}{}", dir_prefix(&base), dir) };
+        let display_path = display_ctx.make_relative(&index_path)?;
+        let display_path = if display_path.is_empty() { "." } else { display_path.as_str() };
+        println!("{:>12} {:>8}  {}", display::format_size(totals.bytes), totals.files, display_path);
+    }
+    Ok(())
+}
 
-    // Find files to prune
-    let files_to_prune = find_files_to_prune(
-        &local_index,
-        &source_index,
-        &repo_root,
-        &source_patterns,
-        &local_patterns,
-        no_ignore,
-        ignored,
-    )?;
+/// `stats --top`: the largest files, and optionally the duplicate groups wasting the most space,
+/// queried from the index with a limit instead of loading every entry
+pub fn stats_top(limit: usize, duplicates: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let index = Index::load(&repo_root)?;
+    let display_ctx = DisplayContext::new(Roots::from_config(repo_root, &config), current_dir);
 
-    if files_to_prune.is_empty() {
-        println!("No files to prune");
+    let largest = index.largest_files(limit)?;
+    if largest.is_empty() {
+        println!("Index is empty");
         return Ok(());
     }
+    println!("Largest files:");
+    for entry in &largest {
+        println!("  {:>10}  {}", format_bytes(entry.num_bytes), display_ctx.make_relative(&entry.path)?);
+    }
 
-    // Execute prune
-    let (pruned_count, duplicate_count, ignored_count, total_bytes) =
-        execute_prune(files_to_prune, &mut local_index, &repo_root)?;
-
-    local_index.save(&repo_root)?;
+    if duplicates {
+        println!();
+        let groups = index.largest_duplicate_groups(limit)?;
+        if groups.is_empty() {
+            println!("No duplicate files");
+            return Ok(());
+        }
+        println!("Largest duplicate groups (by space wasted):");
+        for (hash, num_bytes, copies) in groups {
+            println!(
+                "  {:>10}  {} copies of {} ({})",
+                format_bytes(num_bytes * (copies as u64 - 1)),
+                copies,
+                format_bytes(num_bytes),
+                display::highlight_hash(&hash)
+            );
+            let mut paths: Vec<String> = index.find_by_hash(&hash)?.into_iter().map(|entry| entry.path).collect();
+            paths.sort();
+            for path in paths {
+                println!("              {}", display_ctx.make_relative(&path)?);
+            }
+        }
+    }
 
-    // Clean up any remaining empty directories
-    let empty_dirs_removed = dir_utils::remove_all_empty_dirs(&repo_root)?;
+    Ok(())
+}
 
-    if pruned_count > 0 {
-        println!(
-            "Pruned {} file(s) to .oci/pruneyard/ ({} duplicates, {} ignored, {})",
-            pruned_count, duplicate_count, ignored_count, format_bytes(total_bytes)
-        );
-    } else {
-        println!("Pruned 0 file(s)");
+/// Resolve `.` and `..` components lexically, without touching the filesystem
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            }
+            other => resolved.push(other),
+        }
     }
+    resolved
+}
 
-    if empty_dirs_removed > 0 {
-        println!(
-            "Removed {} empty director{}",
-            empty_dirs_removed,
-            if empty_dirs_removed == 1 { "y" } else { "ies" }
-        );
-    }
+/// Whether an index path is the repository's own `.oci` directory or inside it
+fn is_oci_internal(rel_path: &str) -> bool {
+    rel_path.split('/').next() == Some(OCI_DIR)
+}
 
-    Ok(())
+/// Convert a user-supplied path (relative to the current directory) to an index path
+/// without requiring it to exist on disk
+fn repo_relative_path(roots: &Roots, current_dir: &Path, path: &str) -> Result<String> {
+    let resolved = normalize_path(&current_dir.join(path));
+    roots.require_index_path(&roots.locate(&resolved))
 }
 
-/// Reset the index (clear all entries)
-pub fn reset(force: bool) -> Result<()> {
+/// Tag a path with a storage class, clear its tag, or list tags and rules
+pub fn tier(path: Option<String>, class: Option<String>, clear: bool) -> Result<()> {
     let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let mut index = Index::load(&repo_root)?;
     
-    // Ask for confirmation unless --force is used
-    if !force {
-        println!("This will remove all entries from the index.");
-        print!("Are you sure you want to continue? (y/N): ");
-        std::io::Write::flush(&mut std::io::stdout())?;
-        
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        
-        let confirmed = input.trim().eq_ignore_ascii_case("y") || input.trim().eq_ignore_ascii_case("yes");
-        
-        if !confirmed {
-            println!("Reset cancelled");
+    let Some(path) = path else {
+        let tags = index.storage_class_tags()?;
+        if tags.is_empty() && config.tier_rules.is_empty() {
+            println!("No storage classes assigned");
             return Ok(());
         }
+        for (tag_path, class) in tags {
+            let display_path = if tag_path.is_empty() { ".".to_string() } else { tag_path };
+            println!("{:<8} {}", class, display_path);
+        }
+        for rule in &config.tier_rules {
+            println!("{:<8} {} (rule)", rule.class, rule.patterns.join(", "));
+        }
+        return Ok(());
+    };
+    
+    let _lock = lock_repo(&repo_root, &config)?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let rel_path = repo_relative_path(&roots, &current_dir, &path)?;
+    
+    if clear {
+        if index.clear_storage_class(&rel_path)? {
+            index.log_operation(&Operation::new(&config, "tier", format!("cleared storage class of {}", rel_path)))?;
+            println!("Cleared storage class: {}", path);
+        } else {
+            println!("No storage class assigned: {}", path);
+        }
+        return Ok(());
     }
     
-    let mut index = Index::load(&repo_root)?;
-    index.clear()?;
+    let Some(class) = class else {
+        let tags = index.storage_class_tags()?;
+        match tiering::resolve_class(&rel_path, &tags, &config.tier_rules) {
+            Some(class) => println!("{} {}", class, path),
+            None => println!("unclassified {}", path),
+        }
+        return Ok(());
+    };
+    
+    let class: StorageClass = class.parse()?;
+    if !roots.full_path(&rel_path).exists() && index.get_dir_files_recursive(&rel_path)?.is_empty()
+        && index.get(&rel_path)?.is_none()
+    {
+        eprintln!(
+            "Note: Nothing is indexed at {} yet{}",
+            path,
+            did_you_mean(&roots, &index, &current_dir, &rel_path)?
+        );
+    }
+    index.set_storage_class(&rel_path, class)?;
+    index.log_operation(&Operation::new(&config, "tier", format!("tagged {} as {}", rel_path, class)))?;
+    index.save(&repo_root)?;
+    println!("Tagged {} as {}", path, class);
     
-    println!("Reset index (removed all entries)");
     Ok(())
 }
 
-/// Remove the index (deinitialize)
-pub fn deinit(force: bool) -> Result<()> {
+/// Add tags to a file or directory (a directory's tags apply to everything below it)
+pub fn tag_add(path: String, tags: Vec<String>) -> Result<()> {
+    for tag in &tags {
+        crate::tags::validate(tag)?;
+    }
     let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
-    let oci_dir = repo_root.join(OCI_DIR);
-    
-    // Ask for confirmation unless --force is used
-    if !force {
-        println!("This will permanently delete the index at {}", oci_dir.display());
-        print!("Are you sure you want to continue? (y/N): ");
-        std::io::Write::flush(&mut std::io::stdout())?;
-        
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        
-        let confirmed = input.trim().eq_ignore_ascii_case("y") || input.trim().eq_ignore_ascii_case("yes");
-        
-        if !confirmed {
-            println!("Deinit cancelled");
-            return Ok(());
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let mut index = Index::load(&repo_root)?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let rel_path = repo_relative_path(&roots, &current_dir, &path)?;
+
+    if index.get(&rel_path)?.is_none() && index.get_dir_files_recursive(&rel_path)?.is_empty() {
+        eprintln!(
+            "Note: Nothing is indexed at {} yet{}",
+            path,
+            did_you_mean(&roots, &index, &current_dir, &rel_path)?
+        );
+    }
+    let mut added = Vec::new();
+    for tag in &tags {
+        if index.add_tag(&rel_path, tag)? {
+            added.push(tag.as_str());
         }
     }
-    
-    fs::remove_dir_all(&oci_dir)
-        .context("Failed to remove .oci directory")?;
-    
-    println!("Deinitialized oci index at {}", oci_dir.display());
+    if added.is_empty() {
+        println!("{} is already tagged {}", path, tags.join(", "));
+        return Ok(());
+    }
+    index.log_operation(&Operation::new(&config, "tag", format!("tagged {} with {}", rel_path, added.join(", "))))?;
+    index.save(&repo_root)?;
+    println!("Tagged {} with {}", path, added.join(", "));
     Ok(())
 }
 
-/// Show index statistics
-pub fn stats() -> Result<()> {
+/// Remove tags from a file or directory
+pub fn tag_remove(path: String, tags: Vec<String>) -> Result<()> {
     let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    let mut index = Index::load(&repo_root)?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let rel_path = repo_relative_path(&roots, &current_dir, &path)?;
+
+    let mut removed = Vec::new();
+    for tag in &tags {
+        if index.remove_tag(&rel_path, tag)? {
+            removed.push(tag.as_str());
+        } else {
+            println!("{} is not tagged {}", path, tag);
+        }
+    }
+    if removed.is_empty() {
+        return Ok(());
+    }
+    index.log_operation(&Operation::new(&config, "tag", format!("removed {} from {}", removed.join(", "), rel_path)))?;
+    index.save(&repo_root)?;
+    println!("Removed {} from {}", removed.join(", "), path);
+    Ok(())
+}
+
+/// List the tags of a path (including those of the directories above it), or every tagged path
+pub fn tag_list(path: Option<String>) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
     let index = Index::load(&repo_root)?;
-    
-    // Get all files from the index
-    let all_files = index.get_dir_files_recursive("")?;
-    
-    if all_files.is_empty() {
-        println!("Index is empty");
+    let all = index.tags()?;
+
+    let Some(path) = path else {
+        if all.is_empty() {
+            println!("No tags");
+            return Ok(());
+        }
+        let mut by_path: std::collections::BTreeMap<&str, Vec<&str>> = std::collections::BTreeMap::new();
+        for (tag_path, tag) in &all {
+            by_path.entry(tag_path).or_default().push(tag);
+        }
+        for (tag_path, tags) in by_path {
+            let display_path = if tag_path.is_empty() { "." } else { tag_path };
+            println!("{}  {}", display_path, tags.join(", "));
+        }
+        return Ok(());
+    };
+
+    let roots = Roots::from_config(repo_root, &config);
+    let rel_path = repo_relative_path(&roots, &get_logical_current_dir()?, &path)?;
+    let tags = Tags::new(all);
+    let applying = tags.of(&rel_path);
+    if applying.is_empty() {
+        println!("{} has no tags", path);
         return Ok(());
     }
-    
-    // Calculate statistics
-    let total_files = all_files.len();
-    let total_size: u64 = all_files.iter().map(|f| f.num_bytes).sum();
-    
-    // Group files by hash to find unique hashes and duplicates
-    let mut hash_map: std::collections::HashMap<String, Vec<&crate::index::FileEntry>> = 
-        std::collections::HashMap::new();
-    
-    for entry in &all_files {
-        hash_map.entry(entry.sha256.clone())
-            .or_default()
-            .push(entry);
+    for tag in applying {
+        // Name the directory a tag comes from when it is not on the path itself
+        match tags.origin(&rel_path, tag) {
+            Some(origin) if origin != rel_path => {
+                println!("{}  (from {})", tag, if origin.is_empty() { "." } else { origin })
+            }
+            _ => println!("{}", tag),
+        }
     }
-    
-    let unique_hashes = hash_map.len();
-    
-    // Calculate duplicate files (count all files in groups with >1 file)
-    let duplicate_files: usize = hash_map.values()
-        .filter(|files| files.len() > 1)
-        .map(|files| files.len())
-        .sum();
-    
-    // Calculate unique size (sum of sizes for one file per hash)
-    let unique_size: u64 = hash_map.values()
-        .map(|files| files[0].num_bytes)
-        .sum();
-    
-    // Calculate wasted space (duplicates)
-    let wasted_space: u64 = hash_map.values()
-        .filter(|files| files.len() > 1)
-        .map(|files| {
-            let file_size = files[0].num_bytes;
-            file_size * (files.len() as u64 - 1)
-        })
-        .sum();
-    
-    // Calculate storage efficiency (how much space is actual unique content)
-    let storage_efficiency = if total_size > 0 {
-        (unique_size as f64 / total_size as f64) * 100.0
+    Ok(())
+}
+
+/// Print the value of a config key
+pub fn config_get(key: String, global: bool) -> Result<()> {
+    let value = if global {
+        UserConfig::load()?.get(&key)
     } else {
-        100.0
+        let repo_root = find_repo_root()?;
+        check_version(&repo_root)?.get(&key)
     };
-    
-    // Display statistics
-    println!("Index Statistics:");
-    println!("  Total files: {}", total_files);
-    println!("  Total size: {} bytes ({:.2} MB)", total_size, total_size as f64 / 1_048_576.0);
-    println!("  Unique hashes: {}", unique_hashes);
-    println!("  Duplicate files: {}", duplicate_files);
-    
-    if duplicate_files > 0 {
-        let duplicate_groups = hash_map.values().filter(|files| files.len() > 1).count();
-        println!("  Duplicate groups: {}", duplicate_groups);
-        println!("  Wasted space: {} bytes ({:.2} MB)", wasted_space, wasted_space as f64 / 1_048_576.0);
+    match value {
+        Some(value) => println!("{}", value),
+        None => bail!("Not set: {}", key),
+    }
+    Ok(())
+}
+
+/// Validate a config value and save it
+pub fn config_set(key: String, value: String, global: bool) -> Result<()> {
+    if global {
+        let mut user = UserConfig::load()?;
+        user.set(&key, &value)?;
+        user.save()?;
+        println!("{}={}", key, user.get(&key).unwrap_or_default());
+        return Ok(());
     }
     
-    println!("  Storage efficiency: {:.2}%", storage_efficiency);
-    
+    let repo_root = find_repo_root()?;
+    let mut config = check_version(&repo_root)?;
+    let _lock = lock_repo(&repo_root, &config)?;
+    config.set(&key, &value)?;
+    config.save(&repo_root)?;
+    println!("{}={}", key, config.get(&key).unwrap_or_default());
     Ok(())
 }
 
-/// Prune files matching local ignore patterns
-fn prune_local_ignored_files(repo_root: &Path) -> Result<()> {
-    let mut local_index = Index::load(repo_root)?;
-    let local_patterns = ignore::load_patterns(repo_root)?;
-    
-    if local_patterns.is_empty() {
-        println!("No ignore patterns defined in local ignore");
+/// Print every config key and its value
+pub fn config_list(global: bool) -> Result<()> {
+    if global {
+        print!("{}", UserConfig::load()?.contents());
         return Ok(());
     }
     
-    // Find files to prune - store as (path, in_index)
-    let mut files_to_prune: Vec<(String, bool)> = Vec::new();
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    print!("{}", config.contents());
+    Ok(())
+}
+
+/// Run one of the index audits
+pub fn audit(departed: bool) -> Result<()> {
+    if !departed {
+        bail!("No audit selected (available: --departed)");
+    }
     
-    // Check files in the index
-    let local_files = local_index.get_dir_files_recursive("")?;
-    for local_entry in &local_files {
-        let path = Path::new(&local_entry.path);
-        if ignore::should_ignore(path, &local_patterns) {
-            files_to_prune.push((local_entry.path.clone(), true));
-        }
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    let index = Index::load(&repo_root)?;
+    
+    let (departures, unmounted) = audit::departed(&index, &repo_root)?;
+    if departures.is_empty() {
+        println!("No departed content");
+    } else {
+        println!("Departed content ({} file(s)):", departures.len());
     }
     
-    // Check files on filesystem that aren't in the index
-    for entry in WalkDir::new(repo_root).into_iter()
-        .filter_entry(|e| {
-            // Don't walk into .oci directory
-            if let Ok(rel) = e.path().strip_prefix(repo_root) {
-                let rel_str = rel.to_string_lossy();
-                !rel_str.starts_with(".oci")
-            } else {
-                true
-            }
-        }) {
-        // Handle permission errors gracefully - skip and continue
-        let entry = match entry {
-            Ok(e) => e,
-            Err(_err) => {
-                // Silently skip permission errors
-                continue;
+    let (mut moved, mut pruned, mut elsewhere, mut lost, mut lost_bytes) = (0, 0, 0, 0, 0);
+    for departure in &departures {
+        let path = &departure.tombstone.path;
+        match &departure.whereabouts {
+            Whereabouts::Moved(new_path) => {
+                moved += 1;
+                println!("  moved      {} -> {}", path, new_path);
             }
-        };
-        
-        if entry.file_type().is_file() {
-            let rel_path = entry.path().strip_prefix(repo_root)
-                .context("Path is outside repository")?;
-            let rel_path_str = rel_path.to_string_lossy().to_string();
-            
-            // Skip if already in our prune list
-            if files_to_prune.iter().any(|(p, _)| p == &rel_path_str) {
-                continue;
+            Whereabouts::Pruneyard(location) => {
+                pruned += 1;
+                println!("  pruneyard  {} -> {}", path, location.display());
             }
-            
-            // Skip if in local index (we already checked those above)
-            if local_index.get(&rel_path_str)?.is_some() {
-                continue;
+            Whereabouts::Elsewhere(location) => {
+                elsewhere += 1;
+                println!("  elsewhere  {} -> {}", path, location.display());
             }
-            
-            // Check if file matches local ignore patterns
-            if ignore::should_ignore(rel_path, &local_patterns) {
-                files_to_prune.push((rel_path_str, false));
+            Whereabouts::Lost => {
+                lost += 1;
+                lost_bytes += departure.tombstone.num_bytes;
+                println!("  LOST       {} ({}, {} {})",
+                    path,
+                    format_bytes(departure.tombstone.num_bytes),
+                    departure.tombstone.reason,
+                    oplog::format_timestamp(departure.tombstone.removed)
+                );
             }
         }
     }
     
-    if files_to_prune.is_empty() {
-        println!("No ignored files to prune");
+    if !departures.is_empty() {
+        println!("Summary: {} moved, {} in the pruneyard, {} elsewhere, {} lost ({})",
+            moved, pruned, elsewhere, lost, format_bytes(lost_bytes));
+    }
+    if unmounted > 0 {
+        println!("Note: {} registered repositor{} not mounted and could not be searched",
+            unmounted, if unmounted == 1 { "y is" } else { "ies are" });
+    }
+    
+    if lost > 0 {
+        bail!("{} departed file(s) were not found anywhere", lost);
+    }
+    Ok(())
+}
+
+/// Run one of the index reports
+pub fn report(tiering: bool) -> Result<()> {
+    if !tiering {
+        bail!("No report selected (available: --tiering)");
+    }
+    
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let index = Index::load(&repo_root)?;
+    
+    let entries = index.get_dir_files_recursive("")?;
+    let tags = index.storage_class_tags()?;
+    let report = tiering::build_report(
+        &entries, &tags, &config.tier_rules, file_utils::now_millis(), config.tier_recent_days
+    );
+    
+    println!("Storage tiering:");
+    for class in StorageClass::ALL {
+        let totals = report.totals.get(&class).cloned().unwrap_or_default();
+        println!("  {:<13} {:>10} in {} file(s)", format!("{}:", class), format_bytes(totals.bytes), totals.files);
+    }
+    println!("  {:<13} {:>10} in {} file(s)", "unclassified:", format_bytes(report.unclassified.bytes), report.unclassified.files);
+    
+    if report.violations.is_empty() {
+        println!("No tiering violations");
         return Ok(());
     }
     
-    // Create pruneyard directory
-    let pruneyard_path = repo_root.join(OCI_DIR).join("pruneyard");
-    fs::create_dir_all(&pruneyard_path)
-        .context("Failed to create pruneyard directory")?;
+    println!();
+    println!("Violations (cold/offsite files modified in the last {} day(s)):", config.tier_recent_days);
+    let display_ctx = DisplayContext::new(Roots::from_config(repo_root, &config), current_dir);
+    for violation in &report.violations {
+        println!("  {:<8} {} (modified {} day(s) ago)",
+            violation.class,
+            display_ctx.make_relative(&violation.path)?,
+            violation.age_days
+        );
+    }
     
-    let mut pruned_count = 0;
-    let mut total_bytes = 0u64;
+    Ok(())
+}
+
+/// Show the log of index mutations and who performed them
+pub fn log(limit: Option<usize>) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    check_version(&repo_root)?;
+    let index = Index::load(&repo_root)?;
     
-    // Move files to pruneyard
-    for (path, in_index) in files_to_prune {
-        let source_file = repo_root.join(&path);
-        let dest_file = pruneyard_path.join(&path);
-        
-        // Get file size before moving
-        if let Ok(size) = file_utils::get_file_size(&source_file) {
-            total_bytes += size;
+    let operations = index.operations(limit)?;
+    if operations.is_empty() {
+        println!("No operations logged");
+        return Ok(());
+    }
+    
+    for op in operations {
+        println!("{}  {}  {}: {}",
+            oplog::format_timestamp(op.timestamp),
+            op.actor(),
+            op.command,
+            op.summary
+        );
+    }
+    
+    Ok(())
+}
+
+/// Register a repository on this machine, unregister it, or list registered repositories
+pub fn repos(path: Option<String>, remove: bool) -> Result<()> {
+    let Some(path) = path else {
+        let repos = registry::load()?;
+        if repos.is_empty() {
+            println!("No repositories registered");
         }
-        
-        // Create parent directories in pruneyard
-        if let Some(parent) = dest_file.parent() {
-            fs::create_dir_all(parent)
-                .context(format!("Failed to create directory: {}", parent.display()))?;
+        for repo in repos {
+            let state = if registry::is_available(&repo) { "" } else { " (not mounted)" };
+            println!("{}{}", repo.display(), state);
         }
-        
-        // Move the file
-        fs::rename(&source_file, &dest_file)
-            .context(format!("Failed to move file: {}", source_file.display()))?;
-        
-        // Remove empty parent directories
-        dir_utils::remove_empty_parent_dirs(&source_file, repo_root)?;
-        
-        // Remove from index if it was in the index
-        if in_index {
-            local_index.remove(&path)?;
+        return Ok(());
+    };
+    
+    let path = normalize_path(&get_logical_current_dir()?.join(path));
+    if remove {
+        if !registry::unregister(&path)? {
+            bail!("Repository is not registered: {}", path.display());
         }
-        
-        println!("Pruned (ignored): {}", path);
-        pruned_count += 1;
+        println!("Unregistered {}", path.display());
+        return Ok(());
     }
     
-    local_index.save(repo_root)?;
+    if !registry::is_available(&path) {
+        bail!("Not an oci repository: {} (run 'oci init' there first)", path.display());
+    }
+    if registry::register(&path)? {
+        println!("Registered {}", path.display());
+    } else {
+        println!("Already registered: {}", path.display());
+    }
+    Ok(())
+}
 
-    // Clean up any remaining empty directories
-    let empty_dirs_removed = dir_utils::remove_all_empty_dirs(repo_root)?;
+/// Register a root directory, remove one, or list the roots of a multi-root index
+pub fn root(name: Option<String>, path: Option<String>, remove: bool) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let mut config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
     
-    if pruned_count > 0 {
-        println!("Pruned {} ignored file(s) to .oci/pruneyard/ ({})", pruned_count, format_bytes(total_bytes));
-    } else {
-        println!("Pruned 0 file(s)");
+    let Some(name) = name else {
+        if config.roots.is_empty() {
+            println!("No roots registered (single-root index)");
+        }
+        for root in &config.roots {
+            println!("{:<12} {}", root.name, root.path.display());
+        }
+        return Ok(());
+    };
+    
+    let _lock = lock_repo(&repo_root, &config)?;
+    let mut index = Index::load(&repo_root)?;
+    
+    if remove {
+        let position = config.roots.iter().position(|root| root.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No root named '{}'", name))?;
+        let root = config.roots.remove(position);
+        config.save(&repo_root)?;
+        
+        // Drop the root's entries, since its files are no longer part of the index
+        let entries = index.get_dir_files_recursive(&root.name)?;
+        index.remove_many(entries.iter().map(|entry| &entry.path))?;
+        let marker = root.path.join(roots::ROOT_MARKER);
+        if marker.is_file() {
+            fs::remove_file(&marker).context("Failed to remove root marker")?;
+        }
+        index.log_operation(&Operation::new(
+            &config,
+            "root --remove",
+            format!("removed root {} ({} entries dropped)", root.name, entries.len()),
+        ))?;
+        index.save(&repo_root)?;
+        println!("Removed root {} ({} file(s) dropped from the index)", root.name, entries.len());
+        return Ok(());
     }
     
-    if empty_dirs_removed > 0 {
-        println!("Removed {} empty director{}", empty_dirs_removed, if empty_dirs_removed == 1 { "y" } else { "ies" });
+    let Some(path) = path else {
+        let root = config.roots.iter().find(|root| root.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No root named '{}'", name))?;
+        println!("{}", root.path.display());
+        return Ok(());
+    };
+    
+    if config.roots.iter().any(|root| root.name == name) {
+        bail!("A root named '{}' already exists", name);
+    }
+    if config.roots.is_empty() && !index.get_dir_files_recursive("")?.is_empty() {
+        bail!("Cannot add a root to an index that already has entries; run 'oci reset' first");
+    }
+    
+    let root_path = normalize_path(&current_dir.join(&path));
+    if !root_path.is_dir() {
+        bail!("Root path is not a directory: {}", root_path.display());
     }
+    let root = crate::roots::Root::parse(&name, &root_path.to_string_lossy())?;
+    if config.roots.iter().any(|other| other.path.starts_with(&root.path) || root.path.starts_with(&other.path)) {
+        bail!("Root {} overlaps an existing root", root.path.display());
+    }
+    
+    roots::write_marker(&root.path, &repo_root)?;
+    println!("Added root {} at {}", root.name, root.path.display());
+    index.log_operation(&Operation::new(&config, "root", format!("added root {} at {}", root.name, root.path.display())))?;
+    config.roots.push(root);
+    config.save(&repo_root)?;
+    index.save(&repo_root)?;
     
     Ok(())
 }
 
-/// Check if a file should be updated in the index
-/// Returns true if the file is new or has changed (size or modified time differ)
-fn should_update_file(index: &Index, file_path: &Path, rel_path: &str) -> Result<bool> {
-    if let Some(entry) = index.get(rel_path)? {
-        // File exists in index - check if it has changed
-        file_utils::has_changed(&entry, file_path)
-    } else {
-        // File not in index - needs to be added
-        Ok(true)
+/// Re-hash every fully hashed entry and compare it with the index
+/// Successful runs are recorded in the op log (policies can require a recent verify before a purge).
+/// With `repair`, damaged and missing files are restored from their directories' recovery sets.
+pub fn verify(all_repos: bool, manifest: Option<String>, index_only: bool, io_limit: Option<String>, repair: bool) -> Result<()> {
+    let budget = IoBudget::new(io_limit.map(|limit| parse_size("--io-limit", &limit)).transpose()?);
+    if all_repos {
+        return verify_all_repos(&budget);
+    }
+    if let Some(manifest) = manifest {
+        return verify_against_manifest(&manifest, index_only, &budget);
+    }
+    
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let display_ctx = DisplayContext::new(Roots::from_config(repo_root.clone(), &config), current_dir);
+    
+    let mut damaged = Vec::new();
+    let summary = verify::verify_repo(&repo_root, &budget, true, |finding, path, _| {
+        let display_path = display_ctx.make_relative(path)?;
+        match finding {
+            Finding::Missing => println!("Missing: {}", display_path),
+            Finding::Mismatch => println!("Mismatch: {}", display_path),
+            Finding::PermissionDenied => warn!("Skipping file (permission denied): {}", display_path),
+            Finding::AttributesChanged => println!("Attributes changed: {}", display_path),
+        }
+        if matches!(finding, Finding::Missing | Finding::Mismatch) {
+            damaged.push(path.to_string());
+        }
+        Ok(())
+    })?;
+    
+    println!("Verified {} file(s): {}", summary.verified + summary.mismatched + summary.missing, summary.describe());
+    if summary.bytes > 0 {
+        println!("{}", progress::throughput(summary.bytes, summary.elapsed));
+    }
+    if summary.pending > 0 {
+        println!("Skipped {} file(s) pending a full hash", summary.pending);
+    }
+    
+    if summary.failed() && repair {
+        let repaired = repair_from_parity(&repo_root, &config, &display_ctx, &damaged)?;
+        println!("Repaired {} of {} file(s)", repaired, damaged.len());
+        if repaired < damaged.len() {
+            bail!("Repair failed: {} file(s) still differ from the index", damaged.len() - repaired);
+        }
+    } else if summary.failed() {
+        bail!("Verification failed: {} file(s) differ from the index", summary.mismatched + summary.missing);
     }
+    
+    Ok(())
 }
 
+/// Restore damaged or missing files (index paths) with par2 from their directories' recovery sets,
+/// returning how many now match the index again. A set is only used if every file it protects
+/// still has the digest it was made from: par2 repairs every file of a set that differs from it,
+/// so an outdated set would turn files updated since then back into their old contents.
+fn repair_from_parity(repo_root: &Path, config: &Config, display_ctx: &DisplayContext, damaged: &[String]) -> Result<usize> {
+    let _lock = lock_repo(repo_root, config)?;
+    let roots = Roots::from_config(repo_root.to_path_buf(), config);
+    let mut index = Index::load(repo_root)?;
+    let par2 = Par2::new(config);
 
-/// Check if there are any pending changes in the repository
-fn has_pending_changes(repo_root: &Path) -> Result<bool> {
-    let index = Index::load(repo_root)?;
-    let patterns = ignore::load_patterns(repo_root)?;
+    let mut by_dir: std::collections::BTreeMap<&str, Vec<&str>> = std::collections::BTreeMap::new();
+    for path in damaged {
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        by_dir.entry(dir).or_default().push(path);
+    }
 
-    // Use scanner to get filesystem state
-    let scanner = FileScanner::new(repo_root.to_path_buf(), patterns);
-    let scan_result = scanner.scan_repository_filtered(false)?;
-    let fs_files = scan_result.tracked_files;
+    let mut repaired = 0;
+    for (dir, paths) in by_dir {
+        let set_dir = parity::set_dir(repo_root, dir);
+        let display_dir = Some(display_ctx.make_relative(dir)?).filter(|dir| !dir.is_empty()).unwrap_or_else(|| ".".to_string());
+        if !parity::exists(&set_dir) {
+            println!("No recovery set for {} (make one with 'oci parity')", display_dir);
+            continue;
+        }
+        let recorded = parity::recorded_digests(&set_dir)?;
+        let outdated = recorded.iter().any(|(name, sha256)| {
+            let path = if dir.is_empty() { name.clone() } else { format!("{}/{}", dir, name) };
+            !matches!(index.get(&path), Ok(Some(entry)) if entry.sha256 == *sha256)
+        });
+        if outdated {
+            println!("The recovery set for {} is older than its files' entries (rebuild it with 'oci parity')", display_dir);
+            continue;
+        }
 
-    // Get all indexed files
-    let indexed_files = index.get_dir_files_recursive("")?;
+        let base = roots.full_path(dir);
+        if !par2.repair(&set_dir, &base)? {
+            println!("Not enough recovery data left to repair {}", display_dir);
+            continue;
+        }
+        for path in paths {
+            let Some(entry) = index.get(path)? else {
+                continue;
+            };
+            let full_path = roots.full_path(path);
+            if verify::check_file(&full_path, &entry.sha256, |_| {})?.is_some() {
+                println!("Could not repair: {}", display_ctx.make_relative(path)?);
+                continue;
+            }
+            // par2 rewrote the file; give it back the modified time its entry has, so update sees it as unchanged
+            let modified = std::time::UNIX_EPOCH + Duration::from_millis(entry.modified);
+            if let Err(e) = fs::File::options().write(true).open(file_utils::long_path(&full_path)).and_then(|file| file.set_modified(modified)) {
+                warn!("Failed to restore the modified time of {}: {}", full_path.display(), e);
+            }
+            println!("Repaired: {}", display_ctx.make_relative(path)?);
+            repaired += 1;
+        }
+    }
 
-    // Check for modified or added files
-    for fs_path in &fs_files {
-        let full_path = repo_root.join(fs_path);
+    index.log_operation(&Operation::new(config, "verify --repair", format!("repaired {} of {} file(s)", repaired, damaged.len())))?;
+    index.save(repo_root)?;
+    Ok(repaired)
+}
 
-        if let Some(entry) = index.get(fs_path)? {
-            // File exists in index - check if modified
-            if file_utils::has_changed(&entry, &full_path)? {
-                return Ok(true);
-            }
-        } else {
-            // File not in index - added
-            return Ok(true);
+/// Check the tree (or the index) against a checksum file written by another tool. Its paths are
+/// taken relative to the current directory, as `sha256sum -c` does.
+fn verify_against_manifest(manifest: &str, index_only: bool, budget: &IoBudget) -> Result<()> {
+    let repo_root = find_repo_root()?;
+    let config = check_version(&repo_root)?;
+    let current_dir = get_logical_current_dir()?;
+    let roots = Roots::from_config(repo_root.clone(), &config);
+    let manifest = manifest::load(&current_dir.join(manifest))?;
+    let base = repo_relative_path(&roots, &current_dir, ".")?;
+    let listed = manifest.entries.iter()
+        .map(|(path, hex)| {
+            let index_path = repo_relative_path(&roots, &current_dir, path)
+                .with_context(|| format!("The manifest lists {}, which is outside the repository", path))?;
+            Ok((index_path, hex.clone()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let display_ctx = DisplayContext::new(roots, current_dir);
+
+    let (summary, unlisted) = verify::verify_manifest(&repo_root, manifest.algorithm, &listed, &base, index_only, budget, true, |finding, path, _| {
+        let display_path = display_ctx.make_relative(path)?;
+        match finding {
+            Finding::Missing => println!("Missing: {}", display_path),
+            Finding::Mismatch => println!("Mismatch: {}", display_path),
+            Finding::PermissionDenied => warn!("Skipping file (permission denied): {}", display_path),
+            Finding::AttributesChanged => println!("Attributes changed: {}", display_path),
         }
+        Ok(())
+    })?;
+    for path in &unlisted {
+        println!("Not in manifest: {}", display_ctx.make_relative(path)?);
     }
 
-    // Check for deleted files
-    for entry in indexed_files {
-        if !fs_files.contains(&entry.path) {
-            return Ok(true);
+    println!(
+        "Checked {} of {} file(s) in the manifest ({}): {}",
+        summary.verified + summary.mismatched + summary.missing,
+        listed.len(),
+        manifest.algorithm,
+        summary.describe()
+    );
+    if summary.bytes > 0 {
+        println!("{}", progress::throughput(summary.bytes, summary.elapsed));
+    }
+    if summary.pending > 0 {
+        match manifest.algorithm {
+            Algorithm::Sha256 => println!("Skipped {} file(s) pending a full hash", summary.pending),
+            other => println!("Skipped {} file(s) without a recorded {} digest (see the digests key)", summary.pending, other),
         }
     }
+    if !unlisted.is_empty() {
+        println!("{} indexed file(s) are not in the manifest", unlisted.len());
+    }
 
-    Ok(false)
+    if summary.failed() {
+        bail!("Verification failed: {} file(s) differ from the manifest", summary.mismatched + summary.missing);
+    }
+    Ok(())
 }
 
-/// List all files sorted by size in descending order (largest first)
-pub fn hogs() -> Result<()> {
-    let repo_root = find_repo_root()?;
-    check_version(&repo_root)?;
-    let current_dir = get_logical_current_dir()?;
-    let index = Index::load(&repo_root)?;
-    
-    // Get all files from the repository
-    let mut entries: Vec<_> = index.get_dir_files_recursive("")?;
-    
-    if entries.is_empty() {
-        println!("No files in index");
-        return Ok(());
+/// Verify every registered repository that is mounted, one volume per thread
+fn verify_all_repos(budget: &IoBudget) -> Result<()> {
+    let repos = registry::load()?;
+    if repos.is_empty() {
+        bail!("No repositories registered (register one with: oci repos <path>)");
     }
+    let (mounted, unmounted): (Vec<_>, Vec<_>) = repos.into_iter().partition(|repo| registry::is_available(repo));
+    let groups = verify::group_by_volume(mounted.clone());
+    println!(
+        "Verifying {} repositor{} on {} volume(s){}",
+        mounted.len(),
+        if mounted.len() == 1 { "y" } else { "ies" },
+        groups.len(),
+        if unmounted.is_empty() { String::new() } else { format!(" ({} not mounted)", unmounted.len()) }
+    );
     
-    // Sort by size in descending order (largest first)
-    entries.sort_by(|a, b| b.num_bytes.cmp(&a.num_bytes));
+    // Volumes are handed out to at most `jobs` workers
+    let workers = UserConfig::load()?.jobs().min(groups.len());
+    let queue = std::sync::Mutex::new(groups);
+    let results: Vec<(PathBuf, Result<VerifySummary>)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers)
+            .map(|_| scope.spawn(|| {
+                let next_group = || queue.lock().unwrap_or_else(|e| e.into_inner()).pop();
+                std::iter::from_fn(next_group)
+                    .flatten()
+                    .map(|repo| {
+                        let result = verify::verify_repo(&repo, budget, false, |finding, _, full_path| {
+                            match finding {
+                                Finding::Missing => println!("Missing: {}", full_path.display()),
+                                Finding::Mismatch => println!("Mismatch: {}", full_path.display()),
+                                Finding::AttributesChanged => println!("Attributes changed: {}", full_path.display()),
+                                Finding::PermissionDenied => {
+                                    warn!("Skipping file (permission denied): {}", full_path.display());
+                                }
+                            }
+                            Ok(())
+                        });
+                        (repo, result)
+                    })
+                    .collect::<Vec<_>>()
+            }))
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().expect("verify worker panicked")).collect()
+    });
     
-    let display_ctx = DisplayContext::new(repo_root, current_dir);
-    for entry in entries {
-        let display_path = display_ctx.make_relative(&entry.path)?;
-        let human_size = format_bytes(entry.num_bytes);
-        println!("{:>10} {:>15} {} {}", 
-            human_size,
-            entry.modified,
-            entry.sha256,
-            display_path
-        );
+    // Report in registration order, whichever volume finished first
+    println!("\nSummary:");
+    let mut failed = 0;
+    for repo in &mounted {
+        let Some((_, result)) = results.iter().find(|(r, _)| r == repo) else { continue };
+        match result {
+            Ok(summary) if summary.failed() => {
+                failed += 1;
+                println!("  FAILED     {} ({})", repo.display(), summary.describe());
+            }
+            Ok(summary) => println!("  ok         {} ({})", repo.display(), summary.describe()),
+            Err(e) => {
+                failed += 1;
+                println!("  error      {} ({:#})", repo.display(), e);
+            }
+        }
+    }
+    for repo in &unmounted {
+        println!("  unmounted  {}", repo.display());
     }
     
+    if failed > 0 {
+        bail!("Verification failed in {} of {} mounted repositories", failed, mounted.len());
+    }
     Ok(())
-}
+}
\ No newline at end of file
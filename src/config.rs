@@ -1,14 +1,108 @@
+use std::cmp::Ordering;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 
-const CONFIG_FILE: &str = "config";
+use crate::content_class::ContentClass;
+use crate::digest::DigestSet;
+use crate::display::{ColorMode, MarkerSymbols, StatusMarker};
+use crate::lock::LockStrategy;
+use crate::roots::Root;
+use crate::sqlite_store::SqliteTuning;
+use crate::tiering::TierRule;
+
+pub const CONFIG_FILE: &str = "config";
 const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How `status` and `update` decide whether a file has changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChangeDetection {
+    /// Trust size and modified time (fast)
+    #[default]
+    Metadata,
+    /// Re-hash every file whose size and modified time are unchanged (thorough)
+    Hash,
+}
+
+impl std::str::FromStr for ChangeDetection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "metadata" => Ok(ChangeDetection::Metadata),
+            "hash" => Ok(ChangeDetection::Hash),
+            _ => anyhow::bail!("Invalid value for change_detection: '{}' (expected metadata or hash)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for ChangeDetection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeDetection::Metadata => write!(f, "metadata"),
+            ChangeDetection::Hash => write!(f, "hash"),
+        }
+    }
+}
+
 /// Configuration stored in the .oci directory
 #[derive(Debug)]
 pub struct Config {
     pub version: String,
+    /// Record extended attributes (names + value hashes) for each entry
+    pub capture_xattrs: bool,
+    /// Index dotfiles and dot-directories (false ignores them without any ignore patterns)
+    pub index_hidden: bool,
+    /// Also ignore what `.gitignore` and `.git/info/exclude` files inside the repository ignore
+    pub respect_gitignore: bool,
+    /// Skip version control metadata (`.git`, `.hg`, `.svn`) the way `.oci` is skipped
+    pub skip_vcs: bool,
+    /// Hash the members of zip/tar archives so loose copies of them are found
+    pub index_archives: bool,
+    /// Detect and store each file's content type (MIME type) during `update`
+    pub detect_types: bool,
+    /// Extract capture date, camera and dimensions from photos and videos during `update`
+    pub extract_media: bool,
+    /// Fingerprint audio files during `update` so `duplicates --audio` can match re-encodings
+    pub fingerprint_audio: bool,
+    /// Policy used to detect changed files
+    pub change_detection: ChangeDetection,
+    /// Path classes reported as summary lines instead of per-file output (`summarize.<name>` keys)
+    pub content_classes: Vec<ContentClass>,
+    /// Files at least this large get a quick fingerprint instead of a full hash (0 = disabled)
+    pub quick_hash_threshold: u64,
+    /// Digests recorded for every file besides SHA256 (`digests=md5,sha1`)
+    pub digests: DigestSet,
+    /// Reuse the hashes of moved or renamed files by inode (`.oci/hashcache.db`)
+    pub hash_cache: bool,
+    /// `prune` and `rm` move files to the system trash instead of the pruneyard
+    pub prune_to_trash: bool,
+    /// Rules assigning storage classes to paths (`tier.<class>` keys)
+    pub tier_rules: Vec<TierRule>,
+    /// Cold/offsite files modified within this many days are reported as tiering violations
+    pub tier_recent_days: u64,
+    /// Copies of the index kept in `.oci/backups`, taken before commands that remove data from it (0 = none)
+    pub backups: usize,
+    /// Name recorded in the op log alongside the OS user (e.g. a person's name on a shared NAS)
+    pub identity: Option<String>,
+    /// File whose contents unlock an encrypted index (instead of a passphrase)
+    pub keyfile: Option<String>,
+    /// Program that makes and repairs recovery sets (`oci parity`); `par2` on the PATH by default
+    pub par2: Option<String>,
+    /// Directories tracked by a multi-root index (`root.<name>` keys); empty for a single-root index
+    pub roots: Vec<Root>,
+    /// How mutating commands lock the repository
+    pub lock_strategy: LockStrategy,
+    /// SQLite pragmas for the index database (`sqlite_*` keys)
+    pub sqlite: SqliteTuning,
+    /// Symbols printed for status markers (`marker.<name>` keys)
+    pub markers: MarkerSymbols,
+    /// Print sizes and times in human-readable form by default (None defers to the user config)
+    pub human_readable: Option<bool>,
+    /// When to color output unless `--color` is given (None defers to the user config)
+    pub color: Option<ColorMode>,
+    /// Defaults from the per-user config file
+    pub user: UserConfig,
 }
 
 impl Config {
@@ -16,33 +110,147 @@ impl Config {
     pub fn new() -> Self {
         Config {
             version: TOOL_VERSION.to_string(),
+            capture_xattrs: false,
+            index_hidden: true,
+            respect_gitignore: false,
+            skip_vcs: true,
+            index_archives: false,
+            detect_types: true,
+            extract_media: false,
+            fingerprint_audio: false,
+            change_detection: ChangeDetection::default(),
+            content_classes: Vec::new(),
+            quick_hash_threshold: 0,
+            digests: DigestSet::default(),
+            hash_cache: true,
+            prune_to_trash: false,
+            tier_rules: Vec::new(),
+            tier_recent_days: 30,
+            backups: 5,
+            identity: None,
+            keyfile: None,
+            par2: None,
+            roots: Vec::new(),
+            lock_strategy: LockStrategy::default(),
+            sqlite: SqliteTuning::default(),
+            markers: MarkerSymbols::default(),
+            human_readable: None,
+            color: None,
+            user: UserConfig::default(),
         }
     }
     
     /// Save the config to the .oci directory
     pub fn save(&self, repo_root: &Path) -> Result<()> {
         let config_path = repo_root.join(crate::index::OCI_DIR).join(CONFIG_FILE);
-        let contents = format!("version={}\n", self.version);
-        fs::write(&config_path, contents)
+        fs::write(&config_path, self.contents())
             .context("Failed to write config file")?;
         Ok(())
     }
     
+    /// The config as `key=value` lines, as saved to `.oci/config`
+    pub fn contents(&self) -> String {
+        let mut contents = format!(
+            "version={}\ncapture_xattrs={}\nindex_hidden={}\nrespect_gitignore={}\nskip_vcs={}\nindex_archives={}\ndetect_types={}\nextract_media={}\nfingerprint_audio={}\nchange_detection={}\nquick_hash_threshold={}\nhash_cache={}\nprune_to_trash={}\ntier_recent_days={}\nbackups={}\n\
+             lock_strategy={}\nsqlite_journal_mode={}\nsqlite_synchronous={}\nsqlite_cache_size={}\nsqlite_temp_store={}\n",
+            self.version, self.capture_xattrs, self.index_hidden, self.respect_gitignore, self.skip_vcs, self.index_archives, self.detect_types, self.extract_media, self.fingerprint_audio, self.change_detection, self.quick_hash_threshold, self.hash_cache, self.prune_to_trash,
+            self.tier_recent_days, self.backups, self.lock_strategy, self.sqlite.journal_mode, self.sqlite.synchronous,
+            self.sqlite.cache_size, self.sqlite.temp_store
+        );
+        if !self.digests.is_empty() {
+            contents.push_str(&format!("digests={}\n", self.digests));
+        }
+        // Preferences that can come from the user config are only saved when set for this repository
+        if let Some(human_readable) = self.human_readable {
+            contents.push_str(&format!("human_readable={}\n", human_readable));
+        }
+        if let Some(color) = self.color {
+            contents.push_str(&format!("color={}\n", color));
+        }
+        if let Some(identity) = &self.identity {
+            contents.push_str(&format!("identity={}\n", identity));
+        }
+        if let Some(keyfile) = &self.keyfile {
+            contents.push_str(&format!("keyfile={}\n", keyfile));
+        }
+        if let Some(par2) = &self.par2 {
+            contents.push_str(&format!("par2={}\n", par2));
+        }
+        for root in &self.roots {
+            contents.push_str(&format!("root.{}={}\n", root.name, root.path.display()));
+        }
+        for (marker, symbol) in self.markers.overrides() {
+            contents.push_str(&format!("marker.{}={}\n", marker.name(), symbol));
+        }
+        for class in &self.content_classes {
+            contents.push_str(&format!("summarize.{}={}\n", class.name, class.patterns.join(", ")));
+        }
+        for rule in &self.tier_rules {
+            contents.push_str(&format!("tier.{}={}\n", rule.class, rule.patterns.join(", ")));
+        }
+        contents
+    }
+    
+    /// Current value of a key as it would be saved, or None if the key is unset or unknown
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.contents()
+            .lines()
+            .find_map(|line| line.split_once('=').filter(|(k, _)| *k == key).map(|(_, value)| value.to_string()))
+    }
+    
+    /// Validate and set a key (replacing an existing `summarize.`/`tier.` entry of the same name)
+    /// Keys with side effects elsewhere (`version`, `root.<name>`) are refused.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        if key == "version" {
+            anyhow::bail!("The version is managed by oci and cannot be set");
+        }
+        if key.starts_with("root.") {
+            anyhow::bail!("Roots are registered with 'oci root <name> <path>'");
+        }
+        
+        // Validate on a scratch config first so a bad value leaves this one untouched
+        if !Config::new().apply(key, value)? {
+            if UserConfig::KEYS.contains(&key) {
+                anyhow::bail!("{} can only be set in the user config (oci config set --global)", key);
+            }
+            anyhow::bail!("Unknown config key: '{}' (see 'oci config list')", key);
+        }
+        if let Some(name) = key.strip_prefix("summarize.") {
+            self.content_classes.retain(|class| class.name != name);
+        }
+        if let Some(class) = key.strip_prefix("tier.") {
+            self.tier_rules.retain(|rule| rule.class.to_string() != class);
+        }
+        self.apply(key, value)?;
+        Ok(())
+    }
+    
+    /// Load the config if the repository has one (without creating it)
+    pub fn load_if_present(repo_root: &Path) -> Result<Option<Self>> {
+        if repo_root.join(crate::index::OCI_DIR).join(CONFIG_FILE).exists() {
+            Self::load(repo_root).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+    
     /// Load the config from the .oci directory
     pub fn load(repo_root: &Path) -> Result<Self> {
         let config_path = repo_root.join(crate::index::OCI_DIR).join(CONFIG_FILE);
         
         if !config_path.exists() {
             // For backward compatibility, if config doesn't exist, create one with current version
-            let config = Config::new();
+            let mut config = Config::new();
             config.save(repo_root)?;
+            config.user = UserConfig::load()?;
             return Ok(config);
         }
         
         let contents = fs::read_to_string(&config_path)
             .context("Failed to read config file")?;
         
-        let mut version = TOOL_VERSION.to_string();
+        let mut config = Config::new();
+        config.user = UserConfig::load()?;
         
         for line in contents.lines() {
             let line = line.trim();
@@ -51,17 +259,91 @@ impl Config {
             }
             
             if let Some((key, value)) = line.split_once('=') {
-                let key = key.trim();
-                let value = value.trim();
-                
-                match key {
-                    "version" => version = value.to_string(),
-                    _ => {} // Ignore unknown keys for forward compatibility
-                }
+                // Unknown keys are ignored for forward compatibility
+                config.apply(key.trim(), value.trim())?;
             }
         }
         
-        Ok(Config { version })
+        Ok(config)
+    }
+    
+    /// Set a key from its text value, returning false for unknown keys
+    fn apply(&mut self, key: &str, value: &str) -> Result<bool> {
+        match key {
+            "version" => self.version = value.to_string(),
+            "capture_xattrs" => self.capture_xattrs = parse_bool(key, value)?,
+            "index_hidden" => self.index_hidden = parse_bool(key, value)?,
+            "respect_gitignore" => self.respect_gitignore = parse_bool(key, value)?,
+            "skip_vcs" => self.skip_vcs = parse_bool(key, value)?,
+            "index_archives" => self.index_archives = parse_bool(key, value)?,
+            "detect_types" => self.detect_types = parse_bool(key, value)?,
+            "extract_media" => self.extract_media = parse_bool(key, value)?,
+            "fingerprint_audio" => self.fingerprint_audio = parse_bool(key, value)?,
+            "change_detection" => self.change_detection = value.parse()?,
+            "lock_strategy" => self.lock_strategy = value.parse()?,
+            "human_readable" => self.human_readable = Some(parse_bool(key, value)?),
+            "color" => self.color = Some(value.parse()?),
+            "quick_hash_threshold" => self.quick_hash_threshold = parse_size(key, value)?,
+            "digests" => self.digests = DigestSet::parse(value)?,
+            "hash_cache" => self.hash_cache = parse_bool(key, value)?,
+            "prune_to_trash" => self.prune_to_trash = parse_bool(key, value)?,
+            "sqlite_journal_mode" => {
+                self.sqlite.journal_mode = parse_choice(key, value, SqliteTuning::JOURNAL_MODES)?;
+            }
+            "sqlite_synchronous" => {
+                self.sqlite.synchronous = parse_choice(key, value, SqliteTuning::SYNCHRONOUS)?;
+            }
+            "sqlite_cache_size" => self.sqlite.cache_size = parse_size(key, value)?,
+            "sqlite_temp_store" => {
+                self.sqlite.temp_store = parse_choice(key, value, SqliteTuning::TEMP_STORES)?;
+            }
+            _ if key.starts_with("summarize.") => {
+                let name = &key["summarize.".len()..];
+                self.content_classes.push(ContentClass::parse(name, value));
+            }
+            "identity" => {
+                self.identity = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "keyfile" => {
+                self.keyfile = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "par2" => {
+                self.par2 = Some(value.to_string()).filter(|v| !v.is_empty());
+            }
+            "tier_recent_days" => {
+                self.tier_recent_days = value.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for {}: '{}' (expected a number of days)", key, value))?;
+            }
+            "backups" => {
+                self.backups = value.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for {}: '{}' (expected a number of copies)", key, value))?;
+            }
+            _ if key.starts_with("marker.") => {
+                let marker = StatusMarker::from_name(&key["marker.".len()..])?;
+                self.markers.set(marker, value)?;
+            }
+            _ if key.starts_with("root.") => {
+                self.roots.push(Root::parse(&key["root.".len()..], value)?);
+            }
+            _ if key.starts_with("tier.") => {
+                let class = key["tier.".len()..].parse()?;
+                self.tier_rules.push(TierRule::parse(class, value));
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+    
+    /// Whether to print human-readable sizes and times: `.oci/config`, then the user config, then off
+    /// (`--human` is applied by main on top of this)
+    pub fn human_readable(&self) -> bool {
+        self.human_readable.or(self.user.human_readable).unwrap_or(false)
+    }
+    
+    /// When to color output: `.oci/config`, then the user config, then auto
+    /// (`--color` is applied by main and takes precedence over this)
+    pub fn color(&self) -> ColorMode {
+        self.color.or(self.user.color).unwrap_or_default()
     }
     
     /// Check if the stored version matches the current tool version
@@ -70,12 +352,264 @@ impl Config {
         self.version == TOOL_VERSION
     }
     
+    /// Whether the index was created with an older release of oci than this one
+    pub fn is_older_release(&self) -> bool {
+        compare_versions(&self.version, TOOL_VERSION) == Ordering::Less
+    }
+    
+    /// Record this release of oci as the one the index is kept with
+    pub fn record_tool_version(&mut self, repo_root: &Path) -> Result<()> {
+        self.version = TOOL_VERSION.to_string();
+        self.save(repo_root)
+    }
+    
     /// Display a version mismatch warning
     pub fn warn_version_mismatch(&self) {
-        eprintln!("Warning: Index version mismatch!");
-        eprintln!("  Index was created with: v{}", self.version);
-        eprintln!("  Current tool version:   v{}", TOOL_VERSION);
-        eprintln!("  This may cause compatibility issues. Consider running 'oci update' to refresh the index.");
-        eprintln!();
+        tracing::warn!(
+            "Index version mismatch!\n  \
+             Index was created with: v{}\n  \
+             Current tool version:   v{}\n  \
+             This may cause compatibility issues. Consider running 'oci update' to refresh the index.",
+            self.version, TOOL_VERSION
+        );
+    }
+}
+
+/// Order two versions the way semver does: by release numbers, with a pre-release (`1.2.0-beta`)
+/// before its release and build metadata (`+...`) ignored
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |version: &str| -> (Vec<u64>, Option<String>) {
+        let version = version.split('+').next().unwrap_or(version);
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre.to_string())),
+            None => (version, None),
+        };
+        (release.split('.').map(|part| part.parse().unwrap_or(0)).collect(), pre)
+    };
+    let ((a_release, a_pre), (b_release, b_pre)) = (parse(a), parse(b));
+    a_release.cmp(&b_release).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(a_pre), Some(b_pre)) => compare_pre_releases(&a_pre, &b_pre),
+    })
+}
+
+/// Order two pre-release tags identifier by identifier: numbers numerically and before words,
+/// words alphabetically, and a tag before any longer tag it starts
+fn compare_pre_releases(a: &str, b: &str) -> Ordering {
+    let (a_ids, b_ids): (Vec<&str>, Vec<&str>) = (a.split('.').collect(), b.split('.').collect());
+    for (a_id, b_id) in a_ids.iter().zip(&b_ids) {
+        let order = match (a_id.parse::<u64>(), b_id.parse::<u64>()) {
+            (Ok(a_number), Ok(b_number)) => a_number.cmp(&b_number),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => a_id.cmp(b_id),
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    a_ids.len().cmp(&b_ids.len())
+}
+
+/// Directory for per-user oci files: `$XDG_CONFIG_HOME/oci`, falling back to `~/.config/oci`
+pub fn user_config_dir() -> PathBuf {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(std::env::temp_dir);
+    config_home.join("oci")
+}
+
+/// Per-user defaults for every repository (`~/.config/oci/config`)
+/// Only personal preferences live here; a key set in a repository's `.oci/config` wins.
+#[derive(Debug, Default)]
+pub struct UserConfig {
+    pub color: Option<ColorMode>,
+    pub human_readable: Option<bool>,
+    /// File whose contents `init` writes as a new repository's ignore file instead of the built-in defaults
+    pub ignore_template: Option<PathBuf>,
+    /// Number of repositories `verify --all-repos` checks at once (defaults to the number of CPUs)
+    pub jobs: Option<usize>,
+}
+
+impl UserConfig {
+    pub const KEYS: &'static [&'static str] = &["color", "human_readable", "ignore_template", "jobs"];
+    
+    pub fn path() -> PathBuf {
+        user_config_dir().join(CONFIG_FILE)
+    }
+    
+    /// Load the user config, or the empty defaults if there is none
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(UserConfig::default()),
+            Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+        };
+        
+        let mut config = UserConfig::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                config.apply(key.trim(), value.trim())
+                    .with_context(|| format!("Invalid setting in {}", path.display()))?;
+            }
+        }
+        Ok(config)
+    }
+    
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        fs::create_dir_all(user_config_dir())
+            .context(format!("Failed to create directory: {}", user_config_dir().display()))?;
+        fs::write(&path, self.contents())
+            .context(format!("Failed to write {}", path.display()))
+    }
+    
+    /// The settings that are set, as `key=value` lines
+    pub fn contents(&self) -> String {
+        let mut contents = String::new();
+        if let Some(color) = self.color {
+            contents.push_str(&format!("color={}\n", color));
+        }
+        if let Some(human_readable) = self.human_readable {
+            contents.push_str(&format!("human_readable={}\n", human_readable));
+        }
+        if let Some(template) = &self.ignore_template {
+            contents.push_str(&format!("ignore_template={}\n", template.display()));
+        }
+        if let Some(jobs) = self.jobs {
+            contents.push_str(&format!("jobs={}\n", jobs));
+        }
+        contents
+    }
+    
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.contents()
+            .lines()
+            .find_map(|line| line.split_once('=').filter(|(k, _)| *k == key).map(|(_, value)| value.to_string()))
+    }
+    
+    /// Validate and set a key
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        if !self.apply(key, value)? {
+            anyhow::bail!("Unknown user config key: '{}' (expected one of {})", key, Self::KEYS.join(", "));
+        }
+        Ok(())
+    }
+    
+    /// Number of concurrent jobs: the configured value, or the number of CPUs
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+    }
+    
+    /// Set a key from its text value, returning false for unknown keys
+    fn apply(&mut self, key: &str, value: &str) -> Result<bool> {
+        match key {
+            "color" => self.color = Some(value.parse()?),
+            "human_readable" => self.human_readable = Some(parse_bool(key, value)?),
+            "ignore_template" => {
+                // Relative to the user config directory, like a path in a dotfile
+                self.ignore_template = Some(user_config_dir().join(value)).filter(|_| !value.is_empty());
+            }
+            "jobs" => {
+                let jobs: usize = value.parse().ok().filter(|jobs| *jobs > 0)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid value for {}: '{}' (expected a positive number)", key, value))?;
+                self.jobs = Some(jobs);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Parse a size config value in bytes, with an optional K/M/G/T suffix (powers of 1024)
+pub fn parse_size(key: &str, value: &str) -> Result<u64> {
+    let upper = value.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: u64 = match upper[digits.len()..].trim_end_matches('B').trim_end_matches('I') {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        "T" => 1024 * 1024 * 1024 * 1024,
+        _ => anyhow::bail!("Invalid size for {}: '{}' (expected e.g. 512, 100M or 2G)", key, value),
+    };
+    let number: u64 = digits.trim().parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size for {}: '{}' (expected e.g. 512, 100M or 2G)", key, value))?;
+    Ok(number * multiplier)
+}
+
+/// Parse a config value that must be one of a fixed set of (lowercase) names
+fn parse_choice(key: &str, value: &str, choices: &[&str]) -> Result<String> {
+    let value = value.to_ascii_lowercase();
+    if !choices.contains(&value.as_str()) {
+        anyhow::bail!("Invalid value for {}: '{}' (expected one of {})", key, value, choices.join(", "));
+    }
+    Ok(value)
+}
+
+/// Parse a boolean config value
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => anyhow::bail!("Invalid value for {}: '{}' (expected true or false)", key, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("k", "512").unwrap(), 512);
+        assert_eq!(parse_size("k", "4K").unwrap(), 4096);
+        assert_eq!(parse_size("k", "100MB").unwrap(), 100 * 1024 * 1024);
+        assert_eq!(parse_size("k", "2GiB").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert!(parse_size("k", "lots").is_err());
+        assert!(parse_size("k", "10X").is_err());
+    }
+
+    #[test]
+    fn test_get_and_set() {
+        let mut config = Config::new();
+        config.set("change_detection", "hash").unwrap();
+        config.set("tier.cold", "archive/**").unwrap();
+        config.set("tier.cold", "old/**, scans/**").unwrap();
+        assert_eq!(config.get("change_detection").as_deref(), Some("hash"));
+        assert_eq!(config.get("tier.cold").as_deref(), Some("old/**, scans/**"));
+        assert_eq!(config.get("identity"), None);
+
+        // Invalid values and unknown keys leave the config unchanged
+        assert!(config.set("change_detection", "sometimes").is_err());
+        assert!(config.set("no_such_key", "1").is_err());
+        assert!(config.set("version", "0.0.1").is_err());
+        assert_eq!(config.change_detection, ChangeDetection::Hash);
+    }
+
+    #[test]
+    fn test_release_order() {
+        let mut config = Config::new();
+        assert!(!config.is_older_release());
+        config.version = "0.2.0".to_string();
+        assert!(config.is_older_release());
+        config.version = "0.10.0".to_string();
+        assert!(!config.is_older_release());
+
+        assert_eq!(compare_versions("1.2.0-beta", "1.2.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.2.0", "1.2.0-beta"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2.0-beta", "1.1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.2.0-alpha", "1.2.0-beta"), Ordering::Less);
+        assert_eq!(compare_versions("1.2.0-beta.2", "1.2.0-beta.11"), Ordering::Less);
+        assert_eq!(compare_versions("1.2.0-beta", "1.2.0-beta.1"), Ordering::Less);
+        assert_eq!(compare_versions("1.2.0-1", "1.2.0-rc"), Ordering::Less);
+        assert_eq!(compare_versions("1.2.0+build.5", "1.2.0"), Ordering::Equal);
     }
 }
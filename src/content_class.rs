@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::display::{format_bytes, StatusMarker};
+use crate::ignore;
+
+/// A named group of paths whose changes are reported as a single summary line
+/// (e.g. mailboxes that change constantly)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentClass {
+    pub name: String,
+    pub patterns: Vec<String>,
+}
+
+impl ContentClass {
+    /// Parse a class from its config name and comma-separated pattern list
+    pub fn parse(name: &str, patterns: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            patterns: patterns
+                .split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// Find the first content class whose patterns match the path
+pub fn classify<'a>(classes: &'a [ContentClass], rel_path: &Path) -> Option<&'a str> {
+    classes
+        .iter()
+        .find(|class| ignore::matches_any(rel_path, &class.patterns))
+        .map(|class| class.name.as_str())
+}
+
+/// Per-class totals of changed files
+#[derive(Debug, Default, Clone, PartialEq)]
+struct ClassTotals {
+    bytes: u64,
+    added: usize,
+    updated: usize,
+    deleted: usize,
+}
+
+impl ClassTotals {
+    fn files(&self) -> usize {
+        self.added + self.updated + self.deleted
+    }
+}
+
+/// Accumulates changes folded into content classes during a status or update run
+#[derive(Debug, Default)]
+pub struct ClassSummary {
+    totals: BTreeMap<String, ClassTotals>,
+}
+
+impl ClassSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a changed file for a class
+    pub fn record(&mut self, class: &str, marker: &StatusMarker, bytes: u64) {
+        let totals = self.totals.entry(class.to_string()).or_default();
+        totals.bytes += bytes;
+        match marker {
+            StatusMarker::Added => totals.added += 1,
+            StatusMarker::Deleted => totals.deleted += 1,
+            _ => totals.updated += 1,
+        }
+    }
+
+    /// Summary lines, one per class, sorted by class name
+    pub fn lines(&self) -> Vec<String> {
+        self.totals
+            .iter()
+            .map(|(class, totals)| {
+                format!(
+                    "{}: {} changed in {} file(s) ({} added, {} updated, {} deleted)",
+                    class,
+                    format_bytes(totals.bytes),
+                    totals.files(),
+                    totals.added,
+                    totals.updated,
+                    totals.deleted
+                )
+            })
+            .collect()
+    }
+
+    pub fn print(&self) {
+        for line in self.lines() {
+            println!("{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_uses_first_matching_class() {
+        let classes = vec![
+            ContentClass::parse("Mail", "Library/Mail/, *.ost"),
+            ContentClass::parse("Logs", "*.log"),
+        ];
+
+        assert_eq!(classify(&classes, Path::new("Library/Mail/V10/msg.emlx")), Some("Mail"));
+        assert_eq!(classify(&classes, Path::new("Outlook/archive.ost")), Some("Mail"));
+        assert_eq!(classify(&classes, Path::new("debug.log")), Some("Logs"));
+        assert_eq!(classify(&classes, Path::new("photo.jpg")), None);
+    }
+
+    #[test]
+    fn test_summary_lines() {
+        let mut summary = ClassSummary::new();
+        summary.record("Mail", &StatusMarker::Added, 1024);
+        summary.record("Mail", &StatusMarker::Updated, 1024);
+        summary.record("Mail", &StatusMarker::Deleted, 0);
+
+        assert_eq!(
+            summary.lines(),
+            vec!["Mail: 2.00 KB changed in 3 file(s) (1 added, 1 updated, 1 deleted)"]
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{bail, Context, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::path::Path;
+
+use crate::config::Config;
+
+/// First bytes of an encrypted index, naming the format version
+const MAGIC: &[u8; 8] = b"OCIENC1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Environment variable holding the passphrase of an encrypted index
+pub const PASSPHRASE_ENV: &str = "OCI_PASSPHRASE";
+/// Environment variable naming a keyfile whose contents unlock an encrypted index
+pub const KEYFILE_ENV: &str = "OCI_KEYFILE";
+
+/// A key derived from a passphrase or keyfile, together with the salt it was derived with
+pub struct IndexKey {
+    salt: [u8; SALT_LEN],
+    key: [u8; 32],
+}
+
+impl IndexKey {
+    /// Derive a key for a new encrypted index, with a fresh random salt
+    pub fn new(secret: &[u8]) -> Result<Self> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self::derive(secret, salt)
+    }
+
+    fn derive(secret: &[u8], salt: [u8; SALT_LEN]) -> Result<Self> {
+        let mut key = [0; 32];
+        Argon2::default()
+            .hash_password_into(secret, &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Failed to derive index key: {}", e))?;
+        Ok(IndexKey { salt, key })
+    }
+
+    /// Encrypt data as `MAGIC | salt | nonce | ciphertext`, with a fresh nonce every time
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.key));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt index"))?;
+        let mut data = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&self.salt);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    /// Decrypt data written by `encrypt`, returning the key (to encrypt it again) and the plaintext
+    pub fn decrypt(data: &[u8], secret: &[u8]) -> Result<(Self, Vec<u8>)> {
+        let header_len = MAGIC.len() + SALT_LEN + NONCE_LEN;
+        if data.len() < header_len || &data[..MAGIC.len()] != MAGIC {
+            bail!("Not an encrypted oci index");
+        }
+        let salt: [u8; SALT_LEN] = data[MAGIC.len()..MAGIC.len() + SALT_LEN].try_into().unwrap();
+        let nonce = Nonce::from_slice(&data[MAGIC.len() + SALT_LEN..header_len]);
+        let key = Self::derive(secret, salt)?;
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key.key))
+            .decrypt(nonce, &data[header_len..])
+            .map_err(|_| anyhow::anyhow!("Cannot decrypt the index: wrong passphrase or keyfile, or the file is damaged"))?;
+        Ok((key, plaintext))
+    }
+}
+
+/// The secret unlocking a repository's index: the keyfile named by OCI_KEYFILE or the `keyfile`
+/// config key, else the passphrase in OCI_PASSPHRASE, else a passphrase typed at the terminal
+/// (twice when `confirm` is set, for a new index).
+pub fn secret(repo_root: &Path, confirm: bool) -> Result<Vec<u8>> {
+    let keyfile = std::env::var_os(KEYFILE_ENV)
+        .filter(|v| !v.is_empty())
+        .map(std::path::PathBuf::from)
+        .or(Config::load_if_present(repo_root)?.and_then(|c| c.keyfile).map(Into::into));
+    if let Some(keyfile) = keyfile {
+        let secret = std::fs::read(&keyfile)
+            .context(format!("Failed to read keyfile: {}", keyfile.display()))?;
+        if secret.is_empty() {
+            bail!("Keyfile is empty: {}", keyfile.display());
+        }
+        return Ok(secret);
+    }
+    if let Some(passphrase) = std::env::var_os(PASSPHRASE_ENV).filter(|v| !v.is_empty()) {
+        return Ok(passphrase.into_encoded_bytes());
+    }
+
+    let no_terminal = || format!("The index is encrypted; set {} or {}, or run oci from a terminal", PASSPHRASE_ENV, KEYFILE_ENV);
+    let passphrase = rpassword::prompt_password("Index passphrase: ").with_context(no_terminal)?;
+    if passphrase.is_empty() {
+        bail!("The passphrase must not be empty");
+    }
+    if confirm && rpassword::prompt_password("Repeat passphrase: ").with_context(no_terminal)? != passphrase {
+        bail!("Passphrases do not match");
+    }
+    Ok(passphrase.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let key = IndexKey::new(b"correct horse").unwrap();
+        let data = key.encrypt(b"SQLite format 3").unwrap();
+        assert!(data.starts_with(MAGIC));
+        assert!(!data.windows(6).any(|w| w == b"SQLite"));
+        // A fresh nonce each time, under the same salt
+        assert_ne!(key.encrypt(b"SQLite format 3").unwrap(), data);
+
+        let (key, plaintext) = IndexKey::decrypt(&data, b"correct horse").unwrap();
+        assert_eq!(plaintext, b"SQLite format 3");
+        assert!(IndexKey::decrypt(&key.encrypt(b"again").unwrap(), b"correct horse").is_ok());
+
+        let err = IndexKey::decrypt(&data, b"wrong horse").err().unwrap();
+        assert!(err.to_string().contains("wrong passphrase"), "got: {}", err);
+        assert!(IndexKey::decrypt(b"SQLite format 3\0", b"correct horse").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::index::OCI_DIR;
+
+/// File in `.oci` holding the process ID of the repository's daemon while it runs
+pub const PID_FILE: &str = "daemon.pid";
+
+/// File in `.oci` a daemon started in the background writes its output to
+pub const LOG_FILE: &str = "daemon.log";
+
+/// File in `.oci` holding the last file verified in idle time, so a restarted daemon carries on
+/// with its pass instead of starting over
+pub const VERIFY_FILE: &str = "daemon.verify";
+
+/// Set when the daemon is asked to stop; checked between batches
+static STOP: AtomicBool = AtomicBool::new(false);
+
+pub fn log_path(repo_root: &Path) -> PathBuf {
+    repo_root.join(OCI_DIR).join(LOG_FILE)
+}
+
+/// The process ID of the daemon running for a repository, if any (a record left behind by a
+/// daemon that died doesn't count)
+pub fn running(repo_root: &Path) -> Result<Option<u32>> {
+    let path = repo_root.join(OCI_DIR).join(PID_FILE);
+    let pid = match fs::read_to_string(&path) {
+        Ok(contents) => contents.trim().parse::<u32>().ok(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+    };
+    Ok(pid.filter(|pid| is_alive(*pid)))
+}
+
+/// Records this process as the repository's daemon until dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(repo_root: &Path) -> Result<Self> {
+        if let Some(pid) = running(repo_root)? {
+            anyhow::bail!("A daemon is already running for this repository (pid {})", pid);
+        }
+        let path = repo_root.join(OCI_DIR).join(PID_FILE);
+        fs::write(&path, format!("{}\n", std::process::id())).context(format!("Failed to write {}", path.display()))?;
+        Ok(PidFile { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Start `oci daemon run <args>` in the repository root, detached from the terminal, with its
+/// output appended to the daemon log
+pub fn spawn(repo_root: &Path, args: &[String]) -> Result<Child> {
+    let log_path = log_path(repo_root);
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .context(format!("Failed to open {}", log_path.display()))?;
+    let mut command = Command::new(std::env::current_exe().context("Failed to find the oci executable")?);
+    command
+        .args(["daemon", "run"])
+        .args(args)
+        .current_dir(repo_root)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // Its own process group, so Ctrl-C in the terminal that started it doesn't stop it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    command.spawn().context("Failed to start the daemon")
+}
+
+/// Whether the daemon was asked to stop (SIGTERM, or Ctrl-C when run in the foreground)
+pub fn stop_requested() -> bool {
+    STOP.load(Ordering::Relaxed)
+}
+
+/// Turn SIGTERM and SIGINT into a stop request, so a batch in progress finishes first
+#[cfg(unix)]
+pub fn handle_stop_signals() {
+    extern "C" fn request_stop(_signal: libc::c_int) {
+        STOP.store(true, Ordering::Relaxed);
+    }
+    let handler = request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn handle_stop_signals() {}
+
+/// Ask a running daemon to stop
+#[cfg(unix)]
+pub fn request_stop(pid: u32) -> Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error()).context(format!("Failed to stop the daemon (pid {})", pid));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn request_stop(_pid: u32) -> Result<()> {
+    anyhow::bail!("Stopping the daemon is not supported on this platform")
+}
+
+#[cfg(unix)]
+pub fn is_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists (EPERM: it does, but belongs to someone else)
+    let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+pub fn is_alive(_pid: u32) -> bool {
+    false
+}
+
+/// The last file verified by an unfinished idle-time pass
+pub fn load_verify_position(repo_root: &Path) -> Option<String> {
+    let position = fs::read_to_string(repo_root.join(OCI_DIR).join(VERIFY_FILE)).ok()?;
+    Some(position.trim_end_matches('\n').to_string()).filter(|position| !position.is_empty())
+}
+
+/// Record the last file verified, or clear it (None) when a pass completes
+pub fn save_verify_position(repo_root: &Path, position: Option<&str>) -> Result<()> {
+    let path = repo_root.join(OCI_DIR).join(VERIFY_FILE);
+    match position {
+        Some(position) => fs::write(&path, format!("{}\n", position)).context(format!("Failed to write {}", path.display())),
+        None => match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).context(format!("Failed to remove {}", path.display())),
+            _ => Ok(()),
+        },
+    }
+}
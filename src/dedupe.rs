@@ -0,0 +1,195 @@
+use anyhow::{bail, Result};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+use crate::index::{dir_prefix, FileEntry};
+
+/// Which copy of a duplicate group `duplicates --resolve --keep` keeps
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeepPolicy {
+    /// The copy modified longest ago
+    Oldest,
+    /// The most recently modified copy
+    Newest,
+    /// The copy with the shortest path, usually the least nested one
+    ShortestPath,
+    /// A copy inside this directory (an index path); groups without one are left alone
+    PreferDir(String),
+}
+
+impl KeepPolicy {
+    /// Parse the values of `--keep`: a policy name, followed by a directory for `prefer-dir`
+    pub fn parse(values: &[String]) -> Result<Self> {
+        let policy = match values {
+            [name] if name == "oldest" => KeepPolicy::Oldest,
+            [name] if name == "newest" => KeepPolicy::Newest,
+            [name] if name == "shortest-path" => KeepPolicy::ShortestPath,
+            [name, dir] if name == "prefer-dir" => {
+                let dir = dir.trim_matches('/');
+                KeepPolicy::PreferDir(if dir == "." { String::new() } else { dir.to_string() })
+            }
+            [name] if name == "prefer-dir" => bail!("--keep prefer-dir needs a directory (e.g. --keep prefer-dir originals/)"),
+            [name, ..] if matches!(name.as_str(), "oldest" | "newest" | "shortest-path") => {
+                bail!("--keep {} takes no directory", name)
+            }
+            _ => bail!(
+                "Invalid keep policy: '{}' (expected oldest, newest, shortest-path or prefer-dir <dir>)",
+                values.join(" ")
+            ),
+        };
+        Ok(policy)
+    }
+
+    /// The copy to keep among `files` (sorted by path); ties go to the first by path
+    pub fn choose(&self, files: &[FileEntry]) -> Option<usize> {
+        let indexed = files.iter().enumerate();
+        match self {
+            KeepPolicy::Oldest => indexed.min_by_key(|(i, entry)| (entry.modified, *i)).map(|(i, _)| i),
+            KeepPolicy::Newest => indexed.max_by_key(|(i, entry)| (entry.modified, std::cmp::Reverse(*i))).map(|(i, _)| i),
+            KeepPolicy::ShortestPath => indexed.min_by_key(|(i, entry)| (entry.path.chars().count(), *i)).map(|(i, _)| i),
+            KeepPolicy::PreferDir(dir) => {
+                let prefix = dir_prefix(dir);
+                indexed.map(|(i, _)| i).find(|&i| files[i].path.starts_with(&prefix))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for KeepPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeepPolicy::Oldest => write!(f, "oldest"),
+            KeepPolicy::Newest => write!(f, "newest"),
+            KeepPolicy::ShortestPath => write!(f, "shortest-path"),
+            KeepPolicy::PreferDir(dir) => write!(f, "prefer-dir {}/", dir),
+        }
+    }
+}
+
+/// Listings of duplicate groups for scripts written around other duplicate finders (`duplicates --format`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// fdupes: the paths of each group one per line, with a blank line after each group
+    Fdupes,
+    /// rmlint's `-o json`: a JSON array of a header, an object per file and a summary
+    RmlintJson,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fdupes" => Ok(ExportFormat::Fdupes),
+            "rmlint-json" => Ok(ExportFormat::RmlintJson),
+            _ => bail!("Invalid --format value: '{}' (expected fdupes or rmlint-json)", s),
+        }
+    }
+}
+
+/// Duplicate groups in an export format. Each group holds its copies with their full paths, the
+/// one to keep first (rmlint's "original"); `total_files` is the number of files looked at.
+pub fn export(format: ExportFormat, groups: &[Vec<(PathBuf, FileEntry)>], cwd: &Path, total_files: usize) -> String {
+    match format {
+        ExportFormat::Fdupes => groups
+            .iter()
+            .map(|group| {
+                let mut lines: String = group.iter().map(|(path, _)| format!("{}\n", path.display())).collect();
+                lines.push('\n');
+                lines
+            })
+            .collect(),
+        ExportFormat::RmlintJson => {
+            let mut records = vec![json!({
+                "description": "rmlint json-dump of lint files",
+                "cwd": cwd.to_string_lossy(),
+                "args": "oci duplicates --format rmlint-json",
+                "version": env!("CARGO_PKG_VERSION"),
+                "checksum_type": "sha256",
+            })];
+            let (mut duplicates, mut lint_size) = (0, 0);
+            for (id, (position, (path, entry))) in groups.iter().flat_map(|group| group.iter().enumerate()).enumerate() {
+                let is_original = position == 0;
+                if !is_original {
+                    duplicates += 1;
+                    lint_size += entry.num_bytes;
+                }
+                records.push(json!({
+                    "id": id + 1,
+                    "type": "duplicate_file",
+                    "progress": 100,
+                    "checksum": entry.sha256,
+                    "path": path.to_string_lossy(),
+                    "size": entry.num_bytes,
+                    "depth": entry.path.split('/').count(),
+                    "is_original": is_original,
+                    "mtime": entry.modified as f64 / 1000.0,
+                }));
+            }
+            records.push(json!({
+                "aborted": false,
+                "progress": 100,
+                "total_files": total_files,
+                "ignored_files": 0,
+                "ignored_folders": 0,
+                "duplicates": duplicates,
+                "duplicate_sets": groups.len(),
+                "total_lint_size": lint_size,
+            }));
+            serde_json::to_string_pretty(&records).unwrap_or_default() + "\n"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, modified: u64) -> FileEntry {
+        FileEntry { path: path.to_string(), num_bytes: 1, modified, sha256: "aa".to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_keep_policies() {
+        let files = vec![
+            entry("backup/2020/photo.jpg", 300),
+            entry("originals/photo.jpg", 200),
+            entry("photo.jpg", 200),
+        ];
+        let parse = |values: &[&str]| KeepPolicy::parse(&values.iter().map(|v| v.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(parse(&["oldest"]).unwrap().choose(&files), Some(1));
+        assert_eq!(parse(&["newest"]).unwrap().choose(&files), Some(0));
+        assert_eq!(parse(&["shortest-path"]).unwrap().choose(&files), Some(2));
+        assert_eq!(parse(&["prefer-dir", "originals/"]).unwrap().choose(&files), Some(1));
+        assert_eq!(parse(&["prefer-dir", "backup"]).unwrap().choose(&files), Some(0));
+        assert_eq!(parse(&["prefer-dir", "elsewhere"]).unwrap().choose(&files), None);
+
+        assert!(parse(&["prefer-dir"]).is_err());
+        assert!(parse(&["oldest", "x"]).is_err());
+        assert!(parse(&["largest"]).is_err());
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let groups = vec![
+            vec![(PathBuf::from("/data/a.jpg"), entry("a.jpg", 2000)), (PathBuf::from("/data/b/a.jpg"), entry("b/a.jpg", 1000))],
+            vec![(PathBuf::from("/data/c.txt"), entry("c.txt", 0)), (PathBuf::from("/data/d.txt"), entry("d.txt", 0))],
+        ];
+        assert_eq!(export(ExportFormat::Fdupes, &groups, Path::new("/data"), 5), "/data/a.jpg\n/data/b/a.jpg\n\n/data/c.txt\n/data/d.txt\n\n");
+
+        let json: serde_json::Value = serde_json::from_str(&export(ExportFormat::RmlintJson, &groups, Path::new("/data"), 5)).unwrap();
+        let records = json.as_array().unwrap();
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0]["checksum_type"], "sha256");
+        assert_eq!(records[2]["path"], "/data/b/a.jpg");
+        assert_eq!(records[2]["depth"], 2);
+        assert_eq!(records[2]["mtime"], 1.0);
+        assert_eq!((records[1]["is_original"].as_bool(), records[2]["is_original"].as_bool()), (Some(true), Some(false)));
+        assert_eq!(records[5]["duplicates"], 2);
+        assert_eq!(records[5]["duplicate_sets"], 2);
+        assert_eq!(records[5]["total_lint_size"], 2);
+        assert_eq!(records[5]["total_files"], 5);
+        assert!("csv".parse::<ExportFormat>().is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::{bail, Result};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::index::FileEntry;
+
+/// A hash algorithm an index can record digests in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Every supported algorithm, in the order digests are stored
+    pub const ALL: [Algorithm; 4] = [Algorithm::Md5, Algorithm::Sha1, Algorithm::Sha256, Algorithm::Sha512];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+        }
+    }
+
+    /// The algorithm whose hex digests have this length (SHA256 for anything unrecognized)
+    pub fn for_hex_len(len: usize) -> Self {
+        match len {
+            32 => Algorithm::Md5,
+            40 => Algorithm::Sha1,
+            128 => Algorithm::Sha512,
+            _ => Algorithm::Sha256,
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << Algorithm::ALL.iter().position(|a| a == self).unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_ascii_lowercase().replace('-', "");
+        match Algorithm::ALL.iter().find(|a| a.name() == name) {
+            Some(algorithm) => Ok(*algorithm),
+            None => bail!("Unknown hash algorithm: '{}' (expected md5, sha1, sha256 or sha512)", s),
+        }
+    }
+}
+
+/// Digests recorded for every file in addition to SHA256 (the `digests` config key)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DigestSet(u8);
+
+impl DigestSet {
+    /// Parse a comma-separated list such as `md5,sha1`; `sha256` is accepted but always recorded
+    pub fn parse(list: &str) -> Result<Self> {
+        let mut set = DigestSet::default();
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            set = set.with(name.parse()?);
+        }
+        Ok(set)
+    }
+
+    /// The set computing one algorithm (empty for SHA256, which is always computed)
+    pub fn only(algorithm: Algorithm) -> Self {
+        DigestSet::default().with(algorithm)
+    }
+
+    fn with(self, algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => self,
+            other => DigestSet(self.0 | other.bit()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, algorithm: Algorithm) -> bool {
+        self.0 & algorithm.bit() != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Algorithm> + '_ {
+        Algorithm::ALL.into_iter().filter(|a| self.contains(*a))
+    }
+}
+
+impl fmt::Display for DigestSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.iter().map(|a| a.name()).collect::<Vec<_>>().join(","))
+    }
+}
+
+/// SHA256 plus the extra digests of a set, fed from one read of the file
+pub struct Hasher {
+    sha256: Sha256,
+    md5: Option<Md5>,
+    sha1: Option<Sha1>,
+    sha512: Option<Sha512>,
+}
+
+impl Hasher {
+    pub fn new(extra: DigestSet) -> Self {
+        Hasher {
+            sha256: Sha256::new(),
+            md5: extra.contains(Algorithm::Md5).then(Md5::new),
+            sha1: extra.contains(Algorithm::Sha1).then(Sha1::new),
+            sha512: extra.contains(Algorithm::Sha512).then(Sha512::new),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        if let Some(md5) = &mut self.md5 {
+            md5.update(data);
+        }
+        if let Some(sha1) = &mut self.sha1 {
+            sha1.update(data);
+        }
+        if let Some(sha512) = &mut self.sha512 {
+            sha512.update(data);
+        }
+    }
+
+    /// The SHA256 and the extra digests as stored in an entry (None when there are none)
+    pub fn finish(self) -> (String, Option<String>) {
+        let mut extra = Vec::new();
+        if let Some(md5) = self.md5 {
+            extra.push(format!("md5={:x}", md5.finalize()));
+        }
+        if let Some(sha1) = self.sha1 {
+            extra.push(format!("sha1={:x}", sha1.finalize()));
+        }
+        if let Some(sha512) = self.sha512 {
+            extra.push(format!("sha512={:x}", sha512.finalize()));
+        }
+        let extra = Some(extra.join(";")).filter(|extra| !extra.is_empty());
+        (format!("{:x}", self.sha256.finalize()), extra)
+    }
+}
+
+/// One digest from an entry's stored `name=hex;...` list
+pub fn lookup(digests: Option<&str>, algorithm: Algorithm) -> Option<&str> {
+    digests?.split(';').find_map(|digest| {
+        let (name, hex) = digest.split_once('=')?;
+        (name == algorithm.name()).then_some(hex)
+    })
+}
+
+/// An entry's digest in an algorithm, if recorded (SHA256 is missing only while a full hash is pending)
+pub fn recorded(entry: &FileEntry, algorithm: Algorithm) -> Option<&str> {
+    match algorithm {
+        Algorithm::Sha256 => Some(entry.sha256.as_str()).filter(|sha256| !sha256.is_empty()),
+        other => lookup(entry.digests.as_deref(), other),
+    }
+}
+
+/// Whether a stored digest list lacks any digest of the set
+pub fn missing(digests: Option<&str>, set: DigestSet) -> bool {
+    set.iter().any(|algorithm| lookup(digests, algorithm).is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hasher_and_lookup() {
+        let set = DigestSet::parse("sha1, MD5,sha256").unwrap();
+        assert_eq!(set.to_string(), "md5,sha1");
+        assert!(DigestSet::parse("crc32").is_err());
+
+        let mut hasher = Hasher::new(set);
+        hasher.update(b"hello ");
+        hasher.update(b"world");
+        let (sha256, digests) = hasher.finish();
+        assert_eq!(sha256, "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+        let digests = digests.unwrap();
+        assert_eq!(lookup(Some(&digests), Algorithm::Md5), Some("5eb63bbbe01eeed093cb22bb8f5acdc3"));
+        assert_eq!(lookup(Some(&digests), Algorithm::Sha1), Some("2aae6c35c94fcfb415dbe95f408b9ce91ee846ed"));
+        assert_eq!(lookup(Some(&digests), Algorithm::Sha512), None);
+
+        assert!(!missing(Some(&digests), set));
+        assert!(missing(None, set));
+        assert!(!missing(None, DigestSet::default()));
+        assert_eq!(Hasher::new(DigestSet::default()).finish().1, None);
+        assert_eq!(Algorithm::for_hex_len(40), Algorithm::Sha1);
+    }
+}
@@ -3,6 +3,8 @@ use std::fs;
 use std::path::Path;
 use walkdir::WalkDir;
 
+use crate::index::OCI_DIR;
+
 /// Remove empty parent directories recursively up to the repo root
 pub fn remove_empty_parent_dirs(file_path: &Path, repo_root: &Path) -> Result<()> {
     if let Some(mut parent) = file_path.parent() {
@@ -57,8 +59,7 @@ pub fn remove_all_empty_dirs(repo_root: &Path) -> Result<usize> {
 
             // Skip .oci directory
             if let Ok(rel) = entry.path().strip_prefix(repo_root) {
-                let rel_str = rel.to_string_lossy();
-                if rel_str.starts_with(".oci") {
+                if rel.starts_with(OCI_DIR) {
                     continue;
                 }
             }
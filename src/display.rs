@@ -1,27 +1,287 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::io::IsTerminal;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
 
 use crate::file_utils;
-use crate::index::FileEntry;
+use crate::index::{ArchiveMember, FileEntry};
+use crate::roots::Roots;
+
+/// Format bytes in a human-readable format
+pub fn format_bytes(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+    
+    if bytes >= GB {
+        format!("{:.2} GB", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
+        format!("{:.2} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.2} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
+
+/// Set by `--human` or the `human_readable` config key
+static HUMAN_READABLE: AtomicBool = AtomicBool::new(false);
+
+/// Print sizes and times in human-readable form for the rest of the process
+pub fn set_human_readable(human: bool) {
+    HUMAN_READABLE.store(human, Ordering::Relaxed);
+}
+
+pub fn human_readable() -> bool {
+    HUMAN_READABLE.load(Ordering::Relaxed)
+}
+
+/// Set by `--quiet`: per-file lines are dropped, summaries and errors are still printed
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppress per-file output for the rest of the process
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Set by `--limit`: per-file lines past it are counted instead of printed
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Per-file lines printed so far, and those held back by the limit
+static SHOWN: AtomicUsize = AtomicUsize::new(0);
+static OMITTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Print at most `limit` per-file lines for the rest of the process
+pub fn set_limit(limit: usize) {
+    LIMIT.store(limit, Ordering::Relaxed);
+}
+
+/// Count a per-file line against `--limit`: whether it is printed
+pub fn within_limit() -> bool {
+    if SHOWN.load(Ordering::Relaxed) < LIMIT.load(Ordering::Relaxed) {
+        SHOWN.fetch_add(1, Ordering::Relaxed);
+        true
+    } else {
+        OMITTED.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+}
+
+/// "… and 499,000 more", once `--limit` held lines back
+pub fn omitted_summary() -> Option<String> {
+    match OMITTED.load(Ordering::Relaxed) {
+        0 => None,
+        omitted => Some(format!("… and {} more", crate::progress::group_digits(omitted))),
+    }
+}
+
+/// Format bytes with binary (IEC) units, e.g. "512 B" or "1.50 GiB"
+pub fn format_bytes_iec(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+/// File size column of an entry: bytes, or IEC units in human-readable mode
+pub fn format_size(bytes: u64) -> String {
+    if human_readable() {
+        format_bytes_iec(bytes)
+    } else {
+        bytes.to_string()
+    }
+}
+
+/// Modified time column of an entry: epoch milliseconds, or a local date and time in human-readable mode
+pub fn format_time(millis: u64) -> String {
+    if human_readable() {
+        crate::oplog::format_local_timestamp(millis)
+    } else {
+        millis.to_string()
+    }
+}
+
+/// A total size in summaries: bytes with megabytes, or IEC units in human-readable mode
+pub fn format_total(bytes: u64) -> String {
+    if human_readable() {
+        format_bytes_iec(bytes)
+    } else {
+        format!("{} bytes ({:.2} MB)", bytes, bytes as f64 / 1_048_576.0)
+    }
+}
+
+/// A directory of the tree drawn by `ls --tree`, with its file count and size including subdirectories
+#[derive(Default)]
+struct TreeDir {
+    dirs: std::collections::BTreeMap<String, TreeDir>,
+    files: Vec<(String, u64)>,
+    num_files: usize,
+    num_bytes: u64,
+}
+
+impl TreeDir {
+    fn add(&mut self, path: &str, num_bytes: u64) {
+        self.num_files += 1;
+        self.num_bytes += num_bytes;
+        match path.split_once('/') {
+            Some((dir, rest)) => self.dirs.entry(dir.to_string()).or_default().add(rest, num_bytes),
+            None => self.files.push((path.to_string(), num_bytes)),
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!("({} file(s), {})", self.num_files, format_tree_size(self.num_bytes))
+    }
+
+    fn render(&self, prefix: &str, lines: &mut Vec<String>) {
+        // Directories and files are interleaved by name, like `tree`
+        let mut children: Vec<(&str, Option<&TreeDir>, u64)> = self.dirs.iter()
+            .map(|(name, dir)| (name.as_str(), Some(dir), 0))
+            .chain(self.files.iter().map(|(name, num_bytes)| (name.as_str(), None, *num_bytes)))
+            .collect();
+        children.sort_by(|a, b| a.0.cmp(b.0));
+        for (i, (name, dir, num_bytes)) in children.iter().enumerate() {
+            let last = i == children.len() - 1;
+            let branch = if last { "└── " } else { "├── " };
+            match dir {
+                Some(dir) => {
+                    lines.push(format!("{}{}{}/ {}", prefix, branch, name, dir.summary()));
+                    dir.render(&format!("{}{}", prefix, if last { "    " } else { "│   " }), lines);
+                }
+                None => lines.push(format!("{}{}{} ({})", prefix, branch, name, format_tree_size(*num_bytes))),
+            }
+        }
+    }
+}
+
+fn format_tree_size(bytes: u64) -> String {
+    if human_readable() {
+        format_bytes_iec(bytes)
+    } else {
+        format_bytes(bytes)
+    }
+}
+
+/// Draw files (paths relative to the tree's root, with sizes) as a tree under `root`,
+/// each directory showing how many files it holds and their total size
+pub fn format_tree(root: &str, files: &[(String, u64)]) -> Vec<String> {
+    let mut tree = TreeDir::default();
+    for (path, num_bytes) in files {
+        tree.add(path, *num_bytes);
+    }
+    let mut lines = vec![format!("{} {}", root, tree.summary())];
+    tree.render("", &mut lines);
+    lines
+}
+
+/// When to color output (`--color`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    /// Color when stdout is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => anyhow::bail!("Invalid color mode: '{}' (expected auto, always or never)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            ColorMode::Auto => "auto",
+            ColorMode::Always => "always",
+            ColorMode::Never => "never",
+        })
+    }
+}
+
+/// Whether stdout was a terminal when first asked, so output later sent on to a pager is
+/// still treated as going to one
+static STDOUT_TERMINAL: OnceLock<bool> = OnceLock::new();
+
+pub fn stdout_is_terminal() -> bool {
+    *STDOUT_TERMINAL.get_or_init(|| std::io::stdout().is_terminal())
+}
+
+/// Whether ANSI colors are written, resolved from the first color mode set
+static COLOR: OnceLock<bool> = OnceLock::new();
+
+/// Resolve the color mode for the rest of the process
+/// Only the first call takes effect, so `--color` (set in main) wins over the `color` config key.
+pub fn set_color_mode(mode: ColorMode) {
+    let _ = COLOR.set(resolve_color(mode));
+}
+
+fn resolve_color(mode: ColorMode) -> bool {
+    match mode {
+        ColorMode::Auto => stdout_is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+    }
+}
+
+/// Wrap text in an ANSI SGR sequence when color is enabled
+fn paint(text: &str, sgr: &str) -> String {
+    if *COLOR.get_or_init(|| resolve_color(ColorMode::Auto)) {
+        format!("\x1b[{}m{}\x1b[0m", sgr, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// A content hash, highlighted so it stands out in `grep` and `duplicates`
+pub fn highlight_hash(hash: &str) -> String {
+    paint(hash, "1;35")
+}
 
 /// Helper to compute display paths relative to current directory
 pub struct DisplayContext {
-    repo_root: std::path::PathBuf,
+    roots: Roots,
     current_dir: std::path::PathBuf,
 }
 
 impl DisplayContext {
     /// Create a new DisplayContext
-    pub fn new(repo_root: std::path::PathBuf, current_dir: std::path::PathBuf) -> Self {
+    pub fn new(roots: Roots, current_dir: std::path::PathBuf) -> Self {
         Self {
-            repo_root,
+            roots,
             current_dir,
         }
     }
 
+    /// Mapping between index paths and filesystem locations
+    pub fn roots(&self) -> &Roots {
+        &self.roots
+    }
+
     /// Make a path relative to the current directory for display
     pub fn make_relative(&self, file_path: &str) -> Result<String> {
-        let full_file_path = self.repo_root.join(file_path);
+        let full_file_path = self.roots.full_path(file_path);
 
         if let Ok(rel) = full_file_path.strip_prefix(&self.current_dir) {
             Ok(rel.to_string_lossy().to_string())
@@ -43,6 +303,7 @@ impl DisplayContext {
             modified,
             sha256,
             path: display_path,
+            ..Default::default()
         })
     }
 
@@ -56,6 +317,7 @@ impl DisplayContext {
             modified,
             sha256: String::new(), // Empty hash for status display
             path: display_path,
+            ..Default::default()
         })
     }
 
@@ -66,29 +328,419 @@ impl DisplayContext {
         display_entry.path = display_path;
         Ok(file_utils::format_entry(&display_entry))
     }
+
+    /// Format an archive member with its archive path relative to the current directory
+    pub fn format_member_relative(&self, member: &ArchiveMember) -> Result<String> {
+        let mut display_member = member.clone();
+        display_member.archive = self.make_relative(&member.archive)?;
+        Ok(file_utils::format_member(&display_member))
+    }
+}
+
+/// Fields of an `--format` template, e.g. `{path}\t{size}\t{sha256}`
+const FORMAT_FIELDS: &[&str] = &["path", "name", "size", "bytes", "modified", "mtime", "sha256", "hash", "type", "allocated"];
+
+/// A piece of an `--format` template
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatPart {
+    Text(String),
+    Field(&'static str),
+}
+
+/// Output given with `--format`, shared by `ls` and `grep`
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputFormat {
+    /// One JSON object per line (`--format ndjson`), written as entries are read
+    Ndjson,
+    /// A template: `{field}` is replaced by the field's value, `{{` and `}}` are literal braces,
+    /// and `\t`, `\n` and `\\` are escapes (so tabs can be typed in a shell's single quotes)
+    Template(Vec<FormatPart>),
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "ndjson" {
+            return Ok(OutputFormat::Ndjson);
+        }
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    text.push(c);
+                }
+                ('\\', Some('t')) => {
+                    chars.next();
+                    text.push('\t');
+                }
+                ('\\', Some('n')) => {
+                    chars.next();
+                    text.push('\n');
+                }
+                ('\\', Some('\\')) => {
+                    chars.next();
+                    text.push('\\');
+                }
+                ('{', _) => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        bail!("Unclosed {{{} in format (write {{{{ for a literal brace)", name);
+                    }
+                    let Some(field) = FORMAT_FIELDS.iter().find(|field| **field == name) else {
+                        bail!("Unknown field {{{}}} in format (expected one of: {})", name, FORMAT_FIELDS.join(", "));
+                    };
+                    if !text.is_empty() {
+                        parts.push(FormatPart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(FormatPart::Field(field));
+                }
+                ('}', _) => bail!("Unmatched }} in format (write }}}} for a literal brace)"),
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(FormatPart::Text(text));
+        }
+        Ok(OutputFormat::Template(parts))
+    }
+}
+
+impl OutputFormat {
+    fn render(parts: &[FormatPart], value: impl Fn(&str) -> String) -> String {
+        parts.iter()
+            .map(|part| match part {
+                FormatPart::Text(text) => text.clone(),
+                FormatPart::Field(field) => value(field),
+            })
+            .collect()
+    }
+
+    /// Format an entry, shown at `path`
+    pub fn render_entry(&self, entry: &FileEntry, path: &str) -> String {
+        let parts = match self {
+            OutputFormat::Template(parts) => parts,
+            OutputFormat::Ndjson => {
+                return serde_json::json!({
+                    "path": path,
+                    "num_bytes": entry.num_bytes,
+                    "modified": entry.modified,
+                    "sha256": (!entry.sha256.is_empty()).then_some(&entry.sha256),
+                    "quick_hash": entry.quick_hash,
+                    "type": entry.mime,
+                    "allocated": entry.allocated_bytes(),
+                }).to_string();
+            }
+        };
+        Self::render(parts, |field| match field {
+            "path" => path.to_string(),
+            "name" => file_name(path),
+            "size" => format_size(entry.num_bytes),
+            "bytes" => entry.num_bytes.to_string(),
+            "modified" => format_time(entry.modified),
+            "mtime" => entry.modified.to_string(),
+            "sha256" => entry.sha256.clone(),
+            "hash" => file_utils::display_hash(entry),
+            "type" => entry.mime.clone().unwrap_or_default(),
+            "allocated" => entry.allocated_bytes().to_string(),
+            _ => String::new(),
+        })
+    }
+
+    /// Format an archive member, shown at `archive!member`; members have no modified time,
+    /// content type or allocation of their own, so those fields are empty
+    pub fn render_member(&self, member: &ArchiveMember, archive: &str) -> String {
+        let path = format!("{}!{}", archive, member.member);
+        let parts = match self {
+            OutputFormat::Template(parts) => parts,
+            OutputFormat::Ndjson => {
+                return serde_json::json!({
+                    "path": path,
+                    "archive": archive,
+                    "member": member.member,
+                    "num_bytes": member.num_bytes,
+                    "sha256": member.sha256,
+                }).to_string();
+            }
+        };
+        Self::render(parts, |field| match field {
+            "path" => path.clone(),
+            "name" => file_name(&member.member),
+            "size" => format_size(member.num_bytes),
+            "bytes" => member.num_bytes.to_string(),
+            "sha256" | "hash" => member.sha256.clone(),
+            _ => String::new(),
+        })
+    }
+}
+
+fn file_name(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_string()
 }
 
 /// Status markers for file changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StatusMarker {
     Added,
     Updated,
+    Metadata,
     Deleted,
     Unchanged,
     Ignored,
 }
 
+/// Symbols configured with `marker.<name>` keys; set once when the config is loaded
+static MARKER_SYMBOLS: OnceLock<MarkerSymbols> = OnceLock::new();
+
 impl StatusMarker {
-    pub fn symbol(&self) -> &'static str {
+    pub const ALL: [StatusMarker; 6] = [
+        StatusMarker::Added,
+        StatusMarker::Updated,
+        StatusMarker::Metadata,
+        StatusMarker::Deleted,
+        StatusMarker::Unchanged,
+        StatusMarker::Ignored,
+    ];
+
+    /// Name used in `marker.<name>` config keys and the legend
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusMarker::Added => "added",
+            StatusMarker::Updated => "updated",
+            StatusMarker::Metadata => "metadata",
+            StatusMarker::Deleted => "deleted",
+            StatusMarker::Unchanged => "unchanged",
+            StatusMarker::Ignored => "ignored",
+        }
+    }
+
+    pub fn default_symbol(&self) -> &'static str {
         match self {
             StatusMarker::Added => "+",
             StatusMarker::Updated => "U",
+            StatusMarker::Metadata => "M",
             StatusMarker::Deleted => "-",
             StatusMarker::Unchanged => "=",
             StatusMarker::Ignored => "I",
         }
     }
 
+    /// What the marker means, for `status --legend`
+    pub fn description(&self) -> &'static str {
+        match self {
+            StatusMarker::Added => "On disk but not in the index",
+            StatusMarker::Updated => "Content changed since it was indexed",
+            StatusMarker::Metadata => "Only extended attributes changed (with capture_xattrs)",
+            StatusMarker::Deleted => "In the index but no longer on disk",
+            StatusMarker::Unchanged => "Matches the index (shown with -v)",
+            StatusMarker::Ignored => "Matches an ignore pattern (shown with -v)",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        StatusMarker::ALL.into_iter()
+            .find(|marker| marker.name() == name)
+            .ok_or_else(|| anyhow::anyhow!(
+                "Unknown status marker: '{}' (expected one of {})",
+                name,
+                StatusMarker::ALL.map(|marker| marker.name()).join(", ")
+            ))
+    }
+
+    /// Symbol printed for the marker (configured or default)
+    pub fn symbol(&self) -> &'static str {
+        match MARKER_SYMBOLS.get() {
+            Some(symbols) => symbols.get(*self),
+            None => self.default_symbol(),
+        }
+    }
+
+    /// Whether the marker represents a difference between the index and the filesystem
+    pub fn is_change(&self) -> bool {
+        !matches!(self, StatusMarker::Unchanged | StatusMarker::Ignored)
+    }
+
+    /// ANSI SGR code the marker is colored with, if any
+    fn color(&self) -> Option<&'static str> {
+        match self {
+            StatusMarker::Added => Some("32"),
+            StatusMarker::Updated => Some("33"),
+            StatusMarker::Metadata => Some("36"),
+            StatusMarker::Deleted => Some("31"),
+            StatusMarker::Unchanged => None,
+            StatusMarker::Ignored => Some("2"),
+        }
+    }
+
     pub fn display(&self, formatted_entry: &str) {
-        println!("{} {}", self.symbol(), formatted_entry);
+        if quiet() || !within_limit() {
+            return;
+        }
+        crate::progress::clear_line();
+        let width = MARKER_SYMBOLS.get().map(MarkerSymbols::width).unwrap_or(1);
+        let symbol = pad(self.symbol(), width);
+        match self.color() {
+            Some(sgr) => println!("{} {}", paint(&symbol, sgr), formatted_entry),
+            None => println!("{} {}", symbol, formatted_entry),
+        }
+    }
+}
+
+/// Pad a symbol to a width in characters (so word markers line up)
+fn pad(symbol: &str, width: usize) -> String {
+    format!("{}{}", symbol, " ".repeat(width.saturating_sub(symbol.chars().count())))
+}
+
+/// The symbol printed for each status marker
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarkerSymbols {
+    symbols: [String; 6],
+}
+
+impl Default for MarkerSymbols {
+    fn default() -> Self {
+        MarkerSymbols { symbols: StatusMarker::ALL.map(|marker| marker.default_symbol().to_string()) }
+    }
+}
+
+impl MarkerSymbols {
+    pub fn get(&self, marker: StatusMarker) -> &str {
+        &self.symbols[marker as usize]
+    }
+
+    pub fn set(&mut self, marker: StatusMarker, symbol: &str) -> Result<()> {
+        if symbol.is_empty() {
+            anyhow::bail!("Invalid value for marker.{}: the symbol cannot be empty", marker.name());
+        }
+        self.symbols[marker as usize] = symbol.to_string();
+        Ok(())
+    }
+
+    /// Markers whose symbol differs from the default, for saving the config
+    pub fn overrides(&self) -> impl Iterator<Item = (StatusMarker, &str)> {
+        StatusMarker::ALL.into_iter()
+            .map(|marker| (marker, self.get(marker)))
+            .filter(|(marker, symbol)| *symbol != marker.default_symbol())
+    }
+
+    /// Width of the widest symbol, in characters
+    pub fn width(&self) -> usize {
+        self.symbols.iter().map(|symbol| symbol.chars().count()).max().unwrap_or(1)
+    }
+
+    /// Use these symbols for the rest of the process (the first call wins)
+    pub fn install(self) {
+        let _ = MARKER_SYMBOLS.set(self);
+    }
+
+    /// Legend lines: symbol, name and meaning of each marker
+    pub fn legend(&self) -> Vec<String> {
+        let width = self.width();
+        StatusMarker::ALL.iter()
+            .map(|marker| format!("{}  {:<9}  {}", pad(self.get(*marker), width), marker.name(), marker.description()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes_iec() {
+        assert_eq!(format_bytes_iec(512), "512 B");
+        assert_eq!(format_bytes_iec(1536), "1.50 KiB");
+        assert_eq!(format_bytes_iec(5 * 1024 * 1024 * 1024), "5.00 GiB");
+    }
+
+    #[test]
+    fn test_output_format() {
+        let entry = FileEntry {
+            path: "photos/2020/a.jpg".to_string(),
+            num_bytes: 2048,
+            modified: 1700000000000,
+            sha256: "abc123".to_string(),
+            mime: Some("image/jpeg".to_string()),
+            ..Default::default()
+        };
+        let format: OutputFormat = r"{path}\t{bytes}\t{sha256} {{{type}}} {name}".parse().unwrap();
+        assert_eq!(format.render_entry(&entry, "2020/a.jpg"), "2020/a.jpg\t2048\tabc123 {image/jpeg} a.jpg");
+        let format: OutputFormat = "{mtime},{modified}".parse().unwrap();
+        assert_eq!(format.render_entry(&entry, ""), "1700000000000,1700000000000");
+
+        let member = ArchiveMember {
+            archive: "backup.zip".to_string(),
+            member: "docs/notes.txt".to_string(),
+            num_bytes: 5,
+            sha256: "def456".to_string(),
+        };
+        let format: OutputFormat = "{path} {name} {size} {hash} [{modified}]".parse().unwrap();
+        assert_eq!(format.render_member(&member, "../backup.zip"), "../backup.zip!docs/notes.txt notes.txt 5 def456 []");
+
+        assert!("{path".parse::<OutputFormat>().unwrap_err().to_string().contains("Unclosed"));
+        assert!("{owner}".parse::<OutputFormat>().unwrap_err().to_string().contains("expected one of: path, name"));
+        assert!("a}b".parse::<OutputFormat>().unwrap_err().to_string().contains("Unmatched }"));
+        assert!("".parse::<OutputFormat>().is_ok());
+
+        let json: serde_json::Value = serde_json::from_str(&OutputFormat::Ndjson.render_entry(&entry, "2020/a.jpg")).unwrap();
+        assert_eq!(json["path"], "2020/a.jpg");
+        assert_eq!(json["num_bytes"], 2048);
+        assert_eq!(json["sha256"], "abc123");
+        assert_eq!(json["type"], "image/jpeg");
+        assert!(json["quick_hash"].is_null());
+        let json: serde_json::Value = serde_json::from_str(&OutputFormat::Ndjson.render_member(&member, "backup.zip")).unwrap();
+        assert_eq!(json["path"], "backup.zip!docs/notes.txt");
+        assert_eq!(json["member"], "docs/notes.txt");
+    }
+
+    #[test]
+    fn test_format_tree() {
+        let files = vec![
+            ("b.txt".to_string(), 10),
+            ("photos/2020/a.jpg".to_string(), 1024),
+            ("photos/2020/b.jpg".to_string(), 1024),
+            ("photos/c.jpg".to_string(), 100),
+            ("a.txt".to_string(), 5),
+        ];
+        assert_eq!(format_tree(".", &files), vec![
+            ". (5 file(s), 2.11 KB)",
+            "├── a.txt (5 bytes)",
+            "├── b.txt (10 bytes)",
+            "└── photos/ (3 file(s), 2.10 KB)",
+            "    ├── 2020/ (2 file(s), 2.00 KB)",
+            "    │   ├── a.jpg (1.00 KB)",
+            "    │   └── b.jpg (1.00 KB)",
+            "    └── c.jpg (100 bytes)",
+        ]);
+    }
+
+    #[test]
+    fn test_marker_symbols_legend() {
+        let mut symbols = MarkerSymbols::default();
+        assert_eq!(symbols.overrides().count(), 0);
+        symbols.set(StatusMarker::Added, "ADDED").unwrap();
+        symbols.set(StatusMarker::Deleted, "DELETED").unwrap();
+        assert!(symbols.set(StatusMarker::Ignored, "").is_err());
+
+        assert_eq!(symbols.overrides().collect::<Vec<_>>(), vec![
+            (StatusMarker::Added, "ADDED"),
+            (StatusMarker::Deleted, "DELETED"),
+        ]);
+        let legend = symbols.legend();
+        assert_eq!(legend[0], "ADDED    added      On disk but not in the index");
+        assert_eq!(legend[1], "U        updated    Content changed since it was indexed");
+        assert_eq!(StatusMarker::from_name("metadata").unwrap(), StatusMarker::Metadata);
+        assert!(StatusMarker::from_name("changed").is_err());
     }
 }
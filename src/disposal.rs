@@ -0,0 +1,48 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::file_utils::long_path;
+use crate::index::OCI_DIR;
+
+/// Where `prune` and `rm` move files: the pruneyard, where `prune --restore` and `--purge`
+/// manage them, or the system trash (`--trash` or `prune_to_trash=true`)
+pub enum Disposal {
+    Pruneyard(PathBuf),
+    Trash,
+}
+
+impl Disposal {
+    pub fn new(repo_root: &Path, trash: bool) -> Self {
+        if trash {
+            Disposal::Trash
+        } else {
+            Disposal::Pruneyard(repo_root.join(OCI_DIR).join("pruneyard"))
+        }
+    }
+
+    /// Move a file away; in the pruneyard it keeps its index path so it can be restored there
+    pub fn dispose(&self, file: &Path, index_path: &str) -> Result<()> {
+        match self {
+            Disposal::Pruneyard(pruneyard) => {
+                let dest_file = pruneyard.join(index_path);
+                if let Some(parent) = dest_file.parent() {
+                    fs::create_dir_all(long_path(parent))
+                        .context(format!("Failed to create directory: {}", parent.display()))?;
+                }
+                fs::rename(long_path(file), long_path(&dest_file))
+                    .context(format!("Failed to move file: {}", file.display()))
+            }
+            Disposal::Trash => trash::delete(file)
+                .map_err(|e| anyhow!("Failed to move file to the trash: {}: {}", file.display(), e)),
+        }
+    }
+
+    /// Where disposed files went, for summaries
+    pub fn destination(&self) -> &'static str {
+        match self {
+            Disposal::Pruneyard(_) => ".oci/pruneyard/",
+            Disposal::Trash => "the trash",
+        }
+    }
+}
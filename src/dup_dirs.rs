@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::index::{parent_dir, FileEntry};
+
+/// A directory whose every file has a copy in another directory, reported as one prune candidate
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateDir {
+    pub dir: String,
+    /// The directory holding a copy of everything in `dir`, which is kept
+    pub copy_of: String,
+    /// Whether both directories hold exactly the same content
+    pub identical: bool,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Everything indexed below one directory
+#[derive(Default)]
+struct DirContents<'a> {
+    /// Full hashes of its non-empty files (empty files carry no content to lose)
+    hashes: HashSet<&'a str>,
+    files: usize,
+    bytes: u64,
+    /// Whether every file below it has a full hash, without which it can't be proven a copy
+    hashed: bool,
+}
+
+/// Directories whose complete contents are duplicated in another directory, by path.
+///
+/// Only the topmost such directory is reported, and never one a reported directory relies on:
+/// removing every directory in the result keeps at least one copy of all their content. Of two
+/// identical directories at the same depth, the one later by path is the candidate.
+pub fn find(entries: &[FileEntry]) -> Vec<DuplicateDir> {
+    let mut dirs: HashMap<&str, DirContents> = HashMap::new();
+    for entry in entries {
+        let mut dir = parent_dir(&entry.path);
+        while !dir.is_empty() {
+            let contents = dirs.entry(dir).or_insert_with(|| DirContents { hashed: true, ..Default::default() });
+            contents.files += 1;
+            contents.bytes += entry.num_bytes;
+            if entry.sha256.is_empty() {
+                contents.hashed = false;
+            } else if entry.num_bytes > 0 {
+                contents.hashes.insert(&entry.sha256);
+            }
+            dir = parent_dir(dir);
+        }
+    }
+
+    // Directories holding each hash somewhere below them
+    let mut holders: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (dir, contents) in &dirs {
+        for hash in &contents.hashes {
+            holders.entry(hash).or_default().push(dir);
+        }
+    }
+
+    // Topmost directories first; among equals, later paths first
+    let mut candidates: Vec<&str> = dirs
+        .iter()
+        .filter(|(_, contents)| contents.hashed && contents.bytes > 0 && !contents.hashes.is_empty())
+        .map(|(dir, _)| *dir)
+        .collect();
+    candidates.sort_by(|a, b| depth(a).cmp(&depth(b)).then_with(|| b.cmp(a)));
+
+    let mut found: Vec<DuplicateDir> = Vec::new();
+    let mut kept: Vec<&str> = Vec::new();
+    for dir in candidates {
+        if found.iter().any(|dup| overlaps(dir, &dup.dir)) || kept.iter().any(|kept| overlaps(dir, kept)) {
+            continue;
+        }
+        let contents = &dirs[dir];
+        let Some(rarest) = contents.hashes.iter().min_by_key(|hash| holders[*hash].len()) else { continue };
+
+        // Prefer an identical copy, then the smallest and most specific directory holding everything
+        let mut covers: Vec<(&str, bool)> = holders[rarest]
+            .iter()
+            .filter(|other| !overlaps(dir, other))
+            .filter(|other| !found.iter().any(|dup| overlaps(other, &dup.dir)))
+            .filter(|other| contents.hashes.is_subset(&dirs[*other].hashes))
+            .map(|other| (*other, dirs[*other].hashes.len() == contents.hashes.len()))
+            .collect();
+        covers.sort_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then_with(|| dirs[a.0].bytes.cmp(&dirs[b.0].bytes))
+                .then_with(|| depth(b.0).cmp(&depth(a.0)))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        let Some(&(copy_of, identical)) = covers.first() else { continue };
+
+        kept.push(copy_of);
+        found.push(DuplicateDir {
+            dir: dir.to_string(),
+            copy_of: copy_of.to_string(),
+            identical,
+            files: contents.files,
+            bytes: contents.bytes,
+        });
+    }
+
+    found.sort_by(|a, b| a.dir.cmp(&b.dir));
+    found
+}
+
+/// One side of an `overlap` comparison
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OverlapSide {
+    pub files: usize,
+    /// Size of its distinct content, counting each hash once
+    pub distinct_bytes: u64,
+    /// Files whose content the other side lacks
+    pub unique_files: usize,
+    /// Size of the distinct content the other side lacks
+    pub unique_bytes: u64,
+    /// Files still awaiting a full hash, left out of the comparison
+    pub unhashed: usize,
+}
+
+/// How much content two sets of files share, by full hash
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overlap {
+    pub first: OverlapSide,
+    pub second: OverlapSide,
+    /// Distinct contents found on both sides, and their total size
+    pub shared_hashes: usize,
+    pub shared_bytes: u64,
+}
+
+/// Compare two sets of files (usually two directories, possibly from different indexes) by content
+pub fn overlap(first: &[FileEntry], second: &[FileEntry]) -> Overlap {
+    let distinct = |entries: &[FileEntry]| -> HashMap<String, u64> {
+        entries.iter().filter(|e| !e.sha256.is_empty()).map(|e| (e.sha256.clone(), e.num_bytes)).collect()
+    };
+    let (first_hashes, second_hashes) = (distinct(first), distinct(second));
+    let side = |entries: &[FileEntry], own: &HashMap<String, u64>, other: &HashMap<String, u64>| OverlapSide {
+        files: entries.len(),
+        distinct_bytes: own.values().sum(),
+        unique_files: entries.iter().filter(|e| !e.sha256.is_empty() && !other.contains_key(&e.sha256)).count(),
+        unique_bytes: own.iter().filter(|(hash, _)| !other.contains_key(*hash)).map(|(_, bytes)| bytes).sum(),
+        unhashed: entries.iter().filter(|e| e.sha256.is_empty()).count(),
+    };
+
+    let shared: Vec<u64> = first_hashes.iter().filter(|(hash, _)| second_hashes.contains_key(*hash)).map(|(_, bytes)| *bytes).collect();
+    Overlap {
+        first: side(first, &first_hashes, &second_hashes),
+        second: side(second, &second_hashes, &first_hashes),
+        shared_hashes: shared.len(),
+        shared_bytes: shared.iter().sum(),
+    }
+}
+
+fn depth(dir: &str) -> usize {
+    dir.matches('/').count()
+}
+
+/// Whether one directory is the other or inside it
+fn overlaps(a: &str, b: &str) -> bool {
+    let within = |inner: &str, outer: &str| inner.strip_prefix(outer).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+    within(a, b) || within(b, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, num_bytes: u64, sha256: &str) -> FileEntry {
+        FileEntry { path: path.to_string(), num_bytes, sha256: sha256.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_find_duplicate_dirs() {
+        let entries = vec![
+            entry("photos/2017/a.jpg", 10, "aa"),
+            entry("photos/2017/b.jpg", 20, "bb"),
+            entry("photos/2017/raw/c.cr2", 30, "cc"),
+            // A full copy of photos/2017, whose subdirectories aren't reported separately
+            entry("backup/2017/a.jpg", 10, "aa"),
+            entry("backup/2017/b.jpg", 20, "bb"),
+            entry("backup/2017/raw/c.cr2", 30, "cc"),
+            // Part of photos/2017 plus an empty file
+            entry("old/picks/b.jpg", 20, "bb"),
+            entry("old/picks/empty", 0, "e3"),
+            // Not fully hashed yet
+            entry("misc/a.jpg", 10, "aa"),
+            entry("misc/pending.jpg", 5, ""),
+            // Content found nowhere else
+            entry("unique/d.jpg", 40, "dd"),
+        ];
+
+        let found = find(&entries);
+        let summary: Vec<(&str, &str, bool)> = found.iter().map(|dup| (dup.dir.as_str(), dup.copy_of.as_str(), dup.identical)).collect();
+        // "old" is the topmost copy of old/picks; of the identical photos and backup, the later path is the candidate
+        assert_eq!(summary, vec![
+            ("old", "backup/2017", false),
+            ("photos", "backup/2017", true),
+        ]);
+        assert_eq!((found[0].files, found[0].bytes), (2, 20));
+    }
+
+    #[test]
+    fn test_find_never_removes_every_copy() {
+        // Each directory holds a copy of the other: only one of them is a candidate
+        let entries = vec![entry("a/x", 1, "11"), entry("b/x", 1, "11")];
+        let found = find(&entries);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].dir.as_str(), found[0].copy_of.as_str()), ("b", "a"));
+        assert!(overlaps("a/b", "a") && !overlaps("ab", "a"));
+    }
+
+    #[test]
+    fn test_overlap() {
+        let old = vec![entry("old/a", 10, "aa"), entry("old/a copy", 10, "aa"), entry("old/b", 20, "bb"), entry("old/new", 5, "")];
+        let current = vec![entry("photos/a", 10, "aa"), entry("photos/c", 40, "cc")];
+
+        let result = overlap(&old, &current);
+        assert_eq!((result.shared_hashes, result.shared_bytes), (1, 10));
+        assert_eq!(result.first, OverlapSide { files: 4, distinct_bytes: 30, unique_files: 1, unique_bytes: 20, unhashed: 1 });
+        assert_eq!(result.second, OverlapSide { files: 2, distinct_bytes: 50, unique_files: 1, unique_bytes: 40, unhashed: 0 });
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+/// Failures a caller can act on, as opposed to I/O and the like, which stay plain `anyhow`
+/// errors. They travel inside `anyhow::Error` (as the error or as context), so the context added
+/// on the way up is kept; `kind` finds one anywhere in an error's chain.
+#[derive(Debug)]
+pub enum OciError {
+    /// No `.oci` directory or root marker in the directory or any parent
+    NotARepository,
+    /// A path given to a command lies outside the repository and its roots
+    PathOutsideRepo(PathBuf),
+    /// The operation needs the index to match the files, and it doesn't
+    PendingChanges {
+        /// What was refused, e.g. "prune"
+        action: &'static str,
+        /// Location of the other repository whose index has the changes (None for this one)
+        source: Option<String>,
+    },
+    /// Another oci process holds the repository lock (its description)
+    Locked(String),
+    /// The index file can't be read as an index: damaged, or not an index at all
+    IndexCorrupt(PathBuf),
+    /// The index was written by a newer oci, with a schema this one doesn't know
+    IndexTooNew { version: u32, supported: u32 },
+}
+
+impl OciError {
+    /// Exit code of the CLI when a command fails with this error (1 for other errors)
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OciError::NotARepository => 3,
+            OciError::PathOutsideRepo(_) => 4,
+            OciError::PendingChanges { .. } => 5,
+            OciError::Locked(_) => 6,
+            OciError::IndexCorrupt(_) => 7,
+            OciError::IndexTooNew { .. } => 8,
+        }
+    }
+}
+
+impl std::fmt::Display for OciError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OciError::NotARepository => write!(f, "Not in an oci repository (or any parent directory)"),
+            OciError::PathOutsideRepo(path) => write!(f, "Path is outside repository: {}", path.display()),
+            OciError::PendingChanges { action, source: None } => write!(
+                f,
+                "Cannot {}: there are pending changes in the local index. Run 'oci status' to see changes.",
+                action
+            ),
+            OciError::PendingChanges { action, source: Some(source) } => write!(
+                f,
+                "Cannot {}: there are pending changes in the source index at {}. Run 'oci status' in the source directory to see changes.",
+                action, source
+            ),
+            OciError::Locked(holder) => write!(f, "{} (use --wait to wait for it)", holder),
+            OciError::IndexCorrupt(path) => write!(f, "The index {} is damaged or not an oci index", path.display()),
+            OciError::IndexTooNew { version, supported } => write!(
+                f,
+                "The index uses schema version {}, but this oci only supports up to version {}; upgrade oci to use it",
+                version, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for OciError {}
+
+/// The `OciError` behind an error, if any
+pub fn kind(err: &anyhow::Error) -> Option<&OciError> {
+    err.downcast_ref::<OciError>()
+}
+
+/// Exit code of the CLI for an error: the `OciError`'s code, or 1
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    kind(err).map_or(1, OciError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_kind_is_found_through_context() {
+        let err = anyhow::Error::new(OciError::NotARepository).context("Failed to list files");
+        assert!(matches!(kind(&err), Some(OciError::NotARepository)));
+        assert_eq!(exit_code(&err), 3);
+
+        let err = Err::<(), _>(anyhow::anyhow!("Corrupt index log at line 3"))
+            .context(OciError::IndexCorrupt(PathBuf::from(".oci/index.log")))
+            .context("Failed to open the index")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), 7);
+        assert!(format!("{:#}", err).contains("damaged"));
+
+        assert_eq!(exit_code(&anyhow::anyhow!("Failed to read file")), 1);
+    }
+}
@@ -1,18 +1,142 @@
 use sha2::{Sha256, Digest};
 use std::fs::{self, File};
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::borrow::Cow;
 use std::path::Path;
 use std::time::SystemTime;
 use anyhow::{Context, Result};
-use crate::index::FileEntry;
+use crate::config::{ChangeDetection, Config};
+use crate::digest::{self, DigestSet, Hasher};
+use crate::display;
+use crate::index::{ArchiveMember, FileEntry};
+
+/// Optional metadata captured alongside the hash when creating an entry
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureOptions {
+    pub xattrs: bool,
+    /// Detect each file's content type from its leading bytes
+    pub detect_types: bool,
+    /// Files at least this large get a quick fingerprint instead of a full hash (0 = disabled)
+    pub quick_hash_threshold: u64,
+    /// Digests computed alongside SHA256 in the same read
+    pub digests: DigestSet,
+}
+
+impl CaptureOptions {
+    /// Derive capture options from the repository config
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            xattrs: config.capture_xattrs,
+            detect_types: config.detect_types,
+            quick_hash_threshold: config.quick_hash_threshold,
+            digests: config.digests,
+        }
+    }
+
+    /// Whether a file of the given size should only be quick-hashed
+    pub fn use_quick_hash(&self, num_bytes: u64) -> bool {
+        self.quick_hash_threshold > 0 && num_bytes >= self.quick_hash_threshold
+    }
+}
+
+/// Bytes sampled from each end of a file for its quick hash
+const QUICK_HASH_SAMPLE: u64 = 4 * 1024 * 1024;
+
+/// Smallest and largest read buffers for hashing. Small files need no more than their size, while
+/// multi-gigabyte files on fast storage only keep the disk busy with reads of a megabyte or more.
+const MIN_HASH_BUFFER: u64 = 64 * 1024;
+const MAX_HASH_BUFFER: u64 = 4 * 1024 * 1024;
+
+/// Read buffer size for hashing a file (or an archive member): 1/64th of it (so even large files take dozens of reads,
+/// keeping throttling and progress smooth), between MIN_HASH_BUFFER and MAX_HASH_BUFFER
+pub fn hash_buffer_size(num_bytes: u64) -> usize {
+    (num_bytes / 64).clamp(MIN_HASH_BUFFER, MAX_HASH_BUFFER) as usize
+}
+
+/// The form of an absolute path that file APIs accept beyond MAX_PATH (260 characters): on
+/// Windows the `\\?\` (or `\\?\UNC\`) verbatim form, with `.` and `..` resolved and `/` turned
+/// into `\`, as Windows doesn't normalize verbatim paths. Relative and already verbatim paths are
+/// returned as they are, and so is every path elsewhere.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::path::{Component, PathBuf, Prefix};
+    let unc = match path.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => false,
+            Prefix::UNC(_, _) => true,
+            _ => return Cow::Borrowed(path),
+        },
+        _ => return Cow::Borrowed(path),
+    };
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    let Some(text) = normalized.to_str() else {
+        return Cow::Borrowed(path);
+    };
+    Cow::Owned(if unc {
+        // \\server\share\dir becomes \\?\UNC\server\share\dir
+        PathBuf::from(format!(r"\\?\UNC\{}", &text[2..]))
+    } else {
+        PathBuf::from(format!(r"\\?\{}", text))
+    })
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// The usual form of a path `long_path` (or `canonicalize`, which returns verbatim paths on
+/// Windows) made verbatim, for comparing it with paths that aren't
+#[cfg(windows)]
+pub fn short_path(path: &Path) -> Cow<'_, Path> {
+    match path.to_str() {
+        Some(text) if text.starts_with(r"\\?\UNC\") => Cow::Owned(format!(r"\\{}", &text[r"\\?\UNC\".len()..]).into()),
+        Some(text) if text.starts_with(r"\\?\") && text.as_bytes().get(5) == Some(&b':') => {
+            Cow::Borrowed(Path::new(&text[r"\\?\".len()..]))
+        }
+        _ => Cow::Borrowed(path),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn short_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
 
 /// Compute the SHA256 hash of a file
 pub fn compute_sha256(path: &Path) -> Result<String> {
-    let mut file = File::open(path)
+    compute_sha256_metered(path, |_| {})
+}
+
+/// Compute the SHA256 hash of a file, reporting the size of each block read (e.g. to throttle reads)
+pub fn compute_sha256_metered(path: &Path, on_read: impl FnMut(u64)) -> Result<String> {
+    Ok(compute_digests_metered(path, DigestSet::default(), on_read)?.0)
+}
+
+/// Compute the SHA256 hash of a file and the `extra` digests in a single read
+pub fn compute_digests(path: &Path, extra: DigestSet) -> Result<(String, Option<String>)> {
+    compute_digests_metered(path, extra, |_| {})
+}
+
+/// Compute the SHA256 hash and `extra` digests of a file, reporting the size of each block read
+#[tracing::instrument(name = "sha256", level = "debug", skip_all, fields(path = %path.display()))]
+pub fn compute_digests_metered(path: &Path, extra: DigestSet, mut on_read: impl FnMut(u64)) -> Result<(String, Option<String>)> {
+    let mut file = File::open(long_path(path))
         .context(format!("Failed to open file: {}", path.display()))?;
     
-    let mut hasher = Sha256::new();
-    let mut buffer = vec![0; 8192];
+    // Files that cannot report a size (or change while hashed) just get the smallest buffer
+    let num_bytes = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut hasher = Hasher::new(extra);
+    let mut buffer = vec![0; hash_buffer_size(num_bytes)];
     
     loop {
         let bytes_read = file.read(&mut buffer)
@@ -23,14 +147,54 @@ pub fn compute_sha256(path: &Path) -> Result<String> {
         }
         
         hasher.update(&buffer[..bytes_read]);
+        on_read(bytes_read as u64);
+    }
+    
+    Ok(hasher.finish())
+}
+
+/// Compute a quick fingerprint of a file: SHA256 over its size and the first and last
+/// QUICK_HASH_SAMPLE bytes. Identical quick hashes only make files duplicate *candidates*.
+#[tracing::instrument(name = "quick_hash", level = "debug", skip_all, fields(path = %path.display()))]
+pub fn compute_quick_hash(path: &Path) -> Result<String> {
+    let mut file = File::open(long_path(path))
+        .context(format!("Failed to open file: {}", path.display()))?;
+    let num_bytes = file.metadata()
+        .context(format!("Failed to get metadata for: {}", path.display()))?
+        .len();
+    
+    let mut hasher = Sha256::new();
+    hasher.update(num_bytes.to_le_bytes());
+    
+    let mut head = Vec::new();
+    (&mut file).take(QUICK_HASH_SAMPLE).read_to_end(&mut head)
+        .context("Failed to read file")?;
+    hasher.update(&head);
+    
+    if num_bytes > QUICK_HASH_SAMPLE {
+        let tail_start = num_bytes.saturating_sub(QUICK_HASH_SAMPLE).max(QUICK_HASH_SAMPLE);
+        file.seek(SeekFrom::Start(tail_start))
+            .context("Failed to seek in file")?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)
+            .context("Failed to read file")?;
+        hasher.update(&tail);
     }
     
     Ok(format!("{:x}", hasher.finalize()))
 }
 
+/// Get the current time in milliseconds since epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 /// Get the last modified time of a file in milliseconds since epoch
 pub fn get_modified_time(path: &Path) -> Result<u64> {
-    let metadata = fs::metadata(path)
+    let metadata = fs::metadata(long_path(path))
         .context(format!("Failed to get metadata for: {}", path.display()))?;
     
     let modified = metadata.modified()
@@ -44,50 +208,292 @@ pub fn get_modified_time(path: &Path) -> Result<u64> {
 
 /// Get the size of a file in bytes
 pub fn get_file_size(path: &Path) -> Result<u64> {
-    let metadata = fs::metadata(path)
+    let metadata = fs::metadata(long_path(path))
         .context(format!("Failed to get metadata for: {}", path.display()))?;
     
     Ok(metadata.len())
 }
 
+/// Bytes allocated to a file on disk (512-byte blocks on Unix; None elsewhere)
+#[cfg(unix)]
+pub fn get_allocated_size(path: &Path) -> Result<Option<u64>> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::metadata(path)
+        .context(format!("Failed to get metadata for: {}", path.display()))?;
+    Ok(Some(metadata.blocks() * 512))
+}
+
+#[cfg(not(unix))]
+pub fn get_allocated_size(_path: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Whether a file may be run as a program (any execute bit on Unix; every file elsewhere)
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(_path: &Path) -> bool {
+    true
+}
+
+/// Read the extended attributes of a file as a canonical `name=hash` list
+/// Attribute names are sorted and joined with ';', values are reduced to a short SHA256 prefix.
+/// Returns an empty string for files without extended attributes.
+#[cfg(unix)]
+pub fn read_xattrs(path: &Path) -> Result<String> {
+    let names = xattr::list(path)
+        .context(format!("Failed to list extended attributes for: {}", path.display()))?;
+    
+    let mut attrs = Vec::new();
+    for name in names {
+        let value = xattr::get(path, &name)
+            .context(format!("Failed to read extended attribute for: {}", path.display()))?
+            .unwrap_or_default();
+        attrs.push((name.to_string_lossy().to_string(), value));
+    }
+    
+    Ok(format_xattrs(attrs))
+}
+
+/// Extended attributes are not supported on this platform
+#[cfg(not(unix))]
+pub fn read_xattrs(_path: &Path) -> Result<String> {
+    Ok(String::new())
+}
+
+/// Format attribute names and values as a sorted `name=hash` list
+fn format_xattrs(mut attrs: Vec<(String, Vec<u8>)>) -> String {
+    attrs.sort_by(|a, b| a.0.cmp(&b.0));
+    attrs.iter()
+        .map(|(name, value)| {
+            let digest = format!("{:x}", Sha256::digest(value));
+            format!("{}={}", name, &digest[..16])
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
 /// Create a FileEntry from a file path
-pub fn create_file_entry(path: &Path, relative_path: String) -> Result<FileEntry> {
+pub fn create_file_entry(path: &Path, relative_path: String, options: CaptureOptions) -> Result<FileEntry> {
+    create_file_entry_with_hash(path, relative_path, options, None)
+}
+
+/// Create a FileEntry, using a full hash already known for the file's contents instead of reading them
+pub fn create_file_entry_with_hash(
+    path: &Path,
+    relative_path: String,
+    options: CaptureOptions,
+    known_sha256: Option<String>,
+) -> Result<FileEntry> {
     let num_bytes = get_file_size(path)?;
     let modified = get_modified_time(path)?;
-    let sha256 = compute_sha256(path)?;
+    // Large files may only get a quick fingerprint; the full hash is computed on demand. A known
+    // hash saves no read when other digests are wanted too.
+    let (sha256, quick_hash, digests) = match known_sha256 {
+        Some(sha256) if options.digests.is_empty() => (sha256, None, None),
+        None if options.use_quick_hash(num_bytes) => (String::new(), Some(compute_quick_hash(path)?), None),
+        _ => {
+            let (sha256, digests) = compute_digests(path, options.digests)?;
+            (sha256, None, digests)
+        }
+    };
+    let xattrs = if options.xattrs {
+        Some(read_xattrs(path)?)
+    } else {
+        None
+    };
+    let mime = if options.detect_types {
+        Some(detect_type(path)?)
+    } else {
+        None
+    };
     
     Ok(FileEntry {
         num_bytes,
         modified,
         sha256,
         path: relative_path,
+        xattrs,
+        quick_hash,
+        mime,
+        allocated: get_allocated_size(path)?,
+        digests,
     })
 }
 
+/// Bytes read from the start of a file to detect its content type
+const TYPE_SAMPLE: usize = 8192;
+
+/// Detect a file's content type (MIME type) from its leading bytes, like libmagic
+/// Files without a known signature are "text/plain" if the sample looks like UTF-8 text,
+/// otherwise "application/octet-stream"; empty files are "inode/x-empty".
+pub fn detect_type(path: &Path) -> Result<String> {
+    let file = File::open(long_path(path))
+        .context(format!("Failed to open file: {}", path.display()))?;
+    let mut sample = Vec::with_capacity(TYPE_SAMPLE);
+    file.take(TYPE_SAMPLE as u64).read_to_end(&mut sample)
+        .context("Failed to read file")?;
+    Ok(type_of_sample(&sample).to_string())
+}
+
+fn type_of_sample(sample: &[u8]) -> &'static str {
+    if sample.is_empty() {
+        return "inode/x-empty";
+    }
+    if let Some(kind) = infer::get(sample) {
+        return kind.mime_type();
+    }
+    // A multi-byte character may be cut off at the end of the sample
+    let text = match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if text && !sample.contains(&0) {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Compare two files byte for byte, streaming both (for `--paranoid` checks before removing a copy)
+pub fn same_content(a: &Path, b: &Path) -> Result<bool> {
+    let (long_a, long_b) = (long_path(a), long_path(b));
+    if fs::metadata(&long_a)?.len() != fs::metadata(&long_b)?.len() {
+        return Ok(false);
+    }
+    let mut file_a = File::open(&long_a).context(format!("Failed to open file: {}", a.display()))?;
+    let mut file_b = File::open(&long_b).context(format!("Failed to open file: {}", b.display()))?;
+    let mut buffer_a = vec![0; 64 * 1024];
+    let mut buffer_b = vec![0; 64 * 1024];
+
+    loop {
+        let bytes_read = file_a.read(&mut buffer_a).context("Failed to read file")?;
+        if bytes_read == 0 {
+            // Both files had the same length, unless b grew meanwhile
+            return Ok(file_b.read(&mut buffer_b[..1]).context("Failed to read file")? == 0);
+        }
+        if file_b.read_exact(&mut buffer_b[..bytes_read]).is_err() || buffer_a[..bytes_read] != buffer_b[..bytes_read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Copy a file, keeping its modified time (so e.g. photo dates survive an import)
+pub fn copy_preserving_mtime(src: &Path, dst: &Path) -> Result<()> {
+    let (long_src, long_dst) = (long_path(src), long_path(dst));
+    let copy = fs::copy(&long_src, &long_dst).and_then(|_| {
+        let modified = fs::metadata(&long_src)?.modified()?;
+        File::options().write(true).open(&long_dst)?.set_modified(modified)
+    });
+    if let Err(e) = copy {
+        // Don't leave a partial copy behind to be indexed by the next update
+        let _ = fs::remove_file(&long_dst);
+        return Err(e).context(format!("Failed to copy {} to {}", src.display(), dst.display()));
+    }
+    Ok(())
+}
+
 /// Check if a file has changed based on size and modified time
-pub fn has_changed(entry: &FileEntry, file_path: &Path) -> Result<bool> {
+/// With the `Hash` policy, files whose metadata matches are re-hashed to catch
+/// edits that preserve both size and modified time.
+pub fn has_changed(entry: &FileEntry, file_path: &Path, policy: ChangeDetection) -> Result<bool> {
     let current_size = get_file_size(file_path)?;
     let current_modified = get_modified_time(file_path)?;
     
-    Ok(current_size != entry.num_bytes || current_modified != entry.modified)
+    if current_size != entry.num_bytes || current_modified != entry.modified {
+        return Ok(true);
+    }
+    
+    match policy {
+        ChangeDetection::Metadata => Ok(false),
+        // Entries awaiting a full hash can only be compared by fingerprint
+        ChangeDetection::Hash if entry.sha256.is_empty() => {
+            Ok(entry.quick_hash.as_deref() != Some(compute_quick_hash(file_path)?.as_str()))
+        }
+        ChangeDetection::Hash => Ok(compute_sha256(file_path)? != entry.sha256),
+    }
+}
+
+/// Check if a file's extended attributes differ from those recorded in the index
+/// Always false when xattr capture is disabled.
+pub fn xattrs_changed(entry: &FileEntry, file_path: &Path, options: CaptureOptions) -> Result<bool> {
+    if !options.xattrs {
+        return Ok(false);
+    }
+    
+    let current = read_xattrs(file_path)?;
+    Ok(entry.xattrs.as_deref() != Some(current.as_str()))
+}
+
+/// Check if a fully hashed entry lacks any of the configured extra digests (e.g. `digests` was
+/// set after it was indexed)
+pub fn digests_missing(entry: &FileEntry, options: CaptureOptions) -> bool {
+    !entry.sha256.is_empty() && digest::missing(entry.digests.as_deref(), options.digests)
+}
+
+/// Hash shown for an entry: the full SHA256, or `quick:<fingerprint>` while the full hash is pending
+pub fn display_hash(entry: &FileEntry) -> String {
+    match (&entry.quick_hash, entry.sha256.is_empty()) {
+        (Some(quick_hash), true) => format!("quick:{}", quick_hash),
+        _ => entry.sha256.clone(),
+    }
 }
 
 /// Format a FileEntry for display
 pub fn format_entry(entry: &FileEntry) -> String {
     format!("{:>10} {:>15} {} {}", 
-        entry.num_bytes,
-        entry.modified,
-        entry.sha256,
+        display::format_size(entry.num_bytes),
+        display::format_time(entry.modified),
+        display_hash(entry),
         entry.path
     )
 }
 
+/// Format an archive member like an entry, as `archive!member` (members have no modified time)
+pub fn format_member(member: &ArchiveMember) -> String {
+    format!("{:>10} {:>15} {} {}!{}",
+        display::format_size(member.num_bytes),
+        "(archived)",
+        member.sha256,
+        member.archive,
+        member.member
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
+    #[cfg(windows)]
+    #[test]
+    fn test_long_and_short_paths() {
+        let long = long_path(Path::new(r"C:\data/photos\.\2024\..\2023\a.jpg"));
+        assert_eq!(long, Path::new(r"\\?\C:\data\photos\2023\a.jpg"));
+        assert_eq!(short_path(&long), Path::new(r"C:\data\photos\2023\a.jpg"));
+        let unc = long_path(Path::new(r"\\nas\share\a.jpg"));
+        assert_eq!(unc, Path::new(r"\\?\UNC\nas\share\a.jpg"));
+        assert_eq!(short_path(&unc), Path::new(r"\\nas\share\a.jpg"));
+        assert_eq!(long_path(Path::new(r"photos\a.jpg")), Path::new(r"photos\a.jpg"));
+        assert_eq!(long_path(&long), long);
+    }
+
+    #[test]
+    fn test_type_of_sample() {
+        assert_eq!(type_of_sample(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(type_of_sample(b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(type_of_sample("caf\u{e9} notes\n".as_bytes()), "text/plain");
+        // A character cut off by the end of the sample is still text
+        assert_eq!(type_of_sample(&"caf\u{e9}".as_bytes()[..4]), "text/plain");
+        assert_eq!(type_of_sample(b"\x00\x01\x02\xff"), "application/octet-stream");
+        assert_eq!(type_of_sample(b""), "inode/x-empty");
+    }
+
     #[test]
     fn test_compute_sha256() -> Result<()> {
         let mut temp_file = NamedTempFile::new()?;
@@ -101,6 +507,42 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_compute_sha256_across_buffers() -> Result<()> {
+        assert_eq!(hash_buffer_size(0), 64 * 1024);
+        assert_eq!(hash_buffer_size(128 * 1024 * 1024), 2 * 1024 * 1024);
+        assert_eq!(hash_buffer_size(u64::MAX), 4 * 1024 * 1024);
+
+        // Several full buffers and a partial one hash the same as the content in one piece
+        let mut temp_file = NamedTempFile::new()?;
+        let bytes: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+        temp_file.write_all(&bytes)?;
+        temp_file.flush()?;
+        let mut reads = 0;
+        let hash = compute_sha256_metered(temp_file.path(), |_| reads += 1)?;
+        assert_eq!(hash, format!("{:x}", Sha256::digest(&bytes)));
+        assert!(reads >= 5, "got {} reads", reads);
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_content() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let (a, b, c, d) = (dir.path().join("a"), dir.path().join("b"), dir.path().join("c"), dir.path().join("d"));
+        let bytes: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&a, &bytes)?;
+        fs::write(&b, &bytes)?;
+        let mut changed = bytes.clone();
+        changed[150_000] ^= 1;
+        fs::write(&c, &changed)?;
+        fs::write(&d, &bytes[..1000])?;
+
+        assert!(same_content(&a, &b)?);
+        assert!(!same_content(&a, &c)?);
+        assert!(!same_content(&a, &d)?);
+        Ok(())
+    }
+
     #[test]
     fn test_get_file_size() -> Result<()> {
         let mut temp_file = NamedTempFile::new()?;
@@ -112,4 +554,67 @@ mod tests {
         
         Ok(())
     }
+
+    #[test]
+    fn test_has_changed_hash_policy_detects_same_size_edit() -> Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(b"hello")?;
+        temp_file.flush()?;
+        
+        // Entry with matching metadata but a stale hash
+        let entry = FileEntry {
+            num_bytes: get_file_size(temp_file.path())?,
+            modified: get_modified_time(temp_file.path())?,
+            sha256: "stale".to_string(),
+            path: "file.txt".to_string(),
+            ..Default::default()
+        };
+        
+        assert!(!has_changed(&entry, temp_file.path(), ChangeDetection::Metadata)?);
+        assert!(has_changed(&entry, temp_file.path(), ChangeDetection::Hash)?);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_quick_hash_samples_ends_and_size() -> Result<()> {
+        let sample = QUICK_HASH_SAMPLE as usize;
+        let base = vec![b'a'; sample * 3];
+        
+        // A change in the middle is invisible to the quick hash...
+        let mut middle_changed = base.clone();
+        middle_changed[sample + 10] = b'b';
+        // ...but a change at either end is not
+        let mut tail_changed = base.clone();
+        tail_changed[sample * 3 - 1] = b'b';
+        
+        let hash_of = |data: &[u8]| -> Result<String> {
+            let mut temp_file = NamedTempFile::new()?;
+            temp_file.write_all(data)?;
+            temp_file.flush()?;
+            compute_quick_hash(temp_file.path())
+        };
+        
+        assert_eq!(hash_of(&base)?, hash_of(&middle_changed)?);
+        assert_ne!(hash_of(&base)?, hash_of(&tail_changed)?);
+        assert_ne!(hash_of(&base)?, hash_of(&base[..sample * 2])?);
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_xattrs_is_sorted_and_hashed() {
+        let attrs = vec![
+            ("user.b".to_string(), b"two".to_vec()),
+            ("user.a".to_string(), b"one".to_vec()),
+        ];
+        let formatted = format_xattrs(attrs);
+        
+        let parts: Vec<_> = formatted.split(';').collect();
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].starts_with("user.a="));
+        assert!(parts[1].starts_with("user.b="));
+        assert_eq!(parts[0].len(), "user.a=".len() + 16);
+        assert_eq!(format_xattrs(Vec::new()), "");
+    }
 }
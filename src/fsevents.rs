@@ -0,0 +1,258 @@
+use anyhow::{Context, Result};
+use std::ffi::{c_char, c_void, CStr};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::changes::{self, ChangedDir, Cursor};
+
+/// Backend recorded in cursors taken from FSEvents (replayed for `update --fast` on macOS)
+const BACKEND: &str = "fsevents";
+
+/// Give up on a replay (and scan everything) when no events arrive for this long
+const REPLAY_STALL: Duration = Duration::from_secs(30);
+
+type CFRef = *const c_void;
+type CFIndex = isize;
+
+const UTF8_ENCODING: u32 = 0x0800_0100;
+const CREATE_FLAG_WATCH_ROOT: u32 = 0x4;
+
+const EVENT_MUST_SCAN_SUB_DIRS: u32 = 0x1;
+const EVENT_IDS_WRAPPED: u32 = 0x8;
+const EVENT_HISTORY_DONE: u32 = 0x10;
+const EVENT_ROOT_CHANGED: u32 = 0x20;
+const EVENT_UNMOUNT: u32 = 0x80;
+
+#[repr(C)]
+struct CFArrayCallBacks {
+    version: CFIndex,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+    equal: *const c_void,
+}
+
+#[repr(C)]
+struct StreamContext {
+    version: CFIndex,
+    info: *mut c_void,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+}
+
+type StreamCallback = extern "C" fn(CFRef, *mut c_void, usize, *mut c_void, *const u32, *const u64);
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    static kCFTypeArrayCallBacks: CFArrayCallBacks;
+    static kCFRunLoopDefaultMode: CFRef;
+    fn CFRelease(cf: CFRef);
+    fn CFStringCreateWithBytes(alloc: CFRef, bytes: *const u8, len: CFIndex, encoding: u32, external: u8) -> CFRef;
+    fn CFStringGetCString(string: CFRef, buffer: *mut c_char, size: CFIndex, encoding: u32) -> u8;
+    fn CFArrayCreate(alloc: CFRef, values: *const CFRef, len: CFIndex, callbacks: *const CFArrayCallBacks) -> CFRef;
+    fn CFUUIDCreateString(alloc: CFRef, uuid: CFRef) -> CFRef;
+    fn CFRunLoopGetCurrent() -> CFRef;
+    fn CFRunLoopRunInMode(mode: CFRef, seconds: f64, return_after_source_handled: u8) -> i32;
+}
+
+#[link(name = "CoreServices", kind = "framework")]
+extern "C" {
+    fn FSEventsGetCurrentEventId() -> u64;
+    fn FSEventsCopyUUIDForDevice(dev: libc::dev_t) -> CFRef;
+    fn FSEventStreamCreate(
+        alloc: CFRef,
+        callback: StreamCallback,
+        context: *mut StreamContext,
+        paths: CFRef,
+        since_when: u64,
+        latency: f64,
+        flags: u32,
+    ) -> CFRef;
+    fn FSEventStreamScheduleWithRunLoop(stream: CFRef, run_loop: CFRef, mode: CFRef);
+    fn FSEventStreamStart(stream: CFRef) -> u8;
+    fn FSEventStreamStop(stream: CFRef);
+    fn FSEventStreamInvalidate(stream: CFRef);
+    fn FSEventStreamRelease(stream: CFRef);
+}
+
+/// The current event ID, tagged with the UUID of the event store of the volume holding `dir`
+pub fn current(dir: &Path) -> Result<Option<Cursor>> {
+    let Some(volume) = volume_uuid(dir)? else {
+        return Ok(None);
+    };
+    let position = unsafe { FSEventsGetCurrentEventId() };
+    Ok(Some(Cursor { backend: BACKEND.to_string(), volume, position }))
+}
+
+/// Directories below `dir` with events after `cursor`, replayed from the volume's event store
+pub fn changed_since(dir: &Path, cursor: &Cursor) -> Result<Option<Vec<ChangedDir>>> {
+    if cursor.backend != BACKEND || volume_uuid(dir)?.as_deref() != Some(cursor.volume.as_str()) {
+        return Ok(None);
+    }
+    if cursor.position > unsafe { FSEventsGetCurrentEventId() } {
+        return Ok(None);
+    }
+    // Events carry resolved paths (e.g. /private/var rather than /var)
+    let canonical = fs::canonicalize(dir).context(format!("Failed to resolve {}", dir.display()))?;
+    let Some(events) = replay(&canonical, cursor.position) else {
+        return Ok(None);
+    };
+
+    let mut dirs = Vec::new();
+    for (path, flags) in events {
+        if flags & (EVENT_IDS_WRAPPED | EVENT_ROOT_CHANGED | EVENT_UNMOUNT) != 0 {
+            return Ok(None);
+        }
+        let recursive = flags & EVENT_MUST_SCAN_SUB_DIRS != 0;
+        match path.strip_prefix(&canonical) {
+            Ok(relative) if relative.as_os_str().is_empty() && recursive => return Ok(None),
+            Ok(relative) => dirs.push(ChangedDir { path: dir.join(relative), recursive }),
+            // Dropped events reported for an ancestor (e.g. `/` when the history was purged)
+            Err(_) if recursive && canonical.starts_with(&path) => return Ok(None),
+            Err(_) => {}
+        }
+    }
+    Ok(Some(dirs))
+}
+
+/// Polls the FSEvents history of a set of trees, from the event ID of the previous poll. The
+/// history is replayed rather than streamed live, which keeps the run loop out of `collect`'s callers.
+pub struct Watcher {
+    cursors: Vec<(PathBuf, Cursor)>,
+}
+
+impl Watcher {
+    /// Start from the current event ID of each root; FSEvents watches whole trees, so nothing is skipped
+    pub fn start(roots: &[PathBuf], _skip_dir: Box<dyn Fn(&Path) -> bool>) -> Result<Self> {
+        let mut cursors = Vec::new();
+        for root in roots {
+            match current(root)? {
+                Some(cursor) => cursors.push((root.clone(), cursor)),
+                None => anyhow::bail!("FSEvents keeps no history for {} (e.g. a network volume)", root.display()),
+            }
+        }
+        Ok(Watcher { cursors })
+    }
+
+    /// Number of trees watched
+    pub fn watched_dirs(&self) -> usize {
+        self.cursors.len()
+    }
+
+    /// Wait for `period`, then return the directories changed since the previous call
+    pub fn collect(&mut self, period: Duration) -> Result<Vec<ChangedDir>> {
+        std::thread::sleep(period);
+        let mut dirs = Vec::new();
+        for (root, cursor) in &mut self.cursors {
+            let next = current(root)?;
+            match changed_since(root, cursor)? {
+                Some(changed) => dirs.extend(changed),
+                None => dirs.push(ChangedDir { path: root.clone(), recursive: true }),
+            }
+            if let Some(next) = next {
+                *cursor = next;
+            }
+        }
+        Ok(changes::collapse(dirs))
+    }
+}
+
+fn volume_uuid(dir: &Path) -> Result<Option<String>> {
+    let dev = fs::metadata(dir).context(format!("Failed to stat {}", dir.display()))?.dev();
+    unsafe {
+        let uuid = FSEventsCopyUUIDForDevice(dev as libc::dev_t);
+        if uuid.is_null() {
+            return Ok(None);
+        }
+        let string = CFUUIDCreateString(std::ptr::null(), uuid);
+        CFRelease(uuid);
+        if string.is_null() {
+            return Ok(None);
+        }
+        let mut buffer = [0 as c_char; 64];
+        let ok = CFStringGetCString(string, buffer.as_mut_ptr(), buffer.len() as CFIndex, UTF8_ENCODING);
+        CFRelease(string);
+        Ok((ok != 0).then(|| CStr::from_ptr(buffer.as_ptr()).to_string_lossy().into_owned()))
+    }
+}
+
+struct Replay {
+    events: Vec<(PathBuf, u32)>,
+    done: bool,
+}
+
+extern "C" fn on_events(
+    _stream: CFRef,
+    info: *mut c_void,
+    count: usize,
+    paths: *mut c_void,
+    flags: *const u32,
+    _ids: *const u64,
+) {
+    let replay = unsafe { &mut *(info as *mut Replay) };
+    let paths = paths as *const *const c_char;
+    for i in 0..count {
+        let (path, flags) = unsafe { (CStr::from_ptr(*paths.add(i)), *flags.add(i)) };
+        if flags & EVENT_HISTORY_DONE != 0 {
+            replay.done = true;
+            continue;
+        }
+        let path = Path::new(std::ffi::OsStr::from_bytes(path.to_bytes()));
+        replay.events.push((path.components().collect(), flags));
+    }
+}
+
+/// Run a stream over `dir` from `since` until it reports the end of the stored history
+fn replay(dir: &Path, since: u64) -> Option<Vec<(PathBuf, u32)>> {
+    let replay = Box::into_raw(Box::new(Replay { events: Vec::new(), done: false }));
+    let mut context = StreamContext {
+        version: 0,
+        info: replay as *mut c_void,
+        retain: std::ptr::null(),
+        release: std::ptr::null(),
+        copy_description: std::ptr::null(),
+    };
+    let finished = unsafe {
+        let bytes = dir.as_os_str().as_bytes();
+        let path = CFStringCreateWithBytes(std::ptr::null(), bytes.as_ptr(), bytes.len() as CFIndex, UTF8_ENCODING, 0);
+        let paths = CFArrayCreate(std::ptr::null(), &path, 1, std::ptr::addr_of!(kCFTypeArrayCallBacks));
+        CFRelease(path);
+        let stream = FSEventStreamCreate(
+            std::ptr::null(),
+            on_events,
+            &mut context,
+            paths,
+            since,
+            0.0,
+            CREATE_FLAG_WATCH_ROOT,
+        );
+        CFRelease(paths);
+
+        let mut finished = false;
+        if !stream.is_null() {
+            FSEventStreamScheduleWithRunLoop(stream, CFRunLoopGetCurrent(), kCFRunLoopDefaultMode);
+            if FSEventStreamStart(stream) != 0 {
+                let mut seen = 0;
+                let mut last_progress = Instant::now();
+                while !(*replay).done && last_progress.elapsed() < REPLAY_STALL {
+                    CFRunLoopRunInMode(kCFRunLoopDefaultMode, 0.25, 0);
+                    if (*replay).events.len() != seen {
+                        seen = (*replay).events.len();
+                        last_progress = Instant::now();
+                    }
+                }
+                finished = (*replay).done;
+                FSEventStreamStop(stream);
+            }
+            FSEventStreamInvalidate(stream);
+            FSEventStreamRelease(stream);
+        }
+        finished
+    };
+    let replay = unsafe { Box::from_raw(replay) };
+    finished.then_some(replay.events)
+}
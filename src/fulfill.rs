@@ -0,0 +1,226 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::index::{FileEntry, Index};
+
+/// Checksums of the delivered files, in sha256sum format (`sha256sum -c SHA256SUMS`)
+const MANIFEST_FILE: &str = "SHA256SUMS";
+/// Requested files that could not be delivered, with the reason
+const MISSING_FILE: &str = "MISSING.txt";
+
+/// A requested file, identified by its content hash, its index path, or both
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestedFile {
+    pub sha256: Option<String>,
+    pub path: Option<String>,
+}
+
+impl RequestedFile {
+    /// How the request is shown in messages
+    pub fn describe(&self) -> &str {
+        self.path.as_deref().or(self.sha256.as_deref()).unwrap_or_default()
+    }
+}
+
+/// Parse a request file: one `<sha256>  <path>`, `<sha256>` or `<path>` per line,
+/// as downloaded from a published catalog. Blank lines, `#` comments and repeats are skipped.
+pub fn parse_request(contents: &str) -> Vec<RequestedFile> {
+    let mut requests: Vec<RequestedFile> = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        let request = match (line.get(..64), line.get(64..)) {
+            (Some(hash), Some(rest)) if is_sha256(hash) && (rest.is_empty() || rest.starts_with("  ")) => {
+                // sha256sum marks binary mode with '*' before the path
+                let path = rest.trim_start_matches(' ').trim_start_matches('*');
+                RequestedFile {
+                    sha256: Some(hash.to_ascii_lowercase()),
+                    path: Some(path.to_string()).filter(|p| !p.is_empty()),
+                }
+            }
+            _ => RequestedFile { sha256: None, path: Some(line.trim().trim_start_matches('/').to_string()) },
+        };
+        if !requests.contains(&request) {
+            requests.push(request);
+        }
+    }
+    requests
+}
+
+fn is_sha256(text: &str) -> bool {
+    text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Index entries that may satisfy a request, best first: the entry at the requested path
+/// (if its content matches), then any other file with the requested content
+pub fn candidates(index: &Index, request: &RequestedFile) -> Result<Vec<FileEntry>> {
+    let mut candidates = Vec::new();
+    if let Some(path) = &request.path {
+        if let Some(entry) = index.get(path)? {
+            let matches = match &request.sha256 {
+                Some(hash) => entry.sha256.is_empty() || entry.sha256 == *hash,
+                None => true,
+            };
+            if matches {
+                candidates.push(entry);
+            }
+        }
+    }
+    if let Some(hash) = &request.sha256 {
+        for entry in index.find_by_hash(hash)? {
+            if !candidates.iter().any(|c| c.path == entry.path) {
+                candidates.push(entry);
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+/// A zip archive of delivered files with a checksum manifest
+/// Files are stored uncompressed: archives of photos and videos gain nothing from deflate.
+pub struct Delivery {
+    writer: ZipWriter<File>,
+    /// (sha256, archive name) of each delivered file
+    manifest: Vec<(String, String)>,
+    names: HashSet<String>,
+    pub total_bytes: u64,
+}
+
+impl Delivery {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .context(format!("Failed to create {}", path.display()))?;
+        Ok(Delivery { writer: ZipWriter::new(file), manifest: Vec::new(), names: HashSet::new(), total_bytes: 0 })
+    }
+
+    /// Number of files delivered so far
+    pub fn len(&self) -> usize {
+        self.manifest.len()
+    }
+
+    /// Whether an archive name has already been delivered
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    /// Copy a file into the archive, hashing it as it is read
+    /// If the content does not match `expected`, the file is taken out of the archive again
+    /// and false is returned, so a damaged copy is never delivered.
+    pub fn add(&mut self, name: &str, source: &Path, modified: u64, expected: Option<&str>) -> Result<bool> {
+        let mut file = File::open(source)
+            .context(format!("Failed to open file: {}", source.display()))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(size >= u32::MAX as u64);
+        if let Some(time) = zip_time(modified) {
+            options = options.last_modified_time(time);
+        }
+        self.writer.start_file(name, options)
+            .context(format!("Failed to add {} to the archive", name))?;
+
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let bytes_read = file.read(&mut buffer)
+                .context(format!("Failed to read file: {}", source.display()))?;
+            if bytes_read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..bytes_read]);
+            self.writer.write_all(&buffer[..bytes_read])
+                .context("Failed to write to the archive")?;
+        }
+
+        let sha256 = format!("{:x}", hasher.finalize());
+        if expected.is_some_and(|hash| hash != sha256) {
+            self.writer.abort_file().context("Failed to drop a file from the archive")?;
+            return Ok(false);
+        }
+        self.names.insert(name.to_string());
+        self.manifest.push((sha256, name.to_string()));
+        self.total_bytes += size;
+        Ok(true)
+    }
+
+    /// Write the manifest (and the list of undelivered files, if any) and close the archive
+    pub fn finish(mut self, undelivered: &[String]) -> Result<()> {
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let manifest: String = self.manifest.iter()
+            .map(|(sha256, name)| format!("{}  {}\n", sha256, name))
+            .collect();
+        self.writer.start_file(MANIFEST_FILE, options)?;
+        self.writer.write_all(manifest.as_bytes())?;
+
+        if !undelivered.is_empty() {
+            self.writer.start_file(MISSING_FILE, options)?;
+            self.writer.write_all(format!("{}\n", undelivered.join("\n")).as_bytes())?;
+        }
+        self.writer.finish().context("Failed to finish the archive")?;
+        Ok(())
+    }
+}
+
+/// Zip timestamp (in UTC, as zip times carry no zone) for milliseconds since epoch, if zip can represent it
+fn zip_time(millis: u64) -> Option<zip::DateTime> {
+    let secs = (millis / 1000) as i64;
+    let (days, time) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    // Civil date from days since 1970-01-01 (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    zip::DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month as u8,
+        day as u8,
+        (time / 3600) as u8,
+        (time % 3600 / 60) as u8,
+        (time % 60) as u8,
+    ).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let hash = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let contents = format!(
+            "# oci request from Photos\n{hash}  photos/a b.jpg\n\n{upper}\nnotes/todo.txt\r\n{hash}  photos/a b.jpg\n",
+            hash = hash,
+            upper = hash.to_uppercase(),
+        );
+        assert_eq!(parse_request(&contents), vec![
+            RequestedFile { sha256: Some(hash.to_string()), path: Some("photos/a b.jpg".to_string()) },
+            RequestedFile { sha256: Some(hash.to_string()), path: None },
+            RequestedFile { sha256: None, path: Some("notes/todo.txt".to_string()) },
+        ]);
+    }
+
+    #[test]
+    fn test_zip_time() {
+        // Zip times have a resolution of two seconds
+        let time = zip_time(951_782_400_000 + 3_724_000).unwrap();
+        assert_eq!((time.year(), time.month(), time.day()), (2000, 2, 29));
+        assert_eq!((time.hour(), time.minute(), time.second()), (1, 2, 4));
+        assert!(zip_time(0).is_none());
+    }
+}
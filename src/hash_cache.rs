@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+use crate::file_utils;
+use crate::index::OCI_DIR;
+
+/// Hashes by inode, next to the index in the `.oci` directory
+/// It is a cache rather than part of the index: deleting it only costs re-hashing.
+pub const HASH_CACHE_FILE: &str = "hashcache.db";
+
+/// Identity of a file's contents as far as the filesystem can tell without reading them:
+/// the same inode on the same device, not modified since and of the same size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeKey {
+    device: u64,
+    inode: u64,
+    modified: u64,
+    num_bytes: u64,
+}
+
+impl InodeKey {
+    /// The key of a file, or None where the platform has no inode numbers
+    #[cfg(unix)]
+    pub fn of(path: &Path) -> Result<Option<Self>> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(path).context(format!("Failed to read metadata: {}", path.display()))?;
+        Ok(Some(InodeKey {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            modified: file_utils::get_modified_time(path)?,
+            num_bytes: metadata.len(),
+        }))
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_path: &Path) -> Result<Option<Self>> {
+        Ok(None)
+    }
+}
+
+/// Full hashes of files by inode, so `update` can reuse them for files that were moved or
+/// renamed outside oci instead of reading them again
+pub struct HashCache {
+    conn: Connection,
+    /// Entries stored or looked up during this run get this generation; older ones can be forgotten
+    generation: i64,
+}
+
+impl HashCache {
+    /// Open (or create) the cache of a repository, starting a transaction committed by `finish`
+    pub fn open(repo_root: &Path) -> Result<Self> {
+        let path = repo_root.join(OCI_DIR).join(HASH_CACHE_FILE);
+        let conn = Connection::open(&path).context(format!("Failed to open hash cache: {}", path.display()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS hashes (
+                device INTEGER NOT NULL,
+                inode INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                num_bytes INTEGER NOT NULL,
+                sha256 TEXT NOT NULL,
+                generation INTEGER NOT NULL,
+                PRIMARY KEY (device, inode)
+            );
+            BEGIN;",
+        ).context("Failed to set up hash cache")?;
+        let generation: i64 = conn.query_row("SELECT COALESCE(MAX(generation), 0) + 1 FROM hashes", [], |row| row.get(0))
+            .context("Failed to read hash cache")?;
+        Ok(HashCache { conn, generation })
+    }
+
+    /// The hash recorded for a key, if the inode has not changed since
+    pub fn get(&self, key: &InodeKey) -> Result<Option<String>> {
+        let sha256: Option<String> = self.conn.query_row(
+            "SELECT sha256 FROM hashes WHERE device = ?1 AND inode = ?2 AND modified = ?3 AND num_bytes = ?4",
+            params![key.device as i64, key.inode as i64, key.modified as i64, key.num_bytes as i64],
+            |row| row.get(0),
+        ).optional().context("Failed to read hash cache")?;
+        Ok(sha256)
+    }
+
+    /// Record a file's hash, replacing whatever the inode held before
+    pub fn put(&self, key: &InodeKey, sha256: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO hashes (device, inode, modified, num_bytes, sha256, generation)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![key.device as i64, key.inode as i64, key.modified as i64, key.num_bytes as i64, sha256, self.generation],
+        ).context("Failed to write hash cache")?;
+        Ok(())
+    }
+
+    /// Commit this run's entries; with `forget_unseen` (after a scan of the whole repository),
+    /// drop inodes that no indexed file uses anymore
+    pub fn finish(self, forget_unseen: bool) -> Result<()> {
+        if forget_unseen {
+            self.conn.execute("DELETE FROM hashes WHERE generation < ?1", params![self.generation])
+                .context("Failed to write hash cache")?;
+        }
+        self.conn.execute_batch("COMMIT").context("Failed to write hash cache")
+    }
+
+    /// Delete a repository's cache (e.g. on `reset`, so the next update reads every file)
+    pub fn remove(repo_root: &Path) -> Result<()> {
+        let path = repo_root.join(OCI_DIR).join(HASH_CACHE_FILE);
+        if path.exists() {
+            std::fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_hash_cache_follows_inodes() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir(temp.path().join(OCI_DIR)).unwrap();
+        let file = temp.path().join("a.txt");
+        std::fs::write(&file, "hello").unwrap();
+        let key = InodeKey::of(&file).unwrap().unwrap();
+
+        let cache = HashCache::open(temp.path()).unwrap();
+        cache.put(&key, "abc").unwrap();
+        cache.finish(false).unwrap();
+
+        // A rename keeps the inode
+        let moved = temp.path().join("b.txt");
+        std::fs::rename(&file, &moved).unwrap();
+        let cache = HashCache::open(temp.path()).unwrap();
+        assert_eq!(cache.get(&InodeKey::of(&moved).unwrap().unwrap()).unwrap().as_deref(), Some("abc"));
+        assert_eq!(cache.get(&InodeKey { num_bytes: 6, ..key }).unwrap(), None);
+        cache.finish(false).unwrap();
+
+        // Entries not seen during a full scan are forgotten
+        let cache = HashCache::open(temp.path()).unwrap();
+        cache.finish(true).unwrap();
+        let cache = HashCache::open(temp.path()).unwrap();
+        assert_eq!(cache.get(&key).unwrap(), None);
+    }
+}
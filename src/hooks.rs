@@ -0,0 +1,120 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::warn;
+
+use crate::file_utils;
+use crate::index::OCI_DIR;
+
+/// Directory under `.oci` holding the hook executables
+pub const HOOKS_DIR: &str = "hooks";
+
+/// Points in a command where a user script can run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    /// Before `update` scans anything; a failing hook cancels the update
+    PreUpdate,
+    /// After `update` changed the index
+    PostUpdate,
+    /// After `prune` moved files away
+    PostPrune,
+}
+
+impl Hook {
+    /// File name of the hook in `.oci/hooks`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreUpdate => "pre-update",
+            Hook::PostUpdate => "post-update",
+            Hook::PostPrune => "post-prune",
+        }
+    }
+
+    fn path(&self, repo_root: &Path) -> PathBuf {
+        repo_root.join(OCI_DIR).join(HOOKS_DIR).join(self.name())
+    }
+}
+
+/// Run a hook if the repository has one, passing `fields` as a JSON object on stdin and as
+/// `OCI_<FIELD>` environment variables. Fails if the hook can't be started or exits unsuccessfully.
+pub fn run(repo_root: &Path, hook: Hook, fields: Map<String, Value>) -> Result<()> {
+    let path = hook.path(repo_root);
+    if !path.is_file() {
+        return Ok(());
+    }
+    if !file_utils::is_executable(&path) {
+        warn!("Skipping the {} hook, which is not executable (chmod +x {})", hook.name(), path.display());
+        return Ok(());
+    }
+
+    let mut payload = Map::new();
+    payload.insert("hook".to_string(), Value::from(hook.name()));
+    payload.insert("repo".to_string(), Value::from(repo_root.display().to_string()));
+    payload.extend(fields);
+
+    let mut command = Command::new(&path);
+    command.current_dir(repo_root).stdin(Stdio::piped());
+    for (key, value) in &payload {
+        let value = match value {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        command.env(format!("OCI_{}", key.to_uppercase()), value);
+    }
+
+    let mut child = command.spawn().context(format!("Failed to run the {} hook ({})", hook.name(), path.display()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that ignores its input may exit before reading it
+        match stdin.write_all(Value::Object(payload).to_string().as_bytes()) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                return Err(e).context(format!("Failed to write to the {} hook", hook.name()));
+            }
+            _ => {}
+        }
+    }
+    let status = child.wait().context(format!("Failed to wait for the {} hook", hook.name()))?;
+    if !status.success() {
+        bail!("The {} hook failed ({})", hook.name(), status);
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_hook() {
+        let repo = TempDir::new().unwrap();
+        let hooks_dir = repo.path().join(OCI_DIR).join(HOOKS_DIR);
+        fs::create_dir_all(&hooks_dir).unwrap();
+
+        // No hook: nothing to do
+        assert!(run(repo.path(), Hook::PostUpdate, Map::new()).is_ok());
+
+        let hook = hooks_dir.join("post-update");
+        fs::write(&hook, "#!/bin/sh\ncat > received.json\necho \"$OCI_HOOK $OCI_ADDED\" > env.txt\n").unwrap();
+        // Not executable yet: skipped with a warning
+        assert!(run(repo.path(), Hook::PostUpdate, Map::new()).is_ok());
+        assert!(!repo.path().join("env.txt").exists());
+
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut fields = Map::new();
+        fields.insert("added".to_string(), Value::from(3));
+        run(repo.path(), Hook::PostUpdate, fields).unwrap();
+        assert_eq!(fs::read_to_string(repo.path().join("env.txt")).unwrap(), "post-update 3\n");
+        let received: Value = serde_json::from_str(&fs::read_to_string(repo.path().join("received.json")).unwrap()).unwrap();
+        assert_eq!(received["added"], 3);
+        assert_eq!(received["hook"], "post-update");
+
+        let failing = hooks_dir.join("pre-update");
+        fs::write(&failing, "#!/bin/sh\nexit 1\n").unwrap();
+        fs::set_permissions(&failing, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(run(repo.path(), Hook::PreUpdate, Map::new()).is_err());
+    }
+}
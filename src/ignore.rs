@@ -1,9 +1,132 @@
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
 use anyhow::{Context, Result};
+use ::ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ::ignore::Match;
 use glob::Pattern;
 
-const OCIGNORE_FILE: &str = "ignore";
+use crate::roots::Roots;
+
+pub const OCIGNORE_FILE: &str = "ignore";
+
+/// Visible ignore file at the repository root, which can be versioned and reviewed with the
+/// files; when present it is used instead of `.oci/ignore`
+pub const ROOT_IGNORE_FILE: &str = ".ocignore";
+
+/// Prefix of include-only lines in the ignore file (e.g. `!!*.jpg`): once there is one,
+/// files matching none of them are ignored as well
+pub const INCLUDE_PREFIX: &str = "!!";
+
+/// Whether dotfiles and dot-directories are ignored (`--no-hidden` or `index_hidden=false`)
+static SKIP_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Ignore hidden files and directories in every scan for the rest of the process
+pub fn set_skip_hidden(skip: bool) {
+    SKIP_HIDDEN.store(skip, Ordering::Relaxed);
+}
+
+pub fn skip_hidden() -> bool {
+    SKIP_HIDDEN.load(Ordering::Relaxed)
+}
+
+/// Metadata directories of version control systems, skipped like `.oci` unless `skip_vcs=false`
+pub const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn"];
+
+/// Whether VCS metadata directories are ignored (on unless the repository sets `skip_vcs=false`)
+static SKIP_VCS: AtomicBool = AtomicBool::new(true);
+
+/// Index VCS metadata directories (or not) in every scan for the rest of the process
+pub fn set_skip_vcs(skip: bool) {
+    SKIP_VCS.store(skip, Ordering::Relaxed);
+}
+
+/// Whether any component of a path is a VCS metadata directory
+pub fn is_vcs_internal(path: &Path) -> bool {
+    path.components().any(|component| match component {
+        Component::Normal(name) => VCS_DIRS.iter().any(|dir| name == *dir),
+        _ => false,
+    })
+}
+
+/// `.gitignore` and `.git/info/exclude` rules honored by every scan (`respect_gitignore=true`)
+static GITIGNORE: OnceLock<GitRules> = OnceLock::new();
+
+/// Git ignore rules of the directories under the repository, loaded as scans reach them
+struct GitRules {
+    roots: Roots,
+    /// Rules by directory (None for directories without any)
+    dirs: Mutex<HashMap<PathBuf, Option<Gitignore>>>,
+}
+
+/// Also ignore what `.gitignore` files inside the repository ignore, for the rest of the process
+pub fn set_respect_gitignore(roots: Roots) {
+    let _ = GITIGNORE.set(GitRules { roots, dirs: Mutex::new(HashMap::new()) });
+}
+
+impl GitRules {
+    /// Whether git would ignore an index path, or a directory it is in
+    fn ignores(&self, path: &Path) -> bool {
+        let full = self.roots.full_path(&path.to_string_lossy());
+        let Some((base, _)) = self.roots.base_of(&full) else {
+            return false;
+        };
+        let Ok(rel) = full.strip_prefix(base) else {
+            return false;
+        };
+        let mut dirs = self.dirs.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Check each directory on the way down, as git would never descend into an ignored one;
+        // the rules of the nearest directory that has an opinion decide
+        let mut parents = vec![base.to_path_buf()];
+        let mut candidate = base.to_path_buf();
+        let components: Vec<_> = rel.components().collect();
+        for (i, component) in components.iter().enumerate() {
+            let dir = parents.last().cloned().unwrap_or_default();
+            dirs.entry(dir.clone()).or_insert_with(|| load_git_rules(&dir));
+            candidate.push(component);
+            let is_dir = i + 1 < components.len() || candidate.is_dir();
+            let decision = parents.iter().rev()
+                .filter_map(|dir| dirs.get(dir).and_then(Option::as_ref))
+                .map(|rules| rules.matched(&candidate, is_dir))
+                .find(|matched| !matched.is_none());
+            if matches!(decision, Some(Match::Ignore(_))) {
+                return true;
+            }
+            parents.push(candidate.clone());
+        }
+        false
+    }
+}
+
+/// Read a directory's `.gitignore`, plus `.git/info/exclude` where it is the top of a git repository
+fn load_git_rules(dir: &Path) -> Option<Gitignore> {
+    let files: Vec<PathBuf> = [dir.join(".git").join("info").join("exclude"), dir.join(".gitignore")]
+        .into_iter()
+        .filter(|file| file.is_file())
+        .collect();
+    if files.is_empty() {
+        return None;
+    }
+    // Later files take precedence, so .gitignore overrides the exclude file
+    let mut builder = GitignoreBuilder::new(dir);
+    for file in &files {
+        if let Some(err) = builder.add(file) {
+            tracing::warn!("Skipping unreadable rules in {}: {}", file.display(), err);
+        }
+    }
+    builder.build().ok()
+}
+
+/// Whether any component of a path is a dotfile or dot-directory
+pub fn is_hidden(path: &Path) -> bool {
+    path.components().any(|component| match component {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    })
+}
 
 /// Get default ignore patterns as a formatted string for writing to ignore
 /// These are common intermediate/derived files that are typically not tracked
@@ -14,9 +137,19 @@ pub fn default_ignore_content() -> String {
     include_str!("default_ignore").to_string()
 }
 
+/// The ignore file of a repository: `.ocignore` at the root if there is one, else `.oci/ignore`
+pub fn ignore_file(repo_root: &Path) -> PathBuf {
+    let root_file = repo_root.join(ROOT_IGNORE_FILE);
+    if root_file.is_file() {
+        root_file
+    } else {
+        repo_root.join(crate::index::OCI_DIR).join(OCIGNORE_FILE)
+    }
+}
+
 /// Load ignore patterns from ignore file
 pub fn load_patterns(repo_root: &Path) -> Result<Vec<String>> {
-    let ignore_path = repo_root.join(crate::index::OCI_DIR).join(OCIGNORE_FILE);
+    let ignore_path = ignore_file(repo_root);
     
     // Migration: Check for old ocignore file and rename it
     if !ignore_path.exists() {
@@ -40,14 +173,19 @@ pub fn load_patterns(repo_root: &Path) -> Result<Vec<String>> {
         .collect())
 }
 
-/// Initialize ignore file with default patterns
-pub fn init_ignore_file(repo_root: &Path) -> Result<()> {
+/// Initialize ignore file with default patterns, or the contents of a template file
+pub fn init_ignore_file(repo_root: &Path, template: Option<&Path>) -> Result<()> {
     let oci_dir = repo_root.join(crate::index::OCI_DIR);
     let ignore_path = oci_dir.join(OCIGNORE_FILE);
     
-    // Only write defaults if file doesn't exist
-    if !ignore_path.exists() {
-        fs::write(&ignore_path, default_ignore_content())
+    // Only write defaults if file doesn't exist (nor a versioned .ocignore that takes its place)
+    if !ignore_path.exists() && !repo_root.join(ROOT_IGNORE_FILE).is_file() {
+        let contents = match template {
+            Some(template) => fs::read_to_string(template)
+                .context(format!("Failed to read ignore template: {}", template.display()))?,
+            None => default_ignore_content(),
+        };
+        fs::write(&ignore_path, contents)
             .context("Failed to create ignore file")?;
     }
     
@@ -60,7 +198,7 @@ pub fn add_pattern(repo_root: &Path, pattern: &str) -> Result<()> {
     fs::create_dir_all(&oci_dir)
         .context("Failed to create .oci directory")?;
     
-    let ignore_path = oci_dir.join(OCIGNORE_FILE);
+    let ignore_path = ignore_file(repo_root);
     
     let mut patterns = if ignore_path.exists() {
         fs::read_to_string(&ignore_path)
@@ -154,39 +292,80 @@ fn pattern_matches(pattern: &str, path: &Path, path_str: &str) -> bool {
         }
 
         // For directory patterns, check parent matches
-        if pattern.ends_with('/') {
-            if matches_directory_pattern(pattern, path, path_str) {
-                return true;
-            }
+        if pattern.ends_with('/') && matches_directory_pattern(pattern, path, path_str) {
+            return true;
         }
     }
 
     false
 }
 
-/// Check if a path should be ignored based on patterns from ignore
+/// Check if a file should be ignored based on patterns from ignore
 pub fn should_ignore(path: &Path, patterns: &[String]) -> bool {
+    should_ignore_dir(path, patterns) || !is_included(path, patterns)
+}
+
+/// Check if a directory should be skipped based on patterns from ignore
+/// Include-only lines select files, so they never skip a directory: files inside may still match.
+pub fn should_ignore_dir(path: &Path, patterns: &[String]) -> bool {
     let path_str = path.to_string_lossy();
 
-    // Always ignore the .oci directory itself
-    if is_oci_directory(&path_str) {
+    // Always ignore the .oci directory itself and root marker files
+    if is_oci_directory(&path_str)
+        || path.file_name().is_some_and(|name| name == crate::roots::ROOT_MARKER)
+    {
         return true;
     }
 
-    // Check each pattern
-    for pattern in patterns {
-        if pattern_matches(pattern, path, &path_str) {
-            return true;
-        }
+    if skip_hidden() && is_hidden(path) {
+        return true;
     }
 
-    false
+    if SKIP_VCS.load(Ordering::Relaxed) && is_vcs_internal(path) {
+        return true;
+    }
+
+    if GITIGNORE.get().is_some_and(|rules| rules.ignores(path)) {
+        return true;
+    }
+
+    patterns.iter()
+        .filter(|pattern| !pattern.starts_with(INCLUDE_PREFIX))
+        .any(|pattern| pattern_matches(pattern, path, &path_str))
+}
+
+/// Whether a file matches an include-only line, or there are none
+fn is_included(path: &Path, patterns: &[String]) -> bool {
+    let path_str = path.to_string_lossy();
+    let mut includes = patterns.iter().filter_map(|pattern| pattern.strip_prefix(INCLUDE_PREFIX)).peekable();
+    includes.peek().is_none() || includes.any(|pattern| pattern_matches(pattern, path, &path_str))
+}
+
+/// Check if a path matches any of the given patterns (using ignore pattern semantics)
+pub fn matches_any(path: &Path, patterns: &[String]) -> bool {
+    let path_str = path.to_string_lossy();
+    patterns.iter().any(|pattern| pattern_matches(pattern, path, &path_str))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_include_only_lines() {
+        let patterns = vec!["!!*.jpg".to_string(), "!!*.raw".to_string(), "thumbs/".to_string()];
+
+        assert!(!should_ignore(Path::new("2020/beach.jpg"), &patterns));
+        assert!(!should_ignore(Path::new("IMG_001.raw"), &patterns));
+        assert!(should_ignore(Path::new("notes.txt"), &patterns));
+        // Excludes still apply to included files
+        assert!(should_ignore(Path::new("thumbs/beach.jpg"), &patterns));
+
+        // Directories are only skipped by excludes
+        assert!(!should_ignore_dir(Path::new("2020"), &patterns));
+        assert!(should_ignore_dir(Path::new("thumbs"), &patterns));
+    }
+
     #[test]
     fn test_should_ignore_oci_dir() {
         let path = Path::new(".oci/index.json");
@@ -232,6 +411,23 @@ mod tests {
         assert!(!should_ignore(Path::new(".DS_Store"), &[]));
     }
     
+    #[test]
+    fn test_is_vcs_internal() {
+        assert!(is_vcs_internal(Path::new(".git")));
+        assert!(is_vcs_internal(Path::new("code/oci/.git/objects/ab/cdef")));
+        assert!(is_vcs_internal(Path::new("legacy/.svn/entries")));
+        assert!(!is_vcs_internal(Path::new("code/.gitignore")));
+        assert!(!is_vcs_internal(Path::new("photos/git/a.jpg")));
+    }
+
+    #[test]
+    fn test_is_hidden() {
+        assert!(is_hidden(Path::new(".DS_Store")));
+        assert!(is_hidden(Path::new("photos/.thumbnails/a.jpg")));
+        assert!(!is_hidden(Path::new("photos/a.jpg")));
+        assert!(!is_hidden(Path::new("./photos/a.b.jpg")));
+    }
+    
     #[test]
     fn test_ignore_directory_with_spaces() {
         let patterns = vec!["Library/Application Support/Google/DriveFS/".to_string()];
@@ -1,234 +1,406 @@
-use anyhow::{Context, Result};
-use rusqlite::{params, Connection, OptionalExtension};
-use std::path::Path;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::digest::Algorithm;
+use crate::log_store::LogStore;
+use crate::oplog::Operation;
+use crate::audio::AudioFingerprint;
+use crate::error::OciError;
+use crate::legacy;
+use crate::media::MediaInfo;
+use crate::sqlite_store::SqliteStore;
+use crate::store::{Backend, Store};
+use crate::tiering::StorageClass;
 
 pub const OCI_DIR: &str = ".oci";
-const INDEX_FILE: &str = "index.db";
 
-#[derive(Debug, Clone, PartialEq)]
+/// Writes committed together while a batch is open
+/// Large enough to amortize the sync per commit, small enough that an interrupted
+/// `update` loses little work.
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct FileEntry {
     pub num_bytes: u64,
     pub modified: u64,
     pub sha256: String,
     pub path: String,
+    /// Extended attribute names and value hashes (None if not captured)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xattrs: Option<String>,
+    /// Fingerprint of size + first/last bytes for large files (sha256 is empty until fully hashed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quick_hash: Option<String>,
+    /// Content type detected from the file's leading bytes (e.g. "image/jpeg"; None if not detected)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    /// Bytes allocated on disk, which sparse files and compressing filesystems make smaller
+    /// than num_bytes and block rounding makes larger (None if not recorded)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allocated: Option<u64>,
+    /// Digests in other algorithms, as `md5=<hex>;sha1=<hex>` (None unless the `digests` key asks for them)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digests: Option<String>,
+}
+
+impl FileEntry {
+    /// Space the file takes on disk, or its size where that was not recorded
+    pub fn allocated_bytes(&self) -> u64 {
+        self.allocated.unwrap_or(self.num_bytes)
+    }
+
+    /// Whether two entries for the same path hold different content
+    /// (entries still awaiting a full hash are compared by size and quick hash)
+    pub fn content_differs(&self, other: &FileEntry) -> bool {
+        if !self.sha256.is_empty() && !other.sha256.is_empty() {
+            self.sha256 != other.sha256
+        } else {
+            self.num_bytes != other.num_bytes || self.quick_hash != other.quick_hash
+        }
+    }
+}
+
+/// Record of indexed content that left the repository, kept after its entry is removed
+/// so `audit --departed` can tell where the content went
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub path: String,
+    pub num_bytes: u64,
+    pub sha256: String,
+    /// When the entry was removed (milliseconds since epoch)
+    pub removed: u64,
+    /// Why it was removed: "deleted" (gone from disk), "pruned" (moved to the pruneyard by `prune`)
+    /// or "removed" (moved to the pruneyard by `rm`)
+    pub reason: String,
+}
+
+/// A file inside an indexed archive, recorded with `index_archives` so loose copies of it
+/// can be found (`duplicates`, `grep`, `prune`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveMember {
+    /// Index path of the archive
+    pub archive: String,
+    /// Path of the member inside the archive
+    pub member: String,
+    pub num_bytes: u64,
+    pub sha256: String,
 }
 
 pub struct Index {
-    conn: Connection,
-    repo_root: Option<std::path::PathBuf>,
+    store: Box<dyn Store>,
+    /// Writes since the last commit while a batch is open (None outside batches)
+    batch_writes: Option<usize>,
 }
 
 impl Index {
     /// Create a new empty index (in memory for testing)
     pub fn new() -> Result<Self> {
-        let conn = Connection::open_in_memory()
-            .context("Failed to create in-memory database")?;
-        init_schema(&conn)?;
-        Ok(Index { conn, repo_root: None })
+        Ok(Index { store: Box::new(SqliteStore::in_memory()?), batch_writes: None })
     }
 
-    /// Load the index from disk
+    /// Load the index from disk, using whichever backend the repository was created with
+    /// A repository that only has a legacy `index.txt` is upgraded on the first load.
     pub fn load(repo_root: &Path) -> Result<Self> {
-        let oci_dir = repo_root.join(OCI_DIR);
-        let index_path = oci_dir.join(INDEX_FILE);
-        
-        // Create directory if it doesn't exist
-        std::fs::create_dir_all(&oci_dir)
-            .context("Failed to create .oci directory")?;
-        
-        let conn = Connection::open(&index_path)
-            .context("Failed to open index database")?;
-        
-        // Ensure schema exists (for new databases)
-        init_schema(&conn)?;
-        
-        Ok(Index { 
-            conn, 
-            repo_root: Some(repo_root.to_path_buf()) 
-        })
+        let import_legacy = legacy::needs_import(repo_root);
+        let mut index = Self::open(repo_root, Backend::detect(repo_root))?;
+        if import_legacy {
+            legacy::import(&mut index, repo_root)?;
+        }
+        Ok(index)
+    }
+
+    /// Open (or create) the index of a repository with a specific backend
+    pub fn open(repo_root: &Path, backend: Backend) -> Result<Self> {
+        let store: Result<Box<dyn Store>> = match backend {
+            Backend::Sqlite => SqliteStore::open(repo_root).map(|store| Box::new(store) as Box<dyn Store>),
+            Backend::Log => LogStore::open(repo_root).map(|store| Box::new(store) as Box<dyn Store>),
+            Backend::Encrypted => SqliteStore::open_encrypted(repo_root).map(|store| Box::new(store) as Box<dyn Store>),
+        };
+        let store = store.map_err(|err| {
+            if is_damaged(&err) {
+                err.context(OciError::IndexCorrupt(backend.index_path(repo_root)))
+            } else {
+                err
+            }
+        })?;
+        Ok(Index { store, batch_writes: None })
+    }
+
+    /// Path of the file holding a repository's index
+    pub fn database_path(repo_root: &Path) -> PathBuf {
+        Backend::detect(repo_root).index_path(repo_root)
     }
 
     /// Save the index to disk (no-op for disk-based, required for in-memory)
+    #[tracing::instrument(level = "debug", skip_all)]
     pub fn save(&self, repo_root: &Path) -> Result<()> {
-        // If this is a disk-based database (loaded from disk), it's already saved
-        if self.repo_root.is_some() {
-            return Ok(());
-        }
-        
-        // For in-memory databases (e.g., tests or new index), backup to disk
-        let oci_dir = repo_root.join(OCI_DIR);
-        std::fs::create_dir_all(&oci_dir)
-            .context("Failed to create .oci directory")?;
-        
-        let index_path = oci_dir.join(INDEX_FILE);
-        
-        let mut disk_conn = Connection::open(&index_path)
-            .context("Failed to open destination database")?;
-        
-        let backup = rusqlite::backup::Backup::new(&self.conn, &mut disk_conn)
-            .context("Failed to create backup")?;
-        
-        backup.run_to_completion(5, std::time::Duration::from_millis(250), None)
-            .context("Failed to backup database")?;
-        
-        Ok(())
+        self.store.save(repo_root)
+    }
+
+    /// Fold pending writes into the index file (see `Store::checkpoint`)
+    pub fn checkpoint(&self) -> Result<()> {
+        self.store.checkpoint()
     }
 
     /// Add or update a file entry
     pub fn upsert(&mut self, entry: FileEntry) -> Result<()> {
-        self.conn.execute(
-            "INSERT OR REPLACE INTO files (path, num_bytes, modified, sha256) VALUES (?1, ?2, ?3, ?4)",
-            params![entry.path, entry.num_bytes, entry.modified, entry.sha256],
-        ).context("Failed to upsert file entry")?;
-        Ok(())
+        self.store.upsert(entry)?;
+        self.count_batch_write()
     }
 
-    /// Remove a file entry from the index
+    /// Remove a file entry from the index (and the sidecar records kept for it)
     pub fn remove(&mut self, path: &str) -> Result<()> {
-        self.conn.execute(
-            "DELETE FROM files WHERE path = ?1",
-            params![path],
-        ).context("Failed to remove file entry")?;
+        self.store.remove(path)?;
+        if crate::archive::is_archive(path) {
+            self.store.set_archive_members(path, &[])?;
+        }
+        if crate::media::is_media(path) {
+            self.store.clear_media(path)?;
+        }
+        if crate::audio::is_audio(path) {
+            self.store.clear_audio(path)?;
+        }
+        self.count_batch_write()
+    }
+
+    /// Remove entries whose content left the repository, leaving a tombstone for each fully hashed one
+    pub fn remove_departed(&mut self, entries: Vec<FileEntry>, reason: &str) -> Result<()> {
+        let removed = crate::file_utils::now_millis();
+        self.in_batch(|index| {
+            for entry in entries {
+                if !entry.sha256.is_empty() {
+                    index.store.add_tombstone(&Tombstone {
+                        path: entry.path.clone(),
+                        num_bytes: entry.num_bytes,
+                        sha256: entry.sha256,
+                        removed,
+                        reason: reason.to_string(),
+                    })?;
+                }
+                index.remove(&entry.path)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Get all tombstones, oldest first
+    pub fn tombstones(&self) -> Result<Vec<Tombstone>> {
+        self.store.tombstones()
+    }
+
+    /// Add or update several file entries in one batch
+    pub fn upsert_many(&mut self, entries: impl IntoIterator<Item = FileEntry>) -> Result<()> {
+        self.in_batch(|index| entries.into_iter().try_for_each(|entry| index.upsert(entry)))
+    }
+
+    /// Remove several file entries in one batch
+    pub fn remove_many<S: AsRef<str>>(&mut self, paths: impl IntoIterator<Item = S>) -> Result<()> {
+        self.in_batch(|index| paths.into_iter().try_for_each(|path| index.remove(path.as_ref())))
+    }
+
+    /// Group the following writes into transactions of BATCH_SIZE writes, until `commit_batch`
+    /// Without a batch every write is its own transaction, which is slow on spinning disks.
+    /// Writes after the last commit are lost if the process stops before `commit_batch`.
+    pub fn begin_batch(&mut self) -> Result<()> {
+        if self.batch_writes.is_none() {
+            self.store.begin()?;
+            self.batch_writes = Some(0);
+        }
+        Ok(())
+    }
+
+    /// Commit the writes of the open batch and end it
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub fn commit_batch(&mut self) -> Result<()> {
+        if self.batch_writes.take().is_some() {
+            self.store.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Run writes in a batch, joining the open batch if there is one
+    fn in_batch(&mut self, writes: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let nested = self.batch_writes.is_some();
+        self.begin_batch()?;
+        writes(self)?;
+        if !nested {
+            self.commit_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Commit the open batch every BATCH_SIZE writes
+    fn count_batch_write(&mut self) -> Result<()> {
+        if let Some(writes) = &mut self.batch_writes {
+            *writes += 1;
+            if *writes >= BATCH_SIZE {
+                let _span = tracing::debug_span!("commit", writes = BATCH_SIZE).entered();
+                self.store.commit()?;
+                self.store.begin()?;
+                self.batch_writes = Some(0);
+            }
+        }
         Ok(())
     }
 
     /// Clear all entries from the index
     pub fn clear(&mut self) -> Result<()> {
-        self.conn.execute("DELETE FROM files", [])
-            .context("Failed to clear index")?;
-        Ok(())
+        self.store.clear()
     }
 
     /// Get a file entry
     pub fn get(&self, path: &str) -> Result<Option<FileEntry>> {
-        let result = self.conn.query_row(
-            "SELECT path, num_bytes, modified, sha256 FROM files WHERE path = ?1",
-            params![path],
-            |row| {
-                Ok(FileEntry {
-                    path: row.get(0)?,
-                    num_bytes: row.get(1)?,
-                    modified: row.get(2)?,
-                    sha256: row.get(3)?,
-                })
-            },
-        ).optional().context("Failed to get file entry")?;
-        
-        Ok(result)
+        self.store.get(path)
     }
 
     /// Get all files in a directory (non-recursive)
     pub fn get_dir_files(&self, dir: &str) -> Result<Vec<FileEntry>> {
-        let normalized_dir = normalize_dir_path(dir);
-        
-        let mut stmt = self.conn.prepare(
-            "SELECT path, num_bytes, modified, sha256 FROM files"
-        ).context("Failed to prepare statement")?;
-        
-        let entries = stmt.query_map([], |row| {
-            Ok(FileEntry {
-                path: row.get(0)?,
-                num_bytes: row.get(1)?,
-                modified: row.get(2)?,
-                sha256: row.get(3)?,
-            })
-        }).context("Failed to query files")?;
-        
-        let mut result = Vec::new();
-        for entry in entries {
-            let entry = entry.context("Failed to read entry")?;
-            let parent = Path::new(&entry.path)
-                .parent()
-                .and_then(|p| p.to_str())
-                .unwrap_or("");
-            
-            if parent == normalized_dir {
-                result.push(entry);
-            }
-        }
-        
-        Ok(result)
+        self.store.dir_entries(&normalize_dir_path(dir))
     }
 
     /// Get all files in a directory (recursive)
     pub fn get_dir_files_recursive(&self, dir: &str) -> Result<Vec<FileEntry>> {
-        let normalized_dir = normalize_dir_path(dir);
-        let prefix = if normalized_dir.is_empty() {
-            String::new()
-        } else {
-            format!("{}/", normalized_dir)
-        };
+        self.store.dir_entries_recursive(&normalize_dir_path(dir))
+    }
 
-        let mut stmt = self.conn.prepare(
-            "SELECT path, num_bytes, modified, sha256 FROM files"
-        ).context("Failed to prepare statement")?;
-        
-        let entries = stmt.query_map([], |row| {
-            Ok(FileEntry {
-                path: row.get(0)?,
-                num_bytes: row.get(1)?,
-                modified: row.get(2)?,
-                sha256: row.get(3)?,
-            })
-        }).context("Failed to query files")?;
-        
-        let mut result = Vec::new();
-        for entry in entries {
-            let file_entry: FileEntry = entry.context("Failed to read entry")?;
-            // Filter by prefix
-            if prefix.is_empty() || file_entry.path.starts_with(&prefix) {
-                result.push(file_entry);
-            }
-        }
-        
-        Ok(result)
+    /// The largest files, largest first
+    pub fn largest_files(&self, limit: usize) -> Result<Vec<FileEntry>> {
+        self.store.largest_entries(limit)
+    }
+
+    /// The groups of identical files wasting the most space, as (hash, size, copies)
+    pub fn largest_duplicate_groups(&self, limit: usize) -> Result<Vec<(String, u64, usize)>> {
+        self.store.largest_duplicate_groups(limit)
+    }
+
+    /// Visit the files in a directory (recursively or not) in path order, streaming them from the
+    /// store where it can
+    pub fn visit_dir_files(&self, dir: &str, recursive: bool, mut visit: impl FnMut(FileEntry) -> Result<()>) -> Result<()> {
+        self.store.visit_dir_entries(&normalize_dir_path(dir), recursive, &mut visit)
     }
 
     /// Find all files with a given hash
     pub fn find_by_hash(&self, hash: &str) -> Result<Vec<FileEntry>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT path, num_bytes, modified, sha256 FROM files WHERE sha256 = ?1"
-        ).context("Failed to prepare statement")?;
-        
-        let entries = stmt.query_map(params![hash], |row| {
-            Ok(FileEntry {
-                path: row.get(0)?,
-                num_bytes: row.get(1)?,
-                modified: row.get(2)?,
-                sha256: row.get(3)?,
-            })
-        }).context("Failed to query files by hash")?;
-        
-        let mut result = Vec::new();
-        for entry in entries {
-            result.push(entry.context("Failed to read entry")?);
+        self.store.find_by_hash(hash)
+    }
+
+    /// Find files by a digest in any recorded algorithm
+    pub fn find_by_digest(&self, algorithm: Algorithm, hash: &str) -> Result<Vec<FileEntry>> {
+        match algorithm {
+            Algorithm::Sha256 => self.store.find_by_hash(hash),
+            _ => self.store.find_by_digest(algorithm, hash),
         }
-        
-        Ok(result)
     }
-}
 
-/// Initialize the database schema
-fn init_schema(conn: &Connection) -> Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS files (
-            path TEXT PRIMARY KEY,
-            num_bytes INTEGER NOT NULL,
-            modified INTEGER NOT NULL,
-            sha256 TEXT NOT NULL
-        )",
-        [],
-    ).context("Failed to create files table")?;
-    
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_sha256 ON files(sha256)",
-        [],
-    ).context("Failed to create sha256 index")?;
-    
-    Ok(())
+    /// Replace the members recorded for an archive (an empty list forgets them)
+    pub fn set_archive_members(&mut self, archive: &str, members: &[ArchiveMember]) -> Result<()> {
+        self.store.set_archive_members(archive, members)?;
+        self.count_batch_write()
+    }
+
+    /// Find archive members with a given hash
+    pub fn find_members_by_hash(&self, hash: &str) -> Result<Vec<ArchiveMember>> {
+        self.store.find_members_by_hash(hash)
+    }
+
+    /// Get the members of every indexed archive
+    pub fn archive_members(&self) -> Result<Vec<ArchiveMember>> {
+        self.store.archive_members()
+    }
+
+    /// Record the metadata extracted from a photo or video
+    pub fn set_media(&mut self, info: &MediaInfo) -> Result<()> {
+        self.store.set_media(info)?;
+        self.count_batch_write()
+    }
+
+    /// Get the metadata of every photo and video, by path
+    pub fn media(&self) -> Result<Vec<MediaInfo>> {
+        self.store.media()
+    }
+
+    /// Record the fingerprint of an audio file
+    pub fn set_audio(&mut self, print: &AudioFingerprint) -> Result<()> {
+        self.store.set_audio(print)?;
+        self.count_batch_write()
+    }
+
+    /// Get every audio fingerprint, by path
+    pub fn audio_fingerprints(&self) -> Result<Vec<AudioFingerprint>> {
+        self.store.audio_fingerprints()
+    }
+
+    /// Tag a path (file or directory) with a storage class
+    pub fn set_storage_class(&mut self, path: &str, class: StorageClass) -> Result<()> {
+        self.store.set_storage_class(&normalize_dir_path(path), class)
+    }
+
+    /// Remove the storage class tag from a path, returning whether a tag existed
+    pub fn clear_storage_class(&mut self, path: &str) -> Result<bool> {
+        self.store.clear_storage_class(&normalize_dir_path(path))
+    }
+
+    /// Get all manual storage class tags
+    pub fn storage_class_tags(&self) -> Result<Vec<(String, StorageClass)>> {
+        self.store.storage_class_tags()
+    }
+
+    /// Tag a path (file or directory), returning whether the tag is new
+    pub fn add_tag(&mut self, path: &str, tag: &str) -> Result<bool> {
+        self.store.add_tag(&normalize_dir_path(path), tag)
+    }
+
+    /// Remove a tag from a path, returning whether the path had it
+    pub fn remove_tag(&mut self, path: &str, tag: &str) -> Result<bool> {
+        self.store.remove_tag(&normalize_dir_path(path), tag)
+    }
+
+    /// Get all tags, by path
+    pub fn tags(&self) -> Result<Vec<(String, String)>> {
+        self.store.tags()
+    }
+
+    /// Append an operation to the op log
+    pub fn log_operation(&mut self, op: &Operation) -> Result<()> {
+        self.store.log_operation(op)
+    }
+
+    /// Get the most recent logged operation for a command
+    pub fn last_operation(&self, command: &str) -> Result<Option<Operation>> {
+        self.store.last_operation(command)
+    }
+
+    /// Get logged operations, newest first
+    pub fn operations(&self, limit: Option<usize>) -> Result<Vec<Operation>> {
+        self.store.operations(limit)
+    }
 }
 
 /// Normalize a directory path for consistent comparison
+/// Directory part of an index path ("" for files at the top level)
+pub fn parent_dir(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+/// Whether opening an index failed because SQLite found the file damaged or not a database
+fn is_damaged(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| matches!(
+        cause.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(failure, _))
+            if matches!(failure.code, rusqlite::ErrorCode::NotADatabase | rusqlite::ErrorCode::DatabaseCorrupt)
+    ))
+}
+
+/// Prefix shared by every index path under a directory ("" for the top level)
+pub fn dir_prefix(dir: &str) -> String {
+    if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    }
+}
+
 fn normalize_dir_path(dir: &str) -> String {
     let trimmed = dir.trim_matches('/');
     if trimmed == "." {
@@ -257,6 +429,7 @@ mod tests {
             modified: 1000,
             sha256: "abc123".to_string(),
             path: "file.txt".to_string(),
+            ..Default::default()
         };
         
         index.upsert(entry.clone()).unwrap();
@@ -273,15 +446,36 @@ mod tests {
             modified: 1000,
             sha256: "abc123".to_string(),
             path: "file1.txt".to_string(),
+            ..Default::default()
         }).unwrap();
         index.upsert(FileEntry {
             num_bytes: 100,
             modified: 1000,
             sha256: "abc123".to_string(),
             path: "file2.txt".to_string(),
+            ..Default::default()
         }).unwrap();
         
         let results = index.find_by_hash("abc123").unwrap();
         assert_eq!(results.len(), 2);
     }
+
+    #[test]
+    fn test_batches_commit_every_batch_size_writes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut index = Index::open(dir.path(), Backend::Sqlite).unwrap();
+        let entry = |i: usize| FileEntry { path: format!("f{}.txt", i), ..Default::default() };
+        let committed = || Index::load(dir.path()).unwrap().get_dir_files_recursive("").unwrap().len();
+
+        index.begin_batch().unwrap();
+        for i in 0..BATCH_SIZE + 5 {
+            index.upsert(entry(i)).unwrap();
+        }
+        assert_eq!(committed(), BATCH_SIZE);
+        index.commit_batch().unwrap();
+        assert_eq!(committed(), BATCH_SIZE + 5);
+
+        index.remove_many(["f0.txt", "f1.txt"]).unwrap();
+        assert_eq!(committed(), BATCH_SIZE + 3);
+    }
 }
@@ -0,0 +1,217 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::info;
+use walkdir::WalkDir;
+
+use crate::changes::{self, ChangedDir};
+
+/// Events that change a directory's listing or a file in it
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MODIFY
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_ATTRIB
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_ONLYDIR
+    | libc::IN_EXCL_UNLINK;
+
+const EVENT_HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// Watches every directory of a set of trees with inotify (one watch per directory, as inotify
+/// doesn't watch trees) and collects the directories that changed
+pub struct Watcher {
+    fd: OwnedFd,
+    /// Watched directory of each watch descriptor
+    watches: HashMap<i32, PathBuf>,
+    roots: Vec<PathBuf>,
+    skip_dir: Box<dyn Fn(&Path) -> bool>,
+}
+
+impl Watcher {
+    /// Watch the directories below `roots`, leaving out those `skip_dir` rejects (ignored ones)
+    pub fn start(roots: &[PathBuf], skip_dir: Box<dyn Fn(&Path) -> bool>) -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to start inotify");
+        }
+        let mut watcher = Watcher {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            watches: HashMap::new(),
+            roots: roots.to_vec(),
+            skip_dir,
+        };
+        for root in roots {
+            watcher.add_tree(root)?;
+        }
+        Ok(watcher)
+    }
+
+    /// Number of directories watched
+    pub fn watched_dirs(&self) -> usize {
+        self.watches.len()
+    }
+
+    /// Wait for `period`, then return the directories changed meanwhile. Events lost to a queue
+    /// overflow make every root a recursive change.
+    pub fn collect(&mut self, period: Duration) -> Result<Vec<ChangedDir>> {
+        let deadline = Instant::now() + period;
+        let mut changed: HashMap<PathBuf, bool> = HashMap::new();
+        let mut overflowed = false;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            let mut pollfd = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            let timeout = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+            if unsafe { libc::poll(&mut pollfd, 1, timeout) } <= 0 {
+                continue;
+            }
+            loop {
+                let read = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len()) };
+                if read <= 0 {
+                    break;
+                }
+                overflowed |= self.handle_events(&buffer[..read as usize], &mut changed)?;
+            }
+        }
+
+        if overflowed {
+            info!("inotify queue overflowed; rescanning everything");
+            changed = self.roots.iter().map(|root| (root.clone(), true)).collect();
+        }
+        let dirs = changed.into_iter().map(|(path, recursive)| ChangedDir { path, recursive }).collect();
+        Ok(changes::collapse(dirs))
+    }
+
+    /// Record the directories a buffer of events touches; returns whether events were lost
+    fn handle_events(&mut self, buffer: &[u8], changed: &mut HashMap<PathBuf, bool>) -> Result<bool> {
+        let mut overflowed = false;
+        let mut offset = 0;
+        while offset + EVENT_HEADER <= buffer.len() {
+            let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+            let name_end = (offset + EVENT_HEADER + event.len as usize).min(buffer.len());
+            let name = &buffer[offset + EVENT_HEADER..name_end];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            offset = name_end;
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                overflowed = true;
+                continue;
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.watches.remove(&event.wd);
+                continue;
+            }
+            let Some(dir) = self.watches.get(&event.wd).cloned() else {
+                continue;
+            };
+            if !name.is_empty() && event.mask & libc::IN_ISDIR != 0 {
+                let path = dir.join(OsStr::from_bytes(name));
+                if event.mask & libc::IN_MOVED_FROM != 0 {
+                    self.remove_tree(&path);
+                }
+                // A directory moved in brings files no event reports
+                if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 && !(self.skip_dir)(&path) {
+                    self.add_tree(&path)?;
+                    changed.insert(path, true);
+                }
+            }
+            changed.entry(dir).or_insert(false);
+        }
+        Ok(overflowed)
+    }
+
+    fn add_tree(&mut self, root: &Path) -> Result<()> {
+        let walk = WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|entry| entry.file_type().is_dir() && (entry.depth() == 0 || !(self.skip_dir)(entry.path())));
+        let mut added = Vec::new();
+        for entry in walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    info!("Not watching due to error: {}", err);
+                    continue;
+                }
+            };
+            let path = CString::new(entry.path().as_os_str().as_bytes()).context("Path contains a NUL byte")?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), WATCH_MASK) };
+            if wd >= 0 {
+                added.push((wd, entry.into_path()));
+                continue;
+            }
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOSPC) {
+                bail!(
+                    "Ran out of inotify watches after {} directories; raise the limit with \
+                     'sysctl fs.inotify.max_user_watches=<count>'",
+                    self.watches.len() + added.len()
+                );
+            }
+            // Removed since it was listed, or unreadable
+            info!("Not watching {}: {}", entry.path().display(), err);
+        }
+        // Adding a directory that is watched already returns its descriptor, which then maps to this path
+        self.watches.extend(added);
+        Ok(())
+    }
+
+    /// Stop watching a directory that moved away, so its descriptors don't report the old path
+    fn remove_tree(&mut self, root: &Path) {
+        let fd = self.fd.as_raw_fd();
+        self.watches.retain(|&wd, path| {
+            let moved = path.starts_with(root);
+            if moved {
+                unsafe { libc::inotify_rm_watch(fd, wd) };
+            }
+            !moved
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_watcher_reports_changed_dirs() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("docs/old")).unwrap();
+        fs::create_dir_all(root.join("skipped")).unwrap();
+        let skip = root.join("skipped");
+        let mut watcher = Watcher::start(std::slice::from_ref(&root), Box::new(move |dir: &Path| dir == skip)).unwrap();
+        assert_eq!(watcher.watched_dirs(), 3);
+
+        fs::write(root.join("docs/old/a.txt"), "a").unwrap();
+        fs::write(root.join("skipped/b.txt"), "b").unwrap();
+        fs::create_dir_all(root.join("new/inner")).unwrap();
+        let dirs = watcher.collect(Duration::from_millis(200)).unwrap();
+        assert_eq!(dirs, vec![
+            ChangedDir { path: root.clone(), recursive: false },
+            ChangedDir { path: root.join("docs/old"), recursive: false },
+            ChangedDir { path: root.join("new"), recursive: true },
+        ]);
+        assert_eq!(watcher.watched_dirs(), 5);
+
+        fs::rename(root.join("new"), root.join("renamed")).unwrap();
+        fs::write(root.join("renamed/inner/c.txt"), "c").unwrap();
+        let dirs = watcher.collect(Duration::from_millis(200)).unwrap();
+        assert_eq!(dirs, vec![
+            ChangedDir { path: root.clone(), recursive: false },
+            ChangedDir { path: root.join("renamed"), recursive: true },
+        ]);
+        assert!(watcher.collect(Duration::from_millis(50)).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,432 @@
+// Only `IndexView::Local` exists where there are no Unix sockets
+#![cfg_attr(not(unix), allow(dead_code))]
+
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::digest::Algorithm;
+use crate::index::{dir_prefix, ArchiveMember, FileEntry, Index};
+use crate::media::MediaInfo;
+
+#[cfg(unix)]
+pub use unix::{Client, Server};
+
+/// Stand-in where there are no Unix sockets; the daemon never starts one there
+#[cfg(not(unix))]
+pub struct Server;
+
+#[cfg(not(unix))]
+impl Server {
+    pub fn publish(&self, _dirs: &[crate::changes::ChangedDir], _roots: &crate::roots::Roots) {}
+
+    pub fn settle(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Socket in `.oci` the daemon answers queries on while it runs
+pub const SOCKET_FILE: &str = "daemon.sock";
+
+/// How often a daemon answering queries reads its watcher, which bounds how long `status` waits
+/// for the changes made just before it asked
+pub const TICK: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// A query, sent as one JSON line; the answer is a line per item followed by `"end"` (or an error)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "query", rename_all = "lowercase")]
+pub enum Request {
+    /// Entries in a directory, in path order
+    Files { dir: String, recursive: bool },
+    /// Entries with a digest
+    Digest { algorithm: String, hash: String },
+    /// Archive members with a SHA256
+    Members { hash: String },
+    Tags,
+    Media,
+    /// Whether the index matches the tree below a directory: a single `true` when nothing changed
+    /// there since the last batch, `false` when the daemon can't vouch for it
+    Status { dir: String, recursive: bool },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Reply<T> {
+    Item(T),
+    End,
+    Error(String),
+}
+
+/// Whether a change to the directory `changed` (and below it, if `below`) can show in the
+/// status of `dir`; both are index paths
+fn affects(changed: &str, below: bool, dir: &str, recursive: bool) -> bool {
+    changed == dir
+        || (recursive && changed.starts_with(&dir_prefix(dir)))
+        || (below && dir.starts_with(&dir_prefix(changed)))
+}
+
+/// The index as read-only commands see it: the daemon's copy when one is answering queries,
+/// otherwise the index on disk
+pub enum IndexView {
+    #[cfg(unix)]
+    Daemon(Client),
+    Local(Index),
+}
+
+impl IndexView {
+    pub fn open(repo_root: &Path) -> Result<Self> {
+        #[cfg(unix)]
+        if let Some(client) = Client::connect(repo_root) {
+            return Ok(IndexView::Daemon(client));
+        }
+        Ok(IndexView::Local(Index::load(repo_root)?))
+    }
+
+    /// Visit the files in a directory (recursively or not) in path order
+    pub fn visit_dir_files(&mut self, dir: &str, recursive: bool, visit: impl FnMut(FileEntry) -> Result<()>) -> Result<()> {
+        match self {
+            #[cfg(unix)]
+            IndexView::Daemon(client) => client.query(&Request::Files { dir: dir.to_string(), recursive }, visit),
+            IndexView::Local(index) => index.visit_dir_files(dir, recursive, visit),
+        }
+    }
+
+    pub fn dir_files(&mut self, dir: &str, recursive: bool) -> Result<Vec<FileEntry>> {
+        let mut entries = Vec::new();
+        self.visit_dir_files(dir, recursive, |entry| {
+            entries.push(entry);
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    pub fn find_by_digest(&mut self, algorithm: Algorithm, hash: &str) -> Result<Vec<FileEntry>> {
+        match self {
+            #[cfg(unix)]
+            IndexView::Daemon(client) => {
+                client.collect(&Request::Digest { algorithm: algorithm.to_string(), hash: hash.to_string() })
+            }
+            IndexView::Local(index) => index.find_by_digest(algorithm, hash),
+        }
+    }
+
+    pub fn find_members_by_hash(&mut self, hash: &str) -> Result<Vec<ArchiveMember>> {
+        match self {
+            #[cfg(unix)]
+            IndexView::Daemon(client) => client.collect(&Request::Members { hash: hash.to_string() }),
+            IndexView::Local(index) => index.find_members_by_hash(hash),
+        }
+    }
+
+    pub fn tags(&mut self) -> Result<Vec<(String, String)>> {
+        match self {
+            #[cfg(unix)]
+            IndexView::Daemon(client) => client.collect(&Request::Tags),
+            IndexView::Local(index) => index.tags(),
+        }
+    }
+
+    pub fn media(&mut self) -> Result<Vec<MediaInfo>> {
+        match self {
+            #[cfg(unix)]
+            IndexView::Daemon(client) => client.collect(&Request::Media),
+            IndexView::Local(index) => index.media(),
+        }
+    }
+}
+
+/// Whether the repository's daemon vouches that nothing changed below `dir` (an index path)
+/// since it last updated the index; false when no daemon answers
+pub fn daemon_reports_clean(repo_root: &Path, dir: &str, recursive: bool) -> Result<bool> {
+    #[cfg(unix)]
+    if let Some(mut client) = Client::connect(repo_root) {
+        let clean: Vec<bool> = client.collect(&Request::Status { dir: dir.to_string(), recursive })?;
+        return Ok(clean == [true]);
+    }
+    let _ = (repo_root, dir, recursive);
+    Ok(false)
+}
+
+fn parse_reply<T: DeserializeOwned>(line: &str) -> Result<Option<T>> {
+    match serde_json::from_str(line).context("Malformed reply from the daemon")? {
+        Reply::Item(item) => Ok(Some(item)),
+        Reply::End => Ok(None),
+        Reply::Error(message) => bail!("The daemon failed to answer: {}", message),
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::{bail, Context, Result};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use std::fs;
+    use std::io::{BufRead, BufReader, BufWriter, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::{Duration, SystemTime};
+    use tracing::info;
+
+    use super::{affects, parse_reply, Reply, Request, SOCKET_FILE};
+    use crate::changes::ChangedDir;
+    use crate::index::{Index, OCI_DIR};
+    use crate::oplog::Operation;
+    use crate::roots::Roots;
+
+    /// Longest a client waits for an answer before giving up on a daemon that stopped responding
+    const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Longest a `status` query waits for the watcher to catch up (e.g. while a batch runs)
+    /// before the daemon says it can't vouch for the tree
+    const STATUS_WAIT: Duration = Duration::from_secs(2);
+
+    /// A connection to the repository's daemon
+    pub struct Client {
+        reader: BufReader<UnixStream>,
+        writer: UnixStream,
+    }
+
+    impl Client {
+        /// Connect to the daemon of a repository, or None if none is answering queries
+        pub fn connect(repo_root: &Path) -> Option<Client> {
+            let stream = UnixStream::connect(repo_root.join(OCI_DIR).join(SOCKET_FILE)).ok()?;
+            stream.set_read_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+            let writer = stream.try_clone().ok()?;
+            Some(Client { reader: BufReader::new(stream), writer })
+        }
+
+        /// Send a query and visit each item of its answer as it arrives
+        pub fn query<T: DeserializeOwned>(&mut self, request: &Request, mut visit: impl FnMut(T) -> Result<()>) -> Result<()> {
+            let mut line = serde_json::to_string(request)?;
+            line.push('\n');
+            self.writer.write_all(line.as_bytes()).context("Failed to send a query to the daemon")?;
+            loop {
+                line.clear();
+                if self.reader.read_line(&mut line).context("Failed to read the daemon's answer")? == 0 {
+                    bail!("The daemon closed the connection before answering");
+                }
+                match parse_reply(&line)? {
+                    Some(item) => visit(item)?,
+                    None => return Ok(()),
+                }
+            }
+        }
+
+        pub fn collect<T: DeserializeOwned>(&mut self, request: &Request) -> Result<Vec<T>> {
+            let mut items = Vec::new();
+            self.query(request, |item| {
+                items.push(item);
+                Ok(())
+            })?;
+            Ok(items)
+        }
+    }
+
+    /// The daemon's side: answers queries from an index kept open, and `status` from the
+    /// directories its watcher reported since the last batch. The socket is removed when dropped.
+    pub struct Server {
+        shared: Arc<Shared>,
+        path: PathBuf,
+    }
+
+    struct Shared {
+        repo_root: PathBuf,
+        index: Mutex<WarmIndex>,
+        pending: Mutex<Pending>,
+        published: Condvar,
+    }
+
+    /// The index, reopened when another process rewrote its file (an index log or encrypted
+    /// index is read into memory; SQLite sees other writers by itself)
+    struct WarmIndex {
+        index: Index,
+        stamp: Option<(u64, SystemTime)>,
+    }
+
+    #[derive(Default)]
+    struct Pending {
+        /// Index paths of the directories changed but not yet applied (or left by a failed
+        /// batch), and whether everything below them is in question
+        dirs: Vec<(String, bool)>,
+        /// The latest operation when the index last matched the tree; a newer one means another
+        /// command changed the index behind the watcher's back
+        settled: Option<Operation>,
+        /// Bumped on every publish, so `status` can wait for the watcher's next read
+        generation: u64,
+    }
+
+    fn index_stamp(repo_root: &Path) -> Option<(u64, SystemTime)> {
+        let metadata = fs::metadata(Index::database_path(repo_root)).ok()?;
+        Some((metadata.len(), metadata.modified().ok()?))
+    }
+
+    impl WarmIndex {
+        fn current(&mut self, repo_root: &Path) -> Result<&Index> {
+            let stamp = index_stamp(repo_root);
+            if stamp != self.stamp {
+                self.index = Index::load(repo_root)?;
+                self.stamp = stamp;
+            }
+            Ok(&self.index)
+        }
+    }
+
+    impl Server {
+        /// Listen on the repository's socket, answering each connection on a thread of its own
+        pub fn start(repo_root: &Path) -> Result<Server> {
+            let path = repo_root.join(OCI_DIR).join(SOCKET_FILE);
+            // Left behind by a daemon that died; the pid file keeps a live one from being replaced
+            let _ = fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).context(format!("Failed to listen on {}", path.display()))?;
+            let shared = Arc::new(Shared {
+                repo_root: repo_root.to_path_buf(),
+                index: Mutex::new(WarmIndex { stamp: index_stamp(repo_root), index: Index::load(repo_root)? }),
+                pending: Mutex::new(Pending::default()),
+                published: Condvar::new(),
+            });
+            let accepting = Arc::clone(&shared);
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let shared = Arc::clone(&accepting);
+                    std::thread::spawn(move || {
+                        // A client that goes away mid-answer must not disturb the daemon
+                        if let Err(e) = handle_connection(stream, &shared) {
+                            info!("Query connection closed: {:#}", e);
+                        }
+                    });
+                }
+            });
+            Ok(Server { shared, path })
+        }
+
+        pub fn socket_path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Record the directories the watcher reported that aren't applied yet
+        pub fn publish(&self, dirs: &[ChangedDir], roots: &Roots) {
+            let dirs = dirs
+                .iter()
+                .filter_map(|dir| Some((roots.index_path(&dir.path)?, dir.recursive)))
+                .collect();
+            let mut pending = self.shared.pending.lock().unwrap();
+            pending.dirs = dirs;
+            pending.generation += 1;
+            self.shared.published.notify_all();
+        }
+
+        /// Record that the index matches the tree, apart from the directories published
+        pub fn settle(&self) -> Result<()> {
+            let latest = latest_operation(&self.shared)?;
+            self.shared.pending.lock().unwrap().settled = latest;
+            Ok(())
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn latest_operation(shared: &Shared) -> Result<Option<Operation>> {
+        let mut warm = shared.index.lock().unwrap();
+        Ok(warm.current(&shared.repo_root)?.operations(Some(1))?.pop())
+    }
+
+    fn handle_connection(stream: UnixStream, shared: &Shared) -> Result<()> {
+        let mut out = BufWriter::new(stream.try_clone()?);
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            let result = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => answer(&request, shared, &mut out),
+                Err(e) => Err(e).context("Malformed query"),
+            };
+            if let Err(e) = result {
+                send(&mut out, &Reply::<()>::Error(format!("{:#}", e)))?;
+            }
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn send<T: Serialize>(out: &mut impl Write, reply: &Reply<T>) -> Result<()> {
+        serde_json::to_writer(&mut *out, reply)?;
+        out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn send_all<T: Serialize>(out: &mut impl Write, items: Vec<T>) -> Result<()> {
+        for item in items {
+            send(out, &Reply::Item(item))?;
+        }
+        Ok(())
+    }
+
+    fn answer(request: &Request, shared: &Shared, out: &mut impl Write) -> Result<()> {
+        if let Request::Status { dir, recursive } = request {
+            let clean = status_clean(shared, dir, *recursive)?;
+            send(out, &Reply::Item(clean))?;
+            return send(out, &Reply::<()>::End);
+        }
+
+        let mut warm = shared.index.lock().unwrap();
+        let index = warm.current(&shared.repo_root)?;
+        match request {
+            Request::Files { dir, recursive } => index.visit_dir_files(dir, *recursive, |entry| send(out, &Reply::Item(entry)))?,
+            Request::Digest { algorithm, hash } => send_all(out, index.find_by_digest(algorithm.parse()?, hash)?)?,
+            Request::Members { hash } => send_all(out, index.find_members_by_hash(hash)?)?,
+            Request::Tags => send_all(out, index.tags()?)?,
+            Request::Media => send_all(out, index.media()?)?,
+            Request::Status { .. } => unreachable!(),
+        }
+        send(out, &Reply::<()>::End)
+    }
+
+    /// Wait for the watcher's next read, so changes made just before the query count, then
+    /// check nothing pending touches `dir` and no other command changed the index meanwhile
+    fn status_clean(shared: &Shared, dir: &str, recursive: bool) -> Result<bool> {
+        let (settled, untouched) = {
+            let pending = shared.pending.lock().unwrap();
+            let asked = pending.generation;
+            let (pending, wait) = shared
+                .published
+                .wait_timeout_while(pending, STATUS_WAIT, |pending| pending.generation <= asked)
+                .unwrap();
+            if wait.timed_out() {
+                return Ok(false);
+            }
+            let untouched = !pending.dirs.iter().any(|(changed, below)| affects(changed, *below, dir, recursive));
+            (pending.settled.clone(), untouched)
+        };
+        Ok(untouched && settled.is_some() && latest_operation(shared)? == settled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_affects_and_protocol() {
+        // A file changed in a directory shows in its status, and in the recursive status of its ancestors
+        assert!(affects("docs", false, "docs", false));
+        assert!(affects("docs/old", false, "docs", true));
+        assert!(affects("docs/old", false, "", true));
+        assert!(!affects("docs/old", false, "docs", false));
+        assert!(!affects("docs", false, "docs/old", true));
+        assert!(!affects("documents", false, "docs", true));
+        // A directory to rescan recursively puts everything below it in question
+        assert!(affects("docs", true, "docs/old", false));
+        assert!(affects("", true, "docs", false));
+
+        let request: Request = serde_json::from_str(r#"{"query":"files","dir":"docs","recursive":true}"#).unwrap();
+        assert_eq!(request, Request::Files { dir: "docs".to_string(), recursive: true });
+        assert_eq!(serde_json::to_string(&Request::Tags).unwrap(), r#"{"query":"tags"}"#);
+        assert_eq!(parse_reply::<bool>(r#"{"item":true}"#).unwrap(), Some(true));
+        assert_eq!(parse_reply::<bool>(r#""end""#).unwrap(), None);
+        assert!(parse_reply::<bool>(r#"{"error":"no such table"}"#).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::index::{FileEntry, Index, OCI_DIR};
+use crate::oplog::Operation;
+use crate::store::Backend;
+
+/// Plaintext index written by early versions of oci
+const LEGACY_INDEX_FILE: &str = "index.txt";
+/// Name the legacy index is kept under once imported
+const ARCHIVED_INDEX_FILE: &str = "index.txt.imported";
+
+/// Modified times below this are in seconds rather than milliseconds
+/// (1e11 ms is March 1973, while 1e11 s is far in the future)
+const MILLIS_THRESHOLD: u64 = 100_000_000_000;
+
+fn legacy_path(repo_root: &Path) -> PathBuf {
+    repo_root.join(OCI_DIR).join(LEGACY_INDEX_FILE)
+}
+
+/// Whether a repository only has a legacy plaintext index that still needs importing
+pub fn needs_import(repo_root: &Path) -> bool {
+    legacy_path(repo_root).is_file()
+        && !Backend::Sqlite.index_path(repo_root).exists()
+        && !Backend::Log.index_path(repo_root).exists()
+        && !Backend::Encrypted.index_path(repo_root).exists()
+}
+
+/// Import the legacy index into an (empty) index and archive the old file, returning the number of files
+/// Hashes and modified times are kept, so the next `update` does not re-hash unchanged files.
+pub fn import(index: &mut Index, repo_root: &Path) -> Result<usize> {
+    let path = legacy_path(repo_root);
+    let contents = fs::read_to_string(&path)
+        .context(format!("Failed to read legacy index: {}", path.display()))?;
+
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let entry = parse_line(line)
+            .with_context(|| format!("Invalid line {} in legacy index {}", i + 1, path.display()))?;
+        entries.push(entry);
+    }
+
+    let count = entries.len();
+    let config = Config::load_if_present(repo_root)?.unwrap_or_else(Config::new);
+    index.begin_batch()?;
+    index.upsert_many(entries)?;
+    index.log_operation(&Operation::new(
+        &config,
+        "import",
+        format!("{} file(s) from legacy {}", count, LEGACY_INDEX_FILE),
+    ))?;
+    index.commit_batch()?;
+    index.save(repo_root)?;
+
+    let archived = repo_root.join(OCI_DIR).join(ARCHIVED_INDEX_FILE);
+    fs::rename(&path, &archived).context("Failed to archive legacy index")?;
+    eprintln!(
+        "Note: Imported {} file(s) from legacy {}/{} (kept as {}/{})",
+        count, OCI_DIR, LEGACY_INDEX_FILE, OCI_DIR, ARCHIVED_INDEX_FILE
+    );
+    Ok(count)
+}
+
+/// Parse a legacy line: `<num_bytes> <modified> <sha256> <path>`, separated by spaces or tabs
+/// (the layout `ls` prints). The path is the rest of the line and may contain spaces.
+fn parse_line(line: &str) -> Result<FileEntry> {
+    let mut rest = line.trim_start();
+    let mut fields = Vec::new();
+    for _ in 0..3 {
+        let (field, tail) = rest.split_once([' ', '\t'])
+            .context("expected <num_bytes> <modified> <sha256> <path>")?;
+        fields.push(field);
+        rest = tail.trim_start();
+    }
+    let path = rest.trim_end_matches('\r').trim_start_matches("./");
+    if path.is_empty() {
+        bail!("missing path");
+    }
+
+    let num_bytes: u64 = fields[0].parse().context(format!("invalid size '{}'", fields[0]))?;
+    let mut modified: u64 = fields[1].parse().context(format!("invalid modified time '{}'", fields[1]))?;
+    if modified < MILLIS_THRESHOLD {
+        modified *= 1000;
+    }
+
+    let hash = fields[2];
+    let (sha256, quick_hash) = match hash.strip_prefix("quick:") {
+        Some(quick_hash) => (String::new(), Some(quick_hash.to_string())),
+        None if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) => (hash.to_ascii_lowercase(), None),
+        None => bail!("invalid sha256 '{}'", hash),
+    };
+
+    Ok(FileEntry {
+        path: path.to_string(),
+        num_bytes,
+        modified,
+        sha256,
+        quick_hash,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let hash = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let entry = parse_line(&format!("         5   1700000000000 {} photos/my trip.jpg", hash)).unwrap();
+        assert_eq!(entry.path, "photos/my trip.jpg");
+        assert_eq!((entry.num_bytes, entry.modified), (5, 1_700_000_000_000));
+        assert_eq!(entry.sha256, hash);
+
+        // Seconds are converted to milliseconds
+        let entry = parse_line(&format!("5\t1700000000\t{}\tnotes.txt", hash.to_uppercase())).unwrap();
+        assert_eq!((entry.modified, entry.sha256.as_str()), (1_700_000_000_000, hash));
+
+        let entry = parse_line("9 1 quick:abcd big.mov").unwrap();
+        assert_eq!((entry.sha256.as_str(), entry.quick_hash.as_deref()), ("", Some("abcd")));
+
+        assert!(parse_line("5 1700000000000 nothex a.txt").is_err());
+        assert!(parse_line(&format!("5 1700000000000 {}", hash)).is_err());
+    }
+}
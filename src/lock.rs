@@ -0,0 +1,344 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::error::OciError;
+use crate::file_utils::now_millis;
+use crate::index::OCI_DIR;
+
+/// Lock file used with POSIX advisory locks
+const LOCK_FILE: &str = "lock";
+/// Lock file whose existence (with an unexpired lease) is the lock
+const LEASE_FILE: &str = "lock.lease";
+/// How long a lease is valid without renewal
+const LEASE_MILLIS: u64 = 60_000;
+/// How often the holder renews its lease
+const RENEW_INTERVAL: Duration = Duration::from_secs(15);
+/// How often a waiting process retries a held lock
+const WAIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Set by `--wait`: mutating commands wait for a held lock instead of failing
+static WAIT_FOR_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Make mutating commands wait for a held lock instead of failing
+pub fn set_wait(wait: bool) {
+    WAIT_FOR_LOCK.store(wait, Ordering::Relaxed);
+}
+
+/// Whether mutating commands should wait for a held lock
+pub fn wait_enabled() -> bool {
+    WAIT_FOR_LOCK.load(Ordering::Relaxed)
+}
+
+/// How mutating commands lock the repository (`lock_strategy` config key)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockStrategy {
+    /// Lease files on network filesystems, POSIX locks elsewhere
+    #[default]
+    Auto,
+    /// POSIX advisory locks (`flock`); released by the OS if the process dies
+    Posix,
+    /// A lock file created atomically and kept alive by renewing a lease timestamp
+    Lease,
+}
+
+impl std::str::FromStr for LockStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(LockStrategy::Auto),
+            "posix" => Ok(LockStrategy::Posix),
+            "lease" => Ok(LockStrategy::Lease),
+            _ => bail!("Invalid value for lock_strategy: '{}' (expected auto, posix or lease)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for LockStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockStrategy::Auto => write!(f, "auto"),
+            LockStrategy::Posix => write!(f, "posix"),
+            LockStrategy::Lease => write!(f, "lease"),
+        }
+    }
+}
+
+/// Name of the network filesystem a path is on (e.g. "nfs", "smb"), or None for local filesystems
+#[cfg(target_os = "linux")]
+pub fn network_filesystem(path: &Path) -> Option<&'static str> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    network_fs_from_magic(stat.f_type as u32)
+}
+
+#[cfg(target_os = "macos")]
+pub fn network_filesystem(path: &Path) -> Option<&'static str> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    match name.to_str().ok()? {
+        "nfs" => Some("nfs"),
+        "smbfs" => Some("smb"),
+        "afpfs" => Some("afp"),
+        "webdav" => Some("webdav"),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn network_filesystem(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+/// Network filesystems by their Linux `statfs` magic number
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn network_fs_from_magic(magic: u32) -> Option<&'static str> {
+    match magic {
+        0x6969 => Some("nfs"),
+        0x517B | 0xFE53_4D42 => Some("smb"),
+        0xFF53_4D42 => Some("cifs"),
+        0x5346_414F => Some("afs"),
+        0x00C3_6400 => Some("ceph"),
+        0x0102_1997 => Some("9p"),
+        _ => None,
+    }
+}
+
+/// An exclusive lock on a repository, released when dropped
+pub struct RepoLock {
+    held: Held,
+}
+
+/// Result of one attempt to take the lock
+enum Attempt {
+    Acquired(RepoLock),
+    /// Lock file contents of the current holder
+    Held(String),
+}
+
+enum Held {
+    /// The open lock file; closing it releases the advisory lock
+    Posix(#[allow(dead_code)] File),
+    Lease {
+        path: PathBuf,
+        token: String,
+        stop: Arc<AtomicBool>,
+        renewer: Option<JoinHandle<()>>,
+    },
+}
+
+impl RepoLock {
+    /// Lock a repository for a mutating command
+    /// If another oci process holds the lock this fails, or with `wait` retries until it is released.
+    pub fn acquire(repo_root: &Path, strategy: LockStrategy, wait: bool) -> Result<Self> {
+        let oci_dir = repo_root.join(OCI_DIR);
+        let use_lease = match strategy {
+            LockStrategy::Auto => network_filesystem(&oci_dir).is_some(),
+            LockStrategy::Posix => false,
+            LockStrategy::Lease => true,
+        };
+
+        let mut announced = false;
+        loop {
+            let attempt = if use_lease {
+                Self::try_lease(&oci_dir.join(LEASE_FILE))?
+            } else {
+                Self::try_posix(&oci_dir.join(LOCK_FILE))?
+            };
+            match attempt {
+                Attempt::Acquired(lock) => return Ok(lock),
+                Attempt::Held(holder) if wait => {
+                    if !announced {
+                        eprintln!("{}; waiting for it to finish...", held_message(&holder));
+                        announced = true;
+                    }
+                    std::thread::sleep(WAIT_INTERVAL);
+                }
+                Attempt::Held(holder) => return Err(OciError::Locked(held_message(&holder)).into()),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn try_posix(path: &Path) -> Result<Attempt> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+            .context("Failed to open lock file")?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            return Ok(Attempt::Held(holder));
+        }
+
+        // Record the holder for the error shown to other processes
+        file.set_len(0).context("Failed to write lock file")?;
+        file.rewind().context("Failed to write lock file")?;
+        file.write_all(holder_record(u64::MAX).as_bytes()).context("Failed to write lock file")?;
+        Ok(Attempt::Acquired(RepoLock { held: Held::Posix(file) }))
+    }
+
+    #[cfg(not(unix))]
+    fn try_posix(path: &Path) -> Result<Attempt> {
+        // Without advisory locks the lease file is the only portable option
+        Self::try_lease(&path.with_file_name(LEASE_FILE))
+    }
+
+    fn try_lease(path: &Path) -> Result<Attempt> {
+        let token = holder_record(now_millis() + LEASE_MILLIS);
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    file.write_all(token.as_bytes()).context("Failed to write lease file")?;
+                    file.sync_all().context("Failed to write lease file")?;
+                    break;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let holder = fs::read_to_string(path).unwrap_or_default();
+                    if lease_expires(&holder).is_some_and(|expires| expires > now_millis()) {
+                        return Ok(Attempt::Held(holder));
+                    }
+                    // The holder died without releasing its lease; move the stale file aside and retry.
+                    // Renaming is atomic, so only one of several waiting processes removes it.
+                    let stale = path.with_extension(format!("stale.{}", std::process::id()));
+                    if fs::rename(path, &stale).is_ok() {
+                        let _ = fs::remove_file(&stale);
+                    }
+                }
+                Err(e) => return Err(e).context("Failed to create lease file"),
+            }
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let renewer = {
+            let (path, stop) = (path.to_path_buf(), stop.clone());
+            std::thread::spawn(move || renew_lease(&path, &stop))
+        };
+        Ok(Attempt::Acquired(RepoLock {
+            held: Held::Lease { path: path.to_path_buf(), token, stop, renewer: Some(renewer) },
+        }))
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        if let Held::Lease { path, token, stop, renewer } = &mut self.held {
+            stop.store(true, Ordering::SeqCst);
+            if let Some(renewer) = renewer.take() {
+                renewer.thread().unpark();
+                let _ = renewer.join();
+            }
+            // Only remove the lease if it is still ours
+            let current = fs::read_to_string(&*path).unwrap_or_default();
+            if lease_pid(&current) == lease_pid(token) && lease_host(&current) == lease_host(token) {
+                let _ = fs::remove_file(&*path);
+            }
+        }
+    }
+}
+
+/// Push the lease expiry forward until asked to stop
+fn renew_lease(path: &Path, stop: &AtomicBool) {
+    while !stop.load(Ordering::SeqCst) {
+        std::thread::park_timeout(RENEW_INTERVAL);
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let _ = fs::write(path, holder_record(now_millis() + LEASE_MILLIS));
+    }
+}
+
+/// Lock file contents identifying this process
+fn holder_record(expires: u64) -> String {
+    format!("pid={}\nhost={}\nsince={}\nexpires={}\n", std::process::id(), hostname(), now_millis(), expires)
+}
+
+fn lock_field<'a>(record: &'a str, name: &str) -> Option<&'a str> {
+    record.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn lease_expires(record: &str) -> Option<u64> {
+    lock_field(record, "expires")?.parse().ok()
+}
+
+fn lease_pid(record: &str) -> Option<&str> {
+    lock_field(record, "pid")
+}
+
+fn lease_host(record: &str) -> Option<&str> {
+    lock_field(record, "host")
+}
+
+/// Error shown when another process holds the lock
+fn held_message(holder: &str) -> String {
+    let mut message = "Another oci process is running on this index".to_string();
+    if let (Some(pid), Some(host)) = (lease_pid(holder), lease_host(holder)) {
+        message.push_str(&format!(" (pid {} on {}", pid, host));
+        if let Some(since) = lock_field(holder, "since").and_then(|s| s.parse().ok()) {
+            message.push_str(&format!(", since {} UTC", crate::oplog::format_timestamp(since)));
+        }
+        message.push(')');
+    }
+    message
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return "unknown".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_lease_lock_excludes_and_expires() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join(OCI_DIR)).unwrap();
+        let lease_path = dir.path().join(OCI_DIR).join(LEASE_FILE);
+
+        let lock = RepoLock::acquire(dir.path(), LockStrategy::Lease, false).unwrap();
+        let err = RepoLock::acquire(dir.path(), LockStrategy::Lease, false).err().unwrap();
+        assert!(err.to_string().starts_with("Another oci process is running on this index (pid "));
+        drop(lock);
+        assert!(!lease_path.exists());
+
+        // An expired lease left by a crashed process is taken over
+        fs::write(&lease_path, "pid=1\nhost=elsewhere\nsince=0\nexpires=1\n").unwrap();
+        let _lock = RepoLock::acquire(dir.path(), LockStrategy::Lease, false).unwrap();
+        assert_eq!(lease_pid(&fs::read_to_string(&lease_path).unwrap()), Some(std::process::id().to_string().as_str()));
+    }
+
+    #[test]
+    fn test_network_fs_magic() {
+        assert_eq!(network_fs_from_magic(0x6969), Some("nfs"));
+        assert_eq!(network_fs_from_magic(0xFF53_4D42), Some("cifs"));
+        assert_eq!(network_fs_from_magic(0xEF53), None); // ext4
+    }
+}
@@ -0,0 +1,416 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::index::{dir_prefix, parent_dir, ArchiveMember, FileEntry, Tombstone, OCI_DIR};
+use crate::audio::AudioFingerprint;
+use crate::error::OciError;
+use crate::media::MediaInfo;
+use crate::oplog::Operation;
+use crate::store::{Backend, Store};
+use crate::tiering::StorageClass;
+
+/// Superseded records tolerated before the log is rewritten on open
+const COMPACT_SLACK: usize = 1000;
+
+/// One line of the index log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Upsert(FileEntry),
+    Remove { path: String },
+    Clear,
+    SetClass { path: String, class: String },
+    ClearClass { path: String },
+    Tag { path: String, tag: String },
+    Untag { path: String, tag: String },
+    Members { archive: String, members: Vec<ArchiveMember> },
+    Media(MediaInfo),
+    ClearMedia { path: String },
+    Audio(AudioFingerprint),
+    ClearAudio { path: String },
+    Tombstone(Tombstone),
+    Log(Operation),
+}
+
+/// An index stored as an append-only log of JSON records in `.oci/index.log`
+/// The whole log is replayed into memory on open and every mutation appends one line,
+/// so it needs no file locking or random writes (unlike SQLite over NFS/SMB).
+pub struct LogStore {
+    path: PathBuf,
+    file: File,
+    entries: BTreeMap<String, FileEntry>,
+    paths_by_hash: HashMap<String, BTreeSet<String>>,
+    classes: BTreeMap<String, StorageClass>,
+    tags: BTreeSet<(String, String)>,
+    members: BTreeMap<String, Vec<ArchiveMember>>,
+    media: BTreeMap<String, MediaInfo>,
+    audio: BTreeMap<String, AudioFingerprint>,
+    tombstones: Vec<Tombstone>,
+    operations: Vec<Operation>,
+}
+
+impl LogStore {
+    /// Open (or create) the index log of a repository, compacting it if mostly superseded
+    pub fn open(repo_root: &Path) -> Result<Self> {
+        fs::create_dir_all(repo_root.join(OCI_DIR))
+            .context("Failed to create .oci directory")?;
+        let path = Backend::Log.index_path(repo_root);
+
+        let mut store = LogStore {
+            file: OpenOptions::new().create(true).append(true).open(&path)
+                .context("Failed to open index log")?,
+            path,
+            entries: BTreeMap::new(),
+            paths_by_hash: HashMap::new(),
+            classes: BTreeMap::new(),
+            tags: BTreeSet::new(),
+            members: BTreeMap::new(),
+            media: BTreeMap::new(),
+            audio: BTreeMap::new(),
+            tombstones: Vec::new(),
+            operations: Vec::new(),
+        };
+
+        let record_count = store.replay()?;
+        let live_count = store.entries.len() + store.classes.len() + store.tags.len() + store.members.len() + store.media.len() + store.audio.len()
+            + store.tombstones.len() + store.operations.len();
+        if record_count > live_count * 2 + COMPACT_SLACK {
+            store.compact()?;
+        }
+        Ok(store)
+    }
+
+    /// Apply every record in the log file, returning the number of records
+    fn replay(&mut self) -> Result<usize> {
+        let reader = BufReader::new(File::open(&self.path).context("Failed to read index log")?);
+        let lines: Vec<String> = reader.lines().collect::<std::io::Result<_>>()
+            .context("Failed to read index log")?;
+
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Record>(line) {
+                Ok(record) => self.apply(record)?,
+                // A crash mid-append can leave a partial last line; the write it belonged to never completed
+                Err(_) if i == lines.len() - 1 => {
+                    self.compact()?;
+                    return Ok(lines.len() - 1);
+                }
+                Err(e) => {
+                    return Err(anyhow::anyhow!("Corrupt index log at line {}: {}", i + 1, e))
+                        .context(OciError::IndexCorrupt(self.path.clone()));
+                }
+            }
+        }
+        Ok(lines.len())
+    }
+
+    /// Update the in-memory state for a record
+    fn apply(&mut self, record: Record) -> Result<()> {
+        match record {
+            Record::Upsert(entry) => {
+                self.unlink_hash(&entry.path);
+                if !entry.sha256.is_empty() {
+                    self.paths_by_hash.entry(entry.sha256.clone()).or_default().insert(entry.path.clone());
+                }
+                self.entries.insert(entry.path.clone(), entry);
+            }
+            Record::Remove { path } => {
+                self.unlink_hash(&path);
+                self.entries.remove(&path);
+            }
+            Record::Clear => {
+                self.entries.clear();
+                self.paths_by_hash.clear();
+                self.members.clear();
+                self.media.clear();
+                self.audio.clear();
+            }
+            Record::SetClass { path, class } => {
+                self.classes.insert(path, class.parse()?);
+            }
+            Record::ClearClass { path } => {
+                self.classes.remove(&path);
+            }
+            Record::Tag { path, tag } => {
+                self.tags.insert((path, tag));
+            }
+            Record::Untag { path, tag } => {
+                self.tags.remove(&(path, tag));
+            }
+            Record::Media(info) => {
+                self.media.insert(info.path.clone(), info);
+            }
+            Record::ClearMedia { path } => {
+                self.media.remove(&path);
+            }
+            Record::Audio(print) => {
+                self.audio.insert(print.path.clone(), print);
+            }
+            Record::ClearAudio { path } => {
+                self.audio.remove(&path);
+            }
+            Record::Members { archive, members } => {
+                if members.is_empty() {
+                    self.members.remove(&archive);
+                } else {
+                    self.members.insert(archive, members);
+                }
+            }
+            Record::Tombstone(tombstone) => self.tombstones.push(tombstone),
+            Record::Log(op) => self.operations.push(op),
+        }
+        Ok(())
+    }
+
+    /// Drop a path from the hash lookup (before it is replaced or removed)
+    fn unlink_hash(&mut self, path: &str) {
+        if let Some(old) = self.entries.get(path) {
+            if let Some(paths) = self.paths_by_hash.get_mut(&old.sha256) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.paths_by_hash.remove(&old.sha256);
+                }
+            }
+        }
+    }
+
+    /// Append a record to the log and apply it
+    fn append(&mut self, record: Record) -> Result<()> {
+        let line = serde_json::to_string(&record).context("Failed to encode index record")?;
+        // One write per record so concurrent readers never see half of a line followed by another
+        self.file.write_all(format!("{}\n", line).as_bytes())
+            .context("Failed to append to index log")?;
+        self.apply(record)
+    }
+
+    /// Rewrite the log with only the live state, replacing the file atomically
+    fn compact(&mut self) -> Result<()> {
+        let temp_path = self.path.with_extension("log.tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&temp_path)
+                .context("Failed to create compacted index log")?);
+            let records = self.entries.values().cloned().map(Record::Upsert)
+                .chain(self.classes.iter().map(|(path, class)| Record::SetClass {
+                    path: path.clone(),
+                    class: class.to_string(),
+                }))
+                .chain(self.tags.iter().map(|(path, tag)| Record::Tag { path: path.clone(), tag: tag.clone() }))
+                .chain(self.members.iter().map(|(archive, members)| Record::Members {
+                    archive: archive.clone(),
+                    members: members.clone(),
+                }))
+                .chain(self.media.values().cloned().map(Record::Media))
+                .chain(self.audio.values().cloned().map(Record::Audio))
+                .chain(self.tombstones.iter().cloned().map(Record::Tombstone))
+                .chain(self.operations.iter().cloned().map(Record::Log));
+            for record in records {
+                serde_json::to_writer(&mut writer, &record).context("Failed to encode index record")?;
+                writer.write_all(b"\n").context("Failed to write compacted index log")?;
+            }
+            writer.into_inner().map_err(|e| e.into_error())
+                .and_then(|file| file.sync_all())
+                .context("Failed to write compacted index log")?;
+        }
+        fs::rename(&temp_path, &self.path).context("Failed to replace index log")?;
+        self.file = OpenOptions::new().append(true).open(&self.path)
+            .context("Failed to open index log")?;
+        Ok(())
+    }
+}
+
+impl Store for LogStore {
+    fn upsert(&mut self, entry: FileEntry) -> Result<()> {
+        self.append(Record::Upsert(entry))
+    }
+
+    fn remove(&mut self, path: &str) -> Result<()> {
+        if !self.entries.contains_key(path) {
+            return Ok(());
+        }
+        self.append(Record::Remove { path: path.to_string() })
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.append(Record::Clear)
+    }
+
+    fn get(&self, path: &str) -> Result<Option<FileEntry>> {
+        Ok(self.entries.get(path).cloned())
+    }
+
+    fn entries(&self) -> Result<Vec<FileEntry>> {
+        Ok(self.entries.values().cloned().collect())
+    }
+
+    fn dir_entries(&self, dir: &str) -> Result<Vec<FileEntry>> {
+        Ok(self.dir_entries_recursive(dir)?
+            .into_iter()
+            .filter(|entry| parent_dir(&entry.path) == dir)
+            .collect())
+    }
+
+    fn dir_entries_recursive(&self, dir: &str) -> Result<Vec<FileEntry>> {
+        let prefix = dir_prefix(dir);
+        Ok(self.entries.range(prefix.clone()..)
+            .take_while(|(path, _)| path.starts_with(&prefix))
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+
+    fn find_by_hash(&self, hash: &str) -> Result<Vec<FileEntry>> {
+        Ok(self.paths_by_hash.get(hash)
+            .map(|paths| paths.iter().filter_map(|path| self.entries.get(path)).cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn set_archive_members(&mut self, archive: &str, members: &[ArchiveMember]) -> Result<()> {
+        if members.is_empty() && !self.members.contains_key(archive) {
+            return Ok(());
+        }
+        self.append(Record::Members { archive: archive.to_string(), members: members.to_vec() })
+    }
+
+    fn find_members_by_hash(&self, hash: &str) -> Result<Vec<ArchiveMember>> {
+        Ok(self.members.values().flatten().filter(|member| member.sha256 == hash).cloned().collect())
+    }
+
+    fn archive_members(&self) -> Result<Vec<ArchiveMember>> {
+        Ok(self.members.values().flatten().cloned().collect())
+    }
+
+    fn set_media(&mut self, info: &MediaInfo) -> Result<()> {
+        self.append(Record::Media(info.clone()))
+    }
+
+    fn clear_media(&mut self, path: &str) -> Result<()> {
+        if !self.media.contains_key(path) {
+            return Ok(());
+        }
+        self.append(Record::ClearMedia { path: path.to_string() })
+    }
+
+    fn media(&self) -> Result<Vec<MediaInfo>> {
+        Ok(self.media.values().cloned().collect())
+    }
+
+    fn set_audio(&mut self, print: &AudioFingerprint) -> Result<()> {
+        self.append(Record::Audio(print.clone()))
+    }
+
+    fn clear_audio(&mut self, path: &str) -> Result<()> {
+        if !self.audio.contains_key(path) {
+            return Ok(());
+        }
+        self.append(Record::ClearAudio { path: path.to_string() })
+    }
+
+    fn audio_fingerprints(&self) -> Result<Vec<AudioFingerprint>> {
+        Ok(self.audio.values().cloned().collect())
+    }
+
+    fn set_storage_class(&mut self, path: &str, class: StorageClass) -> Result<()> {
+        self.append(Record::SetClass { path: path.to_string(), class: class.to_string() })
+    }
+
+    fn clear_storage_class(&mut self, path: &str) -> Result<bool> {
+        if !self.classes.contains_key(path) {
+            return Ok(false);
+        }
+        self.append(Record::ClearClass { path: path.to_string() })?;
+        Ok(true)
+    }
+
+    fn storage_class_tags(&self) -> Result<Vec<(String, StorageClass)>> {
+        Ok(self.classes.iter().map(|(path, class)| (path.clone(), *class)).collect())
+    }
+
+    fn add_tag(&mut self, path: &str, tag: &str) -> Result<bool> {
+        if self.tags.contains(&(path.to_string(), tag.to_string())) {
+            return Ok(false);
+        }
+        self.append(Record::Tag { path: path.to_string(), tag: tag.to_string() })?;
+        Ok(true)
+    }
+
+    fn remove_tag(&mut self, path: &str, tag: &str) -> Result<bool> {
+        if !self.tags.contains(&(path.to_string(), tag.to_string())) {
+            return Ok(false);
+        }
+        self.append(Record::Untag { path: path.to_string(), tag: tag.to_string() })?;
+        Ok(true)
+    }
+
+    fn tags(&self) -> Result<Vec<(String, String)>> {
+        Ok(self.tags.iter().cloned().collect())
+    }
+
+    fn add_tombstone(&mut self, tombstone: &Tombstone) -> Result<()> {
+        self.append(Record::Tombstone(tombstone.clone()))
+    }
+
+    fn tombstones(&self) -> Result<Vec<Tombstone>> {
+        Ok(self.tombstones.clone())
+    }
+
+    fn log_operation(&mut self, op: &Operation) -> Result<()> {
+        self.append(Record::Log(op.clone()))
+    }
+
+    fn operations(&self, limit: Option<usize>) -> Result<Vec<Operation>> {
+        Ok(self.operations.iter().rev().take(limit.unwrap_or(usize::MAX)).cloned().collect())
+    }
+
+    fn save(&self, _repo_root: &Path) -> Result<()> {
+        // Every mutation is appended as it happens
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(path: &str, hash: &str) -> FileEntry {
+        FileEntry { path: path.to_string(), num_bytes: 1, modified: 1, sha256: hash.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_log_store_replays_mutations() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut store = LogStore::open(dir.path()).unwrap();
+            store.upsert(entry("a.txt", "aa")).unwrap();
+            store.upsert(entry("b.txt", "aa")).unwrap();
+            store.upsert(entry("a.txt", "cc")).unwrap();
+            store.remove("b.txt").unwrap();
+            store.set_storage_class("photos", StorageClass::Cold).unwrap();
+            store.add_tag("photos", "keep").unwrap();
+            assert!(!store.add_tag("photos", "keep").unwrap());
+            store.add_tombstone(&Tombstone {
+                path: "b.txt".to_string(), num_bytes: 1, sha256: "aa".to_string(), removed: 5, reason: "deleted".to_string(),
+            }).unwrap();
+        }
+
+        // A torn final line from an interrupted write is dropped
+        let log_path = Backend::Log.index_path(dir.path());
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(b"{\"upsert\":{\"num_by").unwrap();
+
+        let store = LogStore::open(dir.path()).unwrap();
+        assert_eq!(store.entries().unwrap(), vec![entry("a.txt", "cc")]);
+        assert!(store.find_by_hash("aa").unwrap().is_empty());
+        assert_eq!(store.find_by_hash("cc").unwrap().len(), 1);
+        assert_eq!(store.storage_class_tags().unwrap(), vec![("photos".to_string(), StorageClass::Cold)]);
+        assert_eq!(store.tags().unwrap(), vec![("photos".to_string(), "keep".to_string())]);
+        assert_eq!(store.tombstones().unwrap().len(), 1);
+        let contents = fs::read_to_string(&log_path).unwrap();
+        assert!(contents.lines().all(|line| serde_json::from_str::<Record>(line).is_ok()));
+    }
+}
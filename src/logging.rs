@@ -0,0 +1,87 @@
+use std::fmt;
+use std::time::Instant;
+
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::{FmtSpan, Writer};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable holding a log filter (e.g. `debug` or `oci::store=trace`)
+pub const LOG_ENV: &str = "OCI_LOG";
+
+/// The filter for a number of `-v` flags: warnings by default, then info, debug and trace
+fn level_for(verbosity: u8) -> &'static str {
+    match verbosity {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    }
+}
+
+/// Install the process-wide logger on stderr
+/// `-v` flags win over `OCI_LOG`, which wins over the default (warnings only).
+pub fn init(verbosity: u8) -> anyhow::Result<()> {
+    let filter = match std::env::var(LOG_ENV) {
+        Ok(spec) if verbosity == 0 && !spec.is_empty() => EnvFilter::try_new(&spec)
+            .map_err(|e| anyhow::anyhow!("Invalid {} filter '{}': {}", LOG_ENV, spec, e))?,
+        _ => EnvFilter::new(level_for(verbosity)),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_span_events(FmtSpan::CLOSE)
+        .event_format(Format { start: Instant::now() })
+        .init();
+    Ok(())
+}
+
+/// Warnings and errors read like the messages oci always printed (`Warning: ...`);
+/// diagnostics carry the level, the time since start and the enclosing spans.
+struct Format {
+    start: Instant,
+}
+
+impl<S, N> FormatEvent<S, N> for Format
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "Error: ")?,
+            Level::WARN => write!(writer, "Warning: ")?,
+            level => {
+                write!(writer, "[{:>5} {:>8.3}s] ", level, self.start.elapsed().as_secs_f64())?;
+                if let Some(scope) = ctx.event_scope() {
+                    for span in scope.from_root() {
+                        write!(writer, "{}", span.name())?;
+                        let extensions = span.extensions();
+                        if let Some(fields) = extensions.get::<tracing_subscriber::fmt::FormattedFields<N>>() {
+                            if !fields.is_empty() {
+                                write!(writer, "{{{}}}", fields)?;
+                            }
+                        }
+                        write!(writer, ": ")?;
+                    }
+                }
+            }
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_for_verbosity() {
+        assert_eq!(level_for(0), "warn");
+        assert_eq!(level_for(1), "info");
+        assert_eq!(level_for(2), "debug");
+        assert_eq!(level_for(5), "trace");
+    }
+}
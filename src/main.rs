@@ -6,6 +6,63 @@ mod config;
 mod scanner;
 mod display;
 mod dir_utils;
+mod content_class;
+mod tiering;
+mod oplog;
+mod roots;
+mod policy;
+mod source;
+mod checks;
+mod serve;
+mod publish;
+mod fulfill;
+mod store;
+mod sqlite_store;
+mod migrations;
+mod crypto;
+mod signing;
+mod tags;
+mod dedupe;
+mod progress;
+mod pager;
+mod since;
+mod error;
+mod hash_cache;
+mod disposal;
+mod log_store;
+mod s3;
+mod lock;
+mod legacy;
+mod breakdown;
+mod suggest;
+mod registry;
+mod verify;
+mod audit;
+mod sync;
+mod archive;
+mod media;
+mod audio;
+mod digest;
+mod manifest;
+mod dup_dirs;
+mod snapshot;
+mod bagit;
+mod checksums;
+mod parity;
+mod backup;
+mod bundle;
+mod hooks;
+mod changes;
+mod daemon;
+mod ipc;
+mod merge;
+#[cfg(target_os = "macos")]
+mod fsevents;
+#[cfg(target_os = "linux")]
+mod inotify;
+#[cfg(windows)]
+mod usn;
+mod logging;
 
 use clap::{Parser, Subcommand};
 use anyhow::Result;
@@ -16,12 +73,217 @@ use anyhow::Result;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    
+    /// Log diagnostics to stderr: -v for progress, -vv for timings of scans, hashing and index writes, -vvv for everything
+    /// (given before the command, e.g. `oci -vv update`; OCI_LOG takes a filter such as `debug` instead)
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    verbose: u8,
+    
+    /// Wait for another oci process to release the index instead of failing
+    #[arg(long, global = true)]
+    wait: bool,
+
+    /// Print sizes in KiB/MiB/GiB and times as local dates
+    #[arg(long, global = true)]
+    human: bool,
+
+    /// Color status markers and hashes: auto (when writing to a terminal), always or never
+    #[arg(long, global = true, value_name = "WHEN")]
+    color: Option<String>,
+
+    /// Skip dotfiles and dot-directories, as if they matched an ignore pattern
+    #[arg(long, global = true)]
+    no_hidden: bool,
+
+    /// Print only summaries and errors, not a line per file
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Don't send the output of status and ls through a pager ($OCI_PAGER, $PAGER or less)
+    #[arg(long, global = true)]
+    no_pager: bool,
+}
+
+#[derive(Subcommand)]
+enum TagAction {
+    /// Add tags to a file or directory (a directory's tags apply to everything below it)
+    Add {
+        /// File or directory to tag
+        path: String,
+        
+        /// Tags to add
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    
+    /// Remove tags from a file or directory
+    Remove {
+        /// File or directory to untag
+        path: String,
+        
+        /// Tags to remove
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    
+    /// List the tags of a path, or every tagged path
+    List {
+        /// File or directory (lists every tagged path when omitted)
+        path: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotAction {
+    /// Save the current index under a name
+    Save {
+        /// Name of the snapshot (letters, digits, '-', '_' and '.')
+        name: String,
+    },
+    
+    /// List snapshots, oldest first
+    List,
+    
+    /// Replace the indexed files with a snapshot's, saving the current state as a snapshot first
+    Restore {
+        /// Name of the snapshot to restore
+        name: String,
+        
+        /// Restore without confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DaemonAction {
+    /// Start the daemon in the background (its output goes to .oci/daemon.log)
+    Start {
+        /// Verbose mode - show all files including unchanged
+        #[arg(short)]
+        v: bool,
+        
+        /// Seconds to collect changes before applying them as one update
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        interval: u64,
+        
+        /// Read rate of the idle-time verification (e.g. 10M per second)
+        #[arg(long, value_name = "SIZE", default_value = "10M")]
+        io_limit: String,
+        
+        /// Days from the last verification (by the daemon or 'oci verify') to the next idle-time pass
+        #[arg(long, value_name = "DAYS", default_value_t = 7)]
+        verify_every: u64,
+        
+        /// Don't verify the index in idle time
+        #[arg(long)]
+        no_verify: bool,
+    },
+    
+    /// Stop the daemon, letting it finish its current batch
+    Stop,
+    
+    /// Show whether a daemon is running for the repository
+    Status,
+    
+    /// Run the daemon in the foreground (what `start` runs in the background)
+    Run {
+        /// Verbose mode - show all files including unchanged
+        #[arg(short)]
+        v: bool,
+        
+        /// Seconds to collect changes before applying them as one update
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        interval: u64,
+        
+        /// Read rate of the idle-time verification (e.g. 10M per second)
+        #[arg(long, value_name = "SIZE", default_value = "10M")]
+        io_limit: String,
+        
+        /// Days from the last verification (by the daemon or 'oci verify') to the next idle-time pass
+        #[arg(long, value_name = "DAYS", default_value_t = 7)]
+        verify_every: u64,
+        
+        /// Don't verify the index in idle time
+        #[arg(long)]
+        no_verify: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysAction {
+    /// Create the repository's signing key pair
+    Generate {
+        /// Replace an existing key pair (exports signed with it can no longer be verified)
+        #[arg(short, long)]
+        force: bool,
+    },
+    
+    /// Print the public key, to give to those who verify your exports
+    Show,
+    
+    /// Trust someone else's public key under a name, for use with --verify-key
+    Trust {
+        /// Name to refer to the key by
+        name: String,
+        
+        /// Public key, or a .pub file containing it
+        key: String,
+    },
+    
+    /// List the signing key and trusted keys
+    List,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the value of a setting
+    Get {
+        /// Setting name (e.g. change_detection, marker.added)
+        key: String,
+        
+        /// Read the per-user config (~/.config/oci/config) instead of the repository's
+        #[arg(long)]
+        global: bool,
+    },
+    
+    /// Validate and change a setting
+    Set {
+        /// Setting name (e.g. change_detection, marker.added)
+        key: String,
+        
+        /// New value
+        value: String,
+        
+        /// Change the per-user config (~/.config/oci/config) instead of the repository's
+        #[arg(long)]
+        global: bool,
+    },
+    
+    /// Print every setting
+    List {
+        /// List the per-user config (~/.config/oci/config) instead of the repository's
+        #[arg(long)]
+        global: bool,
+    },
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize an empty index
-    Init,
+    Init {
+        /// Index storage backend: sqlite (default) or log (append-only file, for NFS/SMB)
+        #[arg(long, default_value = "sqlite")]
+        backend: String,
+        
+        /// Encrypt the index with a passphrase (OCI_PASSPHRASE or prompted) or a keyfile
+        #[arg(long)]
+        encrypt: bool,
+        
+        /// File whose contents unlock the encrypted index instead of a passphrase (saved as the keyfile setting)
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+    },
     
     /// Add patterns to the ignore list
     Ignore {
@@ -41,6 +303,27 @@ enum Commands {
         /// Verbose mode - show all files including unchanged and ignored
         #[arg(short)]
         v: bool,
+        
+        /// Print what each status marker means instead of the status
+        #[arg(long)]
+        legend: bool,
+        
+        /// Exit with 1 if there are changes and 2 on errors (0 when clean), for scripts
+        #[arg(long)]
+        check: bool,
+        
+        /// Only look this many directory levels below the path (1 = files directly inside it)
+        #[arg(long, value_name = "DEPTH")]
+        max_depth: Option<usize>,
+        
+        /// List at most N files, then how many more there are
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+        
+        /// Only look at files modified since a time: a duration back from now (30m, 12h, 7d, 2w)
+        /// or a local date and time (2024-05-01, 2024-05-01 18:30); deleted files are always listed
+        #[arg(long, value_name = "TIME")]
+        since: Option<String>,
     },
     
     /// Update the index with changes from the filesystem
@@ -51,6 +334,88 @@ enum Commands {
         /// Verbose mode - show all files including unchanged
         #[arg(short)]
         v: bool,
+        
+        /// Compute full hashes for large files (including those only quick-hashed so far)
+        #[arg(long)]
+        full_hash: bool,
+        
+        /// Index files even if they violate .oci/policy.toml
+        #[arg(long)]
+        override_policy: bool,
+        
+        /// Only look this many directory levels below the pattern (1 = files directly inside it)
+        #[arg(long, value_name = "DEPTH")]
+        max_depth: Option<usize>,
+        
+        /// Only visit directories the filesystem's change journal reports as changed since the last update (macOS)
+        #[arg(long, conflicts_with_all = ["pattern", "max_depth"])]
+        fast: bool,
+    },
+    
+    /// Keep the index up to date by watching for changes (inotify on Linux, FSEvents on macOS)
+    Watch {
+        /// Verbose mode - show all files including unchanged
+        #[arg(short)]
+        v: bool,
+        
+        /// Seconds to collect changes before applying them as one update
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        interval: u64,
+    },
+    
+    /// Run a background process that keeps the index current and verifies it in idle time
+    Daemon {
+        #[command(subcommand)]
+        action: DaemonAction,
+    },
+    
+    /// Remove entries for files that no longer exist, without hashing anything
+    Gc,
+    
+    /// Move a file or directory and update its index entries without re-hashing
+    Mv {
+        /// File or directory to move
+        source: String,
+        
+        /// New path, or an existing directory to move into
+        destination: String,
+    },
+    
+    /// Delete a file or directory by moving it to the pruneyard and removing it from the index
+    Rm {
+        /// File or directory to remove
+        path: String,
+        
+        /// Remove files even if .oci/policy.toml protects them
+        #[arg(long)]
+        override_policy: bool,
+        
+        /// Move the files to the system trash instead of the pruneyard
+        #[arg(long)]
+        trash: bool,
+    },
+    
+    /// Copy a directory into the repository, skipping files whose content is already indexed
+    Cp {
+        /// Directory to copy from (e.g. a memory card)
+        source: String,
+        
+        /// Directory in the repository to copy into
+        destination: String,
+    },
+    
+    /// Make a destination directory mirror the indexed files, verifying every copy
+    Sync {
+        /// Directory to mirror to (e.g. on an external drive)
+        destination: String,
+        
+        /// Remove destination files that are not in the index
+        #[arg(long)]
+        delete: bool,
+        
+        /// Show what would change without touching the destination
+        #[arg(long)]
+        dry_run: bool,
     },
     
     /// List files in the index
@@ -58,20 +423,99 @@ enum Commands {
         /// Recurse into subdirectories
         #[arg(short)]
         r: bool,
+        
+        /// Draw the files below the current directory as a tree, with file counts and sizes per directory
+        #[arg(long, conflicts_with = "media")]
+        tree: bool,
+        
+        /// Only list files of this content type (e.g. image/jpeg, image/*, or image)
+        #[arg(long = "type", value_name = "TYPE")]
+        content_type: Option<String>,
+        
+        /// Only list files with this tag, on the file or a directory above it (see `oci tag`)
+        #[arg(long, value_name = "TAG")]
+        tag: Option<String>,
+        
+        /// Only list photos and videos taken in this period (e.g. 2017, 2017-06, 2017-06-01)
+        #[arg(long, value_name = "DATE")]
+        taken: Option<String>,
+        
+        /// Only list photos and videos from a camera whose make or model contains this text
+        #[arg(long, value_name = "TEXT")]
+        camera: Option<String>,
+        
+        /// Show capture date, camera and dimensions after each photo and video
+        #[arg(long)]
+        media: bool,
+        
+        /// Print each file with a template, e.g. '{path}\t{size}\t{sha256}' (see README for the fields),
+        /// or `ndjson` to stream one JSON object per line
+        #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["tree", "media"])]
+        format: Option<String>,
+        
+        /// List at most N files, then how many more there are
+        #[arg(long, value_name = "N", conflicts_with = "tree")]
+        limit: Option<usize>,
+        
+        /// Only list files modified since a time: a duration back from now (30m, 12h, 7d, 2w)
+        /// or a local date and time (2024-05-01, 2024-05-01 18:30)
+        #[arg(long, value_name = "TIME")]
+        since: Option<String>,
     },
     
     /// Find files by hash
     Grep {
-        /// SHA256 hash to search for
-        hash: String,
+        /// SHA256 hashes to search for; `-` reads them from stdin, one per line
+        #[arg(required = true, value_name = "HASH")]
+        hashes: Vec<String>,
+        
+        /// Algorithm of the hashes: md5, sha1, sha256 or sha512 (guessed from their length by default)
+        #[arg(long, value_name = "ALGORITHM")]
+        algorithm: Option<String>,
+        
+        /// Print only the matching files, each with a template or as `ndjson` (like `ls --format`)
+        #[arg(long, value_name = "TEMPLATE")]
+        format: Option<String>,
     },
     
     /// Find duplicate files (files with identical content)
-    Duplicates,
+    Duplicates {
+        /// Group audio files that sound the same (by fingerprint) instead of files with identical bytes
+        #[arg(long)]
+        audio: bool,
+        
+        /// Walk each group, choose the copy to keep and move the others to the pruneyard
+        #[arg(long)]
+        resolve: bool,
+        
+        /// With --resolve, keep the first copy of each group (by path) without asking
+        #[arg(long)]
+        auto: bool,
+        
+        /// With --resolve, choose the copy to keep without asking: oldest, newest, shortest-path or prefer-dir <DIR>
+        #[arg(long, num_args = 1..=2, value_names = ["POLICY", "DIR"])]
+        keep: Vec<String>,
+        
+        /// With --resolve, compare each copy byte for byte with the one kept before moving it
+        #[arg(long)]
+        paranoid: bool,
+        
+        /// Report content shared with other indexes (paths, or ssh://, https:// or s3:// URLs) and where each copy lives
+        #[arg(long, num_args = 1.., value_name = "SOURCE")]
+        against: Vec<String>,
+        
+        /// Report whole directories whose every file has a copy in another directory
+        #[arg(long, conflicts_with_all = ["audio", "resolve", "against"])]
+        dirs: bool,
+        
+        /// Print the groups for other tools' scripts: fdupes (paths, a blank line after each group) or rmlint-json
+        #[arg(long, value_name = "FORMAT", conflicts_with_all = ["audio", "resolve", "against", "dirs"])]
+        format: Option<String>,
+    },
     
     /// Remove files that exist in another index
     Prune {
-        /// Path to another oci index (source)
+        /// Path to another oci index, or an ssh://host/path, https:// or s3://bucket/prefix URL (source)
         source: Option<String>,
         
         /// Permanently delete pruned files
@@ -93,6 +537,231 @@ enum Commands {
         /// Prune files matching local ignore patterns
         #[arg(long)]
         ignored: bool,
+        
+        /// Proceed even if the prune or purge violates .oci/policy.toml
+        #[arg(long)]
+        override_policy: bool,
+        
+        /// Require a valid signature by this key: a trusted key name (see `oci keys`), a .pub file or a public key
+        #[arg(long, value_name = "KEY")]
+        verify_key: Option<String>,
+        
+        /// Never prune files with this tag (repeatable, e.g. --skip-tag keep)
+        #[arg(long = "skip-tag", value_name = "TAG")]
+        skip_tags: Vec<String>,
+        
+        /// Compare each duplicate byte for byte with its copy in the source before pruning it (local sources only)
+        #[arg(long)]
+        paranoid: bool,
+        
+        /// Move pruned files to the system trash instead of the pruneyard
+        #[arg(long, conflicts_with_all = ["purge", "restore"])]
+        trash: bool,
+        
+        /// Report what would be pruned, with the source copies and space reclaimed, without changing anything
+        #[arg(long, conflicts_with_all = ["purge", "restore"])]
+        dry_run: bool,
+        
+        /// Only prune files under this file or directory
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["purge", "restore"])]
+        path: Option<String>,
+        
+        /// Only prune files matching this pattern, e.g. '*.jpg' (repeatable; ignore-file syntax)
+        #[arg(long = "match", value_name = "PATTERN", conflicts_with_all = ["purge", "restore"])]
+        matches: Vec<String>,
+    },
+    
+    /// Compare the index with another index (a path, ssh://host/path, https:// or s3:// URL)
+    Diff {
+        /// Path or URL of the other oci index (source)
+        source: String,
+        
+        /// Require a valid signature by this key: a trusted key name (see `oci keys`), a .pub file or a public key
+        #[arg(long, value_name = "KEY")]
+        verify_key: Option<String>,
+    },
+    
+    /// Compare the whole index with another index: identical, conflicting, moved and one-sided files
+    Compare {
+        /// Path or URL of the other oci index
+        other: String,
+        
+        /// Require a valid signature by this key: a trusted key name (see `oci keys`), a .pub file or a public key
+        #[arg(long, value_name = "KEY")]
+        verify_key: Option<String>,
+    },
+    
+    /// Merge another index into this one (e.g. after copying another drive's files into this tree)
+    Merge {
+        /// Path or URL of the other oci index
+        other: String,
+
+        /// Directory (from the current one) the other index's files now live in
+        #[arg(long, value_name = "DIR")]
+        prefix: Option<String>,
+
+        /// Which entry wins where both have different content at a path: ours, theirs or newer
+        #[arg(long, default_value = "ours", value_name = "SIDE")]
+        prefer: String,
+
+        /// Require a valid signature by this key: a trusted key name (see `oci keys`), a .pub file or a public key
+        #[arg(long, value_name = "KEY")]
+        verify_key: Option<String>,
+
+        /// Show what would be merged without changing the index
+        #[arg(long)]
+        dry_run: bool,
+    },
+    
+    /// Give a subdirectory its own index, with the entries moved to its root (no hashing)
+    Split {
+        /// Subdirectory to split off
+        subdir: String,
+
+        /// Directory of the new repository, e.g. on the drive the subdirectory moves to
+        new_repo: String,
+    },
+    
+    /// Compare the content of two directories, which may be in different repositories
+    Overlap {
+        /// First directory
+        first: String,
+        
+        /// Second directory, e.g. an old backup of the first
+        second: String,
+    },
+    
+    /// List files in another index whose content is nowhere in this index
+    Missing {
+        /// Path or URL of the other oci index (source)
+        source: String,
+        
+        /// Require a valid signature by this key: a trusted key name (see `oci keys`), a .pub file or a public key
+        #[arg(long, value_name = "KEY")]
+        verify_key: Option<String>,
+    },
+    
+    /// Re-hash indexed files and check them against the index
+    Verify {
+        /// Verify every registered repository whose volume is mounted (see `oci repos`)
+        #[arg(long, conflicts_with = "manifest")]
+        all_repos: bool,
+        
+        /// Check the files against a checksum file (sha256sum, md5sum, shasum --tag or hashdeep output) instead of the index
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<String>,
+        
+        /// With --manifest, compare against the digests recorded in the index instead of reading the files
+        #[arg(long, requires = "manifest")]
+        index: bool,
+        
+        /// Limit reads to this many bytes per second across all repositories (e.g. 100M)
+        #[arg(long, value_name = "RATE")]
+        io_limit: Option<String>,
+        
+        /// Repair damaged or missing files from their directories' recovery sets (see `oci parity`)
+        #[arg(long, conflicts_with_all = ["all_repos", "manifest"])]
+        repair: bool,
+    },
+    
+    /// Run validation checks and report their findings
+    Check {
+        /// Run the executables in .oci/checks
+        #[arg(long)]
+        external: bool,
+    },
+    
+    /// Serve a read-only HTTP API over the index
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        bind: String,
+    },
+    
+    /// Write a copy of the index to a directory, to hand to others as a prune source
+    Export {
+        /// Directory to write the index to (as DIR/.oci)
+        #[arg(required_unless_present_any = ["bundle", "manifest", "bagit"], conflicts_with_all = ["bundle", "manifest", "bagit"])]
+        dest: Option<String>,
+        
+        /// Write the index, config and ignore patterns to one compressed file instead (e.g. archive.ocib)
+        #[arg(long, value_name = "FILE", conflicts_with = "manifest")]
+        bundle: Option<String>,
+        
+        /// Write a checksum file instead (`<digest>  <path>` lines, as read by sha256sum -c and md5sum -c)
+        #[arg(long, value_name = "FILE", conflicts_with = "sign")]
+        manifest: Option<String>,
+        
+        /// Write a BagIt bag instead: the files copied into DIR/data, with a manifest of their indexed digests
+        #[arg(long, value_name = "DIR", conflicts_with_all = ["bundle", "manifest", "sign"])]
+        bagit: Option<String>,
+        
+        /// With --bagit, only bag the files under this file or directory
+        #[arg(long, value_name = "PATH", requires = "bagit")]
+        path: Option<String>,
+        
+        /// Algorithm of the manifest's digests: md5, sha1, sha256 (the default) or sha512
+        #[arg(long, value_name = "ALGORITHM")]
+        algorithm: Option<String>,
+        
+        /// Sign the exported index with the repository's signing key (see `oci keys`)
+        #[arg(long)]
+        sign: bool,
+        
+        /// Only export files with this tag (see `oci tag`)
+        #[arg(long, value_name = "TAG")]
+        tag: Option<String>,
+    },
+    
+    /// Write a checksum file into each indexed directory, listing its files' digests from the index
+    Checksums {
+        /// Only directories under this one (defaults to the whole repository)
+        path: Option<String>,
+        
+        /// Algorithm of the digests: md5, sha1, sha256 (the default) or sha512
+        #[arg(long, value_name = "ALGORITHM")]
+        algorithm: Option<String>,
+        
+        /// Write SFV files of CRC32s instead (computed by reading the files)
+        #[arg(long, conflicts_with = "algorithm")]
+        sfv: bool,
+    },
+    
+    /// Make PAR2 recovery sets of the indexed directories (kept in .oci/parity), for `verify --repair`
+    Parity {
+        /// Only directories under this one (defaults to the whole repository)
+        path: Option<String>,
+        
+        /// Recovery data as a percentage of each directory's size
+        #[arg(long, value_name = "PERCENT", default_value_t = parity::DEFAULT_REDUNDANCY, value_parser = clap::value_parser!(u8).range(1..=100))]
+        redundancy: u8,
+    },
+    
+    /// Manage the keys that sign exports and verify sources (kept in .oci/keys)
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+    },
+    
+    /// Publish a read-only, browsable catalog of the index
+    Publish {
+        /// Directory to write a static HTML catalog to
+        #[arg(long = "static", value_name = "DIR")]
+        static_dir: String,
+        
+        /// Catalog title (defaults to the repository directory name)
+        #[arg(long)]
+        title: Option<String>,
+    },
+    
+    /// Pack the files listed in a request file into a verified zip archive
+    Fulfill {
+        /// Request file (e.g. downloaded from a published catalog)
+        request: String,
+        
+        /// Archive to write (defaults to the request file name with a .zip extension)
+        #[arg(short, long)]
+        output: Option<String>,
     },
     
     /// Reset the index (clear all entries)
@@ -102,6 +771,22 @@ enum Commands {
         f: bool,
     },
     
+    /// List the index backups in .oci/backups, or replace the index with one of them
+    RestoreIndex {
+        /// Name of the backup to restore (or the start of it, such as its time); lists the backups if omitted
+        name: Option<String>,
+        
+        /// Restore without confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
+    
+    /// Save, list and restore snapshots of the index (kept in .oci/snapshots)
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    
     /// Remove the index (opposite of init)
     Deinit {
         /// Force removal without confirmation
@@ -110,27 +795,241 @@ enum Commands {
     },
     
     /// Show index statistics
-    Stats,
+    Stats {
+        /// Break down files, size and duplicate waste by file extension
+        #[arg(long)]
+        by_extension: bool,
+        
+        /// Break down files, size and duplicate waste by directory, to the given depth (default 1)
+        #[arg(long, value_name = "DEPTH", num_args = 0..=1, default_missing_value = "1")]
+        by_dir: Option<usize>,
+        
+        /// Break down files, size and duplicate waste by detected content type
+        #[arg(long)]
+        by_type: bool,
+        
+        /// Show the oldest and newest files and break down files, size and duplicate waste by year modified
+        #[arg(long)]
+        age: bool,
+        
+        /// Only list the N largest files, read straight from the index instead of summarizing it
+        #[arg(long, value_name = "N", conflicts_with_all = ["by_extension", "by_dir", "by_type", "age"])]
+        top: Option<usize>,
+        
+        /// With --top, also list the N duplicate groups wasting the most space
+        #[arg(long, requires = "top")]
+        duplicates: bool,
+    },
     
     /// List all files sorted by size (largest first)
     Hogs,
+    
+    /// Show the indexed size of each subdirectory, read from the index without touching the files
+    Du {
+        /// Directory to summarize (the current directory when omitted)
+        path: Option<String>,
+        
+        /// Include every level of subdirectories, not just the first
+        #[arg(short, long)]
+        recursive: bool,
+        
+        /// Include subdirectories down to this many levels
+        #[arg(short, long, value_name = "DEPTH", conflicts_with = "recursive")]
+        depth: Option<usize>,
+    },
+    
+    /// Tag a file or directory with a storage class (hot, warm, cold, offsite)
+    Tier {
+        /// Path to tag (lists tags and rules when omitted)
+        path: Option<String>,
+        
+        /// Storage class to assign (shows the current class when omitted)
+        class: Option<String>,
+        
+        /// Remove the manual tag from the path
+        #[arg(long)]
+        clear: bool,
+    },
+    
+    /// Tag files and directories (e.g. keep, to-review, client-x) for ls --tag, prune --skip-tag and export --tag
+    Tag {
+        #[command(subcommand)]
+        action: TagAction,
+    },
+    
+    /// Register a root directory of a multi-root index (lists roots when called without arguments)
+    Root {
+        /// Name of the root (used as the first component of its index paths)
+        name: Option<String>,
+        
+        /// Directory to register under the name (shows the registered path when omitted)
+        path: Option<String>,
+        
+        /// Unregister the root and drop its entries from the index
+        #[arg(long)]
+        remove: bool,
+    },
+    
+    /// Register a repository on this machine (lists registered repositories when called without a path)
+    Repos {
+        /// Repository root to register (defaults to listing)
+        path: Option<String>,
+        
+        /// Unregister the repository
+        #[arg(long)]
+        remove: bool,
+    },
+    
+    /// Show the log of index changes and who made them (newest first)
+    Log {
+        /// Show only the N most recent operations
+        #[arg(short)]
+        n: Option<usize>,
+    },
+    
+    /// Audit the index for content that needs attention
+    Audit {
+        /// Content that left the repository, and whether it still exists elsewhere
+        #[arg(long)]
+        departed: bool,
+    },
+    
+    /// Read and change settings in .oci/config
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    
+    /// Show reports about the index
+    Report {
+        /// Bytes per storage class and files violating their class policy
+        #[arg(long)]
+        tiering: bool,
+    },
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+fn main() {
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(error::exit_code(&err));
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    logging::init(cli.verbose)?;
+    lock::set_wait(cli.wait);
+    if cli.human {
+        display::set_human_readable(true);
+    }
+    if let Some(color) = &cli.color {
+        display::set_color_mode(color.parse()?);
+    }
+    if cli.no_hidden {
+        ignore::set_skip_hidden(true);
+    }
+    if cli.quiet {
+        display::set_quiet(true);
+    }
+    if cli.no_pager {
+        pager::set_enabled(false);
+    }
 
     match cli.command {
-        Commands::Init => commands::init(),
+        Commands::Init { backend, encrypt, keyfile } => commands::init(backend, encrypt, keyfile),
         Commands::Ignore { pattern } => commands::ignore(pattern),
-        Commands::Status { path, r, v } => commands::status(path, r, v),
-        Commands::Update { pattern, v } => commands::update(pattern, v),
-        Commands::Ls { r } => commands::ls(r),
-        Commands::Grep { hash } => commands::grep(&hash),
-        Commands::Duplicates => commands::duplicates(),
-        Commands::Prune { source, purge, restore, force, no_ignore, ignored } => commands::prune(source, purge, restore, force, no_ignore, ignored),
+        Commands::Status { path, r, v, legend, check, max_depth, limit, since } => {
+            let result = commands::status(path, r, v, legend, max_depth, limit, since);
+            if check {
+                match result {
+                    Ok(false) => {}
+                    Ok(true) => std::process::exit(1),
+                    Err(err) => {
+                        eprintln!("Error: {:?}", err);
+                        std::process::exit(2);
+                    }
+                }
+            }
+            result.map(|_| ())
+        }
+        Commands::Update { pattern, v, full_hash, override_policy, max_depth, fast } => {
+            commands::update(pattern, v, full_hash, override_policy, max_depth, fast)
+        }
+        Commands::Watch { v, interval } => commands::watch(v, interval),
+        Commands::Daemon { action } => match action {
+            DaemonAction::Start { v, interval, io_limit, verify_every, no_verify } => {
+                commands::daemon_start(commands::DaemonOptions { interval, io_limit, verify_every, no_verify, verbose: v })
+            }
+            DaemonAction::Stop => commands::daemon_stop(),
+            DaemonAction::Status => commands::daemon_status(),
+            DaemonAction::Run { v, interval, io_limit, verify_every, no_verify } => {
+                commands::daemon_run(commands::DaemonOptions { interval, io_limit, verify_every, no_verify, verbose: v })
+            }
+        },
+        Commands::Gc => commands::gc(),
+        Commands::Mv { source, destination } => commands::mv(source, destination),
+        Commands::Rm { path, override_policy, trash } => commands::rm(path, override_policy, trash),
+        Commands::Cp { source, destination } => commands::cp(source, destination),
+        Commands::Sync { destination, delete, dry_run } => commands::sync(destination, delete, dry_run),
+        Commands::Ls { r, tree, content_type, tag, taken, camera, media, format, limit, since } => {
+            commands::ls(r, tree, content_type, tag, media::MediaFilter { taken, camera, show: media }, format, limit, since)
+        }
+        Commands::Grep { hashes, algorithm, format } => commands::grep(hashes, algorithm, format),
+        Commands::Duplicates { audio, resolve, auto, keep, paranoid, against, dirs, format } => {
+            commands::duplicates(audio, resolve, auto, keep, paranoid, against, dirs, format)
+        }
+        Commands::Prune { source, purge, restore, force, no_ignore, ignored, override_policy, verify_key, skip_tags, paranoid, trash, dry_run, path, matches } => {
+            let scope = commands::PruneScope { path, matches };
+            commands::prune(source, purge, restore, force, no_ignore, ignored, override_policy, verify_key, skip_tags, paranoid, trash, dry_run, scope)
+        }
+        Commands::Diff { source, verify_key } => commands::diff(source, verify_key),
+        Commands::Compare { other, verify_key } => commands::compare(other, verify_key),
+        Commands::Merge { other, prefix, prefer, verify_key, dry_run } => commands::merge(other, prefix, prefer, verify_key, dry_run),
+        Commands::Split { subdir, new_repo } => commands::split(subdir, new_repo),
+        Commands::Missing { source, verify_key } => commands::missing(source, verify_key),
+        Commands::Verify { all_repos, manifest, index, io_limit, repair } => commands::verify(all_repos, manifest, index, io_limit, repair),
+        Commands::Check { external } => commands::check(external),
+        Commands::Serve { bind } => commands::serve(bind),
+        Commands::Export { dest, bundle, manifest, bagit, path, algorithm, sign, tag } => {
+            commands::export(dest, bundle, manifest, bagit, path, algorithm, sign, tag)
+        }
+        Commands::Checksums { path, algorithm, sfv } => commands::checksums(path, algorithm, sfv),
+        Commands::Parity { path, redundancy } => commands::parity(path, redundancy),
+        Commands::Keys { action } => match action {
+            KeysAction::Generate { force } => commands::keys_generate(force),
+            KeysAction::Show => commands::keys_show(),
+            KeysAction::Trust { name, key } => commands::keys_trust(name, key),
+            KeysAction::List => commands::keys_list(),
+        },
+        Commands::Publish { static_dir, title } => commands::publish(static_dir, title),
+        Commands::Fulfill { request, output } => commands::fulfill(request, output),
         Commands::Reset { f } => commands::reset(f),
+        Commands::RestoreIndex { name, force } => commands::restore_index(name, force),
+        Commands::Snapshot { action } => match action {
+            SnapshotAction::Save { name } => commands::snapshot_save(name),
+            SnapshotAction::List => commands::snapshot_list(),
+            SnapshotAction::Restore { name, force } => commands::snapshot_restore(name, force),
+        },
         Commands::Deinit { f } => commands::deinit(f),
-        Commands::Stats => commands::stats(),
+        Commands::Stats { top: Some(limit), duplicates, .. } => commands::stats_top(limit, duplicates),
+        Commands::Stats { by_extension, by_dir, by_type, age, top: None, .. } => commands::stats(by_extension, by_dir, by_type, age),
         Commands::Hogs => commands::hogs(),
+        Commands::Du { path, recursive, depth } => commands::du(path, recursive, depth),
+        Commands::Overlap { first, second } => commands::overlap(first, second),
+        Commands::Tier { path, class, clear } => commands::tier(path, class, clear),
+        Commands::Tag { action } => match action {
+            TagAction::Add { path, tags } => commands::tag_add(path, tags),
+            TagAction::Remove { path, tags } => commands::tag_remove(path, tags),
+            TagAction::List { path } => commands::tag_list(path),
+        },
+        Commands::Root { name, path, remove } => commands::root(name, path, remove),
+        Commands::Repos { path, remove } => commands::repos(path, remove),
+        Commands::Log { n } => commands::log(n),
+        Commands::Audit { departed } => commands::audit(departed),
+        Commands::Config { action } => match action {
+            ConfigAction::Get { key, global } => commands::config_get(key, global),
+            ConfigAction::Set { key, value, global } => commands::config_set(key, value, global),
+            ConfigAction::List { global } => commands::config_list(global),
+        },
+        Commands::Report { tiering } => commands::report(tiering),
     }
 }
@@ -0,0 +1,177 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::digest::Algorithm;
+
+/// A checksum file from another tool: `sha256sum`/`md5sum` output, BSD-style `shasum --tag`
+/// lines or a hashdeep file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub algorithm: Algorithm,
+    /// Paths as written in the manifest (unescaped, without a leading `./`) and their digests
+    pub entries: Vec<(String, String)>,
+}
+
+/// Read and parse a manifest file
+pub fn load(path: &Path) -> Result<Manifest> {
+    let text = fs::read_to_string(path).context(format!("Failed to read manifest {}", path.display()))?;
+    parse(&text).context(format!("Failed to parse manifest {}", path.display()))
+}
+
+/// Parse a manifest, detecting its format from its first line
+pub fn parse(text: &str) -> Result<Manifest> {
+    if text.starts_with("%%%% HASHDEEP") {
+        return parse_hashdeep(text);
+    }
+
+    let mut algorithm = None;
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (line_algorithm, path, hex) = parse_sum_line(line)
+            .with_context(|| format!("Unrecognized line {}: {}", number + 1, line))?;
+        match algorithm {
+            None => algorithm = Some(line_algorithm),
+            Some(algorithm) if algorithm != line_algorithm => {
+                bail!("Line {} uses {}, but earlier lines use {}", number + 1, line_algorithm, algorithm);
+            }
+            Some(_) => {}
+        }
+        entries.push((normalize(&path), hex.to_ascii_lowercase()));
+    }
+    match algorithm {
+        Some(algorithm) => Ok(Manifest { algorithm, entries }),
+        None => bail!("No checksums found"),
+    }
+}
+
+/// One `<hex>  <path>` (or `<hex> *<path>` for binary mode) or `SHA256 (<path>) = <hex>` line
+fn parse_sum_line(line: &str) -> Option<(Algorithm, String, String)> {
+    // GNU tools escape names holding backslashes or newlines and mark the line with a leading `\`
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let unescape = |path: &str| if escaped { unescape(path) } else { path.to_string() };
+
+    if let Some((name, rest)) = line.split_once(" (") {
+        let (path, hex) = rest.rsplit_once(") = ")?;
+        let algorithm: Algorithm = name.parse().ok()?;
+        return is_hex(hex, algorithm).then(|| (algorithm, unescape(path), hex.to_string()));
+    }
+
+    let (hex, rest) = line.split_once(' ')?;
+    let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    let algorithm = Algorithm::for_hex_len(hex.len());
+    is_hex(hex, algorithm).then(|| (algorithm, unescape(path), hex.to_string()))
+}
+
+/// A hashdeep file: `%%%%` and `##` header lines, then `size,<hash>,...,filename` rows in the
+/// columns named by the `%%%% size,...` header. The strongest supported hash is used.
+fn parse_hashdeep(text: &str) -> Result<Manifest> {
+    let columns: Vec<&str> = text
+        .lines()
+        .find_map(|line| line.strip_prefix("%%%% size,"))
+        .context("Missing hashdeep column header")?
+        .trim_end_matches('\r')
+        .split(',')
+        .collect();
+    // The file name is the last column and may itself contain commas
+    let Some((&"filename", hashes)) = columns.split_last() else {
+        bail!("Unexpected hashdeep columns: size,{}", columns.join(","));
+    };
+    let (column, algorithm) = [Algorithm::Sha512, Algorithm::Sha256, Algorithm::Sha1, Algorithm::Md5]
+        .into_iter()
+        .find_map(|algorithm| hashes.iter().position(|name| *name == algorithm.name()).map(|column| (column, algorithm)))
+        .with_context(|| format!("No supported hash among the hashdeep columns: {}", hashes.join(",")))?;
+
+    let mut entries = Vec::new();
+    for line in text.lines().map(|line| line.trim_end_matches('\r')) {
+        if line.is_empty() || line.starts_with("%%%%") || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(hashes.len() + 2, ',').collect();
+        if fields.len() != hashes.len() + 2 || !is_hex(fields[column + 1], algorithm) {
+            bail!("Unrecognized hashdeep line: {}", line);
+        }
+        entries.push((normalize(fields[hashes.len() + 1]), fields[column + 1].to_ascii_lowercase()));
+    }
+    Ok(Manifest { algorithm, entries })
+}
+
+fn is_hex(hex: &str, algorithm: Algorithm) -> bool {
+    Algorithm::for_hex_len(hex.len()) == algorithm
+        && matches!(hex.len(), 32 | 40 | 64 | 128)
+        && hex.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn unescape(path: &str) -> String {
+    let mut result = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+fn normalize(path: &str) -> String {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sum_formats() {
+        let text = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  ./photos/a.jpg\n\
+                    # comment\n\
+                    \\2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881  we\\\\ird\n\
+                    B94D27B9934D3E08A52E52D7DA7DABFAC484EFE37A5380EE9088F7ACE2EFCDE9 *bin/b.dat\n";
+        let manifest = parse(text).unwrap();
+        assert_eq!(manifest.algorithm, Algorithm::Sha256);
+        let paths: Vec<&str> = manifest.entries.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["photos/a.jpg", "we\\ird", "bin/b.dat"]);
+        assert!(manifest.entries[2].1.starts_with("b94d27"));
+
+        let bsd = parse("MD5 (notes (old).txt) = 5eb63bbbe01eeed093cb22bb8f5acdc3\n").unwrap();
+        assert_eq!(bsd.algorithm, Algorithm::Md5);
+        assert_eq!(bsd.entries[0].0, "notes (old).txt");
+
+        assert!(parse("5eb63bbbe01eeed093cb22bb8f5acdc3  a\nb94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  b\n").is_err());
+        assert!(parse("not a checksum\n").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_parse_hashdeep() {
+        let text = "%%%% HASHDEEP-1.0\n\
+                    %%%% size,md5,sha256,filename\n\
+                    ## Invoked from: /data\n\
+                    ## $ hashdeep -r .\n\
+                    ##\n\
+                    11,5eb63bbbe01eeed093cb22bb8f5acdc3,b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9,./docs/a, b.txt\n";
+        let manifest = parse(text).unwrap();
+        assert_eq!(manifest.algorithm, Algorithm::Sha256);
+        assert_eq!(manifest.entries, vec![(
+            "docs/a, b.txt".to_string(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9".to_string(),
+        )]);
+    }
+}
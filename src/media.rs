@@ -0,0 +1,350 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::oplog;
+
+/// Photo extensions read for EXIF metadata (formats the exif reader supports, including TIFF-based raws)
+const PHOTO_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "tif", "tiff", "png", "webp", "heic", "heif", "avif", "dng", "cr2", "nef", "arw", "orf", "rw2",
+];
+/// Video extensions read for their QuickTime/MP4 movie header
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "m4v", "3gp"];
+
+/// Largest `moov` box read into memory; movie headers are normally a few megabytes at most
+const MAX_MOOV_BYTES: u64 = 64 * 1024 * 1024;
+/// Seconds from 1904-01-01 (the QuickTime epoch) to 1970-01-01
+const QUICKTIME_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+/// Metadata extracted from a photo or video with `extract_media`
+/// Every field is optional: a file without metadata is still recorded, so it is not read again.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    /// Index path of the file
+    pub path: String,
+    /// When the photo or video was taken, as "YYYY-MM-DD HH:MM:SS"
+    /// (camera local time for photos, UTC for videos)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken: Option<String>,
+    /// Camera make and model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl MediaInfo {
+    /// Short description of the known fields, e.g. "2017-06-01 12:00:00, Canon EOS 5D, 4000x3000"
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        parts.extend(self.taken.clone());
+        parts.extend(self.camera.clone());
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            parts.push(format!("{}x{}", width, height));
+        }
+        parts.join(", ")
+    }
+}
+
+/// `ls` options selecting and showing photos and videos by their metadata
+#[derive(Debug, Clone, Default)]
+pub struct MediaFilter {
+    /// Prefix of the capture date, e.g. "2017" or "2017-06"
+    pub taken: Option<String>,
+    /// Text contained in the camera make and model (case-insensitive)
+    pub camera: Option<String>,
+    /// Print the metadata after each entry
+    pub show: bool,
+}
+
+impl MediaFilter {
+    /// Whether the filter selects files (rather than only showing metadata)
+    pub fn is_active(&self) -> bool {
+        self.taken.is_some() || self.camera.is_some()
+    }
+
+    pub fn matches(&self, info: &MediaInfo) -> bool {
+        let taken = self.taken.as_ref()
+            .is_none_or(|prefix| info.taken.as_ref().is_some_and(|taken| taken.starts_with(prefix.as_str())));
+        let camera = self.camera.as_ref().is_none_or(|text| {
+            info.camera.as_ref().is_some_and(|camera| camera.to_lowercase().contains(&text.to_lowercase()))
+        });
+        taken && camera
+    }
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path).extension().map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+/// Whether a path names a photo or video whose metadata can be extracted
+pub fn is_media(path: &str) -> bool {
+    extension(path).is_some_and(|ext| {
+        PHOTO_EXTENSIONS.contains(&ext.as_str()) || VIDEO_EXTENSIONS.contains(&ext.as_str())
+    })
+}
+
+/// Extract the metadata of a photo or video
+/// Files that are malformed or carry no metadata yield a MediaInfo without fields; only
+/// errors opening or reading the file are returned.
+pub fn read_media(full_path: &Path, path: &str) -> Result<MediaInfo> {
+    let file = File::open(full_path)
+        .context(format!("Failed to open file: {}", full_path.display()))?;
+    let mut reader = BufReader::new(file);
+    let is_video = extension(path).is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.as_str()));
+    let mut info = if is_video {
+        read_movie_header(&mut reader)?
+    } else {
+        read_exif(&mut reader)?
+    };
+    info.path = path.to_string();
+    Ok(info)
+}
+
+fn read_exif(reader: &mut BufReader<File>) -> Result<MediaInfo> {
+    let exif = match exif::Reader::new().read_from_container(reader) {
+        Ok(exif) => exif,
+        Err(exif::Error::Io(e)) => return Err(e).context("Failed to read file"),
+        Err(_) => return Ok(MediaInfo::default()),
+    };
+    let text = |tag: exif::Tag| -> Option<String> {
+        match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+            exif::Value::Ascii(values) => {
+                let value = String::from_utf8_lossy(values.first()?).trim().to_string();
+                (!value.is_empty()).then_some(value)
+            }
+            _ => None,
+        }
+    };
+    let number = |tags: [exif::Tag; 2]| -> Option<u32> {
+        tags.into_iter()
+            .find_map(|tag| exif.get_field(tag, exif::In::PRIMARY)?.value.get_uint(0))
+            .filter(|n| *n > 0)
+    };
+
+    // "2017:06:01 12:00:00" -> "2017-06-01 12:00:00"
+    let taken = text(exif::Tag::DateTimeOriginal)
+        .or_else(|| text(exif::Tag::DateTime))
+        .filter(|value| value.len() >= 19 && !value.starts_with("0000"))
+        .map(|value| format!("{}-{}-{}", &value[0..4], &value[5..7], &value[8..19]));
+    // Models often repeat the make ("Canon" + "Canon EOS 5D")
+    let camera = match (text(exif::Tag::Make), text(exif::Tag::Model)) {
+        (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => model.or(make),
+    };
+    Ok(MediaInfo {
+        path: String::new(),
+        taken,
+        camera,
+        width: number([exif::Tag::PixelXDimension, exif::Tag::ImageWidth]),
+        height: number([exif::Tag::PixelYDimension, exif::Tag::ImageLength]),
+    })
+}
+
+/// Read the creation time (`mvhd`) and video dimensions (first `tkhd` with a size) of a QuickTime/MP4 file
+fn read_movie_header<R: Read + Seek>(reader: &mut R) -> Result<MediaInfo> {
+    let mut info = MediaInfo::default();
+    let Some(moov) = find_top_level_box(reader, b"moov")? else {
+        return Ok(info);
+    };
+    for (kind, body) in child_boxes(&moov) {
+        match kind {
+            b"mvhd" => info.taken = movie_creation_time(body),
+            b"trak" if info.width.is_none() => {
+                if let Some((width, height)) = child_boxes(body)
+                    .find(|(kind, _)| *kind == b"tkhd")
+                    .and_then(|(_, tkhd)| track_dimensions(tkhd))
+                {
+                    info.width = Some(width);
+                    info.height = Some(height);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+/// Read the body of the first top-level box of a kind, skipping over the others
+fn find_top_level_box<R: Read + Seek>(reader: &mut R, wanted: &[u8; 4]) -> Result<Option<Vec<u8>>> {
+    loop {
+        let mut header = [0u8; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read file"),
+        }
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large).context("Failed to read file")?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        }
+        let body_len = match size {
+            // Extends to the end of the file
+            0 => u64::MAX,
+            size if size < header_len => return Ok(None),
+            size => size - header_len,
+        };
+        if &header[4..8] == wanted {
+            if body_len > MAX_MOOV_BYTES && size != 0 {
+                return Ok(None);
+            }
+            let mut body = Vec::new();
+            reader.take(body_len.min(MAX_MOOV_BYTES)).read_to_end(&mut body).context("Failed to read file")?;
+            return Ok(Some(body));
+        }
+        if size == 0 {
+            return Ok(None);
+        }
+        reader.seek(SeekFrom::Current(body_len as i64)).context("Failed to read file")?;
+    }
+}
+
+/// The (kind, body) of each box within a box body, stopping at the first malformed one
+fn child_boxes(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        if rest.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as usize;
+        if size < 8 || size > rest.len() {
+            return None;
+        }
+        let (kind, body) = (&rest[4..8], &rest[8..size]);
+        rest = &rest[size..];
+        Some((kind, body))
+    })
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u64> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as u64)
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Creation time of an `mvhd` body, as UTC "YYYY-MM-DD HH:MM:SS" (None if unset)
+fn movie_creation_time(mvhd: &[u8]) -> Option<String> {
+    let seconds = match mvhd.first()? {
+        0 => be_u32(mvhd, 4)?,
+        _ => be_u64(mvhd, 4)?,
+    };
+    let unix = seconds.checked_sub(QUICKTIME_EPOCH_OFFSET).filter(|secs| *secs > 0)?;
+    Some(oplog::format_timestamp(unix * 1000))
+}
+
+/// Width and height of a `tkhd` body (16.16 fixed point), None for tracks without a picture
+fn track_dimensions(tkhd: &[u8]) -> Option<(u32, u32)> {
+    // version/flags, times and ids, then reserved, layer, group, volume, reserved and the matrix
+    let at = match tkhd.first()? {
+        0 => 4 + 20 + 52,
+        _ => 4 + 32 + 52,
+    };
+    let width = (be_u32(tkhd, at)? >> 16) as u32;
+    let height = (be_u32(tkhd, at + 4)? >> 16) as u32;
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    /// Box with a kind and body
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn test_is_media() {
+        assert!(is_media("2017/IMG_0001.JPG"));
+        assert!(is_media("clips/a.mov"));
+        assert!(!is_media("notes.txt"));
+        assert!(!is_media("jpg"));
+    }
+
+    #[test]
+    fn test_read_movie_header() {
+        // 2017-06-01 12:00:00 UTC
+        let created = (1_496_318_400 + QUICKTIME_EPOCH_OFFSET) as u32;
+        let mut mvhd = vec![0u8; 100];
+        mvhd[4..8].copy_from_slice(&created.to_be_bytes());
+        let mut tkhd = vec![0u8; 84];
+        tkhd[76..80].copy_from_slice(&(1920u32 << 16).to_be_bytes());
+        tkhd[80..84].copy_from_slice(&(1080u32 << 16).to_be_bytes());
+        let sound_tkhd = vec![0u8; 84];
+
+        let moov = [
+            mp4_box(b"mvhd", &mvhd),
+            mp4_box(b"trak", &mp4_box(b"tkhd", &sound_tkhd)),
+            mp4_box(b"trak", &mp4_box(b"tkhd", &tkhd)),
+        ].concat();
+        let file = [mp4_box(b"ftyp", b"isom"), mp4_box(b"mdat", &[0; 64]), mp4_box(b"moov", &moov)].concat();
+
+        let info = read_movie_header(&mut Cursor::new(file)).unwrap();
+        assert_eq!(info.taken.as_deref(), Some("2017-06-01 12:00:00"));
+        assert_eq!((info.width, info.height), (Some(1920), Some(1080)));
+        assert_eq!(info.describe(), "2017-06-01 12:00:00, 1920x1080");
+
+        assert_eq!(read_movie_header(&mut Cursor::new(b"not a movie".to_vec())).unwrap(), MediaInfo::default());
+    }
+
+    #[test]
+    fn test_read_media_exif() {
+        let fields = [
+            exif::Field { tag: exif::Tag::Make, ifd_num: exif::In::PRIMARY, value: exif::Value::Ascii(vec![b"Canon".to_vec()]) },
+            exif::Field { tag: exif::Tag::Model, ifd_num: exif::In::PRIMARY, value: exif::Value::Ascii(vec![b"Canon EOS 5D".to_vec()]) },
+            exif::Field {
+                tag: exif::Tag::DateTimeOriginal,
+                ifd_num: exif::In::PRIMARY,
+                value: exif::Value::Ascii(vec![b"2017:06:01 12:00:00".to_vec()]),
+            },
+            exif::Field { tag: exif::Tag::PixelXDimension, ifd_num: exif::In::PRIMARY, value: exif::Value::Long(vec![4000]) },
+            exif::Field { tag: exif::Tag::PixelYDimension, ifd_num: exif::In::PRIMARY, value: exif::Value::Long(vec![3000]) },
+        ];
+        let mut writer = exif::experimental::Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        // SOI, an APP1 segment holding the Exif block, EOI
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.jpg");
+        let mut jpeg = File::create(&path).unwrap();
+        jpeg.write_all(&[0xff, 0xd8, 0xff, 0xe1]).unwrap();
+        jpeg.write_all(&((tiff.len() + 8) as u16).to_be_bytes()).unwrap();
+        jpeg.write_all(b"Exif\0\0").unwrap();
+        jpeg.write_all(&tiff).unwrap();
+        jpeg.write_all(&[0xff, 0xd9]).unwrap();
+        drop(jpeg);
+
+        let info = read_media(&path, "photos/a.jpg").unwrap();
+        assert_eq!(info, MediaInfo {
+            path: "photos/a.jpg".to_string(),
+            taken: Some("2017-06-01 12:00:00".to_string()),
+            camera: Some("Canon EOS 5D".to_string()),
+            width: Some(4000),
+            height: Some(3000),
+        });
+
+        // A photo without Exif data is recorded without fields
+        std::fs::write(dir.path().join("b.jpg"), [0xff, 0xd8, 0xff, 0xd9]).unwrap();
+        assert_eq!(read_media(&dir.path().join("b.jpg"), "b.jpg").unwrap().describe(), "");
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+use crate::index::{dir_prefix, FileEntry};
+
+/// Which entry `merge` keeps where both indexes hold different content at the same path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prefer {
+    /// This repository's entry, so merging never replaces what is indexed here
+    Ours,
+    /// The other index's entry
+    Theirs,
+    /// The entry with the later modified time (ours on a tie)
+    Newer,
+}
+
+impl std::str::FromStr for Prefer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ours" => Ok(Prefer::Ours),
+            "theirs" => Ok(Prefer::Theirs),
+            "newer" => Ok(Prefer::Newer),
+            _ => bail!("Invalid --prefer value: '{}' (expected ours, theirs or newer)", s),
+        }
+    }
+}
+
+/// What merging does with one entry of the other index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// Nothing is indexed at its path here
+    Added,
+    /// The same content is indexed here, and ours is kept
+    Identical,
+    /// The same content is indexed here, but only theirs has a full hash, so it replaces ours
+    Hashed,
+    /// Different content is indexed here, and ours wins
+    KeptOurs,
+    /// Different content is indexed here, and theirs wins
+    TookTheirs,
+}
+
+impl Outcome {
+    /// Whether the entry is written to this index
+    pub fn writes(self) -> bool {
+        matches!(self, Outcome::Added | Outcome::Hashed | Outcome::TookTheirs)
+    }
+}
+
+/// Index path of a path of the other index once merged under `prefix` ("" for none)
+pub fn prefixed(prefix: &str, path: &str) -> String {
+    if path.is_empty() {
+        prefix.to_string()
+    } else {
+        format!("{}{}", dir_prefix(prefix), path)
+    }
+}
+
+/// Decide what happens to each entry of the other index, given ours by path. Their paths are
+/// moved under `prefix`; the result is in their path order.
+pub fn plan(ours: &HashMap<String, FileEntry>, theirs: Vec<FileEntry>, prefix: &str, prefer: Prefer) -> Vec<(FileEntry, Outcome)> {
+    let mut planned: Vec<(FileEntry, Outcome)> = theirs
+        .into_iter()
+        .map(|mut entry| {
+            entry.path = prefixed(prefix, &entry.path);
+            let outcome = match ours.get(&entry.path) {
+                None => Outcome::Added,
+                Some(our) if !our.content_differs(&entry) => {
+                    if our.sha256.is_empty() && !entry.sha256.is_empty() {
+                        Outcome::Hashed
+                    } else {
+                        Outcome::Identical
+                    }
+                }
+                Some(our) => {
+                    let theirs_wins = match prefer {
+                        Prefer::Ours => false,
+                        Prefer::Theirs => true,
+                        Prefer::Newer => entry.modified > our.modified,
+                    };
+                    if theirs_wins { Outcome::TookTheirs } else { Outcome::KeptOurs }
+                }
+            };
+            (entry, outcome)
+        })
+        .collect();
+    planned.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+    planned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, sha256: &str, modified: u64) -> FileEntry {
+        FileEntry { path: path.to_string(), sha256: sha256.to_string(), num_bytes: 1, modified, ..Default::default() }
+    }
+
+    #[test]
+    fn test_plan_applies_prefix_and_conflict_rules() {
+        let ours: HashMap<String, FileEntry> = [
+            entry("b/same.txt", "aaa", 10),
+            entry("b/pending.txt", "", 10),
+            entry("b/older.txt", "ccc", 10),
+            entry("b/newer.txt", "ddd", 30),
+        ]
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+        let theirs = vec![
+            entry("new.txt", "eee", 10),
+            entry("same.txt", "aaa", 20),
+            entry("pending.txt", "bbb", 10),
+            entry("older.txt", "fff", 20),
+            entry("newer.txt", "ggg", 20),
+        ];
+        let outcomes = |prefer| -> Vec<(String, Outcome)> {
+            plan(&ours, theirs.clone(), "b", prefer).into_iter().map(|(entry, outcome)| (entry.path, outcome)).collect()
+        };
+
+        assert_eq!(outcomes(Prefer::Newer), vec![
+            ("b/new.txt".to_string(), Outcome::Added),
+            ("b/newer.txt".to_string(), Outcome::KeptOurs),
+            ("b/older.txt".to_string(), Outcome::TookTheirs),
+            ("b/pending.txt".to_string(), Outcome::Hashed),
+            ("b/same.txt".to_string(), Outcome::Identical),
+        ]);
+        let conflicts = |prefer| outcomes(prefer).into_iter().filter(|(path, _)| path.ends_with("er.txt")).map(|(_, o)| o).collect::<Vec<_>>();
+        assert_eq!(conflicts(Prefer::Ours), vec![Outcome::KeptOurs, Outcome::KeptOurs]);
+        assert_eq!(conflicts(Prefer::Theirs), vec![Outcome::TookTheirs, Outcome::TookTheirs]);
+        assert_eq!(plan(&ours, vec![entry("x.txt", "eee", 1)], "", Prefer::Ours)[0].0.path, "x.txt");
+        assert!("mine".parse::<Prefer>().is_err());
+    }
+}
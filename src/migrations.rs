@@ -0,0 +1,312 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+use std::path::{Path, PathBuf};
+
+use crate::error::OciError;
+use crate::index::parent_dir;
+
+/// One step of the index database schema, upgrading it from `version - 1` to `version`
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&Connection) -> Result<()>,
+}
+
+/// Every schema step, oldest first; append new steps here rather than editing old ones
+const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, description: "initial schema", apply: baseline },
+    Migration { version: 2, description: "file tags", apply: add_tags },
+    Migration { version: 3, description: "allocated sizes", apply: add_allocated },
+    Migration { version: 4, description: "size index", apply: add_size_index },
+    Migration { version: 5, description: "extra digests", apply: add_digests },
+];
+
+/// Schema version this build of oci reads and writes
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Schema version recorded in a database (`PRAGMA user_version`; 0 for unversioned databases)
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .context("Failed to read index schema version")
+}
+
+/// Bring a database up to the latest schema, one step per transaction
+/// A database that already holds tables is copied next to `db_path` first, so a failed or
+/// unwanted upgrade can be undone; databases from a newer oci are refused.
+pub fn migrate(conn: &Connection, db_path: Option<&Path>) -> Result<()> {
+    let version = schema_version(conn)?;
+    let latest = latest_version();
+    if version > latest {
+        return Err(OciError::IndexTooNew { version, supported: latest }.into());
+    }
+    if version == latest {
+        return Ok(());
+    }
+
+    let backup = match db_path {
+        Some(path) if has_tables(conn)? => Some(backup(conn, path, version)?),
+        _ => None,
+    };
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        // Take the write lock before re-reading the version, in case another process upgraded meanwhile
+        let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
+            .context("Failed to start transaction")?;
+        if schema_version(&tx)? >= migration.version {
+            continue;
+        }
+        (migration.apply)(&tx)
+            .context(format!("Failed to upgrade index schema to version {} ({})", migration.version, migration.description))?;
+        tx.pragma_update(None, "user_version", migration.version)
+            .context("Failed to record index schema version")?;
+        tx.commit().context("Failed to commit index schema upgrade")?;
+    }
+    if let Some(backup) = backup {
+        eprintln!(
+            "Note: Upgraded the index schema from version {} to {} (previous index kept as {})",
+            version, latest, backup.display()
+        );
+    }
+    Ok(())
+}
+
+fn has_tables(conn: &Connection) -> Result<bool> {
+    conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')", [], |row| row.get(0))
+        .context("Failed to read index schema")
+}
+
+/// Copy the database to `<db_path>.v<version>.bak`, replacing an earlier backup of that version
+fn backup(conn: &Connection, db_path: &Path, version: u32) -> Result<PathBuf> {
+    let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    let path = db_path.with_file_name(name);
+    if path.exists() {
+        std::fs::remove_file(&path)
+            .context(format!("Failed to replace index backup: {}", path.display()))?;
+    }
+    conn.backup(rusqlite::DatabaseName::Main, &path, None)
+        .context(format!("Failed to back up index to {}", path.display()))?;
+    Ok(path)
+}
+
+/// Version 1: the schema as it stood when versioning was introduced
+/// Written to be idempotent, since unversioned databases hold any earlier subset of it.
+fn baseline(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS files (
+            path TEXT PRIMARY KEY,
+            num_bytes INTEGER NOT NULL,
+            modified INTEGER NOT NULL,
+            sha256 TEXT NOT NULL
+        )",
+        [],
+    ).context("Failed to create files table")?;
+    
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_sha256 ON files(sha256)",
+        [],
+    ).context("Failed to create sha256 index")?;
+    
+    // Columns added after the initial schema
+    ensure_column(conn, "files", "xattrs", "TEXT")?;
+    ensure_column(conn, "files", "quick_hash", "TEXT")?;
+    ensure_column(conn, "files", "mime", "TEXT")?;
+    if ensure_column(conn, "files", "dir", "TEXT NOT NULL DEFAULT ''")? {
+        backfill_dirs(conn)?;
+    }
+    
+    // Directory listings (`ls`, `status`) look files up by parent directory
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_dir ON files(dir)",
+        [],
+    ).context("Failed to create dir index")?;
+    
+    // Manual storage class tags, keyed by file or directory path
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS storage_classes (
+            path TEXT PRIMARY KEY,
+            class TEXT NOT NULL
+        )",
+        [],
+    ).context("Failed to create storage_classes table")?;
+    
+    // Files inside indexed archives (`index_archives`), keyed by archive and member path
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS archive_members (
+            archive TEXT NOT NULL,
+            member TEXT NOT NULL,
+            num_bytes INTEGER NOT NULL,
+            sha256 TEXT NOT NULL,
+            PRIMARY KEY (archive, member)
+        )",
+        [],
+    ).context("Failed to create archive_members table")?;
+    
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_member_sha256 ON archive_members(sha256)",
+        [],
+    ).context("Failed to create archive member index")?;
+    
+    // Metadata extracted from photos and videos (`extract_media`), one row per file
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS media (
+            path TEXT PRIMARY KEY,
+            taken TEXT,
+            camera TEXT,
+            width INTEGER,
+            height INTEGER
+        )",
+        [],
+    ).context("Failed to create media table")?;
+    
+    // Fingerprints of audio files (`fingerprint_audio`), one row per file
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS audio (
+            path TEXT PRIMARY KEY,
+            duration_ms INTEGER,
+            fingerprint TEXT NOT NULL
+        )",
+        [],
+    ).context("Failed to create audio table")?;
+    
+    // Content removed from the index, for tracing where it went (`audit --departed`)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tombstones (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL,
+            num_bytes INTEGER NOT NULL,
+            sha256 TEXT NOT NULL,
+            removed INTEGER NOT NULL,
+            reason TEXT NOT NULL
+        )",
+        [],
+    ).context("Failed to create tombstones table")?;
+    
+    // Append-only log of index mutations and who performed them
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS oplog (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL,
+            os_user TEXT NOT NULL,
+            identity TEXT,
+            command TEXT NOT NULL,
+            summary TEXT NOT NULL
+        )",
+        [],
+    ).context("Failed to create oplog table")?;
+    
+    Ok(())
+}
+
+/// User-defined tags (`oci tag`), keyed by file or directory path like storage classes
+fn add_tags(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE tags (
+            path TEXT NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (path, tag)
+        )",
+        [],
+    ).context("Failed to create tags table")?;
+    conn.execute("CREATE INDEX idx_tag ON tags(tag)", [])
+        .context("Failed to create tag index")?;
+    Ok(())
+}
+
+/// Bytes allocated on disk per file; NULL until the next update records it
+fn add_allocated(conn: &Connection) -> Result<()> {
+    conn.execute("ALTER TABLE files ADD COLUMN allocated INTEGER", [])
+        .context("Failed to add allocated column")?;
+    Ok(())
+}
+
+/// Index on file sizes, so `stats --top` reads the largest files without scanning the table
+fn add_size_index(conn: &Connection) -> Result<()> {
+    conn.execute("CREATE INDEX idx_num_bytes ON files(num_bytes)", [])
+        .context("Failed to create size index")?;
+    Ok(())
+}
+
+/// Digests in algorithms other than SHA256 (the `digests` config key); NULL when not recorded
+fn add_digests(conn: &Connection) -> Result<()> {
+    conn.execute("ALTER TABLE files ADD COLUMN digests TEXT", [])
+        .context("Failed to add digests column")?;
+    Ok(())
+}
+
+/// Add a column to an existing table if it is missing (for databases created by older versions),
+/// returning whether it was added
+fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))
+        .context("Failed to read table info")?;
+    let exists = stmt.query_map([], |row| row.get::<_, String>(1))
+        .context("Failed to query table info")?
+        .filter_map(|name| name.ok())
+        .any(|name| name == column);
+    
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        ).context(format!("Failed to add {} column", column))?;
+    }
+    
+    Ok(!exists)
+}
+
+/// Fill in the dir column for files indexed before it existed (inside the migration's transaction)
+fn backfill_dirs(conn: &Connection) -> Result<()> {
+    let mut select = conn.prepare("SELECT path FROM files").context("Failed to prepare statement")?;
+    let paths = select.query_map([], |row| row.get::<_, String>(0))
+        .context("Failed to query files")?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("Failed to read entry")?;
+    let mut update = conn.prepare("UPDATE files SET dir = ?1 WHERE path = ?2")
+        .context("Failed to prepare statement")?;
+    for path in &paths {
+        update.execute(params![parent_dir(path), path]).context("Failed to set file directory")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_migrate_versions_and_backs_up_old_databases() {
+        let dir = TempDir::new().unwrap();
+        let db_path = dir.path().join("index.db");
+
+        // A new database is created at the latest version without a backup
+        let conn = Connection::open(&db_path).unwrap();
+        migrate(&conn, Some(&db_path)).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        assert!(!dir.path().join("index.db.v0.bak").exists());
+        drop(conn);
+        std::fs::remove_file(&db_path).unwrap();
+
+        // An unversioned database from before versioning is upgraded in place and backed up
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE files (path TEXT PRIMARY KEY, num_bytes INTEGER NOT NULL, modified INTEGER NOT NULL, sha256 TEXT NOT NULL);
+             INSERT INTO files VALUES ('photos/a.jpg', 1, 1, 'aa');",
+        ).unwrap();
+        migrate(&conn, Some(&db_path)).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        let dir_of_a: String = conn.query_row("SELECT dir FROM files WHERE path = 'photos/a.jpg'", [], |row| row.get(0)).unwrap();
+        assert_eq!(dir_of_a, "photos");
+
+        let old = Connection::open(dir.path().join("index.db.v0.bak")).unwrap();
+        assert_eq!(schema_version(&old).unwrap(), 0);
+        let count: i64 = old.query_row("SELECT COUNT(*) FROM files", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+
+        // A database from a newer oci is refused
+        conn.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+        let err = migrate(&conn, Some(&db_path)).unwrap_err();
+        assert!(err.to_string().contains("upgrade oci"), "got: {}", err);
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::file_utils;
+
+/// A recorded index mutation (update, prune, reset, ...) and who performed it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Operation {
+    /// Time of the operation in milliseconds since epoch
+    pub timestamp: u64,
+    /// Login name of the OS user that ran the command
+    pub os_user: String,
+    /// Configured identity (`identity` config key), if any
+    pub identity: Option<String>,
+    /// Command that mutated the index (e.g. "update", "prune --purge")
+    pub command: String,
+    /// Short description of what changed
+    pub summary: String,
+}
+
+impl Operation {
+    /// Record an operation performed now by the current user
+    pub fn new(config: &Config, command: &str, summary: impl Into<String>) -> Self {
+        Self {
+            timestamp: file_utils::now_millis(),
+            os_user: current_os_user(),
+            identity: config.identity.clone(),
+            command: command.to_string(),
+            summary: summary.into(),
+        }
+    }
+
+    /// Who performed the operation, e.g. "alice" or "alice (Alice Smith)"
+    pub fn actor(&self) -> String {
+        match &self.identity {
+            Some(identity) if identity != &self.os_user => format!("{} ({})", self.os_user, identity),
+            _ => self.os_user.clone(),
+        }
+    }
+}
+
+/// Login name of the user running oci
+pub fn current_os_user() -> String {
+    ["USER", "USERNAME", "LOGNAME"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Format a timestamp in milliseconds since epoch as "YYYY-MM-DD HH:MM:SS" (UTC)
+pub fn format_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Civil date from days since 1970-01-01 (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, time / 3600, (time % 3600) / 60, time % 60
+    )
+}
+
+/// Format a timestamp in milliseconds since epoch as "YYYY-MM-DD HH:MM:SS" in the local time zone
+#[cfg(unix)]
+pub fn format_local_timestamp(millis: u64) -> String {
+    let secs = (millis / 1000) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return format_timestamp(millis);
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec
+    )
+}
+
+/// Format a timestamp in milliseconds since epoch as "YYYY-MM-DD HH:MM:SS" (UTC where the local zone is unknown)
+#[cfg(not(unix))]
+pub fn format_local_timestamp(millis: u64) -> String {
+    format_timestamp(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951_782_400_000), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(1_735_689_599_999), "2024-12-31 23:59:59");
+    }
+
+    #[test]
+    fn test_actor_includes_distinct_identity() {
+        let mut op = Operation {
+            timestamp: 0,
+            os_user: "alice".to_string(),
+            identity: None,
+            command: "update".to_string(),
+            summary: String::new(),
+        };
+        assert_eq!(op.actor(), "alice");
+        op.identity = Some("Alice Smith".to_string());
+        assert_eq!(op.actor(), "alice (Alice Smith)");
+        op.identity = Some("alice".to_string());
+        assert_eq!(op.actor(), "alice");
+    }
+}
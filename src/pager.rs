@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Cleared by `--no-pager`
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Set while stdout goes to a pager
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Environment variable naming the pager, ahead of `PAGER`
+pub const PAGER_VAR: &str = "OCI_PAGER";
+
+/// Never page output for the rest of the process
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether stdout currently goes to a pager (progress is not drawn over it)
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// The pager command: `$OCI_PAGER`, then `$PAGER`, then `less`; None when one of them is set to
+/// nothing or `cat`
+fn command() -> Option<String> {
+    let command = std::env::var(PAGER_VAR)
+        .or_else(|_| std::env::var("PAGER"))
+        .unwrap_or_else(|_| "less".to_string());
+    let command = command.trim();
+    (!command.is_empty() && command != "cat").then(|| command.to_string())
+}
+
+#[cfg(unix)]
+pub use unix::start;
+
+#[cfg(unix)]
+mod unix {
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::atomic::Ordering;
+
+    use super::{command, ACTIVE, ENABLED};
+    use crate::display;
+
+    /// Stdout sent through a pager; dropping it ends the output and waits for the pager to quit
+    pub struct Pager {
+        child: Child,
+        /// Duplicate of the terminal stdout wrote to before
+        saved: libc::c_int,
+    }
+
+    /// Send the rest of the command's output through a pager (like git), when stdout is a
+    /// terminal. A pager that can't be started just means unpaged output.
+    pub fn start() -> Option<Pager> {
+        if !ENABLED.load(Ordering::Relaxed) || !display::stdout_is_terminal() {
+            return None;
+        }
+        let command = command()?;
+        // The default is started directly, so a missing `less` falls back to printing
+        let mut process = if command == "less" {
+            Command::new("less")
+        } else {
+            let mut process = Command::new("sh");
+            process.arg("-c").arg(&command);
+            process
+        };
+        // Quit if everything fits on one screen, pass colors through and leave the output
+        // on screen, unless the user set their own options
+        if std::env::var_os("LESS").is_none() {
+            process.env("LESS", "FRX");
+        }
+        let mut child = process.stdin(Stdio::piped()).spawn().ok()?;
+        let stdin = child.stdin.take()?;
+
+        let _ = std::io::stdout().flush();
+        let saved = unsafe { libc::dup(1) };
+        if saved < 0 || unsafe { libc::dup2(stdin.as_raw_fd(), 1) } < 0 {
+            drop(stdin);
+            let _ = child.wait();
+            return None;
+        }
+        drop(stdin);
+        // Quitting the pager early ends the command quietly, as it would for `| head`
+        unsafe {
+            libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+        }
+        ACTIVE.store(true, Ordering::Relaxed);
+        Some(Pager { child, saved })
+    }
+
+    impl Drop for Pager {
+        fn drop(&mut self) {
+            let _ = std::io::stdout().flush();
+            // Pointing stdout back at the terminal closes the pipe, so the pager sees the end
+            unsafe {
+                libc::dup2(self.saved, 1);
+                libc::close(self.saved);
+            }
+            ACTIVE.store(false, Ordering::Relaxed);
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Output is never paged on this platform
+#[cfg(not(unix))]
+pub fn start() -> Option<()> {
+    let _ = (command, &ENABLED);
+    None
+}
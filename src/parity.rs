@@ -0,0 +1,142 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::checksums;
+use crate::config::Config;
+use crate::index::OCI_DIR;
+use crate::manifest;
+
+/// Directory under `.oci` holding the recovery sets, one per indexed directory at its index path
+pub const PARITY_DIR: &str = "parity";
+
+/// Prefix of every file of a recovery set: the `.par2` index file, its `.volNN+NN.par2` volumes
+/// and the digests the set was made from
+const SET_PREFIX: &str = "files.";
+const SET_FILE: &str = "files.par2";
+const DIGESTS_FILE: &str = "files.sha256";
+
+/// Default share of recovery data, as a percentage of the protected files' size
+pub const DEFAULT_REDUNDANCY: u8 = 10;
+
+/// Directory holding the recovery set of an indexed directory (`""` for the repository root)
+pub fn set_dir(repo_root: &Path, dir: &str) -> PathBuf {
+    let parity = repo_root.join(OCI_DIR).join(PARITY_DIR);
+    if dir.is_empty() {
+        parity
+    } else {
+        parity.join(dir)
+    }
+}
+
+/// Whether a directory has a complete recovery set (its digests are written last)
+pub fn exists(set_dir: &Path) -> bool {
+    set_dir.join(SET_FILE).is_file() && set_dir.join(DIGESTS_FILE).is_file()
+}
+
+/// The SHA256 of each file a recovery set protects, by name, as recorded when it was made
+pub fn recorded_digests(set_dir: &Path) -> Result<HashMap<String, String>> {
+    Ok(manifest::load(&set_dir.join(DIGESTS_FILE))?.entries.into_iter().collect())
+}
+
+/// Remove a directory's recovery set (the sets of its subdirectories are left alone)
+pub fn remove(set_dir: &Path) -> Result<()> {
+    let Ok(files) = fs::read_dir(set_dir) else {
+        return Ok(());
+    };
+    for file in files.flatten() {
+        if file.file_name().to_string_lossy().starts_with(SET_PREFIX) && file.path().is_file() {
+            fs::remove_file(file.path()).context(format!("Failed to remove {}", file.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// The par2 program (par2cmdline or a compatible one), from the `par2` config key
+pub struct Par2 {
+    program: String,
+}
+
+impl Par2 {
+    pub fn new(config: &Config) -> Self {
+        Par2 { program: config.par2.clone().unwrap_or_else(|| "par2".to_string()) }
+    }
+
+    /// Make a recovery set for `files` (names and SHA256s of files in `base`), replacing any previous one
+    pub fn create(&self, set_dir: &Path, base: &Path, files: &[(String, String)], redundancy: u8) -> Result<()> {
+        remove(set_dir)?;
+        fs::create_dir_all(set_dir).context(format!("Failed to create {}", set_dir.display()))?;
+        let mut command = Command::new(&self.program);
+        command
+            .arg("create")
+            .arg("-q")
+            .arg(format!("-r{}", redundancy))
+            .arg(format!("-B{}", base.display()))
+            .arg(set_dir.join(SET_FILE))
+            .args(files.iter().map(|(name, _)| base.join(name)));
+        let status = self.run(command)?;
+        if status != Some(0) {
+            let _ = remove(set_dir);
+            bail!("{} create failed for {}", self.program, base.display());
+        }
+        // Written last, so a set is only used once it is complete
+        let digests: String = files.iter().map(|(name, sha256)| checksums::coreutils_line(sha256, name)).collect();
+        let digests_file = set_dir.join(DIGESTS_FILE);
+        fs::write(&digests_file, digests).context(format!("Failed to write {}", digests_file.display()))
+    }
+
+    /// Repair the damaged or missing files of `base` from its recovery set.
+    /// Returns false when par2 reports there is too little recovery data left.
+    pub fn repair(&self, set_dir: &Path, base: &Path) -> Result<bool> {
+        let mut command = Command::new(&self.program);
+        command.arg("repair").arg("-q").arg(format!("-B{}", base.display())).arg(set_dir.join(SET_FILE));
+        match self.run(command)? {
+            Some(0) => Ok(true),
+            // par2cmdline's exit code when the damage exceeds the recovery data
+            Some(2) => Ok(false),
+            _ => bail!("{} repair failed for {}", self.program, base.display()),
+        }
+    }
+
+    fn run(&self, mut command: Command) -> Result<Option<i32>> {
+        let output = command
+            .output()
+            .context(format!("Failed to run '{}' (is par2cmdline installed? see the par2 config key)", self.program))?;
+        if !output.status.success() {
+            tracing::debug!("{}: {}", self.program, String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(output.status.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_set_files() {
+        let repo = TempDir::new().unwrap();
+        assert_eq!(set_dir(repo.path(), ""), repo.path().join(".oci/parity"));
+        let set = set_dir(repo.path(), "photos/2020");
+        assert_eq!(set, repo.path().join(".oci/parity/photos/2020"));
+
+        fs::create_dir_all(set.join("raw")).unwrap();
+        fs::write(set.join(SET_FILE), "").unwrap();
+        fs::write(set.join("files.vol00+01.par2"), "").unwrap();
+        assert!(!exists(&set));
+        let (a, b) = ("a".repeat(64), "b".repeat(64));
+        fs::write(set.join(DIGESTS_FILE), format!("{}  a b.jpg\n\\{}  back\\\\slash.jpg\n", a, b)).unwrap();
+        assert!(exists(&set));
+        let recorded = recorded_digests(&set).unwrap();
+        assert_eq!(recorded.get("a b.jpg"), Some(&a));
+        assert_eq!(recorded.get("back\\slash.jpg"), Some(&b));
+
+        // A subdirectory's set is not part of its parent's
+        remove(&set).unwrap();
+        assert!(!exists(&set));
+        assert_eq!(fs::read_dir(&set).unwrap().count(), 1);
+    }
+}
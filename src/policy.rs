@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::config;
+use crate::display::format_bytes;
+use crate::ignore;
+
+const POLICY_FILE: &str = "policy.toml";
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// A size given either as a number of bytes or as a string like "100G"
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SizeValue {
+    Bytes(u64),
+    Text(String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    index: IndexRules,
+    #[serde(default)]
+    prune: PruneRules,
+    #[serde(default)]
+    purge: PurgeRules,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IndexRules {
+    max_file_size: Option<SizeValue>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PruneRules {
+    #[serde(default)]
+    protected: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PurgeRules {
+    require_verify_within_days: Option<u64>,
+}
+
+/// Rules from `.oci/policy.toml`, checked before indexing and destructive actions
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Policy {
+    /// Files larger than this are never indexed
+    pub max_file_size: Option<u64>,
+    /// Paths (or patterns) that must never be pruned
+    pub protected: Vec<String>,
+    /// A successful `verify` must have run within this many days before a purge
+    pub require_verify_within_days: Option<u64>,
+}
+
+/// A rule an action would break
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.rule, self.message)
+    }
+}
+
+impl Policy {
+    /// Load the policy from the .oci directory (an empty policy if the file does not exist)
+    pub fn load(repo_root: &Path) -> Result<Self> {
+        let policy_path = repo_root.join(crate::index::OCI_DIR).join(POLICY_FILE);
+        if !policy_path.exists() {
+            return Ok(Policy::default());
+        }
+
+        let contents = fs::read_to_string(&policy_path)
+            .context("Failed to read policy file")?;
+        Self::parse(&contents)
+    }
+
+    /// Parse policy rules from TOML
+    pub fn parse(contents: &str) -> Result<Self> {
+        let file: PolicyFile = toml::from_str(contents)
+            .context("Failed to parse .oci/policy.toml")?;
+
+        let max_file_size = match file.index.max_file_size {
+            Some(SizeValue::Bytes(bytes)) => Some(bytes),
+            Some(SizeValue::Text(text)) => Some(config::parse_size("index.max_file_size", &text)?),
+            None => None,
+        };
+
+        Ok(Policy {
+            max_file_size,
+            protected: file.prune.protected
+                .iter()
+                .map(|p| p.trim().trim_start_matches('/').to_string())
+                .filter(|p| !p.is_empty())
+                .collect(),
+            require_verify_within_days: file.purge.require_verify_within_days,
+        })
+    }
+
+    /// Check whether a file may be indexed
+    pub fn check_index(&self, rel_path: &str, num_bytes: u64) -> Option<Violation> {
+        let limit = self.max_file_size?;
+        (num_bytes > limit).then(|| Violation {
+            rule: "index.max_file_size",
+            message: format!(
+                "{} is {}, larger than the {} limit",
+                rel_path, format_bytes(num_bytes), format_bytes(limit)
+            ),
+        })
+    }
+
+    /// Check whether a file may be pruned
+    pub fn check_prune(&self, rel_path: &str) -> Option<Violation> {
+        let path = Path::new(rel_path);
+        self.protected
+            .iter()
+            .find(|protected| {
+                // A plain path protects itself and everything below it
+                let as_dir = format!("{}/", protected.trim_end_matches('/'));
+                ignore::matches_any(path, &[protected.to_string(), as_dir])
+            })
+            .map(|protected| Violation {
+                rule: "prune.protected",
+                message: format!("{} is protected by '{}'", rel_path, protected),
+            })
+    }
+
+    /// Check whether pruned files may be purged, given the time of the last successful verify
+    pub fn check_purge(&self, last_verify: Option<u64>, now_millis: u64) -> Option<Violation> {
+        let days = self.require_verify_within_days?;
+        let message = match last_verify {
+            None => format!("no successful 'oci verify' has been recorded (required within {} day(s))", days),
+            Some(timestamp) if now_millis.saturating_sub(timestamp) > days * MILLIS_PER_DAY => format!(
+                "last successful 'oci verify' was {} day(s) ago (required within {} day(s))",
+                now_millis.saturating_sub(timestamp) / MILLIS_PER_DAY,
+                days
+            ),
+            Some(_) => return None,
+        };
+        Some(Violation { rule: "purge.require_verify_within_days", message })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        let policy = Policy::parse(
+            "[index]\nmax_file_size = \"100G\"\n\n[prune]\nprotected = [\"/originals\", \"*.raw\"]\n\n[purge]\nrequire_verify_within_days = 30\n",
+        ).unwrap();
+
+        assert_eq!(policy.max_file_size, Some(100 * 1024 * 1024 * 1024));
+        assert_eq!(policy.protected, vec!["originals".to_string(), "*.raw".to_string()]);
+        assert_eq!(policy.require_verify_within_days, Some(30));
+
+        assert!(Policy::parse("[index]\nmax_size = 1\n").is_err());
+        assert_eq!(Policy::parse("").unwrap(), Policy::default());
+    }
+
+    #[test]
+    fn test_policy_checks() {
+        let policy = Policy {
+            max_file_size: Some(100),
+            protected: vec!["originals".to_string(), "*.raw".to_string()],
+            require_verify_within_days: Some(30),
+        };
+
+        assert!(policy.check_index("small.txt", 100).is_none());
+        assert!(policy.check_index("big.iso", 101).is_some());
+
+        assert!(policy.check_prune("originals/2020/a.jpg").is_some());
+        assert!(policy.check_prune("originals").is_some());
+        assert!(policy.check_prune("photos/a.raw").is_some());
+        assert!(policy.check_prune("originals-copy/a.jpg").is_none());
+
+        let now = 100 * MILLIS_PER_DAY;
+        assert!(policy.check_purge(None, now).is_some());
+        assert!(policy.check_purge(Some(50 * MILLIS_PER_DAY), now).is_some());
+        assert!(policy.check_purge(Some(80 * MILLIS_PER_DAY), now).is_none());
+        assert!(Policy::default().check_purge(None, now).is_none());
+    }
+}
@@ -0,0 +1,225 @@
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::display::{self, format_bytes};
+
+/// How often the live progress line is redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Whether a progress line is on screen, so output printed meanwhile can clear it first
+static DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Erase the live progress line, if one is drawn, before printing something else
+pub fn clear_line() {
+    if DRAWN.swap(false, Ordering::Relaxed) {
+        eprint!("\r\x1b[K");
+    }
+}
+
+/// Files processed and bytes hashed by `update` or `verify`, with a live estimate on stderr
+/// (only when stderr is a terminal and output is not quiet) and a throughput summary at the end
+pub struct Progress {
+    start: Instant,
+    last_draw: Option<Instant>,
+    live: bool,
+    files: usize,
+    bytes: u64,
+    /// Expected number of files, if known (for update, the files indexed so far)
+    total_files: Option<usize>,
+    /// Expected bytes to hash, if known; the estimate then follows bytes rather than files
+    total_bytes: Option<u64>,
+}
+
+impl Progress {
+    pub fn new(show: bool) -> Self {
+        Progress {
+            start: Instant::now(),
+            last_draw: None,
+            live: show && std::io::stderr().is_terminal() && !display::quiet() && !crate::pager::active(),
+            files: 0,
+            bytes: 0,
+            total_files: None,
+            total_bytes: None,
+        }
+    }
+
+    /// Whether progress is drawn, e.g. to skip counting the expected work when it isn't
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
+    pub fn expect(&mut self, files: usize, bytes: Option<u64>) {
+        self.total_files = Some(files);
+        self.total_bytes = bytes;
+    }
+
+    /// Count bytes read to hash a file (as they are read, so a large file moves the estimate too)
+    pub fn hashed(&mut self, bytes: u64) {
+        self.bytes += bytes;
+        self.draw();
+    }
+
+    /// Count a processed file
+    pub fn file_done(&mut self) {
+        self.files += 1;
+        self.draw();
+    }
+
+    /// Redraw the live line, at most every REDRAW_INTERVAL
+    fn draw(&mut self) {
+        if self.live && self.last_draw.is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL) {
+            self.last_draw = Some(Instant::now());
+            eprint!("\r\x1b[K{}", self.line());
+            let _ = std::io::stderr().flush();
+            DRAWN.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// e.g. `12,431/98,000 files, 41.20 GB hashed, 312.00 MB/s, ~14 min remaining`
+    fn line(&self) -> String {
+        let elapsed = self.start.elapsed();
+        let mut line = match self.total_files {
+            Some(total) => format!("{}/{} files", group_digits(self.files), group_digits(total.max(self.files))),
+            None => format!("{} files", group_digits(self.files)),
+        };
+        line.push_str(&format!(", {} hashed, {}", format_bytes(self.bytes), format_rate(self.bytes, elapsed)));
+
+        // Without a known total there is nothing to estimate against
+        let done = match (self.total_bytes, self.total_files) {
+            (Some(total), _) if total > 0 => Some(self.bytes as f64 / total as f64),
+            (None, Some(total)) if total > 0 => Some(self.files as f64 / total as f64),
+            _ => None,
+        };
+        if let Some(done) = done.filter(|done| *done > 0.0 && elapsed >= Duration::from_secs(1)) {
+            let remaining = elapsed.as_secs_f64() * (1.0 - done.min(1.0)) / done;
+            line.push_str(&format!(", ~{} remaining", format_duration(Duration::from_secs_f64(remaining.ceil()))));
+        }
+        line
+    }
+
+    /// Erase the live line and describe the throughput, if anything was hashed
+    pub fn finish(&self) -> Option<String> {
+        clear_line();
+        (self.bytes > 0).then(|| throughput(self.bytes, self.start.elapsed()))
+    }
+}
+
+/// e.g. `Hashed 41.20 GB in 2 min 12 s (312.00 MB/s)`
+pub fn throughput(bytes: u64, elapsed: Duration) -> String {
+    format!("Hashed {} in {} ({})", format_bytes(bytes), format_duration(elapsed), format_rate(bytes, elapsed))
+}
+
+/// The throughput of an update, over its whole run and over the time spent hashing, e.g.
+/// `Hashed 41.20 GB in 2 min 12 s (312.00 MB/s overall, 330.00 MB/s while hashing)`
+pub fn update_throughput(bytes: u64, elapsed: Duration, hash_time: Duration) -> String {
+    format!(
+        "Hashed {} in {} ({} overall, {} while hashing)",
+        format_bytes(bytes),
+        format_duration(elapsed),
+        format_rate(bytes, elapsed),
+        format_rate(bytes, hash_time)
+    )
+}
+
+/// Where an update's time went, e.g.
+/// `Spent 2 min 5 s hashing 1,203 file(s) and 7 s on 98,000 file(s) in all`.
+/// Most of the time hashing at a low rate points at the disk; most of it elsewhere, at the
+/// number of files.
+pub fn time_split(hashed_files: usize, hash_time: Duration, files: usize, elapsed: Duration) -> String {
+    format!(
+        "Spent {} hashing {} file(s) and {} on {} file(s) in all",
+        format_duration(hash_time),
+        group_digits(hashed_files),
+        format_duration(elapsed.saturating_sub(hash_time)),
+        group_digits(files),
+    )
+}
+
+fn format_rate(bytes: u64, elapsed: Duration) -> String {
+    // A run too short to time is reported as instant rather than dividing by zero
+    let secs = elapsed.as_secs_f64().max(0.001);
+    format!("{}/s", format_bytes((bytes as f64 / secs) as u64))
+}
+
+/// Durations for estimates: `45 s`, `14 min`, `2 min 12 s`, `3 h 5 min`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{} s", secs),
+        60..600 if !secs.is_multiple_of(60) => format!("{} min {} s", secs / 60, secs % 60),
+        60..3600 => format!("{} min", secs / 60),
+        _ => format!("{} h {} min", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Thousands separators for file counts: `98,000`
+pub fn group_digits(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+impl Drop for Progress {
+    /// Don't leave the live line under an error message
+    fn drop(&mut self) {
+        clear_line();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line_and_estimate() {
+        assert_eq!(group_digits(98000), "98,000");
+        assert_eq!(group_digits(1234567), "1,234,567");
+        assert_eq!(group_digits(12), "12");
+        assert_eq!(format_duration(Duration::from_secs(45)), "45 s");
+        assert_eq!(format_duration(Duration::from_secs(132)), "2 min 12 s");
+        assert_eq!(format_duration(Duration::from_secs(14 * 60 + 20)), "14 min");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 5 * 60)), "3 h 5 min");
+        assert_eq!(throughput(2 * 1024 * 1024, Duration::from_secs(2)), "Hashed 2.00 MB in 2 s (1.00 MB/s)");
+        assert_eq!(
+            update_throughput(264 * 1024 * 1024, Duration::from_secs(132), Duration::from_secs(66)),
+            "Hashed 264.00 MB in 2 min 12 s (2.00 MB/s overall, 4.00 MB/s while hashing)"
+        );
+        assert_eq!(
+            time_split(1203, Duration::from_secs(125), 98000, Duration::from_secs(132)),
+            "Spent 2 min 5 s hashing 1,203 file(s) and 7 s on 98,000 file(s) in all"
+        );
+
+        let mut progress = Progress::new(false);
+        progress.expect(98000, None);
+        for _ in 0..12432 {
+            progress.file_done();
+        }
+        progress.hashed(1024);
+        assert!(progress.line().starts_with("12,432/98,000 files, 1.00 KB hashed, "), "got: {}", progress.line());
+        // Too early for an estimate
+        assert!(!progress.line().contains("remaining"));
+
+        progress.start -= Duration::from_secs(60);
+        // 12.7% done after a minute
+        assert!(progress.line().contains(", ~6 min "), "got: {}", progress.line());
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+use crate::file_utils::now_millis;
+use crate::index::FileEntry;
+
+/// The catalog page; it loads the data from CATALOG_DATA_FILE
+const PAGE: &str = include_str!("publish.html");
+pub const PAGE_FILE: &str = "index.html";
+/// Catalog data as a script (not JSON) so the page also works when opened from disk
+const CATALOG_DATA_FILE: &str = "catalog.js";
+
+/// Write a static, browsable catalog of index entries to a directory
+/// The catalog is an HTML page plus a data script, with no server-side code: it can be
+/// copied to any web server or opened directly from disk. Visitors can build a list of
+/// files they want and download it as a request file for `oci fulfill`.
+pub fn write_static(entries: &[FileEntry], title: &str, out_dir: &Path) -> Result<()> {
+    fs::create_dir_all(out_dir)
+        .context(format!("Failed to create {}", out_dir.display()))?;
+
+    let mut entries: Vec<&FileEntry> = entries.iter().collect();
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let catalog = json!({
+        "title": title,
+        "generated": now_millis(),
+        "files": entries.iter()
+            .map(|e| json!([e.path, e.num_bytes, e.modified, e.sha256]))
+            .collect::<Vec<_>>(),
+    });
+
+    fs::write(out_dir.join(PAGE_FILE), PAGE)
+        .context(format!("Failed to write {}", PAGE_FILE))?;
+    fs::write(out_dir.join(CATALOG_DATA_FILE), format!("window.OCI_CATALOG = {};\n", catalog))
+        .context(format!("Failed to write {}", CATALOG_DATA_FILE))?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::user_config_dir;
+use crate::index::OCI_DIR;
+
+/// File listing the repositories known on this machine, one absolute path per line
+const REGISTRY_FILE: &str = "repos";
+
+/// Location of the registry: `$XDG_CONFIG_HOME/oci/repos`, falling back to `~/.config/oci/repos`
+pub fn registry_path() -> PathBuf {
+    user_config_dir().join(REGISTRY_FILE)
+}
+
+/// Registered repository roots, in the order they were registered
+pub fn load() -> Result<Vec<PathBuf>> {
+    let path = registry_path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(format!("Failed to read repository registry: {}", path.display())),
+    };
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Add a repository root to the registry, returning false if it was already registered
+pub fn register(repo_root: &Path) -> Result<bool> {
+    let mut repos = load()?;
+    if repos.iter().any(|repo| repo == repo_root) {
+        return Ok(false);
+    }
+    repos.push(repo_root.to_path_buf());
+    save(&repos)?;
+    Ok(true)
+}
+
+/// Remove a repository root from the registry, returning false if it was not registered
+pub fn unregister(repo_root: &Path) -> Result<bool> {
+    let mut repos = load()?;
+    let count = repos.len();
+    repos.retain(|repo| repo != repo_root);
+    if repos.len() == count {
+        return Ok(false);
+    }
+    save(&repos)?;
+    Ok(true)
+}
+
+/// Whether a registered repository can be reached (its volume is mounted)
+pub fn is_available(repo_root: &Path) -> bool {
+    repo_root.join(OCI_DIR).is_dir()
+}
+
+/// Write the registry through a temporary file, so concurrent readers never see a partial list
+fn save(repos: &[PathBuf]) -> Result<()> {
+    let path = registry_path();
+    let dir = path.parent().context("Invalid registry path")?;
+    fs::create_dir_all(dir)
+        .context(format!("Failed to create directory: {}", dir.display()))?;
+
+    let contents: String = repos.iter().map(|repo| format!("{}\n", repo.display())).collect();
+    let temp_path = path.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(&temp_path, contents)
+        .context(format!("Failed to write repository registry: {}", temp_path.display()))?;
+    fs::rename(&temp_path, &path)
+        .context(format!("Failed to write repository registry: {}", path.display()))?;
+    Ok(())
+}
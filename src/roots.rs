@@ -0,0 +1,201 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::OciError;
+
+/// Marker file written at the top of each registered root, pointing back at the index directory
+pub const ROOT_MARKER: &str = ".ociroot";
+
+/// A named directory tracked by a multi-root index (`root.<name>` config keys)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Root {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Root {
+    /// Parse a root from its config name and path
+    pub fn parse(name: &str, path: &str) -> Result<Self> {
+        if name.is_empty() || name.contains('/') || name.starts_with('.') {
+            bail!("Invalid root name: '{}' (must be non-empty, without '/' or a leading '.')", name);
+        }
+        let path = PathBuf::from(path);
+        if !path.is_absolute() {
+            bail!("Root path must be absolute: {}", path.display());
+        }
+        Ok(Self { name: name.to_string(), path })
+    }
+}
+
+/// Maps index paths to filesystem locations
+/// Without configured roots every index path is relative to the repository root. With roots,
+/// index paths are `rootname/relative/path` and the repository root is a virtual parent of all roots.
+#[derive(Debug, Clone)]
+pub struct Roots {
+    repo_root: PathBuf,
+    roots: Vec<Root>,
+}
+
+impl Roots {
+    /// Build the root mapping for a repository from its config
+    pub fn from_config(repo_root: PathBuf, config: &Config) -> Self {
+        Self { repo_root, roots: config.roots.clone() }
+    }
+
+    /// The repository root (the directory containing `.oci`)
+    pub fn repo_root(&self) -> &Path {
+        &self.repo_root
+    }
+
+    /// Whether the index spans multiple registered roots
+    pub fn is_multi(&self) -> bool {
+        !self.roots.is_empty()
+    }
+
+    /// Find the scan base containing a filesystem path, with the index path prefix of that base
+    pub fn base_of(&self, path: &Path) -> Option<(&Path, &str)> {
+        if !self.is_multi() {
+            return path.starts_with(&self.repo_root).then_some((self.repo_root.as_path(), ""));
+        }
+        self.roots
+            .iter()
+            .filter(|root| path.starts_with(&root.path))
+            .max_by_key(|root| root.path.as_os_str().len())
+            .map(|root| (root.path.as_path(), root.name.as_str()))
+    }
+
+    /// Convert a filesystem path given to a command to its index path, failing with
+    /// `PathOutsideRepo` if it lies outside the repository
+    pub fn require_index_path(&self, path: &Path) -> Result<String> {
+        self.index_path(path).ok_or_else(|| OciError::PathOutsideRepo(path.to_path_buf()).into())
+    }
+
+    /// Convert a filesystem path to its index path (None if outside the repository)
+    pub fn index_path(&self, path: &Path) -> Option<String> {
+        if self.is_multi() && path == self.repo_root {
+            return Some(String::new());
+        }
+        let (base, prefix) = self.base_of(path)?;
+        let rel = path.strip_prefix(base).ok()?;
+        Some(join_index_path(prefix, rel).to_string_lossy().to_string())
+    }
+
+    /// Filesystem location of an index path
+    pub fn full_path(&self, index_path: &str) -> PathBuf {
+        let (name, rest) = index_path.split_once('/').unwrap_or((index_path, ""));
+        match self.roots.iter().find(|root| root.name == name) {
+            Some(root) if rest.is_empty() => root.path.clone(),
+            Some(root) => root.path.join(rest),
+            None => self.repo_root.join(index_path),
+        }
+    }
+
+    /// Map a path under the virtual repository root onto the root it names
+    /// (e.g. `<repo>/Photos/2020` becomes `/Volumes/Photos/2020`); other paths are returned as is
+    pub fn locate(&self, path: &Path) -> PathBuf {
+        if self.is_multi() && self.base_of(path).is_none() {
+            if let Ok(rel) = path.strip_prefix(&self.repo_root) {
+                if !rel.as_os_str().is_empty() {
+                    return self.full_path(&rel.to_string_lossy());
+                }
+            }
+        }
+        path.to_path_buf()
+    }
+
+    /// Directories to scan for a target: every root for the virtual repository root, else the target itself
+    pub fn scan_targets(&self, target: &Path) -> Vec<PathBuf> {
+        if self.is_multi() && target == self.repo_root {
+            self.roots.iter().map(|root| root.path.clone()).collect()
+        } else {
+            vec![target.to_path_buf()]
+        }
+    }
+
+    /// Check that a canonicalized path lies within the repository (any root when multi-root)
+    pub fn contains_canonical(&self, canonical: &Path) -> Result<bool> {
+        if !self.is_multi() {
+            let canonical_repo = self.repo_root.canonicalize()
+                .context("Failed to canonicalize repo root")?;
+            return Ok(canonical.starts_with(canonical_repo));
+        }
+        for root in &self.roots {
+            let canonical_root = root.path.canonicalize()
+                .context(format!("Failed to canonicalize root: {}", root.path.display()))?;
+            if canonical.starts_with(canonical_root) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Join an index path prefix and a path relative to its base (without a trailing separator)
+pub fn join_index_path(prefix: &str, rel: &Path) -> PathBuf {
+    if rel.as_os_str().is_empty() {
+        PathBuf::from(prefix)
+    } else {
+        Path::new(prefix).join(rel)
+    }
+}
+
+/// Write the marker file that lets commands run inside a root find its index
+pub fn write_marker(root: &Path, repo_root: &Path) -> Result<()> {
+    fs::write(root.join(ROOT_MARKER), format!("{}\n", repo_root.display()))
+        .context(format!("Failed to write root marker in {}", root.display()))
+}
+
+/// Read the index directory a root marker points at
+pub fn read_marker(marker: &Path) -> Result<PathBuf> {
+    let contents = fs::read_to_string(marker)
+        .context(format!("Failed to read root marker: {}", marker.display()))?;
+    Ok(PathBuf::from(contents.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots() -> Roots {
+        Roots {
+            repo_root: PathBuf::from("/home/me/media"),
+            roots: vec![
+                Root::parse("Photos", "/Volumes/Photos").unwrap(),
+                Root::parse("Video", "/Volumes/Video").unwrap(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_index_and_full_paths_round_trip() {
+        let roots = roots();
+        assert_eq!(roots.index_path(Path::new("/Volumes/Photos/2020/a.jpg")).as_deref(), Some("Photos/2020/a.jpg"));
+        assert_eq!(roots.index_path(Path::new("/Volumes/Video")).as_deref(), Some("Video"));
+        assert_eq!(roots.index_path(Path::new("/home/me/media")).as_deref(), Some(""));
+        assert_eq!(roots.index_path(Path::new("/tmp/x")), None);
+        assert_eq!(roots.full_path("Photos/2020/a.jpg"), PathBuf::from("/Volumes/Photos/2020/a.jpg"));
+        assert_eq!(roots.full_path("Video"), PathBuf::from("/Volumes/Video"));
+    }
+
+    #[test]
+    fn test_locate_and_scan_targets() {
+        let roots = roots();
+        assert_eq!(roots.locate(Path::new("/home/me/media/Photos/2020")), PathBuf::from("/Volumes/Photos/2020"));
+        assert_eq!(roots.locate(Path::new("/Volumes/Video/clip.mp4")), PathBuf::from("/Volumes/Video/clip.mp4"));
+        assert_eq!(roots.scan_targets(Path::new("/home/me/media")).len(), 2);
+
+        let single = Roots { repo_root: PathBuf::from("/repo"), roots: Vec::new() };
+        assert_eq!(single.index_path(Path::new("/repo/a/b.txt")).as_deref(), Some("a/b.txt"));
+        assert_eq!(single.full_path("a/b.txt"), PathBuf::from("/repo/a/b.txt"));
+        assert_eq!(single.scan_targets(Path::new("/repo")), vec![PathBuf::from("/repo")]);
+    }
+
+    #[test]
+    fn test_root_parse_rejects_bad_names() {
+        assert!(Root::parse("a/b", "/x").is_err());
+        assert!(Root::parse(".oci", "/x").is_err());
+        assert!(Root::parse("Photos", "relative/path").is_err());
+    }
+}
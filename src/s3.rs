@@ -0,0 +1,272 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::index::{FileEntry, Index, OCI_DIR};
+use crate::store::Backend;
+
+/// ETags and hashes from the previous listing, so unchanged objects are not queried again
+const ETAG_CACHE_FILE: &str = "s3-etags.json";
+
+/// A bucket and key prefix (`s3://bucket/prefix`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Location {
+    pub bucket: String,
+    /// Key prefix without leading or trailing '/' (empty for the whole bucket)
+    pub prefix: String,
+}
+
+impl S3Location {
+    /// Parse an `s3://bucket[/prefix]` URL
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url.strip_prefix("s3://")
+            .ok_or_else(|| anyhow::anyhow!("Not an s3:// URL: {}", url))?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            bail!("Invalid s3 source (expected s3://bucket/prefix): {}", url);
+        }
+        Ok(Self { bucket: bucket.to_string(), prefix: prefix.trim_matches('/').to_string() })
+    }
+
+    /// Directory holding the cached index for this location
+    fn cache_dir(&self) -> PathBuf {
+        let cache_home = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir);
+        let dir = cache_home.join("oci").join("s3").join(&self.bucket);
+        if self.prefix.is_empty() { dir } else { dir.join(&self.prefix) }
+    }
+}
+
+impl std::fmt::Display for S3Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "s3://{}/{}", self.bucket, self.prefix)
+    }
+}
+
+/// An object from a bucket listing
+#[derive(Debug, Clone, PartialEq)]
+struct S3Object {
+    key: String,
+    size: u64,
+    etag: String,
+    last_modified: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CachedHash {
+    etag: String,
+    sha256: String,
+}
+
+/// Build or refresh the index of a bucket prefix, returning the directory that contains its `.oci`
+/// Index paths are object keys relative to the prefix. SHA-256 hashes come from the object's
+/// full-object SHA-256 checksum or `sha256` user metadata; objects with neither are indexed
+/// with an empty hash (like local files awaiting a full hash) and never match by content.
+pub fn refresh_index(location: &S3Location) -> Result<PathBuf> {
+    let root = location.cache_dir();
+    fs::create_dir_all(root.join(OCI_DIR))
+        .context(format!("Failed to create cache directory: {}", root.display()))?;
+
+    let cache_path = root.join(OCI_DIR).join(ETAG_CACHE_FILE);
+    let cached: HashMap<String, CachedHash> = fs::read_to_string(&cache_path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+
+    let objects = list_objects(location)?;
+    let mut hashes = HashMap::new();
+    let mut entries = Vec::new();
+    let mut unhashed = 0;
+    for object in &objects {
+        let sha256 = match cached.get(&object.key) {
+            Some(hash) if hash.etag == object.etag => hash.sha256.clone(),
+            _ => head_object_sha256(location, &object.key)?.unwrap_or_default(),
+        };
+        if sha256.is_empty() {
+            unhashed += 1;
+        }
+
+        entries.push(FileEntry {
+            path: relative_key(&location.prefix, &object.key).to_string(),
+            num_bytes: object.size,
+            modified: object.last_modified,
+            sha256: sha256.clone(),
+            ..Default::default()
+        });
+        hashes.insert(object.key.clone(), CachedHash { etag: object.etag.clone(), sha256 });
+    }
+
+    let mut index = Index::open(&root, Backend::Sqlite)?;
+    index.begin_batch()?;
+    index.clear()?;
+    index.upsert_many(entries)?;
+    index.commit_batch()?;
+
+    fs::write(&cache_path, serde_json::to_string(&hashes)?)
+        .context("Failed to write s3 hash cache")?;
+
+    println!(
+        "Listed {} object(s) in {}{}",
+        objects.len(),
+        location,
+        if unhashed > 0 { format!(" ({} without a SHA-256, not matched by content)", unhashed) } else { String::new() }
+    );
+    Ok(root)
+}
+
+/// Run an `aws s3api` command and parse its JSON output
+fn aws(args: &[&str]) -> Result<Value> {
+    let output = Command::new("aws")
+        .arg("s3api")
+        .args(args)
+        .args(["--output", "json"])
+        .output()
+        .context("Failed to run 'aws' (is the AWS CLI installed?)")?;
+    if !output.status.success() {
+        bail!("aws s3api {}: {}", args[0], String::from_utf8_lossy(&output.stderr).trim());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_str(&stdout).context("Failed to parse aws output")
+}
+
+fn list_objects(location: &S3Location) -> Result<Vec<S3Object>> {
+    let prefix = if location.prefix.is_empty() { String::new() } else { format!("{}/", location.prefix) };
+    let listing = aws(&["list-objects-v2", "--bucket", &location.bucket, "--prefix", &prefix])?;
+    parse_listing(&listing, &location.prefix)
+}
+
+/// Objects from a list-objects-v2 response (folder markers and `.oci` contents are skipped)
+fn parse_listing(listing: &Value, prefix: &str) -> Result<Vec<S3Object>> {
+    let contents = match listing.get("Contents").and_then(Value::as_array) {
+        Some(contents) => contents,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut objects = Vec::new();
+    for item in contents {
+        let key = item["Key"].as_str().context("Object without a key in listing")?;
+        let rel = relative_key(prefix, key);
+        if rel.is_empty() || key.ends_with('/') || rel == OCI_DIR || rel.starts_with(".oci/") {
+            continue;
+        }
+        objects.push(S3Object {
+            key: key.to_string(),
+            size: item["Size"].as_u64().unwrap_or(0),
+            etag: item["ETag"].as_str().unwrap_or("").trim_matches('"').to_string(),
+            last_modified: item["LastModified"].as_str().and_then(parse_timestamp).unwrap_or(0),
+        });
+    }
+    Ok(objects)
+}
+
+fn head_object_sha256(location: &S3Location, key: &str) -> Result<Option<String>> {
+    let head = aws(&["head-object", "--bucket", &location.bucket, "--key", key, "--checksum-mode", "ENABLED"])?;
+    Ok(sha256_from_head(&head))
+}
+
+/// SHA-256 (hex) of an object from its head-object response, if S3 knows it
+fn sha256_from_head(head: &Value) -> Option<String> {
+    // Multipart uploads have a checksum of checksums ("...-N") unless the checksum type is FULL_OBJECT
+    if let Some(checksum) = head.get("ChecksumSHA256").and_then(Value::as_str) {
+        if !checksum.contains('-') && head.get("ChecksumType").and_then(Value::as_str) != Some("COMPOSITE") {
+            if let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(checksum) {
+                if bytes.len() == 32 {
+                    return Some(bytes.iter().map(|b| format!("{:02x}", b)).collect());
+                }
+            }
+        }
+    }
+
+    let metadata = head.get("Metadata")?.as_object()?;
+    metadata.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("sha256"))
+        .and_then(|(_, value)| value.as_str())
+        .map(|value| value.to_ascii_lowercase())
+        .filter(|value| value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Key relative to the prefix
+fn relative_key<'a>(prefix: &str, key: &'a str) -> &'a str {
+    if prefix.is_empty() {
+        return key.trim_start_matches('/');
+    }
+    key.strip_prefix(prefix)
+        .map(|rest| rest.trim_start_matches('/'))
+        .unwrap_or(key)
+}
+
+/// Parse an ISO 8601 UTC timestamp ("2024-05-01T12:34:56.000Z" or "...+00:00") to milliseconds since epoch
+fn parse_timestamp(text: &str) -> Option<u64> {
+    let (date, time) = text.split_once('T')?;
+    let mut date_parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date_parts.next()??, date_parts.next()??, date_parts.next()??);
+
+    let time = time.trim_end_matches('Z');
+    let time = time.split(['+', '-']).next()?;
+    let (hms, fraction) = time.split_once('.').unwrap_or((time, "0"));
+    let mut time_parts = hms.splitn(3, ':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute, second) = (time_parts.next()??, time_parts.next()??, time_parts.next()??);
+    let millis = format!("{:0<3}", fraction).get(..3)?.parse::<i64>().ok()?;
+
+    // Days since 1970-01-01 from a civil date (proleptic Gregorian calendar)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let total = ((days * 86_400 + hour * 3600 + minute * 60 + second) * 1000) + millis;
+    u64::try_from(total).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_location_and_timestamp() {
+        let location = S3Location::parse("s3://archive/photos/2020/").unwrap();
+        assert_eq!(location.bucket, "archive");
+        assert_eq!(location.prefix, "photos/2020");
+        assert_eq!(S3Location::parse("s3://archive").unwrap().prefix, "");
+        assert!(S3Location::parse("s3:///x").is_err());
+
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2000-02-29T00:00:00.000Z"), Some(951_782_400_000));
+        assert_eq!(parse_timestamp("2024-12-31T23:59:59.999+00:00"), Some(1_735_689_599_999));
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_listing_and_head() {
+        let listing = json!({"Contents": [
+            {"Key": "photos/a.jpg", "Size": 5, "ETag": "\"abc\"", "LastModified": "1970-01-01T00:00:01.000Z"},
+            {"Key": "photos/sub/", "Size": 0, "ETag": "\"d41d\""},
+            {"Key": "photos/.oci/index.db", "Size": 10, "ETag": "\"x\""},
+        ]});
+        let objects = parse_listing(&listing, "photos").unwrap();
+        assert_eq!(objects, vec![S3Object { key: "photos/a.jpg".to_string(), size: 5, etag: "abc".to_string(), last_modified: 1000 }]);
+        assert!(parse_listing(&Value::Null, "").unwrap().is_empty());
+
+        // sha256("hello")
+        let hex = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let checksum = json!({"ChecksumSHA256": "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=", "ChecksumType": "FULL_OBJECT"});
+        assert_eq!(sha256_from_head(&checksum).as_deref(), Some(hex));
+        assert_eq!(sha256_from_head(&json!({"ChecksumSHA256": "abc=-3"})), None);
+        assert_eq!(sha256_from_head(&json!({"Metadata": {"sha256": hex.to_uppercase()}})).as_deref(), Some(hex));
+        assert_eq!(sha256_from_head(&json!({"ETag": "\"abc\""})), None);
+    }
+}
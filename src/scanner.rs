@@ -1,9 +1,55 @@
 use anyhow::{Context, Result};
+use jwalk::{Parallelism, WalkDir};
 use std::collections::HashSet;
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use crate::config::UserConfig;
+use crate::file_utils;
 use crate::ignore;
+use crate::roots::{self, Roots};
+
+/// Walk a directory tree with one thread per job (`jobs` in the user config), which keeps network
+/// shares and spinning disks busy; entries still arrive in index path order, parents first.
+/// Directories for which `skip_dir` returns true (given their full path) are neither yielded nor
+/// read, like walkdir's `filter_entry`. The walk uses the long form of `dir` (`file_utils::long_path`),
+/// so paths past MAX_PATH can be read on Windows; `file_utils::short_path` maps entries back.
+pub fn parallel_walk<F>(dir: &Path, max_depth: Option<usize>, skip_dir: F) -> Result<WalkDir>
+where
+    F: Fn(&Path) -> bool + Send + Sync + 'static,
+{
+    let jobs = UserConfig::load()?.jobs();
+    let parallelism = if jobs > 1 { Parallelism::RayonNewPool(jobs) } else { Parallelism::Serial };
+    // A skipped starting directory yields only itself
+    let max_depth = if dir.is_dir() && skip_dir(dir) { Some(0) } else { max_depth };
+
+    let skip_dir = Arc::new(skip_dir);
+    let walker = WalkDir::new(file_utils::long_path(dir))
+        .skip_hidden(false)
+        .parallelism(parallelism)
+        .process_read_dir(move |_, _, _, children| {
+            children.retain(|child| match child {
+                Ok(entry) => !(entry.file_type.is_dir() && skip_dir(&entry.parent_path.join(&entry.file_name))),
+                Err(_) => true,
+            });
+            // A directory sorts as if its name ended in '/', so `a.txt` comes before `a/b.txt`
+            // as it does in the index, and listings of both line up
+            children.sort_by_cached_key(|child| match child {
+                Ok(entry) => {
+                    let mut name = entry.file_name.to_string_lossy().into_owned();
+                    if entry.file_type.is_dir() {
+                        name.push('/');
+                    }
+                    (false, name)
+                }
+                Err(_) => (true, String::new()),
+            });
+        });
+    Ok(match max_depth {
+        Some(depth) => walker.max_depth(depth),
+        None => walker,
+    })
+}
 
 /// Result of scanning the filesystem
 #[derive(Debug)]
@@ -17,60 +63,75 @@ pub struct ScanResult {
 
 /// Utility for scanning directories with ignore pattern support
 pub struct FileScanner {
-    repo_root: PathBuf,
+    roots: Roots,
     patterns: Vec<String>,
 }
 
 impl FileScanner {
     /// Create a new FileScanner
-    pub fn new(repo_root: PathBuf, patterns: Vec<String>) -> Self {
+    pub fn new(roots: Roots, patterns: Vec<String>) -> Self {
         Self {
-            repo_root,
+            roots,
             patterns,
         }
     }
 
 
-    /// Scan entire repository recursively with filtering
-    pub fn scan_repository_filtered(&self, verbose: bool) -> Result<ScanResult> {
+    /// Scan entire repository (every root of a multi-root index) recursively with filtering
+    pub fn scan_repository_filtered(&self) -> Result<ScanResult> {
         let mut tracked_files = HashSet::new();
         let ignored_files = HashSet::new();
 
-        for entry in WalkDir::new(&self.repo_root)
-            .into_iter()
-            .filter_entry(|e| {
-                // Convert to relative path for pattern matching
-                if let Ok(rel) = e.path().strip_prefix(&self.repo_root) {
-                    !ignore::should_ignore(rel, &self.patterns)
-                } else {
-                    true // Don't filter if path conversion fails
-                }
-            })
-        {
+        for target in self.roots.scan_targets(self.roots.repo_root()) {
+            let (base, prefix) = self.roots.base_of(&target)
+                .context("Path is outside repository")?;
+            self.scan_base(base, prefix, &mut tracked_files)?;
+        }
+
+        Ok(ScanResult {
+            tracked_files,
+            ignored_files,
+        })
+    }
+
+    /// Scan one base directory, recording index paths under the given prefix
+    fn scan_base(
+        &self,
+        base: &Path,
+        prefix: &str,
+        tracked_files: &mut HashSet<String>,
+    ) -> Result<()> {
+        // Convert to index path for pattern matching
+        let index_path = {
+            let (base, prefix) = (base.to_path_buf(), prefix.to_string());
+            move |path: &Path| -> Option<PathBuf> {
+                file_utils::short_path(path).strip_prefix(&base).ok().map(|rel| roots::join_index_path(&prefix, rel))
+            }
+        };
+        let patterns = self.patterns.clone();
+        let walker = parallel_walk(base, None, {
+            let index_path = index_path.clone();
+            move |dir| index_path(dir).is_some_and(|path| ignore::should_ignore_dir(&path, &patterns))
+        })?;
+        for entry in walker {
             // Handle permission errors gracefully - skip and continue
             let entry = match entry {
                 Ok(e) => e,
                 Err(err) => {
-                    if verbose {
-                        eprintln!("Warning: Skipping due to error: {}", err);
-                    }
+                    tracing::info!("Skipping due to error: {}", err);
                     continue;
                 }
             };
 
             if entry.file_type().is_file() {
-                let rel_path = entry
-                    .path()
-                    .strip_prefix(&self.repo_root)
-                    .context("Path is outside repository")?;
-                tracked_files.insert(rel_path.to_string_lossy().to_string());
+                let path = index_path(&entry.path()).context("Path is outside repository")?;
+                if !ignore::should_ignore(&path, &self.patterns) {
+                    tracked_files.insert(path.to_string_lossy().to_string());
+                }
             }
         }
 
-        Ok(ScanResult {
-            tracked_files,
-            ignored_files,
-        })
+        Ok(())
     }
 
 }
@@ -0,0 +1,263 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::index::{FileEntry, Index, OCI_DIR};
+
+/// Files under `.oci` that may be downloaded, so `oci prune`/`oci diff` can use a served index as a source
+const SERVED_OCI_FILES: &[&str] = &["index.db", "index.log", "ignore"];
+
+/// Longest a client may take to send its request or accept the response before it is dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most bytes read of a request line and its headers, so a client can't make the server buffer without end
+const MAX_REQUEST_HEAD: u64 = 8192;
+
+/// An HTTP response to send back
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn json(status: u16, value: Value) -> Self {
+        Self { status, content_type: "application/json", body: value.to_string().into_bytes() }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, json!({ "error": message }))
+    }
+}
+
+/// Serve read-only requests until the process is stopped, each connection on a thread of its own.
+/// The index is opened once: a SQLite index answers from the database on disk as it is at each
+/// request, while a log or encrypted index is read into memory at start and not reread.
+pub fn run(listener: TcpListener, repo_root: &Path) -> Result<()> {
+    let index = Arc::new(Mutex::new(Index::load(repo_root)?));
+    let repo_root = Arc::new(repo_root.to_path_buf());
+    for stream in listener.incoming().flatten() {
+        let index = Arc::clone(&index);
+        let repo_root = Arc::clone(&repo_root);
+        std::thread::spawn(move || {
+            // A client that disconnects mid-request must not stop the server
+            let _ = handle_connection(stream, &index, &repo_root);
+        });
+    }
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream, index: &Mutex<Index>, repo_root: &Path) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone().context("Failed to clone connection")?.take(MAX_REQUEST_HEAD));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain the headers; requests never carry a body we care about
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        // A line cut off by the limit has no line ending
+        (Some(method), Some(target)) if request_line.ends_with('\n') => {
+            let index = index.lock().unwrap();
+            handle_request(method, target, &index, repo_root)
+                .unwrap_or_else(|e| Response::error(500, &format!("{:#}", e)))
+        }
+        _ => Response::error(400, "malformed request"),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Route a request to its endpoint
+pub fn handle_request(method: &str, target: &str, index: &Index, repo_root: &Path) -> Result<Response> {
+    if method != "GET" {
+        return Ok(Response::error(405, "only GET is supported"));
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = parse_query(query);
+    let path = path.trim_end_matches('/');
+
+    if path == "/files" {
+        let prefix = query.get("prefix").map(|p| p.trim_matches('/')).unwrap_or("");
+        let files = index.get_dir_files_recursive(prefix)?;
+        return Ok(Response::json(200, Value::Array(files.iter().map(entry_json).collect())));
+    }
+
+    if let Some(hash) = path.strip_prefix("/files/by-hash/") {
+        let files = index.find_by_hash(&percent_decode(hash).to_lowercase())?;
+        if files.is_empty() {
+            return Ok(Response::error(404, "no file with this hash"));
+        }
+        return Ok(Response::json(200, Value::Array(files.iter().map(entry_json).collect())));
+    }
+
+    if path == "/stats" {
+        return Ok(Response::json(200, stats_json(&index.get_dir_files_recursive("")?)));
+    }
+
+    if let Some(name) = path.strip_prefix(&format!("/{}/", OCI_DIR)) {
+        if SERVED_OCI_FILES.contains(&name) {
+            let file_path = match name {
+                "ignore" => crate::ignore::ignore_file(repo_root),
+                _ => repo_root.join(OCI_DIR).join(name),
+            };
+            if file_path.exists() {
+                // With WAL, recent writes may not be in index.db yet
+                index.checkpoint()?;
+                let body = std::fs::read(&file_path)
+                    .context(format!("Failed to read {}", file_path.display()))?;
+                return Ok(Response { status: 200, content_type: "application/octet-stream", body });
+            }
+        }
+    }
+
+    Ok(Response::error(404, "not found"))
+}
+
+fn entry_json(entry: &FileEntry) -> Value {
+    json!({
+        "path": entry.path,
+        "num_bytes": entry.num_bytes,
+        "modified": entry.modified,
+        "sha256": if entry.sha256.is_empty() { Value::Null } else { Value::from(entry.sha256.clone()) },
+    })
+}
+
+fn stats_json(files: &[FileEntry]) -> Value {
+    let mut sizes_by_hash: HashMap<&str, (u64, usize)> = HashMap::new();
+    for entry in files.iter().filter(|f| !f.sha256.is_empty()) {
+        let group = sizes_by_hash.entry(&entry.sha256).or_insert((entry.num_bytes, 0));
+        group.1 += 1;
+    }
+
+    json!({
+        "files": files.len(),
+        "bytes": files.iter().map(|f| f.num_bytes).sum::<u64>(),
+        "unique_hashes": sizes_by_hash.len(),
+        "unique_bytes": sizes_by_hash.values().map(|(bytes, _)| bytes).sum::<u64>(),
+        "duplicate_files": sizes_by_hash.values().filter(|(_, n)| *n > 1).map(|(_, n)| n).sum::<usize>(),
+        "pending_full_hash": files.iter().filter(|f| f.sha256.is_empty()).count(),
+    })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes and `+` (as a space) in a URL component
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> Index {
+        let mut index = Index::new().unwrap();
+        for (path, hash) in [("photos/a b.jpg", "aa"), ("photos/copy.jpg", "aa"), ("docs/x.txt", "bb")] {
+            index.upsert(FileEntry {
+                path: path.to_string(),
+                num_bytes: 10,
+                modified: 1,
+                sha256: hash.to_string(),
+                ..Default::default()
+            }).unwrap();
+        }
+        index
+    }
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let index = index();
+        let root = Path::new("/nonexistent");
+
+        let files = handle_request("GET", "/files?prefix=photos", &index, root).unwrap();
+        assert_eq!(files.status, 200);
+        assert_eq!(body(&files).as_array().unwrap().len(), 2);
+
+        let by_hash = handle_request("GET", "/files/by-hash/AA", &index, root).unwrap();
+        assert_eq!(body(&by_hash)[0]["sha256"], "aa");
+        assert_eq!(handle_request("GET", "/files/by-hash/cc", &index, root).unwrap().status, 404);
+
+        let stats = body(&handle_request("GET", "/stats", &index, root).unwrap());
+        assert_eq!(stats["files"], 3);
+        assert_eq!(stats["unique_hashes"], 2);
+        assert_eq!(stats["duplicate_files"], 2);
+
+        assert_eq!(handle_request("POST", "/files", &index, root).unwrap().status, 405);
+        assert_eq!(handle_request("GET", "/.oci/config", &index, root).unwrap().status, 404);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("photos%2Fa+b%20c"), "photos/a b c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
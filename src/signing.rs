@@ -0,0 +1,242 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::file_utils::compute_sha256;
+use crate::index::OCI_DIR;
+
+/// Directory under `.oci` holding the signing key pair and trusted public keys
+pub const KEYS_DIR: &str = "keys";
+const SIGNING_KEY_FILE: &str = "signing.key";
+const PUBLIC_KEY_FILE: &str = "signing.pub";
+const TRUSTED_DIR: &str = "trusted";
+const PUB_EXTENSION: &str = "pub";
+
+/// Signature over the files of an exported index, next to them in its `.oci` directory
+pub const SIGNATURE_FILE: &str = "index.sig";
+const SIGNATURE_HEADER: &str = "oci-signature 1";
+
+fn keys_dir(repo_root: &Path) -> PathBuf {
+    repo_root.join(OCI_DIR).join(KEYS_DIR)
+}
+
+/// A public key as printed by `keys show` and stored in `.pub` files
+pub fn encode_public_key(key: &VerifyingKey) -> String {
+    BASE64.encode(key.as_bytes())
+}
+
+pub fn decode_public_key(text: &str) -> Result<VerifyingKey> {
+    let bytes = BASE64.decode(text.trim()).ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| anyhow::anyhow!("Not an ed25519 public key: {}", text.trim()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| anyhow::anyhow!("Not an ed25519 public key: {}", text.trim()))
+}
+
+/// Create the repository's signing key pair; an existing pair is only replaced with `force`
+pub fn generate(repo_root: &Path, force: bool) -> Result<VerifyingKey> {
+    let dir = keys_dir(repo_root);
+    let secret_path = dir.join(SIGNING_KEY_FILE);
+    if secret_path.exists() && !force {
+        bail!("A signing key already exists in {} (use --force to replace it)", dir.display());
+    }
+    fs::create_dir_all(&dir).context("Failed to create keys directory")?;
+
+    let mut seed = [0; 32];
+    OsRng.fill_bytes(&mut seed);
+    let key = SigningKey::from_bytes(&seed);
+    write_private(&secret_path, &BASE64.encode(seed))?;
+    fs::write(dir.join(PUBLIC_KEY_FILE), encode_public_key(&key.verifying_key()) + "\n")
+        .context("Failed to write public key")?;
+    Ok(key.verifying_key())
+}
+
+/// Write a file readable only by its owner (on Unix)
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).context(format!("Failed to write {}", path.display()))?;
+    std::io::Write::write_all(&mut file, format!("{}\n", contents).as_bytes())
+        .context(format!("Failed to write {}", path.display()))
+}
+
+pub fn signing_key(repo_root: &Path) -> Result<SigningKey> {
+    let path = keys_dir(repo_root).join(SIGNING_KEY_FILE);
+    if !path.exists() {
+        bail!("No signing key in this repository; create one with 'oci keys generate'");
+    }
+    let text = fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+    let seed = BASE64.decode(text.trim()).ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| anyhow::anyhow!("Damaged signing key: {}", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// The repository's own public key, if it has a signing key
+pub fn public_key(repo_root: &Path) -> Result<Option<VerifyingKey>> {
+    let path = keys_dir(repo_root).join(SIGNING_KEY_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(signing_key(repo_root)?.verifying_key()))
+}
+
+/// Trust a public key under a name, so `--verify-key <name>` can refer to it
+pub fn trust(repo_root: &Path, name: &str, key: &VerifyingKey) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        bail!("Invalid key name: {}", name);
+    }
+    let dir = keys_dir(repo_root).join(TRUSTED_DIR);
+    fs::create_dir_all(&dir).context("Failed to create keys directory")?;
+    fs::write(dir.join(name).with_extension(PUB_EXTENSION), encode_public_key(key) + "\n")
+        .context("Failed to write trusted key")
+}
+
+/// Trusted public keys by name, sorted by name
+pub fn trusted_keys(repo_root: &Path) -> Result<Vec<(String, VerifyingKey)>> {
+    let dir = keys_dir(repo_root).join(TRUSTED_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut keys = Vec::new();
+    for entry in fs::read_dir(&dir).context("Failed to read trusted keys")? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(PUB_EXTENSION) {
+            continue;
+        }
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let text = fs::read_to_string(&path)?;
+        keys.push((name, decode_public_key(&text).context(format!("In {}", path.display()))?));
+    }
+    keys.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(keys)
+}
+
+/// Resolve a `--verify-key` argument: the name of a trusted key, a `.pub` file, or a key itself
+pub fn resolve_key(repo_root: &Path, arg: &str) -> Result<VerifyingKey> {
+    if let Some((_, key)) = trusted_keys(repo_root)?.into_iter().find(|(name, _)| name == arg) {
+        return Ok(key);
+    }
+    let path = Path::new(arg);
+    if path.is_file() {
+        let text = fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+        return decode_public_key(&text).context(format!("In {}", path.display()));
+    }
+    decode_public_key(arg).context("Expected the name of a trusted key (see 'oci keys list'), a public key file or a public key")
+}
+
+/// The signed text: the header, the signer's key and the hash of each file
+fn manifest(key: &VerifyingKey, files: &[(String, String)]) -> String {
+    let mut text = format!("{}\nkey {}\n", SIGNATURE_HEADER, encode_public_key(key));
+    for (hash, name) in files {
+        text.push_str(&format!("file {} {}\n", hash, name));
+    }
+    text
+}
+
+/// Sign the named files of an `.oci` directory (those that exist), writing the signature file next to them
+pub fn sign(oci_dir: &Path, names: &[&str], key: &SigningKey) -> Result<()> {
+    let mut files = Vec::new();
+    for name in names.iter().filter(|name| oci_dir.join(name).exists()) {
+        files.push((compute_sha256(&oci_dir.join(name))?, name.to_string()));
+    }
+    let text = manifest(&key.verifying_key(), &files);
+    let signature = key.sign(text.as_bytes());
+    fs::write(oci_dir.join(SIGNATURE_FILE), format!("{}signature {}\n", text, BASE64.encode(signature.to_bytes())))
+        .context("Failed to write signature")
+}
+
+/// Check that the `.oci` directory was signed by `key` and that no signed file changed since.
+/// Any of `names` that exists must be covered by the signature.
+pub fn verify(oci_dir: &Path, names: &[&str], key: &VerifyingKey) -> Result<()> {
+    let path = oci_dir.join(SIGNATURE_FILE);
+    let text = fs::read_to_string(&path)
+        .map_err(|_| anyhow::anyhow!("The index is not signed (no {})", SIGNATURE_FILE))?;
+    let damaged = || anyhow::anyhow!("Damaged signature file: {}", path.display());
+
+    let (body, signature) = text.trim_end().rsplit_once('\n').ok_or_else(damaged)?;
+    let signature = signature.strip_prefix("signature ")
+        .and_then(|s| BASE64.decode(s).ok())
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(damaged)?;
+    let mut lines = body.lines();
+    if lines.next() != Some(SIGNATURE_HEADER) {
+        return Err(damaged());
+    }
+    let signer = lines.next().and_then(|l| l.strip_prefix("key ")).ok_or_else(damaged)?;
+    let signer = decode_public_key(signer)?;
+    if signer != *key {
+        bail!("The index was signed by a different key ({})", encode_public_key(&signer));
+    }
+    key.verify(format!("{}\n", body).as_bytes(), &signature)
+        .map_err(|_| anyhow::anyhow!("Invalid signature: the signature file was altered"))?;
+
+    let mut signed = std::collections::HashSet::new();
+    for line in lines {
+        let (hash, name) = line.strip_prefix("file ").and_then(|l| l.split_once(' ')).ok_or_else(damaged)?;
+        let file = oci_dir.join(name);
+        if !file.exists() {
+            bail!("{} was removed after it was signed", name);
+        }
+        if hash != compute_sha256(&file)? {
+            bail!("{} was modified after it was signed", name);
+        }
+        signed.insert(name);
+    }
+    // Unsigned files must not sneak in next to the signed ones either
+    if let Some(name) = names.iter().find(|name| !signed.contains(*name) && oci_dir.join(name).exists()) {
+        bail!("{} is not covered by the signature", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let temp = TempDir::new().unwrap();
+        let repo = temp.path();
+        let public = generate(repo, false).unwrap();
+        assert!(generate(repo, false).is_err());
+        assert_eq!(decode_public_key(&encode_public_key(&public)).unwrap(), public);
+
+        let oci_dir = repo.join(OCI_DIR);
+        fs::write(oci_dir.join("index.db"), "entries").unwrap();
+        fs::write(oci_dir.join("ignore"), "*.tmp\n").unwrap();
+        sign(&oci_dir, &["index.db", "ignore"], &signing_key(repo).unwrap()).unwrap();
+        verify(&oci_dir, &["index.db", "ignore", "index.log"], &public).unwrap();
+
+        // Trusted names resolve to their keys
+        trust(repo, "alice", &public).unwrap();
+        assert_eq!(resolve_key(repo, "alice").unwrap(), public);
+        assert!(resolve_key(repo, "bob").is_err());
+
+        let other = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        let err = verify(&oci_dir, &["index.db"], &other).unwrap_err();
+        assert!(err.to_string().contains("different key"), "got: {}", err);
+
+        fs::write(oci_dir.join("ignore"), "").unwrap();
+        let err = verify(&oci_dir, &["index.db", "ignore"], &public).unwrap_err();
+        assert!(err.to_string().contains("ignore was modified"), "got: {}", err);
+        fs::remove_file(oci_dir.join("ignore")).unwrap();
+        let err = verify(&oci_dir, &["index.db", "ignore"], &public).unwrap_err();
+        assert!(err.to_string().contains("ignore was removed"), "got: {}", err);
+
+        fs::write(oci_dir.join("ignore"), "*.tmp\n").unwrap();
+        fs::write(oci_dir.join("index.log"), "{}").unwrap();
+        let err = verify(&oci_dir, &["index.log"], &public).unwrap_err();
+        assert!(err.to_string().contains("not covered"), "got: {}", err);
+    }
+}
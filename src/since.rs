@@ -0,0 +1,112 @@
+use anyhow::{anyhow, Result};
+
+use crate::file_utils;
+
+/// Start of the period given to `--since`, in milliseconds since epoch: a duration back from now
+/// ("30m", "12h", "7d", "2w", "1y") or a local date ("2024-05-01") or date and time
+/// ("2024-05-01 18:30" or "2024-05-01 18:30:00")
+pub fn parse(text: &str) -> Result<u64> {
+    parse_at(text, file_utils::now_millis())
+}
+
+fn parse_at(text: &str, now_millis: u64) -> Result<u64> {
+    let text = text.trim();
+    duration_millis(text)
+        .map(|millis| now_millis.saturating_sub(millis))
+        .or_else(|| local_date_millis(text))
+        .ok_or_else(|| anyhow!(
+            "Invalid --since value: '{}' (expected a duration such as 7d or 12h, or a date such as 2024-05-01)",
+            text
+        ))
+}
+
+/// "7d" in milliseconds
+fn duration_millis(text: &str) -> Option<u64> {
+    let unit = text.chars().last()?;
+    let count: u64 = text[..text.len() - unit.len_utf8()].parse().ok()?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        'w' => 7 * 86_400,
+        'y' => 365 * 86_400,
+        _ => return None,
+    };
+    count.checked_mul(seconds * 1000)
+}
+
+/// "2024-05-01", "2024-05-01 18:30" or "2024-05-01 18:30:00" (a `T` may separate date and time)
+fn local_date_millis(text: &str) -> Option<u64> {
+    let (date, time) = text.split_once([' ', 'T']).unwrap_or((text, "00:00"));
+    let date: Vec<i32> = date.split('-').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let time: Vec<i32> = time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let (&[year, month, day], &[hour, minute, ref second @ ..]) = (date.as_slice(), time.as_slice()) else {
+        return None;
+    };
+    let second = match second {
+        [] => 0,
+        [second] => *second,
+        _ => return None,
+    };
+    let valid = (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && (0..24).contains(&hour)
+        && (0..60).contains(&minute)
+        && (0..60).contains(&second);
+    valid.then(|| local_millis(year, month, day, hour, minute, second)).flatten()
+}
+
+#[cfg(unix)]
+fn local_millis(year: i32, month: i32, day: i32, hour: i32, minute: i32, second: i32) -> Option<u64> {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = year - 1900;
+    tm.tm_mon = month - 1;
+    tm.tm_mday = day;
+    tm.tm_hour = hour;
+    tm.tm_min = minute;
+    tm.tm_sec = second;
+    // Let the time zone rules decide whether daylight saving time applies
+    tm.tm_isdst = -1;
+    let secs = unsafe { libc::mktime(&mut tm) };
+    u64::try_from(secs).ok().map(|secs| secs * 1000)
+}
+
+/// UTC where the local time zone is unknown
+#[cfg(not(unix))]
+fn local_millis(year: i32, month: i32, day: i32, hour: i32, minute: i32, second: i32) -> Option<u64> {
+    // Days since 1970-01-01 from a civil date (proleptic Gregorian calendar)
+    let (year, month, day) = (year as i64, month as i64, day as i64);
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from((days * 86_400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64) * 1000).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oplog::format_local_timestamp;
+
+    #[test]
+    fn test_parse_durations_and_dates() {
+        let now = 1_000_000_000_000;
+        assert_eq!(parse_at("7d", now).unwrap(), now - 7 * 86_400_000);
+        assert_eq!(parse_at("12h", now).unwrap(), now - 12 * 3_600_000);
+        assert_eq!(parse_at("30m", now).unwrap(), now - 30 * 60_000);
+        assert_eq!(parse_at("2w", now).unwrap(), now - 14 * 86_400_000);
+
+        let date = parse_at("2024-05-01 18:30:15", now).unwrap();
+        assert_eq!(format_local_timestamp(date), "2024-05-01 18:30:15");
+        assert_eq!(format_local_timestamp(parse_at("2024-05-01", now).unwrap()), "2024-05-01 00:00:00");
+        assert_eq!(parse_at("2024-05-01T18:30", now).unwrap(), date - 15_000);
+
+        for invalid in ["", "7", "d", "7x", "yesterday", "2024-13-01", "2024-05", "2024-05-01 25:00"] {
+            assert!(parse_at(invalid, now).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::index::OCI_DIR;
+use crate::oplog::format_timestamp;
+use crate::source::EXPORT_FILE;
+
+/// Directory under `.oci` holding one saved copy of the index per snapshot
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// A saved snapshot of the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+    /// When it was taken, in milliseconds since epoch (from its export marker)
+    pub created: Option<u64>,
+}
+
+/// Directory holding a snapshot, laid out like an export so it also works as a source (`oci diff`)
+pub fn snapshot_dir(repo_root: &Path, name: &str) -> PathBuf {
+    repo_root.join(OCI_DIR).join(SNAPSHOTS_DIR).join(name)
+}
+
+/// Check that a snapshot name is usable as a single directory name
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') {
+        bail!("Invalid snapshot name: '{}' (must not be empty or start with '.')", name);
+    }
+    if let Some(c) = name.chars().find(|c| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))) {
+        bail!("Invalid snapshot name: '{}' (contains '{}'; use letters, digits, '-', '_' and '.')", name, c);
+    }
+    Ok(())
+}
+
+/// Every snapshot of a repository, oldest first
+pub fn list(repo_root: &Path) -> Result<Vec<Snapshot>> {
+    let dir = repo_root.join(OCI_DIR).join(SNAPSHOTS_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        let created = fs::read_to_string(entry.path().join(OCI_DIR).join(EXPORT_FILE))
+            .ok()
+            .and_then(|marker| marker.lines().nth(1).and_then(|millis| millis.trim().parse().ok()));
+        snapshots.push(Snapshot { name, created });
+    }
+    snapshots.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.name.cmp(&b.name)));
+    Ok(snapshots)
+}
+
+/// Name for the snapshot `snapshot restore` takes of the state it replaces, unique within the repository
+pub fn backup_name(repo_root: &Path, now: u64) -> String {
+    let stamp = format_timestamp(now).replace(' ', "-").replace(':', "");
+    let base = format!("before-restore-{}", stamp);
+    let mut name = base.clone();
+    let mut n = 1;
+    while snapshot_dir(repo_root, &name).exists() {
+        n += 1;
+        name = format!("{}-{}", base, n);
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_names_and_listing() {
+        assert!(validate_name("before-gc_2024.1").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("..").is_err());
+        assert!(validate_name("a/b").is_err());
+        assert!(validate_name("a b").is_err());
+
+        let repo = TempDir::new().unwrap();
+        assert!(list(repo.path()).unwrap().is_empty());
+        for (name, created) in [("second", 2000), ("first", 1000)] {
+            let oci_dir = snapshot_dir(repo.path(), name).join(OCI_DIR);
+            fs::create_dir_all(&oci_dir).unwrap();
+            fs::write(oci_dir.join(EXPORT_FILE), format!("/repo\n{}\n", created)).unwrap();
+        }
+        let names: Vec<String> = list(repo.path()).unwrap().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["first", "second"]);
+
+        let backup = backup_name(repo.path(), 0);
+        assert_eq!(backup, "before-restore-1970-01-01-000000");
+        fs::create_dir_all(snapshot_dir(repo.path(), &backup)).unwrap();
+        assert_eq!(backup_name(repo.path(), 0), "before-restore-1970-01-01-000000-2");
+    }
+}
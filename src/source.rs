@@ -0,0 +1,311 @@
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::VerifyingKey;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+use crate::bundle;
+use crate::ignore;
+use crate::index::OCI_DIR;
+use crate::s3::{self, S3Location};
+use crate::signing::{self, SIGNATURE_FILE};
+use crate::store::Backend;
+
+const IGNORE_FILE: &str = "ignore";
+/// Marks a `.oci` directory written by `export`: an index without the files it describes
+pub const EXPORT_FILE: &str = "exported";
+
+/// Where a source index (for `prune` and `diff`) lives
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A repository on a local (or mounted) filesystem
+    Local(PathBuf),
+    /// A local index file (`index.db`, `index.log` or `index.db.enc`) or a bundle written by `export --bundle`
+    File(PathBuf),
+    /// A repository reachable over ssh (`ssh://[user@]host[:port]/path`), fetched with scp
+    Ssh { host: String, port: Option<u16>, path: String },
+    /// A repository served over http(s), fetched with curl; the URL is either the repository
+    /// root (serving `.oci/index.db`, `.oci/index.log` or `.oci/index.db.enc`) or the index file itself
+    Http(String),
+    /// Objects in an S3-compatible bucket (`s3://bucket/prefix`), indexed from a bucket listing
+    S3(S3Location),
+}
+
+impl Source {
+    /// Parse a source argument; anything that is not an ssh://, http(s):// or s3:// URL is a local path
+    pub fn parse(arg: &str, current_dir: &Path) -> Result<Self> {
+        if let Some(rest) = arg.strip_prefix("ssh://") {
+            let (authority, path) = rest.split_once('/')
+                .ok_or_else(|| anyhow::anyhow!("Invalid ssh source (expected ssh://host/path): {}", arg))?;
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => {
+                    let port = port.parse::<u16>()
+                        .context(format!("Invalid port in ssh source: {}", arg))?;
+                    (host, Some(port))
+                }
+                None => (authority, None),
+            };
+            if host.is_empty() || path.is_empty() {
+                bail!("Invalid ssh source (expected ssh://host/path): {}", arg);
+            }
+            // scp would take such a host for an option (e.g. -oProxyCommand=...)
+            if host.starts_with('-') {
+                bail!("Invalid ssh source (the host cannot start with '-'): {}", arg);
+            }
+            // ssh://host/~/archive is relative to the remote home directory
+            let path = path.trim_end_matches('/');
+            let path = match path.strip_prefix("~/") {
+                Some(home_relative) => home_relative.to_string(),
+                None => format!("/{}", path),
+            };
+            return Ok(Source::Ssh { host: host.to_string(), port, path });
+        }
+
+        if arg.starts_with("s3://") {
+            return Ok(Source::S3(S3Location::parse(arg)?));
+        }
+
+        if arg.starts_with("http://") || arg.starts_with("https://") {
+            return Ok(Source::Http(arg.trim_end_matches('/').to_string()));
+        }
+
+        let path = Path::new(arg);
+        let path = if path.is_absolute() { path.to_path_buf() } else { current_dir.join(path) };
+        Ok(if path.is_file() { Source::File(path) } else { Source::Local(path) })
+    }
+
+    /// Make the source's index available locally
+    /// Remote indexes are copied into a temporary directory that is removed when the result is dropped.
+    pub fn open(&self) -> Result<SourceRepo> {
+        match self {
+            Source::Local(path) => {
+                if !path.exists() {
+                    bail!("Source path does not exist: {}", path.display());
+                }
+                Ok(SourceRepo { root: path.clone(), _temp_dir: None })
+            }
+            Source::File(path) => {
+                // Copied or unpacked for the duration of the command, so opening it (which may
+                // migrate the schema) never writes to the original
+                let repo = SourceRepo::temporary()?;
+                match index_file_backend(path) {
+                    Some(backend) => copy_index_file(path, backend, &repo.root.join(OCI_DIR))?,
+                    None => bundle::extract(path, &repo.root)?,
+                }
+                Ok(repo)
+            }
+            Source::S3(location) => Ok(SourceRepo { root: s3::refresh_index(location)?, _temp_dir: None }),
+            Source::Ssh { .. } | Source::Http(_) => {
+                let repo = SourceRepo::temporary()?;
+                let oci_dir = repo.root.join(OCI_DIR);
+                fs::create_dir_all(&oci_dir)
+                    .context("Failed to create temporary directory for remote index")?;
+
+                // Try the default backend first, then the log backend and an encrypted index
+                let sqlite = Backend::Sqlite.file_name();
+                let log = Backend::Log.file_name();
+                let encrypted = Backend::Encrypted.file_name();
+                self.fetch(sqlite, &oci_dir.join(sqlite))
+                    .or_else(|e| {
+                        let _ = fs::remove_file(oci_dir.join(sqlite));
+                        self.fetch(log, &oci_dir.join(log)).map_err(|_| e)
+                    })
+                    .or_else(|e| {
+                        let _ = fs::remove_file(oci_dir.join(log));
+                        self.fetch(encrypted, &oci_dir.join(encrypted)).map_err(|_| e)
+                    })
+                    .context(format!("Failed to fetch remote index from {}", self))?;
+                // The ignore file is optional; without it no source ignore patterns apply. A
+                // versioned .ocignore at the source root takes precedence over .oci/ignore
+                let root_ignore = format!("../{}", ignore::ROOT_IGNORE_FILE);
+                let _ = self.fetch(&root_ignore, &oci_dir.join(IGNORE_FILE))
+                    .or_else(|_| self.fetch(IGNORE_FILE, &oci_dir.join(IGNORE_FILE)));
+                // So are the marker and signature of an exported index
+                let _ = self.fetch(SIGNATURE_FILE, &oci_dir.join(SIGNATURE_FILE));
+                let _ = self.fetch(EXPORT_FILE, &oci_dir.join(EXPORT_FILE));
+                Ok(repo)
+            }
+        }
+    }
+
+    /// Copy a file from the remote `.oci` directory to a local path
+    fn fetch(&self, name: &str, dest: &Path) -> Result<()> {
+        let mut command = match self {
+            Source::Ssh { host, port, path } => {
+                let mut command = Command::new("scp");
+                command.arg("-q").arg("-B");
+                if let Some(port) = port {
+                    command.arg("-P").arg(port.to_string());
+                }
+                command.arg("--").arg(format!("{}:{}/{}/{}", host, path, OCI_DIR, name)).arg(dest);
+                command
+            }
+            Source::Http(url) => {
+                let mut command = Command::new("curl");
+                command.arg("-fsSL").arg("-o").arg(dest).arg(http_file_url(url, name)?);
+                command
+            }
+            Source::Local(_) | Source::File(_) | Source::S3(_) => return Ok(()),
+        };
+
+        let output = command.output()
+            .context(format!("Failed to run {:?}", command.get_program()))?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+}
+
+/// URL of a file of the `.oci` directory of an http(s) source. A URL naming an index file
+/// (`.db`, `.log` or `.enc`) stands for that index, with the other files next to it.
+fn http_file_url(url: &str, name: &str) -> Result<String> {
+    let backend = |file: &str| index_file_backend(Path::new(file));
+    Ok(match url.rsplit_once('/') {
+        Some((dir, file)) if backend(file).is_some() => {
+            if backend(name).is_none() {
+                format!("{}/{}", dir, name)
+            } else if backend(file) == backend(name) {
+                url.to_string()
+            } else {
+                bail!("{} is not an {} file", url, name);
+            }
+        }
+        _ => format!("{}/{}/{}", url, OCI_DIR, name),
+    })
+}
+
+/// Backend of a file given as a source by its extension, or None for a bundle
+fn index_file_backend(path: &Path) -> Option<Backend> {
+    match path.extension()?.to_str()? {
+        "db" => Some(Backend::Sqlite),
+        "log" => Some(Backend::Log),
+        "enc" => Some(Backend::Encrypted),
+        _ => None,
+    }
+}
+
+/// Copy an index file into `oci_dir` under its backend's name, with the files that sit next to it
+/// in a `.oci` directory: ignore patterns (a `.ocignore` one level up first), the export marker
+/// and signature, and an SQLite write-ahead log not yet folded into the database
+fn copy_index_file(path: &Path, backend: Backend, oci_dir: &Path) -> Result<()> {
+    fs::create_dir_all(oci_dir).context("Failed to create temporary directory for the source index")?;
+    fs::copy(path, oci_dir.join(backend.file_name())).context(format!("Failed to copy {}", path.display()))?;
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let wal = format!("{}-wal", path.file_name().unwrap_or_default().to_string_lossy());
+    let siblings = [
+        (dir.parent().map(|parent| parent.join(ignore::ROOT_IGNORE_FILE)), IGNORE_FILE),
+        (Some(dir.join(IGNORE_FILE)), IGNORE_FILE),
+        (Some(dir.join(EXPORT_FILE)), EXPORT_FILE),
+        (Some(dir.join(SIGNATURE_FILE)), SIGNATURE_FILE),
+        (Some(dir.join(&wal)), &format!("{}-wal", backend.file_name())),
+    ];
+    for (sibling, name) in siblings {
+        let Some(sibling) = sibling.filter(|sibling| sibling.is_file()) else { continue };
+        if !oci_dir.join(name).exists() {
+            fs::copy(&sibling, oci_dir.join(name)).context(format!("Failed to copy {}", sibling.display()))?;
+        }
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Local(path) | Source::File(path) => write!(f, "{}", path.display()),
+            Source::Ssh { host, port, path } => {
+                write!(f, "ssh://{}", host)?;
+                if let Some(port) = port {
+                    write!(f, ":{}", port)?;
+                }
+                match path.strip_prefix('/') {
+                    Some(absolute) => write!(f, "/{}", absolute),
+                    None => write!(f, "/~/{}", path),
+                }
+            }
+            Source::Http(url) => write!(f, "{}", url),
+            Source::S3(location) => write!(f, "{}", location),
+        }
+    }
+}
+
+/// A source repository whose `.oci` directory is readable locally
+pub struct SourceRepo {
+    /// Directory containing the source's `.oci` directory
+    pub root: PathBuf,
+    /// Holds a fetched or unpacked index, and is removed with it when the source is dropped
+    _temp_dir: Option<TempDir>,
+}
+
+impl SourceRepo {
+    /// A source in a fresh temporary directory, created with a name no other user can predict
+    fn temporary() -> Result<Self> {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("oci-source-")
+            .tempdir()
+            .context("Failed to create temporary directory for the source index")?;
+        Ok(SourceRepo { root: temp_dir.path().to_path_buf(), _temp_dir: Some(temp_dir) })
+    }
+
+    /// Whether the source is an exported index, a snapshot with no files to scan for changes
+    pub fn is_export(&self) -> bool {
+        self.root.join(OCI_DIR).join(EXPORT_FILE).exists()
+    }
+
+    /// Check that the source's index and ignore patterns carry a valid signature by `key`
+    pub fn verify(&self, key: &VerifyingKey) -> Result<()> {
+        let names = [
+            Backend::Sqlite.file_name(),
+            Backend::Log.file_name(),
+            Backend::Encrypted.file_name(),
+            IGNORE_FILE,
+            EXPORT_FILE,
+        ];
+        signing::verify(&self.root.join(OCI_DIR), &names, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources() {
+        let cwd = Path::new("/home/me");
+        assert_eq!(
+            Source::parse("ssh://nas/volume1/archive/", cwd).unwrap(),
+            Source::Ssh { host: "nas".to_string(), port: None, path: "/volume1/archive".to_string() }
+        );
+        assert_eq!(
+            Source::parse("ssh://me@nas:2222/~/archive", cwd).unwrap(),
+            Source::Ssh { host: "me@nas".to_string(), port: Some(2222), path: "archive".to_string() }
+        );
+        assert_eq!(
+            Source::parse("https://nas.local/archive/", cwd).unwrap(),
+            Source::Http("https://nas.local/archive".to_string())
+        );
+        assert_eq!(Source::parse("../archive", cwd).unwrap(), Source::Local(PathBuf::from("/home/me/../archive")));
+        assert!(matches!(Source::parse("s3://archive/photos", cwd).unwrap(), Source::S3(_)));
+        assert!(Source::parse("ssh://nas", cwd).is_err());
+        assert!(Source::parse("ssh://nas:port/x", cwd).is_err());
+        assert!(Source::parse("ssh://-oProxyCommand=touch pwned/x", cwd).is_err());
+        assert_eq!(Source::parse("/mnt/archive", cwd).unwrap(), Source::Local(PathBuf::from("/mnt/archive")));
+        assert_eq!(index_file_backend(Path::new("/mnt/drive/.oci/index.db")), Some(Backend::Sqlite));
+        assert_eq!(index_file_backend(Path::new("drive-index.log")), Some(Backend::Log));
+        assert_eq!(index_file_backend(Path::new("index.db.enc")), Some(Backend::Encrypted));
+        assert_eq!(index_file_backend(Path::new("archive.ocib")), None);
+    }
+
+    #[test]
+    fn test_http_file_urls() {
+        assert_eq!(http_file_url("https://nas/archive", "index.db").unwrap(), "https://nas/archive/.oci/index.db");
+        let encrypted = "https://nas/drive/index.db.enc";
+        assert!(http_file_url(encrypted, "index.db").is_err());
+        assert!(http_file_url(encrypted, "index.log").is_err());
+        assert_eq!(http_file_url(encrypted, "index.db.enc").unwrap(), encrypted);
+        assert_eq!(http_file_url(encrypted, "ignore").unwrap(), "https://nas/drive/ignore");
+        assert_eq!(http_file_url("https://nas/drive/index.log", "index.log").unwrap(), "https://nas/drive/index.log");
+    }
+}
@@ -0,0 +1,661 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::digest::{self, Algorithm};
+use crate::index::{dir_prefix, parent_dir, ArchiveMember, FileEntry, Tombstone, OCI_DIR};
+use crate::audio::AudioFingerprint;
+use crate::media::MediaInfo;
+use crate::lock;
+use crate::crypto::{self, IndexKey};
+use crate::migrations;
+use crate::oplog::Operation;
+use crate::store::{Backend, Store};
+use crate::tiering::StorageClass;
+
+/// Columns selected for a FileEntry, in the order expected by `row_to_entry`
+const ENTRY_COLUMNS: &str = "path, num_bytes, modified, sha256, xattrs, quick_hash, mime, allocated, digests";
+
+/// Map a row selected with ENTRY_COLUMNS to a FileEntry
+fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<FileEntry> {
+    Ok(FileEntry {
+        path: row.get(0)?,
+        num_bytes: row.get(1)?,
+        modified: row.get(2)?,
+        sha256: row.get(3)?,
+        xattrs: row.get(4)?,
+        quick_hash: row.get(5)?,
+        mime: row.get(6)?,
+        allocated: row.get(7)?,
+        digests: row.get(8)?,
+    })
+}
+
+/// Map an archive_members row (archive, member, num_bytes, sha256) to an ArchiveMember
+fn row_to_member(row: &rusqlite::Row) -> rusqlite::Result<ArchiveMember> {
+    Ok(ArchiveMember {
+        archive: row.get(0)?,
+        member: row.get(1)?,
+        num_bytes: row.get(2)?,
+        sha256: row.get(3)?,
+    })
+}
+
+/// Map an oplog row (timestamp, os_user, identity, command, summary) to an Operation
+fn row_to_operation(row: &rusqlite::Row) -> rusqlite::Result<Operation> {
+    Ok(Operation {
+        timestamp: row.get(0)?,
+        os_user: row.get(1)?,
+        identity: row.get(2)?,
+        command: row.get(3)?,
+        summary: row.get(4)?,
+    })
+}
+
+/// Connection settings for the index database (`sqlite_*` config keys)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqliteTuning {
+    /// Journal mode; `auto` uses WAL except on network filesystems, where WAL cannot work
+    pub journal_mode: String,
+    /// How often SQLite syncs to disk (`off`, `normal`, `full` or `extra`)
+    pub synchronous: String,
+    /// Page cache size in bytes
+    pub cache_size: u64,
+    /// Where temporary tables and indices are kept (`default`, `file` or `memory`)
+    pub temp_store: String,
+}
+
+impl Default for SqliteTuning {
+    fn default() -> Self {
+        SqliteTuning {
+            journal_mode: "auto".to_string(),
+            // With WAL, `normal` only risks the last transactions on power loss, never corruption
+            synchronous: "normal".to_string(),
+            cache_size: 64 * 1024 * 1024,
+            temp_store: "memory".to_string(),
+        }
+    }
+}
+
+impl SqliteTuning {
+    pub const JOURNAL_MODES: &'static [&'static str] = &["auto", "wal", "delete", "truncate", "persist"];
+    pub const SYNCHRONOUS: &'static [&'static str] = &["off", "normal", "full", "extra"];
+    pub const TEMP_STORES: &'static [&'static str] = &["default", "file", "memory"];
+
+    /// Journal mode to use for a database in the given .oci directory
+    fn resolved_journal_mode(&self, oci_dir: &Path) -> &str {
+        match self.journal_mode.as_str() {
+            // WAL relies on shared memory, which network filesystems do not provide
+            "auto" if lock::network_filesystem(oci_dir).is_some() => "delete",
+            "auto" => "wal",
+            mode => mode,
+        }
+    }
+
+    /// Set the pragmas on a newly opened connection
+    fn apply(&self, conn: &Connection, oci_dir: &Path) -> Result<()> {
+        // journal_mode reports the resulting mode, so it cannot be set with pragma_update
+        conn.pragma_update_and_check(None, "journal_mode", self.resolved_journal_mode(oci_dir), |_| Ok(()))
+            .context("Failed to set journal mode")?;
+        conn.pragma_update(None, "synchronous", &self.synchronous)
+            .context("Failed to set synchronous mode")?;
+        // A negative cache size is in KiB rather than pages
+        conn.pragma_update(None, "cache_size", -((self.cache_size / 1024) as i64))
+            .context("Failed to set cache size")?;
+        conn.pragma_update(None, "temp_store", &self.temp_store)
+            .context("Failed to set temp store")?;
+        Ok(())
+    }
+}
+
+/// The default index store: a SQLite database in `.oci/index.db`
+pub struct SqliteStore {
+    conn: Connection,
+    repo_root: Option<PathBuf>,
+    /// Key of an encrypted index, which lives in memory and is written back whole by `save`
+    key: Option<IndexKey>,
+}
+
+impl SqliteStore {
+    /// Create an empty in-memory store
+    pub fn in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()
+            .context("Failed to create in-memory database")?;
+        migrations::migrate(&conn, None)?;
+        Ok(SqliteStore { conn, repo_root: None, key: None })
+    }
+
+    /// Decrypt a repository's encrypted database (`.oci/index.db.enc`) into memory,
+    /// or start a new one if the repository has none yet
+    pub fn open_encrypted(repo_root: &Path) -> Result<Self> {
+        let path = Backend::Encrypted.index_path(repo_root);
+        let mut conn = Connection::open_in_memory()
+            .context("Failed to create in-memory database")?;
+        let key = if path.exists() {
+            let data = std::fs::read(&path)
+                .context(format!("Failed to read encrypted index: {}", path.display()))?;
+            let (key, plaintext) = IndexKey::decrypt(&data, &crypto::secret(repo_root, false)?)?;
+            deserialize(&mut conn, &plaintext)?;
+            // The upgrade is only written by `save`, so the backup is a copy of the encrypted file
+            let version = migrations::schema_version(&conn)?;
+            if version < migrations::latest_version() {
+                let backup = path.with_file_name(format!("{}.v{}.bak", Backend::Encrypted.file_name(), version));
+                std::fs::copy(&path, &backup)
+                    .context(format!("Failed to back up index to {}", backup.display()))?;
+            }
+            key
+        } else {
+            IndexKey::new(&crypto::secret(repo_root, true)?)?
+        };
+        migrations::migrate(&conn, None)?;
+        Ok(SqliteStore { conn, repo_root: None, key: Some(key) })
+    }
+
+    /// Open (or create) the database of a repository
+    pub fn open(repo_root: &Path) -> Result<Self> {
+        let oci_dir = repo_root.join(OCI_DIR);
+        
+        // Create directory if it doesn't exist
+        std::fs::create_dir_all(&oci_dir)
+            .context("Failed to create .oci directory")?;
+        
+        let conn = Connection::open(Backend::Sqlite.index_path(repo_root))
+            .context("Failed to open index database")?;
+        
+        let tuning = Config::load_if_present(repo_root)?
+            .map(|config| config.sqlite)
+            .unwrap_or_default();
+        tuning.apply(&conn, &oci_dir)?;
+        
+        // Create the schema of a new database, or upgrade that of an older one
+        migrations::migrate(&conn, Some(&Backend::Sqlite.index_path(repo_root)))?;
+        
+        Ok(SqliteStore { 
+            conn, 
+            repo_root: Some(repo_root.to_path_buf()),
+            key: None,
+        })
+    }
+}
+
+impl SqliteStore {
+    /// Run a query selecting ENTRY_COLUMNS
+    fn query_entries(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<FileEntry>> {
+        let mut stmt = self.conn.prepare(sql).context("Failed to prepare statement")?;
+        let entries = stmt.query_map(params, row_to_entry)
+            .context("Failed to query files")?;
+        
+        let mut result = Vec::new();
+        for entry in entries {
+            result.push(entry.context("Failed to read entry")?);
+        }
+        
+        Ok(result)
+    }
+}
+
+impl Store for SqliteStore {
+    fn upsert(&mut self, entry: FileEntry) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO files (path, dir, num_bytes, modified, sha256, xattrs, quick_hash, mime, allocated, digests)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![entry.path, parent_dir(&entry.path), entry.num_bytes, entry.modified, entry.sha256,
+                entry.xattrs, entry.quick_hash, entry.mime, entry.allocated, entry.digests],
+        ).context("Failed to upsert file entry")?;
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM files WHERE path = ?1",
+            params![path],
+        ).context("Failed to remove file entry")?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.conn.execute("DELETE FROM files", [])
+            .context("Failed to clear index")?;
+        self.conn.execute("DELETE FROM archive_members", [])
+            .context("Failed to clear archive members")?;
+        self.conn.execute("DELETE FROM media", [])
+            .context("Failed to clear media metadata")?;
+        self.conn.execute("DELETE FROM audio", [])
+            .context("Failed to clear audio fingerprints")?;
+        Ok(())
+    }
+
+    fn get(&self, path: &str) -> Result<Option<FileEntry>> {
+        let result = self.conn.query_row(
+            &format!("SELECT {} FROM files WHERE path = ?1", ENTRY_COLUMNS),
+            params![path],
+            row_to_entry,
+        ).optional().context("Failed to get file entry")?;
+        
+        Ok(result)
+    }
+
+    fn entries(&self) -> Result<Vec<FileEntry>> {
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT {} FROM files ORDER BY path", ENTRY_COLUMNS)
+        ).context("Failed to prepare statement")?;
+        
+        let entries = stmt.query_map([], row_to_entry)
+            .context("Failed to query files")?;
+        
+        let mut result = Vec::new();
+        for entry in entries {
+            result.push(entry.context("Failed to read entry")?);
+        }
+        
+        Ok(result)
+    }
+
+    fn dir_entries(&self, dir: &str) -> Result<Vec<FileEntry>> {
+        self.query_entries(&format!("SELECT {} FROM files WHERE dir = ?1 ORDER BY path", ENTRY_COLUMNS), params![dir])
+    }
+
+    fn dir_entries_recursive(&self, dir: &str) -> Result<Vec<FileEntry>> {
+        if dir.is_empty() {
+            return self.entries();
+        }
+        // A range on the primary key: paths starting with "dir/" sort between "dir/" and "dir0"
+        let prefix = dir_prefix(dir);
+        let end = format!("{}0", dir);
+        self.query_entries(
+            &format!("SELECT {} FROM files WHERE path >= ?1 AND path < ?2 ORDER BY path", ENTRY_COLUMNS),
+            params![prefix, end],
+        )
+    }
+
+    fn visit_dir_entries(&self, dir: &str, recursive: bool, visit: &mut dyn FnMut(FileEntry) -> Result<()>) -> Result<()> {
+        let (condition, args) = match (recursive, dir.is_empty()) {
+            (false, _) => ("WHERE dir = ?1", vec![dir.to_string()]),
+            (true, true) => ("", Vec::new()),
+            (true, false) => ("WHERE path >= ?1 AND path < ?2", vec![dir_prefix(dir), format!("{}0", dir)]),
+        };
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT {} FROM files {} ORDER BY path", ENTRY_COLUMNS, condition)
+        ).context("Failed to prepare statement")?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(args))
+            .context("Failed to query files")?;
+        while let Some(row) = rows.next().context("Failed to read entry")? {
+            visit(row_to_entry(row).context("Failed to read entry")?)?;
+        }
+        Ok(())
+    }
+
+    fn find_by_digest(&self, algorithm: Algorithm, hash: &str) -> Result<Vec<FileEntry>> {
+        // The pattern narrows the scan; the exact comparison keeps `%` or `_` in `hash` from matching
+        let mut entries = self.query_entries(
+            &format!("SELECT {} FROM files WHERE digests LIKE ?1 ORDER BY path", ENTRY_COLUMNS),
+            params![format!("%{}={}%", algorithm.name(), hash)],
+        )?;
+        entries.retain(|entry| digest::lookup(entry.digests.as_deref(), algorithm) == Some(hash));
+        Ok(entries)
+    }
+
+    fn largest_entries(&self, limit: usize) -> Result<Vec<FileEntry>> {
+        self.query_entries(
+            &format!("SELECT {} FROM files ORDER BY num_bytes DESC, path LIMIT ?1", ENTRY_COLUMNS),
+            params![limit as i64],
+        )
+    }
+
+    fn largest_duplicate_groups(&self, limit: usize) -> Result<Vec<(String, u64, usize)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sha256, MAX(num_bytes), COUNT(*) FROM files WHERE sha256 != ''
+             GROUP BY sha256 HAVING COUNT(*) > 1
+             ORDER BY MAX(num_bytes) * (COUNT(*) - 1) DESC, sha256 LIMIT ?1"
+        ).context("Failed to prepare statement")?;
+        let groups = stmt.query_map(params![limit as i64], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        }).context("Failed to query duplicate groups")?;
+        groups.collect::<rusqlite::Result<_>>().context("Failed to read duplicate group")
+    }
+
+    fn find_by_hash(&self, hash: &str) -> Result<Vec<FileEntry>> {
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT {} FROM files WHERE sha256 = ?1 ORDER BY path", ENTRY_COLUMNS)
+        ).context("Failed to prepare statement")?;
+        
+        let entries = stmt.query_map(params![hash], row_to_entry)
+            .context("Failed to query files by hash")?;
+        
+        let mut result = Vec::new();
+        for entry in entries {
+            result.push(entry.context("Failed to read entry")?);
+        }
+        
+        Ok(result)
+    }
+
+    fn set_archive_members(&mut self, archive: &str, members: &[ArchiveMember]) -> Result<()> {
+        self.conn.execute("DELETE FROM archive_members WHERE archive = ?1", params![archive])
+            .context("Failed to remove archive members")?;
+        for member in members {
+            self.conn.execute(
+                "INSERT OR REPLACE INTO archive_members (archive, member, num_bytes, sha256) VALUES (?1, ?2, ?3, ?4)",
+                params![archive, member.member, member.num_bytes, member.sha256],
+            ).context("Failed to record archive member")?;
+        }
+        Ok(())
+    }
+
+    fn find_members_by_hash(&self, hash: &str) -> Result<Vec<ArchiveMember>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT archive, member, num_bytes, sha256 FROM archive_members WHERE sha256 = ?1 ORDER BY archive, member"
+        ).context("Failed to prepare statement")?;
+        let rows = stmt.query_map(params![hash], row_to_member)
+            .context("Failed to query archive members")?;
+        rows.map(|row| row.context("Failed to read archive member")).collect()
+    }
+
+    fn archive_members(&self) -> Result<Vec<ArchiveMember>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT archive, member, num_bytes, sha256 FROM archive_members ORDER BY archive, member"
+        ).context("Failed to prepare statement")?;
+        let rows = stmt.query_map([], row_to_member)
+            .context("Failed to query archive members")?;
+        rows.map(|row| row.context("Failed to read archive member")).collect()
+    }
+
+    fn set_media(&mut self, info: &MediaInfo) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO media (path, taken, camera, width, height) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![info.path, info.taken, info.camera, info.width, info.height],
+        ).context("Failed to record media metadata")?;
+        Ok(())
+    }
+
+    fn clear_media(&mut self, path: &str) -> Result<()> {
+        self.conn.execute("DELETE FROM media WHERE path = ?1", params![path])
+            .context("Failed to remove media metadata")?;
+        Ok(())
+    }
+
+    fn media(&self) -> Result<Vec<MediaInfo>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, taken, camera, width, height FROM media ORDER BY path"
+        ).context("Failed to prepare statement")?;
+        let rows = stmt.query_map([], |row| Ok(MediaInfo {
+            path: row.get(0)?,
+            taken: row.get(1)?,
+            camera: row.get(2)?,
+            width: row.get(3)?,
+            height: row.get(4)?,
+        })).context("Failed to query media metadata")?;
+        rows.map(|row| row.context("Failed to read media metadata")).collect()
+    }
+
+    fn set_audio(&mut self, print: &AudioFingerprint) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO audio (path, duration_ms, fingerprint) VALUES (?1, ?2, ?3)",
+            params![print.path, print.duration_ms, print.fingerprint],
+        ).context("Failed to record audio fingerprint")?;
+        Ok(())
+    }
+
+    fn clear_audio(&mut self, path: &str) -> Result<()> {
+        self.conn.execute("DELETE FROM audio WHERE path = ?1", params![path])
+            .context("Failed to remove audio fingerprint")?;
+        Ok(())
+    }
+
+    fn audio_fingerprints(&self) -> Result<Vec<AudioFingerprint>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, duration_ms, fingerprint FROM audio ORDER BY path"
+        ).context("Failed to prepare statement")?;
+        let rows = stmt.query_map([], |row| Ok(AudioFingerprint {
+            path: row.get(0)?,
+            duration_ms: row.get(1)?,
+            fingerprint: row.get(2)?,
+        })).context("Failed to query audio fingerprints")?;
+        rows.map(|row| row.context("Failed to read audio fingerprint")).collect()
+    }
+
+    fn set_storage_class(&mut self, path: &str, class: StorageClass) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO storage_classes (path, class) VALUES (?1, ?2)",
+            params![path, class.to_string()],
+        ).context("Failed to set storage class")?;
+        Ok(())
+    }
+
+    fn clear_storage_class(&mut self, path: &str) -> Result<bool> {
+        let removed = self.conn.execute(
+            "DELETE FROM storage_classes WHERE path = ?1",
+            params![path],
+        ).context("Failed to clear storage class")?;
+        Ok(removed > 0)
+    }
+
+    fn storage_class_tags(&self) -> Result<Vec<(String, StorageClass)>> {
+        let mut stmt = self.conn.prepare("SELECT path, class FROM storage_classes ORDER BY path")
+            .context("Failed to prepare statement")?;
+        
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .context("Failed to query storage classes")?;
+        
+        let mut result = Vec::new();
+        for row in rows {
+            let (path, class) = row.context("Failed to read storage class")?;
+            result.push((path, class.parse()?));
+        }
+        
+        Ok(result)
+    }
+
+    fn add_tag(&mut self, path: &str, tag: &str) -> Result<bool> {
+        let added = self.conn.execute(
+            "INSERT OR IGNORE INTO tags (path, tag) VALUES (?1, ?2)",
+            params![path, tag],
+        ).context("Failed to add tag")?;
+        Ok(added > 0)
+    }
+
+    fn remove_tag(&mut self, path: &str, tag: &str) -> Result<bool> {
+        let removed = self.conn.execute(
+            "DELETE FROM tags WHERE path = ?1 AND tag = ?2",
+            params![path, tag],
+        ).context("Failed to remove tag")?;
+        Ok(removed > 0)
+    }
+
+    fn tags(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare("SELECT path, tag FROM tags ORDER BY path, tag")
+            .context("Failed to prepare statement")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("Failed to query tags")?;
+        rows.map(|row| row.context("Failed to read tag")).collect()
+    }
+
+    fn add_tombstone(&mut self, tombstone: &Tombstone) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO tombstones (path, num_bytes, sha256, removed, reason) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![tombstone.path, tombstone.num_bytes, tombstone.sha256, tombstone.removed, tombstone.reason],
+        ).context("Failed to record tombstone")?;
+        Ok(())
+    }
+
+    fn tombstones(&self) -> Result<Vec<Tombstone>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, num_bytes, sha256, removed, reason FROM tombstones ORDER BY id"
+        ).context("Failed to prepare statement")?;
+        
+        let rows = stmt.query_map([], |row| {
+            Ok(Tombstone {
+                path: row.get(0)?,
+                num_bytes: row.get(1)?,
+                sha256: row.get(2)?,
+                removed: row.get(3)?,
+                reason: row.get(4)?,
+            })
+        }).context("Failed to query tombstones")?;
+        
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row.context("Failed to read tombstone")?);
+        }
+        
+        Ok(result)
+    }
+
+    fn log_operation(&mut self, op: &Operation) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO oplog (timestamp, os_user, identity, command, summary)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![op.timestamp, op.os_user, op.identity, op.command, op.summary],
+        ).context("Failed to record operation")?;
+        Ok(())
+    }
+
+    fn last_operation(&self, command: &str) -> Result<Option<Operation>> {
+        self.conn.query_row(
+            "SELECT timestamp, os_user, identity, command, summary FROM oplog
+             WHERE command = ?1 ORDER BY id DESC LIMIT 1",
+            params![command],
+            row_to_operation,
+        ).optional().context("Failed to query op log")
+    }
+
+    fn operations(&self, limit: Option<usize>) -> Result<Vec<Operation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT timestamp, os_user, identity, command, summary FROM oplog ORDER BY id DESC LIMIT ?1"
+        ).context("Failed to prepare statement")?;
+        
+        let limit = limit.map(|n| n as i64).unwrap_or(-1);
+        let rows = stmt.query_map(params![limit], row_to_operation)
+            .context("Failed to query op log")?;
+        
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row.context("Failed to read operation")?);
+        }
+        
+        Ok(result)
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        self.conn.execute_batch("BEGIN").context("Failed to start transaction")
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.conn.execute_batch("COMMIT").context("Failed to commit transaction")
+    }
+
+    fn checkpoint(&self) -> Result<()> {
+        // A no-op unless the database is in WAL mode
+        self.conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))
+            .context("Failed to checkpoint index database")
+    }
+
+    fn save(&self, repo_root: &Path) -> Result<()> {
+        // If this is a disk-based database (loaded from disk), it's already saved
+        if self.repo_root.is_some() {
+            return Ok(());
+        }
+        
+        // An encrypted index is written whole, through a temporary file so a failed save keeps the old one
+        if let Some(key) = &self.key {
+            let data = key.encrypt(&self.conn.serialize(rusqlite::DatabaseName::Main)
+                .context("Failed to serialize index database")?)?;
+            let path = Backend::Encrypted.index_path(repo_root);
+            let temp_path = path.with_extension("enc.tmp");
+            std::fs::write(&temp_path, data)
+                .context(format!("Failed to write encrypted index: {}", temp_path.display()))?;
+            return std::fs::rename(&temp_path, &path)
+                .context(format!("Failed to write encrypted index: {}", path.display()));
+        }
+        
+        // For in-memory databases (e.g., tests or new index), backup to disk
+        let oci_dir = repo_root.join(OCI_DIR);
+        std::fs::create_dir_all(&oci_dir)
+            .context("Failed to create .oci directory")?;
+        
+        let mut disk_conn = Connection::open(Backend::Sqlite.index_path(repo_root))
+            .context("Failed to open destination database")?;
+        
+        let backup = rusqlite::backup::Backup::new(&self.conn, &mut disk_conn)
+            .context("Failed to create backup")?;
+        
+        backup.run_to_completion(5, std::time::Duration::from_millis(250), None)
+            .context("Failed to backup database")?;
+        
+        Ok(())
+    }
+}
+
+/// Load a serialized database into a connection
+fn deserialize(conn: &mut Connection, bytes: &[u8]) -> Result<()> {
+    // SQLite takes ownership of the buffer, so it must come from sqlite3_malloc
+    let data = unsafe {
+        let ptr = rusqlite::ffi::sqlite3_malloc64(bytes.len() as u64) as *mut u8;
+        let ptr = std::ptr::NonNull::new(ptr).context("Failed to allocate memory for the index")?;
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.as_ptr(), bytes.len());
+        rusqlite::serialize::OwnedData::from_raw_nonnull(ptr, bytes.len())
+    };
+    conn.deserialize(rusqlite::DatabaseName::Main, data, false)
+        .context("Failed to load decrypted index")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_open_applies_tuning() {
+        let dir = TempDir::new().unwrap();
+        let store = SqliteStore::open(dir.path()).unwrap();
+        let journal_mode: String = store.conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        let synchronous: i64 = store.conn.query_row("PRAGMA synchronous", [], |row| row.get(0)).unwrap();
+        let cache_size: i64 = store.conn.query_row("PRAGMA cache_size", [], |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "wal");
+        assert_eq!(synchronous, 1);
+        assert_eq!(cache_size, -65536);
+        drop(store);
+
+        let mut config = Config::new();
+        config.sqlite.journal_mode = "delete".to_string();
+        config.sqlite.synchronous = "full".to_string();
+        config.save(dir.path()).unwrap();
+        let store = SqliteStore::open(dir.path()).unwrap();
+        let journal_mode: String = store.conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        let synchronous: i64 = store.conn.query_row("PRAGMA synchronous", [], |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "delete");
+        assert_eq!(synchronous, 2);
+    }
+
+    #[test]
+    fn test_dir_queries_and_dir_migration() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join(OCI_DIR)).unwrap();
+        {
+            // A database from before the dir column existed
+            let conn = Connection::open(Backend::Sqlite.index_path(dir.path())).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE files (path TEXT PRIMARY KEY, num_bytes INTEGER NOT NULL, modified INTEGER NOT NULL, sha256 TEXT NOT NULL);
+                 INSERT INTO files VALUES ('top.txt', 1, 1, ''), ('photos/a.jpg', 1, 1, ''), ('photos/2020/b.jpg', 1, 1, '');",
+            ).unwrap();
+        }
+
+        let mut store = SqliteStore::open(dir.path()).unwrap();
+        for path in ["photos-old/c.jpg", "photos0/d.jpg", "photos/e.jpg"] {
+            store.upsert(FileEntry { path: path.to_string(), ..Default::default() }).unwrap();
+        }
+        let paths = |entries: Vec<FileEntry>| {
+            let mut paths: Vec<String> = entries.into_iter().map(|e| e.path).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(paths(store.dir_entries("").unwrap()), vec!["top.txt"]);
+        assert_eq!(paths(store.dir_entries("photos").unwrap()), vec!["photos/a.jpg", "photos/e.jpg"]);
+        assert_eq!(
+            paths(store.dir_entries_recursive("photos").unwrap()),
+            vec!["photos/2020/b.jpg", "photos/a.jpg", "photos/e.jpg"]
+        );
+        assert_eq!(store.dir_entries_recursive("").unwrap().len(), 6);
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+
+use crate::digest::{self, Algorithm};
+use crate::index::{ArchiveMember, FileEntry, Tombstone, OCI_DIR};
+use crate::audio::AudioFingerprint;
+use crate::media::MediaInfo;
+use crate::oplog::Operation;
+use crate::tiering::StorageClass;
+
+/// Storage for index entries, their sidecar records (archive members, media metadata, audio
+/// fingerprints), storage class tags, tombstones and the op log
+/// Paths passed to storage class methods are already normalized by `Index`. Stores are `Send` so
+/// the daemon can answer queries from its connection threads. Lists of entries come in path
+/// order, so every listing built from them is the same from run to run.
+pub trait Store: Send {
+    /// Add or update a file entry
+    fn upsert(&mut self, entry: FileEntry) -> Result<()>;
+
+    /// Remove a file entry
+    fn remove(&mut self, path: &str) -> Result<()>;
+
+    /// Remove all file entries and their sidecar records (tags and the op log are kept)
+    fn clear(&mut self) -> Result<()>;
+
+    /// Get a file entry
+    fn get(&self, path: &str) -> Result<Option<FileEntry>>;
+
+    /// Get every file entry, in path order
+    fn entries(&self) -> Result<Vec<FileEntry>>;
+
+    /// Get the entries directly in a directory ("" for the top level)
+    fn dir_entries(&self, dir: &str) -> Result<Vec<FileEntry>>;
+
+    /// Get the entries anywhere under a directory ("" for all entries)
+    fn dir_entries_recursive(&self, dir: &str) -> Result<Vec<FileEntry>>;
+
+    /// Visit the entries of a directory (or, recursively, under it) one at a time in path order;
+    /// stores that can stream them don't hold the whole listing in memory
+    fn visit_dir_entries(&self, dir: &str, recursive: bool, visit: &mut dyn FnMut(FileEntry) -> Result<()>) -> Result<()> {
+        let mut entries = if recursive { self.dir_entries_recursive(dir)? } else { self.dir_entries(dir)? };
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries.into_iter().try_for_each(visit)
+    }
+
+    /// Entries with a digest in an algorithm other than SHA256 (see `find_by_hash`)
+    fn find_by_digest(&self, algorithm: Algorithm, hash: &str) -> Result<Vec<FileEntry>> {
+        let mut entries = self.entries()?;
+        entries.retain(|entry| digest::lookup(entry.digests.as_deref(), algorithm) == Some(hash));
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    /// The `limit` largest entries, largest first
+    fn largest_entries(&self, limit: usize) -> Result<Vec<FileEntry>> {
+        let mut entries = self.entries()?;
+        entries.sort_by(|a, b| b.num_bytes.cmp(&a.num_bytes).then_with(|| a.path.cmp(&b.path)));
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    /// The `limit` groups of duplicates wasting the most space (every copy beyond the first), as
+    /// (hash, size of one copy, number of copies), most wasteful first
+    fn largest_duplicate_groups(&self, limit: usize) -> Result<Vec<(String, u64, usize)>> {
+        let mut groups: std::collections::HashMap<String, (u64, usize)> = std::collections::HashMap::new();
+        for entry in self.entries()?.into_iter().filter(|entry| !entry.sha256.is_empty()) {
+            let group = groups.entry(entry.sha256).or_insert((0, 0));
+            group.0 = group.0.max(entry.num_bytes);
+            group.1 += 1;
+        }
+        let mut groups: Vec<_> = groups.into_iter()
+            .filter(|(_, (_, copies))| *copies > 1)
+            .map(|(hash, (num_bytes, copies))| (hash, num_bytes, copies))
+            .collect();
+        groups.sort_by(|a, b| (b.1 * (b.2 as u64 - 1)).cmp(&(a.1 * (a.2 as u64 - 1))).then_with(|| a.0.cmp(&b.0)));
+        groups.truncate(limit);
+        Ok(groups)
+    }
+
+    /// Get the entries with a full hash
+    fn find_by_hash(&self, hash: &str) -> Result<Vec<FileEntry>>;
+
+    /// Replace the members recorded for an archive
+    fn set_archive_members(&mut self, archive: &str, members: &[ArchiveMember]) -> Result<()>;
+
+    /// Get the archive members with a hash
+    fn find_members_by_hash(&self, hash: &str) -> Result<Vec<ArchiveMember>>;
+
+    /// Get every archive member
+    fn archive_members(&self) -> Result<Vec<ArchiveMember>>;
+
+    /// Record the metadata extracted from a photo or video, replacing any earlier record
+    fn set_media(&mut self, info: &MediaInfo) -> Result<()>;
+
+    /// Forget the metadata of a path
+    fn clear_media(&mut self, path: &str) -> Result<()>;
+
+    /// Get the metadata of every photo and video, by path
+    fn media(&self) -> Result<Vec<MediaInfo>>;
+
+    /// Record the fingerprint of an audio file, replacing any earlier one
+    fn set_audio(&mut self, print: &AudioFingerprint) -> Result<()>;
+
+    /// Forget the fingerprint of a path
+    fn clear_audio(&mut self, path: &str) -> Result<()>;
+
+    /// Get every audio fingerprint, by path
+    fn audio_fingerprints(&self) -> Result<Vec<AudioFingerprint>>;
+
+    /// Tag a path with a storage class
+    fn set_storage_class(&mut self, path: &str, class: StorageClass) -> Result<()>;
+
+    /// Remove a storage class tag, returning whether a tag existed
+    fn clear_storage_class(&mut self, path: &str) -> Result<bool>;
+
+    /// Get all storage class tags, sorted by path
+    fn storage_class_tags(&self) -> Result<Vec<(String, StorageClass)>>;
+
+    /// Tag a path with a user-defined tag, returning whether the tag is new
+    fn add_tag(&mut self, path: &str, tag: &str) -> Result<bool>;
+
+    /// Remove a tag from a path, returning whether the path had it
+    fn remove_tag(&mut self, path: &str, tag: &str) -> Result<bool>;
+
+    /// Get all `(path, tag)` pairs, sorted by path and tag
+    fn tags(&self) -> Result<Vec<(String, String)>>;
+
+    /// Record content that left the repository
+    fn add_tombstone(&mut self, tombstone: &Tombstone) -> Result<()>;
+
+    /// Get all tombstones, oldest first
+    fn tombstones(&self) -> Result<Vec<Tombstone>>;
+
+    /// Append an operation to the op log
+    fn log_operation(&mut self, op: &Operation) -> Result<()>;
+
+    /// Get logged operations, newest first
+    fn operations(&self, limit: Option<usize>) -> Result<Vec<Operation>>;
+
+    /// Get the most recent logged operation for a command
+    fn last_operation(&self, command: &str) -> Result<Option<Operation>> {
+        Ok(self.operations(None)?.into_iter().find(|op| op.command == command))
+    }
+
+    /// Fold pending writes into the index file so the file can be copied on its own
+    fn checkpoint(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Start a transaction grouping the following writes (a no-op for stores without transactions)
+    fn begin(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Commit the writes since `begin`
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Persist the store to a repository (a no-op for stores that write through)
+    fn save(&self, repo_root: &Path) -> Result<()>;
+}
+
+/// Index storage backends, chosen with `oci init --backend`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// SQLite database (`.oci/index.db`), the default
+    Sqlite,
+    /// Append-only log of JSON records (`.oci/index.log`) for filesystems where SQLite locking is unreliable
+    Log,
+    /// SQLite database encrypted with a passphrase or keyfile (`.oci/index.db.enc`), held in memory while open
+    Encrypted,
+}
+
+impl Backend {
+    /// Name of the file holding the index in the .oci directory
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Backend::Sqlite => "index.db",
+            Backend::Log => "index.log",
+            Backend::Encrypted => "index.db.enc",
+        }
+    }
+
+    /// Path of the file holding the index
+    pub fn index_path(&self, repo_root: &Path) -> PathBuf {
+        repo_root.join(OCI_DIR).join(self.file_name())
+    }
+
+    /// Backend of an existing repository (SQLite unless an index log exists)
+    pub fn detect(repo_root: &Path) -> Self {
+        if Backend::Log.index_path(repo_root).exists() {
+            Backend::Log
+        } else if Backend::Encrypted.index_path(repo_root).exists() {
+            Backend::Encrypted
+        } else {
+            Backend::Sqlite
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sqlite" => Ok(Backend::Sqlite),
+            "log" => Ok(Backend::Log),
+            _ => bail!("Unknown backend: '{}' (expected sqlite or log)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Backend::Sqlite => "sqlite",
+            Backend::Log => "log",
+            Backend::Encrypted => "encrypted",
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use std::collections::BTreeSet;
+
+use crate::index::Index;
+
+/// Most suggestions shown for a mistyped path
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Edit distance between two strings (insertions, deletions and substitutions of characters)
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Candidates within a third of the target's length in edits, closest first
+pub fn closest<'a>(target: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let max_distance = (target.chars().count() / 3).max(1);
+    let mut matches: Vec<(usize, &str)> = candidates.into_iter()
+        .map(|candidate| (levenshtein(target, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    matches.sort();
+    matches.into_iter().take(MAX_SUGGESTIONS).map(|(_, candidate)| candidate).collect()
+}
+
+/// Indexed files and directories whose paths are close to a path that matched nothing
+/// Only paths with as many components as the target are compared, so a typo in any
+/// component is found without comparing against every file in a deep archive.
+pub fn similar_paths(index: &Index, path: &str) -> Result<Vec<String>> {
+    let depth = path.split('/').count();
+    let mut candidates = BTreeSet::new();
+    for entry in index.get_dir_files_recursive("")? {
+        let components: Vec<&str> = entry.path.split('/').collect();
+        if components.len() >= depth {
+            candidates.insert(components[..depth].join("/"));
+        }
+    }
+    Ok(closest(path, candidates.iter().map(String::as_str)).into_iter().map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closest() {
+        assert_eq!(levenshtein("photos/2071", "photos/2017"), 2);
+        assert_eq!(levenshtein("", "abc"), 3);
+        let candidates = ["photos/2017", "photos/2018", "music/2017"];
+        assert_eq!(closest("photos/2071", candidates), vec!["photos/2017", "photos/2018"]);
+        assert_eq!(closest("phtos/2018", candidates), vec!["photos/2018", "photos/2017"]);
+        assert!(closest("videos", candidates).is_empty());
+    }
+}
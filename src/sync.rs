@@ -0,0 +1,285 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::config::ChangeDetection;
+use crate::dir_utils;
+use crate::file_utils;
+use crate::ignore;
+use crate::index::{FileEntry, Index, OCI_DIR};
+use crate::roots::{self, Roots};
+
+/// A change made (or, in a dry run, that would be made) to the destination
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Copied from the repository to a path that did not exist at the destination
+    Copy(String),
+    /// Copied from the repository over a destination file with different content
+    Replace(String),
+    /// Content already at the destination under another path, moved into place (with `--delete`)
+    Move { from: String, to: String },
+    /// Not in the index, removed from the destination (with `--delete`)
+    Delete(String),
+    /// Changed or gone since the last update, so the indexed content cannot be copied
+    Stale(String),
+    /// The copy did not match the index hash and was discarded
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SyncOptions {
+    /// Remove destination files that are not in the index
+    pub delete: bool,
+    /// Only report what would change
+    pub dry_run: bool,
+}
+
+/// Counts from one sync
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncSummary {
+    pub copied: usize,
+    pub replaced: usize,
+    pub moved: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+    pub stale: usize,
+    pub failed: usize,
+    /// Bytes read from the repository
+    pub bytes_copied: u64,
+}
+
+impl SyncSummary {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} copied, {} replaced, {} moved, {} deleted, {} unchanged",
+            self.copied, self.replaced, self.moved, self.deleted, self.unchanged
+        )
+    }
+
+    fn record(&mut self, action: &Action) {
+        match action {
+            Action::Copy(_) => self.copied += 1,
+            Action::Replace(_) => self.replaced += 1,
+            Action::Move { .. } => self.moved += 1,
+            Action::Delete(_) => self.deleted += 1,
+            Action::Stale(_) => self.stale += 1,
+            Action::Failed(_) => self.failed += 1,
+        }
+    }
+}
+
+/// Make `dest` hold exactly the indexed files of the repository, at the same paths
+/// Destination files whose size and modified time match the index are trusted; any other
+/// file at an indexed path is hashed first, and only copied over if its content differs.
+/// Every copy is written to a temporary file and checked against the index hash before it
+/// replaces anything. Paths matching the ignore patterns and the destination's own `.oci`
+/// directory are never touched.
+pub fn sync(
+    roots: &Roots,
+    index: &Index,
+    patterns: &[String],
+    dest: &Path,
+    options: SyncOptions,
+    mut on_action: impl FnMut(&Action),
+) -> Result<SyncSummary> {
+    let mut entries = index.get_dir_files_recursive("")?;
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let dest_files = scan_destination(dest, patterns)?;
+    let mut extras: HashMap<u64, Vec<String>> = HashMap::new();
+    if options.delete {
+        let indexed: HashSet<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        for (path, (num_bytes, _)) in &dest_files {
+            if !indexed.contains(path.as_str()) {
+                extras.entry(*num_bytes).or_default().push(path.clone());
+            }
+        }
+        extras.values_mut().for_each(|paths| paths.sort());
+    }
+
+    let mut summary = SyncSummary::default();
+    let mut act = |action: Action, summary: &mut SyncSummary| {
+        summary.record(&action);
+        on_action(&action);
+    };
+
+    for entry in &entries {
+        let dest_path = dest.join(&entry.path);
+        let existing = dest_files.get(&entry.path).copied();
+        if existing == Some((entry.num_bytes, entry.modified)) {
+            summary.unchanged += 1;
+            continue;
+        }
+        if existing.is_some_and(|(num_bytes, _)| num_bytes == entry.num_bytes) && matches_entry(&dest_path, entry)? {
+            // Same content with another modified time: align it so the next sync trusts it
+            if !options.dry_run {
+                set_modified(&dest_path, entry.modified)?;
+            }
+            summary.unchanged += 1;
+            continue;
+        }
+
+        if existing.is_none() {
+            if let Some(from) = take_matching_extra(&mut extras, dest, entry)? {
+                if !options.dry_run {
+                    create_parent(&dest_path)?;
+                    fs::rename(dest.join(&from), &dest_path)
+                        .context(format!("Failed to move {} within the destination", from))?;
+                }
+                act(Action::Move { from, to: entry.path.clone() }, &mut summary);
+                continue;
+            }
+        }
+
+        let source = roots.full_path(&entry.path);
+        if !source.is_file() || file_utils::has_changed(entry, &source, ChangeDetection::Metadata)? {
+            act(Action::Stale(entry.path.clone()), &mut summary);
+            continue;
+        }
+        if !options.dry_run && !copy_verified(&source, &dest_path, entry)? {
+            act(Action::Failed(entry.path.clone()), &mut summary);
+            continue;
+        }
+        summary.bytes_copied += entry.num_bytes;
+        match existing {
+            Some(_) => act(Action::Replace(entry.path.clone()), &mut summary),
+            None => act(Action::Copy(entry.path.clone()), &mut summary),
+        }
+    }
+
+    let mut leftovers: Vec<String> = extras.into_values().flatten().collect();
+    leftovers.sort();
+    for path in leftovers {
+        if !options.dry_run {
+            let dest_path = dest.join(&path);
+            fs::remove_file(&dest_path)
+                .context(format!("Failed to remove {}", dest_path.display()))?;
+            dir_utils::remove_empty_parent_dirs(&dest_path, dest)?;
+        }
+        act(Action::Delete(path), &mut summary);
+    }
+
+    Ok(summary)
+}
+
+/// Files at the destination by index-style path, with their size and modified time
+fn scan_destination(dest: &Path, patterns: &[String]) -> Result<HashMap<String, (u64, u64)>> {
+    let mut files = HashMap::new();
+    if !dest.exists() {
+        return Ok(files);
+    }
+    let walker = WalkDir::new(dest).min_depth(1).into_iter().filter_entry(|e| {
+        let rel = e.path().strip_prefix(dest).unwrap_or(e.path());
+        let ignored = if e.file_type().is_dir() {
+            ignore::should_ignore_dir(rel, patterns)
+        } else {
+            ignore::should_ignore(rel, patterns)
+        };
+        rel != Path::new(OCI_DIR) && !ignored
+    });
+    for entry in walker {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(dest).context("Path is outside destination")?;
+        let path = roots::join_index_path("", rel).to_string_lossy().to_string();
+        let num_bytes = file_utils::get_file_size(entry.path())?;
+        let modified = file_utils::get_modified_time(entry.path())?;
+        files.insert(path, (num_bytes, modified));
+    }
+    Ok(files)
+}
+
+/// Whether a file has the content recorded in an entry (by quick hash while the full hash is pending)
+fn matches_entry(path: &Path, entry: &FileEntry) -> Result<bool> {
+    if !entry.sha256.is_empty() {
+        return Ok(file_utils::compute_sha256(path)? == entry.sha256);
+    }
+    match &entry.quick_hash {
+        Some(quick_hash) => Ok(file_utils::compute_quick_hash(path)? == *quick_hash),
+        None => Ok(false),
+    }
+}
+
+/// Find (and claim) a destination file outside the index that has an entry's content
+fn take_matching_extra(extras: &mut HashMap<u64, Vec<String>>, dest: &Path, entry: &FileEntry) -> Result<Option<String>> {
+    let Some(candidates) = extras.get_mut(&entry.num_bytes) else {
+        return Ok(None);
+    };
+    for i in 0..candidates.len() {
+        if matches_entry(&dest.join(&candidates[i]), entry)? {
+            return Ok(Some(candidates.remove(i)));
+        }
+    }
+    Ok(None)
+}
+
+/// Copy a file through a temporary file next to the target, replacing the target only if
+/// the copy matches the index; returns false (leaving the target alone) if it does not
+fn copy_verified(source: &Path, target: &Path, entry: &FileEntry) -> Result<bool> {
+    create_parent(target)?;
+    let mut temp_name = target.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".oci-sync");
+    let temp_path: PathBuf = target.with_file_name(temp_name);
+
+    let copied = file_utils::copy_preserving_mtime(source, &temp_path)
+        .and_then(|()| matches_entry(&temp_path, entry))
+        .and_then(|matches| {
+            if matches {
+                fs::rename(&temp_path, target).context(format!("Failed to replace {}", target.display()))?;
+            }
+            Ok(matches)
+        });
+    // A copy that failed, half-finished or didn't match is not left next to the target
+    if !matches!(copied, Ok(true)) && temp_path.exists() {
+        fs::remove_file(&temp_path).context(format!("Failed to remove {}", temp_path.display()))?;
+    }
+    copied
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .context(format!("Failed to create directory: {}", parent.display()))?;
+    }
+    Ok(())
+}
+
+/// Set a file's modified time from milliseconds since the epoch
+fn set_modified(path: &Path, millis: u64) -> Result<()> {
+    let modified = std::time::UNIX_EPOCH + std::time::Duration::from_millis(millis);
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(modified))
+        .context(format!("Failed to set modified time of {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_copy_verified_leaves_no_temp_file() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("a.txt");
+        fs::write(&source, "hello").unwrap();
+        let entry = FileEntry { sha256: file_utils::compute_sha256(&source).unwrap(), ..Default::default() };
+
+        assert!(copy_verified(&source, &dir.path().join("dest/a.txt"), &entry).unwrap());
+        assert_eq!(fs::read_to_string(dir.path().join("dest/a.txt")).unwrap(), "hello");
+
+        let other = FileEntry { sha256: "0".repeat(64), ..Default::default() };
+        assert!(!copy_verified(&source, &dir.path().join("dest/b.txt"), &other).unwrap());
+        assert!(!dir.path().join("dest/b.txt.oci-sync").exists());
+
+        // The verified copy can't replace a directory
+        fs::create_dir_all(dir.path().join("dest/c.txt/x")).unwrap();
+        assert!(copy_verified(&source, &dir.path().join("dest/c.txt"), &entry).is_err());
+        assert!(!dir.path().join("dest/c.txt.oci-sync").exists());
+    }
+}
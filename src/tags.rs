@@ -0,0 +1,78 @@
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+
+/// Check a tag name: letters, digits, `-`, `_`, `.` and `:` (e.g. `keep`, `to-review`, `client:x`)
+pub fn validate(tag: &str) -> Result<()> {
+    if tag.is_empty()
+        || !tag.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+    {
+        bail!("Invalid tag: '{}' (use letters, digits, '-', '_', '.' or ':')", tag);
+    }
+    Ok(())
+}
+
+/// Whether a tag on `tag_path` applies to `path`: the path itself, or anything below a tagged directory
+fn covers(tag_path: &str, path: &str) -> bool {
+    tag_path.is_empty() || path == tag_path || path.starts_with(&format!("{}/", tag_path))
+}
+
+/// All `(path, tag)` pairs of an index, answering which tags apply to a file
+pub struct Tags {
+    tags: Vec<(String, String)>,
+}
+
+impl Tags {
+    pub fn new(tags: Vec<(String, String)>) -> Self {
+        Tags { tags }
+    }
+
+    /// Tags applying to a path, directly or through a directory above it
+    pub fn of(&self, path: &str) -> BTreeSet<&str> {
+        self.tags.iter()
+            .filter(|(tag_path, _)| covers(tag_path, path))
+            .map(|(_, tag)| tag.as_str())
+            .collect()
+    }
+
+    pub fn has(&self, path: &str, tag: &str) -> bool {
+        self.tags.iter().any(|(tag_path, t)| t == tag && covers(tag_path, path))
+    }
+
+    /// The most specific tagged path a tag of `path` comes from (the path itself when tagged directly)
+    pub fn origin(&self, path: &str, tag: &str) -> Option<&str> {
+        self.tags.iter()
+            .filter(|(tag_path, t)| t == tag && covers(tag_path, path))
+            .map(|(tag_path, _)| tag_path.as_str())
+            .max_by_key(|tag_path| tag_path.len())
+    }
+
+    /// Whether any of the tags applies to a path
+    pub fn has_any(&self, path: &str, tags: &[String]) -> bool {
+        tags.iter().any(|tag| self.has(path, tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_apply_below_tagged_directories() {
+        let tags = Tags::new(vec![
+            ("clients/x".to_string(), "client-x".to_string()),
+            ("clients/x/draft.doc".to_string(), "to-review".to_string()),
+            ("photos".to_string(), "keep".to_string()),
+        ]);
+        assert_eq!(tags.of("clients/x/draft.doc").into_iter().collect::<Vec<_>>(), vec!["client-x", "to-review"]);
+        assert!(tags.has("photos/2020/a.jpg", "keep"));
+        assert!(!tags.has("photos2/a.jpg", "keep"));
+        assert!(tags.has_any("clients/x/final.doc", &["keep".to_string(), "client-x".to_string()]));
+        assert!(tags.of("other.txt").is_empty());
+        assert_eq!(tags.origin("clients/x/draft.doc", "client-x"), Some("clients/x"));
+        assert_eq!(tags.origin("clients/x", "client-x"), Some("clients/x"));
+
+        assert!(validate("client:x").is_ok());
+        assert!(validate("to review").is_err());
+        assert!(validate("").is_err());
+    }
+}
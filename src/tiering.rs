@@ -0,0 +1,215 @@
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::ignore;
+use crate::index::FileEntry;
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Storage tier an entry is expected to live on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StorageClass {
+    Hot,
+    Warm,
+    Cold,
+    Offsite,
+}
+
+impl StorageClass {
+    pub const ALL: [StorageClass; 4] = [
+        StorageClass::Hot,
+        StorageClass::Warm,
+        StorageClass::Cold,
+        StorageClass::Offsite,
+    ];
+
+    /// Whether files of this class are expected to stay unmodified
+    pub fn expects_dormant(&self) -> bool {
+        matches!(self, StorageClass::Cold | StorageClass::Offsite)
+    }
+}
+
+impl std::str::FromStr for StorageClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hot" => Ok(StorageClass::Hot),
+            "warm" => Ok(StorageClass::Warm),
+            "cold" => Ok(StorageClass::Cold),
+            "offsite" => Ok(StorageClass::Offsite),
+            _ => bail!("Invalid storage class: '{}' (expected hot, warm, cold or offsite)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for StorageClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            StorageClass::Hot => "hot",
+            StorageClass::Warm => "warm",
+            StorageClass::Cold => "cold",
+            StorageClass::Offsite => "offsite",
+        };
+        f.pad(name)
+    }
+}
+
+/// A config rule assigning a storage class to paths matching patterns (`tier.<class>` keys)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TierRule {
+    pub class: StorageClass,
+    pub patterns: Vec<String>,
+}
+
+impl TierRule {
+    /// Parse a rule from its class and comma-separated pattern list
+    pub fn parse(class: StorageClass, patterns: &str) -> Self {
+        Self {
+            class,
+            patterns: patterns
+                .split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// Resolve the storage class of a path
+/// Manual tags win over rules; among tags the most specific (longest) path wins.
+/// A tag on a directory applies to every file below it.
+pub fn resolve_class(
+    path: &str,
+    tags: &[(String, StorageClass)],
+    rules: &[TierRule],
+) -> Option<StorageClass> {
+    let tagged = tags
+        .iter()
+        .filter(|(tag_path, _)| {
+            tag_path.is_empty() || path == tag_path || path.starts_with(&format!("{}/", tag_path))
+        })
+        .max_by_key(|(tag_path, _)| tag_path.len())
+        .map(|(_, class)| *class);
+
+    tagged.or_else(|| {
+        rules
+            .iter()
+            .find(|rule| ignore::matches_any(Path::new(path), &rule.patterns))
+            .map(|rule| rule.class)
+    })
+}
+
+/// File count and bytes for one storage class
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TierTotals {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// A file whose modification history contradicts its storage class
+#[derive(Debug, Clone, PartialEq)]
+pub struct TierViolation {
+    pub class: StorageClass,
+    pub path: String,
+    pub age_days: u64,
+}
+
+/// Result of classifying every indexed file
+#[derive(Debug, Default)]
+pub struct TieringReport {
+    pub totals: BTreeMap<StorageClass, TierTotals>,
+    pub unclassified: TierTotals,
+    pub violations: Vec<TierViolation>,
+}
+
+/// Classify entries and find cold/offsite files modified within `recent_days` of `now_millis`
+pub fn build_report(
+    entries: &[FileEntry],
+    tags: &[(String, StorageClass)],
+    rules: &[TierRule],
+    now_millis: u64,
+    recent_days: u64,
+) -> TieringReport {
+    let mut report = TieringReport::default();
+
+    for entry in entries {
+        let totals = match resolve_class(&entry.path, tags, rules) {
+            Some(class) => {
+                let age_days = now_millis.saturating_sub(entry.modified) / MILLIS_PER_DAY;
+                if class.expects_dormant() && age_days < recent_days {
+                    report.violations.push(TierViolation {
+                        class,
+                        path: entry.path.clone(),
+                        age_days,
+                    });
+                }
+                report.totals.entry(class).or_default()
+            }
+            None => &mut report.unclassified,
+        };
+        totals.files += 1;
+        totals.bytes += entry.num_bytes;
+    }
+
+    report.violations.sort_by(|a, b| a.path.cmp(&b.path));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, num_bytes: u64, modified: u64) -> FileEntry {
+        FileEntry {
+            num_bytes,
+            modified,
+            sha256: "abc".to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_class_prefers_specific_tags_over_rules() {
+        let tags = vec![
+            ("archive".to_string(), StorageClass::Cold),
+            ("archive/current".to_string(), StorageClass::Hot),
+        ];
+        let rules = vec![TierRule { class: StorageClass::Offsite, patterns: vec!["*.iso".to_string()] }];
+
+        assert_eq!(resolve_class("archive/2010/a.jpg", &tags, &rules), Some(StorageClass::Cold));
+        assert_eq!(resolve_class("archive/current/b.jpg", &tags, &rules), Some(StorageClass::Hot));
+        assert_eq!(resolve_class("archive/disk.iso", &tags, &rules), Some(StorageClass::Cold));
+        assert_eq!(resolve_class("images/disk.iso", &tags, &rules), Some(StorageClass::Offsite));
+        assert_eq!(resolve_class("archived.txt", &tags, &rules), None);
+    }
+
+    #[test]
+    fn test_build_report_flags_recent_cold_files() {
+        let now = 100 * MILLIS_PER_DAY;
+        let entries = vec![
+            entry("cold/old.txt", 10, 10 * MILLIS_PER_DAY),
+            entry("cold/new.txt", 20, 95 * MILLIS_PER_DAY),
+            entry("hot/new.txt", 30, 99 * MILLIS_PER_DAY),
+            entry("misc.txt", 40, 0),
+        ];
+        let tags = vec![
+            ("cold".to_string(), StorageClass::Cold),
+            ("hot".to_string(), StorageClass::Hot),
+        ];
+
+        let report = build_report(&entries, &tags, &[], now, 30);
+
+        assert_eq!(report.totals[&StorageClass::Cold], TierTotals { files: 2, bytes: 30 });
+        assert_eq!(report.totals[&StorageClass::Hot], TierTotals { files: 1, bytes: 30 });
+        assert_eq!(report.unclassified, TierTotals { files: 1, bytes: 40 });
+        assert_eq!(report.violations, vec![TierViolation {
+            class: StorageClass::Cold,
+            path: "cold/new.txt".to_string(),
+            age_days: 5,
+        }]);
+    }
+}
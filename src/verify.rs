@@ -0,0 +1,311 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::commands::is_permission_error;
+use crate::config::Config;
+use crate::digest::{self, Algorithm, DigestSet};
+use crate::file_utils;
+use crate::index::Index;
+use crate::oplog::Operation;
+use crate::progress::{self, Progress};
+use crate::roots::Roots;
+
+/// A problem found while verifying a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Finding {
+    Missing,
+    Mismatch,
+    /// The file could not be read; it is skipped rather than counted as a failure
+    PermissionDenied,
+    /// The contents match, but the extended attributes differ from those captured
+    /// (`capture_xattrs`); reported like `status`'s `M`, but not a failure
+    AttributesChanged,
+}
+
+/// Counts from verifying one repository
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifySummary {
+    pub verified: usize,
+    pub mismatched: usize,
+    pub missing: usize,
+    /// Verified files whose extended attributes drifted from the index
+    pub attributes_changed: usize,
+    /// Files that only have a quick fingerprint and cannot be verified yet
+    pub pending: usize,
+    /// Bytes re-hashed and how long it took
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl VerifySummary {
+    pub fn failed(&self) -> bool {
+        self.mismatched + self.missing > 0
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = format!("{} verified, {} mismatched, {} missing", self.verified, self.mismatched, self.missing);
+        if self.attributes_changed > 0 {
+            description.push_str(&format!(", {} with changed attributes", self.attributes_changed));
+        }
+        description
+    }
+}
+
+/// Read budget in bytes per second shared by every verification running in the process
+/// Readers sleep whenever the total read so far is ahead of the budget.
+pub struct IoBudget {
+    bytes_per_sec: Option<u64>,
+    /// When the budget started and how many bytes have been read since
+    state: Mutex<(Instant, u64)>,
+}
+
+impl IoBudget {
+    pub fn new(bytes_per_sec: Option<u64>) -> Self {
+        IoBudget { bytes_per_sec: bytes_per_sec.filter(|rate| *rate > 0), state: Mutex::new((Instant::now(), 0)) }
+    }
+
+    /// Account for bytes read, waiting until they fit in the budget
+    /// The lock is held while sleeping so concurrent readers queue behind each other.
+    pub fn consume(&self, bytes: u64) {
+        let Some(rate) = self.bytes_per_sec else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.1 += bytes;
+        let due = Duration::from_secs_f64(state.1 as f64 / rate as f64);
+        if let Some(wait) = due.checked_sub(state.0.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Re-hash every fully hashed file of a repository against its index and log the result
+/// Each finding is passed to `on_finding` with the file's index path and location as it is found.
+/// With `show_progress`, a live estimate is drawn on stderr when it is a terminal.
+pub fn verify_repo(
+    repo_root: &Path,
+    budget: &IoBudget,
+    show_progress: bool,
+    mut on_finding: impl FnMut(Finding, &str, &Path) -> Result<()>,
+) -> Result<VerifySummary> {
+    let config = Config::load(repo_root)?;
+    let roots = Roots::from_config(repo_root.to_path_buf(), &config);
+    let mut index = Index::load(repo_root)?;
+    let options = file_utils::CaptureOptions::from_config(&config);
+
+    let mut entries = index.get_dir_files_recursive("")?;
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut progress = Progress::new(show_progress);
+    let hashed: Vec<_> = entries.iter().filter(|entry| !entry.sha256.is_empty()).collect();
+    progress.expect(hashed.len(), Some(hashed.iter().map(|entry| entry.num_bytes).sum()));
+
+    let mut summary = VerifySummary::default();
+    let mut report = |finding, path: &str, full_path: &Path| {
+        progress::clear_line();
+        on_finding(finding, path, full_path)
+    };
+    for entry in &entries {
+        if entry.sha256.is_empty() {
+            summary.pending += 1;
+            continue;
+        }
+
+        let full_path = roots.full_path(&entry.path);
+        let finding = check_file(&full_path, &entry.sha256, |bytes| {
+            budget.consume(bytes);
+            progress.hashed(bytes);
+        })?;
+        // Only a file whose contents check out is looked at for attribute drift
+        let finding = match finding {
+            None if file_utils::xattrs_changed(entry, &full_path, options).unwrap_or(false) => Some(Finding::AttributesChanged),
+            finding => finding,
+        };
+        match finding {
+            None => summary.verified += 1,
+            Some(finding) => {
+                report(finding, &entry.path, &full_path)?;
+                match finding {
+                    Finding::Missing => summary.missing += 1,
+                    Finding::Mismatch => summary.mismatched += 1,
+                    Finding::PermissionDenied => {}
+                    Finding::AttributesChanged => {
+                        summary.verified += 1;
+                        summary.attributes_changed += 1;
+                    }
+                }
+            }
+        }
+        progress.file_done();
+    }
+    progress.finish();
+    summary.bytes = progress.bytes();
+    summary.elapsed = progress.elapsed();
+
+    let command = if summary.failed() { "verify (failed)" } else { "verify" };
+    index.log_operation(&Operation::new(&config, command, summary.describe()))?;
+    index.save(repo_root)?;
+    Ok(summary)
+}
+
+/// Re-hash one file against its recorded SHA256, passing the bytes read to `on_read`
+/// Returns None when the file matches.
+pub fn check_file(full_path: &Path, sha256: &str, on_read: impl FnMut(u64)) -> Result<Option<Finding>> {
+    if !full_path.exists() {
+        return Ok(Some(Finding::Missing));
+    }
+    match file_utils::compute_sha256_metered(full_path, on_read) {
+        Ok(actual) if actual == sha256 => Ok(None),
+        Ok(_) => Ok(Some(Finding::Mismatch)),
+        Err(e) if is_permission_error(&e) => Ok(Some(Finding::PermissionDenied)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Check the files listed by a checksum file (as index paths and digests) against the tree, or
+/// against the digests recorded in the index with `index_only`, reporting each finding like
+/// `verify_repo`. Returns the counts and the indexed files below `base` that the manifest doesn't list.
+/// With `index_only`, files without a recorded digest in the manifest's algorithm count as pending.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_manifest(
+    repo_root: &Path,
+    algorithm: Algorithm,
+    listed: &[(String, String)],
+    base: &str,
+    index_only: bool,
+    budget: &IoBudget,
+    show_progress: bool,
+    mut on_finding: impl FnMut(Finding, &str, &Path) -> Result<()>,
+) -> Result<(VerifySummary, Vec<String>)> {
+    let config = Config::load(repo_root)?;
+    let roots = Roots::from_config(repo_root.to_path_buf(), &config);
+    let mut index = Index::load(repo_root)?;
+
+    let mut progress = Progress::new(show_progress && !index_only);
+    progress.expect(listed.len(), None);
+    let mut summary = VerifySummary::default();
+    let mut report = |finding, path: &str, full_path: &Path| {
+        progress::clear_line();
+        on_finding(finding, path, full_path)
+    };
+    for (path, expected) in listed {
+        let full_path = roots.full_path(path);
+        let actual = if index_only {
+            match index.get(path)? {
+                Some(entry) => digest::recorded(&entry, algorithm).map(str::to_string),
+                None => {
+                    report(Finding::Missing, path, &full_path)?;
+                    summary.missing += 1;
+                    continue;
+                }
+            }
+        } else if !full_path.is_file() {
+            report(Finding::Missing, path, &full_path)?;
+            summary.missing += 1;
+            progress.file_done();
+            continue;
+        } else {
+            let digests = file_utils::compute_digests_metered(&full_path, DigestSet::only(algorithm), |bytes| {
+                budget.consume(bytes);
+                progress.hashed(bytes);
+            });
+            progress.file_done();
+            match digests {
+                Ok((sha256, _)) if algorithm == Algorithm::Sha256 => Some(sha256),
+                Ok((_, extra)) => digest::lookup(extra.as_deref(), algorithm).map(str::to_string),
+                Err(e) if is_permission_error(&e) => {
+                    report(Finding::PermissionDenied, path, &full_path)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        match actual {
+            Some(actual) if actual == *expected => summary.verified += 1,
+            Some(_) => {
+                report(Finding::Mismatch, path, &full_path)?;
+                summary.mismatched += 1;
+            }
+            None => summary.pending += 1,
+        }
+    }
+    progress.finish();
+    summary.bytes = progress.bytes();
+    summary.elapsed = progress.elapsed();
+
+    let listed_paths: HashSet<&str> = listed.iter().map(|(path, _)| path.as_str()).collect();
+    let mut unlisted: Vec<String> = index.get_dir_files_recursive(base)?
+        .into_iter()
+        .map(|entry| entry.path)
+        .filter(|path| !listed_paths.contains(path.as_str()))
+        .collect();
+    unlisted.sort();
+
+    let command = if summary.failed() { "verify --manifest (failed)" } else { "verify --manifest" };
+    index.log_operation(&Operation::new(&config, command, summary.describe()))?;
+    index.save(repo_root)?;
+    Ok((summary, unlisted))
+}
+
+/// Group repositories by the volume they live on, keeping registration order
+/// Repositories on one volume are verified one after another, since reading two
+/// trees from the same disk at once only makes it seek.
+pub fn group_by_volume(repos: Vec<PathBuf>) -> Vec<Vec<PathBuf>> {
+    let mut groups: Vec<(Option<u64>, Vec<PathBuf>)> = Vec::new();
+    for repo in repos {
+        let volume = volume_id(&repo);
+        match groups.iter_mut().find(|(id, _)| volume.is_some() && *id == volume) {
+            Some((_, group)) => group.push(repo),
+            None => groups.push((volume, vec![repo])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+#[cfg(unix)]
+fn volume_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|metadata| metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn volume_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_budget_waits_for_rate() {
+        let budget = IoBudget::new(Some(1000));
+        let start = Instant::now();
+        budget.consume(100);
+        budget.consume(100);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let unlimited = IoBudget::new(None);
+        let start = Instant::now();
+        unlimited.consume(u64::MAX / 2);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_group_by_volume() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        std::fs::create_dir(&a).unwrap();
+        std::fs::create_dir(&b).unwrap();
+        let groups = group_by_volume(vec![a.clone(), b.clone()]);
+        if cfg!(unix) {
+            assert_eq!(groups, vec![vec![a, b]]);
+        } else {
+            assert_eq!(groups.len(), 2);
+        }
+    }
+}
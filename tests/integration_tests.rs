@@ -1,39 +1,41 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::Once;
+use std::sync::OnceLock;
 use tempfile::TempDir;
 
-static INIT: Once = Once::new();
-static mut OCI_BIN: Option<PathBuf> = None;
+static OCI_BIN: OnceLock<PathBuf> = OnceLock::new();
 
 fn get_oci_binary() -> &'static Path {
-    unsafe {
-        INIT.call_once(|| {
-            // Build the binary once
-            let output = Command::new("cargo")
-                .args(&["build", "--quiet"])
-                .current_dir(env!("CARGO_MANIFEST_DIR"))
-                .output()
-                .expect("Failed to build oci");
-            
-            if !output.status.success() {
-                panic!("Failed to build oci binary");
-            }
-            
-            let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-            let bin_path = manifest_dir.join("target/debug/oci");
-            OCI_BIN = Some(bin_path);
-        });
+    OCI_BIN.get_or_init(|| {
+        // Build the binary once
+        let output = Command::new("cargo")
+            .args(["build", "--quiet"])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to build oci");
         
-        OCI_BIN.as_ref().unwrap()
-    }
+        if !output.status.success() {
+            panic!("Failed to build oci binary");
+        }
+        
+        let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        manifest_dir.join("target/debug/oci")
+    })
 }
 
 fn run_oci(args: &[&str], working_dir: &Path) -> (String, String, i32) {
+    // Keep `init` from registering test repositories in the user's real registry
+    let config_home = std::env::temp_dir().join(format!("oci-tests-{}", std::process::id()));
+    run_oci_with_config_home(args, working_dir, &config_home)
+}
+
+/// Run oci with its per-user files (such as the repository registry) under `config_home`
+fn run_oci_with_config_home(args: &[&str], working_dir: &Path, config_home: &Path) -> (String, String, i32) {
     let output = Command::new(get_oci_binary())
         .args(args)
         .current_dir(working_dir)
+        .env("XDG_CONFIG_HOME", config_home)
         .output()
         .expect("Failed to execute oci");
     
@@ -44,6 +46,25 @@ fn run_oci(args: &[&str], working_dir: &Path) -> (String, String, i32) {
     (stdout, stderr, exit_code)
 }
 
+/// Set a key in the repository's .oci/config, replacing any existing value
+fn set_config_value(repo_root: &Path, key: &str, value: &str) {
+    let config_path = repo_root.join(".oci/config");
+    let contents = fs::read_to_string(&config_path).unwrap_or_default();
+    let mut lines: Vec<String> = contents
+        .lines()
+        .filter(|line| line.split_once('=').map(|(k, _)| k.trim()) != Some(key))
+        .map(String::from)
+        .collect();
+    lines.push(format!("{}={}", key, value));
+    fs::write(&config_path, lines.join("\n") + "\n").unwrap();
+}
+
+/// Hex SHA-256 of some content
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(data))
+}
+
 #[test]
 fn test_init_creates_oci_directory() {
     let temp_dir = TempDir::new().unwrap();
@@ -129,6 +150,813 @@ fn test_status_shows_changes() {
     assert!(stdout.contains("test.txt"));
 }
 
+#[test]
+fn test_status_check_exit_codes() {
+    let temp_dir = TempDir::new().unwrap();
+    let (_, stderr, exit_code) = run_oci(&["status", "--check"], temp_dir.path());
+    assert_eq!(exit_code, 2, "got: {}", stderr);
+    
+    run_oci(&["init"], temp_dir.path());
+    fs::write(temp_dir.path().join("test.txt"), "original").unwrap();
+    run_oci(&["update"], temp_dir.path());
+    let (stdout, _, exit_code) = run_oci(&["status", "--check"], temp_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("No changes"));
+    
+    fs::write(temp_dir.path().join("test.txt"), "modified").unwrap();
+    let (stdout, _, exit_code) = run_oci(&["status", "--check"], temp_dir.path());
+    assert_eq!(exit_code, 1);
+    assert!(stdout.contains("test.txt"));
+    
+    // Without --check, changes still exit 0
+    let (_, _, exit_code) = run_oci(&["status"], temp_dir.path());
+    assert_eq!(exit_code, 0);
+    
+    fs::remove_file(temp_dir.path().join("test.txt")).unwrap();
+    let (_, _, exit_code) = run_oci(&["status", "--check"], temp_dir.path());
+    assert_eq!(exit_code, 1);
+}
+
+#[test]
+fn test_quiet_prints_only_summaries() {
+    let temp_dir = TempDir::new().unwrap();
+    run_oci(&["init"], temp_dir.path());
+    fs::write(temp_dir.path().join("a.txt"), "a").unwrap();
+    fs::write(temp_dir.path().join("b.txt"), "b").unwrap();
+    
+    let (stdout, _, exit_code) = run_oci(&["update", "--quiet"], temp_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(!stdout.contains("a.txt") && !stdout.contains("b.txt"), "got: {}", stdout);
+    assert!(stdout.contains("Updated 2 file(s) in the index (2 added"), "got: {}", stdout);
+    
+    fs::write(temp_dir.path().join("a.txt"), "changed").unwrap();
+    let (stdout, _, exit_code) = run_oci(&["-q", "status", "--check"], temp_dir.path());
+    assert_eq!(exit_code, 1);
+    assert!(!stdout.contains("a.txt"), "got: {}", stdout);
+}
+
+#[test]
+fn test_verbose_logs_timing_spans() {
+    let temp_dir = TempDir::new().unwrap();
+    run_oci(&["init"], temp_dir.path());
+    fs::write(temp_dir.path().join("a.txt"), "a").unwrap();
+    
+    // -v before the command sets the log level; -v after it keeps its meaning for update
+    let (stdout, stderr, exit_code) = run_oci(&["-vv", "update", "-v"], temp_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(stdout.contains("+ a.txt"), "got: {}", stdout);
+    assert!(stderr.contains("Scanning "), "got: {}", stderr);
+    assert!(stderr.contains("sha256{path=") && stderr.contains("close time.busy="), "got: {}", stderr);
+    assert!(stderr.contains("commit_batch: close"), "got: {}", stderr);
+    
+    let (_, stderr, exit_code) = run_oci(&["status"], temp_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stderr.is_empty(), "got: {}", stderr);
+}
+
+#[test]
+fn test_encrypted_index_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path().join("repo");
+    fs::create_dir(&repo).unwrap();
+    let keyfile = temp_dir.path().join("index.key");
+    fs::write(&keyfile, "correct horse battery staple").unwrap();
+
+    let (_, stderr, code) = run_oci(&["init", "--keyfile", keyfile.to_str().unwrap()], &repo);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("--encrypt"), "stderr: {}", stderr);
+
+    let (_, stderr, code) = run_oci(&["init", "--encrypt", "--keyfile", keyfile.to_str().unwrap()], &repo);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    fs::write(repo.join("secret-plans.txt"), "hello").unwrap();
+    let (_, stderr, code) = run_oci(&["update"], &repo);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+
+    // Only the encrypted file exists, and it does not reveal paths
+    assert!(!repo.join(".oci/index.db").exists());
+    let data = fs::read(repo.join(".oci/index.db.enc")).unwrap();
+    assert!(!data.windows(12).any(|w| w == b"secret-plans"));
+
+    let (stdout, _, _) = run_oci(&["ls"], &repo);
+    assert!(stdout.contains("secret-plans.txt"), "stdout: {}", stdout);
+
+    // Snapshots would be plain SQLite next to the encrypted index; an export says it is not encrypted
+    for args in [["snapshot", "save", "before"], ["snapshot", "restore", "before"]] {
+        let (_, stderr, code) = run_oci(&args, &repo);
+        assert_ne!(code, 0);
+        assert!(stderr.contains("An encrypted index has no snapshots"), "stderr: {}", stderr);
+    }
+    assert!(!repo.join(".oci/snapshots").exists());
+    let export = temp_dir.path().join("export");
+    let (_, stderr, code) = run_oci(&["export", export.to_str().unwrap()], &repo);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stderr.contains("the export is not"), "stderr: {}", stderr);
+    // External checks would be handed the ciphertext
+    fs::create_dir(repo.join(".oci/checks")).unwrap();
+    let (_, stderr, code) = run_oci(&["check", "--external"], &repo);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("can't read an encrypted index"), "stderr: {}", stderr);
+
+    // The wrong key is refused, not mistaken for an empty index
+    let wrong = temp_dir.path().join("wrong.key");
+    fs::write(&wrong, "wrong").unwrap();
+    set_config_value(&repo, "keyfile", wrong.to_str().unwrap());
+    let (_, stderr, code) = run_oci(&["ls"], &repo);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("wrong passphrase or keyfile"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_signed_export_is_verified_before_prune() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = temp_dir.path().join("archive");
+    let local = temp_dir.path().join("local");
+    let export = temp_dir.path().join("export");
+    fs::create_dir(&archive).unwrap();
+    fs::create_dir(&local).unwrap();
+
+    fs::write(archive.join("photo.jpg"), "photo content").unwrap();
+    run_oci(&["init"], &archive);
+    run_oci(&["update"], &archive);
+    let (_, stderr, code) = run_oci(&["export", export.to_str().unwrap(), "--sign"], &archive);
+    assert_ne!(code, 0, "signing needs a key");
+    assert!(stderr.contains("oci keys generate"), "stderr: {}", stderr);
+    assert!(!export.exists());
+
+    let (stdout, _, code) = run_oci(&["keys", "generate"], &archive);
+    assert_eq!(code, 0);
+    let public_key = stdout.lines().find_map(|l| l.strip_prefix("Public key: ")).unwrap().to_string();
+    let (stdout, stderr, code) = run_oci(&["export", export.to_str().unwrap(), "--sign"], &archive);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Exported 1 file(s)"), "stdout: {}", stdout);
+    assert!(export.join(".oci/index.sig").exists());
+
+    fs::write(local.join("copy.jpg"), "photo content").unwrap();
+    run_oci(&["init"], &local);
+    run_oci(&["update"], &local);
+    run_oci(&["keys", "trust", "archive", &public_key], &local);
+
+    // An unknown key and a tampered export are both refused before anything is pruned
+    let (_, stderr, code) = run_oci(&["prune", "../export", "--verify-key", "nobody"], &local);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("trusted key"), "stderr: {}", stderr);
+    let ignore_file = export.join(".oci/ignore");
+    let original = fs::read_to_string(&ignore_file).unwrap();
+    fs::write(&ignore_file, format!("{}*.jpg\n", original)).unwrap();
+    let (_, stderr, code) = run_oci(&["prune", "../export", "--verify-key", "archive"], &local);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("ignore was modified after it was signed"), "stderr: {}", stderr);
+    assert!(local.join("copy.jpg").exists());
+
+    // The exported index has no files of its own, so it is not scanned for pending changes
+    fs::write(&ignore_file, original).unwrap();
+    let (stdout, stderr, code) = run_oci(&["prune", "../export", "--verify-key", "archive"], &local);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Verified the signature"), "stdout: {}", stdout);
+    assert!(stdout.contains("exported index"), "stdout: {}", stdout);
+    assert!(!local.join("copy.jpg").exists());
+}
+
+#[test]
+fn test_tags_filter_ls_and_protect_from_prune() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = temp_dir.path().join("archive");
+    let local = temp_dir.path().join("local");
+    fs::create_dir(&archive).unwrap();
+    fs::create_dir_all(local.join("clients/x")).unwrap();
+
+    fs::write(archive.join("a.doc"), "contract").unwrap();
+    fs::write(archive.join("b.doc"), "invoice").unwrap();
+    run_oci(&["init"], &archive);
+    run_oci(&["update"], &archive);
+
+    fs::write(local.join("clients/x/a.doc"), "contract").unwrap();
+    fs::write(local.join("clients/x/b.doc"), "invoice").unwrap();
+    fs::write(local.join("notes.txt"), "notes").unwrap();
+    run_oci(&["init"], &local);
+    run_oci(&["update"], &local);
+
+    let (stdout, _, code) = run_oci(&["tag", "add", "clients/x", "client-x"], &local);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Tagged clients/x with client-x"), "stdout: {}", stdout);
+    run_oci(&["tag", "add", "clients/x/a.doc", "keep"], &local);
+    let (_, stderr, code) = run_oci(&["tag", "add", "notes.txt", "to review"], &local);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("Invalid tag"), "stderr: {}", stderr);
+
+    // A directory's tag applies to the files below it
+    let (stdout, _, _) = run_oci(&["tag", "list", "clients/x/a.doc"], &local);
+    assert!(stdout.contains("client-x  (from clients/x)"), "stdout: {}", stdout);
+    assert!(stdout.contains("keep"), "stdout: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "-r", "--tag", "client-x"], &local);
+    assert!(stdout.contains("a.doc") && stdout.contains("b.doc"), "stdout: {}", stdout);
+    assert!(!stdout.contains("notes.txt"), "stdout: {}", stdout);
+
+    let (stdout, stderr, code) = run_oci(&["prune", "../archive", "--skip-tag", "keep"], &local);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Kept 1 file(s) tagged keep"), "stdout: {}", stdout);
+    assert!(local.join("clients/x/a.doc").exists());
+    assert!(!local.join("clients/x/b.doc").exists());
+}
+
+#[test]
+fn test_duplicates_resolve_moves_unchosen_copies_to_pruneyard() {
+    use std::io::Write;
+
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path();
+    fs::create_dir_all(repo.join("a")).unwrap();
+    fs::create_dir_all(repo.join("b")).unwrap();
+    fs::write(repo.join("a/photo.jpg"), "photo").unwrap();
+    fs::write(repo.join("b/photo.jpg"), "photo").unwrap();
+    fs::write(repo.join("a/notes.txt"), "notes").unwrap();
+    fs::write(repo.join("b/notes.txt"), "notes").unwrap();
+    run_oci(&["init"], repo);
+    run_oci(&["update"], repo);
+
+    // Reject an invalid answer, keep the second copy (b/) of the first group and skip the other
+    let mut child = Command::new(get_oci_binary())
+        .args(["duplicates", "--resolve"])
+        .current_dir(repo)
+        .env("XDG_CONFIG_HOME", std::env::temp_dir().join(format!("oci-tests-{}", std::process::id())))
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"7\n2\ns\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {}", stdout);
+    assert!(stdout.contains("Enter a number from 1 to 2"), "stdout: {}", stdout);
+    assert!(stdout.contains("Moved 1 duplicate file(s)"), "stdout: {}", stdout);
+
+    let moved: Vec<_> = ["a/notes.txt", "a/photo.jpg"].into_iter().filter(|p| !repo.join(p).exists()).collect();
+    assert_eq!(moved.len(), 1, "only the first group is resolved");
+    assert!(repo.join(".oci/pruneyard").join(moved[0]).exists());
+    let (stdout, _, _) = run_oci(&["status"], repo);
+    assert!(stdout.contains("No changes"), "the index follows the move: {}", stdout);
+
+    // --auto keeps the first copy by path in every remaining group
+    let (stdout, _, code) = run_oci(&["duplicates", "--resolve", "--auto"], repo);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Moved 1 duplicate file(s)"), "stdout: {}", stdout);
+    let (stdout, _, _) = run_oci(&["duplicates"], repo);
+    assert!(stdout.contains("No duplicate files found"), "stdout: {}", stdout);
+
+    let (_, stderr, code) = run_oci(&["duplicates", "--auto"], repo);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("--auto requires --resolve"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_duplicates_resolve_keep_prefer_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path();
+    fs::create_dir_all(repo.join("originals")).unwrap();
+    fs::create_dir_all(repo.join("imports/2020")).unwrap();
+    fs::write(repo.join("originals/photo.jpg"), "photo").unwrap();
+    fs::write(repo.join("imports/2020/photo.jpg"), "photo").unwrap();
+    fs::write(repo.join("imports/notes.txt"), "notes").unwrap();
+    fs::write(repo.join("imports/2020/notes.txt"), "notes").unwrap();
+    run_oci(&["init"], repo);
+    run_oci(&["update"], repo);
+
+    // Only groups with a copy in originals/ are resolved, from a subdirectory too
+    let (stdout, stderr, code) = run_oci(&["duplicates", "--resolve", "--keep", "prefer-dir", "../originals/"], &repo.join("imports"));
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Pruned (duplicate of originals/photo.jpg): imports/2020/photo.jpg"), "stdout: {}", stdout);
+    assert!(stdout.contains("Left 1 group(s) without a copy in originals/"), "stdout: {}", stdout);
+    assert!(repo.join("originals/photo.jpg").exists());
+    assert!(repo.join(".oci/pruneyard/imports/2020/photo.jpg").exists());
+
+    let (stdout, _, code) = run_oci(&["duplicates", "--resolve", "--keep", "shortest-path"], repo);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Pruned (duplicate of imports/notes.txt): imports/2020/notes.txt"), "stdout: {}", stdout);
+
+    let (_, stderr, code) = run_oci(&["duplicates", "--keep", "oldest"], repo);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("--keep requires --resolve"), "stderr: {}", stderr);
+    let (_, stderr, code) = run_oci(&["duplicates", "--resolve", "--keep", "largest"], repo);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("Invalid keep policy"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_duplicates_against_other_indexes() {
+    let temp_dir = TempDir::new().unwrap();
+    let local = temp_dir.path().join("local");
+    let photos = temp_dir.path().join("photos");
+    let music = temp_dir.path().join("music");
+    for repo in [&local, &photos, &music] {
+        fs::create_dir_all(repo).unwrap();
+    }
+    fs::write(local.join("photo.jpg"), "photo").unwrap();
+    fs::write(local.join("song.mp3"), "song").unwrap();
+    fs::write(local.join("only-here.txt"), "only here").unwrap();
+    fs::write(photos.join("photo-copy.jpg"), "photo").unwrap();
+    fs::write(music.join("track.mp3"), "song").unwrap();
+    fs::write(music.join("other.mp3"), "other").unwrap();
+    for repo in [&local, &photos, &music] {
+        run_oci(&["init"], repo);
+        run_oci(&["update"], repo);
+    }
+
+    let (stdout, stderr, code) = run_oci(&["duplicates", "--against", "../photos", "../music"], &local);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Found 2 file(s) here"), "stdout: {}", stdout);
+    assert!(stdout.contains("in 2 group(s)"), "stdout: {}", stdout);
+    assert!(stdout.contains("photo-copy.jpg") && stdout.contains("track.mp3"), "stdout: {}", stdout);
+    assert!(!stdout.contains("only-here.txt") && !stdout.contains("other.mp3"), "stdout: {}", stdout);
+
+    let (stdout, _, code) = run_oci(&["duplicates", "--against", "../photos"], &music);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("No content here is also in ../photos"), "stdout: {}", stdout);
+
+    // Content shared between the other indexes only is not reported
+    fs::write(photos.join("other.mp3"), "other").unwrap();
+    run_oci(&["update"], &photos);
+    let (stdout, _, _) = run_oci(&["duplicates", "--against", "../photos", "../music"], &local);
+    assert!(!stdout.contains("other.mp3"), "stdout: {}", stdout);
+
+    let (_, stderr, code) = run_oci(&["duplicates", "--resolve", "--against", "../photos"], &local);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("--against cannot be combined with --resolve"), "stderr: {}", stderr);
+}
+
+/// Rewrite a file with different bytes of the same size and modified time, which the index can't
+/// tell apart from the original: the stand-in for a hash collision
+fn swap_bytes_keeping_metadata(path: &Path, contents: &str) {
+    let modified = fs::metadata(path).unwrap().modified().unwrap();
+    fs::write(path, contents).unwrap();
+    fs::File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+}
+
+#[test]
+fn test_paranoid_keeps_files_that_differ_byte_for_byte() {
+    let temp_dir = TempDir::new().unwrap();
+    let local = temp_dir.path().join("local");
+    let source = temp_dir.path().join("source");
+    fs::create_dir_all(&local).unwrap();
+    fs::create_dir_all(&source).unwrap();
+    fs::write(local.join("same.txt"), "same").unwrap();
+    fs::write(local.join("collides.txt"), "abcd").unwrap();
+    fs::write(source.join("same.txt"), "same").unwrap();
+    fs::write(source.join("collides.txt"), "abcd").unwrap();
+    for repo in [&local, &source] {
+        run_oci(&["init"], repo);
+        run_oci(&["update"], repo);
+    }
+    swap_bytes_keeping_metadata(&source.join("collides.txt"), "wxyz");
+
+    let (stdout, stderr, code) = run_oci(&["prune", "--paranoid", "../source"], &local);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Kept (no byte-identical copy in the source): collides.txt"), "stdout: {}", stdout);
+    assert!(stdout.contains("Pruned 1 file(s)"), "stdout: {}", stdout);
+    assert!(local.join("collides.txt").exists());
+    assert!(!local.join("same.txt").exists());
+
+    // The same check guards duplicates --resolve
+    fs::write(local.join("copy.txt"), "abcd").unwrap();
+    run_oci(&["update"], &local);
+    swap_bytes_keeping_metadata(&local.join("copy.txt"), "wxyz");
+    let (stdout, _, code) = run_oci(&["duplicates", "--resolve", "--auto", "--paranoid"], &local);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Kept (differs from collides.txt byte for byte): copy.txt"), "stdout: {}", stdout);
+    assert!(local.join("copy.txt").exists());
+
+    let (_, stderr, code) = run_oci(&["duplicates", "--paranoid"], &local);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("--paranoid requires --resolve"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_ls_tree_shows_counts_and_sizes_per_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path();
+    fs::create_dir_all(repo.join("photos/2020")).unwrap();
+    fs::write(repo.join("notes.txt"), "0123456789").unwrap();
+    fs::write(repo.join("photos/c.jpg"), "cc").unwrap();
+    fs::write(repo.join("photos/2020/a.jpg"), "aaa").unwrap();
+    fs::write(repo.join("photos/2020/b.jpg"), "bbbb").unwrap();
+    run_oci(&["init"], repo);
+    run_oci(&["update"], repo);
+
+    let (stdout, _, code) = run_oci(&["ls", "--tree"], repo);
+    assert_eq!(code, 0);
+    let expected = [
+        ". (4 file(s), 19 bytes)",
+        "├── notes.txt (10 bytes)",
+        "└── photos/ (3 file(s), 9 bytes)",
+        "    ├── 2020/ (2 file(s), 7 bytes)",
+        "    │   ├── a.jpg (3 bytes)",
+        "    │   └── b.jpg (4 bytes)",
+        "    └── c.jpg (2 bytes)",
+    ];
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+
+    // Rooted at the current directory
+    let (stdout, _, _) = run_oci(&["ls", "--tree"], &repo.join("photos"));
+    assert!(stdout.starts_with(". (3 file(s), 9 bytes)\n├── 2020/ (2 file(s), 7 bytes)"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_update_and_verify_report_throughput() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path();
+    fs::write(repo.join("a.txt"), "0123456789").unwrap();
+    run_oci(&["init"], repo);
+
+    let (stdout, stderr, code) = run_oci(&["update"], repo);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Hashed 10 bytes in "), "stdout: {}", stdout);
+    assert!(stdout.contains(" overall, ") && stdout.contains(" while hashing)"), "stdout: {}", stdout);
+    assert!(stdout.contains("Spent ") && stdout.contains(" hashing 1 file(s) and "), "stdout: {}", stdout);
+    assert!(stdout.contains(" on 1 file(s) in all"), "stdout: {}", stdout);
+    // No live progress line when stderr is not a terminal
+    assert!(!stderr.contains('\r'), "stderr: {:?}", stderr);
+
+    // Nothing hashed, nothing to report
+    let (stdout, _, _) = run_oci(&["update"], repo);
+    assert!(!stdout.contains("Hashed"), "stdout: {}", stdout);
+
+    let (stdout, _, code) = run_oci(&["verify"], repo);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Hashed 10 bytes in"), "stdout: {}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_reuses_hashes_of_renamed_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path();
+    fs::create_dir_all(repo.join("a")).unwrap();
+    fs::write(repo.join("a/photo.jpg"), "photo").unwrap();
+    run_oci(&["init"], repo);
+    run_oci(&["update"], repo);
+    assert!(repo.join(".oci/hashcache.db").exists());
+
+    fs::rename(repo.join("a"), repo.join("b")).unwrap();
+    let (stdout, _, code) = run_oci(&["update"], repo);
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Reused the hash of 1 moved or renamed file(s)"), "stdout: {}", stdout);
+    assert!(!stdout.contains("Hashed"), "nothing is read: {}", stdout);
+    let (stdout, _, _) = run_oci(&["verify"], repo);
+    assert!(stdout.contains("1 verified, 0 mismatched"), "stdout: {}", stdout);
+
+    // A rename of a changed file is hashed again
+    fs::write(repo.join("b/photo.jpg"), "edited").unwrap();
+    fs::rename(repo.join("b/photo.jpg"), repo.join("b/edited.jpg")).unwrap();
+    let (stdout, _, _) = run_oci(&["update"], repo);
+    assert!(!stdout.contains("Reused"), "stdout: {}", stdout);
+    let (stdout, _, _) = run_oci(&["verify"], repo);
+    assert!(stdout.contains("1 verified, 0 mismatched"), "stdout: {}", stdout);
+
+    // Turned off, a rename is read again
+    set_config_value(repo, "hash_cache", "false");
+    fs::rename(repo.join("b/edited.jpg"), repo.join("b/renamed.jpg")).unwrap();
+    let (stdout, _, _) = run_oci(&["update"], repo);
+    assert!(!stdout.contains("Reused") && stdout.contains("Hashed"), "stdout: {}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_stats_reports_allocated_size_of_sparse_files() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+
+    // A sparse file: 10 MB long but with no blocks written
+    let sparse = fs::File::create(test_dir.path().join("sparse.img")).unwrap();
+    sparse.set_len(10 * 1024 * 1024).unwrap();
+    drop(sparse);
+    run_oci(&["update"], test_dir.path());
+
+    let (stdout, _, exit_code) = run_oci(&["stats"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Total size: 10485760 bytes"), "got: {}", stdout);
+    let allocated: u64 = stdout.lines()
+        .find_map(|line| line.trim().strip_prefix("Allocated on disk: "))
+        .and_then(|rest| rest.split(' ').next())
+        .and_then(|bytes| bytes.parse().ok())
+        .expect("allocated size in stats");
+    assert!(allocated < 10 * 1024 * 1024, "got: {}", stdout);
+}
+
+#[test]
+fn test_respect_gitignore() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    let project = test_dir.path().join("project");
+    fs::create_dir_all(project.join("target")).unwrap();
+    fs::create_dir_all(project.join(".git").join("info")).unwrap();
+    fs::write(project.join(".gitignore"), "target/\n*.log\n!keep.log\n").unwrap();
+    fs::write(project.join(".git").join("info").join("exclude"), "notes.txt\n").unwrap();
+    fs::write(project.join("target").join("app.o"), "object").unwrap();
+    fs::write(project.join("build.log"), "log").unwrap();
+    fs::write(project.join("keep.log"), "kept").unwrap();
+    fs::write(project.join("notes.txt"), "notes").unwrap();
+    fs::write(project.join("main.rs"), "fn main() {}").unwrap();
+    // Rules only apply below the directory of the .gitignore
+    fs::write(test_dir.path().join("other.log"), "other").unwrap();
+
+    // Off by default
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("build.log"), "got: {}", stdout);
+
+    set_config_value(test_dir.path(), "respect_gitignore", "true");
+    run_oci(&["update"], test_dir.path());
+    let (stdout, _, exit_code) = run_oci(&["ls", "-r"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("project/main.rs"), "got: {}", stdout);
+    assert!(stdout.contains("project/keep.log"), "got: {}", stdout);
+    assert!(stdout.contains("other.log"), "got: {}", stdout);
+    assert!(!stdout.contains("app.o"), "got: {}", stdout);
+    assert!(!stdout.contains("build.log"), "got: {}", stdout);
+    assert!(!stdout.contains("notes.txt"), "got: {}", stdout);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_rm_and_prune_to_trash() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path().join("repo");
+    let data_home = temp_dir.path().join("data");
+    fs::create_dir_all(repo.join("old")).unwrap();
+    fs::write(repo.join("old/draft.txt"), "draft").unwrap();
+    fs::write(repo.join("keep.txt"), "keep").unwrap();
+    fs::write(repo.join("debug.log"), "log").unwrap();
+    run_oci(&["init"], &repo);
+    fs::write(repo.join(".oci/ignore"), "*.log\n").unwrap();
+    run_oci(&["update"], &repo);
+    // The freedesktop trash lives under $XDG_DATA_HOME
+    let run_with_trash = |args: &[&str]| {
+        let output = Command::new(get_oci_binary())
+            .args(args)
+            .current_dir(&repo)
+            .env("XDG_CONFIG_HOME", std::env::temp_dir().join(format!("oci-tests-{}", std::process::id())))
+            .env("XDG_DATA_HOME", &data_home)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let stdout = run_with_trash(&["rm", "old", "--trash"]);
+    assert!(stdout.contains("Moved 1 file(s) (5 bytes) to the trash"), "stdout: {}", stdout);
+    assert!(!repo.join("old").exists());
+    assert!(!repo.join(".oci/pruneyard").exists());
+    assert_eq!(fs::read_to_string(data_home.join("Trash/files/draft.txt")).unwrap(), "draft");
+
+    // The config makes it the default for prune
+    set_config_value(&repo, "prune_to_trash", "true");
+    let stdout = run_with_trash(&["prune", "--ignored"]);
+    assert!(stdout.contains("Pruned 1 ignored file(s) to the trash"), "stdout: {}", stdout);
+    assert!(data_home.join("Trash/files/debug.log").exists());
+
+    let (stdout, _, _) = run_oci(&["status"], &repo);
+    assert!(stdout.contains("No changes"), "the index follows the moves: {}", stdout);
+}
+
+#[test]
+fn test_prune_dry_run_reports_without_changing_anything() {
+    let temp_dir = TempDir::new().unwrap();
+    let source = temp_dir.path().join("source");
+    let local = temp_dir.path().join("local");
+    fs::create_dir_all(source.join("2020")).unwrap();
+    fs::create_dir_all(&local).unwrap();
+    fs::write(source.join("2020/beach.jpg"), "beach photo").unwrap();
+    fs::write(source.join("beach-copy.jpg"), "beach photo").unwrap();
+    run_oci(&["init"], &source);
+    fs::write(source.join(".oci/ignore"), "*.tmp\n").unwrap();
+    run_oci(&["update"], &source);
+
+    fs::write(local.join("IMG_001.jpg"), "beach photo").unwrap();
+    fs::write(local.join("scratch.tmp"), "scratch").unwrap();
+    fs::write(local.join("notes.txt"), "only here").unwrap();
+    run_oci(&["init"], &local);
+    run_oci(&["update"], &local);
+
+    let (stdout, stderr, code) = run_oci(&["prune", "../source", "--dry-run"], &local);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Duplicates (1 file(s), 11 bytes):\n  IMG_001.jpg (11 bytes)\n      = 2020/beach.jpg\n      = beach-copy.jpg\n"),
+        "stdout: {}", stdout);
+    assert!(stdout.contains("Ignored (1 file(s), 7 bytes):\n  scratch.tmp (7 bytes)\n"), "stdout: {}", stdout);
+    assert!(stdout.contains("Would prune 2 file(s) to .oci/pruneyard/ (1 duplicates, 1 ignored), reclaiming 18 bytes"),
+        "stdout: {}", stdout);
+    assert!(!stdout.contains("notes.txt"), "stdout: {}", stdout);
+
+    assert!(local.join("IMG_001.jpg").exists());
+    assert!(local.join("scratch.tmp").exists());
+    assert!(!local.join(".oci/pruneyard").exists());
+    let (stdout, _, _) = run_oci(&["ls"], &local);
+    assert!(stdout.contains("IMG_001.jpg"), "the index is unchanged: {}", stdout);
+}
+
+#[test]
+fn test_prune_limited_to_path_and_pattern() {
+    let temp_dir = TempDir::new().unwrap();
+    let source = temp_dir.path().join("source");
+    let local = temp_dir.path().join("local");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("photo.jpg"), "photo").unwrap();
+    fs::write(source.join("notes.txt"), "notes").unwrap();
+    run_oci(&["init"], &source);
+    run_oci(&["update"], &source);
+
+    fs::create_dir_all(local.join("downloads")).unwrap();
+    fs::create_dir_all(local.join("photos")).unwrap();
+    fs::write(local.join("downloads/photo.jpg"), "photo").unwrap();
+    fs::write(local.join("downloads/notes.txt"), "notes").unwrap();
+    fs::write(local.join("photos/photo.jpg"), "photo").unwrap();
+    run_oci(&["init"], &local);
+    run_oci(&["update"], &local);
+
+    let (stdout, stderr, code) = run_oci(&["prune", "../source", "--path", "downloads/", "--match", "*.jpg"], &local);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Pruned 1 file(s)"), "stdout: {}", stdout);
+    assert!(!local.join("downloads/photo.jpg").exists());
+    assert!(local.join("downloads/notes.txt").exists());
+    assert!(local.join("photos/photo.jpg").exists());
+
+    // --path is relative to the current directory
+    let (stdout, _, code) = run_oci(&["prune", "../../source", "--path", "."], &local.join("downloads"));
+    assert_eq!(code, 0);
+    assert!(stdout.contains("Pruned 1 file(s)"), "stdout: {}", stdout);
+    assert!(!local.join("downloads/notes.txt").exists());
+    assert!(local.join("photos/photo.jpg").exists());
+
+    let (_, stderr, code) = run_oci(&["prune", "../source", "--path", "missing"], &local);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("Path does not exist: missing"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_gc_removes_entries_of_missing_files() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::create_dir_all(test_dir.path().join("old")).unwrap();
+    fs::write(test_dir.path().join("keep.txt"), "keep").unwrap();
+    fs::write(test_dir.path().join("gone.txt"), "gone").unwrap();
+    fs::write(test_dir.path().join("old/a.txt"), "a").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    fs::remove_file(test_dir.path().join("gone.txt")).unwrap();
+    fs::remove_dir_all(test_dir.path().join("old")).unwrap();
+    // A changed file is left for update to re-hash
+    fs::write(test_dir.path().join("keep.txt"), "changed").unwrap();
+
+    let (stdout, _, exit_code) = run_oci(&["gc"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("gone.txt"), "got: {}", stdout);
+    assert!(stdout.contains("old/a.txt"), "got: {}", stdout);
+    assert!(stdout.contains("Removed 2 missing file(s) (5 bytes) from the index; checked 3 file(s)"), "got: {}", stdout);
+
+    let (stdout, _, _) = run_oci(&["ls", "-r"], test_dir.path());
+    assert!(stdout.contains("keep.txt"), "got: {}", stdout);
+    assert!(!stdout.contains("gone.txt"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("keep.txt") && !stdout.contains("gone.txt"), "got: {}", stdout);
+
+    let (stdout, _, _) = run_oci(&["gc"], test_dir.path());
+    assert!(stdout.contains("No missing files among 1 indexed file(s)"), "got: {}", stdout);
+}
+
+#[test]
+fn test_max_depth_limits_update_and_status() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::create_dir_all(test_dir.path().join("a/b/c")).unwrap();
+    fs::write(test_dir.path().join("top.txt"), "top").unwrap();
+    fs::write(test_dir.path().join("a/x.txt"), "x").unwrap();
+    fs::write(test_dir.path().join("a/b/c/deep.txt"), "deep").unwrap();
+
+    let (stdout, _, exit_code) = run_oci(&["update", "--max-depth", "2"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("2 added"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["status", "--max-depth", "2"], test_dir.path());
+    assert!(stdout.contains("No changes"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("a/b/c/deep.txt"), "got: {}", stdout);
+
+    // Deeper entries are left alone rather than taken for deleted
+    run_oci(&["update"], test_dir.path());
+    fs::remove_file(test_dir.path().join("a/x.txt")).unwrap();
+    let (stdout, _, _) = run_oci(&["update", "--max-depth", "1"], test_dir.path());
+    assert!(stdout.contains("Updated 0 file(s)"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["status", "a", "--max-depth", "1"], test_dir.path());
+    assert!(stdout.contains("a/x.txt") && !stdout.contains("deep.txt"), "got: {}", stdout);
+
+    let (_, stderr, exit_code) = run_oci(&["update", "--max-depth", "0"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--max-depth must be at least 1"), "got: {}", stderr);
+}
+
+#[test]
+fn test_include_only_ignore_lines() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::create_dir_all(test_dir.path().join("2020/raw")).unwrap();
+    fs::write(test_dir.path().join("2020/beach.jpg"), "jpg").unwrap();
+    fs::write(test_dir.path().join("2020/raw/beach.raw"), "raw").unwrap();
+    fs::write(test_dir.path().join("2020/notes.txt"), "notes").unwrap();
+    fs::write(test_dir.path().join("readme.md"), "readme").unwrap();
+    let ignore_file = test_dir.path().join(".oci/ignore");
+    let mut contents = fs::read_to_string(&ignore_file).unwrap();
+    contents.push_str("!!*.jpg\n!!*.raw\n");
+    fs::write(&ignore_file, contents).unwrap();
+
+    let (stdout, _, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("2 added"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "-r"], test_dir.path());
+    assert!(stdout.contains("2020/beach.jpg"), "got: {}", stdout);
+    assert!(stdout.contains("2020/raw/beach.raw"), "got: {}", stdout);
+    assert!(!stdout.contains("notes.txt") && !stdout.contains("readme.md"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("No changes"), "got: {}", stdout);
+}
+
+#[test]
+fn test_root_ocignore_preferred() {
+    let test_dir = TempDir::new().unwrap();
+    fs::write(test_dir.path().join(".ocignore"), "*.log\n").unwrap();
+    run_oci(&["init"], test_dir.path());
+    assert!(!test_dir.path().join(".oci/ignore").exists());
+    fs::write(test_dir.path().join("keep.txt"), "keep").unwrap();
+    fs::write(test_dir.path().join("skip.log"), "skip").unwrap();
+    fs::write(test_dir.path().join("skip.tmp"), "tmp").unwrap();
+
+    let (_, _, exit_code) = run_oci(&["ignore", "*.tmp"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert_eq!(fs::read_to_string(test_dir.path().join(".ocignore")).unwrap(), "*.log\n*.tmp\n");
+    assert!(!test_dir.path().join(".oci/ignore").exists());
+
+    let (stdout, _, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    let (stdout_ls, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(stdout.contains("2 added"), "got: {}", stdout);
+    assert!(stdout_ls.contains("keep.txt") && stdout_ls.contains(".ocignore"), "got: {}", stdout_ls);
+    assert!(!stdout_ls.contains("skip.log") && !stdout_ls.contains("skip.tmp"), "got: {}", stdout_ls);
+}
+
+#[test]
+fn test_vcs_directories_skipped() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    let project = test_dir.path().join("project");
+    fs::create_dir_all(project.join(".git/objects/ab")).unwrap();
+    fs::create_dir_all(project.join(".svn")).unwrap();
+    fs::write(project.join(".git/objects/ab/cdef"), "object").unwrap();
+    fs::write(project.join(".svn/entries"), "entries").unwrap();
+    fs::write(project.join(".gitignore"), "*.o\n").unwrap();
+    fs::write(project.join("main.rs"), "fn main() {}").unwrap();
+
+    let (stdout, _, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("2 added"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "-r"], test_dir.path());
+    assert!(stdout.contains("project/.gitignore") && stdout.contains("project/main.rs"), "got: {}", stdout);
+    assert!(!stdout.contains("objects") && !stdout.contains("entries"), "got: {}", stdout);
+
+    set_config_value(test_dir.path(), "skip_vcs", "false");
+    let (stdout, _, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("2 added"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "-r"], test_dir.path());
+    assert!(stdout.contains("project/.git/objects/ab/cdef"), "got: {}", stdout);
+}
+
+#[test]
+fn test_parallel_walk_is_ordered() {
+    let config_home = TempDir::new().unwrap();
+    let test_dir = TempDir::new().unwrap();
+    let oci = |args: &[&str]| run_oci_with_config_home(args, test_dir.path(), config_home.path());
+    oci(&["init"]);
+    for dir in ["b", "a", "a/z", "a/c", "skip"] {
+        fs::create_dir_all(test_dir.path().join(dir)).unwrap();
+    }
+    for file in ["b/2.txt", "b/1.txt", "a/z/x.txt", "a/c/y.txt", "a/d.txt", "top.txt", "skip/s.txt"] {
+        fs::write(test_dir.path().join(file), file).unwrap();
+    }
+    oci(&["ignore", "skip"]);
+
+    let expected = ["a/c/y.txt", "a/d.txt", "a/z/x.txt", "b/1.txt", "b/2.txt", "top.txt"];
+    let listed = |stdout: &str| -> Vec<String> {
+        stdout.lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .filter(|path| path.ends_with(".txt"))
+            .map(String::from)
+            .collect()
+    };
+    for jobs in ["1", "4"] {
+        oci(&["config", "set", "--global", "jobs", jobs]);
+        let (stdout, _, _) = oci(&["status"]);
+        assert_eq!(listed(&stdout), expected, "jobs={}, got: {}", jobs, stdout);
+    }
+    let (stdout, _, exit_code) = oci(&["update"]);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("6 added"), "got: {}", stdout);
+}
+
 #[test]
 fn test_grep_finds_files_by_hash() {
     let temp_dir = TempDir::new().unwrap();
@@ -154,6 +982,128 @@ fn test_grep_finds_files_by_hash() {
     assert!(stdout.contains("Found 2 file(s)"));
 }
 
+#[test]
+fn test_grep_multiple_hashes_and_stdin() {
+    use std::io::Write;
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path();
+    run_oci(&["init"], repo);
+    fs::write(repo.join("a.txt"), "alpha").unwrap();
+    fs::write(repo.join("b.txt"), "beta").unwrap();
+    run_oci(&["update"], repo);
+    let (stdout, _, _) = run_oci(&["ls"], repo);
+    let hash_of = |name: &str| -> String {
+        stdout.lines()
+            .find(|line| line.ends_with(name))
+            .and_then(|line| line.split_whitespace().nth(2))
+            .expect("Failed to extract hash")
+            .to_string()
+    };
+    let (a, b) = (hash_of("a.txt"), hash_of("b.txt"));
+    let missing = "0".repeat(64);
+
+    let (stdout, _, exit_code) = run_oci(&["grep", &a, &missing, &b.to_uppercase()], repo);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("a.txt") && stdout.contains("b.txt"), "stdout: {}", stdout);
+    assert!(stdout.contains("No match for 1 of 3 hash(es):\n  0000"), "stdout: {}", stdout);
+
+    // Hashes from stdin, in sha256sum format
+    let mut child = Command::new(get_oci_binary())
+        .args(["grep", "-"])
+        .current_dir(repo)
+        .env("XDG_CONFIG_HOME", std::env::temp_dir().join(format!("oci-tests-{}", std::process::id())))
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input = format!("{}  a.txt\n\n# from another tool\n{}  gone.txt\n", a, missing);
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {}", stdout);
+    assert!(stdout.contains("Found 1 file(s)") && stdout.contains("a.txt"), "stdout: {}", stdout);
+    assert!(!stdout.contains("b.txt"), "stdout: {}", stdout);
+    assert!(stdout.contains("No match for 1 of 2 hash(es)"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_ls_and_grep_format() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path();
+    run_oci(&["init"], repo);
+    fs::create_dir_all(repo.join("docs")).unwrap();
+    fs::write(repo.join("docs/a.txt"), "alpha").unwrap();
+    fs::write(repo.join("docs/b.txt"), "alpha").unwrap();
+    fs::write(repo.join("c.txt"), "gamma!").unwrap();
+    run_oci(&["update"], repo);
+
+    let (stdout, _, exit_code) = run_oci(&["ls", "-r", "--format", r"{path}\t{bytes}\t{type}"], repo);
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout, "c.txt\t6\ttext/plain\ndocs/a.txt\t5\ttext/plain\ndocs/b.txt\t5\ttext/plain\n");
+    // Paths are relative to the current directory, as in plain ls
+    let (stdout, _, _) = run_oci(&["ls", "--format", "{path} {name}"], &repo.join("docs"));
+    assert_eq!(stdout, "a.txt a.txt\nb.txt b.txt\n");
+
+    let (stdout, _, _) = run_oci(&["ls", "-r", "--format", "{path} {sha256}"], repo);
+    let alpha = stdout.lines()
+        .find_map(|line| line.strip_prefix("docs/a.txt "))
+        .unwrap()
+        .to_string();
+    let missing = "0".repeat(64);
+    let (stdout, stderr, exit_code) = run_oci(&["grep", "--format", "{path}", &alpha, &missing], repo);
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout, "docs/a.txt\ndocs/b.txt\n");
+    assert!(stderr.contains("No match for 1 of 2 hash(es)"), "stderr: {}", stderr);
+
+    let (_, stderr, exit_code) = run_oci(&["ls", "--format", "{owner}"], repo);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Unknown field {owner}"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_ls_ndjson_streams_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path();
+    run_oci(&["init"], repo);
+    fs::create_dir_all(repo.join("docs")).unwrap();
+    for i in 0..50 {
+        fs::write(repo.join(format!("docs/{:02}.txt", i)), format!("file {}", i)).unwrap();
+    }
+    fs::write(repo.join("top.txt"), "top").unwrap();
+    run_oci(&["update"], repo);
+
+    let (stdout, _, exit_code) = run_oci(&["ls", "-r", "--format", "ndjson"], repo);
+    assert_eq!(exit_code, 0);
+    let lines: Vec<serde_json::Value> = stdout.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 51);
+    // In path order, as they come out of the index
+    assert_eq!(lines[0]["path"], "docs/00.txt");
+    assert_eq!(lines[50]["path"], "top.txt");
+    assert_eq!(lines[50]["num_bytes"], 3);
+    assert_eq!(lines[50]["sha256"].as_str().unwrap().len(), 64);
+
+    // Not recursive, relative to the current directory, and nothing at all when nothing matches
+    let (stdout, _, _) = run_oci(&["ls", "--format", "ndjson"], &repo.join("docs"));
+    assert_eq!(stdout.lines().count(), 50);
+    assert!(stdout.starts_with("{") && stdout.contains("\"path\":\"00.txt\""), "stdout: {}", stdout);
+    let (stdout, _, exit_code) = run_oci(&["ls", "-r", "--format", "ndjson", "--type", "video"], repo);
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout, "");
+
+    // A reader that stops early is not an error
+    let mut child = Command::new(get_oci_binary())
+        .args(["ls", "-r", "--format", "ndjson"])
+        .current_dir(repo)
+        .env("XDG_CONFIG_HOME", std::env::temp_dir().join(format!("oci-tests-{}", std::process::id())))
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+}
+
 #[test]
 fn test_ignore_excludes_files() {
     let temp_dir = TempDir::new().unwrap();
@@ -836,272 +1786,2877 @@ fn test_stats_with_files() {
 }
 
 #[test]
-fn test_stats_no_duplicates() {
+fn test_stats_top() {
     let test_dir = TempDir::new().unwrap();
     run_oci(&["init"], test_dir.path());
-    
-    // Create unique files
-    fs::write(test_dir.path().join("file1.txt"), "content1").unwrap();
-    fs::write(test_dir.path().join("file2.txt"), "content2").unwrap();
-    fs::write(test_dir.path().join("file3.txt"), "content3").unwrap();
-    
+
+    fs::write(test_dir.path().join("big.bin"), vec![1u8; 5000]).unwrap();
+    fs::write(test_dir.path().join("medium.bin"), vec![2u8; 3000]).unwrap();
+    fs::write(test_dir.path().join("small.txt"), "small").unwrap();
+    fs::write(test_dir.path().join("copy1.txt"), vec![3u8; 1000]).unwrap();
+    fs::write(test_dir.path().join("copy2.txt"), vec![3u8; 1000]).unwrap();
+    fs::write(test_dir.path().join("copy3.txt"), vec![3u8; 1000]).unwrap();
+    fs::write(test_dir.path().join("pair1.txt"), "pair").unwrap();
+    fs::write(test_dir.path().join("pair2.txt"), "pair").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    let (stdout, _, exit_code) = run_oci(&["stats", "--top", "2"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    let big = stdout.find("big.bin").unwrap();
+    let medium = stdout.find("medium.bin").unwrap();
+    assert!(big < medium);
+    assert!(!stdout.contains("small.txt"));
+    assert!(!stdout.contains("duplicate groups"));
+
+    // Groups are ranked by the space their extra copies waste
+    let (stdout, _, exit_code) = run_oci(&["stats", "--top", "1", "--duplicates"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Largest duplicate groups"));
+    assert!(stdout.contains("3 copies of 1000 bytes"));
+    assert!(stdout.contains("copy3.txt"));
+    assert!(!stdout.contains("pair1.txt"));
+
+    let (_, _, exit_code) = run_oci(&["stats", "--duplicates"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    let (_, _, exit_code) = run_oci(&["stats", "--top", "2", "--by-dir"], test_dir.path());
+    assert_ne!(exit_code, 0);
+}
+
+#[test]
+fn test_stats_age() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+
+    // Mid-year times, so the local time zone cannot move them across a year boundary
+    for (name, secs) in [("old.jpg", 1_182_000_000u64), ("mid.jpg", 1_592_000_000), ("new.jpg", 1_593_000_000)] {
+        let path = test_dir.path().join(name);
+        fs::write(&path, name).unwrap();
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    }
+    run_oci(&["update"], test_dir.path());
+
+    let (stdout, _, exit_code) = run_oci(&["stats", "--age"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("By age:"));
+    assert!(stdout.contains("Oldest: 2007-06-"), "stdout: {}", stdout);
+    assert!(stdout.lines().any(|line| line.starts_with("  Oldest:") && line.ends_with("old.jpg")));
+    assert!(stdout.lines().any(|line| line.starts_with("  Newest:") && line.ends_with("new.jpg")));
+    let years: Vec<&str> = stdout.lines().filter(|line| line.trim_start().starts_with("20")).collect();
+    assert_eq!(years.len(), 2, "stdout: {}", stdout);
+    assert!(years[0].trim_start().starts_with("2007") && years[0].contains(" 1 "));
+    assert!(years[1].trim_start().starts_with("2020") && years[1].contains(" 2 "));
+}
+
+#[test]
+fn test_duplicates_dirs() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+
+    for dir in ["photos/2017", "copy/2017"] {
+        fs::create_dir_all(test_dir.path().join(dir)).unwrap();
+        fs::write(test_dir.path().join(dir).join("a.jpg"), "image a").unwrap();
+        fs::write(test_dir.path().join(dir).join("b.jpg"), "image b").unwrap();
+    }
+    fs::create_dir_all(test_dir.path().join("picks")).unwrap();
+    fs::write(test_dir.path().join("picks/a.jpg"), "image a").unwrap();
+    fs::create_dir_all(test_dir.path().join("own")).unwrap();
+    fs::write(test_dir.path().join("own/c.jpg"), "image c").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    let (stdout, _, exit_code) = run_oci(&["duplicates", "--dirs"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Found 2 duplicate director(ies) holding 3 file(s)"), "stdout: {}", stdout);
+    assert!(stdout.contains("photos/ (2 file(s), 14 bytes)\n  identical to copy/2017/"), "stdout: {}", stdout);
+    assert!(stdout.contains("picks/ (1 file(s), 7 bytes)\n  contained in copy/2017/"), "stdout: {}", stdout);
+    assert!(!stdout.contains("own/"));
+
+    let (_, _, exit_code) = run_oci(&["duplicates", "--dirs", "--audio"], test_dir.path());
+    assert_ne!(exit_code, 0);
+}
+
+#[test]
+fn test_overlap_across_repositories() {
+    let temp_dir = TempDir::new().unwrap();
+    let current = temp_dir.path().join("current");
+    let old = temp_dir.path().join("old");
+    fs::create_dir_all(current.join("photos")).unwrap();
+    fs::create_dir_all(old.join("backup/photos")).unwrap();
+    fs::write(current.join("photos/a.jpg"), "image a").unwrap();
+    fs::write(current.join("photos/new.jpg"), "a newer image").unwrap();
+    fs::write(old.join("backup/photos/a.jpg"), "image a").unwrap();
+    fs::write(old.join("backup/photos/a copy.jpg"), "image a").unwrap();
+    for repo in [&current, &old] {
+        run_oci(&["init"], repo);
+        run_oci(&["update"], repo);
+    }
+
+    let (stdout, stderr, exit_code) = run_oci(&["overlap", "photos", "../old/backup"], &current);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Shared content: 1 distinct file(s), 7 bytes"), "stdout: {}", stdout);
+    assert!(stdout.contains("35.0% of photos/ (2 file(s), 20 bytes distinct)"), "stdout: {}", stdout);
+    assert!(stdout.contains("100.0% of ../old/backup/ (2 file(s), 7 bytes distinct)"), "stdout: {}", stdout);
+    assert!(stdout.contains("Only in photos/: 1 file(s), 13 bytes of unique content"));
+    assert!(stdout.contains("Everything in ../old/backup/ is also in photos/"));
+    assert!(!stdout.contains("Everything in photos/"));
+
+    let (_, stderr, exit_code) = run_oci(&["overlap", "photos", "../old/missing"], &current);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("No indexed files under ../old/missing"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_snapshot_save_and_restore() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join("a.txt"), "alpha").unwrap();
+    fs::write(test_dir.path().join("b.txt"), "bravo").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    let (stdout, _, exit_code) = run_oci(&["snapshot", "save", "good"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Saved snapshot good (2 file(s), 10 bytes)"), "stdout: {}", stdout);
+    let (_, stderr, exit_code) = run_oci(&["snapshot", "save", "good"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("already exists"));
+
+    // A bad update drops a file from the index
+    fs::remove_file(test_dir.path().join("b.txt")).unwrap();
+    run_oci(&["update"], test_dir.path());
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(!stdout.contains("b.txt"));
+
+    let (stdout, stderr, exit_code) = run_oci(&["snapshot", "restore", "good", "-f"], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Restored snapshot good (2 file(s))"));
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(stdout.contains("a.txt") && stdout.contains("b.txt"));
+
+    // The replaced state was saved, and the snapshot itself is never indexed
+    let (stdout, _, _) = run_oci(&["snapshot", "list"], test_dir.path());
+    assert!(stdout.contains("good  2 file(s)"), "stdout: {}", stdout);
+    assert!(stdout.contains("before-restore-") && stdout.contains("1 file(s)"), "stdout: {}", stdout);
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("b.txt") && !stdout.contains("snapshots"), "stdout: {}", stdout);
+
+    let (_, stderr, exit_code) = run_oci(&["snapshot", "restore", "missing", "-f"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("No snapshot named missing"));
+    let (_, _, exit_code) = run_oci(&["snapshot", "save", "../escape"], test_dir.path());
+    assert_ne!(exit_code, 0);
+}
+
+#[test]
+fn test_export_bundle_as_source() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = temp_dir.path().join("archive");
+    let local = temp_dir.path().join("local");
+    fs::create_dir_all(&archive).unwrap();
+    fs::create_dir_all(&local).unwrap();
+    fs::write(archive.join("a.txt"), "alpha").unwrap();
+    fs::write(archive.join("b.txt"), "bravo").unwrap();
+    run_oci(&["init"], &archive);
+    run_oci(&["update"], &archive);
+    run_oci(&["keys", "generate"], &archive);
+
+    let (stdout, stderr, exit_code) = run_oci(&["export", "--bundle", "../archive.ocib", "--sign"], &archive);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Exported 2 file(s)"));
+    assert!(temp_dir.path().join("archive.ocib").is_file());
+    let (_, stderr, exit_code) = run_oci(&["export", "--bundle", "../archive.ocib"], &archive);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("already exists"));
+
+    fs::write(local.join("copy.txt"), "alpha").unwrap();
+    run_oci(&["init"], &local);
+    run_oci(&["update"], &local);
+    let (stdout, _, exit_code) = run_oci(&["missing", "../archive.ocib"], &local);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("b.txt") && !stdout.contains("a.txt"), "stdout: {}", stdout);
+
+    let key = archive.join(".oci/keys/signing.pub");
+    let (stdout, stderr, exit_code) = run_oci(&["prune", "../archive.ocib", "--verify-key", key.to_str().unwrap()], &local);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Verified the signature"), "stdout: {}", stdout);
+    assert!(!local.join("copy.txt").exists());
+
+    fs::write(temp_dir.path().join("notes.txt"), "not a bundle").unwrap();
+    let (_, stderr, exit_code) = run_oci(&["diff", "../notes.txt"], &local);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("is not an oci bundle"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_index_file_as_source() {
+    let temp_dir = TempDir::new().unwrap();
+    let drive = temp_dir.path().join("drive");
+    let laptop = temp_dir.path().join("laptop");
+    fs::create_dir_all(&drive).unwrap();
+    fs::create_dir_all(&laptop).unwrap();
+    fs::write(drive.join("a.txt"), "alpha").unwrap();
+    fs::write(drive.join("b.txt"), "bravo").unwrap();
+    run_oci(&["init"], &drive);
+    run_oci(&["update"], &drive);
+    run_oci(&["ignore", "*.tmp"], &drive);
+    fs::copy(drive.join(".oci/index.db"), temp_dir.path().join("drive.db")).unwrap();
+    let original = fs::read(drive.join(".oci/index.db")).unwrap();
+
+    fs::write(laptop.join("a.txt"), "alpha").unwrap();
+    fs::write(laptop.join("c.txt"), "charlie").unwrap();
+    fs::write(laptop.join("scratch.tmp"), "scratch").unwrap();
+    run_oci(&["init"], &laptop);
+    run_oci(&["update"], &laptop);
+
+    let (stdout, _, exit_code) = run_oci(&["diff", "../drive.db"], &laptop);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("b.txt") && stdout.contains("c.txt"), "stdout: {}", stdout);
+
+    let (_, stderr, exit_code) = run_oci(&["prune", ".oci/index.db"], &laptop);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("own index"), "stderr: {}", stderr);
+    assert!(laptop.join("a.txt").exists());
+
+    // The drive's own index file brings its ignore patterns along
+    let (stdout, stderr, exit_code) = run_oci(&["prune", "../drive/.oci/index.db"], &laptop);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("cannot check"), "stdout: {}", stdout);
+    assert!(!laptop.join("a.txt").exists());
+    assert!(!laptop.join("scratch.tmp").exists());
+    assert!(laptop.join("c.txt").exists());
+    assert_eq!(fs::read(drive.join(".oci/index.db")).unwrap(), original);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_and_prune_hooks() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let local = temp_dir.path().join("local");
+    let source = temp_dir.path().join("source");
+    fs::create_dir_all(&local).unwrap();
+    fs::create_dir_all(&source).unwrap();
+    run_oci(&["init"], &local);
+    let hooks_dir = local.join(".oci/hooks");
+    fs::create_dir_all(&hooks_dir).unwrap();
+    let log = temp_dir.path().join("hooks.log");
+    let write_hook = |name: &str, body: &str| {
+        let path = hooks_dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    };
+
+    // A failing pre-update hook cancels the update
+    write_hook("pre-update", "exit 3");
+    fs::write(local.join("a.txt"), "alpha").unwrap();
+    let (_, stderr, exit_code) = run_oci(&["update"], &local);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Update cancelled") && stderr.contains("pre-update hook failed"), "stderr: {}", stderr);
+    let (stdout, _, _) = run_oci(&["ls"], &local);
+    assert!(!stdout.contains("a.txt"));
+
+    write_hook("pre-update", &format!("echo \"pre $OCI_COMMAND\" >> '{}'", log.display()));
+    write_hook("post-update", &format!("cat >> '{}'; echo >> '{}'", log.display(), log.display()));
+    write_hook("post-prune", &format!("echo \"pruned $OCI_PRUNED $OCI_DUPLICATES\" >> '{}'", log.display()));
+    let (_, stderr, exit_code) = run_oci(&["update"], &local);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    // Nothing changed, so only the pre-update hook runs the second time
+    run_oci(&["update"], &local);
+
+    fs::write(source.join("a.txt"), "alpha").unwrap();
+    run_oci(&["init"], &source);
+    run_oci(&["update"], &source);
+    let (_, stderr, exit_code) = run_oci(&["prune", "../source"], &local);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+
+    let lines: Vec<String> = fs::read_to_string(&log).unwrap().lines().map(str::to_string).collect();
+    assert_eq!(lines.len(), 4, "log: {:?}", lines);
+    assert_eq!(lines[0], "pre update");
+    let summary: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+    assert_eq!(summary["hook"], "post-update");
+    assert_eq!(summary["added"], 1);
+    assert_eq!(lines[2], "pre update");
+    assert_eq!(lines[3], "pruned 1 1");
+}
+
+#[test]
+fn test_du() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+
+    fs::create_dir_all(test_dir.path().join("photos/raw")).unwrap();
+    fs::create_dir_all(test_dir.path().join("docs")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), vec![1u8; 300]).unwrap();
+    fs::write(test_dir.path().join("photos/raw/b.cr2"), vec![2u8; 700]).unwrap();
+    fs::write(test_dir.path().join("docs/notes.txt"), vec![3u8; 50]).unwrap();
+    fs::write(test_dir.path().join("top.txt"), vec![4u8; 5]).unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    let line_for = |stdout: &str, path: &str| -> Option<(u64, usize)> {
+        stdout.lines().find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            (fields.len() == 3 && fields[2] == path).then(|| (fields[0].parse().unwrap(), fields[1].parse().unwrap()))
+        })
+    };
+
+    // One level by default, with the total last
+    let (stdout, _, exit_code) = run_oci(&["du"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert_eq!(line_for(&stdout, "photos"), Some((1000, 2)));
+    assert_eq!(line_for(&stdout, "docs"), Some((50, 1)));
+    assert_eq!(line_for(&stdout, "photos/raw"), None);
+    assert_eq!(line_for(stdout.lines().last().unwrap(), "."), Some((1055, 4)));
+
+    let (stdout, _, _) = run_oci(&["du", "-r"], test_dir.path());
+    assert_eq!(line_for(&stdout, "photos/raw"), Some((700, 1)));
+
+    // Paths are relative to the current directory
+    let (stdout, _, exit_code) = run_oci(&["du", "--depth", "1"], &test_dir.path().join("photos"));
+    assert_eq!(exit_code, 0);
+    assert_eq!(line_for(&stdout, "raw"), Some((700, 1)));
+    assert_eq!(line_for(&stdout, "."), Some((1000, 2)));
+
+    let (_, stderr, exit_code) = run_oci(&["du", "missing"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("No indexed files under missing"));
+}
+
+#[test]
+fn test_extra_digests() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join("hello.txt"), "hello world").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    // Only SHA256 until the digests key asks for more
+    let (_, stderr, exit_code) = run_oci(&["export", "--manifest", "sums.md5", "--algorithm", "md5"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("No md5 digests in the index"));
+
+    let (_, _, exit_code) = run_oci(&["config", "set", "digests", "md5,sha1"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    let (stdout, _, _) = run_oci(&["update"], test_dir.path());
+    assert!(stdout.contains("Computed the missing digests of 1 existing file(s)"));
+
+    // grep recognizes MD5 and SHA-1 digests by their length
+    let (stdout, _, exit_code) = run_oci(&["grep", "5eb63bbbe01eeed093cb22bb8f5acdc3"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Found 1 file(s) with md5 digest"));
+    assert!(stdout.contains("hello.txt"));
+    let (stdout, _, _) = run_oci(&["grep", "--algorithm", "sha1", "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed"], test_dir.path());
+    assert!(stdout.contains("hello.txt"));
+
+    let (stdout, _, exit_code) = run_oci(&["export", "--manifest", "sums.md5", "--algorithm", "md5"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Wrote md5 digests of 1 file(s)"));
+    let manifest = fs::read_to_string(test_dir.path().join("sums.md5")).unwrap();
+    assert_eq!(manifest, "5eb63bbbe01eeed093cb22bb8f5acdc3  hello.txt\n");
+
+    let (_, stderr, exit_code) = run_oci(&["export", "elsewhere", "--algorithm", "md5"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--algorithm requires --manifest"));
+}
+
+#[test]
+fn test_verify_manifest() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::create_dir_all(test_dir.path().join("docs")).unwrap();
+    fs::write(test_dir.path().join("docs/hello.txt"), "hello world").unwrap();
+    fs::write(test_dir.path().join("docs/notes.txt"), "notes").unwrap();
+    fs::write(test_dir.path().join("extra.txt"), "extra").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    // A sha256sum manifest with one correct, one wrong and one missing file
+    let manifest = test_dir.path().join("SHA256SUMS");
+    fs::write(&manifest, "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  docs/hello.txt\n\
+        0000000000000000000000000000000000000000000000000000000000000000 *./docs/notes.txt\n\
+        b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  docs/gone.txt\n").unwrap();
+    let (stdout, stderr, exit_code) = run_oci(&["verify", "--manifest", "SHA256SUMS"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stdout.contains("Mismatch: docs/notes.txt"));
+    assert!(stdout.contains("Missing: docs/gone.txt"));
+    assert!(stdout.contains("Not in manifest: extra.txt"));
+    assert!(stdout.contains("Checked 3 of 3 file(s) in the manifest (sha256): 1 verified, 1 mismatched, 1 missing"));
+    assert!(stderr.contains("Verification failed: 2 file(s) differ from the manifest"));
+
+    // BSD-style MD5 lines, relative to the current directory; the index has no MD5 digests yet
+    fs::write(test_dir.path().join("docs/sums.md5"), "MD5 (hello.txt) = 5eb63bbbe01eeed093cb22bb8f5acdc3\n").unwrap();
+    let docs = test_dir.path().join("docs");
+    let (stdout, _, exit_code) = run_oci(&["verify", "--manifest", "sums.md5"], &docs);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("1 verified, 0 mismatched, 0 missing"));
+    assert!(stdout.contains("Not in manifest: notes.txt"));
+    let (stdout, _, exit_code) = run_oci(&["verify", "--manifest", "sums.md5", "--index"], &docs);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Skipped 1 file(s) without a recorded md5 digest"));
+
+    fs::write(test_dir.path().join("outside.md5"), "5eb63bbbe01eeed093cb22bb8f5acdc3  ../../elsewhere.txt\n").unwrap();
+    let (_, stderr, exit_code) = run_oci(&["verify", "--manifest", "outside.md5"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("outside the repository"));
+}
+
+#[test]
+#[cfg(not(any(target_os = "macos", windows)))]
+fn test_update_fast_unsupported() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    run_oci(&["init"], dir);
+
+    let (_, stderr, code) = run_oci(&["update", "--fast"], dir);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("only read on macOS"), "stderr: {}", stderr);
+    assert!(!dir.join(".oci/changes").exists());
+
+    let (_, stderr, code) = run_oci(&["update", "--fast", "sub"], dir);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("cannot be used with"), "stderr: {}", stderr);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_watch_applies_changes() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs/old.txt"), "old").unwrap();
+    run_oci(&["init"], dir);
+
+    let mut child = Command::new(get_oci_binary())
+        .args(["watch", "--interval", "1"])
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", std::env::temp_dir().join(format!("oci-tests-{}", std::process::id())))
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to execute oci");
+    let listed = |name: &str| run_oci(&["ls", "-r"], dir).0.contains(name);
+    let wait_for = |done: &dyn Fn() -> bool| {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(20);
+        while !done() && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+        }
+        done()
+    };
+
+    // The first update catches up, then changes are applied as they happen
+    assert!(wait_for(&|| listed("docs/old.txt")));
+    fs::create_dir_all(dir.join("docs/new")).unwrap();
+    fs::write(dir.join("docs/new/a.txt"), "a").unwrap();
+    fs::remove_file(dir.join("docs/old.txt")).unwrap();
+    let applied = wait_for(&|| listed("docs/new/a.txt") && !listed("docs/old.txt"));
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(applied, "index: {}", run_oci(&["ls", "-r"], dir).0);
+
+    let (stdout, _, _) = run_oci(&["log"], dir);
+    assert!(stdout.contains("watch: "), "log: {}", stdout);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_daemon_start_stop() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    run_oci(&["init"], dir);
+
+    let (stdout, stderr, code) = run_oci(&["daemon", "start", "--interval", "1", "--io-limit", "1M"], dir);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Started the daemon"), "stdout: {}", stdout);
+    let (status, _, _) = run_oci(&["daemon", "status"], dir);
+    let (_, second_start, _) = run_oci(&["daemon", "start"], dir);
+    let (_, watch, _) = run_oci(&["watch"], dir);
+
+    // Changes are applied, and the idle time goes into verifying the index
+    fs::write(dir.join("b.txt"), "b").unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(20);
+    let done = || run_oci(&["ls", "-r"], dir).0.contains("b.txt") && run_oci(&["log"], dir).0.contains("verify: ");
+    while !done() && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+    let applied = done();
+    let (stopped, _, _) = run_oci(&["daemon", "stop"], dir);
+
+    assert!(status.contains("Daemon running"), "status: {}", status);
+    assert!(second_start.contains("already running"), "stderr: {}", second_start);
+    assert!(watch.contains("already keeps this index current"), "stderr: {}", watch);
+    assert!(applied, "log: {}", run_oci(&["log"], dir).0);
+    assert!(stopped.contains("Stopped the daemon"), "stdout: {}", stopped);
+    assert!(run_oci(&["daemon", "status"], dir).0.contains("No daemon running"));
+    assert!(!dir.join(".oci/daemon.pid").exists());
+    assert!(fs::read_to_string(dir.join(".oci/daemon.log")).unwrap().contains("Daemon stopped"));
+    let (_, stderr, code) = run_oci(&["daemon", "stop"], dir);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("No daemon is running"), "stderr: {}", stderr);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_daemon_answers_queries() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    fs::create_dir_all(dir.join("docs")).unwrap();
+    fs::write(dir.join("docs/a.txt"), "a").unwrap();
+    run_oci(&["init"], dir);
+    run_oci(&["update"], dir);
+
+    // A long interval keeps changes pending, so status has to notice them before the daemon does
+    let (_, stderr, code) = run_oci(&["daemon", "start", "--interval", "60", "--no-verify"], dir);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while !dir.join(".oci/daemon.sock").exists() && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    let (clean, _, clean_code) = run_oci(&["status", "--check"], dir);
+    let (listing, _, _) = run_oci(&["ls", "-r"], dir);
+    let (found, _, _) = run_oci(&["grep", "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb"], dir);
+    fs::write(dir.join("docs/b.txt"), "b").unwrap();
+    let (changed, _, changed_code) = run_oci(&["status", "--check"], dir);
+    let (docs, _, _) = run_oci(&["status", "docs"], dir);
+    run_oci(&["daemon", "stop"], dir);
+
+    assert_eq!(clean_code, 0);
+    assert!(clean.contains("No changes"), "stdout: {}", clean);
+    assert!(listing.contains("docs/a.txt"), "stdout: {}", listing);
+    assert!(found.contains("Found 1 file(s)"), "stdout: {}", found);
+    assert_eq!(changed_code, 1);
+    assert!(changed.contains("b.txt"), "stdout: {}", changed);
+    assert!(docs.contains("b.txt"), "stdout: {}", docs);
+    assert!(!dir.join(".oci/daemon.sock").exists());
+}
+
+#[test]
+fn test_merge_with_prefix_and_prefer() {
+    let temp_dir = TempDir::new().unwrap();
+    let main = temp_dir.path().join("main");
+    let other = temp_dir.path().join("other");
+    fs::create_dir_all(main.join("drive-b")).unwrap();
+    fs::create_dir_all(other.join("sub")).unwrap();
+    fs::write(main.join("drive-b/notes.txt"), "ours").unwrap();
+    fs::write(main.join("drive-b/same.txt"), "same").unwrap();
+    run_oci(&["init"], &main);
+    run_oci(&["update"], &main);
+    fs::write(other.join("notes.txt"), "theirs").unwrap();
+    fs::write(other.join("same.txt"), "same").unwrap();
+    fs::write(other.join("sub/new.txt"), "new").unwrap();
+    run_oci(&["init"], &other);
+    run_oci(&["update"], &other);
+
+    let other_arg = other.to_str().unwrap();
+    let (dry_run, _, _) = run_oci(&["merge", other_arg, "--prefix", "drive-b", "--dry-run"], &main);
+    let (listed_after_dry_run, _, _) = run_oci(&["ls", "-r"], &main);
+    let (stdout, stderr, code) = run_oci(&["merge", other_arg, "--prefix", "drive-b", "--prefer", "theirs"], &main);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    let (listed, _, _) = run_oci(&["ls", "-r"], &main);
+    let (_, bad_prefer, bad_code) = run_oci(&["merge", other_arg, "--prefer", "mine"], &main);
+
+    assert!(dry_run.contains("drive-b/notes.txt (kept ours)"), "stdout: {}", dry_run);
+    assert!(dry_run.contains("Dry run"), "stdout: {}", dry_run);
+    assert!(!listed_after_dry_run.contains("new.txt"), "stdout: {}", listed_after_dry_run);
+    assert!(stdout.contains("drive-b/notes.txt (took theirs)"), "stdout: {}", stdout);
+    assert!(stdout.contains("1 added, 1 identical, 1 conflict(s)"), "stdout: {}", stdout);
+    assert!(listed.contains("drive-b/sub/new.txt"), "stdout: {}", listed);
+    // The entry now describes the other drive's file, which isn't in this tree
+    let (status, _, _) = run_oci(&["status"], &main);
+    assert!(status.contains("drive-b/notes.txt"), "stdout: {}", status);
+    assert_ne!(bad_code, 0);
+    assert!(bad_prefer.contains("Invalid --prefer value"), "stderr: {}", bad_prefer);
+}
+
+#[test]
+fn test_split_subdir_into_new_repo() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path().join("repo");
+    let drive = temp_dir.path().join("drive");
+    fs::create_dir_all(repo.join("photos/2020")).unwrap();
+    fs::write(repo.join("photos/2020/a.jpg"), "a").unwrap();
+    fs::write(repo.join("notes.txt"), "notes").unwrap();
+    run_oci(&["init"], &repo);
+    run_oci(&["update"], &repo);
+    run_oci(&["tag", "add", "photos/2020", "best"], &repo);
+
+    let (stdout, stderr, code) = run_oci(&["split", "photos", drive.to_str().unwrap()], &repo);
+    assert_eq!(code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Split 1 file(s)"), "stdout: {}", stdout);
+    fs::rename(repo.join("photos/2020"), drive.join("2020")).unwrap();
+
+    let (listed, _, _) = run_oci(&["ls", "-r"], &drive);
+    let (tags, _, _) = run_oci(&["tag", "list"], &drive);
+    let (status, _, _) = run_oci(&["status"], &drive);
+    assert!(listed.contains("2020/a.jpg") && !listed.contains("notes.txt"), "stdout: {}", listed);
+    assert!(tags.contains("2020  best"), "stdout: {}", tags);
+    assert!(status.contains("No changes"), "stdout: {}", status);
+    // The source index keeps its entries until its next update
+    assert!(run_oci(&["ls", "-r"], &repo).0.contains("photos/2020/a.jpg"));
+
+    let (_, again, code) = run_oci(&["split", "photos", drive.to_str().unwrap()], &repo);
+    assert_ne!(code, 0);
+    assert!(again.contains("Index already exists"), "stderr: {}", again);
+}
+
+#[test]
+fn test_limit_status_and_ls() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    for i in 0..5 {
+        fs::write(dir.join(format!("file{}.txt", i)), format!("content {}", i)).unwrap();
+    }
+    run_oci(&["init"], dir);
+
+    let (stdout, _, code) = run_oci(&["status", "--limit", "2", "--check"], dir);
+    assert_eq!(code, 1);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", stdout);
+    assert!(lines[0].ends_with("file0.txt") && lines[1].ends_with("file1.txt"));
+    assert_eq!(lines[2], "… and 3 more");
+
+    run_oci(&["update"], dir);
+    let (stdout, _, _) = run_oci(&["ls", "--limit", "4"], dir);
+    assert_eq!(stdout.lines().count(), 5);
+    assert!(stdout.ends_with("… and 1 more\n"), "{}", stdout);
+
+    // Templated output keeps stdout to one file per line
+    let (stdout, stderr, _) = run_oci(&["ls", "--limit", "1", "--format", "{path}"], dir);
+    assert_eq!(stdout, "file0.txt\n");
+    assert!(stderr.contains("… and 4 more"));
+    let (stdout, _, _) = run_oci(&["ls", "--limit", "10", "--format", "ndjson"], dir);
+    assert_eq!(stdout.lines().count(), 5);
+}
+
+#[test]
+fn test_output_in_path_order() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    fs::create_dir(dir.join("a")).unwrap();
+    fs::write(dir.join("a/b.txt"), "b").unwrap();
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    fs::write(dir.join("z.txt"), "z").unwrap();
+    run_oci(&["init"], dir);
+
+    let paths = |stdout: &str| -> Vec<String> {
+        stdout.lines().filter_map(|line| line.split_whitespace().last()).map(str::to_string).collect()
+    };
+    let (stdout, _, _) = run_oci(&["status"], dir);
+    assert_eq!(paths(&stdout), vec!["a.txt", "a/b.txt", "z.txt"]);
+
+    // Indexed in two updates, so the later one isn't stored first
+    fs::remove_file(dir.join("a.txt")).unwrap();
+    run_oci(&["update"], dir);
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    run_oci(&["update"], dir);
+    let (stdout, _, _) = run_oci(&["ls", "-r"], dir);
+    assert_eq!(paths(&stdout), vec!["a.txt", "a/b.txt", "z.txt"]);
+
+    fs::remove_file(dir.join("z.txt")).unwrap();
+    fs::remove_file(dir.join("a.txt")).unwrap();
+    let (stdout, _, _) = run_oci(&["status"], dir);
+    assert_eq!(paths(&stdout), vec!["a.txt", "z.txt"]);
+    let (stdout, _, _) = run_oci(&["update"], dir);
+    assert_eq!(paths(&stdout)[..2], ["a.txt", "z.txt"]);
+}
+
+#[test]
+fn test_since_filters_status_and_ls() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    let old = std::time::SystemTime::now() - std::time::Duration::from_secs(30 * 86_400);
+    for name in ["old.txt", "gone.txt"] {
+        fs::write(dir.join(name), name).unwrap();
+        fs::File::options().write(true).open(dir.join(name)).unwrap().set_modified(old).unwrap();
+    }
+    fs::write(dir.join("new.txt"), "new").unwrap();
+    run_oci(&["init"], dir);
+    run_oci(&["update"], dir);
+
+    fs::write(dir.join("old.txt"), "changed").unwrap();
+    fs::File::options().write(true).open(dir.join("old.txt")).unwrap().set_modified(old).unwrap();
+    fs::write(dir.join("added.txt"), "added").unwrap();
+    fs::remove_file(dir.join("gone.txt")).unwrap();
+
+    // Deleted files have no modified time on disk, so they are always listed
+    let (stdout, _, _) = run_oci(&["status", "--since", "7d"], dir);
+    assert!(stdout.contains("added.txt") && stdout.contains("gone.txt"), "{}", stdout);
+    assert!(!stdout.contains("old.txt"), "{}", stdout);
+
+    let (stdout, _, _) = run_oci(&["ls", "--since", "7d"], dir);
+    assert!(stdout.contains("new.txt") && !stdout.contains("old.txt"), "{}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "--since", "2000-01-01"], dir);
+    assert!(stdout.contains("old.txt"), "{}", stdout);
+
+    let (_, stderr, code) = run_oci(&["status", "--since", "last week"], dir);
+    assert_ne!(code, 0);
+    assert!(stderr.contains("Invalid --since value"), "{}", stderr);
+}
+
+#[test]
+fn test_exit_codes_by_error_kind() {
+    let temp_dir = TempDir::new().unwrap();
+    let outside = temp_dir.path().join("outside");
+    let repo = temp_dir.path().join("repo");
+    fs::create_dir_all(&outside).unwrap();
+    fs::create_dir_all(&repo).unwrap();
+    fs::write(repo.join("a.txt"), "a").unwrap();
+
+    let (_, stderr, code) = run_oci(&["ls"], &outside);
+    assert_eq!(code, 3, "{}", stderr);
+    assert!(stderr.contains("Not in an oci repository"));
+
+    run_oci(&["init"], &repo);
+    let (_, stderr, code) = run_oci(&["status", outside.to_str().unwrap()], &repo);
+    assert_eq!(code, 4, "{}", stderr);
+    assert!(stderr.contains("outside repository"));
+
+    let (_, stderr, code) = run_oci(&["prune", outside.to_str().unwrap()], &repo);
+    assert_eq!(code, 5, "{}", stderr);
+    assert!(stderr.contains("pending changes"));
+
+    fs::write(repo.join(".oci/index.db"), "not a database").unwrap();
+    let (_, stderr, code) = run_oci(&["ls"], &repo);
+    assert_eq!(code, 7, "{}", stderr);
+    assert!(stderr.contains("damaged"));
+
+    // status --check keeps 2 for every error
+    let (_, _, code) = run_oci(&["status", "--check"], &outside);
+    assert_eq!(code, 2);
+}
+
+#[test]
+fn test_index_backups_before_destructive_commands() {
+    let test_dir = TempDir::new().unwrap();
+    let backups_dir = test_dir.path().join(".oci/backups");
+    let backups = || -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&backups_dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+        names.sort();
+        names
+    };
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join("keep.txt"), "keep").unwrap();
+    fs::write(test_dir.path().join("gone.txt"), "gone").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    // Nothing to remove, nothing to back up
+    run_oci(&["gc"], test_dir.path());
+    assert!(!backups_dir.exists());
+
+    fs::remove_file(test_dir.path().join("gone.txt")).unwrap();
+    let (_, _, exit_code) = run_oci(&["gc"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    let names = backups();
+    assert_eq!(names.len(), 1);
+    assert!(names[0].ends_with("-gc-index.db"), "got: {:?}", names);
+
+    // The backup still has the entry gc removed
+    let backup = backups_dir.join(&names[0]);
+    fs::copy(&backup, test_dir.path().join(".oci/index.db")).unwrap();
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(stdout.contains("gone.txt"), "got: {}", stdout);
+
+    run_oci(&["config", "set", "backups", "2"], test_dir.path());
+    for _ in 0..3 {
+        run_oci(&["reset", "-f"], test_dir.path());
+    }
+    let names = backups();
+    assert_eq!(names.len(), 2, "got: {:?}", names);
+    assert!(names.iter().all(|name| name.contains("-reset-")), "got: {:?}", names);
+
+    run_oci(&["config", "set", "backups", "0"], test_dir.path());
+    run_oci(&["reset", "-f"], test_dir.path());
+    assert_eq!(backups(), names);
+    let (_, stderr, exit_code) = run_oci(&["config", "set", "backups", "many"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("expected a number of copies"), "got: {}", stderr);
+}
+
+#[test]
+fn test_restore_index_from_backup() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    let (stdout, _, _) = run_oci(&["restore-index"], test_dir.path());
+    assert!(stdout.contains("No index backups"), "got: {}", stdout);
+
+    fs::write(test_dir.path().join("keep.txt"), "keep").unwrap();
+    fs::write(test_dir.path().join("gone.txt"), "gone").unwrap();
+    run_oci(&["update"], test_dir.path());
+    fs::remove_file(test_dir.path().join("gone.txt")).unwrap();
+    run_oci(&["gc"], test_dir.path());
+
+    let (stdout, _, exit_code) = run_oci(&["restore-index"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    let name = stdout.lines().next().unwrap().split_whitespace().next().unwrap().to_string();
+    assert!(name.ends_with("-gc-index.db"), "got: {}", stdout);
+
+    // A backup that doesn't open leaves the index alone
+    fs::write(test_dir.path().join(".oci/backups/2000-01-01-000000-gc-index.db"), "not a database").unwrap();
+    let (_, stderr, exit_code) = run_oci(&["restore-index", "2000-01-01", "-f"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("cannot be opened; the index is unchanged"), "got: {}", stderr);
+    let (_, stderr, exit_code) = run_oci(&["restore-index", "1999", "-f"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("No backup named 1999"), "got: {}", stderr);
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(!stdout.contains("gone.txt"), "got: {}", stdout);
+
+    let (stdout, _, exit_code) = run_oci(&["restore-index", &name[..17], "-f"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stdout);
+    assert!(stdout.contains(&format!("Restored the index from backup {} (2 file(s))", name)), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(stdout.contains("gone.txt") && stdout.contains("keep.txt"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["log"], test_dir.path());
+    assert!(stdout.contains("restore-index"), "got: {}", stdout);
+    // The replaced index was backed up too
+    let (stdout, _, _) = run_oci(&["restore-index"], test_dir.path());
+    assert!(stdout.contains("-restore-index-index.db"), "got: {}", stdout);
+}
+
+#[test]
+fn test_paths_longer_than_max_path() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    let deep: PathBuf = (0..12).map(|i| format!("nested-directory-level-{:02}", i)).collect();
+    fs::create_dir_all(test_dir.path().join(&deep)).unwrap();
+    fs::write(test_dir.path().join(&deep).join("file.txt"), "deep").unwrap();
+    assert!(test_dir.path().join(&deep).join("file.txt").to_string_lossy().len() > 260);
+
+    let (_, stderr, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    let (stdout, _, _) = run_oci(&["ls", "-r"], test_dir.path());
+    assert!(stdout.contains("nested-directory-level-11/file.txt"), "got: {}", stdout);
+    let (stdout, _, exit_code) = run_oci(&["status"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(!stdout.contains("file.txt"), "got: {}", stdout);
+}
+
+#[test]
+fn test_duplicates_export_formats() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::create_dir_all(test_dir.path().join("backup")).unwrap();
+    fs::write(test_dir.path().join("a.txt"), "same").unwrap();
+    fs::write(test_dir.path().join("backup/a.txt"), "same").unwrap();
+    fs::write(test_dir.path().join("unique.txt"), "unique").unwrap();
+    run_oci(&["update"], test_dir.path());
+    let root = test_dir.path().canonicalize().unwrap();
+
+    let (stdout, _, exit_code) = run_oci(&["duplicates", "--format", "fdupes"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout, format!("{}\n{}\n\n", root.join("a.txt").display(), root.join("backup/a.txt").display()));
+
+    let (stdout, _, exit_code) = run_oci(&["duplicates", "--format", "rmlint-json"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    let records: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let records = records.as_array().unwrap();
+    assert_eq!(records.len(), 4, "got: {}", stdout);
+    assert_eq!(records[1]["type"], "duplicate_file");
+    assert_eq!(records[1]["is_original"], true);
+    assert_eq!(records[2]["path"], root.join("backup/a.txt").to_string_lossy().as_ref());
+    assert_eq!(records[3]["duplicates"], 1);
+    assert_eq!(records[3]["total_files"], 3);
+
+    let (_, stderr, exit_code) = run_oci(&["duplicates", "--format", "csv"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("expected fdupes or rmlint-json"), "got: {}", stderr);
+    let (_, _, exit_code) = run_oci(&["duplicates", "--format", "fdupes", "--resolve"], test_dir.path());
+    assert_ne!(exit_code, 0);
+}
+
+#[test]
+fn test_export_bagit() {
+    let test_dir = TempDir::new().unwrap();
+    let out_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::create_dir_all(test_dir.path().join("photos/2020")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), "a").unwrap();
+    fs::write(test_dir.path().join("photos/2020/b.jpg"), "bb").unwrap();
+    fs::write(test_dir.path().join("notes.txt"), "notes").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    let bag = out_dir.path().join("bag");
+    let (stdout, stderr, exit_code) = run_oci(&["export", "--bagit", bag.to_str().unwrap(), "--path", "photos"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(stdout.contains("Wrote a bag of 2 file(s)"), "got: {}", stdout);
+    assert_eq!(fs::read_to_string(bag.join("data/2020/b.jpg")).unwrap(), "bb");
+    assert!(!bag.join("data/notes.txt").exists());
+    assert_eq!(fs::read_to_string(bag.join("bagit.txt")).unwrap(), "BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n");
+    assert!(fs::read_to_string(bag.join("bag-info.txt")).unwrap().contains("Payload-Oxum: 3.2"));
+    let manifest = fs::read_to_string(bag.join("manifest-sha256.txt")).unwrap();
+    let (stdout, _, _) = run_oci(&["ls", "-r"], test_dir.path());
+    let hash_of = |name: &str| stdout.lines().find(|line| line.ends_with(name)).unwrap().split_whitespace().nth(2).unwrap().to_string();
+    assert_eq!(manifest, format!("{}  data/2020/b.jpg\n{}  data/a.jpg\n", hash_of("2020/b.jpg"), hash_of("photos/a.jpg")));
+    assert_eq!(fs::read_to_string(bag.join("tagmanifest-sha256.txt")).unwrap().lines().count(), 3);
+
+    // The bag must not exist yet, and files must still match the index
+    let (_, stderr, exit_code) = run_oci(&["export", "--bagit", bag.to_str().unwrap()], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("already exists"), "got: {}", stderr);
+    fs::write(test_dir.path().join("notes.txt"), "changed").unwrap();
+    let other = out_dir.path().join("other");
+    let (_, stderr, exit_code) = run_oci(&["export", "--bagit", other.to_str().unwrap()], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("changed or missing since they were indexed, e.g. notes.txt"), "got: {}", stderr);
+    assert!(!other.join("data").exists());
+}
+
+#[test]
+fn test_checksums_per_directory() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::create_dir_all(test_dir.path().join("photos/2020")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), "hello world").unwrap();
+    fs::write(test_dir.path().join("photos/2020/b.jpg"), "bb").unwrap();
+    fs::write(test_dir.path().join("notes.txt"), "notes").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    let (stdout, stderr, exit_code) = run_oci(&["checksums", "photos"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(stdout.contains("Wrote checksums.sha256 to 2 director(ies), listing 2 file(s)"), "got: {}", stdout);
+    assert!(!test_dir.path().join("checksums.sha256").exists());
+    assert_eq!(
+        fs::read_to_string(test_dir.path().join("photos/checksums.sha256")).unwrap(),
+        "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9  a.jpg\n"
+    );
+    assert!(fs::read_to_string(test_dir.path().join("photos/2020/checksums.sha256")).unwrap().ends_with("  b.jpg\n"));
+
+    // The checksum files are ignored, so the next update finds nothing new
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(!stdout.contains("checksums.sha256"), "got: {}", stdout);
+
+    // SFV files hold CRC32s; changed files are left out
+    fs::write(test_dir.path().join("notes.txt"), "changed").unwrap();
+    let (stdout, stderr, exit_code) = run_oci(&["checksums", "--sfv"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(stdout.contains("Skipped 1 file(s) changed or missing"), "got: {}", stdout);
+    assert!(fs::read_to_string(test_dir.path().join("photos/checksums.sfv")).unwrap().ends_with("\na.jpg 0D4A1185\n"));
+    assert!(!test_dir.path().join("checksums.sfv").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_parity_and_verify_repair() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let test_dir = TempDir::new().unwrap();
+    let tools = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::create_dir_all(test_dir.path().join("photos")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), "aaaa").unwrap();
+    fs::write(test_dir.path().join("photos/b.jpg"), "bbbb").unwrap();
+    fs::write(test_dir.path().join("notes.txt"), "notes").unwrap();
+    run_oci(&["update"], test_dir.path());
+
+    // A stand-in for par2 that keeps whole copies as its "recovery data"
+    let par2 = tools.path().join("par2");
+    fs::write(&par2, r#"#!/bin/sh
+op=$1; shift 2
+[ "$op" = create ] && shift
+base=${1#-B}; set_file=$2; shift 2
+dir=$(dirname "$set_file")
+case $op in
+create) for f; do cp "$f" "$dir/files.copy-$(basename "$f")"; done; touch "$set_file" ;;
+repair) for f in "$dir"/files.copy-*; do cp "$f" "$base/${f##*/files.copy-}"; done ;;
+esac
+"#).unwrap();
+    fs::set_permissions(&par2, fs::Permissions::from_mode(0o755)).unwrap();
+    set_config_value(test_dir.path(), "par2", par2.to_str().unwrap());
+
+    let (stdout, stderr, exit_code) = run_oci(&["parity", "--redundancy", "20"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(stdout.contains("Made 2 recovery set(s) with 20% redundancy, protecting 3 file(s)"), "got: {}", stdout);
+    assert!(test_dir.path().join(".oci/parity/photos/files.par2").exists());
+
+    fs::write(test_dir.path().join("photos/a.jpg"), "aXaa").unwrap();
+    fs::remove_file(test_dir.path().join("photos/b.jpg")).unwrap();
+    let (stdout, stderr, exit_code) = run_oci(&["verify", "--repair"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(stdout.contains("Repaired: photos/a.jpg"), "got: {}", stdout);
+    assert!(stdout.contains("Repaired 2 of 2 file(s)"), "got: {}", stdout);
+    assert_eq!(fs::read_to_string(test_dir.path().join("photos/b.jpg")).unwrap(), "bbbb");
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("No changes"), "got: {}", stdout);
+
+    // A set made before a file was updated would revert it, so it is not used
+    fs::write(test_dir.path().join("notes.txt"), "new notes").unwrap();
+    run_oci(&["update"], test_dir.path());
+    fs::write(test_dir.path().join("notes.txt"), "rotten...").unwrap();
+    let (stdout, _, exit_code) = run_oci(&["verify", "--repair"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stdout.contains("is older than its files' entries"), "got: {}", stdout);
+    assert_eq!(fs::read_to_string(test_dir.path().join("notes.txt")).unwrap(), "rotten...");
+}
+
+#[test]
+fn test_index_from_newer_oci_refused() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join("a.txt"), "a").unwrap();
+
+    // An index kept with an older release is recorded with this one on update
+    let config_path = test_dir.path().join(".oci/config");
+    let config = fs::read_to_string(&config_path).unwrap();
+    let current = config.lines().find(|line| line.starts_with("version=")).unwrap().to_string();
+    fs::write(&config_path, config.replace(&current, "version=0.0.1")).unwrap();
+    let (_, stderr, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(stderr.contains("version mismatch"), "got: {}", stderr);
+    assert!(fs::read_to_string(&config_path).unwrap().contains(&current));
+    let (_, stderr, _) = run_oci(&["status"], test_dir.path());
+    assert!(!stderr.contains("version mismatch"), "got: {}", stderr);
+
+    // A schema newer than this oci knows is refused rather than written to
+    let conn = rusqlite::Connection::open(test_dir.path().join(".oci/index.db")).unwrap();
+    let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+    conn.pragma_update(None, "user_version", version + 1).unwrap();
+    drop(conn);
+    let (_, stderr, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 8, "got: {}", stderr);
+    assert!(stderr.contains("upgrade oci"), "got: {}", stderr);
+}
+
+#[test]
+fn test_mistyped_subcommands_get_suggestions() {
+    let test_dir = TempDir::new().unwrap();
+
+    // clap's own suggestions, for top-level commands and those of a group alike
+    let (_, stderr, exit_code) = run_oci(&["stauts"], test_dir.path());
+    assert_eq!(exit_code, 2);
+    assert!(stderr.contains("unrecognized subcommand 'stauts'"), "got: {}", stderr);
+    assert!(stderr.contains("'status'"), "got: {}", stderr);
+
+    let (_, stderr, exit_code) = run_oci(&["snapshot", "sav"], test_dir.path());
+    assert_eq!(exit_code, 2);
+    assert!(stderr.contains("a similar subcommand exists: 'save'"), "got: {}", stderr);
+}
+
+#[test]
+fn test_rm_refuses_oci_dir() {
+    let test_dir = TempDir::new().unwrap();
+    fs::create_dir(test_dir.path().join("sub")).unwrap();
+    fs::write(test_dir.path().join("a.txt"), "a").unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
+    
+    for path in [".oci", ".oci/config", "./.oci/", "sub/../.oci/config"] {
+        let (_, stderr, exit_code) = run_oci(&["rm", path], test_dir.path());
+        assert_ne!(exit_code, 0, "rm {} succeeded", path);
+        assert!(stderr.contains("part of the repository's .oci directory"), "got: {}", stderr);
+    }
+    let (_, stderr, exit_code) = run_oci(&["rm", "../.oci"], &test_dir.path().join("sub"));
+    assert_ne!(exit_code, 0, "stderr: {}", stderr);
+    assert!(test_dir.path().join(".oci/config").is_file());
+    assert!(!test_dir.path().join(".oci/pruneyard").exists());
+    
+    let (stdout, stderr, exit_code) = run_oci(&["status"], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(!stdout.contains("a.txt"), "got: {}", stdout);
+}
+
+#[test]
+fn test_mv_refuses_oci_dir_and_ignored_source() {
+    let test_dir = TempDir::new().unwrap();
+    fs::create_dir(test_dir.path().join("photos")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), "a").unwrap();
+    fs::write(test_dir.path().join("debug.log"), "log").unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join(".oci/ignore"), "*.log\n").unwrap();
+    run_oci(&["update"], test_dir.path());
+    
+    let (_, stderr, exit_code) = run_oci(&["mv", ".oci/config", "config"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("part of the repository's .oci directory"), "got: {}", stderr);
+    let (_, stderr, exit_code) = run_oci(&["mv", "photos", ".oci"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("into the repository's .oci directory"), "got: {}", stderr);
+    let (_, stderr, exit_code) = run_oci(&["mv", "photos/a.jpg", ".oci/a.jpg"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("into the repository's .oci directory"), "got: {}", stderr);
+    assert!(test_dir.path().join(".oci/config").is_file());
+    assert!(test_dir.path().join("photos/a.jpg").is_file());
+    
+    let (_, stderr, exit_code) = run_oci(&["mv", "debug.log", "debug.txt"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Source is ignored: debug.log"), "got: {}", stderr);
+    assert!(test_dir.path().join("debug.log").is_file());
+    
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(!stdout.contains("a.jpg") && !stdout.contains("debug"), "got: {}", stdout);
+}
+
+#[test]
+fn test_stats_no_duplicates() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    // Create unique files
+    fs::write(test_dir.path().join("file1.txt"), "content1").unwrap();
+    fs::write(test_dir.path().join("file2.txt"), "content2").unwrap();
+    fs::write(test_dir.path().join("file3.txt"), "content3").unwrap();
+    
+    run_oci(&["update"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["stats"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Total files: 3"));
+    assert!(stdout.contains("Unique hashes: 3"));
+    assert!(stdout.contains("Duplicate files: 0"));
+    assert!(!stdout.contains("Duplicate groups:")); // Should not show when there are no duplicates
+    assert!(!stdout.contains("Wasted space:")); // Should not show when there are no duplicates
+    assert!(stdout.contains("Storage efficiency: 100.00%"));
+}
+
+#[test]
+fn test_status_dot_from_subdirectory_with_spaces() {
+    // Regression test for bug where "oci status ." from a subdirectory with spaces
+    // would show files as both added (+) and deleted (-)
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    // Create a subdirectory with spaces in the name
+    let subdir = test_dir.path().join("Google Drive").join("Papers");
+    fs::create_dir_all(&subdir).unwrap();
+    
+    // Create files in the subdirectory
+    fs::write(subdir.join("paper1.pdf"), "content1").unwrap();
+    fs::write(subdir.join("paper2.pdf"), "content2").unwrap();
+    
+    // Update the index
+    run_oci(&["update"], test_dir.path());
+    
+    // Run "status ." from the subdirectory
+    let (stdout, _, exit_code) = run_oci(&["status", "."], &subdir);
+    assert_eq!(exit_code, 0);
+    
+    // Should show "No changes"
+    assert!(stdout.contains("No changes"), "Expected 'No changes' but got:\n{}", stdout);
+    
+    // Should NOT show both + and - for the same file (the bug we're testing for)
+    assert!(!stdout.contains("+ "), "Unexpectedly found '+' (added) in output:\n{}", stdout);
+    assert!(!stdout.contains("- "), "Unexpectedly found '-' (deleted) in output:\n{}", stdout);
+    
+    // Modify one file and verify status detects it correctly
+    fs::write(subdir.join("paper1.pdf"), "modified content").unwrap();
+    let (stdout, _, exit_code) = run_oci(&["status", "."], &subdir);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("U "), "Should show 'U' for modified file");
+    assert!(stdout.contains("paper1.pdf"));
+    // Should show exactly one line with U, not both + and -
+    let u_count = stdout.matches("U ").count();
+    let plus_count = stdout.matches("+ ").count();
+    let minus_count = stdout.matches("- ").count();
+    assert_eq!(u_count, 1, "Should have exactly 1 'U' line");
+    assert_eq!(plus_count, 0, "Should have 0 '+' lines");
+    assert_eq!(minus_count, 0, "Should have 0 '-' lines");
+}
+
+#[test]
+fn test_update_dot_from_subdirectory_with_spaces() {
+    // Regression test to ensure "oci update ." works correctly from subdirectories with spaces
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    // Create a subdirectory with spaces
+    let subdir = test_dir.path().join("My Documents").join("Projects");
+    fs::create_dir_all(&subdir).unwrap();
+    
+    // Create files in the subdirectory
+    fs::write(subdir.join("file1.txt"), "content1").unwrap();
+    fs::write(subdir.join("file2.txt"), "content2").unwrap();
+    
+    // Update the index from the subdirectory using "."
+    let (stdout, _, exit_code) = run_oci(&["update", "."], &subdir);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Updated 2 file(s)"));
+    assert!(stdout.contains("2 added"));
+    
+    // Verify status shows no changes
+    let (stdout, _, exit_code) = run_oci(&["status", "."], &subdir);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("No changes"));
+    
+    // Modify a file and update again
+    fs::write(subdir.join("file1.txt"), "modified").unwrap();
+    let (stdout, _, exit_code) = run_oci(&["update", "."], &subdir);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Updated 1 file(s)"));
+    assert!(stdout.contains("1 updated"));
+    
+    // Verify status shows no changes after update
+    let (stdout, _, exit_code) = run_oci(&["status", "."], &subdir);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("No changes"));
+}
+
+#[test]
+fn test_prune_ignored_flag_with_source() {
+    let source_dir = TempDir::new().unwrap();
+    let local_dir = TempDir::new().unwrap();
+    
+    // Initialize both repositories
+    run_oci(&["init"], source_dir.path());
+    run_oci(&["init"], local_dir.path());
+    
+    // Add ignore pattern to local ignore (not source)
+    run_oci(&["ignore", "*.tmp"], local_dir.path());
+    
+    // Create files in source
+    fs::write(source_dir.path().join("shared.txt"), "shared content").unwrap();
+    run_oci(&["update"], source_dir.path());
+    
+    // Create files in local (including a .tmp file and a duplicate)
+    fs::write(local_dir.path().join("shared.txt"), "shared content").unwrap();
+    fs::write(local_dir.path().join("temp.tmp"), "temporary file").unwrap();
+    fs::write(local_dir.path().join("unique.txt"), "keep this").unwrap();
+    run_oci(&["update"], local_dir.path());
+    
+    // Prune local using source with --ignored flag
+    // Should remove both the duplicate and the locally ignored file
+    let source_path = source_dir.path().to_str().unwrap();
+    let (stdout, _, exit_code) = run_oci(&["prune", source_path, "--ignored"], local_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Pruned 2 file(s)"));
+    assert!(stdout.contains("shared.txt"));
+    assert!(stdout.contains("temp.tmp"));
+    assert!(stdout.contains("1 duplicates, 1 ignored"));
+    
+    // Verify files were pruned
+    assert!(!local_dir.path().join("shared.txt").exists());
+    assert!(!local_dir.path().join("temp.tmp").exists());
+    assert!(local_dir.path().join(".oci/pruneyard/shared.txt").exists());
+    assert!(local_dir.path().join(".oci/pruneyard/temp.tmp").exists());
+    
+    // Verify unique.txt still exists
+    assert!(local_dir.path().join("unique.txt").exists());
+}
+
+#[test]
+fn test_prune_ignored_flag_without_source() {
+    let local_dir = TempDir::new().unwrap();
+    
+    // Initialize repository
+    run_oci(&["init"], local_dir.path());
+    
+    // Add ignore patterns to local ignore
+    run_oci(&["ignore", "*.log"], local_dir.path());
+    run_oci(&["ignore", "*.tmp"], local_dir.path());
+    
+    // Create files (including ignored files)
+    fs::write(local_dir.path().join("important.txt"), "keep this").unwrap();
+    fs::write(local_dir.path().join("debug.log"), "remove this").unwrap();
+    fs::write(local_dir.path().join("cache.tmp"), "remove this too").unwrap();
+    
+    // Update index - ignored files won't be added to index
+    run_oci(&["update"], local_dir.path());
+    
+    // Verify only important.txt is in the index
+    let (stdout, _, _) = run_oci(&["ls"], local_dir.path());
+    assert!(stdout.contains("important.txt"));
+    assert!(!stdout.contains("debug.log"));
+    assert!(!stdout.contains("cache.tmp"));
+    
+    // Prune using --ignored flag without source
+    let (stdout, _, exit_code) = run_oci(&["prune", "--ignored"], local_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Pruned 2 ignored file(s)"));
+    assert!(stdout.contains("debug.log"));
+    assert!(stdout.contains("cache.tmp"));
+    
+    // Verify ignored files were pruned
+    assert!(!local_dir.path().join("debug.log").exists());
+    assert!(!local_dir.path().join("cache.tmp").exists());
+    assert!(local_dir.path().join(".oci/pruneyard/debug.log").exists());
+    assert!(local_dir.path().join(".oci/pruneyard/cache.tmp").exists());
+    
+    // Verify important.txt still exists
+    assert!(local_dir.path().join("important.txt").exists());
+}
+
+#[test]
+fn test_prune_ignored_flag_with_indexed_ignored_files() {
+    let local_dir = TempDir::new().unwrap();
+    
+    // Initialize repository
+    run_oci(&["init"], local_dir.path());
+    
+    // Create files and add them to index
+    fs::write(local_dir.path().join("important.txt"), "keep this").unwrap();
+    fs::write(local_dir.path().join("old_cache.tmp"), "was not ignored initially").unwrap();
+    run_oci(&["update"], local_dir.path());
+    
+    // Now add ignore pattern for .tmp files and create a new ignored file
+    run_oci(&["ignore", "*.tmp"], local_dir.path());
+    
+    // The old .tmp file is still in the index (but now matches ignore patterns)
+    // Run update to sync the index with current ignore patterns
+    run_oci(&["update"], local_dir.path());
+    
+    // The .tmp file should be removed from index by update (since it's now ignored)
+    let (stdout, _, _) = run_oci(&["ls"], local_dir.path());
+    assert!(!stdout.contains("old_cache.tmp"), "Ignored file should be removed from index by update");
+    
+    // But the file still exists on filesystem
+    assert!(local_dir.path().join("old_cache.tmp").exists());
+    
+    // Prune using --ignored flag - should move ignored files from filesystem to pruneyard
+    let (stdout, stderr, exit_code) = run_oci(&["prune", "--ignored"], local_dir.path());
+    if exit_code != 0 {
+        eprintln!("stdout: {}", stdout);
+        eprintln!("stderr: {}", stderr);
+    }
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Pruned 1 ignored file(s)"));
+    assert!(stdout.contains("old_cache.tmp"));
+    
+    // Verify file was pruned from filesystem
+    assert!(!local_dir.path().join("old_cache.tmp").exists());
+    assert!(local_dir.path().join(".oci/pruneyard/old_cache.tmp").exists());
+    
+    // Verify important.txt still exists
+    assert!(local_dir.path().join("important.txt").exists());
+}
+
+#[test]
+fn test_hogs_empty_index() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["hogs"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("No files in index"));
+}
+
+#[test]
+fn test_hogs_sorts_by_size() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    // Create files of different sizes
+    fs::write(test_dir.path().join("small.txt"), "x").unwrap(); // 1 byte
+    fs::write(test_dir.path().join("medium.txt"), "x".repeat(100)).unwrap(); // 100 bytes
+    fs::write(test_dir.path().join("large.txt"), "x".repeat(1000)).unwrap(); // 1000 bytes
+    
+    run_oci(&["update"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["hogs"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    
+    // Verify all files are listed
+    assert!(stdout.contains("small.txt"));
+    assert!(stdout.contains("medium.txt"));
+    assert!(stdout.contains("large.txt"));
+    
+    // Verify they appear in size order (largest first)
+    let large_pos = stdout.find("large.txt").unwrap();
+    let medium_pos = stdout.find("medium.txt").unwrap();
+    let small_pos = stdout.find("small.txt").unwrap();
+    
+    assert!(large_pos < medium_pos, "large.txt should appear before medium.txt");
+    assert!(medium_pos < small_pos, "medium.txt should appear before small.txt");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_status_flags_xattr_drift_when_enabled() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    set_config_value(test_dir.path(), "capture_xattrs", "true");
+    
+    let file_path = test_dir.path().join("photo.jpg");
+    fs::write(&file_path, "image data").unwrap();
+    run_oci(&["update"], test_dir.path());
+    
+    // Filesystems without user xattr support can't exercise drift detection
+    if !user_xattrs_supported(&file_path) {
+        return;
+    }
+    xattr::set(&file_path, "user.oci.tag", b"red").unwrap();
+    
+    let (stdout, _, exit_code) = run_oci(&["status"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("M "), "Expected metadata marker, got: {}", stdout);
+    assert!(stdout.contains("photo.jpg"));
+    
+    let (stdout, _, _) = run_oci(&["update"], test_dir.path());
+    assert!(stdout.contains("M photo.jpg"));
+    assert!(stdout.contains("1 updated"));
+    
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("No changes"));
+}
+
+/// Whether a file's filesystem takes user extended attributes; if not, the tests that need them
+/// say so on stderr before skipping, rather than passing silently
+#[cfg(unix)]
+fn user_xattrs_supported(path: &Path) -> bool {
+    let supported = xattr::set(path, "user.oci.probe", b"1").and_then(|()| xattr::remove(path, "user.oci.probe")).is_ok();
+    if !supported {
+        eprintln!("note: skipping, the filesystem of {} has no user extended attributes", path.display());
+    }
+    supported
+}
+
+#[cfg(unix)]
+#[test]
+fn test_verify_reports_xattr_drift_when_enabled() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    set_config_value(test_dir.path(), "capture_xattrs", "true");
+
+    let file_path = test_dir.path().join("photo.jpg");
+    fs::write(&file_path, "image data").unwrap();
+    fs::write(test_dir.path().join("other.jpg"), "other data").unwrap();
+    run_oci(&["update"], test_dir.path());
+    if !user_xattrs_supported(&file_path) {
+        return;
+    }
+
+    let (stdout, stderr, exit_code) = run_oci(&["verify"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(!stdout.contains("Attributes changed"), "got: {}", stdout);
+
+    // Drifted attributes are reported and counted, but the contents still verify
+    xattr::set(&file_path, "user.oci.tag", b"red").unwrap();
+    let (stdout, stderr, exit_code) = run_oci(&["verify"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stderr);
+    assert!(stdout.contains("Attributes changed: photo.jpg"), "got: {}", stdout);
+    assert!(stdout.contains("2 verified, 0 mismatched, 0 missing, 1 with changed attributes"), "got: {}", stdout);
+
+    // Without capture_xattrs, attributes are not compared
+    set_config_value(test_dir.path(), "capture_xattrs", "false");
+    let (stdout, _, _) = run_oci(&["verify"], test_dir.path());
+    assert!(!stdout.contains("Attributes changed"), "got: {}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_xattr_changes_ignored_when_disabled() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    let file_path = test_dir.path().join("doc.txt");
+    fs::write(&file_path, "content").unwrap();
+    run_oci(&["update"], test_dir.path());
+    
+    let _ = xattr::set(&file_path, "user.oci.tag", b"blue");
+    
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("No changes"));
+}
+
+#[test]
+fn test_hash_change_detection_catches_same_metadata_edit() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    let file_path = test_dir.path().join("notes.txt");
+    fs::write(&file_path, "aaaa").unwrap();
+    run_oci(&["update"], test_dir.path());
+    
+    // Rewrite with same-size content and restore the original modified time
+    let modified = fs::metadata(&file_path).unwrap().modified().unwrap();
+    fs::write(&file_path, "bbbb").unwrap();
+    fs::File::options().write(true).open(&file_path).unwrap().set_modified(modified).unwrap();
+    
+    // Default metadata policy trusts size + mtime
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("No changes"));
+    
+    set_config_value(test_dir.path(), "change_detection", "hash");
+    let (stdout, _, exit_code) = run_oci(&["status"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("U "), "Expected updated marker, got: {}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["update"], test_dir.path());
+    assert!(stdout.contains("1 updated"));
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains("No changes"));
+}
+
+#[test]
+fn test_invalid_change_detection_is_rejected() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    set_config_value(test_dir.path(), "change_detection", "sometimes");
+    
+    let (_, stderr, exit_code) = run_oci(&["status"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("change_detection"));
+}
+
+#[test]
+fn test_content_class_changes_are_summarized() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    set_config_value(test_dir.path(), "summarize.Mail", "Mail/, *.ost");
+    
+    fs::create_dir_all(test_dir.path().join("Mail/Inbox")).unwrap();
+    fs::write(test_dir.path().join("Mail/Inbox/1.eml"), "message one").unwrap();
+    fs::write(test_dir.path().join("Mail/Inbox/2.eml"), "message two").unwrap();
+    fs::write(test_dir.path().join("archive.ost"), "outlook").unwrap();
+    fs::write(test_dir.path().join("report.txt"), "report").unwrap();
+    
+    let (stdout, _, exit_code) = run_oci(&["status"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("report.txt"));
+    assert!(!stdout.contains("1.eml"), "Mail files should be summarized: {}", stdout);
+    assert!(!stdout.contains("archive.ost"));
+    assert!(stdout.contains("Mail: 29 bytes changed in 3 file(s) (3 added, 0 updated, 0 deleted)"));
+    
+    let (stdout, _, _) = run_oci(&["update"], test_dir.path());
+    assert!(!stdout.contains("1.eml"));
+    assert!(stdout.contains("Mail: 29 bytes changed in 3 file(s)"));
+    assert!(stdout.contains("Updated 4 file(s)"));
+    
+    // Verbose mode still lists every file
+    fs::remove_file(test_dir.path().join("Mail/Inbox/1.eml")).unwrap();
+    let (stdout, _, _) = run_oci(&["status", "-v"], test_dir.path());
+    assert!(stdout.contains("1.eml"));
+}
+
+#[test]
+fn test_quick_hash_triage_and_full_hash_on_demand() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    set_config_value(test_dir.path(), "quick_hash_threshold", "16");
+    
+    let big = "x".repeat(64);
+    fs::write(test_dir.path().join("movie1.mp4"), &big).unwrap();
+    fs::write(test_dir.path().join("movie2.mp4"), &big).unwrap();
+    fs::write(test_dir.path().join("small.txt"), "tiny").unwrap();
+    
+    let (stdout, _, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Quick-hashed 2 large file(s)"), "got: {}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(stdout.lines().any(|l| l.contains("movie1.mp4") && l.contains("quick:")));
+    assert!(stdout.lines().any(|l| l.contains("small.txt") && !l.contains("quick:")));
+    
+    // Quick hashes only produce candidates
+    let (stdout, _, _) = run_oci(&["duplicates"], test_dir.path());
+    assert!(stdout.contains("No duplicate files found"));
+    assert!(stdout.contains("1 duplicate candidate group(s)"));
+    
+    let (stdout, _, _) = run_oci(&["stats"], test_dir.path());
+    assert!(stdout.contains("Pending full hashes: 2"));
+    
+    let (stdout, _, _) = run_oci(&["update", "--full-hash"], test_dir.path());
+    assert!(stdout.contains("Computed 2 pending full hash(es)"), "got: {}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["duplicates"], test_dir.path());
+    assert!(stdout.contains("Found 2 duplicate file(s) in 1 group(s)"));
+    assert!(!stdout.contains("candidate"));
+}
+
+#[test]
+fn test_tier_tags_and_tiering_report() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    set_config_value(test_dir.path(), "tier.offsite", "*.iso");
+    
+    fs::create_dir_all(test_dir.path().join("archive/current")).unwrap();
+    fs::write(test_dir.path().join("archive/old.txt"), "old data").unwrap();
+    fs::write(test_dir.path().join("archive/current/work.txt"), "work").unwrap();
+    fs::write(test_dir.path().join("disk.iso"), "iso image").unwrap();
+    fs::write(test_dir.path().join("notes.txt"), "notes").unwrap();
+    run_oci(&["update"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["tier", "archive", "cold"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Tagged archive as cold"));
+    run_oci(&["tier", "archive/current", "hot"], test_dir.path());
+    
+    let (stdout, _, _) = run_oci(&["tier", "archive/old.txt"], test_dir.path());
+    assert!(stdout.contains("cold archive/old.txt"));
+    
+    let (stdout, _, _) = run_oci(&["tier"], test_dir.path());
+    assert!(stdout.contains("*.iso (rule)"));
+    
+    // Freshly written cold/offsite files violate their class
+    let (stdout, _, exit_code) = run_oci(&["report", "--tiering"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("hot:             4 bytes in 1 file(s)"), "got: {}", stdout);
+    assert!(stdout.contains("cold:            8 bytes in 1 file(s)"));
+    assert!(stdout.contains("offsite:         9 bytes in 1 file(s)"));
+    assert!(stdout.contains("unclassified:    5 bytes in 1 file(s)"));
+    assert!(stdout.contains("cold     archive/old.txt"));
+    assert!(stdout.contains("offsite  disk.iso"));
+    assert!(!stdout.contains("work.txt (modified"));
+    
+    set_config_value(test_dir.path(), "tier_recent_days", "0");
+    let (stdout, _, _) = run_oci(&["report", "--tiering"], test_dir.path());
+    assert!(stdout.contains("No tiering violations"));
+    
+    let (stdout, _, _) = run_oci(&["tier", "archive", "--clear"], test_dir.path());
+    assert!(stdout.contains("Cleared storage class: archive"));
+    let (stdout, _, _) = run_oci(&["tier", "archive/old.txt"], test_dir.path());
+    assert!(stdout.contains("unclassified archive/old.txt"));
+}
+
+#[test]
+fn test_tier_rejects_unknown_class() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    let (_, stderr, exit_code) = run_oci(&["tier", "file.txt", "lukewarm"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Invalid storage class"));
+    
+    let (_, stderr, exit_code) = run_oci(&["report"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("No report selected"));
+}
+
+#[test]
+fn test_log_records_mutations_with_user() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    let (stdout, _, _) = run_oci(&["log"], test_dir.path());
+    assert!(stdout.contains("No operations logged"));
+    
+    fs::write(test_dir.path().join("a.txt"), "a").unwrap();
+    let output = Command::new(get_oci_binary())
+        .args(["update"])
+        .env("USER", "alice")
+        .current_dir(test_dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    
+    // A no-op update is not logged
     run_oci(&["update"], test_dir.path());
     
-    let (stdout, _, exit_code) = run_oci(&["stats"], test_dir.path());
+    set_config_value(test_dir.path(), "identity", "Bob Jones");
+    run_oci(&["reset", "-f"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["log"], test_dir.path());
     assert_eq!(exit_code, 0);
-    assert!(stdout.contains("Total files: 3"));
-    assert!(stdout.contains("Unique hashes: 3"));
-    assert!(stdout.contains("Duplicate files: 0"));
-    assert!(!stdout.contains("Duplicate groups:")); // Should not show when there are no duplicates
-    assert!(!stdout.contains("Wasted space:")); // Should not show when there are no duplicates
-    assert!(stdout.contains("Storage efficiency: 100.00%"));
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "got: {}", stdout);
+    assert!(lines[0].contains("(Bob Jones)  reset: removed 1 file(s)"), "got: {}", lines[0]);
+    assert!(lines[1].contains("alice  update: 1 added, 0 updated, 0 removed"), "got: {}", lines[1]);
+    
+    let (stdout, _, _) = run_oci(&["log", "-n", "1"], test_dir.path());
+    assert_eq!(stdout.lines().count(), 1);
+}
+
+#[test]
+fn test_multiple_roots_in_one_index() {
+    let temp_dir = TempDir::new().unwrap();
+    let index_dir = temp_dir.path().join("index");
+    let photos = temp_dir.path().join("Photos");
+    let video = temp_dir.path().join("Video");
+    fs::create_dir_all(&index_dir).unwrap();
+    fs::create_dir_all(photos.join("2020")).unwrap();
+    fs::create_dir_all(&video).unwrap();
+    fs::write(photos.join("2020/a.jpg"), "same").unwrap();
+    fs::write(video.join("clip.mp4"), "same").unwrap();
+    
+    run_oci(&["init"], &index_dir);
+    let (stdout, _, exit_code) = run_oci(&["root", "Photos", "../Photos"], &index_dir);
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Added root Photos"));
+    run_oci(&["root", "Video", video.to_str().unwrap()], &index_dir);
+    assert!(photos.join(".ociroot").exists());
+    
+    let (stdout, _, _) = run_oci(&["update"], &index_dir);
+    assert!(stdout.contains("Updated 2 file(s)"), "got: {}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["ls", "-r"], &index_dir);
+    assert!(stdout.contains("Photos/2020/a.jpg"));
+    assert!(stdout.contains("Video/clip.mp4"));
+    assert!(!stdout.contains(".ociroot"));
+    
+    let (stdout, _, _) = run_oci(&["duplicates"], &index_dir);
+    assert!(stdout.contains("Found 2 duplicate file(s) in 1 group(s)"));
+    
+    // Commands run inside a root find the index through the root marker
+    fs::write(photos.join("2020/b.jpg"), "new").unwrap();
+    let (stdout, _, exit_code) = run_oci(&["status"], &photos.join("2020"));
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("+ ") && stdout.contains("b.jpg"), "got: {}", stdout);
+    run_oci(&["update", "."], &photos.join("2020"));
+    
+    let (stdout, _, _) = run_oci(&["status", "Photos/2020"], &index_dir);
+    assert!(stdout.contains("No changes"), "got: {}", stdout);
+    
+    // Files can't yet be moved to the pruneyard from another root and restored there
+    let (_, stderr, exit_code) = run_oci(&["rm", "Photos/2020/b.jpg"], &index_dir);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("multiple roots"), "got: {}", stderr);
+    assert!(photos.join("2020/b.jpg").exists());
+    
+    fs::remove_file(video.join("clip.mp4")).unwrap();
+    let (stdout, _, _) = run_oci(&["status"], &index_dir);
+    assert!(stdout.contains("- ") && stdout.contains("Video/clip.mp4"), "got: {}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["root", "Video", "--remove"], &index_dir);
+    assert!(stdout.contains("Removed root Video"));
+    let (stdout, _, _) = run_oci(&["root"], &index_dir);
+    assert!(stdout.contains("Photos"));
+    assert!(!stdout.contains("Video"));
+}
+
+#[test]
+fn test_root_requires_empty_index() {
+    let test_dir = TempDir::new().unwrap();
+    let root_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join("a.txt"), "a").unwrap();
+    run_oci(&["update"], test_dir.path());
+    
+    let (_, stderr, exit_code) = run_oci(&["root", "Photos", root_dir.path().to_str().unwrap()], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("already has entries"));
+}
+
+fn write_policy(repo_root: &Path, contents: &str) {
+    fs::write(repo_root.join(".oci/policy.toml"), contents).unwrap();
+}
+
+#[test]
+fn test_policy_max_file_size_blocks_indexing() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    write_policy(test_dir.path(), "[index]\nmax_file_size = 10\n");
+    
+    fs::write(test_dir.path().join("small.txt"), "small").unwrap();
+    fs::write(test_dir.path().join("big.bin"), "x".repeat(64)).unwrap();
+    
+    let (stdout, stderr, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stderr.contains("Policy violation: index.max_file_size: big.bin"), "got: {}", stderr);
+    assert!(stdout.contains("Skipped 1 file(s) violating .oci/policy.toml"));
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(!stdout.contains("big.bin"));
+    
+    let (stdout, _, _) = run_oci(&["update", "--override-policy"], test_dir.path());
+    assert!(stdout.contains("Updated 1 file(s)"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["log", "-n", "1"], test_dir.path());
+    assert!(stdout.contains("(policy overridden)"));
+}
+
+#[test]
+fn test_policy_protects_paths_from_prune() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    write_policy(test_dir.path(), "[prune]\nprotected = [\"/originals\"]\n");
+    run_oci(&["ignore", "*.tmp"], test_dir.path());
+    
+    fs::create_dir_all(test_dir.path().join("originals")).unwrap();
+    fs::write(test_dir.path().join("originals/scan.tmp"), "scan").unwrap();
+    fs::write(test_dir.path().join("other.tmp"), "other").unwrap();
+    
+    let (_, stderr, exit_code) = run_oci(&["prune", "--ignored"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("prune.protected: originals/scan.tmp is protected by 'originals'"), "got: {}", stderr);
+    assert!(stderr.contains("Prune blocked by .oci/policy.toml"));
+    assert!(test_dir.path().join("other.tmp").exists());
+    
+    let (stdout, _, exit_code) = run_oci(&["prune", "--ignored", "--override-policy"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Pruned 2 ignored file(s)"), "got: {}", stdout);
+}
+
+#[test]
+fn test_policy_requires_verify_before_purge() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    write_policy(test_dir.path(), "[purge]\nrequire_verify_within_days = 30\n");
+    run_oci(&["ignore", "*.tmp"], test_dir.path());
+    fs::write(test_dir.path().join("keep.txt"), "keep").unwrap();
+    fs::write(test_dir.path().join("junk.tmp"), "junk").unwrap();
+    run_oci(&["update"], test_dir.path());
+    run_oci(&["prune", "--ignored"], test_dir.path());
+    
+    let (_, stderr, exit_code) = run_oci(&["prune", "--purge", "-f"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("no successful 'oci verify' has been recorded"), "got: {}", stderr);
+    
+    let (stdout, _, exit_code) = run_oci(&["verify"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("1 verified, 0 mismatched, 0 missing"), "got: {}", stdout);
+    
+    let (stdout, _, exit_code) = run_oci(&["prune", "--purge", "-f"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Permanently deleted 1 pruned file(s)"));
+}
+
+#[test]
+fn test_verify_reports_mismatched_and_missing_files() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join("a.txt"), "aaaa").unwrap();
+    fs::write(test_dir.path().join("b.txt"), "bbbb").unwrap();
+    run_oci(&["update"], test_dir.path());
+    
+    // Same size, restored modified time: only a re-hash notices
+    let modified = fs::metadata(test_dir.path().join("a.txt")).unwrap().modified().unwrap();
+    fs::write(test_dir.path().join("a.txt"), "AAAA").unwrap();
+    fs::File::options().write(true).open(test_dir.path().join("a.txt")).unwrap().set_modified(modified).unwrap();
+    fs::remove_file(test_dir.path().join("b.txt")).unwrap();
+    
+    let (stdout, stderr, exit_code) = run_oci(&["verify"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stdout.contains("Mismatch: a.txt"));
+    assert!(stdout.contains("Missing: b.txt"));
+    assert!(stderr.contains("Verification failed"));
+    
+    let (stdout, _, _) = run_oci(&["log", "-n", "1"], test_dir.path());
+    assert!(stdout.contains("verify (failed)"));
+}
+
+#[test]
+fn test_diff_against_source_index() {
+    let source_dir = TempDir::new().unwrap();
+    let local_dir = TempDir::new().unwrap();
+    run_oci(&["init"], source_dir.path());
+    run_oci(&["init"], local_dir.path());
+    
+    fs::write(source_dir.path().join("same.txt"), "same").unwrap();
+    fs::write(local_dir.path().join("same.txt"), "same").unwrap();
+    fs::write(source_dir.path().join("changed.txt"), "old").unwrap();
+    fs::write(local_dir.path().join("changed.txt"), "new content").unwrap();
+    fs::write(source_dir.path().join("archived.txt"), "archived").unwrap();
+    fs::write(local_dir.path().join("local.txt"), "local").unwrap();
+    run_oci(&["update"], source_dir.path());
+    run_oci(&["update"], local_dir.path());
+    
+    let source_path = source_dir.path().to_str().unwrap();
+    let (stdout, _, exit_code) = run_oci(&["diff", source_path], local_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.lines().any(|l| l.starts_with('+') && l.ends_with("local.txt")), "got: {}", stdout);
+    assert!(stdout.lines().any(|l| l.starts_with('-') && l.ends_with("archived.txt")), "got: {}", stdout);
+    assert!(stdout.lines().any(|l| l.starts_with('U') && l.ends_with("changed.txt")), "got: {}", stdout);
+    assert!(!stdout.contains("same.txt"));
+    assert!(stdout.contains("1 only here, 1 only in"));
+    
+    let (_, stderr, exit_code) = run_oci(&["diff", "https://127.0.0.1:1/archive"], local_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Failed to fetch remote index"), "got: {}", stderr);
+}
+
+#[test]
+fn test_missing_lists_source_content_absent_here() {
+    let source_dir = TempDir::new().unwrap();
+    let local_dir = TempDir::new().unwrap();
+    run_oci(&["init"], source_dir.path());
+    run_oci(&["init"], local_dir.path());
+    
+    fs::write(source_dir.path().join("kept.txt"), "kept").unwrap();
+    fs::write(local_dir.path().join("renamed.txt"), "kept").unwrap();
+    fs::write(source_dir.path().join("lost.txt"), "lost content").unwrap();
+    fs::create_dir(source_dir.path().join("copy")).unwrap();
+    fs::write(source_dir.path().join("copy/lost.txt"), "lost content").unwrap();
+    fs::write(local_dir.path().join("local.txt"), "local").unwrap();
+    run_oci(&["update"], source_dir.path());
+    run_oci(&["update"], local_dir.path());
+    
+    let source_path = source_dir.path().to_str().unwrap();
+    let (stdout, _, exit_code) = run_oci(&["missing", source_path], local_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.lines().any(|l| l.ends_with(" lost.txt")), "got: {}", stdout);
+    assert!(stdout.lines().any(|l| l.ends_with(" copy/lost.txt")), "got: {}", stdout);
+    assert!(!stdout.contains("kept.txt"));
+    assert!(!stdout.contains("local.txt"));
+    assert!(stdout.contains("2 file(s) (24 bytes) in"), "got: {}", stdout);
+    assert!(stdout.contains("are missing here, 1 with distinct content (12 bytes)"), "got: {}", stdout);
+    
+    fs::write(local_dir.path().join("found.txt"), "lost content").unwrap();
+    run_oci(&["update"], local_dir.path());
+    let (stdout, _, exit_code) = run_oci(&["missing", source_path], local_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("is missing here"), "got: {}", stdout);
+    assert!(stdout.starts_with("Nothing in"), "got: {}", stdout);
+}
+
+#[test]
+fn test_compare_categorizes_both_repositories() {
+    let a_dir = TempDir::new().unwrap();
+    let b_dir = TempDir::new().unwrap();
+    run_oci(&["init"], a_dir.path());
+    run_oci(&["init"], b_dir.path());
+    
+    fs::write(a_dir.path().join("same.txt"), "same").unwrap();
+    fs::write(b_dir.path().join("same.txt"), "same").unwrap();
+    fs::write(a_dir.path().join("todo.txt"), "old").unwrap();
+    fs::write(b_dir.path().join("todo.txt"), "new content").unwrap();
+    fs::write(a_dir.path().join("old.jpg"), "photo").unwrap();
+    fs::write(b_dir.path().join("new.jpg"), "photo").unwrap();
+    fs::write(a_dir.path().join("a.txt"), "only a").unwrap();
+    fs::write(b_dir.path().join("b.txt"), "only b").unwrap();
+    run_oci(&["update"], a_dir.path());
+    run_oci(&["update"], b_dir.path());
+    
+    let b_path = b_dir.path().to_str().unwrap();
+    let (stdout, _, exit_code) = run_oci(&["compare", b_path], a_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Different content at the same path (1):\n  todo.txt\n"), "got: {}", stdout);
+    assert!(stdout.contains("Same content at a different path (1):\n  old.jpg -> new.jpg\n"), "got: {}", stdout);
+    assert!(stdout.contains("Only here (1, 6 bytes):\n  a.txt\n"), "got: {}", stdout);
+    assert!(stdout.contains("(1, 6 bytes):\n  b.txt\n"), "got: {}", stdout);
+    assert!(stdout.contains("1 identical, 1 with different content, 1 at a different path, 1 only here, 1 only in"), "got: {}", stdout);
+    
+    let (stdout, _, exit_code) = run_oci(&["compare", a_dir.path().to_str().unwrap()], a_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.starts_with("Identical to") && stdout.contains("(4 file(s))"), "got: {}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_check_external_aggregates_findings() {
+    use std::os::unix::fs::PermissionsExt;
+    
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join("IMG 1.jpg"), "photo").unwrap();
+    run_oci(&["update"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["check", "--external"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("No checks found"));
+    
+    let checks_dir = test_dir.path().join(".oci/checks");
+    fs::create_dir_all(&checks_dir).unwrap();
+    let write_check = |name: &str, script: &str| {
+        let path = checks_dir.join(name);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    };
+    write_check("naming", "#!/bin/sh\ntest -f \"$OCI_INDEX\" || exit 2\necho '{\"severity\":\"warning\",\"path\":\"IMG 1.jpg\",\"message\":\"contains a space\"}'\n");
+    let (stdout, _, exit_code) = run_oci(&["check", "--external"], test_dir.path());
+    assert_eq!(exit_code, 0, "got: {}", stdout);
+    assert!(stdout.contains("warning naming: IMG 1.jpg: contains a space"), "got: {}", stdout);
+    assert!(stdout.contains("Ran 1 check(s): 0 error(s), 1 warning(s), 0 info"));
+    
+    write_check("broken", "#!/bin/sh\necho oops\nexit 3\n");
+    let (stdout, stderr, exit_code) = run_oci(&["check", "--external"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stdout.contains("error   broken: invalid output line"), "got: {}", stdout);
+    assert!(stdout.contains("Ran 2 check(s): 1 error(s), 1 warning(s)"));
+    assert!(stderr.contains("Checks failed"));
+}
+
+#[test]
+fn test_publish_static_catalog() {
+    let test_dir = TempDir::new().unwrap();
+    let repo = test_dir.path().join("archive");
+    fs::create_dir_all(repo.join("photos")).unwrap();
+    fs::write(repo.join("photos/a.jpg"), "same").unwrap();
+    fs::write(repo.join("b.jpg"), "same").unwrap();
+    run_oci(&["init"], &repo);
+    run_oci(&["update"], &repo);
+    
+    let (stdout, stderr, exit_code) = run_oci(&["publish", "--static", "../site"], &repo);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Published 2 file(s)"), "got: {}", stdout);
+    
+    let site = test_dir.path().join("site");
+    assert!(fs::read_to_string(site.join("index.html")).unwrap().contains("catalog.js"));
+    let data = fs::read_to_string(site.join("catalog.js")).unwrap();
+    let json = data.trim().strip_prefix("window.OCI_CATALOG = ").unwrap().trim_end_matches(';');
+    let catalog: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(catalog["title"], "archive");
+    let files = catalog["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0][0], "b.jpg");
+    assert_eq!(files[1][0], "photos/a.jpg");
+    assert_eq!(files[0][3], files[1][3]);
+}
+
+#[test]
+fn test_fulfill_packs_verified_files() {
+    let test_dir = TempDir::new().unwrap();
+    let repo = test_dir.path();
+    fs::create_dir_all(repo.join("photos")).unwrap();
+    fs::create_dir_all(repo.join("backup")).unwrap();
+    fs::write(repo.join("photos/a.jpg"), "aaaa").unwrap();
+    fs::write(repo.join("backup/a.jpg"), "aaaa").unwrap();
+    fs::write(repo.join("photos/b.jpg"), "bbbb").unwrap();
+    fs::write(repo.join("notes.txt"), "notes").unwrap();
+    run_oci(&["init"], repo);
+    run_oci(&["update"], repo);
+    
+    let hash_a = sha256_hex(b"aaaa");
+    let hash_b = sha256_hex(b"bbbb");
+    
+    // photos/a.jpg is damaged after indexing; its backup copy is delivered instead
+    fs::write(repo.join("photos/a.jpg"), "xxxx").unwrap();
+    let request = format!(
+        "# oci request\n{}  photos/a.jpg\n{}\nnotes.txt\nphotos/gone.jpg\n",
+        hash_a, hash_b
+    );
+    fs::write(repo.join("request.txt"), request).unwrap();
+    
+    let (stdout, stderr, exit_code) = run_oci(&["fulfill", "request.txt"], repo);
+    assert_ne!(exit_code, 0);
+    assert!(stdout.contains("Mismatch: photos/a.jpg"), "got: {}", stdout);
+    assert!(stdout.contains("+ photos/a.jpg (from backup/a.jpg)"), "got: {}", stdout);
+    assert!(stdout.contains("+ photos/b.jpg"), "got: {}", stdout);
+    assert!(stdout.contains("Not delivered: photos/gone.jpg (not in the index)"), "got: {}", stdout);
+    assert!(stdout.contains("Packed 3 of 4 requested file(s)"), "got: {}", stdout);
+    assert!(stderr.contains("1 requested file(s) could not be delivered"), "got: {}", stderr);
+    
+    let mut archive = zip::ZipArchive::new(fs::File::open(repo.join("request.zip")).unwrap()).unwrap();
+    let mut read = |name: &str| {
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name(name).unwrap(), &mut contents).unwrap();
+        contents
+    };
+    assert_eq!(read("photos/a.jpg"), "aaaa");
+    assert_eq!(read("notes.txt"), "notes");
+    let manifest = read("SHA256SUMS");
+    assert!(manifest.contains(&format!("{}  photos/a.jpg", hash_a)));
+    assert!(manifest.contains(&format!("{}  photos/b.jpg", hash_b)));
+    assert!(read("MISSING.txt").contains("photos/gone.jpg"));
+    
+    // An existing archive is never overwritten
+    let (_, stderr, exit_code) = run_oci(&["fulfill", "request.txt"], repo);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("already exists"));
+}
+
+#[test]
+fn test_legacy_index_txt_is_imported() {
+    let test_dir = TempDir::new().unwrap();
+    let repo = test_dir.path();
+    fs::create_dir_all(repo.join(".oci")).unwrap();
+    fs::create_dir_all(repo.join("docs")).unwrap();
+    fs::write(repo.join("docs/my notes.txt"), "notes").unwrap();
+    let modified = fs::metadata(repo.join("docs/my notes.txt")).unwrap().modified().unwrap()
+        .duration_since(std::time::UNIX_EPOCH).unwrap().as_millis();
+    let legacy = format!("{:>10} {:>15} {} docs/my notes.txt\n", 5, modified, sha256_hex(b"notes"));
+    fs::write(repo.join(".oci/index.txt"), legacy).unwrap();
+    
+    // The first command to load the index converts it, keeping hashes and modified times
+    let (stdout, stderr, exit_code) = run_oci(&["status"], repo);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stderr.contains("Imported 1 file(s) from legacy .oci/index.txt"), "got: {}", stderr);
+    assert!(stdout.contains("No changes"), "got: {}", stdout);
+    assert!(!repo.join(".oci/index.txt").exists());
+    assert!(repo.join(".oci/index.txt.imported").exists());
+    
+    let (stdout, _, _) = run_oci(&["log"], repo);
+    assert!(stdout.contains("import"), "got: {}", stdout);
+    
+    // init upgrades a legacy repository instead of refusing it
+    let other = TempDir::new().unwrap();
+    fs::create_dir_all(other.path().join(".oci")).unwrap();
+    fs::write(other.path().join(".oci/index.txt"), "5 1700000000 quick:abcd big.mov\n").unwrap();
+    let (stdout, stderr, exit_code) = run_oci(&["init"], other.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Upgraded legacy oci index"), "got: {}", stdout);
+    assert!(other.path().join(".oci/config").exists());
+    let (stdout, _, _) = run_oci(&["ls"], other.path());
+    assert!(stdout.contains("quick:abcd big.mov"), "got: {}", stdout);
+}
+
+#[test]
+fn test_configured_status_markers_and_legend() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    fs::write(test_dir.path().join("old.txt"), "old").unwrap();
+    run_oci(&["update"], test_dir.path());
+    fs::remove_file(test_dir.path().join("old.txt")).unwrap();
+    fs::write(test_dir.path().join("new.txt"), "new").unwrap();
+    
+    set_config_value(test_dir.path(), "marker.added", "ADDED");
+    set_config_value(test_dir.path(), "marker.deleted", "DELETED");
+    let (stdout, _, exit_code) = run_oci(&["status"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    // Symbols are padded to the widest one so paths line up
+    let line = |suffix: &str| stdout.lines().find(|l| l.ends_with(suffix)).unwrap_or_default().to_string();
+    assert!(line("new.txt").starts_with("ADDED         "), "got: {}", stdout);
+    assert!(line("old.txt").starts_with("DELETED       "), "got: {}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["status", "--legend"], test_dir.path());
+    assert!(stdout.contains("ADDED    added      On disk but not in the index"), "got: {}", stdout);
+    assert!(stdout.contains("U        updated"), "got: {}", stdout);
+    assert!(!stdout.contains("new.txt"));
+    
+    set_config_value(test_dir.path(), "marker.changed", "C");
+    let (_, stderr, exit_code) = run_oci(&["status"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Unknown status marker: 'changed'"), "got: {}", stderr);
+}
+
+#[test]
+fn test_stats_breakdowns() {
+    let test_dir = TempDir::new().unwrap();
+    fs::create_dir_all(test_dir.path().join("2017/raw")).unwrap();
+    fs::create_dir_all(test_dir.path().join("backup")).unwrap();
+    fs::write(test_dir.path().join("2017/raw/a.CR2"), "raw").unwrap();
+    fs::write(test_dir.path().join("backup/a.cr2"), "raw").unwrap();
+    fs::write(test_dir.path().join("notes"), "n").unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["stats", "--by-extension"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("By extension:"));
+    let cr2 = stdout.lines().find(|l| l.trim_start().starts_with(".cr2")).unwrap();
+    assert_eq!(cr2.split_whitespace().collect::<Vec<_>>(), vec![".cr2", "2", "6", "bytes", "85.7%", "3", "bytes"]);
+    assert!(!stdout.contains("By directory:"));
+    
+    let (stdout, _, _) = run_oci(&["stats", "--by-dir", "2"], test_dir.path());
+    assert!(stdout.lines().any(|l| l.trim_start().starts_with("2017/raw ")), "got: {}", stdout);
+    assert!(stdout.lines().any(|l| l.trim_start().starts_with(". ")), "got: {}", stdout);
+}
+
+#[test]
+fn test_human_readable_output() {
+    let test_dir = TempDir::new().unwrap();
+    fs::write(test_dir.path().join("big.bin"), vec![0u8; 1536]).unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
+    
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(stdout.trim_start().starts_with("1536 "), "got: {}", stdout);
+    
+    let (stdout, _, exit_code) = run_oci(&["ls", "--human"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    let fields: Vec<&str> = stdout.split_whitespace().collect();
+    assert_eq!(&fields[..2], &["1.50", "KiB"]);
+    // Local date and time, e.g. 2026-03-01 14:02:11
+    assert_eq!((fields[2].len(), fields[2].matches('-').count()), (10, 2), "got: {}", stdout);
+    assert_eq!(fields[3].matches(':').count(), 2, "got: {}", stdout);
+    
+    set_config_value(test_dir.path(), "human_readable", "true");
+    let (stdout, _, _) = run_oci(&["stats"], test_dir.path());
+    assert!(stdout.contains("Total size: 1.50 KiB"), "got: {}", stdout);
+}
+
+#[test]
+fn test_suggests_similar_paths() {
+    let test_dir = TempDir::new().unwrap();
+    fs::create_dir_all(test_dir.path().join("photos/2017")).unwrap();
+    fs::write(test_dir.path().join("photos/2017/a.jpg"), "a").unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
+    
+    let (_, stderr, exit_code) = run_oci(&["status", "photos/2071"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("(did you mean photos/2017?)"), "got: {}", stderr);
+    
+    let (_, stderr, _) = run_oci(&["update", "videos"], test_dir.path());
+    assert!(stderr.contains("Path does not exist") && !stderr.contains("did you mean"), "got: {}", stderr);
+    
+    let (_, stderr, _) = run_oci(&["stauts"], test_dir.path());
+    assert!(stderr.contains("'status'"), "got: {}", stderr);
+}
+
+#[test]
+fn test_color_output() {
+    let test_dir = TempDir::new().unwrap();
+    fs::write(test_dir.path().join("a.txt"), "same").unwrap();
+    fs::write(test_dir.path().join("b.txt"), "same").unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    // Output to a pipe is not colored unless asked for
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(!stdout.contains('\x1b'), "got: {:?}", stdout);
+    let (stdout, _, exit_code) = run_oci(&["status", "--color", "always"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("\x1b[32m+\x1b[0m "), "got: {:?}", stdout);
+    
+    run_oci(&["update"], test_dir.path());
+    let hash = sha256_hex(b"same");
+    let (stdout, _, _) = run_oci(&["duplicates", "--color", "always"], test_dir.path());
+    assert!(stdout.contains(&format!("Hash: \x1b[1;35m{}\x1b[0m", hash)), "got: {:?}", stdout);
+    
+    let (_, stderr, exit_code) = run_oci(&["status", "--color", "sometimes"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("expected auto, always or never"), "got: {}", stderr);
+}
+
+#[test]
+fn test_verify_all_repos() {
+    let config_home = TempDir::new().unwrap();
+    let photos = TempDir::new().unwrap();
+    let music = TempDir::new().unwrap();
+    let gone = TempDir::new().unwrap();
+    let oci = |args: &[&str], dir: &Path| run_oci_with_config_home(args, dir, config_home.path());
+    
+    for dir in [photos.path(), music.path(), gone.path()] {
+        fs::write(dir.join("a.txt"), "content").unwrap();
+        oci(&["init"], dir);
+        oci(&["update"], dir);
+    }
+    let (stdout, _, _) = oci(&["repos"], photos.path());
+    assert_eq!(stdout.lines().count(), 3, "got: {}", stdout);
+    
+    // An unmounted volume is skipped, a damaged file fails the whole run
+    let gone_path = gone.path().to_path_buf();
+    gone.close().unwrap();
+    fs::write(music.path().join("a.txt"), "CONTENT").unwrap();
+    let (stdout, stderr, exit_code) = oci(&["verify", "--all-repos", "--io-limit", "100M"], photos.path());
+    assert_ne!(exit_code, 0);
+    assert!(stdout.contains("Verifying 2 repositories") && stdout.contains("(1 not mounted)"), "got: {}", stdout);
+    assert!(stdout.contains(&format!("Mismatch: {}", music.path().join("a.txt").display())), "got: {}", stdout);
+    assert!(stdout.contains(&format!("ok         {} (1 verified", photos.path().display())), "got: {}", stdout);
+    assert!(stdout.contains(&format!("FAILED     {}", music.path().display())), "got: {}", stdout);
+    assert!(stdout.contains(&format!("unmounted  {}", gone_path.display())), "got: {}", stdout);
+    assert!(stderr.contains("Verification failed in 1 of 2 mounted repositories"), "got: {}", stderr);
+    
+    let (stdout, _, exit_code) = oci(&["repos", music.path().to_str().unwrap(), "--remove"], photos.path());
+    assert_eq!(exit_code, 0, "got: {}", stdout);
+    let (_, _, exit_code) = oci(&["verify", "--all-repos"], photos.path());
+    assert_eq!(exit_code, 0);
+}
+
+#[test]
+fn test_audit_departed() {
+    let config_home = TempDir::new().unwrap();
+    let home = TempDir::new().unwrap();
+    let backup = TempDir::new().unwrap();
+    let oci = |args: &[&str], dir: &Path| run_oci_with_config_home(args, dir, config_home.path());
+    
+    fs::write(home.path().join("moved.txt"), "moved").unwrap();
+    fs::write(home.path().join("backed_up.txt"), "backed up").unwrap();
+    fs::write(home.path().join("lost.txt"), "lost").unwrap();
+    fs::write(backup.path().join("copy.txt"), "backed up").unwrap();
+    for dir in [home.path(), backup.path()] {
+        oci(&["init"], dir);
+        oci(&["update"], dir);
+    }
+    let (stdout, _, _) = oci(&["audit", "--departed"], home.path());
+    assert!(stdout.contains("No departed content"), "got: {}", stdout);
+    
+    fs::create_dir(home.path().join("archive")).unwrap();
+    fs::rename(home.path().join("moved.txt"), home.path().join("archive/moved.txt")).unwrap();
+    fs::remove_file(home.path().join("backed_up.txt")).unwrap();
+    fs::remove_file(home.path().join("lost.txt")).unwrap();
+    oci(&["update"], home.path());
+    
+    let (stdout, stderr, exit_code) = oci(&["audit", "--departed"], home.path());
+    assert_ne!(exit_code, 0);
+    assert!(stdout.contains("moved      moved.txt -> archive/moved.txt"), "got: {}", stdout);
+    assert!(stdout.contains(&format!("elsewhere  backed_up.txt -> {}", backup.path().join("copy.txt").display())), "got: {}", stdout);
+    assert!(stdout.contains("LOST       lost.txt (4 bytes, deleted "), "got: {}", stdout);
+    assert!(stdout.contains("Summary: 1 moved, 0 in the pruneyard, 1 elsewhere, 1 lost"), "got: {}", stdout);
+    assert!(stderr.contains("1 departed file(s) were not found anywhere"), "got: {}", stderr);
+    
+    // Content that comes back is no longer departed
+    fs::write(home.path().join("lost.txt"), "lost").unwrap();
+    oci(&["update"], home.path());
+    let (_, _, exit_code) = oci(&["audit", "--departed"], home.path());
+    assert_eq!(exit_code, 0);
+}
+
+#[test]
+fn test_config_command() {
+    let test_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["config", "get", "change_detection"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout.trim(), "metadata");
+    
+    let (stdout, _, exit_code) = run_oci(&["config", "set", "quick_hash_threshold", "1g"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert_eq!(stdout.trim(), "quick_hash_threshold=1073741824");
+    let contents = fs::read_to_string(test_dir.path().join(".oci/config")).unwrap();
+    assert!(contents.contains("quick_hash_threshold=1073741824"));
+    
+    let (_, stderr, exit_code) = run_oci(&["config", "set", "change_detection", "sometimes"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("expected metadata or hash"), "got: {}", stderr);
+    let (_, stderr, exit_code) = run_oci(&["config", "get", "colour"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Not set: colour"), "got: {}", stderr);
+    
+    // The config default applies unless --color is given
+    run_oci(&["config", "set", "color", "always"], test_dir.path());
+    fs::write(test_dir.path().join("a.txt"), "a").unwrap();
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains('\x1b'), "got: {:?}", stdout);
+    let (stdout, _, _) = run_oci(&["status", "--color", "never"], test_dir.path());
+    assert!(!stdout.contains('\x1b'), "got: {:?}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["config", "list"], test_dir.path());
+    assert!(stdout.lines().any(|line| line == "color=always"), "got: {}", stdout);
+    assert!(stdout.lines().all(|line| line.contains('=')), "got: {}", stdout);
+}
+
+#[test]
+fn test_user_config_defaults_and_precedence() {
+    let test_dir = TempDir::new().unwrap();
+    let config_home = TempDir::new().unwrap();
+    let oci = |args: &[&str]| run_oci_with_config_home(args, test_dir.path(), config_home.path());
+    
+    // Invalid values are rejected before anything is written
+    let (_, stderr, exit_code) = oci(&["config", "set", "--global", "jobs", "0"]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("positive number"), "got: {}", stderr);
+    
+    // The template path is relative to the user config directory
+    fs::create_dir_all(config_home.path().join("oci")).unwrap();
+    fs::write(config_home.path().join("oci/ignore-template"), "*.tmp\n").unwrap();
+    let (stdout, _, exit_code) = oci(&["config", "set", "--global", "human_readable", "true"]);
+    assert_eq!(exit_code, 0, "got: {}", stdout);
+    oci(&["config", "set", "--global", "ignore_template", "ignore-template"]);
+    let (stdout, _, _) = oci(&["config", "list", "--global"]);
+    assert!(stdout.contains("human_readable=true"), "got: {}", stdout);
+    
+    // New repositories start from the template and pick up the user's preferences
+    fs::write(test_dir.path().join("big.bin"), vec![0u8; 1536]).unwrap();
+    fs::write(test_dir.path().join("scratch.tmp"), "x").unwrap();
+    oci(&["init"]);
+    assert_eq!(fs::read_to_string(test_dir.path().join(".oci/ignore")).unwrap(), "*.tmp\n");
+    oci(&["update"]);
+    let (stdout, _, _) = oci(&["stats"]);
+    assert!(stdout.contains("Total size: 1.50 KiB"), "got: {}", stdout);
+    
+    // A setting in the repository wins over the user config
+    let (_, _, exit_code) = oci(&["config", "set", "human_readable", "false"]);
+    assert_eq!(exit_code, 0);
+    let (stdout, _, _) = oci(&["stats"]);
+    assert!(stdout.contains("Total size: 1536 bytes"), "got: {}", stdout);
+    
+    let (_, stderr, exit_code) = oci(&["config", "set", "jobs", "4"]);
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("--global"), "got: {}", stderr);
+}
+
+#[test]
+fn test_no_hidden_skips_dotfiles() {
+    let test_dir = TempDir::new().unwrap();
+    fs::create_dir_all(test_dir.path().join("photos/.thumbnails")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), "a").unwrap();
+    fs::write(test_dir.path().join("photos/.thumbnails/a.jpg"), "t").unwrap();
+    fs::write(test_dir.path().join(".profile"), "d").unwrap();
+    run_oci(&["init"], test_dir.path());
+    
+    let (stdout, _, exit_code) = run_oci(&["status", "--no-hidden"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("photos/a.jpg"), "got: {}", stdout);
+    assert!(!stdout.contains(".thumbnails") && !stdout.contains(".profile"), "got: {}", stdout);
+    
+    // Without the flag or setting, hidden files are indexed as usual
+    let (stdout, _, _) = run_oci(&["status"], test_dir.path());
+    assert!(stdout.contains(".profile"), "got: {}", stdout);
+    
+    run_oci(&["config", "set", "index_hidden", "false"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
+    let (stdout, _, _) = run_oci(&["ls", "-r"], test_dir.path());
+    assert!(stdout.contains("photos/a.jpg"), "got: {}", stdout);
+    assert!(!stdout.contains(".thumbnails") && !stdout.contains(".profile"), "got: {}", stdout);
+}
+
+#[test]
+fn test_mv_renames_index_entries() {
+    let test_dir = TempDir::new().unwrap();
+    fs::create_dir_all(test_dir.path().join("inbox/raw")).unwrap();
+    fs::create_dir(test_dir.path().join("archive")).unwrap();
+    fs::write(test_dir.path().join("inbox/raw/a.jpg"), "a").unwrap();
+    fs::write(test_dir.path().join("inbox/b.jpg"), "b").unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
+    run_oci(&["tier", "inbox/raw", "cold"], test_dir.path());
+    
+    // Moving onto an existing directory moves into it, taking every entry under the prefix along
+    let (stdout, stderr, exit_code) = run_oci(&["mv", "inbox", "archive"], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Moved inbox -> archive/inbox (2 index entries updated)"), "got: {}", stdout);
+    assert!(test_dir.path().join("archive/inbox/raw/a.jpg").exists());
+    
+    let (stdout, _, _) = run_oci(&["status", "-r"], test_dir.path());
+    assert!(stdout.contains("No changes"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "-r"], test_dir.path());
+    assert!(stdout.contains(&format!("{} archive/inbox/raw/a.jpg", sha256_hex(b"a"))), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["tier"], test_dir.path());
+    assert!(stdout.contains("archive/inbox/raw"), "got: {}", stdout);
+    
+    // Single files, relative to the current directory
+    let (_, _, exit_code) = run_oci(&["mv", "b.jpg", "../../c.jpg"], &test_dir.path().join("archive/inbox"));
+    assert_eq!(exit_code, 0);
+    let (stdout, _, _) = run_oci(&["ls"], test_dir.path());
+    assert!(stdout.contains("c.jpg"), "got: {}", stdout);
+    
+    let (_, stderr, exit_code) = run_oci(&["mv", "c.jpg", "archive/inbox/raw/a.jpg"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Destination already exists"), "got: {}", stderr);
+    let (_, stderr, exit_code) = run_oci(&["mv", "archive", "archive/inbox/x"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("into itself"), "got: {}", stderr);
 }
 
 #[test]
-fn test_status_dot_from_subdirectory_with_spaces() {
-    // Regression test for bug where "oci status ." from a subdirectory with spaces
-    // would show files as both added (+) and deleted (-)
+fn test_rm_moves_to_pruneyard() {
     let test_dir = TempDir::new().unwrap();
+    fs::create_dir_all(test_dir.path().join("old/raw")).unwrap();
+    fs::write(test_dir.path().join("old/raw/a.jpg"), "a").unwrap();
+    fs::write(test_dir.path().join("old/b.jpg"), "b").unwrap();
+    fs::write(test_dir.path().join("keep.txt"), "k").unwrap();
     run_oci(&["init"], test_dir.path());
-    
-    // Create a subdirectory with spaces in the name
-    let subdir = test_dir.path().join("Google Drive").join("Papers");
-    fs::create_dir_all(&subdir).unwrap();
-    
-    // Create files in the subdirectory
-    fs::write(subdir.join("paper1.pdf"), "content1").unwrap();
-    fs::write(subdir.join("paper2.pdf"), "content2").unwrap();
-    
-    // Update the index
     run_oci(&["update"], test_dir.path());
     
-    // Run "status ." from the subdirectory
-    let (stdout, _, exit_code) = run_oci(&["status", "."], &subdir);
-    assert_eq!(exit_code, 0);
-    
-    // Should show "No changes"
-    assert!(stdout.contains("No changes"), "Expected 'No changes' but got:\n{}", stdout);
+    let (stdout, stderr, exit_code) = run_oci(&["rm", "old"], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Moved 2 file(s)"), "got: {}", stdout);
+    assert!(!test_dir.path().join("old").exists());
+    assert!(test_dir.path().join(".oci/pruneyard/old/raw/a.jpg").exists());
     
-    // Should NOT show both + and - for the same file (the bug we're testing for)
-    assert!(!stdout.contains("+ "), "Unexpectedly found '+' (added) in output:\n{}", stdout);
-    assert!(!stdout.contains("- "), "Unexpectedly found '-' (deleted) in output:\n{}", stdout);
+    // The index stays consistent with the disk, and the content can be traced
+    let (stdout, _, _) = run_oci(&["status", "-r"], test_dir.path());
+    assert!(stdout.contains("No changes"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["audit", "--departed"], test_dir.path());
+    assert!(stdout.contains("2 in the pruneyard"), "got: {}", stdout);
     
-    // Modify one file and verify status detects it correctly
-    fs::write(subdir.join("paper1.pdf"), "modified content").unwrap();
-    let (stdout, _, exit_code) = run_oci(&["status", "."], &subdir);
+    let (_, _, exit_code) = run_oci(&["prune", "--restore"], test_dir.path());
     assert_eq!(exit_code, 0);
-    assert!(stdout.contains("U "), "Should show 'U' for modified file");
-    assert!(stdout.contains("paper1.pdf"));
-    // Should show exactly one line with U, not both + and -
-    let u_count = stdout.matches("U ").count();
-    let plus_count = stdout.matches("+ ").count();
-    let minus_count = stdout.matches("- ").count();
-    assert_eq!(u_count, 1, "Should have exactly 1 'U' line");
-    assert_eq!(plus_count, 0, "Should have 0 '+' lines");
-    assert_eq!(minus_count, 0, "Should have 0 '-' lines");
+    assert!(test_dir.path().join("old/raw/a.jpg").exists());
+    
+    fs::write(test_dir.path().join(".oci/policy.toml"), "[prune]\nprotected = [\"keep.txt\"]\n").unwrap();
+    let (_, stderr, exit_code) = run_oci(&["rm", "keep.txt"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("protected"), "got: {}", stderr);
+    assert!(test_dir.path().join("keep.txt").exists());
 }
 
 #[test]
-fn test_update_dot_from_subdirectory_with_spaces() {
-    // Regression test to ensure "oci update ." works correctly from subdirectories with spaces
+fn test_cp_skips_indexed_content() {
     let test_dir = TempDir::new().unwrap();
+    let card = TempDir::new().unwrap();
+    fs::create_dir(test_dir.path().join("photos")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), "a").unwrap();
+    fs::create_dir_all(card.path().join("DCIM/100")).unwrap();
+    fs::write(card.path().join("DCIM/100/IMG1.jpg"), "a").unwrap();
+    fs::write(card.path().join("DCIM/100/IMG2.jpg"), "b").unwrap();
+    fs::write(card.path().join("DCIM/100/IMG3.jpg"), "b").unwrap();
     run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
     
-    // Create a subdirectory with spaces
-    let subdir = test_dir.path().join("My Documents").join("Projects");
-    fs::create_dir_all(&subdir).unwrap();
-    
-    // Create files in the subdirectory
-    fs::write(subdir.join("file1.txt"), "content1").unwrap();
-    fs::write(subdir.join("file2.txt"), "content2").unwrap();
+    let source = card.path().join("DCIM");
+    let (stdout, stderr, exit_code) = run_oci(&["cp", source.to_str().unwrap(), "import"], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Skipped (duplicate of photos/a.jpg): 100/IMG1.jpg"), "got: {}", stdout);
+    assert!(stdout.contains("Skipped (duplicate of import/100/IMG2.jpg): 100/IMG3.jpg"), "got: {}", stdout);
+    assert!(stdout.contains("Copied 1 file(s)"), "got: {}", stdout);
+    assert!(!test_dir.path().join("import/100/IMG1.jpg").exists());
+    assert_eq!(fs::read_to_string(test_dir.path().join("import/100/IMG2.jpg")).unwrap(), "b");
+    
+    // Copied files are already indexed
+    let (stdout, _, _) = run_oci(&["status", "-r"], test_dir.path());
+    assert!(stdout.contains("No changes"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["grep", &sha256_hex(b"b")], test_dir.path());
+    assert!(stdout.contains("import/100/IMG2.jpg"), "got: {}", stdout);
+}
+
+#[test]
+fn test_sync_mirrors_index() {
+    let test_dir = TempDir::new().unwrap();
+    let mirror = TempDir::new().unwrap();
+    let dest = mirror.path().join("backup");
+    let dest_arg = dest.to_str().unwrap();
+    fs::create_dir(test_dir.path().join("photos")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), "a").unwrap();
+    fs::write(test_dir.path().join("notes.txt"), "notes").unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
     
-    // Update the index from the subdirectory using "."
-    let (stdout, _, exit_code) = run_oci(&["update", "."], &subdir);
-    assert_eq!(exit_code, 0);
-    assert!(stdout.contains("Updated 2 file(s)"));
-    assert!(stdout.contains("2 added"));
+    let (stdout, stderr, exit_code) = run_oci(&["sync", dest_arg], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("2 copied"), "got: {}", stdout);
+    assert_eq!(fs::read_to_string(dest.join("photos/a.jpg")).unwrap(), "a");
     
-    // Verify status shows no changes
-    let (stdout, _, exit_code) = run_oci(&["status", "."], &subdir);
-    assert_eq!(exit_code, 0);
-    assert!(stdout.contains("No changes"));
+    let (stdout, _, _) = run_oci(&["sync", dest_arg], test_dir.path());
+    assert!(stdout.contains("0 copied") && stdout.contains("2 unchanged"), "got: {}", stdout);
     
-    // Modify a file and update again
-    fs::write(subdir.join("file1.txt"), "modified").unwrap();
-    let (stdout, _, exit_code) = run_oci(&["update", "."], &subdir);
-    assert_eq!(exit_code, 0);
-    assert!(stdout.contains("Updated 1 file(s)"));
-    assert!(stdout.contains("1 updated"));
+    // Renamed content is moved within the destination instead of copied again
+    run_oci(&["mv", "photos/a.jpg", "photos/b.jpg"], test_dir.path());
+    fs::write(dest.join("stray.txt"), "stray").unwrap();
+    let (stdout, _, _) = run_oci(&["sync", dest_arg, "--delete", "--dry-run"], test_dir.path());
+    assert!(stdout.contains("photos/b.jpg (moved from photos/a.jpg)"), "got: {}", stdout);
+    assert!(dest.join("stray.txt").exists());
     
-    // Verify status shows no changes after update
-    let (stdout, _, exit_code) = run_oci(&["status", "."], &subdir);
+    let (stdout, _, exit_code) = run_oci(&["sync", dest_arg, "--delete"], test_dir.path());
     assert_eq!(exit_code, 0);
-    assert!(stdout.contains("No changes"));
+    assert!(stdout.contains("1 moved, 1 deleted"), "got: {}", stdout);
+    assert!(dest.join("photos/b.jpg").exists());
+    assert!(!dest.join("photos/a.jpg").exists() && !dest.join("stray.txt").exists());
+    
+    // Files that changed since the last update are not copied
+    fs::write(dest.join("notes.txt"), "other").unwrap();
+    fs::write(test_dir.path().join("notes.txt"), "edited").unwrap();
+    let (_, stderr, exit_code) = run_oci(&["sync", dest_arg], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("changed since the last update"), "got: {}", stderr);
+    assert_eq!(fs::read_to_string(dest.join("notes.txt")).unwrap(), "other");
 }
 
 #[test]
-fn test_prune_ignored_flag_with_source() {
-    let source_dir = TempDir::new().unwrap();
+fn test_index_archives_finds_loose_copies() {
+    use std::io::Write;
+    
+    let test_dir = TempDir::new().unwrap();
     let local_dir = TempDir::new().unwrap();
+    fs::create_dir(test_dir.path().join("photos")).unwrap();
+    fs::write(test_dir.path().join("photos/a.jpg"), "photo a").unwrap();
+    fs::create_dir(test_dir.path().join("backups")).unwrap();
+    let mut zip = zip::ZipWriter::new(fs::File::create(test_dir.path().join("backups/2017.zip")).unwrap());
+    zip.start_file("album/a.jpg", zip::write::SimpleFileOptions::default()).unwrap();
+    zip.write_all(b"photo a").unwrap();
+    zip.finish().unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
     
-    // Initialize both repositories
-    run_oci(&["init"], source_dir.path());
+    // Members are only recorded once index_archives is on, then read on the next update
+    let (stdout, _, _) = run_oci(&["duplicates"], test_dir.path());
+    assert!(stdout.contains("No duplicate files found"), "got: {}", stdout);
+    run_oci(&["config", "set", "index_archives", "true"], test_dir.path());
+    let (stdout, stderr, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Indexed 1 member(s) of 1 archive(s)"), "got: {}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["duplicates"], test_dir.path());
+    assert!(stdout.contains("Found 2 duplicate file(s) in 1 group(s)"), "got: {}", stdout);
+    assert!(stdout.contains("backups/2017.zip!album/a.jpg"), "got: {}", stdout);
+    
+    // Members follow their archive when it is moved
+    run_oci(&["mv", "backups", "archive"], test_dir.path());
+    let (stdout, _, _) = run_oci(&["grep", &sha256_hex(b"photo a")], test_dir.path());
+    assert!(stdout.contains("Found 2 file(s)"), "got: {}", stdout);
+    assert!(stdout.contains("archive/2017.zip!album/a.jpg"), "got: {}", stdout);
+    
+    // Loose copies elsewhere are pruned as already archived
+    fs::write(local_dir.path().join("a.jpg"), "photo a").unwrap();
     run_oci(&["init"], local_dir.path());
-    
-    // Add ignore pattern to local ignore (not source)
-    run_oci(&["ignore", "*.tmp"], local_dir.path());
-    
-    // Create files in source
-    fs::write(source_dir.path().join("shared.txt"), "shared content").unwrap();
-    run_oci(&["update"], source_dir.path());
-    
-    // Create files in local (including a .tmp file and a duplicate)
-    fs::write(local_dir.path().join("shared.txt"), "shared content").unwrap();
-    fs::write(local_dir.path().join("temp.tmp"), "temporary file").unwrap();
-    fs::write(local_dir.path().join("unique.txt"), "keep this").unwrap();
     run_oci(&["update"], local_dir.path());
-    
-    // Prune local using source with --ignored flag
-    // Should remove both the duplicate and the locally ignored file
-    let source_path = source_dir.path().to_str().unwrap();
-    let (stdout, _, exit_code) = run_oci(&["prune", source_path, "--ignored"], local_dir.path());
-    assert_eq!(exit_code, 0);
-    assert!(stdout.contains("Pruned 2 file(s)"));
-    assert!(stdout.contains("shared.txt"));
-    assert!(stdout.contains("temp.tmp"));
-    assert!(stdout.contains("1 duplicates, 1 ignored"));
-    
-    // Verify files were pruned
-    assert!(!local_dir.path().join("shared.txt").exists());
-    assert!(!local_dir.path().join("temp.tmp").exists());
-    assert!(local_dir.path().join(".oci/pruneyard/shared.txt").exists());
-    assert!(local_dir.path().join(".oci/pruneyard/temp.tmp").exists());
-    
-    // Verify unique.txt still exists
-    assert!(local_dir.path().join("unique.txt").exists());
+    fs::remove_file(test_dir.path().join("photos/a.jpg")).unwrap();
+    run_oci(&["update"], test_dir.path());
+    let (stdout, stderr, exit_code) = run_oci(&["prune", test_dir.path().to_str().unwrap()], local_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Pruned (archived): a.jpg"), "got: {}", stdout);
 }
 
 #[test]
-fn test_prune_ignored_flag_without_source() {
-    let local_dir = TempDir::new().unwrap();
+fn test_detects_content_types() {
+    let test_dir = TempDir::new().unwrap();
+    fs::create_dir(test_dir.path().join("photos")).unwrap();
+    // A PNG signature under a misleading extension
+    fs::write(test_dir.path().join("photos/scan.dat"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+    fs::write(test_dir.path().join("notes.txt"), "notes").unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["config", "set", "detect_types", "false"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
     
-    // Initialize repository
-    run_oci(&["init"], local_dir.path());
+    // Entries indexed without a type get one on the next update, without being re-hashed
+    run_oci(&["config", "set", "detect_types", "true"], test_dir.path());
+    let (stdout, _, _) = run_oci(&["update"], test_dir.path());
+    assert!(stdout.contains("Detected the content type of 2 existing file(s)"), "got: {}", stdout);
+    assert!(stdout.contains("Updated 0 file(s)"), "got: {}", stdout);
     
-    // Add ignore patterns to local ignore
-    run_oci(&["ignore", "*.log"], local_dir.path());
-    run_oci(&["ignore", "*.tmp"], local_dir.path());
+    let (stdout, _, exit_code) = run_oci(&["ls", "-r", "--type", "image"], test_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("photos/scan.dat") && !stdout.contains("notes.txt"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "-r", "--type", "video/*"], test_dir.path());
+    assert!(stdout.contains("No files of type video/*"), "got: {}", stdout);
     
-    // Create files (including ignored files)
-    fs::write(local_dir.path().join("important.txt"), "keep this").unwrap();
-    fs::write(local_dir.path().join("debug.log"), "remove this").unwrap();
-    fs::write(local_dir.path().join("cache.tmp"), "remove this too").unwrap();
+    let (stdout, _, _) = run_oci(&["stats", "--by-type"], test_dir.path());
+    assert!(stdout.contains("image/png") && stdout.contains("text/plain"), "got: {}", stdout);
+}
+
+/// Minimal QuickTime movie: a movie header created at `created` (seconds since 1904) and one video track
+fn quicktime_movie(created: u32, width: u32, height: u32) -> Vec<u8> {
+    fn mp4_box(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        [&((body.len() + 8) as u32).to_be_bytes()[..], kind, body].concat()
+    }
+    let mut mvhd = vec![0u8; 100];
+    mvhd[4..8].copy_from_slice(&created.to_be_bytes());
+    let mut tkhd = vec![0u8; 84];
+    tkhd[76..80].copy_from_slice(&(width << 16).to_be_bytes());
+    tkhd[80..84].copy_from_slice(&(height << 16).to_be_bytes());
+    let moov = [mp4_box(b"mvhd", &mvhd), mp4_box(b"trak", &mp4_box(b"tkhd", &tkhd))].concat();
+    [mp4_box(b"ftyp", b"qt  "), mp4_box(b"mdat", &[0; 32]), mp4_box(b"moov", &moov)].concat()
+}
+
+#[test]
+fn test_extract_media_metadata() {
+    let test_dir = TempDir::new().unwrap();
+    fs::create_dir(test_dir.path().join("clips")).unwrap();
+    // 2017-06-01 12:00:00 UTC
+    fs::write(test_dir.path().join("clips/beach.mov"), quicktime_movie(3_579_163_200, 1920, 1080)).unwrap();
+    fs::write(test_dir.path().join("clips/plain.jpg"), [0xff, 0xd8, 0xff, 0xd9]).unwrap();
+    fs::write(test_dir.path().join("notes.txt"), "notes").unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["config", "set", "extract_media", "true"], test_dir.path());
+    
+    let (stdout, stderr, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Extracted metadata from 2 photo(s) and video(s)"), "got: {}", stdout);
+    // Files are read once, even those without metadata
+    let (stdout, _, _) = run_oci(&["update"], test_dir.path());
+    assert!(!stdout.contains("Extracted metadata"), "got: {}", stdout);
+    
+    let (stdout, _, _) = run_oci(&["ls", "-r", "--taken", "2017-06"], test_dir.path());
+    assert!(stdout.contains("clips/beach.mov") && !stdout.contains("plain.jpg"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "-r", "--taken", "2018"], test_dir.path());
+    assert!(stdout.contains("No matching photos or videos"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["ls", "--media"], &test_dir.path().join("clips"));
+    assert!(stdout.contains("beach.mov  [2017-06-01 12:00:00, 1920x1080]"), "got: {}", stdout);
+    
+    // Metadata follows a moved file
+    run_oci(&["mv", "clips/beach.mov", "clips/2017-beach.mov"], test_dir.path());
+    let (stdout, _, _) = run_oci(&["ls", "-r", "--taken", "2017"], test_dir.path());
+    assert!(stdout.contains("clips/2017-beach.mov"), "got: {}", stdout);
+}
+
+/// 16-bit mono WAV of a melody with harmonics (notes change every quarter second)
+fn melody_wav(notes: &[f32], gain: f32, seconds: usize) -> Vec<u8> {
+    let rate = 11_025u32;
+    let samples: Vec<i16> = (0..rate as usize * seconds)
+        .map(|i| {
+            let note = notes[(i * 4 / rate as usize) % notes.len()];
+            let t = i as f32 / rate as f32;
+            let tone: f32 = (1..=12)
+                .map(|k| (2.0 * std::f32::consts::PI * note / 2.0 * k as f32 * t).sin() / k as f32)
+                .sum();
+            (tone * gain * 8000.0) as i16
+        })
+        .collect();
+    let data_len = (samples.len() * 2) as u32;
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&rate.to_le_bytes());
+    wav.extend_from_slice(&(rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+#[test]
+fn test_duplicates_audio_groups_similar_tracks() {
+    let test_dir = TempDir::new().unwrap();
+    let tune = [440.0, 523.0, 659.0, 784.0, 659.0, 523.0, 392.0, 330.0];
+    let other = [349.0, 1047.0, 880.0, 311.0, 1175.0, 466.0];
+    fs::create_dir_all(test_dir.path().join("music/flac")).unwrap();
+    fs::create_dir_all(test_dir.path().join("music/mp3")).unwrap();
+    fs::write(test_dir.path().join("music/flac/tune.wav"), melody_wav(&tune, 1.0, 8)).unwrap();
+    // The same recording mastered quieter: different bytes, same sound
+    fs::write(test_dir.path().join("music/mp3/tune.wav"), melody_wav(&tune, 0.5, 8)).unwrap();
+    fs::write(test_dir.path().join("music/other.wav"), melody_wav(&other, 1.0, 8)).unwrap();
+    fs::write(test_dir.path().join("music/broken.mp3"), "not audio").unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["update"], test_dir.path());
     
-    // Update index - ignored files won't be added to index
-    run_oci(&["update"], local_dir.path());
+    let (_, stderr, exit_code) = run_oci(&["duplicates", "--audio"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("fingerprint_audio"), "got: {}", stderr);
     
-    // Verify only important.txt is in the index
-    let (stdout, _, _) = run_oci(&["ls"], local_dir.path());
-    assert!(stdout.contains("important.txt"));
-    assert!(!stdout.contains("debug.log"));
-    assert!(!stdout.contains("cache.tmp"));
+    run_oci(&["config", "set", "fingerprint_audio", "true"], test_dir.path());
+    let (stdout, stderr, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("Fingerprinted 4 audio file(s)"), "got: {}", stdout);
     
-    // Prune using --ignored flag without source
-    let (stdout, _, exit_code) = run_oci(&["prune", "--ignored"], local_dir.path());
+    let (stdout, _, _) = run_oci(&["duplicates"], test_dir.path());
+    assert!(stdout.contains("No duplicate files found"), "got: {}", stdout);
+    let (stdout, _, exit_code) = run_oci(&["duplicates", "--audio"], test_dir.path());
     assert_eq!(exit_code, 0);
-    assert!(stdout.contains("Pruned 2 ignored file(s)"));
-    assert!(stdout.contains("debug.log"));
-    assert!(stdout.contains("cache.tmp"));
-    
-    // Verify ignored files were pruned
-    assert!(!local_dir.path().join("debug.log").exists());
-    assert!(!local_dir.path().join("cache.tmp").exists());
-    assert!(local_dir.path().join(".oci/pruneyard/debug.log").exists());
-    assert!(local_dir.path().join(".oci/pruneyard/cache.tmp").exists());
+    assert!(stdout.contains("Found 2 similar audio file(s) in 1 group(s)"), "got: {}", stdout);
+    assert!(stdout.contains("music/flac/tune.wav") && stdout.contains("music/mp3/tune.wav"), "got: {}", stdout);
+    assert!(!stdout.contains("other.wav"), "got: {}", stdout);
+}
+
+#[test]
+fn test_serve_answers_queries_and_serves_index() {
+    use std::io::{BufRead, BufReader, Read, Write};
     
-    // Verify important.txt still exists
-    assert!(local_dir.path().join("important.txt").exists());
+    let test_dir = TempDir::new().unwrap();
+    let other_dir = TempDir::new().unwrap();
+    run_oci(&["init"], test_dir.path());
+    run_oci(&["init"], other_dir.path());
+    fs::write(test_dir.path().join("a.txt"), "hello").unwrap();
+    fs::write(other_dir.path().join("b.txt"), "other").unwrap();
+    run_oci(&["update"], test_dir.path());
+    run_oci(&["update"], other_dir.path());
+    
+    let mut server = Command::new(get_oci_binary())
+        .args(["serve", "--bind", "127.0.0.1:0"])
+        .current_dir(test_dir.path())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first_line = String::new();
+    BufReader::new(server.stdout.take().unwrap()).read_line(&mut first_line).unwrap();
+    let address = first_line.split("http://").nth(1).unwrap().split_whitespace().next().unwrap().to_string();
+    
+    let get = |target: &str| {
+        let mut stream = std::net::TcpStream::connect(&address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    
+    // sha256("hello")
+    let response = get("/files/by-hash/2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+    assert!(response.starts_with("HTTP/1.1 200"), "got: {}", response);
+    assert!(response.contains("\"path\":\"a.txt\""));
+    assert!(get("/files/by-hash/0000").starts_with("HTTP/1.1 404"));
+    assert!(get("/stats").contains("\"files\":1"));
+    
+    // A client that never finishes its request doesn't hold up the others
+    let mut stalled = std::net::TcpStream::connect(&address).unwrap();
+    write!(stalled, "GET /stats HTTP/1.1\r\n").unwrap();
+    assert!(get("/stats").starts_with("HTTP/1.1 200"));
+    drop(stalled);
+    // Nor can it make the server wait for the end of an endless request line
+    let mut response = String::new();
+    let mut long = std::net::TcpStream::connect(&address).unwrap();
+    write!(long, "GET /{}", "a".repeat(8192 - 5)).unwrap();
+    long.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"), "got: {}", response);
+    
+    // The served index works as a remote source
+    let (stdout, _, exit_code) = run_oci(&["diff", &format!("http://{}", address)], other_dir.path());
+    server.kill().unwrap();
+    server.wait().unwrap();
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("1 only here, 1 only in"), "got: {}", stdout);
 }
 
 #[test]
-fn test_prune_ignored_flag_with_indexed_ignored_files() {
+fn test_log_backend() {
+    let source_dir = TempDir::new().unwrap();
     let local_dir = TempDir::new().unwrap();
+    run_oci(&["init"], source_dir.path());
+    let (stdout, _, exit_code) = run_oci(&["init", "--backend", "log"], local_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Initialized empty oci index"));
+    assert!(local_dir.path().join(".oci/index.log").exists());
+    assert!(!local_dir.path().join(".oci/index.db").exists());
     
-    // Initialize repository
-    run_oci(&["init"], local_dir.path());
-    
-    // Create files and add them to index
-    fs::write(local_dir.path().join("important.txt"), "keep this").unwrap();
-    fs::write(local_dir.path().join("old_cache.tmp"), "was not ignored initially").unwrap();
-    run_oci(&["update"], local_dir.path());
-    
-    // Now add ignore pattern for .tmp files and create a new ignored file
-    run_oci(&["ignore", "*.tmp"], local_dir.path());
+    fs::write(source_dir.path().join("common.txt"), "shared content").unwrap();
+    fs::write(local_dir.path().join("common.txt"), "shared content").unwrap();
+    fs::write(local_dir.path().join("unique.txt"), "unique content").unwrap();
+    run_oci(&["update"], source_dir.path());
+    let (stdout, _, _) = run_oci(&["update"], local_dir.path());
+    assert!(stdout.contains("2 added"), "got: {}", stdout);
     
-    // The old .tmp file is still in the index (but now matches ignore patterns)
-    // Run update to sync the index with current ignore patterns
-    run_oci(&["update"], local_dir.path());
+    let (stdout, _, _) = run_oci(&["status"], local_dir.path());
+    assert!(!stdout.contains("+ "), "got: {}", stdout);
     
-    // The .tmp file should be removed from index by update (since it's now ignored)
+    // A log-backed index prunes against a SQLite source and persists the change
+    let source_path = source_dir.path().to_str().unwrap();
+    let (stdout, _, exit_code) = run_oci(&["prune", source_path], local_dir.path());
+    assert_eq!(exit_code, 0);
+    assert!(stdout.contains("Pruned 1 file(s)"));
     let (stdout, _, _) = run_oci(&["ls"], local_dir.path());
-    assert!(!stdout.contains("old_cache.tmp"), "Ignored file should be removed from index by update");
-    
-    // But the file still exists on filesystem
-    assert!(local_dir.path().join("old_cache.tmp").exists());
+    assert!(stdout.contains("unique.txt") && !stdout.contains("common.txt"), "got: {}", stdout);
+    let (stdout, _, _) = run_oci(&["log"], local_dir.path());
+    assert!(stdout.contains("prune"));
     
-    // Prune using --ignored flag - should move ignored files from filesystem to pruneyard
-    let (stdout, stderr, exit_code) = run_oci(&["prune", "--ignored"], local_dir.path());
-    if exit_code != 0 {
-        eprintln!("stdout: {}", stdout);
-        eprintln!("stderr: {}", stderr);
-    }
-    assert_eq!(exit_code, 0);
-    assert!(stdout.contains("Pruned 1 ignored file(s)"));
-    assert!(stdout.contains("old_cache.tmp"));
+    let other_dir = TempDir::new().unwrap();
+    let (_, stderr, exit_code) = run_oci(&["init", "--backend", "lmdb"], other_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Unknown backend"), "got: {}", stderr);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_diff_and_prune_against_s3_listing() {
+    use std::os::unix::fs::PermissionsExt;
     
-    // Verify file was pruned from filesystem
-    assert!(!local_dir.path().join("old_cache.tmp").exists());
-    assert!(local_dir.path().join(".oci/pruneyard/old_cache.tmp").exists());
+    let local_dir = TempDir::new().unwrap();
+    let tools_dir = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    run_oci(&["init"], local_dir.path());
+    fs::write(local_dir.path().join("a.txt"), "hello").unwrap();
+    fs::write(local_dir.path().join("b.txt"), "only local").unwrap();
+    run_oci(&["update"], local_dir.path());
     
-    // Verify important.txt still exists
-    assert!(local_dir.path().join("important.txt").exists());
+    // Stand-in for the AWS CLI: a listing with one object carrying sha256("hello") as metadata
+    let aws = tools_dir.path().join("aws");
+    fs::write(&aws, r#"#!/bin/sh
+case "$2" in
+  list-objects-v2) echo '{"Contents": [{"Key": "mirror/a.txt", "Size": 5, "ETag": "\"e1\"", "LastModified": "2024-01-01T00:00:00.000Z"}]}' ;;
+  head-object) echo '{"Metadata": {"sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"}}' ;;
+esac
+"#).unwrap();
+    fs::set_permissions(&aws, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", tools_dir.path().display(), std::env::var("PATH").unwrap_or_default());
+    let run_with_fake_aws = |args: &[&str]| {
+        let output = Command::new(get_oci_binary())
+            .args(args)
+            .current_dir(local_dir.path())
+            .env("PATH", &path)
+            .env("XDG_CACHE_HOME", cache_dir.path())
+            .output()
+            .unwrap();
+        (String::from_utf8_lossy(&output.stdout).to_string(), output.status.code().unwrap_or(-1))
+    };
+    
+    let (stdout, exit_code) = run_with_fake_aws(&["diff", "s3://bucket/mirror"]);
+    assert_eq!(exit_code, 0, "got: {}", stdout);
+    assert!(stdout.contains("Listed 1 object(s) in s3://bucket/mirror"));
+    assert!(stdout.contains("1 only here, 0 only in s3://bucket/mirror, 0 with different content"), "got: {}", stdout);
+    
+    let (stdout, exit_code) = run_with_fake_aws(&["prune", "s3://bucket/mirror"]);
+    assert_eq!(exit_code, 0, "got: {}", stdout);
+    assert!(stdout.contains("Pruned 1 file(s)"), "got: {}", stdout);
+    assert!(local_dir.path().join(".oci/pruneyard/a.txt").exists());
+    assert!(local_dir.path().join("b.txt").exists());
 }
 
 #[test]
-fn test_hogs_empty_index() {
+fn test_update_refuses_while_lease_is_held() {
     let test_dir = TempDir::new().unwrap();
     run_oci(&["init"], test_dir.path());
+    set_config_value(test_dir.path(), "lock_strategy", "lease");
+    fs::write(test_dir.path().join("a.txt"), "a").unwrap();
     
-    let (stdout, _, exit_code) = run_oci(&["hogs"], test_dir.path());
+    // A live lease from a process on another machine
+    let lease = test_dir.path().join(".oci/lock.lease");
+    fs::write(&lease, "pid=4242\nhost=nas\nsince=0\nexpires=99999999999999\n").unwrap();
+    let (_, stderr, exit_code) = run_oci(&["update"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("Another oci process is running on this index (pid 4242 on nas"), "got: {}", stderr);
+    
+    // Once the lease has expired the lock is taken over, and released afterwards
+    fs::write(&lease, "pid=4242\nhost=nas\nsince=0\nexpires=1\n").unwrap();
+    let (stdout, _, exit_code) = run_oci(&["update"], test_dir.path());
     assert_eq!(exit_code, 0);
-    assert!(stdout.contains("No files in index"));
+    assert!(stdout.contains("1 added"));
+    assert!(!lease.exists());
 }
 
 #[test]
-fn test_hogs_sorts_by_size() {
+fn test_wait_for_held_lock() {
     let test_dir = TempDir::new().unwrap();
     run_oci(&["init"], test_dir.path());
+    set_config_value(test_dir.path(), "lock_strategy", "lease");
+    fs::write(test_dir.path().join("a.txt"), "a").unwrap();
     
-    // Create files of different sizes
-    fs::write(test_dir.path().join("small.txt"), "x").unwrap(); // 1 byte
-    fs::write(test_dir.path().join("medium.txt"), "x".repeat(100)).unwrap(); // 100 bytes
-    fs::write(test_dir.path().join("large.txt"), "x".repeat(1000)).unwrap(); // 1000 bytes
-    
-    run_oci(&["update"], test_dir.path());
-    
-    let (stdout, _, exit_code) = run_oci(&["hogs"], test_dir.path());
-    assert_eq!(exit_code, 0);
-    
-    // Verify all files are listed
-    assert!(stdout.contains("small.txt"));
-    assert!(stdout.contains("medium.txt"));
-    assert!(stdout.contains("large.txt"));
-    
-    // Verify they appear in size order (largest first)
-    let large_pos = stdout.find("large.txt").unwrap();
-    let medium_pos = stdout.find("medium.txt").unwrap();
-    let small_pos = stdout.find("small.txt").unwrap();
-    
-    assert!(large_pos < medium_pos, "large.txt should appear before medium.txt");
-    assert!(medium_pos < small_pos, "medium.txt should appear before small.txt");
+    let lease = test_dir.path().join(".oci/lock.lease");
+    fs::write(&lease, "pid=4242\nhost=nas\nsince=0\nexpires=99999999999999\n").unwrap();
+    let (_, stderr, exit_code) = run_oci(&["reset", "-f"], test_dir.path());
+    assert_ne!(exit_code, 0);
+    assert!(stderr.contains("use --wait"), "got: {}", stderr);
+    
+    // The other process finishes while this one waits
+    let release = {
+        let lease = lease.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(800));
+            fs::remove_file(lease).unwrap();
+        })
+    };
+    let (stdout, stderr, exit_code) = run_oci(&["update", "--wait"], test_dir.path());
+    release.join().unwrap();
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stderr.contains("waiting for it to finish"), "got: {}", stderr);
+    assert!(stdout.contains("1 added"));
 }